@@ -0,0 +1,121 @@
+//! Hes1 oscillator with delayed transcription (Monk 2003), simulated
+//! with the delay SSA (Barrio et al. 2006).  See the README for the
+//! reference period.
+
+use rebop::gillespie::{Expr, Gillespie, Rate};
+
+/// Delay of transcription, in minutes.
+const TAU: f64 = 18.7;
+/// Maximal transcription rate, per minute.
+const ALPHA_M: f64 = 1.;
+/// Translation rate, per mRNA and per minute.
+const ALPHA_P: f64 = 1.;
+/// Degradation rate of the mRNA and of the protein, per minute.
+const MU: f64 = 0.03;
+/// Protein amount for half-maximal repression.
+const P0: f64 = 100.;
+/// Hill coefficient of the repression.
+const H: f64 = 5.;
+
+/// Returns the Hes1 problem `[mRNA, protein]`, without molecules.
+fn hes1(seed: u64) -> Gillespie {
+    use Expr::*;
+    let mut p = Gillespie::new_with_seed([0, 0], seed);
+    // transcription, repressed by the protein
+    let repression = Pow(
+        Box::new(Div(Box::new(Concentration(1)), Box::new(Constant(P0)))),
+        Box::new(Constant(H)),
+    );
+    p.add_reaction(
+        Rate::Expr(Div(
+            Box::new(Constant(ALPHA_M)),
+            Box::new(Add(Box::new(Constant(1.)), Box::new(repression))),
+        )),
+        [1, 0],
+    );
+    p.set_delay(0, TAU);
+    p.add_reaction(Rate::lma(ALPHA_P, [1, 0]), [0, 1]);
+    p.add_reaction(Rate::lma(MU, [1, 0]), [-1, 0]);
+    p.add_reaction(Rate::lma(MU, [0, 1]), [0, -1]);
+    p
+}
+
+/// Returns the amounts of protein every minute from `0` to `tmax`.
+fn protein(seed: u64, tmax: usize) -> Vec<f64> {
+    let mut p = hes1(seed);
+    (0..=tmax)
+        .map(|t| {
+            p.advance_until(t as f64);
+            p.get_species(1) as f64
+        })
+        .collect()
+}
+
+/// Returns the mean and the period of oscillation, estimated from the
+/// first peak of the autocorrelation, of a series sampled every minute.
+fn statistics(series: &[f64]) -> (f64, f64) {
+    let n = series.len();
+    let mean = series.iter().sum::<f64>() / n as f64;
+    let autocorrelation = |lag: usize| -> f64 {
+        (0..n - lag)
+            .map(|i| (series[i] - mean) * (series[i + lag] - mean))
+            .sum::<f64>()
+            / (n - lag) as f64
+    };
+    let correlations: Vec<f64> = (0..n / 2).map(autocorrelation).collect();
+    // the highest point of the first positive lobe after a negative one
+    let negative = correlations.iter().position(|&c| c < 0.).unwrap();
+    let positive = negative
+        + correlations[negative..]
+            .iter()
+            .position(|&c| c > 0.)
+            .unwrap();
+    let end = positive
+        + correlations[positive..]
+            .iter()
+            .position(|&c| c < 0.)
+            .unwrap();
+    let period = (positive..end)
+        .max_by(|&a, &b| correlations[a].total_cmp(&correlations[b]))
+        .unwrap();
+    (mean, period as f64)
+}
+
+/// Returns the average mean protein amount and period of `nb_runs`
+/// simulations of `tmax` minutes.
+fn reference(nb_runs: u64, tmax: usize) -> (f64, f64) {
+    let (mut mean, mut period) = (0., 0.);
+    for seed in 0..nb_runs {
+        // the first 6 hours are a transient
+        let statistics = statistics(&protein(seed, tmax)[360..]);
+        mean += statistics.0 / nb_runs as f64;
+        period += statistics.1 / nb_runs as f64;
+    }
+    (mean, period)
+}
+
+fn main() {
+    let (mean, period) = reference(100, 3000);
+    println!("mean protein: {mean:.1}");
+    println!("period: {period:.1} min");
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{reference, statistics};
+    #[test]
+    fn statistics_of_a_sine() {
+        let sine: Vec<f64> = (0..1200)
+            .map(|t| 10. + (t as f64 * std::f64::consts::TAU / 120.).sin())
+            .collect();
+        let (mean, period) = statistics(&sine);
+        assert!((mean - 10.).abs() < 0.01);
+        assert_eq!(period, 120.);
+    }
+    #[test]
+    fn reference_period() {
+        // about 2 hours, see the README, with fewer simulations
+        let (_, period) = reference(10, 3000);
+        assert!((period - 120.).abs() < 30., "{period}");
+    }
+}
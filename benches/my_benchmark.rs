@@ -1,7 +1,8 @@
 #![allow(unused_variables)]
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
 use rebop::define_system;
-use rebop::gillespie::{Gillespie, Rate};
+use rebop::gillespie::{Gillespie, Method, Rate, Stepper};
+use rebop::qmc::{uniformized_ensemble, Sampling};
 
 fn bench_sir(c: &mut Criterion) {
     define_system! {
@@ -35,6 +36,26 @@ fn bench_sir(c: &mut Criterion) {
     group.finish();
 }
 
+fn bench_short_runs(c: &mut Criterion) {
+    let mut sir = Gillespie::new_with_seed([999, 1, 0], 0);
+    sir.add_reaction(Rate::lma(1e-4, [1, 1, 0]), [-1, 1, 0]);
+    sir.add_reaction(Rate::lma(0.01, [0, 1, 0]), [0, -1, 1]);
+    let mut group = c.benchmark_group("short_runs");
+    group.bench_function("api", |b| {
+        b.iter(|| {
+            for _ in 0..1000 {
+                let mut problem = sir.clone();
+                problem.advance_until(0.1);
+            }
+        })
+    });
+    group.bench_function("stepper", |b| {
+        let mut stepper = Stepper::new(sir.clone());
+        b.iter(|| stepper.run_many(1000, 0.1, |_| {}))
+    });
+    group.finish();
+}
+
 fn bench_dimers(c: &mut Criterion) {
     define_system! {
         r_tx r_tl r_dim r_decay_mrna r_decay_prot;
@@ -192,8 +213,8 @@ fn macro_erk() {
 
 fn bench_erk(c: &mut Criterion) {
     let mut group = c.benchmark_group("erk");
-    group.bench_function("macro", |b| b.iter(|| macro_erk()));
-    group.bench_function("api", |b| b.iter(|| api_erk()));
+    group.bench_function("macro", |b| b.iter(macro_erk));
+    group.bench_function("api", |b| b.iter(api_erk));
     group.finish();
 }
 
@@ -235,7 +256,7 @@ fn bench_mm(c: &mut Criterion) {
 
 #[rustfmt::skip]
 #[allow(non_snake_case)]
-fn api_vilar() {
+fn api_vilar(method: Method) {
     // Da, Dr, Dpa, Dpr, Ma, Mr, A, R, C
     let alphaA = 50.;
     let alphapA = 500.;
@@ -269,6 +290,7 @@ fn api_vilar() {
     vilar.add_reaction(Rate::lma(deltaMR, [0, 0, 0, 0, 0, 1, 0, 0, 0]), [0, 0, 0, 0, 0, -1, 0, 0, 0]);
     vilar.add_reaction(Rate::lma(deltaA,  [0, 0, 0, 0, 0, 0, 1, 0, 0]), [0, 0, 0, 0, 0, 0, -1, 0, 0]);
     vilar.add_reaction(Rate::lma(deltaR,  [0, 0, 0, 0, 0, 0, 0, 1, 0]), [0, 0, 0, 0, 0, 0, 0, -1, 0]);
+    vilar.set_method(method);
     vilar.advance_until(200.);
 }
 
@@ -367,7 +389,10 @@ fn bench_vilar(c: &mut Criterion) {
             vilar.advance_until(200.);
         })
     });
-    group.bench_function("api/normal_order", |b| b.iter(|| api_vilar()));
+    group.bench_function("api/normal_order", |b| b.iter(|| api_vilar(Method::Direct)));
+    group.bench_function("api/sorting_direct", |b| {
+        b.iter(|| api_vilar(Method::SortingDirect))
+    });
     group.finish();
 }
 
@@ -624,21 +649,11 @@ fn bench_ring(c: &mut Criterion) {
             })
         });
     }
-    group.bench_function(BenchmarkId::new("macro", 10), |b| {
-        b.iter(|| macro_ring_10())
-    });
-    group.bench_function(BenchmarkId::new("macro", 20), |b| {
-        b.iter(|| macro_ring_20())
-    });
-    group.bench_function(BenchmarkId::new("macro", 30), |b| {
-        b.iter(|| macro_ring_30())
-    });
-    group.bench_function(BenchmarkId::new("macro", 40), |b| {
-        b.iter(|| macro_ring_40())
-    });
-    group.bench_function(BenchmarkId::new("macro", 50), |b| {
-        b.iter(|| macro_ring_50())
-    });
+    group.bench_function(BenchmarkId::new("macro", 10), |b| b.iter(macro_ring_10));
+    group.bench_function(BenchmarkId::new("macro", 20), |b| b.iter(macro_ring_20));
+    group.bench_function(BenchmarkId::new("macro", 30), |b| b.iter(macro_ring_30));
+    group.bench_function(BenchmarkId::new("macro", 40), |b| b.iter(macro_ring_40));
+    group.bench_function(BenchmarkId::new("macro", 50), |b| b.iter(macro_ring_50));
     group.finish();
 }
 
@@ -722,9 +737,42 @@ fn bench_flocculation(c: &mut Criterion) {
     group.finish();
 }
 
+fn bench_uniformization(c: &mut Criterion) {
+    let mut birth_death = Gillespie::new([0]);
+    birth_death.add_reaction(Rate::lma(10., [0]), [1]);
+    birth_death.add_reaction(Rate::lma(1., [1]), [-1]);
+    let times = [0.5, 1.];
+    // variance of the mean amount at t = 1 over independent seeds
+    let variance = |sampling| {
+        let estimates: Vec<f64> = (0..50)
+            .map(|seed| {
+                let runs = uniformized_ensemble(&birth_death, &times, 1024, 30., sampling, seed);
+                runs.iter().map(|run| run.species[0][1] as f64).sum::<f64>() / 1024.
+            })
+            .collect();
+        let mean = estimates.iter().sum::<f64>() / 50.;
+        estimates.iter().map(|e| (e - mean).powi(2)).sum::<f64>() / 49.
+    };
+    println!(
+        "uniformization: variance reduction of quasi-Monte Carlo: {:.1}",
+        variance(Sampling::MonteCarlo) / variance(Sampling::QuasiMonteCarlo)
+    );
+    let mut group = c.benchmark_group("uniformization");
+    for (name, sampling) in [
+        ("mc", Sampling::MonteCarlo),
+        ("qmc", Sampling::QuasiMonteCarlo),
+    ] {
+        group.bench_function(name, |b| {
+            b.iter(|| uniformized_ensemble(&birth_death, &times, 1024, 30., sampling, 0))
+        });
+    }
+    group.finish();
+}
+
 criterion_group!(
     benches,
     bench_sir,
+    bench_short_runs,
     bench_dimers,
     bench_dimers2,
     bench_mm,
@@ -732,6 +780,7 @@ criterion_group!(
     bench_vilar,
     bench_flocculation,
     bench_ring,
+    bench_uniformization,
 );
 
 criterion_main!(benches);
@@ -0,0 +1,64 @@
+//! Ensemble of SIR epidemics, summarized by their mean and a 90%
+//! confidence band over time.
+//!
+//! `cargo run --release --features examples --example ensemble_sir`
+//! writes `ensemble_sir.csv`, which `examples/plot.py` turns into a
+//! figure.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use rand::RngCore;
+use rebop::gillespie::{Gillespie, Rate, Trajectory};
+use rebop::rng::Philox;
+use rebop::stats::EnsembleStats;
+
+const NAMES: [&str; 3] = ["S", "I", "R"];
+
+fn main() -> std::io::Result<()> {
+    let mut sir = Gillespie::new([999, 1, 0]);
+    sir.add_reaction(Rate::lma(1e-4, [1, 1, 0]), [-1, 1, 0]);
+    sir.add_reaction(Rate::lma(0.01, [0, 1, 0]), [0, -1, 1]);
+    let times: Vec<f64> = (0..=250).map(f64::from).collect();
+    // the statistics do not keep the trajectories, so that the number of
+    // replicates is only limited by time
+    let mut stats = EnsembleStats::new(&times, NAMES.len()).with_quantiles();
+    for irun in 0..10_000 {
+        let mut problem = sir.clone();
+        problem.seed(Philox::new(0, irun).next_u64());
+        let mut trajectory = Trajectory::new(NAMES.len());
+        for &t in &times {
+            problem.advance_until(t);
+            trajectory.push(&problem);
+        }
+        stats.push(&trajectory);
+    }
+    let mut file = BufWriter::new(File::create("ensemble_sir.csv")?);
+    write!(file, "time")?;
+    for name in NAMES {
+        write!(file, ",{name}_mean,{name}_q05,{name}_q95")?;
+    }
+    writeln!(file)?;
+    let bands: Vec<[Vec<f64>; 3]> = (0..NAMES.len())
+        .map(|s| {
+            [
+                stats.mean(s).to_vec(),
+                stats.quantile(s, 0.05),
+                stats.quantile(s, 0.95),
+            ]
+        })
+        .collect();
+    for (i, t) in times.iter().enumerate() {
+        write!(file, "{t}")?;
+        for [mean, low, high] in &bands {
+            write!(file, ",{},{},{}", mean[i], low[i], high[i])?;
+        }
+        writeln!(file)?;
+    }
+    println!(
+        "{} epidemics, {:.1} recovered on average at t = 250",
+        stats.count(),
+        bands[2][0][250]
+    );
+    Ok(())
+}
@@ -0,0 +1,77 @@
+//! Accuracy and speed of R-leaping compared to the exact direct method,
+//! on the model of gene expression with dimerization of the benchmarks.
+//!
+//! `cargo run --release --features examples --example leaping_comparison`
+//! writes the mean and standard deviation of the amount of dimers over
+//! time for each method in `leaping_comparison.csv`, which
+//! `examples/plot.py` turns into a figure, and prints the timings.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::Instant;
+
+use rand::RngCore;
+use rebop::gillespie::{Gillespie, Method, Rate, Trajectory};
+use rebop::rng::Philox;
+use rebop::stats::EnsembleStats;
+
+const NB_RUNS: u64 = 200;
+
+fn main() -> std::io::Result<()> {
+    let mut dimers = Gillespie::new([1, 0, 0, 0]);
+    dimers.add_reaction(Rate::lma(25., [1, 0, 0, 0]), [0, 1, 0, 0]);
+    dimers.add_reaction(Rate::lma(1000., [0, 1, 0, 0]), [0, 0, 1, 0]);
+    dimers.add_reaction(Rate::lma(0.001, [0, 0, 2, 0]), [0, 0, -2, 1]);
+    dimers.add_reaction(Rate::lma(0.1, [0, 1, 0, 0]), [0, -1, 0, 0]);
+    dimers.add_reaction(Rate::lma(1., [0, 0, 1, 0]), [0, 0, -1, 0]);
+    let times: Vec<f64> = (0..=100).map(|i| i as f64 / 10.).collect();
+    let methods = [
+        Method::Direct,
+        Method::RLeaping(10),
+        Method::RLeaping(100),
+        Method::RLeaping(1000),
+    ];
+    let mut results = Vec::new();
+    for method in methods {
+        let mut stats = EnsembleStats::new(&times, 4);
+        let start = Instant::now();
+        for irun in 0..NB_RUNS {
+            let mut problem = dimers.clone();
+            problem.set_method(method);
+            problem.seed(Philox::new(0, irun).next_u64());
+            let mut trajectory = Trajectory::new(4);
+            for &t in &times {
+                problem.advance_until(t);
+                trajectory.push(&problem);
+            }
+            stats.push(&trajectory);
+        }
+        let duration = start.elapsed();
+        let (mean, variance) = (stats.mean(3)[100], stats.variance(3)[100]);
+        println!(
+            "{:>15}: {duration:>10.2?}, {mean:.1} ± {:.1} dimers at t = 10",
+            method.to_string(),
+            variance.sqrt()
+        );
+        results.push((method, stats));
+    }
+    let mut file = BufWriter::new(File::create("leaping_comparison.csv")?);
+    write!(file, "time")?;
+    for (method, _) in &results {
+        write!(file, ",{method}_mean,{method}_sd")?;
+    }
+    writeln!(file)?;
+    for (i, t) in times.iter().enumerate() {
+        write!(file, "{t}")?;
+        for (_, stats) in &results {
+            write!(
+                file,
+                ",{},{}",
+                stats.mean(3)[i],
+                stats.variance(3)[i].sqrt()
+            )?;
+        }
+        writeln!(file)?;
+    }
+    Ok(())
+}
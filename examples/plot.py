@@ -0,0 +1,65 @@
+"""Plot the CSV files written by the tutorial examples.
+
+Run the examples with `cargo run --release --features examples --example <name>`,
+then `python examples/plot.py` in the same directory to save one PNG per CSV file.
+"""
+
+from pathlib import Path
+
+import matplotlib.pyplot as plt
+import numpy as np
+
+
+def plot_ensemble_sir(path: Path) -> None:
+    """Plot the mean and the 90% band of each species of the SIR model."""
+    data = np.genfromtxt(path, delimiter=",", names=True)
+    for i, name in enumerate("SIR"):
+        plt.fill_between(
+            data["time"],
+            data[f"{name}_q05"],
+            data[f"{name}_q95"],
+            color=f"C{i}",
+            alpha=0.3,
+        )
+        plt.plot(data["time"], data[f"{name}_mean"], color=f"C{i}", label=name)
+    plt.xlabel("Time")
+    plt.ylabel("Number of individuals")
+
+
+def plot_vilar_period(path: Path) -> None:
+    """Plot a trajectory of the Vilar oscillator."""
+    data = np.genfromtxt(path, delimiter=",", names=True)
+    for name in ["A", "R", "C"]:
+        plt.plot(data["time"], data[name], label=name)
+    plt.xlabel("Time")
+    plt.ylabel("Number of molecules")
+
+
+def plot_leaping_comparison(path: Path) -> None:
+    """Plot the mean and standard deviation of dimers for each method."""
+    with path.open() as file:
+        columns = file.readline().rstrip().split(",")
+    data = np.loadtxt(path, delimiter=",", skiprows=1)
+    for i in range(1, len(columns), 2):
+        mean, sd = data[:, i], data[:, i + 1]
+        color = f"C{i // 2}"
+        plt.fill_between(data[:, 0], mean - sd, mean + sd, color=color, alpha=0.2)
+        plt.plot(data[:, 0], mean, color=color, label=columns[i].removesuffix("_mean"))
+    plt.xlabel("Time")
+    plt.ylabel("Number of dimers")
+
+
+PLOTS = {
+    "ensemble_sir": plot_ensemble_sir,
+    "vilar_period": plot_vilar_period,
+    "leaping_comparison": plot_leaping_comparison,
+}
+
+for name, plot in PLOTS.items():
+    path = Path(f"{name}.csv")
+    if path.exists():
+        plot(path)
+        plt.grid(visible=True)
+        plt.legend()
+        plt.savefig(path.with_suffix(".png"), bbox_inches="tight")
+        plt.close()
@@ -0,0 +1,96 @@
+//! Period of the Vilar oscillator (Vilar et al., PNAS 2002), from the
+//! autocorrelation of the amount of repressor.
+//!
+//! `cargo run --release --features examples --example vilar_period`
+//! writes a trajectory in `vilar_period.csv`, which `examples/plot.py`
+//! turns into a figure, and prints the period estimated from several
+//! trajectories.
+
+#![allow(mixed_script_confusables, non_snake_case)]
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use rebop::define_system;
+
+define_system! {
+    αA = 50. αpA = 500. αR = 0.01 αpR = 50. βA = 50. βR = 5. δMA = 10. δMR = 0.5
+    δA = 1. δR = 0.2 γA = 1. γR = 1. γC = 2. θA = 50. θR = 100.;
+    Vilar { Da = 1, Dr = 1, Dpa, Dpr, Ma, Mr, A, R, C }
+    r_activation_a      : Da + A    => Dpa      @ γA
+    r_activation_r      : Dr + A    => Dpr      @ γR
+    r_deactivation_a    : Dpa       => Da + A   @ θA
+    r_deactivation_r    : Dpr       => Dr + A   @ θR
+    r_transcription_a   : Da        => Da + Ma  @ αA
+    r_transcription_r   : Dr        => Dr + Mr  @ αR
+    r_transcription_p_a : Dpa       => Dpa + Ma @ αpA
+    r_transcription_p_r : Dpr       => Dpr + Mr @ αpR
+    r_translation_a     : Ma        => Ma + A   @ βA
+    r_translation_r     : Mr        => Mr + R   @ βR
+    r_complexation      : A + R     => C        @ γC
+    r_decomplexation    : C         => R        @ δA
+    r_decay_mRNA_a      : Ma        =>          @ δMA
+    r_decay_mRNA_r      : Mr        =>          @ δMR
+    r_decay_prot_a      : A         =>          @ δA
+    r_decay_prot_r      : R         =>          @ δR
+}
+
+/// Returns the lag of the first peak of the autocorrelation of a series
+/// sampled at regular intervals, after its first negative value.
+fn period(series: &[f64]) -> usize {
+    let n = series.len();
+    let mean = series.iter().sum::<f64>() / n as f64;
+    let correlations: Vec<f64> = (0..n / 2)
+        .map(|lag| {
+            (0..n - lag)
+                .map(|i| (series[i] - mean) * (series[i + lag] - mean))
+                .sum::<f64>()
+                / (n - lag) as f64
+        })
+        .collect();
+    let negative = correlations.iter().position(|&c| c < 0.).unwrap();
+    let positive = negative
+        + correlations[negative..]
+            .iter()
+            .position(|&c| c > 0.)
+            .unwrap();
+    let end = positive
+        + correlations[positive..]
+            .iter()
+            .position(|&c| c < 0.)
+            .unwrap_or(n / 2 - positive);
+    (positive..end)
+        .max_by(|&a, &b| correlations[a].total_cmp(&correlations[b]))
+        .unwrap()
+}
+
+fn main() -> std::io::Result<()> {
+    // sampled every 0.1 time unit until 400, after a transient of 50
+    let times: Vec<f64> = (500..=4500).map(|i| i as f64 / 10.).collect();
+    let mut periods = Vec::new();
+    for seed in 0..20 {
+        let mut vilar = Vilar::new();
+        vilar.seed(seed);
+        let mut repressor = Vec::new();
+        let mut file = if seed == 0 {
+            let mut file = BufWriter::new(File::create("vilar_period.csv")?);
+            writeln!(file, "time,A,R,C")?;
+            Some(file)
+        } else {
+            None
+        };
+        for &t in &times {
+            vilar.advance_until(t);
+            repressor.push(vilar.R as f64);
+            if let Some(file) = &mut file {
+                writeln!(file, "{t},{},{},{}", vilar.A, vilar.R, vilar.C)?;
+            }
+        }
+        periods.push(period(&repressor) as f64 / 10.);
+    }
+    let mean = periods.iter().sum::<f64>() / periods.len() as f64;
+    let sd = (periods.iter().map(|p| (p - mean).powi(2)).sum::<f64>() / (periods.len() - 1) as f64)
+        .sqrt();
+    println!("period: {mean:.1} ± {sd:.1} time units");
+    Ok(())
+}
@@ -0,0 +1,76 @@
+/*
+ * C interface of rebop, built with the `capi` feature:
+ *
+ *     cargo build --release --features capi
+ *
+ * and linked with the dynamic library `target/release/librebop.so`.  See
+ * the documentation of the module `rebop::capi` for the details.
+ */
+
+#ifndef REBOP_H
+#define REBOP_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Opaque problem, simulated by the Gillespie algorithm. */
+typedef struct rebop_gillespie rebop_gillespie;
+
+/*
+ * Creates a problem with `nb_species` species of initial amounts `species`,
+ * which can be NULL if `nb_species` is 0, or returns NULL.
+ */
+rebop_gillespie *rebop_new(size_t nb_species, const int64_t *species);
+
+/* Creates the problem of a model in the TOML format, or returns NULL. */
+rebop_gillespie *rebop_from_toml(const char *toml);
+
+/* Releases a problem, which can be NULL. */
+void rebop_free(rebop_gillespie *problem);
+
+/*
+ * Adds a reaction of law of mass action rate `rate`, with one reactant
+ * stoichiometry and one change per species.  Returns 0, or -1 on error.
+ */
+int rebop_add_reaction(rebop_gillespie *problem, double rate,
+                       const uint32_t *reactants, const int64_t *differences);
+
+/* Do nothing if `problem` is NULL. */
+void rebop_seed(rebop_gillespie *problem, uint64_t seed);
+void rebop_advance_until(rebop_gillespie *problem, double tmax);
+
+/* Return NaN or 0 if `problem` is NULL. */
+double rebop_get_time(const rebop_gillespie *problem);
+size_t rebop_nb_species(const rebop_gillespie *problem);
+size_t rebop_nb_reactions(const rebop_gillespie *problem);
+
+/*
+ * Writes the amount of the species of index `species` in `amount`.  Returns
+ * 0, or -1 on error, as when there is no such species.
+ */
+int rebop_get_species(const rebop_gillespie *problem, size_t species,
+                      int64_t *amount);
+
+/* Sets the amounts of all species.  Returns 0, or -1 on error. */
+int rebop_set_species(rebop_gillespie *problem, const int64_t *species);
+
+/*
+ * Simulates `nb_runs` runs in parallel from the current state until `tmax`
+ * with `nb_steps` steps, and writes the amount of the species `s` at the
+ * time point `i` of the run `r` in
+ * `out[(r * (nb_steps + 1) + i) * nb_species + s]`.  Returns 0, or -1 on
+ * error.
+ */
+int rebop_run_ensemble(const rebop_gillespie *problem, double tmax,
+                       size_t nb_steps, size_t nb_runs, uint64_t seed,
+                       int64_t *out);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* REBOP_H */
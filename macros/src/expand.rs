@@ -0,0 +1,404 @@
+//! Code generation for the reaction network DSL.
+
+use proc_macro2::{Group, Span, TokenStream, TokenTree};
+use quote::{format_ident, quote};
+use syn::Ident;
+
+use crate::parse::{Invocation, Stoichiometry, Term};
+
+/// Identifier that cannot clash with the names chosen by the user.
+fn internal(name: &str) -> Ident {
+    Ident::new(name, Span::mixed_site())
+}
+
+/// Stoichiometry of a term, as an integer expression.
+fn stoichiometry(term: &Term) -> TokenStream {
+    match &term.stoichiometry {
+        Stoichiometry::One => quote!(1),
+        Stoichiometry::Literal(n) => quote!(#n),
+        Stoichiometry::Expr(n) => quote!((#n)),
+    }
+}
+
+/// Propensity factor of a reactant: the falling factorial of its
+/// amount.
+fn factor(term: &Term) -> TokenStream {
+    let species = &term.species;
+    let rate = internal("rate");
+    let i = internal("i");
+    match &term.stoichiometry {
+        Stoichiometry::One => quote!(self.#species as f64),
+        _ => {
+            let n = stoichiometry(term);
+            // in floating point, large stoichiometries would overflow
+            quote! {{
+                let mut #rate = self.#species as f64;
+                for #i in 1..#n {
+                    #rate *= (self.#species - #i) as f64;
+                }
+                #rate
+            }}
+        }
+    }
+}
+
+/// Replaces the identifier `t` by the current time of the problem.
+fn with_time(tokens: TokenStream) -> TokenStream {
+    tokens
+        .into_iter()
+        .flat_map(|token| match token {
+            TokenTree::Ident(ident) if ident == "t" => quote!(self.t),
+            TokenTree::Group(group) => {
+                let mut new = Group::new(group.delimiter(), with_time(group.stream()));
+                new.set_span(group.span());
+                TokenTree::Group(new).into()
+            }
+            token => token.into(),
+        })
+        .collect()
+}
+
+/// Generates the structures and methods of the reaction network.
+pub fn expand(invocation: Invocation) -> TokenStream {
+    let Invocation { krate, system } = invocation;
+    let name = &system.name;
+    let trace = format_ident!("{}Trace", name);
+    let species: Vec<&Ident> = system.species.iter().map(|s| &s.name).collect();
+    let species_defaults: Vec<TokenStream> = system
+        .species
+        .iter()
+        .map(|s| match &s.default {
+            Some(default) => quote!(#default),
+            None => quote!(0),
+        })
+        .collect();
+    let params: Vec<&Ident> = system.params.iter().map(|p| &p.name).collect();
+    let param_defaults: Vec<TokenStream> = system
+        .params
+        .iter()
+        .map(|p| match &p.default {
+            Some(default) => quote!(#default as f64),
+            None => quote!(f64::NAN),
+        })
+        .collect();
+    let events: Vec<&Ident> = system.events.iter().map(|e| &e.name).collect();
+    let event_names: Vec<String> = events.iter().map(|e| e.to_string()).collect();
+    let conditions: Vec<TokenStream> = system
+        .events
+        .iter()
+        .map(|e| with_time(e.condition.clone()))
+        .collect();
+    let bodies: Vec<&TokenStream> = system.events.iter().map(|e| &e.body).collect();
+
+    let tmax = internal("tmax");
+    let seed = internal("seed");
+    let t = internal("t");
+    let t0 = internal("t0");
+    let t_event = internal("t_event");
+    let t_next = internal("t_next");
+    let total_rate = internal("total_rate");
+    let reaction_choice = internal("reaction_choice");
+    let (lo, hi, mid) = (internal("lo"), internal("hi"), internal("mid"));
+    let (names, index, problem) = (internal("names"), internal("index"), internal("problem"));
+    let (reactants, jump) = (internal("reactants"), internal("jump"));
+    let (record, i, n_steps) = (internal("trace"), internal("i"), internal("n_steps"));
+    let (n, fired) = (internal("n"), internal("fired"));
+
+    // rate of each reaction
+    let rates: Vec<Ident> = (0..system.reactions.len())
+        .map(|i| Ident::new(&format!("rate_{i}"), Span::mixed_site()))
+        .collect();
+    let propensities: Vec<TokenStream> = system
+        .reactions
+        .iter()
+        .zip(&rates)
+        .map(|(reaction, rname)| {
+            let rate = &reaction.rate;
+            if reaction.reactants.is_empty() {
+                return quote!(let #rname = #rate;);
+            }
+            let factors = reaction.reactants.iter().map(factor);
+            quote!(let #rname = (#rate) #(* #factors)*;)
+        })
+        .collect();
+    // selection of the reaction that happens
+    let mut choice = quote!();
+    for (reaction, rname) in system.reactions.iter().zip(&rates).rev() {
+        let consumed = reaction.reactants.iter().map(|term| {
+            let species = &term.species;
+            let n = stoichiometry(term);
+            quote!(self.#species -= #n;)
+        });
+        let produced = reaction.products.iter().map(|term| {
+            let species = &term.species;
+            let n = stoichiometry(term);
+            quote!(self.#species += #n;)
+        });
+        let previous = rates.iter().take_while(|r| *r != rname);
+        choice = quote! {
+            if #reaction_choice < 0. #(+ #previous)* + #rname {
+                #(#consumed)*
+                #(#produced)*
+            } else {
+                #choice
+            }
+        };
+    }
+    // conversion to the function-based API
+    let conversions = system.reactions.iter().map(|reaction| {
+        let rate = &reaction.rate;
+        let consumed = reaction.reactants.iter().map(|term| {
+            let species = term.species.to_string();
+            let n = stoichiometry(term);
+            quote! {
+                #reactants[#index(#species)] += #n as u32;
+                #jump[#index(#species)] -= #n as isize;
+            }
+        });
+        let produced = reaction.products.iter().map(|term| {
+            let species = term.species.to_string();
+            let n = stoichiometry(term);
+            quote!(#jump[#index(#species)] += #n as isize;)
+        });
+        quote! {
+            let mut #reactants = vec![0; #names.len()];
+            let mut #jump = vec![0; #names.len()];
+            #(#consumed)*
+            #(#produced)*
+            #problem.add_reaction(#krate::gillespie::Rate::lma(#rate, #reactants), #jump);
+        }
+    });
+    let nb_species = species.len();
+    let species_names: Vec<String> = species.iter().map(|s| s.to_string()).collect();
+
+    quote! {
+        /// Structure representing the problem, with the species and the time.
+        #[allow(non_snake_case, clippy::upper_case_acronyms)]
+        #[derive(Clone, Debug)]
+        struct #name {
+            #(#species: isize,)*
+            #(#params: f64,)*
+            #(#events: bool,)*
+            t: f64,
+            rng: #krate::rand::rngs::SmallRng,
+        }
+        #[allow(dead_code)]
+        impl #name {
+            /// Names of the events of the problem.
+            const EVENTS: &'static [&'static str] = &[#(#event_names),*];
+            /// Constructs an object representing the problem.
+            fn new() -> Self {
+                use #krate::rand::SeedableRng;
+                #name {
+                    #(#species: #species_defaults,)*
+                    #(#params: #param_defaults,)*
+                    #(#events: false,)*
+                    t: 0.,
+                    rng: #krate::rand::rngs::SmallRng::from_entropy()
+                }
+            }
+            /// Seeds the random number generator.
+            fn seed(&mut self, #seed: u64) {
+                use #krate::rand::SeedableRng;
+                self.rng = #krate::rand::rngs::SmallRng::seed_from_u64(#seed);
+            }
+            /// Constructs an object representing the problem,
+            /// specifying parameter values.
+            #[allow(non_snake_case, clippy::too_many_arguments)]
+            fn with_parameters(#(#params: f64),*) -> Self {
+                use #krate::rand::SeedableRng;
+                #name {
+                    #(#species: #species_defaults,)*
+                    #(#params,)*
+                    #(#events: false,)*
+                    t: 0.,
+                    rng: #krate::rand::rngs::SmallRng::from_entropy()
+                }
+            }
+            /// Converts the problem into a problem of the function-based
+            /// API, in its current state.
+            #[allow(non_snake_case, unused_variables, unused_mut)]
+            fn to_gillespie(&self) -> #krate::gillespie::Gillespie {
+                assert!(Self::EVENTS.is_empty(), "events cannot be converted");
+                let #names = [#(#species_names),*];
+                let #index = |name: &str| #names.iter().position(|&n| n == name).unwrap();
+                #(let #params = self.#params;)*
+                let mut #problem = #krate::gillespie::Gillespie::new([#(self.#species),*]);
+                #problem.set_time(self.t);
+                #(#conversions)*
+                #problem
+            }
+            /// Fires the events whose conditions are satisfied.
+            #[allow(non_snake_case, unused_variables, unused_mut)]
+            fn fire_events(&mut self) {
+                #(let mut #species = self.#species;)*
+                #(let mut #params = self.#params;)*
+                #(
+                    if !self.#events && (#conditions) {
+                        self.#events = true;
+                        #bodies
+                    }
+                )*
+                #(self.#species = #species;)*
+                #(self.#params = #params;)*
+            }
+            /// Returns the earliest time in `(self.t, tmax]` at which the
+            /// condition of an event becomes true, the state being fixed,
+            /// among the conditions that are true at `tmax`: a condition
+            /// that becomes false again before `tmax` is not detected.
+            #[allow(non_snake_case, unused_variables, unused_mut)]
+            fn next_event_time(&mut self, #tmax: f64) -> f64 {
+                let #t = self.t;
+                let mut #t_event = f64::INFINITY;
+                #(let #species = self.#species;)*
+                #(let #params = self.#params;)*
+                #(
+                    self.t = #tmax;
+                    if !self.#events && (#conditions) {
+                        // bisection between a time where the condition is
+                        // false and a time where it is true
+                        let (mut #lo, mut #hi) = (#t, #tmax);
+                        loop {
+                            let #mid = 0.5 * (#lo + #hi);
+                            if #mid <= #lo || #mid >= #hi {
+                                break;
+                            }
+                            self.t = #mid;
+                            if (#conditions) {
+                                #hi = #mid;
+                            } else {
+                                #lo = #mid;
+                            }
+                        }
+                        #t_event = #t_event.min(#hi);
+                    }
+                )*
+                self.t = #t;
+                #t_event
+            }
+            /// Simulates the problem until `t = tmax`.
+            #[allow(non_snake_case, unused_variables, unused_mut, unused_assignments)]
+            fn advance_until(&mut self, #tmax: f64) {
+                use #krate::rand::Rng;
+                #(let mut #params = self.#params;)*
+                #(let #species = self.#species as f64;)*
+                loop {
+                    if !Self::EVENTS.is_empty() {
+                        self.fire_events();
+                        #(#params = self.#params;)*
+                    }
+                    #(#propensities)*
+                    let #total_rate = 0. #(+ #rates)*;
+                    // we don't want to use partial_cmp, for performance
+                    #[allow(clippy::neg_cmp_op_on_partial_ord)]
+                    let #t_next = if !(#total_rate > 0.) {
+                        f64::INFINITY
+                    } else {
+                        self.t + self.rng.sample::<f64, _>(#krate::rand_distr::Exp1) / #total_rate
+                    };
+                    if !Self::EVENTS.is_empty() {
+                        // the process being memoryless, the sampled reaction
+                        // can be discarded if an event happens before
+                        let #t_event = self.next_event_time(#t_next.min(#tmax));
+                        if #t_event < f64::INFINITY {
+                            self.t = #t_event;
+                            continue;
+                        }
+                    }
+                    if #t_next > #tmax {
+                        self.t = #tmax;
+                        return
+                    }
+                    self.t = #t_next;
+                    let #reaction_choice = #total_rate * self.rng.gen::<f64>();
+                    #choice
+                }
+            }
+            /// Simulates `n` reactions, or fewer if the total rate becomes
+            /// zero, and returns the number of reactions that fired.
+            #[allow(non_snake_case, unused_variables, unused_mut, unused_assignments)]
+            fn advance_n_reactions(&mut self, #n: usize) -> usize {
+                use #krate::rand::Rng;
+                #(let mut #params = self.#params;)*
+                #(let #species = self.#species as f64;)*
+                for #fired in 0..#n {
+                    loop {
+                        if !Self::EVENTS.is_empty() {
+                            self.fire_events();
+                            #(#params = self.#params;)*
+                        }
+                        #(#propensities)*
+                        let #total_rate = 0. #(+ #rates)*;
+                        // we don't want to use partial_cmp, for performance
+                        #[allow(clippy::neg_cmp_op_on_partial_ord)]
+                        if !(#total_rate > 0.) {
+                            return #fired;
+                        }
+                        let #t_next = self.t
+                            + self.rng.sample::<f64, _>(#krate::rand_distr::Exp1) / #total_rate;
+                        if !Self::EVENTS.is_empty() {
+                            let #t_event = self.next_event_time(#t_next);
+                            if #t_event < f64::INFINITY {
+                                self.t = #t_event;
+                                continue;
+                            }
+                        }
+                        self.t = #t_next;
+                        let #reaction_choice = #total_rate * self.rng.gen::<f64>();
+                        #choice
+                        break;
+                    }
+                }
+                #n
+            }
+        }
+        impl #krate::system::SSASystem for #name {
+            fn nb_species(&self) -> usize {
+                #nb_species
+            }
+            fn species(&self) -> ::std::borrow::Cow<'_, [isize]> {
+                ::std::borrow::Cow::Owned(vec![#(self.#species),*])
+            }
+            fn t(&self) -> f64 {
+                self.t
+            }
+            fn seed(&mut self, #seed: u64) {
+                #name::seed(self, #seed)
+            }
+            fn advance_until(&mut self, #tmax: f64) {
+                #name::advance_until(self, #tmax)
+            }
+        }
+        /// Values of all species at successive time points.
+        #[allow(non_snake_case, dead_code)]
+        #[derive(Clone, Debug, Default, PartialEq)]
+        struct #trace {
+            t: Vec<f64>,
+            #(#species: Vec<isize>,)*
+        }
+        #[allow(dead_code)]
+        impl #name {
+            /// Simulates the problem until `t = tmax`, recording the
+            /// state at `n_steps + 1` uniformly spaced time points
+            /// between the current time and `tmax`.
+            fn advance_and_record(&mut self, #tmax: f64, #n_steps: usize) -> #trace {
+                let #t0 = self.t;
+                let mut #record = #trace {
+                    t: Vec::with_capacity(#n_steps + 1),
+                    #(#species: Vec::with_capacity(#n_steps + 1),)*
+                };
+                for #i in 0..=#n_steps {
+                    let #t = if #i == #n_steps {
+                        #tmax
+                    } else {
+                        #t0 + (#tmax - #t0) * #i as f64 / #n_steps as f64
+                    };
+                    self.advance_until(#t);
+                    #record.t.push(self.t);
+                    #(#record.#species.push(self.#species);)*
+                }
+                #record
+            }
+        }
+    }
+}
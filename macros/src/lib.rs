@@ -0,0 +1,18 @@
+//! Procedural macros of rebop.
+//!
+//! This crate is an implementation detail of rebop: use the macros
+//! through their re-exports, documented in rebop.
+
+use proc_macro::TokenStream;
+use syn::parse_macro_input;
+
+mod expand;
+mod parse;
+
+/// Definition of a chemical reaction network, preceded by `$crate;`
+/// when called by the `define_system!` of rebop.
+#[proc_macro]
+pub fn define_system(input: TokenStream) -> TokenStream {
+    let invocation = parse_macro_input!(input as parse::Invocation);
+    expand::expand(invocation).into()
+}
@@ -0,0 +1,397 @@
+//! Parsing and validation of the reaction network DSL.
+
+use proc_macro2::TokenStream;
+use syn::ext::IdentExt;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{braced, parenthesized, token, Error, Expr, Ident, Lit, LitInt, Result, Token};
+
+/// Input of the procedural macro: the path of rebop, which is `$crate`
+/// in the `macro_rules!` wrapper of rebop, and the reaction network.
+pub struct Invocation {
+    pub krate: Ident,
+    pub system: System,
+}
+
+/// Reaction network, as written in `define_system!`.
+pub struct System {
+    pub params: Vec<Param>,
+    pub name: Ident,
+    pub species: Vec<Species>,
+    pub reactions: Vec<Reaction>,
+    pub events: Vec<Event>,
+}
+
+/// Parameter, with an optional default value.
+pub struct Param {
+    pub name: Ident,
+    pub default: Option<Lit>,
+}
+
+/// Species, with an optional initial amount.
+pub struct Species {
+    pub name: Ident,
+    pub default: Option<Expr>,
+}
+
+/// Reaction `name : reactants => products @ rate`.
+pub struct Reaction {
+    pub name: Ident,
+    pub reactants: Vec<Term>,
+    pub products: Vec<Term>,
+    pub rate: Expr,
+}
+
+/// Species in a reaction, with its stoichiometry.
+pub struct Term {
+    pub stoichiometry: Stoichiometry,
+    pub species: Ident,
+}
+
+/// Stoichiometry of a species in a reaction.
+pub enum Stoichiometry {
+    /// No stoichiometry given.
+    One,
+    /// Integer literal, as in `2 A`.
+    Literal(LitInt),
+    /// Parenthesized integer expression, as in `(N + 1) A`.
+    Expr(Expr),
+}
+
+/// Event `event name @ (condition) { body }`.
+pub struct Event {
+    pub name: Ident,
+    pub condition: TokenStream,
+    pub body: TokenStream,
+}
+
+impl Parse for Invocation {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let krate = Ident::parse_any(input)?;
+        input.parse::<Token![;]>()?;
+        let system = input.parse()?;
+        Ok(Invocation { krate, system })
+    }
+}
+
+impl Parse for System {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let mut params = Vec::new();
+        while !input.peek(Token![;]) {
+            if !input.peek(Ident) {
+                return Err(input.error("expected a parameter name or `;`"));
+            }
+            params.push(input.parse()?);
+        }
+        input.parse::<Token![;]>()?;
+        let name = input.parse()?;
+        let content;
+        braced!(content in input);
+        let species = Punctuated::<Species, Token![,]>::parse_terminated(&content)?
+            .into_iter()
+            .collect();
+        let mut reactions = Vec::new();
+        while !input.is_empty() && !input.peek(Token![;]) {
+            reactions.push(input.parse()?);
+        }
+        let mut events = Vec::new();
+        if input.peek(Token![;]) {
+            input.parse::<Token![;]>()?;
+            while !input.is_empty() {
+                events.push(input.parse()?);
+            }
+        }
+        let system = System {
+            params,
+            name,
+            species,
+            reactions,
+            events,
+        };
+        system.validate()?;
+        Ok(system)
+    }
+}
+
+impl Parse for Param {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let name = input.parse()?;
+        let default = if input.peek(Token![=]) {
+            input.parse::<Token![=]>()?;
+            Some(input.parse()?)
+        } else {
+            None
+        };
+        Ok(Param { name, default })
+    }
+}
+
+impl Parse for Species {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let name = input.parse()?;
+        let default = if input.peek(Token![=]) {
+            input.parse::<Token![=]>()?;
+            Some(input.parse()?)
+        } else {
+            None
+        };
+        Ok(Species { name, default })
+    }
+}
+
+impl Parse for Reaction {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let name = input.parse()?;
+        input.parse::<Token![:]>()?;
+        let reactants = parse_side(input, |input| input.peek(Token![=>]))?;
+        input.parse::<Token![=>]>()?;
+        let products = parse_side(input, |input| input.peek(Token![@]))?;
+        input.parse::<Token![@]>()?;
+        let rate = input.parse()?;
+        Ok(Reaction {
+            name,
+            reactants,
+            products,
+            rate,
+        })
+    }
+}
+
+/// Parses the species on one side of a reaction, until `end` is true.
+fn parse_side(input: ParseStream, end: fn(ParseStream) -> bool) -> Result<Vec<Term>> {
+    let mut terms = Vec::new();
+    if end(input) {
+        return Ok(terms);
+    }
+    terms.push(input.parse()?);
+    while input.peek(Token![+]) {
+        input.parse::<Token![+]>()?;
+        terms.push(input.parse()?);
+    }
+    Ok(terms)
+}
+
+impl Parse for Term {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let stoichiometry = if input.peek(LitInt) {
+            let n: LitInt = input.parse()?;
+            match n.base10_parse::<u32>() {
+                Ok(0) => return Err(Error::new(n.span(), "stoichiometry must be positive")),
+                Ok(_) if n.suffix().is_empty() => Stoichiometry::Literal(n),
+                _ => {
+                    return Err(Error::new(
+                        n.span(),
+                        "stoichiometry must be an integer without suffix, at most `u32::MAX`",
+                    ))
+                }
+            }
+        } else if input.peek(Lit) {
+            return Err(input.error("stoichiometry must be a positive integer"));
+        } else if input.peek(token::Paren) {
+            let content;
+            parenthesized!(content in input);
+            Stoichiometry::Expr(content.parse()?)
+        } else {
+            Stoichiometry::One
+        };
+        let species = input.parse()?;
+        Ok(Term {
+            stoichiometry,
+            species,
+        })
+    }
+}
+
+impl Parse for Event {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let keyword: Ident = input.parse()?;
+        if keyword != "event" {
+            return Err(Error::new(keyword.span(), "expected `event`"));
+        }
+        let name = input.parse()?;
+        input.parse::<Token![@]>()?;
+        let condition;
+        parenthesized!(condition in input);
+        let body;
+        braced!(body in input);
+        Ok(Event {
+            name,
+            condition: condition.parse()?,
+            body: body.parse()?,
+        })
+    }
+}
+
+impl System {
+    /// Checks that all names are unique and that the reactions only
+    /// involve known species, reporting all the errors at once.
+    fn validate(&self) -> Result<()> {
+        let mut errors = Vec::new();
+        let mut fields: Vec<(&Ident, &str)> = Vec::new();
+        let names = self
+            .species
+            .iter()
+            .map(|s| (&s.name, "species"))
+            .chain(self.params.iter().map(|p| (&p.name, "parameter")))
+            .chain(self.events.iter().map(|e| (&e.name, "event")));
+        for (name, kind) in names {
+            if name == "t" || name == "rng" {
+                errors.push(Error::new(
+                    name.span(),
+                    format!("`{name}` is reserved and cannot be the name of a {kind}"),
+                ));
+            } else if let Some((_, other)) = fields.iter().find(|(n, _)| *n == name) {
+                errors.push(Error::new(
+                    name.span(),
+                    format!("`{name}` is already defined as a {other}"),
+                ));
+            } else {
+                fields.push((name, kind));
+            }
+        }
+        for (i, reaction) in self.reactions.iter().enumerate() {
+            if self.reactions[..i].iter().any(|r| r.name == reaction.name) {
+                errors.push(Error::new(
+                    reaction.name.span(),
+                    format!("reaction `{}` is defined twice", reaction.name),
+                ));
+            }
+            for term in reaction.reactants.iter().chain(&reaction.products) {
+                if !self.species.iter().any(|s| s.name == term.species) {
+                    let mut message = format!(
+                        "unknown species `{}` in reaction `{}`",
+                        term.species, reaction.name
+                    );
+                    if let Some(closest) = self.closest_species(&term.species.to_string()) {
+                        message += &format!(", did you mean `{closest}`?");
+                    }
+                    errors.push(Error::new(term.species.span(), message));
+                }
+            }
+        }
+        match errors.into_iter().reduce(|mut all, error| {
+            all.combine(error);
+            all
+        }) {
+            Some(errors) => Err(errors),
+            None => Ok(()),
+        }
+    }
+    /// Returns the species whose name is close enough to `name`, if any.
+    fn closest_species(&self, name: &str) -> Option<&Ident> {
+        self.species
+            .iter()
+            .map(|s| (levenshtein(name, &s.name.to_string()), &s.name))
+            .filter(|&(distance, _)| distance <= 1.max(name.len() / 3) && distance < name.len())
+            .min_by_key(|&(distance, _)| distance)
+            .map(|(_, species)| species)
+    }
+}
+
+/// Edit distance between two strings.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse::{levenshtein, Stoichiometry, System};
+
+    fn error(input: &str) -> String {
+        match syn::parse_str::<System>(input) {
+            Ok(_) => panic!("no error"),
+            Err(errors) => errors
+                .into_iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let system: System = syn::parse_str(
+            "r_tx = 25. r_dim;
+            Dimers { gene = 1, mRNA, protein, dimer, }
+            transcription : gene => gene + mRNA @ r_tx
+            dimerization : 2 protein => dimer @ r_dim
+            decay : (1 + 1) dimer => @ 0.1;
+            event stop @ (t > 2.) { r_tx = 0.; }",
+        )
+        .unwrap();
+        assert_eq!(system.name, "Dimers");
+        assert_eq!(system.params.len(), 2);
+        assert!(system.params[0].default.is_some());
+        assert_eq!(system.species.len(), 4);
+        assert!(system.species[0].default.is_some());
+        assert_eq!(system.reactions.len(), 3);
+        assert_eq!(system.reactions[0].products.len(), 2);
+        let dimerization = &system.reactions[1].reactants[0];
+        assert!(matches!(
+            dimerization.stoichiometry,
+            Stoichiometry::Literal(_)
+        ));
+        let decay = &system.reactions[2];
+        assert!(matches!(
+            decay.reactants[0].stoichiometry,
+            Stoichiometry::Expr(_)
+        ));
+        assert!(decay.products.is_empty());
+        assert_eq!(system.events.len(), 1);
+    }
+
+    #[test]
+    fn diagnostics() {
+        assert_eq!(
+            error("r; A { protein } decay : proteinn => @ r"),
+            "unknown species `proteinn` in reaction `decay`, did you mean `protein`?"
+        );
+        assert_eq!(
+            error("r; A { X } decay : Y => @ r"),
+            "unknown species `Y` in reaction `decay`"
+        );
+        assert_eq!(
+            error("r; A { X, r, X } f : X => @ r f : => X @ r"),
+            "`X` is already defined as a species\n\
+             `r` is already defined as a species\n\
+             reaction `f` is defined twice"
+        );
+        assert_eq!(
+            error("; A { t }"),
+            "`t` is reserved and cannot be the name of a species"
+        );
+        assert_eq!(error("; A { X }; X < 1 { }"), "expected `event`");
+        assert_eq!(
+            error("; A { X } f : 0 X => @ 1."),
+            "stoichiometry must be positive"
+        );
+        assert_eq!(
+            error("; A { X } f : 2.5 X => @ 1."),
+            "stoichiometry must be a positive integer"
+        );
+        assert_eq!(
+            error("; A { X } f : 2u8 X => @ 1."),
+            "stoichiometry must be an integer without suffix, at most `u32::MAX`"
+        );
+        assert!(syn::parse_str::<System>("; A { X, Y } f : 12 X => 3 Y @ 1.").is_ok());
+    }
+
+    #[test]
+    fn distance() {
+        assert_eq!(levenshtein("protein", "protein"), 0);
+        assert_eq!(levenshtein("proteinn", "protein"), 1);
+        assert_eq!(levenshtein("mRNA", "RNA"), 1);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+    }
+}
@@ -1,34 +1,282 @@
 from __future__ import annotations
 
+from typing import TYPE_CHECKING
+
+import numpy as np
 import xarray as xr
 
-from .rebop import Gillespie, __version__
+from .interop import from_pysb, from_sbml
+from .rebop import Gillespie, Reaction, State, __version__
+
+if TYPE_CHECKING:
+    from collections.abc import Callable, Iterator, Mapping, Sequence
 
-__all__ = ("Gillespie", "__version__")
+__all__ = (
+    "Gillespie",
+    "Reaction",
+    "State",
+    "__version__",
+    "from_pysb",
+    "from_sbml",
+)
 
 og_run = Gillespie.run
+og_run_schedule = Gillespie.run_schedule
+og_run_ensemble = Gillespie.run_ensemble
+og_sweep = Gillespie.sweep
+og_run_ode = Gillespie.run_ode
+og_iter_run = Gillespie.iter_run
+
+
+def to_dataset(
+    times: list[float],
+    result: Mapping[str, Sequence[float]],
+    metadata: dict[str, object],
+) -> xr.Dataset:
+    """Convert the output of a simulation to an xarray Dataset.
+
+    The metadata of the simulation are stored in the attributes.
+    """
+    return xr.Dataset(
+        data_vars={
+            name: xr.DataArray(values, dims="time", coords={"time": times})
+            for name, values in result.items()
+        },
+        attrs=metadata,
+    )
+
+
+def run_xarray(  # noqa: PLR0913
+    self: Gillespie,
+    init: dict[str, int],
+    tmax: float | None = None,
+    nb_steps: int | None = None,
+    seed: int | None = None,
+    t_eval: Sequence[float] | None = None,
+    resume: State | None = None,
+    *,
+    return_state: bool = False,
+    firings: bool = False,
+    max_wall_time: float | None = None,
+    progress: bool | Callable[[float, float], object] = False,
+    method: str = "direct",
+) -> xr.Dataset | tuple[xr.Dataset, State]:
+    """Run the system until `tmax` with `nb_steps` steps.
+
+    The initial configuration is specified in the dictionary `init`.
+    Instead of `tmax` and `nb_steps`, `t_eval` can give sorted time points,
+    not necessarily uniformly spaced.  Returns an xarray Dataset, with the
+    metadata of the simulation in its attributes.
+
+    If `return_state` is true, also returns the `State` at the end of the
+    run, from which another run can continue when given as `resume`, for
+    example after changing rates.  The amounts given in `init` then replace
+    those of the state, and the time points start at its time.
+
+    If `firings` is true, the variable `firings` of the Dataset, along the
+    dimensions `reaction` and `time`, counts the firings of each reaction
+    since the previous time point, for flux analyses.
+
+    The observables of `add_observable` are variables of the Dataset, next to
+    the species.
+
+    The run can be interrupted with Ctrl-C, and raises a `TimeoutError` if it
+    takes more than `max_wall_time` seconds.  If `progress` is true, displays
+    a progress bar, which requires `tqdm`.  It can also be a function, called
+    with the simulated time and the fraction of the run done a few times per
+    second.
+
+    `method` selects the simulation method, like `"r_leaping:100"` for the
+    approximate R-leaping with 100 reactions per step, which trades accuracy
+    for speed.  The methods are those of `run_schedule`.
+    """
+    if progress is True:
+        from tqdm.auto import tqdm
+
+        with tqdm(total=100, unit="%") as bar:
+            return run_xarray(
+                self,
+                init,
+                tmax,
+                nb_steps,
+                seed,
+                t_eval,
+                resume,
+                return_state=return_state,
+                firings=firings,
+                max_wall_time=max_wall_time,
+                progress=lambda _, fraction: bar.update(round(100 * fraction) - bar.n),
+                method=method,
+            )
+    callback = progress if callable(progress) else None
+    times, result, metadata, state, counts, observables = og_run(
+        self,
+        init,
+        tmax,
+        nb_steps,
+        seed,
+        t_eval,
+        resume,
+        firings,
+        max_wall_time,
+        callback,
+        method,
+    )
+    ds = to_dataset(times, {**result, **observables}, metadata)
+    if counts is not None:
+        counts = np.reshape(np.array(counts, dtype=np.uint64), (-1, len(times)))
+        ds["firings"] = xr.DataArray(
+            counts, dims=("reaction", "time"), coords={"time": times}
+        )
+    return (ds, state) if return_state else ds
+
+
+def iter_run_numpy(  # noqa: PLR0913
+    self: Gillespie,
+    init: dict[str, int],
+    tmax: float,
+    nb_steps: int,
+    seed: int | None = None,
+    chunk_size: int = 100_000,
+) -> Iterator[tuple[np.ndarray, np.ndarray]]:
+    """Run the system until `tmax` with `nb_steps` steps, by chunks.
 
+    Yields `times, states` arrays of at most `chunk_size` time points as the
+    simulation proceeds, where `states[i, s]` is the amount of the species
+    `s`, in the order of `species`, at `times[i]`.  With `nb_steps = 0`,
+    every reaction is returned, and the chunks let such long runs be
+    processed or written incrementally.
+    """
+    nb_species = len(self.species)
+    for times, values in og_iter_run(self, init, tmax, nb_steps, seed, chunk_size):
+        states = np.reshape(np.array(values, dtype=np.int64), (-1, nb_species))
+        yield np.array(times), states
+
+
+def run_schedule_xarray(
+    self: Gillespie,
+    init: dict[str, int],
+    phases: list[dict],
+    seed: int | None = None,
+) -> xr.Dataset:
+    """Run the system through consecutive phases.
+
+    Each phase is a dictionary with the keys `start`, `end`, `nb_steps`,
+    and optionally `rates` and `method`.  Returns an xarray Dataset.
+    """
+    times, result, metadata = og_run_schedule(self, init, phases, seed)
+    return to_dataset(times, result, metadata)
 
-def run_xarray(
+
+def run_ode_xarray(
     self: Gillespie,
     init: dict[str, int],
     tmax: float,
     nb_steps: int,
+    params: dict[int, float] | None = None,
+) -> xr.Dataset:
+    """Integrate the mean-field ODEs until `tmax` with `nb_steps` steps.
+
+    `params` can override the rates of some reactions, by index.  Returns an
+    xarray Dataset like `run`, with real amounts, to overlay the
+    deterministic prediction on stochastic runs.
+    """
+    times, result, metadata = og_run_ode(self, init, tmax, nb_steps, params)
+    return to_dataset(times, result, metadata)
+
+
+def run_ensemble_xarray(  # noqa: PLR0913
+    self: Gillespie,
+    init: dict[str, int],
+    tmax: float,
+    nb_steps: int,
+    nb_runs: int,
     seed: int | None = None,
+    progress: bool | Callable[[int, int], object] = False,
+    max_memory: int | None = None,
 ) -> xr.Dataset:
-    """Run the system until `tmax` with `nb_steps` steps.
+    """Run `nb_runs` simulations until `tmax` with `nb_steps` steps.
 
-    The initial configuration is specified in the dictionary `init`.
-    Returns an xarray Dataset.
+    Returns an xarray Dataset with the dimensions `run` and `time`.  If
+    `progress` is true, displays a progress bar, which requires `tqdm`.
+    It can also be a function, called with the numbers of completed and
+    total simulations a few times per second.  If the trajectories would
+    take more than `max_memory` bytes, only their means and standard
+    deviations are kept, along the dimension `statistic` instead of
+    `run`, and the attribute `mode` is `"summary"`.
     """
-    times, result = og_run(self, init, tmax, nb_steps, seed)
-    ds = xr.Dataset(
+    if progress is True:
+        from tqdm.auto import tqdm
+
+        with tqdm(total=nb_runs, unit="run") as bar:
+            return run_ensemble_xarray(
+                self,
+                init,
+                tmax,
+                nb_steps,
+                nb_runs,
+                seed,
+                lambda completed, _: bar.update(completed - bar.n),
+                max_memory,
+            )
+    callback = progress if callable(progress) else None
+    times, result, metadata = og_run_ensemble(
+        self, init, tmax, nb_steps, nb_runs, seed, callback, max_memory
+    )
+    if metadata["mode"] == "summary":
+        dims = ("statistic", "time")
+        coords = {"statistic": ["mean", "std"], "time": times}
+    else:
+        dims = ("run", "time")
+        coords = {"time": times}
+    return xr.Dataset(
         data_vars={
-            name: xr.DataArray(values, dims="time", coords={"time": times})
+            name: xr.DataArray(values, dims=dims, coords=coords)
             for name, values in result.items()
         },
+        attrs=metadata,
+    )
+
+
+def sweep_xarray(  # noqa: PLR0913
+    self: Gillespie,
+    init: dict[str, int],
+    params_grid: dict[int, Sequence[float]],
+    tmax: float,
+    nb_steps: int,
+    nb_replicates: int,
+    seed: int | None = None,
+) -> xr.DataArray:
+    """Run `nb_replicates` simulations for each combination of rates.
+
+    `params_grid` maps the index of a reaction to the list of its rates to
+    try.  Returns an xarray DataArray with the dimensions `combo`,
+    `replicate`, `time` and `species`, and the rate of the reaction `i` in
+    each combination as the coordinate `rate_i` along `combo`, for example
+    to select with `.swap_dims(combo="rate_0")`.
+    """
+    times, reactions, combos, values, metadata = og_sweep(
+        self, init, params_grid, tmax, nb_steps, nb_replicates, seed
+    )
+    shape = (len(combos), nb_replicates, len(times), len(self.species))
+    coords = {
+        "replicate": np.arange(nb_replicates),
+        "time": times,
+        "species": self.species,
+    }
+    for i, reaction in enumerate(reactions):
+        coords[f"rate_{reaction}"] = ("combo", [combo[i] for combo in combos])
+    return xr.DataArray(
+        np.reshape(np.array(values, dtype=np.int64), shape),
+        dims=("combo", "replicate", "time", "species"),
+        coords=coords,
+        attrs=metadata,
     )
-    return ds
 
 
 Gillespie.run = run_xarray
+Gillespie.run_schedule = run_schedule_xarray
+Gillespie.run_ensemble = run_ensemble_xarray
+Gillespie.run_ode = run_ode_xarray
+Gillespie.iter_run = iter_run_numpy
+Gillespie.sweep = sweep_xarray
@@ -0,0 +1,189 @@
+"""Translation of the models of other tools into rebop systems.
+
+`from_sbml` reads the reactions of an SBML file whose kinetic laws follow
+the law of mass action, and `from_pysb` those of a PySB model, so that
+existing models run with rebop without being rewritten.
+"""
+
+from __future__ import annotations
+
+import tempfile
+import xml.etree.ElementTree as ET
+from pathlib import Path
+from typing import TYPE_CHECKING
+
+from .rebop import Gillespie
+
+if TYPE_CHECKING:
+    from collections.abc import Mapping
+
+__all__ = ("from_pysb", "from_sbml")
+
+# Kinetic law as a rate constant and the exponents of the species.
+Monomial = tuple[float, dict[str, int]]
+
+
+def from_sbml(path: str | Path) -> Gillespie:
+    """Load the reactions of an SBML file into a `Gillespie` system.
+
+    The species are named by their ids, and start from their initial
+    amounts, or from their initial concentrations times the size of their
+    compartments, kept in `init`.  The kinetic laws must follow the law of
+    mass action: products of constants, parameters, compartment sizes and
+    the reactants to the power of their stoichiometries, with the reverse
+    reaction subtracted for reversible reactions, like `kf * A * B - kr * C`.
+    Their rate constants are used as written.  The reactions do not change
+    the species with a boundary condition or that are constant.
+
+    Raises a `ValueError` for the other kinetic laws, like Michaelis-Menten
+    ones, for events, rules and non-integer stoichiometries.
+    """
+    model = ET.parse(path).getroot().find("{*}model")  # noqa: S314
+    if model is None:
+        msg = f"no model in the SBML file {path}"
+        raise ValueError(msg)
+    for unsupported in ("listOfEvents", "listOfRules"):
+        if model.find(f"{{*}}{unsupported}") is not None:
+            msg = f"SBML models with {unsupported[6:].lower()} are not supported"
+            raise ValueError(msg)
+    values = _values(model, "listOfCompartments", "size")
+    values |= _values(model, "listOfParameters", "value")
+    init, fixed = dict[str, int](), set[str]()
+    for species in model.iterfind("{*}listOfSpecies/{*}species"):
+        name = species.get("id", "")
+        if "initialAmount" in species.attrib:
+            amount = float(species.get("initialAmount", "0"))
+        else:
+            concentration = float(species.get("initialConcentration", "0"))
+            amount = concentration * values.get(species.get("compartment", ""), 1.0)
+        if round(amount) > 0:
+            init[name] = round(amount)
+        if "true" in (species.get("boundaryCondition"), species.get("constant")):
+            fixed.add(name)
+    system = Gillespie()
+    for reaction in model.iterfind("{*}listOfReactions/{*}reaction"):
+        name = reaction.get("id", "")
+        law = reaction.find("{*}kineticLaw")
+        math = None if law is None else law.find("{*}math/*")
+        if law is None or math is None:
+            msg = f"reaction {name} has no kinetic law"
+            raise ValueError(msg)
+        local = values | _values(law, "listOfParameters", "value")
+        local |= _values(law, "listOfLocalParameters", "value")
+        reactants = _stoichiometries(reaction, "listOfReactants")
+        products = _stoichiometries(reaction, "listOfProducts")
+        directions = [(math, reactants, products)]
+        if _operator(math) == "minus" and len(math) == 3:  # noqa: PLR2004
+            directions = [
+                (math[1], reactants, products),
+                (math[2], products, reactants),
+            ]
+        for law_math, consumed, produced in directions:
+            rate = _mass_action(name, law_math, local, consumed)
+            # the fixed species are given back by the reaction
+            changed = {s: n for s, n in produced.items() if s not in fixed}
+            changed |= {s: n for s, n in consumed.items() if s in fixed}
+            system.add_reaction(
+                rate,
+                [s for s, n in consumed.items() for _ in range(n)],
+                [s for s, n in changed.items() for _ in range(n)],
+            )
+    system.init = init
+    return system
+
+
+def from_pysb(model: object) -> Gillespie:
+    """Translate the reactions of a PySB model into a `Gillespie` system.
+
+    The network is generated by BioNetGen, which PySB must find, then read
+    like a `.net` file by `Gillespie.from_file`: the species are named by
+    their BioNetGen patterns, like `A(b!1).B(a!1)`, the initial amounts are
+    kept in `init`, and the observables become those of the system.
+    """
+    from pysb.bng import generate_network
+
+    with tempfile.TemporaryDirectory() as directory:
+        path = Path(directory) / "model.net"
+        path.write_text(generate_network(model))
+        return Gillespie.from_file(str(path))
+
+
+def _values(element: ET.Element, list_name: str, attribute: str) -> dict[str, float]:
+    """Values of the attribute `attribute` of the elements of a list, by id."""
+    return {
+        child.get("id", ""): float(child.get(attribute, "nan"))
+        for child in element.iterfind(f"{{*}}{list_name}/*")
+    }
+
+
+def _stoichiometries(reaction: ET.Element, side: str) -> dict[str, int]:
+    """Stoichiometries of the species of one side of an SBML reaction."""
+    stoichiometries: dict[str, int] = {}
+    for reference in reaction.iterfind(f"{{*}}{side}/{{*}}speciesReference"):
+        stoichiometry = float(reference.get("stoichiometry", "1"))
+        if not stoichiometry.is_integer() or stoichiometry < 0:
+            msg = f"reaction {reaction.get('id')} has a non-integer stoichiometry"
+            raise ValueError(msg)
+        species = reference.get("species", "")
+        stoichiometries[species] = stoichiometries.get(species, 0) + int(stoichiometry)
+    return {species: n for species, n in stoichiometries.items() if n > 0}
+
+
+def _operator(element: ET.Element) -> str | None:
+    """Operator of a MathML `apply`, like `times`, or `None`."""
+    if element.tag.rpartition("}")[2] != "apply" or len(element) == 0:
+        return None
+    return element[0].tag.rpartition("}")[2]
+
+
+def _mass_action(
+    name: str,
+    math: ET.Element,
+    values: Mapping[str, float],
+    reactants: Mapping[str, int],
+) -> float:
+    """Rate constant of the mass-action kinetic law `math` of `reactants`."""
+    constant, exponents = _monomial(name, math, values)
+    if exponents != reactants:
+        msg = f"the kinetic law of reaction {name} is not of mass action"
+        raise ValueError(msg)
+    return constant
+
+
+def _monomial(name: str, math: ET.Element, values: Mapping[str, float]) -> Monomial:
+    """Constant and exponents of the species of a MathML product."""
+    tag = math.tag.rpartition("}")[2]
+    text = (math.text or "").strip()
+    if tag == "ci":
+        return (values[text], {}) if text in values else (1.0, {text: 1})
+    if tag == "cn":
+        if math.get("type") in ("e-notation", "rational"):
+            mantissa, exponent = text, (math[0].tail or "").strip()
+            if math.get("type") == "rational":
+                return (float(mantissa) / float(exponent), {})
+            return (float(f"{mantissa}e{exponent}"), {})
+        return (float(text), {})
+    operator, operands = _operator(math), list(math)[1:]
+    if operator == "times":
+        constant, exponents = 1.0, dict[str, int]()
+        for operand in operands:
+            factor, powers = _monomial(name, operand, values)
+            constant *= factor
+            for species, n in powers.items():
+                exponents[species] = exponents.get(species, 0) + n
+        return (constant, exponents)
+    if operator == "divide" and len(operands) == 2:  # noqa: PLR2004
+        (numerator, exponents), (denominator, powers) = (
+            _monomial(name, operand, values) for operand in operands
+        )
+        if not powers:
+            return (numerator / denominator, exponents)
+    if operator == "power" and len(operands) == 2:  # noqa: PLR2004
+        (base, exponents), (exponent, powers) = (
+            _monomial(name, operand, values) for operand in operands
+        )
+        if not powers and (not exponents or exponent.is_integer()):
+            powered = {species: n * int(exponent) for species, n in exponents.items()}
+            return (base**exponent, powered)
+    msg = f"the kinetic law of reaction {name} is not of mass action"
+    raise ValueError(msg)
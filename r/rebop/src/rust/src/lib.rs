@@ -0,0 +1,140 @@
+//! R bindings of rebop, with extendr.
+//!
+//! They mirror the `Gillespie` class of the Python bindings, on top of
+//! [`GillespieBuilder`]: the species are declared by the reactions that
+//! use them, and the runs return data frames with a `time` column and
+//! one column per species.  The reactions are indexed from `1`, as usual
+//! in R.
+
+use std::collections::BTreeMap;
+
+use extendr_api::prelude::*;
+use rebop::builder::GillespieBuilder;
+use rebop::model;
+
+/// Reaction system composed of species and reactions.
+#[derive(Clone, Debug, Default)]
+struct Gillespie {
+    /// Names of the species, in the order in which they appeared.
+    species: Vec<String>,
+    reactions: Vec<(f64, Vec<String>, Vec<String>)>,
+}
+
+#[extendr]
+impl Gillespie {
+    fn new() -> Self {
+        Gillespie::default()
+    }
+    /// Add a reaction of law of mass action rate `rate`, from the species `reactants` to the
+    /// species `products`, repeated according to their stoichiometries.
+    fn add_reaction(&mut self, rate: f64, reactants: Vec<String>, products: Vec<String>) {
+        for name in reactants.iter().chain(&products) {
+            if !self.species.contains(name) {
+                self.species.push(name.clone());
+            }
+        }
+        self.reactions.push((rate, reactants, products));
+    }
+    /// Add a reaction written as an equation, like `"2 A + B -> C @ 0.1"`.
+    fn add_reaction_str(&mut self, equation: &str) -> Result<()> {
+        let reaction: model::Reaction = equation
+            .parse()
+            .map_err(|e: model::ModelError| Error::Other(e.to_string()))?;
+        let model::RateSpec::Value(rate) = reaction.rate else {
+            return Err(Error::Other(format!(
+                "the rate of reaction `{equation}` is not a number"
+            )));
+        };
+        let expand = |side: &BTreeMap<String, u32>| {
+            side.iter()
+                .flat_map(|(name, &n)| std::iter::repeat_n(name.clone(), n as usize))
+                .collect()
+        };
+        self.add_reaction(
+            rate,
+            expand(&reaction.reactants),
+            expand(&reaction.products),
+        );
+        Ok(())
+    }
+    /// Set the rate of the reaction of index `index`, from `1` in the order in which they
+    /// were added.
+    fn set_rate(&mut self, index: usize, rate: f64) -> Result<()> {
+        let nb_reactions = self.reactions.len();
+        match index.checked_sub(1).and_then(|i| self.reactions.get_mut(i)) {
+            Some(reaction) => {
+                reaction.0 = rate;
+                Ok(())
+            }
+            None => Err(Error::Other(format!(
+                "unknown reaction of index {index}, the system has {nb_reactions} reactions"
+            ))),
+        }
+    }
+    /// Names of the species, in the order in which they appeared.
+    fn species(&self) -> Vec<String> {
+        self.species.clone()
+    }
+    fn nb_species(&self) -> usize {
+        self.species.len()
+    }
+    fn nb_reactions(&self) -> usize {
+        self.reactions.len()
+    }
+    /// Run the system until `tmax` with `nb_steps` steps.
+    ///
+    /// The initial amounts are given by the named list `init`, `0` for the species that it
+    /// omits, and `seed` can be `NULL`.  Returns a data frame with the column `time` and
+    /// one column per species.
+    fn run(&self, init: List, tmax: f64, nb_steps: usize, seed: Nullable<f64>) -> Result<Robj> {
+        if nb_steps == 0 {
+            return Err(Error::Other("runs need at least one step".to_string()));
+        }
+        let mut initials = vec![0; self.species.len()];
+        for (name, value) in init.iter() {
+            let value = value
+                .as_real()
+                .ok_or_else(|| Error::Other(format!("the amount of `{name}` is not a number")))?;
+            if let Some(s) = self.species.iter().position(|species| species == name) {
+                initials[s] = value as isize;
+            }
+        }
+        let mut builder = GillespieBuilder::new();
+        for (name, &initial) in self.species.iter().zip(&initials) {
+            builder = builder.species(name, initial);
+        }
+        for (i, (rate, reactants, products)) in self.reactions.iter().enumerate() {
+            builder = builder
+                .reaction(&format!("r{}", i + 1))
+                .reactants(reactants)
+                .products(products)
+                .rate_lma(*rate);
+        }
+        let mut g = builder.build().map_err(|e| Error::Other(e.to_string()))?;
+        if let Nullable::NotNull(seed) = seed {
+            g.seed(seed as u64);
+        }
+        let times: Vec<f64> = (0..=nb_steps)
+            .map(|i| tmax * i as f64 / nb_steps as f64)
+            .collect();
+        let mut amounts = vec![Vec::with_capacity(times.len()); self.species.len()];
+        for &t in &times {
+            g.advance_until(t);
+            for (values, &amount) in amounts.iter_mut().zip(g.species()) {
+                values.push(amount as f64);
+            }
+        }
+        let nb_rows = times.len() as i32;
+        let names = std::iter::once("time").chain(self.species.iter().map(String::as_str));
+        let columns = std::iter::once(times).chain(amounts).map(Robj::from);
+        let mut df = List::from_names_and_values(names, columns)?;
+        df.set_class(&["data.frame"])?;
+        df.set_attrib("row.names", (1..=nb_rows).collect::<Vec<_>>())?;
+        Ok(df.into())
+    }
+}
+
+extendr_module! {
+    mod rebop;
+    impl Gillespie;
+}
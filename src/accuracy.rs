@@ -0,0 +1,239 @@
+//! Accuracy study of the approximate simulation methods on a model.
+//!
+//! Whether an approximate method like R-leaping or the slow-scale SSA is
+//! accurate enough depends on the model, the time points of interest and
+//! the tolerance of the user.  [`accuracy_report`] answers it in one
+//! call: it simulates an ensemble with the exact direct method as the
+//! reference and with each candidate method, compares the distributions
+//! of the species at each time point with [`compare_ensembles`], and
+//! recommends the cheapest method whose distributions are within the
+//! tolerance.
+//!
+//! ```
+//! use rebop::accuracy::accuracy_report;
+//! use rebop::gillespie::{Gillespie, Method, Rate};
+//! let mut decay = Gillespie::new([1000]);
+//! decay.add_reaction(Rate::lma(1., [1]), [-1]);
+//! let methods = [Method::RLeaping(10), Method::RLeaping(1000)];
+//! let report = accuracy_report(&decay, &[0.5, 1., 2.], &methods, 500, 0.15, 0);
+//! println!("{report}");
+//! // a single leap of 1000 decays is far from the exact distribution
+//! assert!(!report.methods[1].acceptable);
+//! assert_ne!(report.recommended(), Method::RLeaping(1000));
+//! ```
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use rand::RngCore;
+
+use crate::gillespie::{Gillespie, Method, Trajectory};
+use crate::inference::parallel_map;
+use crate::rng::Philox;
+use crate::stats::{compare_ensembles, EnsembleComparison, EnsembleStats};
+
+/// Accuracy and cost of a method, in an [`AccuracyReport`].
+#[derive(Clone, Debug)]
+pub struct MethodAccuracy {
+    pub method: Method,
+    /// Total simulation time of the runs, summed over the threads.
+    pub duration: Duration,
+    /// Comparison of the distributions with the reference.
+    pub comparison: EnsembleComparison,
+    /// Largest Kolmogorov--Smirnov distance to the reference, over the
+    /// species and the time points.
+    pub max_ks: f64,
+    /// Largest total variation distance to the reference.
+    pub max_total_variation: f64,
+    /// Whether `max_ks` is within the tolerance.
+    pub acceptable: bool,
+}
+
+/// Result of [`accuracy_report`].
+#[derive(Clone, Debug)]
+pub struct AccuracyReport {
+    /// Tolerance on the Kolmogorov--Smirnov distances.
+    pub tolerance: f64,
+    /// Kolmogorov--Smirnov distance under which two ensembles of the
+    /// same distribution fall 95% of the time, below which the tolerance
+    /// cannot be resolved.
+    pub noise: f64,
+    /// Total simulation time of the exact reference.
+    pub reference_duration: Duration,
+    /// Accuracy of each candidate method, in the order given.
+    pub methods: Vec<MethodAccuracy>,
+}
+
+impl AccuracyReport {
+    /// Returns the cheapest acceptable method, which is the direct
+    /// method when no approximate method is both acceptable and faster.
+    pub fn recommended(&self) -> Method {
+        self.methods
+            .iter()
+            .filter(|accuracy| accuracy.acceptable && accuracy.duration < self.reference_duration)
+            .min_by_key(|accuracy| accuracy.duration)
+            .map_or(Method::Direct, |accuracy| accuracy.method)
+    }
+}
+
+impl fmt::Display for AccuracyReport {
+    /// Writes a table of the costs and accuracies of the methods, and the
+    /// recommendation.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<20} {:>12} {:>8} {:>8}  acceptable",
+            "method", "time", "max KS", "max TV"
+        )?;
+        writeln!(
+            f,
+            "{:<20} {:>12.2?} {:>8} {:>8}  reference",
+            Method::Direct.to_string(),
+            self.reference_duration,
+            "-",
+            "-"
+        )?;
+        for accuracy in &self.methods {
+            writeln!(
+                f,
+                "{:<20} {:>12.2?} {:>8.3} {:>8.3}  {}",
+                accuracy.method.to_string(),
+                accuracy.duration,
+                accuracy.max_ks,
+                accuracy.max_total_variation,
+                if accuracy.acceptable { "yes" } else { "no" }
+            )?;
+        }
+        writeln!(
+            f,
+            "tolerance {} on the KS distances (sampling noise {:.3})",
+            self.tolerance, self.noise
+        )?;
+        write!(f, "recommended: {}", self.recommended())
+    }
+}
+
+/// Simulates `nb_runs` runs of `problem` with the exact direct method
+/// and with each method of `methods`, and compares their distributions
+/// of species at the time points `times`.
+///
+/// A method is acceptable when the Kolmogorov--Smirnov distances between
+/// its distributions and the reference ones stay within `tolerance`.
+/// Even an exact method has distances of the order of the sampling
+/// [`noise`](AccuracyReport::noise), `1.36 √(2 / nb_runs)`, so the
+/// tolerance should be larger.  The run of index `i` of every candidate
+/// method uses the seed drawn from the [`Philox`] stream `i` of `seed`,
+/// and the reference the stream `nb_runs + i`, so that it is independent
+/// of the candidates.  The runs of each method are spread over all
+/// available threads.
+///
+/// # Panics
+///
+/// Panics if there is no run, or if the problem has custom waiting times
+/// or delays that a method does not support.
+pub fn accuracy_report(
+    problem: &Gillespie,
+    times: &[f64],
+    methods: &[Method],
+    nb_runs: usize,
+    tolerance: f64,
+    seed: u64,
+) -> AccuracyReport {
+    assert!(nb_runs > 0, "no simulation to run");
+    let (reference, reference_duration) =
+        ensemble(problem, Method::Direct, times, nb_runs, seed, nb_runs);
+    let methods = methods
+        .iter()
+        .map(|&method| {
+            let (stats, duration) = ensemble(problem, method, times, nb_runs, seed, 0);
+            let comparison = compare_ensembles(&reference, &stats);
+            let max =
+                |distances: &[Vec<f64>]| distances.iter().flatten().fold(0_f64, |a, &b| a.max(b));
+            let max_ks = max(&comparison.ks_statistics);
+            MethodAccuracy {
+                method,
+                duration,
+                max_total_variation: max(&comparison.total_variations),
+                max_ks,
+                acceptable: max_ks <= tolerance,
+                comparison,
+            }
+        })
+        .collect();
+    AccuracyReport {
+        tolerance,
+        noise: 1.36 * (2. / nb_runs as f64).sqrt(),
+        reference_duration,
+        methods,
+    }
+}
+
+/// Simulates `nb_runs` runs of `problem` with `method`, from the
+/// [`Philox`] streams of `seed` starting at `first_stream`, and returns
+/// their statistics at `times` and the total simulation time.
+fn ensemble(
+    problem: &Gillespie,
+    method: Method,
+    times: &[f64],
+    nb_runs: usize,
+    seed: u64,
+    first_stream: usize,
+) -> (EnsembleStats, Duration) {
+    let runs = parallel_map(nb_runs, |irun| {
+        let mut trajectory = Trajectory::new(problem.nb_species());
+        let mut p = problem.clone();
+        p.set_method(method);
+        p.seed(Philox::new(seed, (first_stream + irun) as u64).next_u64());
+        let start = Instant::now();
+        for &t in times {
+            p.advance_until(t);
+            trajectory.push(&p);
+        }
+        (trajectory, start.elapsed())
+    });
+    let mut stats = EnsembleStats::new(times, problem.nb_species()).with_quantiles();
+    for (trajectory, _) in &runs {
+        stats.push(trajectory);
+    }
+    (stats, runs.iter().map(|&(_, duration)| duration).sum())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::accuracy::accuracy_report;
+    use crate::gillespie::{Gillespie, Method, Rate};
+
+    #[test]
+    fn report() {
+        let mut dimers = Gillespie::new([100, 0]);
+        dimers.add_reaction(Rate::lma(0.01, [2, 0]), [-2, 1]);
+        dimers.add_reaction(Rate::lma(0.1, [0, 1]), [2, -1]);
+        let methods = [
+            Method::SortingDirect,
+            Method::RLeaping(1),
+            Method::RLeaping(200),
+        ];
+        let times = [1., 5., 10.];
+        let report = accuracy_report(&dimers, &times, &methods, 400, 0.2, 0);
+        assert!((report.noise - 0.096).abs() < 1e-3);
+        assert_eq!(report.methods.len(), 3);
+        // exact methods, up to the sampling noise
+        for accuracy in &report.methods[..2] {
+            assert!(accuracy.acceptable, "{report}");
+            assert!(accuracy.max_ks < 0.15);
+        }
+        assert_eq!(report.methods[0].comparison.times, times);
+        assert!(!report.methods[2].acceptable, "{report}");
+        assert!(report.methods[2].max_total_variation > 0.5);
+        assert!(matches!(
+            report.recommended(),
+            Method::Direct | Method::SortingDirect | Method::RLeaping(1)
+        ));
+        let table = report.to_string();
+        assert_eq!(table.lines().count(), 7);
+        assert!(table.contains("r_leaping:200"));
+        // nothing is acceptable with a tolerance below the noise
+        let report = accuracy_report(&dimers, &times, &methods[2..], 10, 0., 0);
+        assert_eq!(report.recommended(), Method::Direct);
+    }
+}
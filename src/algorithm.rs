@@ -0,0 +1,418 @@
+//! Pluggable simulation algorithms.
+//!
+//! Besides the algorithms of [`Method`], a [`Gillespie`] problem can be
+//! simulated by any implementation of [`SsaAlgorithm`], given to
+//! [`Gillespie::set_algorithm`].  The trait only sees the state of the
+//! problem through an [`SsaState`], so that it does not depend on the
+//! internals of rebop, and [`compare_algorithms`] runs the same problem
+//! with several algorithms to compare their speed and their results.
+//!
+//! ```
+//! use rebop::algorithm::{compare_algorithms, Algorithm, SsaAlgorithm, SsaState};
+//! use rebop::gillespie::{Gillespie, Method, Rate};
+//! use rand::Rng;
+//!
+//! /// Direct method that recomputes all rates for each reaction.
+//! #[derive(Debug)]
+//! struct Naive;
+//!
+//! impl SsaAlgorithm for Naive {
+//!     fn name(&self) -> String {
+//!         "naive".to_string()
+//!     }
+//!     fn is_exact(&self) -> bool {
+//!         true
+//!     }
+//!     fn advance_until(&self, state: &mut SsaState, tmax: f64) {
+//!         let mut rates = vec![0.; state.nb_reactions()];
+//!         loop {
+//!             let total: f64 = state.rates(&mut rates);
+//!             let t = state.time() - state.rng.gen::<f64>().ln() / total;
+//!             if t > tmax {
+//!                 return state.set_time(tmax);
+//!             }
+//!             state.set_time(t);
+//!             let mut chosen = total * state.rng.gen::<f64>();
+//!             let ireaction = rates.iter().position(|&r| {
+//!                 chosen -= r;
+//!                 chosen < 0.
+//!             });
+//!             state.fire(ireaction.unwrap_or(rates.len() - 1));
+//!         }
+//!     }
+//! }
+//!
+//! let mut sir = Gillespie::new([999, 1, 0]);
+//! sir.add_reaction(Rate::lma(1e-4, [1, 1, 0]), [-1, 1, 0]);
+//! sir.add_reaction(Rate::lma(0.01, [0, 1, 0]), [0, -1, 1]);
+//! let comparisons = compare_algorithms(
+//!     &sir,
+//!     &[Algorithm::from(Method::Direct), Algorithm::custom(Naive)],
+//!     250.,
+//!     200,
+//!     0,
+//! );
+//! assert_eq!(comparisons[1].name, "naive");
+//! // both are exact, so they agree up to the noise
+//! assert!((comparisons[0].means[2] - comparisons[1].means[2]).abs() < 60.);
+//! ```
+
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rand::RngCore;
+
+use crate::gillespie::{Gillespie, Jump, Method, Rate, Records};
+
+/// Simulation algorithm that can be plugged into [`Gillespie`] with
+/// [`Gillespie::set_algorithm`].
+///
+/// # Contract
+///
+/// [`advance_until`](SsaAlgorithm::advance_until) simulates the problem
+/// from the current time of the state until `tmax`, and leaves the state
+/// at `tmax`: the time is then exactly `tmax`, and the amounts of species
+/// are those at `tmax`.  If no reaction can happen anymore, the time
+/// jumps to `tmax` directly.
+///
+/// * All the randomness comes from [`SsaState::rng`], so that the
+///   simulations are reproducible under a seed.
+/// * The algorithm keeps no state from one call to the next, which is
+///   why it receives `&self`: the amounts of species, the rates and the
+///   enabled reactions may change between calls, and the problem may be
+///   cloned.  Data structures like priority queues or sorted reactions
+///   are rebuilt at the beginning of each call.
+/// * [`is_exact`](SsaAlgorithm::is_exact) is `true` only if the
+///   trajectories follow exactly the distribution of the chemical master
+///   equation, like those of the direct method.
+/// * [`supports`](SsaAlgorithm::supports) tells which kinds of rates the
+///   algorithm can simulate; rebop checks all the reactions before each
+///   call and panics if one is not supported.
+/// * Custom algorithms are not combined with custom waiting times or
+///   delays, which need their own algorithms: rebop panics if a problem
+///   has both.
+pub trait SsaAlgorithm: Debug + Send + Sync {
+    /// Returns the name of the algorithm, for the reports.
+    fn name(&self) -> String;
+    /// Whether the algorithm samples the exact distribution of the
+    /// trajectories.
+    fn is_exact(&self) -> bool;
+    /// Whether the algorithm can simulate reactions with the rate
+    /// `rate`, all rates by default.  The expression rates are given
+    /// compiled, as [`Rate::Compiled`].
+    fn supports(&self, rate: &Rate) -> bool {
+        let _ = rate;
+        true
+    }
+    /// Simulates the problem in `state` until `tmax`, or until
+    /// [`SsaState::limit_reached`].
+    fn advance_until(&self, state: &mut SsaState, tmax: f64);
+}
+
+/// State of a problem, as seen by an [`SsaAlgorithm`].
+pub struct SsaState<'a> {
+    t: &'a mut f64,
+    species: &'a mut [isize],
+    reactions: &'a [(Rate, Jump)],
+    scales: &'a [f64],
+    records: &'a mut Records,
+    /// Random number generator of the problem.
+    pub rng: &'a mut dyn RngCore,
+}
+
+impl<'a> SsaState<'a> {
+    pub(crate) fn new(
+        t: &'a mut f64,
+        species: &'a mut [isize],
+        reactions: &'a [(Rate, Jump)],
+        scales: &'a [f64],
+        records: &'a mut Records,
+        rng: &'a mut dyn RngCore,
+    ) -> Self {
+        SsaState {
+            t,
+            species,
+            reactions,
+            scales,
+            records,
+            rng,
+        }
+    }
+    /// Returns the current time.
+    pub fn time(&self) -> f64 {
+        *self.t
+    }
+    /// Sets the current time.
+    pub fn set_time(&mut self, t: f64) {
+        *self.t = t;
+    }
+    /// Returns the current amounts of all species.
+    pub fn species(&self) -> &[isize] {
+        self.species
+    }
+    /// Returns the number of reactions.
+    pub fn nb_reactions(&self) -> usize {
+        self.reactions.len()
+    }
+    /// Returns the rate and the jump of the reaction of index
+    /// `ireaction`, for example to know which species it reads.  The rate
+    /// does not include the factor of
+    /// [`Gillespie::scale_rate`], unlike [`rate`](SsaState::rate).
+    pub fn reaction(&self, ireaction: usize) -> &(Rate, Jump) {
+        &self.reactions[ireaction]
+    }
+    /// Returns the rate of the reaction of index `ireaction` in the
+    /// current state.
+    pub fn rate(&self, ireaction: usize) -> f64 {
+        let rate = self.reactions[ireaction].0.rate(self.species);
+        match self.scales.get(ireaction) {
+            Some(scale) => rate * scale,
+            None => rate,
+        }
+    }
+    /// Computes the rates of all reactions in the current state into
+    /// `rates`, and returns their sum.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rates` does not have one element per reaction.
+    pub fn rates(&self, rates: &mut [f64]) -> f64 {
+        assert_eq!(rates.len(), self.reactions.len());
+        let mut total = 0.;
+        for (ireaction, rate) in rates.iter_mut().enumerate() {
+            *rate = self.rate(ireaction);
+            total += *rate;
+        }
+        total
+    }
+    /// Whether a limit of [`Gillespie::set_limits`] was reached, in
+    /// which case the simulation should return, at the last reaction.
+    pub fn limit_reached(&self) -> bool {
+        self.records.stopped()
+    }
+    /// Applies the effect of the reaction of index `ireaction` to the
+    /// species, at the current time.
+    pub fn fire(&mut self, ireaction: usize) {
+        self.reactions[ireaction].1.affect(self.species);
+        self.records.count(
+            *self.t,
+            ireaction,
+            1,
+            self.reactions,
+            self.scales,
+            self.species,
+        );
+    }
+}
+
+/// Algorithm compared by [`compare_algorithms`]: one of the methods of
+/// rebop, or a custom one.
+#[derive(Clone, Debug)]
+pub enum Algorithm {
+    /// Method of [`Gillespie::set_method`].
+    Method(Method),
+    /// Algorithm of [`Gillespie::set_algorithm`].
+    Custom(Arc<dyn SsaAlgorithm>),
+}
+
+impl Algorithm {
+    /// Wraps a custom algorithm.
+    pub fn custom<A: SsaAlgorithm + 'static>(algorithm: A) -> Self {
+        Algorithm::Custom(Arc::new(algorithm))
+    }
+    /// Whether the algorithm samples the exact distribution of the
+    /// trajectories.
+    pub fn is_exact(&self) -> bool {
+        match self {
+            Algorithm::Method(method) => method.is_exact(),
+            Algorithm::Custom(algorithm) => algorithm.is_exact(),
+        }
+    }
+}
+
+impl From<Method> for Algorithm {
+    fn from(method: Method) -> Self {
+        Algorithm::Method(method)
+    }
+}
+
+impl fmt::Display for Algorithm {
+    /// Writes the name of the method or of the custom algorithm.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Algorithm::Method(method) => write!(f, "{method}"),
+            Algorithm::Custom(algorithm) => write!(f, "{}", algorithm.name()),
+        }
+    }
+}
+
+/// Speed and results of an algorithm, as returned by
+/// [`compare_algorithms`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comparison {
+    /// Name of the algorithm.
+    pub name: String,
+    /// Whether the algorithm is exact.
+    pub exact: bool,
+    /// Total time taken by the simulations.
+    pub duration: Duration,
+    /// Mean amount of each species at the end of the simulations.
+    pub means: Vec<f64>,
+    /// Unbiased variance of the amount of each species at the end of the
+    /// simulations.
+    pub variances: Vec<f64>,
+}
+
+/// Simulates `nb_runs` copies of `problem` until `tmax` with each of the
+/// `algorithms`, and returns their timings and the mean and variance of
+/// the final amounts of species.
+///
+/// The runs of each algorithm use the seeds `seed`, `seed + 1`, ..., and
+/// are run one after the other on the current thread, so that the
+/// timings can be compared.  The exact algorithms should give the same
+/// statistics up to the noise, and the approximate ones show how far
+/// they are from them.
+///
+/// # Panics
+///
+/// Panics if there are fewer than two runs.
+pub fn compare_algorithms(
+    problem: &Gillespie,
+    algorithms: &[Algorithm],
+    tmax: f64,
+    nb_runs: u64,
+    seed: u64,
+) -> Vec<Comparison> {
+    assert!(nb_runs > 1, "at least two runs are needed for a variance");
+    let nb_species = problem.nb_species();
+    algorithms
+        .iter()
+        .map(|algorithm| {
+            let mut template = problem.clone();
+            match algorithm {
+                Algorithm::Method(method) => template.set_method(*method),
+                Algorithm::Custom(custom) => template.set_algorithm_arc(custom.clone()),
+            }
+            let mut sums = vec![0.; nb_species];
+            let mut squares = vec![0.; nb_species];
+            let mut duration = Duration::ZERO;
+            for irun in 0..nb_runs {
+                let mut problem = template.clone();
+                problem.seed(seed.wrapping_add(irun));
+                let start = Instant::now();
+                problem.advance_until(tmax);
+                duration += start.elapsed();
+                for ((sum, square), &s) in sums.iter_mut().zip(&mut squares).zip(problem.species())
+                {
+                    *sum += s as f64;
+                    *square += (s as f64).powi(2);
+                }
+            }
+            let n = nb_runs as f64;
+            let means: Vec<f64> = sums.iter().map(|sum| sum / n).collect();
+            let variances = squares
+                .iter()
+                .zip(&means)
+                .map(|(square, mean)| (square - n * mean * mean) / (n - 1.))
+                .collect();
+            Comparison {
+                name: algorithm.to_string(),
+                exact: algorithm.is_exact(),
+                duration,
+                means,
+                variances,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use crate::algorithm::{compare_algorithms, Algorithm, SsaAlgorithm, SsaState};
+    use crate::gillespie::{Gillespie, Method, Rate};
+
+    /// First reaction method, where all reactions fire at the same time
+    /// when asked to.
+    #[derive(Debug)]
+    struct Lockstep;
+
+    impl SsaAlgorithm for Lockstep {
+        fn name(&self) -> String {
+            "lockstep".to_string()
+        }
+        fn is_exact(&self) -> bool {
+            false
+        }
+        fn supports(&self, rate: &Rate) -> bool {
+            !matches!(rate, Rate::Compiled(_))
+        }
+        fn advance_until(&self, state: &mut SsaState, tmax: f64) {
+            while state.time() + 1. <= tmax {
+                let t = state.time() + 1.;
+                state.set_time(t);
+                for ireaction in 0..state.nb_reactions() {
+                    if state.rate(ireaction) > state.rng.gen() {
+                        state.fire(ireaction);
+                    }
+                }
+            }
+            state.set_time(tmax);
+        }
+    }
+
+    #[test]
+    fn custom_algorithm() {
+        let mut p = Gillespie::new_with_seed([0, 0], 0);
+        p.add_reaction(Rate::lma(2., [0, 0]), [1, 0]);
+        p.add_reaction(Rate::lma(0.5, [0, 0]), [0, 1]);
+        p.set_algorithm(Lockstep);
+        assert_eq!(p.algorithm().unwrap().name(), "lockstep");
+        p.advance_until(10.5);
+        assert_eq!(p.get_time(), 10.5);
+        assert_eq!(p.get_species(0), 10);
+        assert!((1..10).contains(&p.get_species(1)));
+        // the problem can be modified between calls
+        p.set_reaction_enabled(0, false);
+        p.advance_until(20.);
+        assert_eq!(p.get_species(0), 10);
+        // the methods replace the custom algorithm
+        p.set_method(Method::NextReaction);
+        assert!(p.algorithm().is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "does not support")]
+    fn unsupported_rates() {
+        let mut p = Gillespie::new([0]);
+        p.add_reaction(Rate::Expr(crate::gillespie::Expr::Constant(1.)), [1]);
+        p.set_algorithm(Lockstep);
+        p.advance_until(1.);
+    }
+
+    #[test]
+    fn comparisons() {
+        let mut p = Gillespie::new([0, 0]);
+        p.add_reaction(Rate::lma(1., [0, 0]), [1, 0]);
+        p.add_reaction(Rate::lma(0.5, [0, 0]), [0, 1]);
+        let algorithms = [
+            Algorithm::from(Method::Direct),
+            Algorithm::from(Method::RLeaping(4)),
+            Algorithm::custom(Lockstep),
+        ];
+        let comparisons = compare_algorithms(&p, &algorithms, 100., 100, 0);
+        let names: Vec<_> = comparisons.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["direct", "r_leaping:4", "lockstep"]);
+        let exact: Vec<_> = comparisons.iter().map(|c| c.exact).collect();
+        assert_eq!(exact, [true, false, false]);
+        // Poisson with mean and variance 100
+        assert!((comparisons[0].means[0] - 100.).abs() < 5.);
+        assert!((comparisons[0].variances[0] - 100.).abs() < 40.);
+        // one reaction per time unit, deterministically
+        assert_eq!(comparisons[2].means[0], 100.);
+        assert_eq!(comparisons[2].variances[0], 0.);
+        assert!((comparisons[2].means[1] - 50.).abs() < 2.);
+    }
+}
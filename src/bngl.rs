@@ -0,0 +1,425 @@
+//! Import of flattened BioNetGen networks.
+//!
+//! BioNetGen expands the rules of a BNGL model into a network of species
+//! and reactions, which it writes to a `.net` file with
+//! `generate_network`.  [`parse_net`] reads the blocks `parameters`,
+//! `species`, `reactions` and `groups` of such a file into a [`Model`],
+//! which [`Model::to_gillespie`] turns into a problem.  The other blocks
+//! are ignored.
+//!
+//! The parameters, the initial amounts and the rates can be arithmetic
+//! expressions of numbers and parameters, with `+`, `-`, `*`, `/`, `^`
+//! and parentheses.  The rates are the rate constants of the law of mass
+//! action as written by BioNetGen, which already include the symmetry
+//! factors, like `0.5*kp` for `A + A`.  The other rate laws, like `Sat`,
+//! `MM` or functions, are not supported.  The fixed species, whose names
+//! start with `$`, keep their amounts: they are annotated with `fixed`,
+//! and the reactions do not change them.  The groups become observables.
+//!
+//! ```
+//! use rebop::bngl;
+//!
+//! let net = "
+//! begin parameters
+//!     1 kp  1e-3
+//!     2 km  2*kp  # ConstantExpression
+//! end parameters
+//! begin species
+//!     1 A(b) 100
+//!     2 B(a) 50
+//!     3 A(b!1).B(a!1) 0
+//! end species
+//! begin reactions
+//!     1 1,2 3 kp #_R1
+//!     2 3 1,2 km #_reverse_R1
+//! end reactions
+//! begin groups
+//!     1 Atot 1,3
+//! end groups
+//! ";
+//! let model = bngl::parse_net(net).unwrap();
+//! assert_eq!(model.species[2].name, "A(b!1).B(a!1)");
+//! assert_eq!(model.parameters[1].value, 2e-3);
+//! let mut problem = model.to_gillespie();
+//! problem.advance_until(10.);
+//! assert_eq!(problem.get_species(0) + problem.get_species(2), 100);
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::model::{Annotations, Model, ModelError, Observable, Parameter, RateSpec, Reaction};
+use crate::model::{Noise, Species};
+
+/// Parses and validates the network of a BioNetGen `.net` file.
+pub fn parse_net(text: &str) -> Result<Model, ModelError> {
+    let mut model = Model::new();
+    let mut params: HashMap<String, f64> = HashMap::new();
+    // indices of the species in the file, to their indices in the model
+    let mut species: HashMap<usize, usize> = HashMap::new();
+    let mut block: Option<&str> = None;
+    for (iline, line) in text.lines().enumerate() {
+        let error = |msg: String| ModelError::Parse(format!("{msg} at line {}", iline + 1));
+        let (line, comment) = match line.split_once('#') {
+            Some((line, comment)) => (line, comment.trim()),
+            None => (line, ""),
+        };
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match (block, fields.as_slice()) {
+            (_, []) => {}
+            // the blocks may be wrapped in a `model` block
+            (None, ["begin" | "end", "model"]) => {}
+            (None, ["begin", name @ ..]) => block = Some(name.first().copied().unwrap_or("")),
+            (None, _) => return Err(error("text outside of a block".to_string())),
+            (Some(_), ["end", ..]) => block = None,
+            (Some("parameters"), [_, name, value @ ..]) if !value.is_empty() => {
+                let value = eval(&value.concat(), &params).map_err(error)?;
+                params.insert(name.to_string(), value);
+                model.parameters.push(Parameter {
+                    name: name.to_string(),
+                    value,
+                });
+            }
+            (Some("species"), [index, name, amount @ ..]) if !amount.is_empty() => {
+                let index = parse_index(index).map_err(error)?;
+                let amount = eval(&amount.concat(), &params).map_err(error)?;
+                if !(amount >= 0. && amount.is_finite()) {
+                    return Err(error(format!("invalid amount {amount}")));
+                }
+                let mut annotations = Annotations::new();
+                let name = match name.strip_prefix('$') {
+                    Some(name) => {
+                        annotations.insert("fixed".to_string(), "true".to_string());
+                        name
+                    }
+                    None => name,
+                };
+                if species.insert(index, model.species.len()).is_some() {
+                    return Err(error(format!("species {index} is declared twice")));
+                }
+                model.species.push(Species {
+                    name: name.to_string(),
+                    initial: amount.round() as isize,
+                    annotations,
+                });
+            }
+            (Some("reactions"), [_, reactants, products, rate @ ..]) if !rate.is_empty() => {
+                let mut reaction = Reaction {
+                    name: None,
+                    reactants: BTreeMap::new(),
+                    products: BTreeMap::new(),
+                    rate: RateSpec::Value(0.),
+                    annotations: Annotations::new(),
+                };
+                for (side, indices) in [
+                    (&mut reaction.reactants, reactants),
+                    (&mut reaction.products, products),
+                ] {
+                    for index in indices.split(',').filter(|&index| index != "0") {
+                        let index = parse_index(index).map_err(error)?;
+                        let &ispecies = species
+                            .get(&index)
+                            .ok_or_else(|| error(format!("unknown species {index}")))?;
+                        *side
+                            .entry(model.species[ispecies].name.clone())
+                            .or_insert(0) += 1;
+                    }
+                }
+                for s in model
+                    .species
+                    .iter()
+                    .filter(|s| s.annotations.contains_key("fixed"))
+                {
+                    match reaction.reactants.get(&s.name) {
+                        Some(&n) => reaction.products.insert(s.name.clone(), n),
+                        None => reaction.products.remove(&s.name),
+                    };
+                }
+                let rate = rate.concat();
+                reaction.rate =
+                    match params.contains_key(&rate) {
+                        true => RateSpec::Parameter(rate),
+                        false => RateSpec::Value(eval(&rate, &params).map_err(|msg| {
+                            error(format!("unsupported rate law `{rate}`: {msg}"))
+                        })?),
+                    };
+                if let Some(rule) = comment.split_whitespace().next() {
+                    reaction
+                        .annotations
+                        .insert("rule".to_string(), rule.to_string());
+                }
+                model.reactions.push(reaction);
+            }
+            (Some("groups"), [_, name, members @ ..]) => {
+                let mut weights = BTreeMap::new();
+                for member in members.concat().split(',').filter(|m| !m.is_empty()) {
+                    let (weight, index) = match member.split_once('*') {
+                        Some((weight, index)) => (
+                            weight
+                                .parse()
+                                .map_err(|_| error(format!("invalid weight `{weight}`")))?,
+                            index,
+                        ),
+                        None => (1., member),
+                    };
+                    let index = parse_index(index).map_err(error)?;
+                    let &ispecies = species
+                        .get(&index)
+                        .ok_or_else(|| error(format!("unknown species {index}")))?;
+                    *weights
+                        .entry(model.species[ispecies].name.clone())
+                        .or_insert(0.) += weight;
+                }
+                model.observables.push(Observable {
+                    name: name.to_string(),
+                    species: weights,
+                    times: Vec::new(),
+                    noise: Noise::None,
+                });
+            }
+            (Some("parameters" | "species" | "reactions" | "groups"), _) => {
+                return Err(error("missing fields".to_string()));
+            }
+            (Some(_), _) => {}
+        }
+    }
+    if let Some(block) = block {
+        return Err(ModelError::Parse(format!("block `{block}` is not ended")));
+    }
+    model.validate()?;
+    Ok(model)
+}
+
+/// Parses the index of a species, which starts at `1`.
+fn parse_index(index: &str) -> Result<usize, String> {
+    match index.parse() {
+        Ok(index) if index > 0 => Ok(index),
+        _ => Err(format!("invalid species index `{index}`")),
+    }
+}
+
+/// Evaluates the arithmetic expression `expr` of numbers and of the
+/// parameters `params`.
+fn eval(expr: &str, params: &HashMap<String, f64>) -> Result<f64, String> {
+    let mut parser = Parser {
+        chars: expr.chars().filter(|c| !c.is_whitespace()).collect(),
+        pos: 0,
+        params,
+    };
+    let value = parser.sum()?;
+    match parser.peek() {
+        None => Ok(value),
+        Some(c) => Err(format!("unexpected `{c}` in `{expr}`")),
+    }
+}
+
+/// Recursive descent parser of the arithmetic expressions.
+struct Parser<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    params: &'a HashMap<String, f64>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+    /// Consumes `c` if it is the next character.
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        self.pos += usize::from(found);
+        found
+    }
+    /// Consumes the operator of power, `^` or `**`, if it is next.
+    fn eat_power(&mut self) -> bool {
+        if self.chars[self.pos..].starts_with(&['*', '*']) {
+            self.pos += 2;
+            return true;
+        }
+        self.eat('^')
+    }
+    fn sum(&mut self) -> Result<f64, String> {
+        let mut value = self.product()?;
+        loop {
+            if self.eat('+') {
+                value += self.product()?;
+            } else if self.eat('-') {
+                value -= self.product()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+    fn product(&mut self) -> Result<f64, String> {
+        let mut value = self.unary()?;
+        loop {
+            if self.chars[self.pos..].starts_with(&['*', '*']) {
+                return Err("unexpected `**`".to_string());
+            } else if self.eat('*') {
+                value *= self.unary()?;
+            } else if self.eat('/') {
+                value /= self.unary()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+    fn unary(&mut self) -> Result<f64, String> {
+        if self.eat('-') {
+            Ok(-self.unary()?)
+        } else if self.eat('+') {
+            self.unary()
+        } else {
+            self.power()
+        }
+    }
+    /// Power, which is right-associative and binds tighter than the
+    /// unary minus on its left, like `-2^2 = -4`.
+    fn power(&mut self) -> Result<f64, String> {
+        let base = self.atom()?;
+        match self.eat_power() {
+            true => Ok(base.powf(self.unary()?)),
+            false => Ok(base),
+        }
+    }
+    fn atom(&mut self) -> Result<f64, String> {
+        let start = self.pos;
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                let value = self.sum()?;
+                match self.eat(')') {
+                    true => Ok(value),
+                    false => Err("missing `)`".to_string()),
+                }
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                while let Some(c) = self.peek() {
+                    let exponent = matches!(c, 'e' | 'E');
+                    if !(c.is_ascii_digit() || c == '.' || exponent) {
+                        break;
+                    }
+                    self.pos += 1;
+                    if exponent && matches!(self.peek(), Some('+' | '-')) {
+                        self.pos += 1;
+                    }
+                }
+                let number: String = self.chars[start..self.pos].iter().collect();
+                number
+                    .parse()
+                    .map_err(|_| format!("invalid number `{number}`"))
+            }
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                while self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+                    self.pos += 1;
+                }
+                let name: String = self.chars[start..self.pos].iter().collect();
+                self.params
+                    .get(&name)
+                    .copied()
+                    .ok_or_else(|| format!("unknown name `{name}`"))
+            }
+            Some(c) => Err(format!("unexpected `{c}`")),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bngl::*;
+
+    #[test]
+    fn expressions() {
+        let params = HashMap::from([("k".to_string(), 2.), ("k_2".to_string(), 0.5)]);
+        for (expr, value) in [
+            ("1", 1.),
+            ("1.5e-3", 1.5e-3),
+            ("2.E+2", 200.),
+            ("k*k_2", 1.),
+            ("0.5*k", 1.),
+            ("1 + 2*3", 7.),
+            ("(1 + 2)*3", 9.),
+            ("-2^2", -4.),
+            ("2^3^2", 512.),
+            ("k**-1", 0.5),
+            ("8/2/2", 2.),
+            ("1 - 2 - 3", -4.),
+        ] {
+            assert_eq!(eval(expr, &params), Ok(value), "{expr}");
+        }
+        for expr in ["", "k +", "(1", "1)", "Sat(k,k_2)", "x", "1.2.3"] {
+            assert!(eval(expr, &params).is_err(), "{expr}");
+        }
+    }
+
+    #[test]
+    fn network() {
+        let net = "# Created by BioNetGen 2.9.0
+begin parameters
+    1 NA    6.022e23  # Constant
+    2 V     1e-15
+    3 kp    1e6/(NA*V)
+    4 S0    100
+end parameters
+begin molecule types
+    1 A(b)
+end molecule types
+begin species
+    1 A(b) S0
+    2 $Src() 1
+    3 A(b!1).A(b!1) 0
+end species
+begin reactions
+    1 1,1 3 0.5*kp #_R1
+    2 2 1,2 10 #_R2
+    3 3 0 kp #_R3 degradation
+end reactions
+begin groups
+    1 Atot 1,2*3
+    2 Empty
+end groups
+";
+        let model = parse_net(net).unwrap();
+        assert_eq!(model.species.len(), 3);
+        assert_eq!(model.species[0].initial, 100);
+        assert_eq!(model.species[1].name, "Src()");
+        assert_eq!(model.species[1].annotations["fixed"], "true");
+        let dimerization = &model.reactions[0];
+        assert_eq!(dimerization.reactants["A(b)"], 2);
+        assert_eq!(dimerization.annotations["rule"], "_R1");
+        let RateSpec::Value(rate) = dimerization.rate else {
+            panic!("rate {:?}", dimerization.rate)
+        };
+        assert!((rate - 0.5 / 602.2).abs() < 1e-12);
+        assert_eq!(model.reactions[2].rate, RateSpec::Parameter("kp".into()));
+        assert!(model.reactions[2].products.is_empty());
+        // the source is not consumed
+        assert_eq!(model.stoichiometry_matrix()[1], [0, 0, 0]);
+        assert_eq!(model.stoichiometry_matrix()[0], [-2, 1, 0]);
+        assert_eq!(model.observables[0].species["A(b!1).A(b!1)"], 2.);
+        assert!(model.observables[1].species.is_empty());
+        let mut problem = model.to_gillespie();
+        problem.advance_until(1.);
+        assert_eq!(problem.get_species(1), 1);
+        for (net, message) in [
+            ("begin species\n1 A 1\n", "not ended"),
+            ("1 A 1\n", "outside of a block"),
+            (
+                "begin species\n1 A\nend species",
+                "missing fields at line 2",
+            ),
+            (
+                "begin reactions\n1 1 0 1\nend reactions",
+                "unknown species 1",
+            ),
+            (
+                "begin species\n1 A 1\nend species\nbegin reactions\n1 1 0 Sat(1,2)\n",
+                "Sat",
+            ),
+            ("begin species\n1 A -1\nend species", "invalid amount"),
+        ] {
+            let err = parse_net(net).unwrap_err();
+            assert!(err.to_string().contains(message), "{err}");
+        }
+    }
+}
@@ -0,0 +1,305 @@
+//! Construction of problems by name.
+//!
+//! [`GillespieBuilder`] describes a reaction network with the names of
+//! its species and of its reactions, like the Python bindings, instead
+//! of the vectors indexed by species of
+//! [`Gillespie::add_reaction`].  Everything is validated at once by
+//! [`build`](GillespieBuilder::build), which reports the first mistake
+//! with the names used in the description.
+//!
+//! ```
+//! use rebop::builder::GillespieBuilder;
+//! let mut sir = GillespieBuilder::new()
+//!     .species("S", 999)
+//!     .species("I", 1)
+//!     .species("R", 0)
+//!     .reaction("infection")
+//!     .reactants(["S", "I"])
+//!     .products(["I", "I"])
+//!     .rate_lma(1e-4)
+//!     .reaction("recovery")
+//!     .reactants(["I"])
+//!     .products(["R"])
+//!     .rate_lma(0.01)
+//!     .build()
+//!     .unwrap();
+//! sir.advance_until(250.);
+//! assert_eq!(sir.species().iter().sum::<isize>(), 1000);
+//! ```
+
+use crate::error::Error;
+use crate::gillespie::{Gillespie, Rate};
+
+/// Reaction described by a [`GillespieBuilder`].
+#[derive(Clone, Debug, Default)]
+struct ReactionSpec {
+    name: String,
+    reactants: Vec<String>,
+    products: Vec<String>,
+    rate: Option<f64>,
+}
+
+/// Builder of a [`Gillespie`] problem from named species and reactions.
+///
+/// Each call to [`reaction`](GillespieBuilder::reaction) starts a new
+/// reaction, that the following calls to
+/// [`reactants`](GillespieBuilder::reactants),
+/// [`products`](GillespieBuilder::products) and
+/// [`rate_lma`](GillespieBuilder::rate_lma) describe.  Species can be
+/// declared in any order, even after the reactions that use them.
+#[derive(Clone, Debug, Default)]
+pub struct GillespieBuilder {
+    species: Vec<(String, isize)>,
+    reactions: Vec<ReactionSpec>,
+}
+
+impl GillespieBuilder {
+    /// Creates a builder without species nor reactions.
+    pub fn new() -> Self {
+        GillespieBuilder::default()
+    }
+    /// Declares a species with its initial amount.
+    pub fn species(mut self, name: &str, initial: isize) -> Self {
+        self.species.push((name.to_string(), initial));
+        self
+    }
+    /// Starts a new reaction, without reactants nor products.
+    pub fn reaction(mut self, name: &str) -> Self {
+        self.reactions.push(ReactionSpec {
+            name: name.to_string(),
+            ..ReactionSpec::default()
+        });
+        self
+    }
+    /// Adds reactants to the current reaction, a species appearing as
+    /// many times as it is consumed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no reaction was started.
+    pub fn reactants<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let reaction = self.current("reactants");
+        reaction
+            .reactants
+            .extend(names.into_iter().map(|name| name.as_ref().to_string()));
+        self
+    }
+    /// Adds products to the current reaction, a species appearing as
+    /// many times as it is produced.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no reaction was started.
+    pub fn products<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let reaction = self.current("products");
+        reaction
+            .products
+            .extend(names.into_iter().map(|name| name.as_ref().to_string()));
+        self
+    }
+    /// Sets the rate constant of the current reaction, whose rate
+    /// follows the law of mass action.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no reaction was started.
+    pub fn rate_lma(mut self, rate: f64) -> Self {
+        self.current("a rate").rate = Some(rate);
+        self
+    }
+    /// Returns the names of the species, in the order of the state
+    /// vector of the problem.
+    pub fn species_names(&self) -> Vec<&str> {
+        self.species.iter().map(|(name, _)| name.as_str()).collect()
+    }
+    /// Returns the names of the reactions, in the order of their indices
+    /// in the problem.
+    pub fn reaction_names(&self) -> Vec<&str> {
+        self.reactions
+            .iter()
+            .map(|reaction| reaction.name.as_str())
+            .collect()
+    }
+    /// Validates the description and builds the problem.
+    ///
+    /// Fails if a species or a reaction is declared twice, if an initial
+    /// amount is negative, if a reaction refers to an undeclared species,
+    /// or if it has no rate or a rate constant that is negative or not
+    /// finite.
+    ///
+    /// ```
+    /// use rebop::builder::GillespieBuilder;
+    /// use rebop::Error;
+    /// let builder = GillespieBuilder::new()
+    ///     .species("A", 10)
+    ///     .reaction("decay")
+    ///     .reactants(["a"])
+    ///     .rate_lma(0.1);
+    /// assert_eq!(
+    ///     builder.build().unwrap_err(),
+    ///     Error::UndeclaredSpecies { reaction: "decay".into(), name: "a".into() },
+    /// );
+    /// ```
+    pub fn build(&self) -> Result<Gillespie, Error> {
+        for (i, (name, initial)) in self.species.iter().enumerate() {
+            if self.species[..i].iter().any(|(other, _)| other == name) {
+                return Err(Error::DuplicateSpecies { name: name.clone() });
+            }
+            if *initial < 0 {
+                return Err(Error::NegativeInitial {
+                    name: name.clone(),
+                    initial: *initial,
+                });
+            }
+        }
+        let names = self.species_names();
+        let initials: Vec<isize> = self.species.iter().map(|&(_, initial)| initial).collect();
+        let mut problem = Gillespie::new(initials);
+        for (i, reaction) in self.reactions.iter().enumerate() {
+            if self.reactions[..i].iter().any(|r| r.name == reaction.name) {
+                return Err(Error::DuplicateReaction {
+                    name: reaction.name.clone(),
+                });
+            }
+            let mut reactants = vec![0; names.len()];
+            let mut differences = vec![0; names.len()];
+            for (species, sign) in reaction
+                .reactants
+                .iter()
+                .map(|s| (s, -1))
+                .chain(reaction.products.iter().map(|s| (s, 1)))
+            {
+                let Some(index) = names.iter().position(|name| name == species) else {
+                    return Err(Error::UndeclaredSpecies {
+                        reaction: reaction.name.clone(),
+                        name: species.clone(),
+                    });
+                };
+                if sign < 0 {
+                    reactants[index] += 1;
+                }
+                differences[index] += sign;
+            }
+            let rate = match reaction.rate {
+                None => {
+                    return Err(Error::MissingRate {
+                        reaction: reaction.name.clone(),
+                    })
+                }
+                Some(rate) if !rate.is_finite() || rate < 0. => {
+                    return Err(Error::InvalidRateConstant {
+                        reaction: reaction.name.clone(),
+                        rate,
+                    })
+                }
+                Some(rate) => rate,
+            };
+            problem.try_add_reaction(Rate::lma(rate, reactants), differences)?;
+        }
+        Ok(problem)
+    }
+    /// Returns the reaction being described.
+    fn current(&mut self, what: &str) -> &mut ReactionSpec {
+        self.reactions
+            .last_mut()
+            .unwrap_or_else(|| panic!("{what} given before any reaction"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::GillespieBuilder;
+    use crate::gillespie::{Gillespie, Rate};
+    use crate::Error;
+
+    fn dimers() -> GillespieBuilder {
+        GillespieBuilder::new()
+            .reaction("dimerization")
+            .reactants(["P", "P"])
+            .products(["D"])
+            .rate_lma(0.01)
+            .reaction("production")
+            .products(["P"])
+            .rate_lma(10.)
+            .species("P", 5)
+            .species("D", 0)
+    }
+
+    #[test]
+    fn build() {
+        let builder = dimers();
+        assert_eq!(builder.species_names(), ["P", "D"]);
+        assert_eq!(builder.reaction_names(), ["dimerization", "production"]);
+        let mut built = builder.build().unwrap();
+        let mut expected = Gillespie::new([5, 0]);
+        expected.add_reaction(Rate::lma(0.01, [2, 0]), [-2, 1]);
+        expected.add_reaction(Rate::lma(10., [0, 0]), [1, 0]);
+        built.seed(0);
+        expected.seed(0);
+        built.advance_until(10.);
+        expected.advance_until(10.);
+        assert_eq!(built.species(), expected.species());
+        // catalysts do not change
+        let mut catalysis = GillespieBuilder::new()
+            .species("E", 1)
+            .species("S", 10)
+            .reaction("consumption")
+            .reactants(["E", "S"])
+            .products(["E"])
+            .rate_lma(1.)
+            .build()
+            .unwrap();
+        catalysis.advance_until(100.);
+        assert_eq!(catalysis.species(), [1, 0]);
+    }
+
+    #[test]
+    fn validation() {
+        let err = |builder: GillespieBuilder| builder.build().unwrap_err();
+        assert_eq!(
+            err(dimers().species("P", 1)),
+            Error::DuplicateSpecies { name: "P".into() }
+        );
+        assert_eq!(
+            err(dimers().species("Q", -1)),
+            Error::NegativeInitial {
+                name: "Q".into(),
+                initial: -1
+            }
+        );
+        assert_eq!(
+            err(dimers().reaction("production").rate_lma(1.)),
+            Error::DuplicateReaction {
+                name: "production".into()
+            }
+        );
+        assert_eq!(
+            err(dimers().reaction("decay").reactants(["D"])),
+            Error::MissingRate {
+                reaction: "decay".into()
+            }
+        );
+        assert_eq!(
+            err(dimers().reaction("decay").rate_lma(f64::NAN)).to_string(),
+            "reaction `decay` has the invalid rate constant NaN"
+        );
+        assert_eq!(
+            err(dimers().reaction("decay").products(["d"]).rate_lma(1.)).to_string(),
+            "reaction `decay` refers to the undeclared species `d`"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "reactants given before any reaction")]
+    fn no_reaction() {
+        let _ = GillespieBuilder::new().species("A", 1).reactants(["A"]);
+    }
+}
@@ -0,0 +1,430 @@
+//! C interface, with the `capi` feature.
+//!
+//! The functions of this module are exported with the C calling
+//! convention by the dynamic library of the crate, and declared in the
+//! header `include/rebop.h`, so that rebop can be called from C, C++,
+//! Julia or R without going through Python.  A problem is an opaque
+//! pointer to a [`Gillespie`], created by [`rebop_new`] or
+//! [`rebop_from_toml`] and released by [`rebop_free`].  The amounts of
+//! species are `int64_t`, and the functions that can fail return `0` on
+//! success and `-1` on error.
+//!
+//! All functions accept `NULL` pointers: the constructors then return
+//! `NULL`, the functions that can fail `-1`, [`rebop_get_time`] NaN and
+//! [`rebop_nb_species`] and [`rebop_nb_reactions`] `0`, and the others
+//! do nothing.
+//!
+//! ```c
+//! #include "rebop.h"
+//! int64_t init[2] = {1000, 0};
+//! rebop_gillespie *p = rebop_new(2, init);
+//! uint32_t reactants[2] = {1, 0};
+//! int64_t differences[2] = {-1, 1};
+//! rebop_add_reaction(p, 0.1, reactants, differences);
+//! rebop_seed(p, 42);
+//! rebop_advance_until(p, 10.0);
+//! int64_t amount;
+//! if (rebop_get_species(p, 1, &amount) == 0) {
+//!     printf("%lld\n", (long long)amount);
+//! }
+//! rebop_free(p);
+//! ```
+
+use std::ffi::{c_char, c_int, CStr};
+use std::slice;
+
+use rand::RngCore;
+
+use crate::gillespie::{Gillespie, Rate};
+use crate::inference::parallel_map;
+use crate::model::Model;
+use crate::rng::Philox;
+
+/// Returns the `len` elements pointed to by `data`, or `None` if `data`
+/// is `NULL` while `len` is not `0`.  `data` can be `NULL` or dangling if
+/// `len` is `0`, as is common in C.
+///
+/// # Safety
+///
+/// `data` must be `NULL` or point to `len` elements if `len` is not `0`.
+unsafe fn elements<'a, T>(data: *const T, len: usize) -> Option<&'a [T]> {
+    match (len, data.is_null()) {
+        (0, _) => Some(&[]),
+        (_, true) => None,
+        (_, false) => Some(slice::from_raw_parts(data, len)),
+    }
+}
+
+/// Creates a problem with `nb_species` species of initial amounts
+/// `species`, and no reaction, or returns `NULL` if `species` is `NULL`.
+///
+/// # Safety
+///
+/// `species` must be `NULL` or point to `nb_species` amounts.
+#[no_mangle]
+pub unsafe extern "C" fn rebop_new(nb_species: usize, species: *const i64) -> *mut Gillespie {
+    let Some(species) = elements(species, nb_species) else {
+        return std::ptr::null_mut();
+    };
+    let species: Vec<isize> = species.iter().map(|&n| n as isize).collect();
+    Box::into_raw(Box::new(Gillespie::new(species)))
+}
+
+/// Creates the problem of a model in the TOML format of
+/// [`Model`](crate::model::Model), with the species at their initial
+/// amounts, or returns `NULL` if the model is invalid or `NULL`.
+///
+/// # Safety
+///
+/// `toml` must be `NULL` or a null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rebop_from_toml(toml: *const c_char) -> *mut Gillespie {
+    if toml.is_null() {
+        return std::ptr::null_mut();
+    }
+    let Ok(toml) = CStr::from_ptr(toml).to_str() else {
+        return std::ptr::null_mut();
+    };
+    match Model::from_toml_str(toml) {
+        Ok(model) => Box::into_raw(Box::new(model.to_gillespie())),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Releases a problem.
+///
+/// # Safety
+///
+/// `problem` must come from [`rebop_new`] or [`rebop_from_toml`], and not
+/// be used afterwards.  It can be `NULL`.
+#[no_mangle]
+pub unsafe extern "C" fn rebop_free(problem: *mut Gillespie) {
+    if !problem.is_null() {
+        drop(Box::from_raw(problem));
+    }
+}
+
+/// Adds a reaction of law of mass action rate `rate`, with the
+/// stoichiometries of the reactants `reactants` and the changes of the
+/// species `differences`, both with one element per species.  Returns
+/// `-1` if the rate is invalid or if a pointer is `NULL`.
+///
+/// # Safety
+///
+/// `problem` must be `NULL` or a valid problem, and `reactants` and
+/// `differences` must be `NULL` or point to one element per species.
+#[no_mangle]
+pub unsafe extern "C" fn rebop_add_reaction(
+    problem: *mut Gillespie,
+    rate: f64,
+    reactants: *const u32,
+    differences: *const i64,
+) -> c_int {
+    let Some(problem) = problem.as_mut() else {
+        return -1;
+    };
+    let nb_species = problem.nb_species();
+    let (Some(reactants), Some(differences)) = (
+        elements(reactants, nb_species),
+        elements(differences, nb_species),
+    ) else {
+        return -1;
+    };
+    let differences: Vec<isize> = differences.iter().map(|&n| n as isize).collect();
+    if !(rate >= 0. && rate.is_finite()) {
+        return -1;
+    }
+    match problem.try_add_reaction(Rate::lma(rate, reactants), differences) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Sets the seed of the random number generator of a problem.
+///
+/// # Safety
+///
+/// `problem` must be `NULL` or a valid problem.
+#[no_mangle]
+pub unsafe extern "C" fn rebop_seed(problem: *mut Gillespie, seed: u64) {
+    if let Some(problem) = problem.as_mut() {
+        problem.seed(seed);
+    }
+}
+
+/// Simulates a problem until `tmax`.
+///
+/// # Safety
+///
+/// `problem` must be `NULL` or a valid problem.
+#[no_mangle]
+pub unsafe extern "C" fn rebop_advance_until(problem: *mut Gillespie, tmax: f64) {
+    if let Some(problem) = problem.as_mut() {
+        problem.advance_until(tmax);
+    }
+}
+
+/// Returns the current time of a problem, or NaN if it is `NULL`.
+///
+/// # Safety
+///
+/// `problem` must be `NULL` or a valid problem.
+#[no_mangle]
+pub unsafe extern "C" fn rebop_get_time(problem: *const Gillespie) -> f64 {
+    problem.as_ref().map_or(f64::NAN, Gillespie::get_time)
+}
+
+/// Returns the number of species of a problem, or `0` if it is `NULL`.
+///
+/// # Safety
+///
+/// `problem` must be `NULL` or a valid problem.
+#[no_mangle]
+pub unsafe extern "C" fn rebop_nb_species(problem: *const Gillespie) -> usize {
+    problem.as_ref().map_or(0, Gillespie::nb_species)
+}
+
+/// Returns the number of reactions of a problem, or `0` if it is `NULL`.
+///
+/// # Safety
+///
+/// `problem` must be `NULL` or a valid problem.
+#[no_mangle]
+pub unsafe extern "C" fn rebop_nb_reactions(problem: *const Gillespie) -> usize {
+    problem.as_ref().map_or(0, Gillespie::nb_reactions)
+}
+
+/// Writes the current amount of the species of index `species` in
+/// `amount`.  Returns `-1` if there is no such species or if a pointer is
+/// `NULL`, as any amount, even negative, is valid.
+///
+/// # Safety
+///
+/// `problem` must be `NULL` or a valid problem, and `amount` must be
+/// `NULL` or point to an amount.
+#[no_mangle]
+pub unsafe extern "C" fn rebop_get_species(
+    problem: *const Gillespie,
+    species: usize,
+    amount: *mut i64,
+) -> c_int {
+    let (Some(problem), Some(amount)) = (problem.as_ref(), amount.as_mut()) else {
+        return -1;
+    };
+    match problem.species().get(species) {
+        Some(&n) => {
+            *amount = n as i64;
+            0
+        }
+        None => -1,
+    }
+}
+
+/// Sets the current amounts of all species.  Returns `-1` if a pointer
+/// is `NULL`.
+///
+/// # Safety
+///
+/// `problem` must be `NULL` or a valid problem, and `species` must be
+/// `NULL` or point to one amount per species.
+#[no_mangle]
+pub unsafe extern "C" fn rebop_set_species(problem: *mut Gillespie, species: *const i64) -> c_int {
+    let Some(problem) = problem.as_mut() else {
+        return -1;
+    };
+    let Some(species) = elements(species, problem.nb_species()) else {
+        return -1;
+    };
+    problem.set_species(species.iter().map(|&n| n as isize).collect::<Vec<_>>());
+    0
+}
+
+/// Simulates `nb_runs` runs of a problem from its current state until
+/// `tmax` with `nb_steps` steps, in parallel, and writes the amount of
+/// the species `s` at the time point `i` of the run `r` in
+/// `out[(r * (nb_steps + 1) + i) * nb_species + s]`.
+///
+/// The time points are uniformly spaced from the current time of the
+/// problem, which is not modified.  The run `r` uses the seed drawn from
+/// the [`Philox`] stream `r` of `seed`, so that the results do not depend
+/// on the number of threads.  Returns `-1` if `nb_steps` is `0`, if the
+/// size of `out` overflows or if a pointer is `NULL`.
+///
+/// # Safety
+///
+/// `problem` must be `NULL` or a valid problem, and `out` must be `NULL`
+/// or point to `nb_runs * (nb_steps + 1) * nb_species` amounts.
+#[no_mangle]
+pub unsafe extern "C" fn rebop_run_ensemble(
+    problem: *const Gillespie,
+    tmax: f64,
+    nb_steps: usize,
+    nb_runs: usize,
+    seed: u64,
+    out: *mut i64,
+) -> c_int {
+    let Some(problem) = problem.as_ref() else {
+        return -1;
+    };
+    let Some((block, len)) = nb_steps
+        .checked_add(1)
+        .and_then(|n| n.checked_mul(problem.nb_species()))
+        .and_then(|block| Some((block, block.checked_mul(nb_runs)?)))
+    else {
+        return -1;
+    };
+    if nb_steps == 0 || (out.is_null() && len > 0) {
+        return -1;
+    }
+    if len == 0 {
+        return 0;
+    }
+    let t0 = problem.get_time();
+    let times: Vec<f64> = (0..=nb_steps)
+        .map(|i| t0 + (tmax - t0) * i as f64 / nb_steps as f64)
+        .collect();
+    let runs = parallel_map(nb_runs, |irun| {
+        let mut g = problem.clone();
+        g.seed(Philox::new(seed, irun as u64).next_u64());
+        let mut run = Vec::with_capacity(block);
+        for &t in &times {
+            g.advance_until(t);
+            run.extend(g.species().iter().map(|&n| n as i64));
+        }
+        run
+    });
+    let out = slice::from_raw_parts_mut(out, len);
+    for (run, values) in runs.iter().zip(out.chunks_mut(block)) {
+        values.copy_from_slice(run);
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+    use std::ptr::{null, null_mut};
+
+    use crate::capi::*;
+
+    /// Returns the amount of a species, which must exist.
+    unsafe fn get_species(problem: *const Gillespie, species: usize) -> i64 {
+        let mut amount = 0;
+        assert_eq!(rebop_get_species(problem, species, &mut amount), 0);
+        amount
+    }
+
+    #[test]
+    fn decay() {
+        unsafe {
+            let p = rebop_new(2, [1000, 0].as_ptr());
+            assert_eq!(
+                rebop_add_reaction(p, 0.1, [1, 0].as_ptr(), [-1, 1].as_ptr()),
+                0
+            );
+            assert_eq!(
+                rebop_add_reaction(p, -1., [1, 0].as_ptr(), [-1, 1].as_ptr()),
+                -1
+            );
+            assert_eq!((rebop_nb_species(p), rebop_nb_reactions(p)), (2, 1));
+            rebop_seed(p, 0);
+            rebop_advance_until(p, 10.);
+            assert_eq!(rebop_get_time(p), 10.);
+            let (a, b) = (get_species(p, 0), get_species(p, 1));
+            assert_eq!(a + b, 1000);
+            assert!((300..450).contains(&a), "{a}");
+            let mut amount = 7;
+            assert_eq!(rebop_get_species(p, 2, &mut amount), -1);
+            assert_eq!(amount, 7);
+            // negative amounts are valid
+            assert_eq!(rebop_set_species(p, [-3, 0].as_ptr()), 0);
+            assert_eq!(get_species(p, 0), -3);
+            assert_eq!(rebop_set_species(p, [10, 0].as_ptr()), 0);
+            let mut out = vec![0; 3 * 5 * 2];
+            assert_eq!(rebop_run_ensemble(p, 20., 4, 3, 0, out.as_mut_ptr()), 0);
+            assert_eq!(rebop_get_time(p), 10.);
+            for run in out.chunks(10) {
+                assert_eq!(run[..2], [10, 0]);
+                assert!(run.chunks(2).all(|point| point[0] + point[1] == 10));
+            }
+            let mut again = vec![0; 3 * 5 * 2];
+            rebop_run_ensemble(p, 20., 4, 3, 0, again.as_mut_ptr());
+            assert_eq!(out, again);
+            rebop_free(p);
+        }
+    }
+
+    #[test]
+    fn from_toml() {
+        let toml = CString::new(
+            r#"
+            version = 1
+            species = [{ name = "A", initial = 5 }]
+            [[reactions]]
+            reactants = { A = 1 }
+            rate = 1.0
+            "#,
+        )
+        .unwrap();
+        let invalid = CString::new("version = 1\nspecies = 3").unwrap();
+        unsafe {
+            let p = rebop_from_toml(toml.as_ptr());
+            assert!(!p.is_null());
+            assert_eq!(get_species(p, 0), 5);
+            rebop_advance_until(p, 100.);
+            assert_eq!(get_species(p, 0), 0);
+            rebop_free(p);
+            assert!(rebop_from_toml(invalid.as_ptr()).is_null());
+        }
+    }
+
+    #[test]
+    fn null_pointers() {
+        unsafe {
+            // empty arrays can be NULL
+            let p = rebop_new(0, null());
+            assert!(!p.is_null());
+            assert_eq!(rebop_add_reaction(p, 1., null(), null()), 0);
+            assert_eq!(rebop_set_species(p, null()), 0);
+            assert_eq!(rebop_run_ensemble(p, 1., 2, 3, 0, null_mut()), 0);
+            rebop_free(p);
+            // the others cannot
+            assert!(rebop_new(2, null()).is_null());
+            assert!(rebop_from_toml(null()).is_null());
+            let p = rebop_new(1, [5].as_ptr());
+            assert_eq!(rebop_add_reaction(p, 1., null(), [1].as_ptr()), -1);
+            assert_eq!(rebop_add_reaction(p, 1., [1].as_ptr(), null()), -1);
+            assert_eq!(rebop_set_species(p, null()), -1);
+            assert_eq!(get_species(p, 0), 5);
+            assert_eq!(rebop_get_species(p, 0, null_mut()), -1);
+            assert_eq!(rebop_run_ensemble(p, 1., 2, 3, 0, null_mut()), -1);
+            let mut out = [0; 3];
+            assert_eq!(
+                rebop_run_ensemble(null(), 1., 2, 1, 0, out.as_mut_ptr()),
+                -1
+            );
+            assert_eq!(
+                rebop_add_reaction(null_mut(), 1., [1].as_ptr(), [1].as_ptr()),
+                -1
+            );
+            // the size of the output overflows
+            assert_eq!(
+                rebop_run_ensemble(p, 1., usize::MAX, 1, 0, out.as_mut_ptr()),
+                -1
+            );
+            assert_eq!(
+                rebop_run_ensemble(p, 1., 1, usize::MAX, 0, out.as_mut_ptr()),
+                -1
+            );
+            rebop_free(p);
+            // a NULL problem
+            rebop_seed(null_mut(), 0);
+            rebop_advance_until(null_mut(), 1.);
+            assert!(rebop_get_time(null()).is_nan());
+            assert_eq!(rebop_nb_species(null()), 0);
+            assert_eq!(rebop_nb_reactions(null()), 0);
+            let mut amount = 0;
+            assert_eq!(rebop_get_species(null(), 0, &mut amount), -1);
+        }
+    }
+}
@@ -0,0 +1,292 @@
+//! Generation of the Rust source of a problem.
+//!
+//! [`define_system`] writes the [`define_system!`](crate::define_system)
+//! definition of a problem built at runtime, for example from Python or
+//! from a model file, with the current initial amounts and rates.  Once
+//! pasted into a Rust program, the macro compiles it into a dedicated
+//! simulator, the fastest way to run large ensembles of the same model.
+//!
+//! ```
+//! use rebop::codegen;
+//! use rebop::gillespie::{Gillespie, Rate};
+//!
+//! let mut sir = Gillespie::new([999, 1, 0]);
+//! sir.set_param("r_inf", 1e-4);
+//! sir.add_reaction(Rate::lma_param("r_inf", [1, 1, 0]), [-1, 1, 0]);
+//! sir.add_reaction(Rate::lma(0.01, [0, 1, 0]), [0, -1, 1]);
+//! let source = codegen::define_system(&sir, "SIR", &["S", "I", "R"]).unwrap();
+//! assert_eq!(
+//!     source,
+//!     "use rebop::define_system;
+//!
+//! define_system! {
+//!     r_inf = 0.0001;
+//!     SIR { S = 999, I = 1, R }
+//!     r0 : S + I => 2 I @ r_inf
+//!     r1 : I => R @ 0.01
+//! }
+//! "
+//! );
+//! ```
+//!
+//! The generated source compiles as is:
+//!
+//! ```
+//! use rebop::define_system;
+//!
+//! define_system! {
+//!     r_inf = 0.0001;
+//!     SIR { S = 999, I = 1, R }
+//!     r0 : S + I => 2 I @ r_inf
+//!     r1 : I => R @ 0.01
+//! }
+//! let mut sir = SIR::new();
+//! sir.advance_until(250.);
+//! assert_eq!(sir.S + sir.I + sir.R, 1000);
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+
+use rand::{Rng, SeedableRng};
+
+use crate::gillespie::{Gillespie, Jump, Rate};
+
+/// Rust keywords, which cannot be the names of species or parameters.
+const KEYWORDS: [&str; 52] = [
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl",
+    "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "Self", "static", "struct", "super", "trait", "true", "try", "type",
+    "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Errors when generating the source of a problem.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CodegenError {
+    /// The number of names differs from the number of species.
+    SpeciesCount { expected: usize, found: usize },
+    /// A name is not a Rust identifier, is reserved, or is used twice.
+    InvalidName(String),
+    /// A reaction cannot be written in `define_system!`.
+    UnsupportedReaction {
+        reaction: usize,
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodegenError::SpeciesCount { expected, found } => {
+                write!(f, "{found} names given for {expected} species")
+            }
+            CodegenError::InvalidName(name) => write!(f, "invalid name `{name}`"),
+            CodegenError::UnsupportedReaction { reaction, reason } => {
+                write!(f, "reaction {reaction} cannot be generated: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CodegenError {}
+
+/// Returns the source of the [`define_system!`](crate::define_system)
+/// definition of `problem`, as a structure named `name` whose species are
+/// named `species`, in order.
+///
+/// The species start from their current amounts, and the reactions are
+/// named `r0`, `r1`, and so on.  The parameters of the rates become
+/// parameters of the structure, with their current values as defaults;
+/// the factors of [`scale_rate`](Gillespie::scale_rate) multiply the
+/// rates, and the disabled reactions have a rate of zero.
+///
+/// Returns an error if a name is not a valid Rust identifier, or if a
+/// reaction does not follow the law of mass action, has a negative or
+/// infinite rate constant, or consumes species that it does not have as
+/// reactants.
+pub fn define_system<R, N>(
+    problem: &Gillespie<R>,
+    name: &str,
+    species: &[N],
+) -> Result<String, CodegenError>
+where
+    R: Rng + SeedableRng,
+    N: AsRef<str>,
+{
+    let nb_species = problem.nb_species();
+    if species.len() != nb_species {
+        return Err(CodegenError::SpeciesCount {
+            expected: nb_species,
+            found: species.len(),
+        });
+    }
+    check_name(name)?;
+    let species: Vec<&str> = species.iter().map(AsRef::as_ref).collect();
+    let mut params = BTreeMap::new();
+    let mut reactions = String::new();
+    for (ireaction, (rate, jump)) in problem.reactions().iter().enumerate() {
+        let unsupported = |reason| CodegenError::UnsupportedReaction {
+            reaction: ireaction,
+            reason,
+        };
+        let disabled = problem.disabled_rate(ireaction);
+        let Rate::LMA(constant, reactants) = disabled.unwrap_or(rate).clone().dense(nb_species)
+        else {
+            return Err(unsupported("the rate is not a law of mass action"));
+        };
+        let mut rate = match problem.symbolic_rate(ireaction) {
+            Some(Rate::LMAParam(param, _)) => {
+                let value = problem
+                    .get_param(param)
+                    .expect("the parameters are defined");
+                params.insert(
+                    param.as_str(),
+                    literal(value).ok_or_else(|| {
+                        unsupported("the rate constant is negative or not finite")
+                    })?,
+                );
+                param.clone()
+            }
+            _ => literal(constant)
+                .ok_or_else(|| unsupported("the rate constant is negative or not finite"))?,
+        };
+        let scale = problem.rate_scale(ireaction);
+        if disabled.is_some() {
+            rate = literal(0.).unwrap();
+        } else if scale != 1. {
+            rate = format!("{rate} * {}", literal(scale).unwrap());
+        }
+        let Jump::Flat(differences) = jump.clone().dense(nb_species) else {
+            unreachable!("dense jumps are flat")
+        };
+        let products: Vec<isize> = reactants
+            .iter()
+            .zip(&differences)
+            .map(|(&reactant, &difference)| reactant as isize + difference)
+            .collect();
+        if products.iter().any(|&product| product < 0) {
+            return Err(unsupported("it consumes species that are not reactants"));
+        }
+        let reactants: Vec<isize> = reactants.iter().map(|&n| n as isize).collect();
+        let (reactants, products) = (side(&species, &reactants), side(&species, &products));
+        let equation = [reactants.as_str(), "=>", &products, "@", &rate];
+        let equation: Vec<&str> = equation.into_iter().filter(|s| !s.is_empty()).collect();
+        writeln!(reactions, "    r{ireaction} : {}", equation.join(" ")).unwrap();
+    }
+    let mut names: Vec<&str> = species.clone();
+    names.extend(params.keys());
+    for (i, name) in names.iter().enumerate() {
+        check_name(name)?;
+        if ["t", "rng"].contains(name) || names[..i].contains(name) {
+            return Err(CodegenError::InvalidName(name.to_string()));
+        }
+    }
+    let params: Vec<String> = params
+        .iter()
+        .map(|(name, value)| format!("{name} = {value}"))
+        .collect();
+    let initial: Vec<String> = species
+        .iter()
+        .zip(problem.species())
+        .map(|(name, &amount)| match amount {
+            0 => name.to_string(),
+            _ => format!("{name} = {amount}"),
+        })
+        .collect();
+    Ok(format!(
+        "use rebop::define_system;\n\ndefine_system! {{\n    {};\n    {name} {{ {} }}\n{reactions}}}\n",
+        params.join(" "),
+        initial.join(", "),
+    ))
+}
+
+/// Checks that `name` is a Rust identifier, and not a keyword.
+fn check_name(name: &str) -> Result<(), CodegenError> {
+    let mut chars = name.chars();
+    let valid = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+        && name != "_"
+        && !KEYWORDS.contains(&name);
+    match valid {
+        true => Ok(()),
+        false => Err(CodegenError::InvalidName(name.to_string())),
+    }
+}
+
+/// Returns the Rust literal of `value`, if it is nonnegative and finite.
+fn literal(value: f64) -> Option<String> {
+    (value.is_finite() && value >= 0.).then(|| format!("{value:?}"))
+}
+
+/// Returns the side of a reaction with the stoichiometries
+/// `stoichiometries` of the species `species`, like `2 A + B`.
+fn side(species: &[&str], stoichiometries: &[isize]) -> String {
+    let terms: Vec<String> = species
+        .iter()
+        .zip(stoichiometries)
+        .filter(|&(_, &n)| n > 0)
+        .map(|(name, &n)| match n {
+            1 => name.to_string(),
+            _ => format!("{n} {name}"),
+        })
+        .collect();
+    terms.join(" + ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codegen::*;
+    use crate::gillespie::Expr;
+
+    #[test]
+    fn generation() {
+        let mut dimers = Gillespie::new([1, 0, 5, 0]);
+        dimers.add_reaction(Rate::lma(25., [1, 0, 0, 0]), [0, 1, 0, 0]);
+        dimers.add_reaction(Rate::lma(1e-3, [0, 0, 2, 0]), [0, 0, -2, 1]);
+        dimers.add_reaction(Rate::lma(0.1, [0, 1, 0, 0]), [0, -1, 0, 0]);
+        dimers.add_reaction(Rate::lma(1., [0, 0, 1, 0]), [0, 0, -1, 0]);
+        dimers.scale_rate(0, 2.);
+        dimers.set_reaction_enabled(3, false);
+        let names = ["gene", "mRNA", "protein", "dimer"];
+        assert_eq!(
+            define_system(&dimers, "Dimers", &names).unwrap(),
+            "use rebop::define_system;
+
+define_system! {
+    ;
+    Dimers { gene = 1, mRNA, protein = 5, dimer }
+    r0 : gene => gene + mRNA @ 25.0 * 2.0
+    r1 : 2 protein => dimer @ 0.001
+    r2 : mRNA => @ 0.1
+    r3 : protein => @ 0.0
+}
+"
+        );
+        assert_eq!(
+            define_system(&dimers, "Dimers", &names[..3]),
+            Err(CodegenError::SpeciesCount {
+                expected: 4,
+                found: 3
+            })
+        );
+        let invalid = ["gene", "mRNA", "t", "dimer"];
+        assert_eq!(
+            define_system(&dimers, "Dimers", &invalid),
+            Err(CodegenError::InvalidName("t".to_string()))
+        );
+        let invalid = ["gene", "mRNA", "2x", "dimer"];
+        assert!(define_system(&dimers, "Dimers", &invalid).is_err());
+        assert!(define_system(&dimers, "fn", &names).is_err());
+        // consumption without reactants
+        let mut p = Gillespie::new([0]);
+        p.add_reaction(Rate::lma(1., [0]), [-1]);
+        assert!(matches!(
+            define_system(&p, "P", &["A"]),
+            Err(CodegenError::UnsupportedReaction { reaction: 0, .. })
+        ));
+        let mut p = Gillespie::new([0]);
+        p.add_reaction(Rate::Expr(Expr::Constant(1.)), [1]);
+        assert!(define_system(&p, "P", &["A"]).is_err());
+    }
+}
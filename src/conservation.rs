@@ -0,0 +1,345 @@
+//! Conservation laws of the stoichiometry, and model reduction.
+//!
+//! A conservation law is a linear combination of the species that no
+//! reaction changes, like `S + I + R` in the SIR model, or the total
+//! amount of an enzyme, free and bound to its substrate.
+//! [`conservation_laws`] finds a basis of these laws from the jumps of
+//! the reactions, and [`reduce`] uses them to eliminate one species per
+//! law: the reduced problem only simulates the other species, and
+//! computes the eliminated ones from the laws where the rates need them,
+//! which shrinks the state and the computations of the propensities.
+//!
+//! ```
+//! use rebop::conservation::{conservation_laws, reduce};
+//! use rebop::gillespie::{Gillespie, Rate};
+//! let mut sir = Gillespie::new_with_seed([999, 1, 0], 0);
+//! sir.add_reaction(Rate::lma(1e-4, [1, 1, 0]), [-1, 1, 0]);
+//! sir.add_reaction(Rate::lma(0.01, [0, 1, 0]), [0, -1, 1]);
+//! let laws = conservation_laws(&sir);
+//! assert_eq!(laws[0].weights, [1., 1., 1.]);
+//! assert_eq!(laws[0].total, 1000.);
+//! // R = 1000 - S - I
+//! let (mut reduced, reduction) = reduce(&sir);
+//! assert_eq!(reduction.eliminated(), [2]);
+//! assert_eq!(reduced.species(), [999, 1]);
+//! reduced.advance_until(250.);
+//! let species = reduction.expand(reduced.species());
+//! assert_eq!(species.iter().sum::<isize>(), 1000);
+//! ```
+
+use crate::gillespie::{reads, Expr, Gillespie, Jump, Rate};
+
+/// Linear combination of the species that no reaction changes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConservationLaw {
+    /// Weight of each species.
+    pub weights: Vec<f64>,
+    /// Value of the combination in the state of the problem.
+    pub total: f64,
+    /// Index of the species that the law determines from the others: its
+    /// weight is `1`, and its weight in the other laws of the basis is
+    /// `0`.
+    pub dependent: usize,
+}
+
+impl ConservationLaw {
+    /// Returns the amount of the dependent species determined by the law
+    /// from the amounts of the other species `species`, rounded to the
+    /// nearest integer.
+    pub fn dependent_amount(&self, species: &[isize]) -> isize {
+        let others: f64 = (self.weights.iter().zip(species).enumerate())
+            .filter(|&(s, _)| s != self.dependent)
+            .map(|(_, (weight, &amount))| weight * amount as f64)
+            .sum();
+        (self.total - others).round() as isize
+    }
+}
+
+/// Returns a basis of the conservation laws of `problem`, with their
+/// totals in its current state.
+///
+/// The dependent species of the laws are preferably those that the rates
+/// read the least, to make the reduction by [`reduce`] cheaper.
+pub fn conservation_laws(problem: &Gillespie) -> Vec<ConservationLaw> {
+    let reactions = problem.reactions();
+    let cost = |s: usize| -> usize {
+        (reactions.iter())
+            .filter(|(rate, _)| reads(rate, s))
+            .map(|(rate, _)| match rate {
+                Rate::LMA(..) | Rate::LMASparse(..) | Rate::LMAParam(..) => 1,
+                Rate::Expr(_) | Rate::Compiled(_) => problem.nb_reactions() + 1,
+            })
+            .sum()
+    };
+    // the species read the most are the pivots of the elimination, and
+    // the others are left to depend on them
+    let mut order: Vec<usize> = (0..problem.nb_species()).collect();
+    order.sort_by_key(|&s| std::cmp::Reverse(cost(s)));
+    null_space(problem, &order)
+        .into_iter()
+        .map(|(dependent, weights)| ConservationLaw {
+            total: (weights.iter().zip(problem.species()))
+                .map(|(weight, &amount)| weight * amount as f64)
+                .sum(),
+            weights,
+            dependent,
+        })
+        .collect()
+}
+
+/// Returns a basis of the weights `w` such that the sum of `w[i]` times
+/// the amount of species `i` is not changed by any reaction of `problem`,
+/// each with the species of weight `1` that the others do not contain.
+///
+/// The stoichiometry matrix is reduced with its columns taken in the
+/// order of the species indices `order`, so that the species of the
+/// laws are the last ones that are linearly dependent on the previous
+/// ones.
+pub(crate) fn null_space(problem: &Gillespie, order: &[usize]) -> Vec<(usize, Vec<f64>)> {
+    let nb_species = problem.nb_species();
+    // stoichiometry matrix, one row per reaction, in reduced row echelon form
+    let mut matrix: Vec<Vec<f64>> = problem
+        .reactions()
+        .iter()
+        .map(|(_, jump)| {
+            let mut row = vec![0.; nb_species];
+            let mut species = vec![0; nb_species];
+            jump.affect(&mut species);
+            for (r, s) in row.iter_mut().zip(species) {
+                *r = s as f64;
+            }
+            row
+        })
+        .collect();
+    let mut pivots = Vec::new();
+    let mut irow = 0;
+    for &column in order {
+        let Some(best) = (irow..matrix.len())
+            .max_by(|&a, &b| matrix[a][column].abs().total_cmp(&matrix[b][column].abs()))
+            .filter(|&best| matrix[best][column].abs() > 1e-9)
+        else {
+            continue;
+        };
+        matrix.swap(irow, best);
+        let pivot = matrix[irow][column];
+        matrix[irow].iter_mut().for_each(|x| *x /= pivot);
+        let pivot_row = matrix[irow].clone();
+        for (other, row) in matrix.iter_mut().enumerate() {
+            let factor = row[column];
+            if other != irow && factor != 0. {
+                row.iter_mut()
+                    .zip(&pivot_row)
+                    .for_each(|(x, p)| *x -= factor * p);
+            }
+        }
+        pivots.push(column);
+        irow += 1;
+    }
+    // one law per free column
+    (order.iter())
+        .filter(|column| !pivots.contains(column))
+        .map(|&free| {
+            let mut law = vec![0.; nb_species];
+            law[free] = 1.;
+            for (row, &pivot) in pivots.iter().enumerate() {
+                law[pivot] = -matrix[row][free];
+            }
+            (free, law)
+        })
+        .collect()
+}
+
+/// How a problem was reduced by [`reduce`], to go from its states to
+/// those of the reduced problem and back.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reduction {
+    /// Laws that determine the eliminated species.
+    laws: Vec<ConservationLaw>,
+    /// Indices of the species kept in the reduced problem, in order.
+    kept: Vec<usize>,
+}
+
+impl Reduction {
+    /// Returns the conservation laws that determine the eliminated
+    /// species.
+    pub fn laws(&self) -> &[ConservationLaw] {
+        &self.laws
+    }
+    /// Returns the indices in the original problem of the species of the
+    /// reduced problem.
+    pub fn kept(&self) -> &[usize] {
+        &self.kept
+    }
+    /// Returns the indices in the original problem of the eliminated
+    /// species.
+    pub fn eliminated(&self) -> Vec<usize> {
+        self.laws.iter().map(|law| law.dependent).collect()
+    }
+    /// Returns the amounts of all species of the original problem from
+    /// the amounts `reduced` of the species of the reduced problem.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `reduced` does not have one element per kept species.
+    pub fn expand(&self, reduced: &[isize]) -> Vec<isize> {
+        assert_eq!(reduced.len(), self.kept.len());
+        let mut species = vec![0; self.kept.len() + self.laws.len()];
+        for (&s, &amount) in self.kept.iter().zip(reduced) {
+            species[s] = amount;
+        }
+        for law in &self.laws {
+            species[law.dependent] = law.dependent_amount(&species);
+        }
+        species
+    }
+    /// Returns the amounts of the species of the reduced problem from the
+    /// amounts `species` of all species of the original problem.
+    pub fn restrict(&self, species: &[isize]) -> Vec<isize> {
+        self.kept.iter().map(|&s| species[s]).collect()
+    }
+}
+
+/// Eliminates one species per conservation law of `problem`, and returns
+/// the reduced problem with the [`Reduction`] that relates their states.
+///
+/// The reduced problem has the same reactions, with the current values of
+/// the rates and of their factors, and starts from the current time and
+/// state of `problem` with the same random number generator, so that it
+/// simulates the same trajectories.  Its rates compute the eliminated
+/// species from the totals of the laws in the current state.  The species
+/// read by expression rates are never eliminated, and the observables,
+/// waiting times, delays, settings and records of `problem` are not
+/// kept.
+pub fn reduce(problem: &Gillespie) -> (Gillespie, Reduction) {
+    let reactions = problem.reactions();
+    let laws: Vec<ConservationLaw> = conservation_laws(problem)
+        .into_iter()
+        .filter(|law| {
+            !reactions.iter().any(|(rate, _)| {
+                matches!(rate, Rate::Expr(_) | Rate::Compiled(_)) && reads(rate, law.dependent)
+            })
+        })
+        .collect();
+    let kept: Vec<usize> = (0..problem.nb_species())
+        .filter(|&s| !laws.iter().any(|law| law.dependent == s))
+        .collect();
+    let reduction = Reduction { laws, kept };
+    // index of each species in the reduced problem, if kept
+    let mut index = vec![None; problem.nb_species()];
+    for (i, &s) in reduction.kept.iter().enumerate() {
+        index[s] = Some(i);
+    }
+    let amount = |s: usize| -> Expr {
+        if let Some(i) = index[s] {
+            return Expr::Concentration(i);
+        }
+        let law = reduction
+            .laws
+            .iter()
+            .find(|law| law.dependent == s)
+            .unwrap();
+        (law.weights.iter().enumerate())
+            .filter(|&(other, &weight)| other != s && weight != 0.)
+            .fold(Expr::Constant(law.total), |total, (other, &weight)| {
+                let term = Expr::Mul(
+                    Box::new(Expr::Constant(weight)),
+                    Box::new(Expr::Concentration(index[other].unwrap())),
+                );
+                Expr::Sub(Box::new(total), Box::new(term))
+            })
+    };
+    let mut reduced = Gillespie::new(reduction.restrict(problem.species()));
+    for (ireaction, (rate, jump)) in reactions.iter().enumerate() {
+        let rate = match rate.clone().sparse() {
+            Rate::LMASparse(k, sparse)
+                if sparse.iter().all(|&(s, _)| index[s as usize].is_some()) =>
+            {
+                let sparse = (sparse.iter())
+                    .map(|&(s, exponent)| (index[s as usize].unwrap() as u32, exponent))
+                    .collect();
+                Rate::LMASparse(k, sparse)
+            }
+            Rate::LMASparse(k, sparse) => {
+                // falling factorials of the amounts, as in the law of mass action
+                let mut expr = Expr::Constant(k);
+                for (s, exponent) in sparse {
+                    for m in 0..exponent {
+                        let factor = Expr::Sub(
+                            Box::new(amount(s as usize)),
+                            Box::new(Expr::Constant(m as f64)),
+                        );
+                        expr = Expr::Mul(Box::new(expr), Box::new(factor));
+                    }
+                }
+                Rate::Expr(expr)
+            }
+            Rate::Compiled(program) => Rate::Compiled(program.map_species(|s| index[s].unwrap())),
+            Rate::LMA(..) | Rate::LMAParam(..) | Rate::Expr(_) => {
+                unreachable!("the problems store sparse, resolved and compiled rates")
+            }
+        };
+        let Jump::Flat(differences) = jump.clone().dense(problem.nb_species()) else {
+            unreachable!("dense jumps are flat")
+        };
+        reduced.add_reaction(rate, reduction.restrict(&differences));
+        let factor = problem.rate_scale(ireaction);
+        if factor != 1. {
+            reduced.scale_rate(ireaction, factor);
+        }
+    }
+    reduced.set_time(problem.get_time());
+    reduced.set_rng(problem.rng().clone());
+    (reduced, reduction)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conservation::{conservation_laws, reduce};
+    use crate::gillespie::{Expr, Gillespie, Rate};
+
+    #[test]
+    fn enzyme() {
+        // E + S <-> ES -> E + P
+        let mut mm = Gillespie::new_with_seed([10, 100, 0, 0], 0);
+        mm.add_reaction(Rate::lma(0.01, [1, 1, 0, 0]), [-1, -1, 1, 0]);
+        mm.add_reaction(Rate::lma(0.1, [0, 0, 1, 0]), [1, 1, -1, 0]);
+        mm.add_reaction(Rate::lma(0.1, [0, 0, 1, 0]), [1, 0, -1, 1]);
+        let laws = conservation_laws(&mm);
+        assert_eq!(laws.len(), 2);
+        // the free enzyme and the product depend on the complex and the
+        // substrate
+        assert_eq!(laws[0].weights, [1., 0., 1., 0.]);
+        assert_eq!((laws[0].dependent, laws[0].total), (0, 10.));
+        assert_eq!(laws[1].weights, [0., 1., 1., 1.]);
+        assert_eq!((laws[1].dependent, laws[1].total), (3, 100.));
+        let (mut reduced, reduction) = reduce(&mm);
+        assert_eq!(reduction.kept(), [1, 2]);
+        assert_eq!(reduced.species(), [100, 0]);
+        // the same random numbers give the same trajectory
+        for t in 1..=10 {
+            mm.advance_until(t as f64);
+            reduced.advance_until(t as f64);
+            assert_eq!(reduction.expand(reduced.species()), mm.species());
+        }
+        assert_eq!(reduction.restrict(mm.species()), reduced.species());
+    }
+    #[test]
+    fn expressions() {
+        // the species read by an expression rate is not eliminated
+        let mut p = Gillespie::new([3, 4]);
+        p.add_reaction(Rate::Expr(Expr::Concentration(1)), [1, -1]);
+        p.add_reaction(Rate::lma(1., [1, 0]), [-1, 1]);
+        p.scale_rate(1, 2.);
+        let (reduced, reduction) = reduce(&p);
+        assert_eq!(reduction.eliminated(), [0]);
+        assert_eq!(reduced.current_propensities(), [4., 6.]);
+        // nor when no other species can be eliminated
+        let mut p = Gillespie::new([3, 4]);
+        p.add_reaction(Rate::Expr(Expr::Concentration(0)), [1, -1]);
+        p.add_reaction(Rate::Expr(Expr::Concentration(1)), [-1, 1]);
+        let (reduced, reduction) = reduce(&p);
+        assert!(reduction.laws().is_empty());
+        assert_eq!(reduced.nb_species(), 2);
+        assert_eq!(conservation_laws(&p).len(), 1);
+    }
+}
@@ -0,0 +1,149 @@
+//! Errors of the function-based API.
+
+use std::fmt;
+
+use crate::gillespie::Limit;
+
+/// Error of a fallible method of [`Gillespie`](crate::gillespie::Gillespie),
+/// like [`try_add_reaction`](crate::gillespie::Gillespie::try_add_reaction),
+/// or of [`GillespieBuilder::build`](crate::builder::GillespieBuilder::build).
+///
+/// The infallible versions of these methods panic with the message of
+/// the error.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    /// A vector indexed by species does not have one element per
+    /// species.
+    SpeciesCount { expected: usize, found: usize },
+    /// A rate or a jump refers to a species that does not exist.
+    UnknownSpecies { index: usize, nb_species: usize },
+    /// There is no reaction of this index.
+    UnknownReaction { index: usize, nb_reactions: usize },
+    /// A rate refers to a parameter that is not defined.
+    UnknownParameter { name: String },
+    /// Firing the reaction of index `reaction` at `time` in the state
+    /// `state` made the amount of the species of index `species`
+    /// negative, usually because of a wrong sign in its jump.
+    NegativeAmount {
+        time: f64,
+        reaction: usize,
+        species: usize,
+        state: Vec<isize>,
+    },
+    /// The rate of the reaction of index `reaction` is negative or `NaN`
+    /// at `time` in the state `state`.
+    InvalidRate {
+        time: f64,
+        reaction: usize,
+        rate: f64,
+        state: Vec<isize>,
+    },
+    /// The sum of the rates of all reactions is infinite at `time` in the
+    /// state `state`.
+    InfiniteTotalRate { time: f64, state: Vec<isize> },
+    /// A limit of
+    /// [`Gillespie::set_limits`](crate::gillespie::Gillespie::set_limits)
+    /// stopped the simulation at `time`, at the last reaction, in the
+    /// state `state`.
+    LimitReached {
+        limit: Limit,
+        time: f64,
+        state: Vec<isize>,
+    },
+    /// A species is declared twice in a
+    /// [`GillespieBuilder`](crate::builder::GillespieBuilder).
+    DuplicateSpecies { name: String },
+    /// A species is declared with a negative initial amount.
+    NegativeInitial { name: String, initial: isize },
+    /// A reaction is declared twice.
+    DuplicateReaction { name: String },
+    /// A reaction refers to a species that is not declared.
+    UndeclaredSpecies { reaction: String, name: String },
+    /// A reaction is declared without rate.
+    MissingRate { reaction: String },
+    /// The rate constant of a reaction is negative or not finite.
+    InvalidRateConstant { reaction: String, rate: f64 },
+    /// The factor of the rate of the reaction of index `reaction` is
+    /// negative or not finite.
+    InvalidFactor { reaction: usize, factor: f64 },
+    /// The operation `operation` only supports problems simulated by the
+    /// direct method, with the default precision and without custom
+    /// waiting times, delays or algorithm.
+    DirectOnly { operation: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::SpeciesCount { expected, found } => write!(
+                f,
+                "expected one value per species ({expected}) but found {found}"
+            ),
+            Error::UnknownSpecies { index, nb_species } => write!(
+                f,
+                "unknown species of index {index}, the problem has {nb_species} species"
+            ),
+            Error::UnknownReaction {
+                index,
+                nb_reactions,
+            } => write!(
+                f,
+                "unknown reaction of index {index}, the problem has {nb_reactions} reactions"
+            ),
+            Error::UnknownParameter { name } => write!(f, "unknown parameter `{name}`"),
+            Error::NegativeAmount {
+                time,
+                reaction,
+                species,
+                state,
+            } => write!(
+                f,
+                "reaction {reaction} makes species {species} negative at time {time} \
+                 from the state {state:?}"
+            ),
+            Error::InvalidRate {
+                time,
+                reaction,
+                rate,
+                state,
+            } => write!(
+                f,
+                "reaction {reaction} has the invalid rate {rate} at time {time} \
+                 in the state {state:?}"
+            ),
+            Error::InfiniteTotalRate { time, state } => write!(
+                f,
+                "the total rate is infinite at time {time} in the state {state:?}"
+            ),
+            Error::LimitReached { limit, time, state } => write!(
+                f,
+                "the simulation reached its {limit} at time {time} in the state {state:?}"
+            ),
+            Error::DuplicateSpecies { name } => write!(f, "species `{name}` declared twice"),
+            Error::NegativeInitial { name, initial } => write!(
+                f,
+                "species `{name}` has the negative initial amount {initial}"
+            ),
+            Error::DuplicateReaction { name } => write!(f, "reaction `{name}` declared twice"),
+            Error::UndeclaredSpecies { reaction, name } => write!(
+                f,
+                "reaction `{reaction}` refers to the undeclared species `{name}`"
+            ),
+            Error::MissingRate { reaction } => write!(f, "reaction `{reaction}` has no rate"),
+            Error::InvalidRateConstant { reaction, rate } => write!(
+                f,
+                "reaction `{reaction}` has the invalid rate constant {rate}"
+            ),
+            Error::InvalidFactor { reaction, factor } => {
+                write!(f, "reaction {reaction} has the invalid factor {factor}")
+            }
+            Error::DirectOnly { operation } => write!(
+                f,
+                "{operation} only support the direct method, without custom waiting times, \
+                 delays, algorithm or extended precision"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,228 @@
+//! Exact logs of the reactions that fire during a simulation.
+//!
+//! When [`Gillespie::set_record_events`] is enabled, the problem records
+//! the time and the index of each reaction that fires in an
+//! [`EventLog`], together with the state at which the log starts.  The
+//! log determines the whole trajectory: [`Gillespie::replay`] reproduces
+//! it without random numbers, so that any observable can be computed
+//! after the fact, and a rare event can be inspected reaction by
+//! reaction.  Logs are saved in a compact binary format, of 12 bytes per
+//! reaction, by [`EventLog::write_to`] and read by
+//! [`EventLog::read_from`].
+//!
+//! ```
+//! use rebop::events::EventLog;
+//! use rebop::gillespie::{Gillespie, Rate};
+//!
+//! let mut dimers = Gillespie::new_with_seed([100, 0], 0);
+//! dimers.add_reaction(Rate::lma(0.01, [2, 0]), [-2, 1]);
+//! dimers.set_record_events(true);
+//! dimers.advance_until(10.);
+//! let mut bytes = Vec::new();
+//! dimers.event_log().unwrap().write_to(&mut bytes).unwrap();
+//!
+//! let log = EventLog::read_from(&bytes[..]).unwrap();
+//! let mut replayed = Gillespie::new([0, 0]);
+//! replayed.add_reaction(Rate::lma(0.01, [2, 0]), [-2, 1]);
+//! let mut max_dimers = 0;
+//! replayed
+//!     .replay_with(&log, |_, _, species| max_dimers = max_dimers.max(species[1]))
+//!     .unwrap();
+//! assert_eq!(replayed.species(), dimers.species());
+//! assert_eq!(max_dimers, dimers.species()[1]);
+//! ```
+//!
+//! [`Gillespie::set_record_events`]: crate::gillespie::Gillespie::set_record_events
+//! [`Gillespie::replay`]: crate::gillespie::Gillespie::replay
+
+use std::fmt;
+use std::io::{Read, Write};
+
+/// First bytes of the files of event logs.
+const MAGIC: &[u8; 8] = b"rebopLOG";
+
+/// Version of the binary format of event logs.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Errors when reading an event log.
+#[derive(Debug)]
+pub enum LogError {
+    /// The underlying reader failed, or the log is truncated.
+    Io(std::io::Error),
+    /// The data is not an event log of a supported version.
+    Invalid(String),
+}
+
+impl fmt::Display for LogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogError::Io(e) => write!(f, "cannot read event log: {e}"),
+            LogError::Invalid(msg) => write!(f, "invalid event log: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for LogError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LogError::Io(e) => Some(e),
+            LogError::Invalid(_) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for LogError {
+    fn from(e: std::io::Error) -> Self {
+        LogError::Io(e)
+    }
+}
+
+/// Sequence of the reactions that fired from an initial state, with
+/// their times.
+///
+/// The approximate methods, like R-leaping, record the reactions of a
+/// step at its end.  The slow-scale method also moves the fast pairs to
+/// their equilibrium without firing them, so that its logs cannot be
+/// replayed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EventLog {
+    start: f64,
+    initial: Vec<isize>,
+    times: Vec<f64>,
+    reactions: Vec<u32>,
+}
+
+impl EventLog {
+    /// Creates an empty log from the time `start` and the amounts of
+    /// species `initial`.
+    pub fn new(start: f64, initial: Vec<isize>) -> Self {
+        EventLog {
+            start,
+            initial,
+            times: Vec::new(),
+            reactions: Vec::new(),
+        }
+    }
+    /// Time at which the log starts.
+    pub fn start(&self) -> f64 {
+        self.start
+    }
+    /// Amounts of species at which the log starts.
+    pub fn initial(&self) -> &[isize] {
+        &self.initial
+    }
+    /// Number of reactions that fired.
+    pub fn len(&self) -> usize {
+        self.times.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.times.is_empty()
+    }
+    /// Records that the reaction of index `ireaction` fired at `t`.
+    pub fn push(&mut self, t: f64, ireaction: usize) {
+        self.times.push(t);
+        self.reactions.push(ireaction as u32);
+    }
+    /// Renumbers the reactions, the reaction of index `i` becoming the
+    /// reaction of index `position[i]`.
+    pub(crate) fn renumber(&mut self, position: &[usize]) {
+        for ireaction in &mut self.reactions {
+            *ireaction = position[*ireaction as usize] as u32;
+        }
+    }
+    /// Returns the times and the indices of the reactions that fired, in
+    /// order.
+    pub fn events(&self) -> impl Iterator<Item = (f64, usize)> + '_ {
+        self.times
+            .iter()
+            .zip(&self.reactions)
+            .map(|(&t, &ireaction)| (t, ireaction as usize))
+    }
+    /// Writes the log in its binary format.
+    ///
+    /// All numbers are little-endian: after the 8 bytes `rebopLOG`, the
+    /// version of the format (`u32`), the number of species and the
+    /// number of reactions that fired (`u64`), the start time (`f64`),
+    /// the initial amounts (`i64`), then the times (`f64`) and the
+    /// indices (`u32`) of the reactions.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        writer.write_all(&(self.initial.len() as u64).to_le_bytes())?;
+        writer.write_all(&(self.times.len() as u64).to_le_bytes())?;
+        writer.write_all(&self.start.to_le_bytes())?;
+        for &n in &self.initial {
+            writer.write_all(&(n as i64).to_le_bytes())?;
+        }
+        for t in &self.times {
+            writer.write_all(&t.to_le_bytes())?;
+        }
+        for ireaction in &self.reactions {
+            writer.write_all(&ireaction.to_le_bytes())?;
+        }
+        writer.flush()
+    }
+    /// Reads a log written by [`write_to`](EventLog::write_to).
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self, LogError> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(LogError::Invalid("not an event log".to_string()));
+        }
+        let version = u32::from_le_bytes(read_bytes(&mut reader)?);
+        if version != FORMAT_VERSION {
+            return Err(LogError::Invalid(format!(
+                "format version {version}, expected {FORMAT_VERSION}"
+            )));
+        }
+        let nb_species = u64::from_le_bytes(read_bytes(&mut reader)?);
+        let nb_events = u64::from_le_bytes(read_bytes(&mut reader)?);
+        let mut log = EventLog::new(f64::from_le_bytes(read_bytes(&mut reader)?), Vec::new());
+        for _ in 0..nb_species {
+            log.initial
+                .push(i64::from_le_bytes(read_bytes(&mut reader)?) as isize);
+        }
+        for _ in 0..nb_events {
+            log.times.push(f64::from_le_bytes(read_bytes(&mut reader)?));
+        }
+        for _ in 0..nb_events {
+            log.reactions
+                .push(u32::from_le_bytes(read_bytes(&mut reader)?));
+        }
+        Ok(log)
+    }
+}
+
+/// Reads the next `N` bytes of `reader`.
+fn read_bytes<const N: usize, R: Read>(reader: &mut R) -> std::io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::events::*;
+
+    #[test]
+    fn binary_format() {
+        let mut log = EventLog::new(1.5, vec![3, -1]);
+        log.push(2., 0);
+        log.push(2.25, 7);
+        let mut bytes = Vec::new();
+        log.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 8 + 4 + 8 + 8 + 8 + 2 * 8 + 2 * 12);
+        let read = EventLog::read_from(&bytes[..]).unwrap();
+        assert_eq!(read, log);
+        assert_eq!(read.events().collect::<Vec<_>>(), [(2., 0), (2.25, 7)]);
+        assert!(matches!(
+            EventLog::read_from(&bytes[..bytes.len() - 1]),
+            Err(LogError::Io(_))
+        ));
+        bytes[0] = b'R';
+        assert!(matches!(
+            EventLog::read_from(&bytes[..]),
+            Err(LogError::Invalid(_))
+        ));
+    }
+}
@@ -0,0 +1,492 @@
+//! State estimation from noisy observations.
+//!
+//! An [`EnsembleKalmanFilter`] propagates an ensemble of stochastic
+//! simulations of a problem and corrects them at each observation with
+//! a Gaussian update.  It is much cheaper than a particle filter for
+//! models with many species, and accurate when the copy numbers are
+//! large enough for their distribution to be nearly Gaussian.
+//!
+//! ```
+//! use rebop::filter::EnsembleKalmanFilter;
+//! use rebop::gillespie::{Gillespie, Rate};
+//! let mut birth_death = Gillespie::new([0]);
+//! birth_death.add_reaction(Rate::lma(10., [0]), [1]);
+//! birth_death.add_reaction(Rate::lma(0.1, [1]), [-1]);
+//! // the amount of A is observed with a noise of variance 4
+//! let mut enkf = EnsembleKalmanFilter::new(&birth_death, 100, vec![0], vec![4.]);
+//! enkf.seed(0);
+//! enkf.assimilate(10., &[70.]);
+//! enkf.assimilate(20., &[85.]);
+//! assert!((enkf.mean()[0] - 85.).abs() < 10.);
+//! ```
+//!
+//! A [`ParticleFilter`] (bootstrap filter) keeps the simulations
+//! unchanged, but weights and resamples them according to the
+//! likelihood of each observation.  It makes no Gaussian approximation,
+//! and it estimates the likelihood of the observations under the model
+//! without bias, which is what particle MCMC methods need: see
+//! [`particle_log_likelihood`].
+//!
+//! ```
+//! use rebop::filter::particle_log_likelihood;
+//! use rebop::gillespie::{Gillespie, Rate};
+//! use rebop::inference::Observations;
+//! let make = |k: f64| {
+//!     let mut p = Gillespie::new([0]);
+//!     p.add_reaction(Rate::lma(k, [0]), [1]);
+//!     p.add_reaction(Rate::lma(0.1, [1]), [-1]);
+//!     p
+//! };
+//! let observed = Observations::simulate(&make(10.), &[10., 20., 30.], &[0], 0);
+//! let likelihood = |k| particle_log_likelihood(&make(k), &observed, &[4.], 200, 0);
+//! assert!(likelihood(10.) > likelihood(5.));
+//! assert!(likelihood(10.) > likelihood(20.));
+//! ```
+
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::StandardNormal;
+
+use crate::gillespie::Gillespie;
+use crate::inference::Observations;
+use crate::system::SSASystem;
+
+/// Ensemble Kalman filter with perturbed observations.
+///
+/// Some species are observed, with independent Gaussian noises.  At each
+/// observation, all the members of the ensemble are simulated until the
+/// time of the observation, and their states are then moved towards the
+/// observation according to the covariances estimated from the
+/// ensemble.  The updated amounts are rounded to the nearest
+/// non-negative integers.
+#[derive(Clone, Debug)]
+pub struct EnsembleKalmanFilter {
+    ensemble: Vec<Gillespie>,
+    observed: Vec<usize>,
+    noise_variances: Vec<f64>,
+    rng: SmallRng,
+}
+
+impl EnsembleKalmanFilter {
+    /// Creates a filter with `size` members, all in the current state of
+    /// `problem`.  The species of indices `observed` are observed with
+    /// noises of variances `noise_variances`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size < 2`, if `observed` and `noise_variances` have
+    /// different lengths, or if an observed species does not exist.
+    pub fn new(
+        problem: &Gillespie,
+        size: usize,
+        observed: Vec<usize>,
+        noise_variances: Vec<f64>,
+    ) -> Self {
+        assert!(size >= 2, "the ensemble needs at least two members");
+        assert_eq!(observed.len(), noise_variances.len());
+        assert!(observed.iter().all(|&i| i < problem.nb_species()));
+        let mut filter = EnsembleKalmanFilter {
+            ensemble: vec![problem.clone(); size],
+            observed,
+            noise_variances,
+            rng: SmallRng::from_entropy(),
+        };
+        filter.reseed_members();
+        filter
+    }
+    /// Seeds the random number generators of the filter and of all
+    /// members of the ensemble.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = SmallRng::seed_from_u64(seed);
+        self.reseed_members();
+    }
+    fn reseed_members(&mut self) {
+        for member in self.ensemble.iter_mut() {
+            member.seed(self.rng.gen());
+        }
+    }
+    /// Returns the members of the ensemble.
+    pub fn ensemble(&self) -> &[Gillespie] {
+        &self.ensemble
+    }
+    /// Returns the mean amount of each species in the ensemble.
+    pub fn mean(&self) -> Vec<f64> {
+        let nb_species = self.ensemble[0].nb_species();
+        let mut mean = vec![0.; nb_species];
+        for member in &self.ensemble {
+            for (m, s) in mean.iter_mut().zip(member.species()) {
+                *m += *s as f64;
+            }
+        }
+        let size = self.ensemble.len() as f64;
+        mean.iter_mut().for_each(|m| *m /= size);
+        mean
+    }
+    /// Simulates all the members until `t`, then updates them with the
+    /// values `observation` of the observed species at time `t`.
+    pub fn assimilate(&mut self, t: f64, observation: &[f64]) {
+        assert_eq!(observation.len(), self.observed.len());
+        for member in self.ensemble.iter_mut() {
+            member.advance_until(t);
+        }
+        self.update(observation);
+    }
+    /// Gaussian update of all the members with `observation`.
+    fn update(&mut self, observation: &[f64]) {
+        let size = self.ensemble.len();
+        let nb_species = self.ensemble[0].nb_species();
+        let nb_observed = self.observed.len();
+        let states: Vec<Vec<f64>> = self
+            .ensemble
+            .iter()
+            .map(|member| member.species().iter().map(|&s| s as f64).collect())
+            .collect();
+        let mean = self.mean();
+        // covariances between all species and the observed species, and
+        // between observed species
+        let mut cov_xh = vec![vec![0.; nb_observed]; nb_species];
+        let mut cov_hh = vec![vec![0.; nb_observed]; nb_observed];
+        for state in &states {
+            for (j, &oj) in self.observed.iter().enumerate() {
+                let dj = state[oj] - mean[oj];
+                for (row, (x, m)) in cov_xh.iter_mut().zip(state.iter().zip(&mean)) {
+                    row[j] += (x - m) * dj;
+                }
+                for (k, &ok) in self.observed.iter().enumerate() {
+                    cov_hh[k][j] += (state[ok] - mean[ok]) * dj;
+                }
+            }
+        }
+        let norm = 1. / (size - 1) as f64;
+        cov_xh.iter_mut().flatten().for_each(|c| *c *= norm);
+        cov_hh.iter_mut().flatten().for_each(|c| *c *= norm);
+        for (j, variance) in self.noise_variances.iter().enumerate() {
+            cov_hh[j][j] += variance;
+        }
+        let Some(cholesky) = cholesky(&cov_hh) else {
+            // no information: degenerate ensemble without observation noise
+            return;
+        };
+        for (member, state) in self.ensemble.iter_mut().zip(states) {
+            let mut innovation: Vec<f64> = self
+                .observed
+                .iter()
+                .zip(observation)
+                .zip(&self.noise_variances)
+                .map(|((&o, y), variance)| {
+                    y + variance.sqrt() * self.rng.sample::<f64, _>(StandardNormal) - state[o]
+                })
+                .collect();
+            cholesky_solve(&cholesky, &mut innovation);
+            let species: Vec<isize> = state
+                .iter()
+                .zip(&cov_xh)
+                .map(|(x, row)| {
+                    let x = x + row.iter().zip(&innovation).map(|(c, w)| c * w).sum::<f64>();
+                    x.round().max(0.) as isize
+                })
+                .collect();
+            member.set_species(species);
+        }
+    }
+}
+
+/// Bootstrap particle filter.
+///
+/// Some species are observed, with independent Gaussian noises.  At each
+/// observation, all the particles are simulated until the time of the
+/// observation and weighted by the likelihood of the observation given
+/// their state.  The mean of the weights estimates the likelihood of the
+/// observation given the previous ones, and the particles are then
+/// resampled in proportion to their weights (systematic resampling),
+/// with new seeds so that copies of a particle evolve independently.
+///
+/// The particles can be problems of either API of rebop (see
+/// [`SSASystem`]).
+#[derive(Clone, Debug)]
+pub struct ParticleFilter<P = Gillespie> {
+    particles: Vec<P>,
+    observed: Vec<usize>,
+    noise_variances: Vec<f64>,
+    log_likelihood: f64,
+    rng: SmallRng,
+}
+
+impl<P: SSASystem + Clone> ParticleFilter<P> {
+    /// Creates a filter with `size` particles, all in the current state
+    /// of `problem`.  The species of indices `observed` are observed with
+    /// noises of variances `noise_variances`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no particle, if `observed` and
+    /// `noise_variances` have different lengths, if an observed species
+    /// does not exist, or if a variance is not positive.
+    pub fn new(problem: &P, size: usize, observed: Vec<usize>, noise_variances: Vec<f64>) -> Self {
+        assert!(size > 0, "the filter needs at least one particle");
+        assert_eq!(observed.len(), noise_variances.len());
+        assert!(observed.iter().all(|&i| i < problem.nb_species()));
+        assert!(
+            noise_variances.iter().all(|&v| v > 0.),
+            "the noise variances must be positive"
+        );
+        let mut filter = ParticleFilter {
+            particles: vec![problem.clone(); size],
+            observed,
+            noise_variances,
+            log_likelihood: 0.,
+            rng: SmallRng::from_entropy(),
+        };
+        filter.reseed_particles();
+        filter
+    }
+    /// Seeds the random number generators of the filter and of all
+    /// particles.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = SmallRng::seed_from_u64(seed);
+        self.reseed_particles();
+    }
+    fn reseed_particles(&mut self) {
+        for particle in self.particles.iter_mut() {
+            particle.seed(self.rng.gen());
+        }
+    }
+    /// Returns the particles, which all have the same weight after each
+    /// observation.
+    pub fn particles(&self) -> &[P] {
+        &self.particles
+    }
+    /// Returns the mean amount of each species over the particles.
+    pub fn mean(&self) -> Vec<f64> {
+        let nb_species = self.particles[0].nb_species();
+        let mut mean = vec![0.; nb_species];
+        for particle in &self.particles {
+            for (m, s) in mean.iter_mut().zip(particle.species().iter()) {
+                *m += *s as f64;
+            }
+        }
+        let size = self.particles.len() as f64;
+        mean.iter_mut().for_each(|m| *m /= size);
+        mean
+    }
+    /// Returns the estimated log-likelihood of all the observations so
+    /// far, `-∞` if none of the particles could explain one of them.
+    pub fn log_likelihood(&self) -> f64 {
+        self.log_likelihood
+    }
+    /// Simulates all the particles until `t`, then weights and resamples
+    /// them with the values `observation` of the observed species at
+    /// time `t`.  Returns the estimated log-likelihood of this
+    /// observation given the previous ones.
+    pub fn assimilate(&mut self, t: f64, observation: &[f64]) -> f64 {
+        assert_eq!(observation.len(), self.observed.len());
+        let log_weights: Vec<f64> = self
+            .particles
+            .iter_mut()
+            .map(|particle| {
+                particle.advance_until(t);
+                let species = particle.species();
+                self.observed
+                    .iter()
+                    .zip(observation)
+                    .zip(&self.noise_variances)
+                    .map(|((&o, y), variance)| {
+                        let residual = y - species[o] as f64;
+                        -0.5 * (residual * residual / variance
+                            + (2. * std::f64::consts::PI * variance).ln())
+                    })
+                    .sum()
+            })
+            .collect();
+        let max = log_weights
+            .iter()
+            .copied()
+            .fold(f64::NEG_INFINITY, f64::max);
+        if max == f64::NEG_INFINITY {
+            self.log_likelihood = f64::NEG_INFINITY;
+            return f64::NEG_INFINITY;
+        }
+        let weights: Vec<f64> = log_weights.iter().map(|w| (w - max).exp()).collect();
+        let total: f64 = weights.iter().sum();
+        let size = self.particles.len();
+        let increment = max + (total / size as f64).ln();
+        self.log_likelihood += increment;
+        // systematic resampling
+        let step = total / size as f64;
+        let mut position = step * self.rng.gen::<f64>();
+        let mut cumulated = 0.;
+        let mut chosen = 0;
+        let mut resampled = Vec::with_capacity(size);
+        for (i, weight) in weights.iter().enumerate() {
+            cumulated += weight;
+            while position < cumulated && resampled.len() < size {
+                resampled.push(i);
+                position += step;
+            }
+            chosen = i;
+        }
+        // rounding errors may leave the last positions unassigned
+        resampled.resize(size, chosen);
+        self.particles = resampled
+            .into_iter()
+            .map(|i| self.particles[i].clone())
+            .collect();
+        self.reseed_particles();
+        increment
+    }
+}
+
+/// Estimates the log-likelihood of `observations` under `problem`, with
+/// a [`ParticleFilter`] of `nb_particles` particles seeded with `seed`.
+///
+/// The observed species are those of `observations`, with independent
+/// Gaussian noises of variances `noise_variances`.  The likelihood itself
+/// (not its logarithm) is estimated without bias, so that this estimate
+/// can replace the exact likelihood in the acceptance ratio of a
+/// particle marginal Metropolis-Hastings algorithm.  Its variance
+/// decreases with the number of particles, and using the same seed for
+/// all parameters makes the estimates vary smoothly with them.
+///
+/// # Panics
+///
+/// Panics in the same cases as [`ParticleFilter::new`].
+pub fn particle_log_likelihood<P: SSASystem + Clone>(
+    problem: &P,
+    observations: &Observations,
+    noise_variances: &[f64],
+    nb_particles: usize,
+    seed: u64,
+) -> f64 {
+    let mut filter = ParticleFilter::new(
+        problem,
+        nb_particles,
+        observations.observables.clone(),
+        noise_variances.to_vec(),
+    );
+    filter.seed(seed);
+    let mut observation = vec![0.; observations.observables.len()];
+    for (i, &t) in observations.times.iter().enumerate() {
+        for (o, values) in observation.iter_mut().zip(&observations.values) {
+            *o = values[i];
+        }
+        if filter.assimilate(t, &observation) == f64::NEG_INFINITY {
+            break;
+        }
+    }
+    filter.log_likelihood()
+}
+
+/// Returns the lower triangular Cholesky factor of the symmetric
+/// positive definite matrix `a`, or `None` if it is not positive
+/// definite.
+fn cholesky(a: &[Vec<f64>]) -> Option<Vec<Vec<f64>>> {
+    let n = a.len();
+    let mut l = vec![vec![0.; n]; n];
+    for i in 0..n {
+        for j in 0..=i {
+            let s: f64 = (0..j).map(|k| l[i][k] * l[j][k]).sum();
+            if i == j {
+                let d = a[i][i] - s;
+                if d <= 0. || d.is_nan() {
+                    return None;
+                }
+                l[i][i] = d.sqrt();
+            } else {
+                l[i][j] = (a[i][j] - s) / l[j][j];
+            }
+        }
+    }
+    Some(l)
+}
+
+/// Solves `L L^T x = b` in place, `l` being a Cholesky factor.
+fn cholesky_solve(l: &[Vec<f64>], b: &mut [f64]) {
+    let n = l.len();
+    for i in 0..n {
+        let s: f64 = (0..i).map(|k| l[i][k] * b[k]).sum();
+        b[i] = (b[i] - s) / l[i][i];
+    }
+    for i in (0..n).rev() {
+        let s: f64 = (i + 1..n).map(|k| l[k][i] * b[k]).sum();
+        b[i] = (b[i] - s) / l[i][i];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::filter::{
+        cholesky, cholesky_solve, particle_log_likelihood, EnsembleKalmanFilter, ParticleFilter,
+    };
+    use crate::gillespie::{Gillespie, Rate};
+    use crate::inference::Observations;
+    #[test]
+    fn solve() {
+        let a = vec![vec![4., 2.], vec![2., 3.]];
+        let l = cholesky(&a).unwrap();
+        let mut b = [2., 5.];
+        cholesky_solve(&l, &mut b);
+        assert!((b[0] + 0.5).abs() < 1e-12);
+        assert!((b[1] - 2.).abs() < 1e-12);
+        assert!(cholesky(&[vec![0.]]).is_none());
+    }
+    #[test]
+    fn tracks_observation() {
+        // A is converted to B, only B is observed
+        let mut problem = Gillespie::new_with_seed([1000, 0], 0);
+        problem.add_reaction(Rate::lma(0.05, [1, 0]), [-1, 1]);
+        let mut truth = problem.clone();
+        // the filter starts from a wrong initial state
+        problem.set_species([800, 0]);
+        let mut enkf = EnsembleKalmanFilter::new(&problem, 200, vec![1], vec![4.]);
+        enkf.seed(0);
+        for t in 1..=20 {
+            truth.advance_until(t as f64);
+            enkf.assimilate(t as f64, &[truth.get_species(1) as f64]);
+        }
+        let mean = enkf.mean();
+        assert!((mean[1] - truth.get_species(1) as f64).abs() < 20.);
+        assert!(enkf
+            .ensemble()
+            .iter()
+            .all(|member| member.get_time() == 20.));
+    }
+    #[test]
+    fn particle_filter() {
+        // deterministic problem: no reaction, A stays at 10
+        let mut problem = Gillespie::new([10, 3]);
+        problem.add_reaction(Rate::lma(0., [1, 0]), [-1, 0]);
+        let mut filter = ParticleFilter::new(&problem, 10, vec![0], vec![4.]);
+        filter.seed(0);
+        // Gaussian density of the residual 2 with variance 4
+        let expected = -0.5 * (1. + (8. * std::f64::consts::PI).ln());
+        assert!((filter.assimilate(1., &[12.]) - expected).abs() < 1e-12);
+        assert!((filter.assimilate(2., &[8.]) - expected).abs() < 1e-12);
+        assert!((filter.log_likelihood() - 2. * expected).abs() < 1e-12);
+        assert_eq!(filter.mean(), [10., 3.]);
+        assert_eq!(filter.particles().len(), 10);
+    }
+    #[test]
+    fn likelihood() {
+        let make = |k: f64| {
+            let mut p = Gillespie::new([1000, 0]);
+            p.add_reaction(Rate::lma(k, [1, 0]), [-1, 1]);
+            p
+        };
+        let times: Vec<f64> = (1..=10).map(f64::from).collect();
+        let observed = Observations::simulate(&make(0.05), &times, &[1], 0);
+        let likelihoods: Vec<f64> = [0.03, 0.05, 0.07]
+            .iter()
+            .map(|&k| particle_log_likelihood(&make(k), &observed, &[9.], 200, 0))
+            .collect();
+        assert!(likelihoods[1] > likelihoods[0], "{likelihoods:?}");
+        assert!(likelihoods[1] > likelihoods[2], "{likelihoods:?}");
+        // the seed determines the estimate
+        let again = particle_log_likelihood(&make(0.05), &observed, &[9.], 200, 0);
+        assert_eq!(again, likelihoods[1]);
+        // no particle can explain an observation
+        let mut impossible = observed.clone();
+        impossible.values[0][5] = 1e6;
+        let l = particle_log_likelihood(&make(0.05), &impossible, &[1e-300], 10, 0);
+        assert_eq!(l, f64::NEG_INFINITY);
+    }
+}
@@ -0,0 +1,233 @@
+//! Problems with a number of species fixed at compile time.
+//!
+//! [`Gillespie<N>`](Gillespie) stores the amounts of its `N` species, and
+//! the reactants and the changes of each reaction, in arrays instead of
+//! vectors.  The compiler then knows the length of the loops over the
+//! species, which it unrolls, and the inner loop of the direct method
+//! does not follow pointers to the heap.  This suits small models, of up
+//! to about 16 species, whose rates follow the law of mass action.
+//!
+//! The simulations are the same as those of
+//! [`gillespie::Gillespie`](crate::gillespie::Gillespie) with the direct
+//! method: from the same seed, both give the same trajectories.
+//!
+//! ```
+//! use rebop::fixed::Gillespie;
+//! let mut sir = Gillespie::new_with_seed([999, 1, 0], 0);
+//! sir.add_reaction(1e-4, [1, 1, 0], [-1, 1, 0]);
+//! sir.add_reaction(0.01, [0, 1, 0], [0, -1, 1]);
+//! sir.advance_until(250.);
+//! assert_eq!(sir.species().iter().sum::<isize>(), 1000);
+//! assert_eq!(sir.to_gillespie().species(), sir.species());
+//! ```
+
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::Exp1;
+
+use crate::gillespie::{self, Rate};
+
+/// Reaction of a problem with `N` species.
+#[derive(Clone, Debug)]
+struct Reaction<const N: usize> {
+    /// Rate constant of the law of mass action.
+    rate: f64,
+    /// Stoichiometry of each species in the reactants.
+    reactants: [u32; N],
+    /// Indices and stoichiometries of the reactants, in the order of the
+    /// species, followed by unused entries.
+    sparse: [(usize, u32); N],
+    /// Number of reactants.
+    nb_reactants: usize,
+    /// Change of each species when the reaction fires.
+    differences: [isize; N],
+}
+
+impl<const N: usize> Reaction<N> {
+    /// Returns the rate for the amounts of species `species`, like
+    /// [`Rate::lma`].
+    fn rate(&self, species: &[isize; N]) -> f64 {
+        let mut rate = self.rate;
+        for &(i, e) in &self.sparse[..self.nb_reactants] {
+            let n = species[i];
+            // most reactions are of order one in each species
+            match e {
+                1 => rate *= n as f64,
+                _ => {
+                    for x in n + 1 - e as isize..=n {
+                        rate *= x as f64;
+                    }
+                }
+            }
+        }
+        rate
+    }
+}
+
+/// Reaction system of `N` species, simulated with the direct method.
+#[derive(Clone, Debug)]
+pub struct Gillespie<const N: usize, R = SmallRng> {
+    species: [isize; N],
+    t: f64,
+    reactions: Vec<Reaction<N>>,
+    rng: R,
+    /// Cumulative rates of the reactions, reused by the simulations.
+    cum_rates: Vec<f64>,
+}
+
+impl<const N: usize> Gillespie<N> {
+    /// Creates a problem with the initial amounts of species `species`.
+    pub fn new(species: [isize; N]) -> Self {
+        Gillespie::with_rng(species, SmallRng::from_entropy())
+    }
+    /// Creates a problem with the initial amounts of species `species`,
+    /// whose random number generator is seeded by `seed`.
+    pub fn new_with_seed(species: [isize; N], seed: u64) -> Self {
+        Gillespie::with_rng(species, SmallRng::seed_from_u64(seed))
+    }
+}
+
+impl<const N: usize, R: Rng + SeedableRng> Gillespie<N, R> {
+    /// Creates a problem with the initial amounts of species `species`
+    /// and the random number generator `rng`.
+    pub fn with_rng(species: [isize; N], rng: R) -> Self {
+        Gillespie {
+            species,
+            t: 0.,
+            reactions: Vec::new(),
+            rng,
+            cum_rates: Vec::new(),
+        }
+    }
+    /// Seeds the random number generator.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = R::seed_from_u64(seed);
+    }
+    /// Adds a reaction of law of mass action with the rate constant
+    /// `rate` and the stoichiometries `reactants`, which changes the
+    /// amounts of species by `differences`.
+    pub fn add_reaction(&mut self, rate: f64, reactants: [u32; N], differences: [isize; N]) {
+        let mut sparse = [(0, 0); N];
+        let mut nb_reactants = 0;
+        for (i, &e) in reactants.iter().enumerate() {
+            if e > 0 {
+                sparse[nb_reactants] = (i, e);
+                nb_reactants += 1;
+            }
+        }
+        self.reactions.push(Reaction {
+            rate,
+            reactants,
+            sparse,
+            nb_reactants,
+            differences,
+        });
+        self.cum_rates.push(f64::NAN);
+    }
+    pub fn nb_species(&self) -> usize {
+        N
+    }
+    pub fn nb_reactions(&self) -> usize {
+        self.reactions.len()
+    }
+    /// Returns the current time in the model.
+    pub fn get_time(&self) -> f64 {
+        self.t
+    }
+    /// Sets the current time in the model.
+    pub fn set_time(&mut self, t: f64) {
+        self.t = t;
+    }
+    /// Returns the current amount of the species of index `s`.
+    pub fn get_species(&self, s: usize) -> isize {
+        self.species[s]
+    }
+    /// Returns the current amounts of all species.
+    pub fn species(&self) -> &[isize; N] {
+        &self.species
+    }
+    /// Sets the amounts of species in the model.
+    pub fn set_species(&mut self, species: [isize; N]) {
+        self.species = species;
+    }
+    /// Simulates the problem until `tmax` with the direct method.
+    pub fn advance_until(&mut self, tmax: f64) {
+        loop {
+            let mut total_rate = 0.;
+            for (reaction, cum_rate) in self.reactions.iter().zip(self.cum_rates.iter_mut()) {
+                total_rate += reaction.rate(&self.species);
+                *cum_rate = total_rate;
+            }
+            // we don't want to use partial_cmp, for performance
+            #[allow(clippy::neg_cmp_op_on_partial_ord)]
+            if !(0. < total_rate) {
+                self.t = tmax;
+                return;
+            }
+            let t = self.t + self.rng.sample::<f64, _>(Exp1) / total_rate;
+            if t > tmax {
+                self.t = tmax;
+                return;
+            }
+            self.t = t;
+            let chosen_rate = total_rate * self.rng.gen::<f64>();
+            let ireaction = self
+                .cum_rates
+                .iter()
+                .filter(|&&cum_rate| cum_rate < chosen_rate)
+                .count();
+            // here ireaction < self.reactions.len() because chosen_rate < total_rate
+            let differences = &self.reactions[ireaction].differences;
+            for (amount, difference) in self.species.iter_mut().zip(differences) {
+                *amount += difference;
+            }
+        }
+    }
+    /// Converts the problem to a [`gillespie::Gillespie`], with the same
+    /// state and random number generator, to use its other methods.
+    pub fn to_gillespie(&self) -> gillespie::Gillespie<R>
+    where
+        R: Clone,
+    {
+        let mut problem = gillespie::Gillespie::with_rng(self.species, self.rng.clone());
+        problem.set_time(self.t);
+        for reaction in &self.reactions {
+            problem.add_reaction(
+                Rate::lma(reaction.rate, reaction.reactants),
+                reaction.differences,
+            );
+        }
+        problem
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixed::Gillespie;
+    use crate::gillespie::{self, Rate};
+
+    #[test]
+    fn same_trajectories() {
+        let mut fixed = Gillespie::new_with_seed([20, 0, 0], 3);
+        let mut problem = gillespie::Gillespie::new_with_seed([20, 0, 0], 3);
+        for (rate, reactants, differences) in [
+            (0.1, [2, 0, 0], [-2, 1, 0]),
+            (1., [0, 1, 0], [2, -1, 0]),
+            (0.5, [0, 1, 0], [0, -1, 1]),
+            (0.2, [0, 0, 1], [0, 0, -1]),
+        ] {
+            fixed.add_reaction(rate, reactants, differences);
+            problem.add_reaction(Rate::lma(rate, reactants), differences);
+        }
+        for t in [0.5, 1., 2., 5., 10.] {
+            fixed.advance_until(t);
+            problem.advance_until(t);
+            assert_eq!(&fixed.species()[..], problem.species());
+            assert_eq!(fixed.get_time(), problem.get_time());
+        }
+        let mut converted = fixed.to_gillespie();
+        fixed.advance_until(20.);
+        converted.advance_until(20.);
+        assert_eq!(&fixed.species()[..], converted.species());
+    }
+}
@@ -1,9 +1,24 @@
 //! Function-based API to describe chemical reaction networks and
 //! simulate them.
 
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::fmt::{self, Debug};
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
 use rand::rngs::SmallRng;
-use rand::{Rng, SeedableRng};
-use rand_distr::Exp1;
+use rand::{Rng, RngCore, SeedableRng};
+use rand_distr::{Binomial, Distribution, Exp1, Gamma};
+
+use crate::algorithm::{SsaAlgorithm, SsaState};
+use crate::error::Error;
+use crate::events::EventLog;
+#[cfg(feature = "jit")]
+use crate::jit::NativeRates;
+use crate::simd::{self, LmaRates};
 
 #[derive(Clone, Debug)]
 pub enum Expr {
@@ -15,12 +30,387 @@ pub enum Expr {
     Div(Box<Expr>, Box<Expr>),
     Pow(Box<Expr>, Box<Expr>),
     Exp(Box<Expr>),
+    /// Natural logarithm.
+    Ln(Box<Expr>),
+    Log10(Box<Expr>),
+    Sqrt(Box<Expr>),
+    Abs(Box<Expr>),
+    Floor(Box<Expr>),
+    Ceil(Box<Expr>),
+    Sin(Box<Expr>),
+    Cos(Box<Expr>),
+    Min(Box<Expr>, Box<Expr>),
+    Max(Box<Expr>, Box<Expr>),
+    /// Hill function `x^n / (k^n + x^n)` of `x`, `k` and `n`.
+    Hill(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// Comparisons, `1` when they hold and `0` otherwise.
+    Lt(Box<Expr>, Box<Expr>),
+    Le(Box<Expr>, Box<Expr>),
+    Gt(Box<Expr>, Box<Expr>),
+    Ge(Box<Expr>, Box<Expr>),
+    Eq(Box<Expr>, Box<Expr>),
+    Ne(Box<Expr>, Box<Expr>),
+    /// Logical operators, that consider any value but `0` as true, and
+    /// return `1` or `0`.
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    /// `If(condition, a, b)` is `a` if `condition` is not `0`, and `b`
+    /// otherwise.  Only the chosen branch is evaluated.  Nested, they
+    /// express piecewise functions, like threshold-dependent rates or
+    /// step inputs.
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// Value of a named parameter of the problem, see
+    /// [`Gillespie::set_param`].
+    Parameter(String),
 }
 
 impl Expr {
+    /// Returns the operands of the expression.
+    fn operands(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Constant(_) | Expr::Concentration(_) | Expr::Parameter(_) => Vec::new(),
+            Expr::Exp(a)
+            | Expr::Ln(a)
+            | Expr::Log10(a)
+            | Expr::Sqrt(a)
+            | Expr::Abs(a)
+            | Expr::Floor(a)
+            | Expr::Ceil(a)
+            | Expr::Sin(a)
+            | Expr::Cos(a)
+            | Expr::Not(a) => vec![a],
+            Expr::Add(a, b)
+            | Expr::Sub(a, b)
+            | Expr::Mul(a, b)
+            | Expr::Div(a, b)
+            | Expr::Pow(a, b)
+            | Expr::Min(a, b)
+            | Expr::Max(a, b)
+            | Expr::Lt(a, b)
+            | Expr::Le(a, b)
+            | Expr::Gt(a, b)
+            | Expr::Ge(a, b)
+            | Expr::Eq(a, b)
+            | Expr::Ne(a, b)
+            | Expr::And(a, b)
+            | Expr::Or(a, b) => vec![a, b],
+            Expr::Hill(x, k, n) => vec![x, k, n],
+            Expr::If(condition, a, b) => vec![condition, a, b],
+        }
+    }
+    /// Checks that the expression only refers to the `nb_species`
+    /// species of a problem.
+    fn check(&self, nb_species: usize) -> Result<(), Error> {
+        match self {
+            &Expr::Concentration(index) if index >= nb_species => {
+                Err(Error::UnknownSpecies { index, nb_species })
+            }
+            _ => self
+                .operands()
+                .into_iter()
+                .try_for_each(|a| a.check(nb_species)),
+        }
+    }
+    /// Returns the expression with its parameters replaced by their
+    /// values in `params`.
+    fn resolve(&self, params: &BTreeMap<String, f64>) -> Result<Expr, Error> {
+        let resolve = |a: &Expr| a.resolve(params).map(Box::new);
+        Ok(match self {
+            Expr::Constant(_) | Expr::Concentration(_) => self.clone(),
+            Expr::Add(a, b) => Expr::Add(resolve(a)?, resolve(b)?),
+            Expr::Sub(a, b) => Expr::Sub(resolve(a)?, resolve(b)?),
+            Expr::Mul(a, b) => Expr::Mul(resolve(a)?, resolve(b)?),
+            Expr::Div(a, b) => Expr::Div(resolve(a)?, resolve(b)?),
+            Expr::Pow(a, b) => Expr::Pow(resolve(a)?, resolve(b)?),
+            Expr::Exp(a) => Expr::Exp(resolve(a)?),
+            Expr::Ln(a) => Expr::Ln(resolve(a)?),
+            Expr::Log10(a) => Expr::Log10(resolve(a)?),
+            Expr::Sqrt(a) => Expr::Sqrt(resolve(a)?),
+            Expr::Abs(a) => Expr::Abs(resolve(a)?),
+            Expr::Floor(a) => Expr::Floor(resolve(a)?),
+            Expr::Ceil(a) => Expr::Ceil(resolve(a)?),
+            Expr::Sin(a) => Expr::Sin(resolve(a)?),
+            Expr::Cos(a) => Expr::Cos(resolve(a)?),
+            Expr::Min(a, b) => Expr::Min(resolve(a)?, resolve(b)?),
+            Expr::Max(a, b) => Expr::Max(resolve(a)?, resolve(b)?),
+            Expr::Hill(x, k, n) => Expr::Hill(resolve(x)?, resolve(k)?, resolve(n)?),
+            Expr::Lt(a, b) => Expr::Lt(resolve(a)?, resolve(b)?),
+            Expr::Le(a, b) => Expr::Le(resolve(a)?, resolve(b)?),
+            Expr::Gt(a, b) => Expr::Gt(resolve(a)?, resolve(b)?),
+            Expr::Ge(a, b) => Expr::Ge(resolve(a)?, resolve(b)?),
+            Expr::Eq(a, b) => Expr::Eq(resolve(a)?, resolve(b)?),
+            Expr::Ne(a, b) => Expr::Ne(resolve(a)?, resolve(b)?),
+            Expr::And(a, b) => Expr::And(resolve(a)?, resolve(b)?),
+            Expr::Or(a, b) => Expr::Or(resolve(a)?, resolve(b)?),
+            Expr::Not(a) => Expr::Not(resolve(a)?),
+            Expr::If(condition, a, b) => Expr::If(resolve(condition)?, resolve(a)?, resolve(b)?),
+            Expr::Parameter(name) => Expr::Constant(parameter(params, name)?),
+        })
+    }
+    /// Whether the expression refers to parameters.
+    fn has_parameters(&self) -> bool {
+        match self {
+            Expr::Parameter(_) => true,
+            _ => self.operands().into_iter().any(Expr::has_parameters),
+        }
+    }
+    /// Returns an equivalent expression that is cheaper to evaluate: the
+    /// operations on constants are computed, the conditions on constants
+    /// are resolved, the neutral operations like `a + 0`, `a * 1` and
+    /// `a ^ 1` are removed, `a * 0` becomes `0` when `a` is always finite,
+    /// and the nested powers `(a ^ b) ^ c` of constant exponents collapse
+    /// into `a ^ (b c)` when it is exact, for integer exponents or an
+    /// amount of species `a`.
+    ///
+    /// The problems simplify the expression rates once their parameters
+    /// are replaced by their values, so that the terms that only depend
+    /// on parameters are computed once.  The simplified expression has
+    /// the same value for all amounts of species, up to the rounding of
+    /// the collapsed powers and the sign of zeros.
+    ///
+    /// ```
+    /// use rebop::gillespie::Expr;
+    /// let c = |value: f64| Box::new(Expr::Constant(value));
+    /// // (1 + 0) * x0 ^ (4 / 2) + 0
+    /// let expr = Expr::Add(
+    ///     Box::new(Expr::Mul(
+    ///         Box::new(Expr::Add(c(1.), c(0.))),
+    ///         Box::new(Expr::Pow(
+    ///             Box::new(Expr::Concentration(0)),
+    ///             Box::new(Expr::Div(c(4.), c(2.))),
+    ///         )),
+    ///     )),
+    ///     c(0.),
+    /// );
+    /// let simplified = expr.simplify();
+    /// assert_eq!(format!("{simplified:?}"), "Pow(Concentration(0), Constant(2.0))");
+    /// assert_eq!(simplified.compile().eval(&[3]), 9.);
+    /// ```
+    pub fn simplify(&self) -> Expr {
+        let simplify = |a: &Expr| Box::new(a.simplify());
+        let expr = match self {
+            Expr::Constant(_) | Expr::Concentration(_) | Expr::Parameter(_) => return self.clone(),
+            Expr::Add(a, b) => Expr::Add(simplify(a), simplify(b)),
+            Expr::Sub(a, b) => Expr::Sub(simplify(a), simplify(b)),
+            Expr::Mul(a, b) => Expr::Mul(simplify(a), simplify(b)),
+            Expr::Div(a, b) => Expr::Div(simplify(a), simplify(b)),
+            Expr::Pow(a, b) => Expr::Pow(simplify(a), simplify(b)),
+            Expr::Exp(a) => Expr::Exp(simplify(a)),
+            Expr::Ln(a) => Expr::Ln(simplify(a)),
+            Expr::Log10(a) => Expr::Log10(simplify(a)),
+            Expr::Sqrt(a) => Expr::Sqrt(simplify(a)),
+            Expr::Abs(a) => Expr::Abs(simplify(a)),
+            Expr::Floor(a) => Expr::Floor(simplify(a)),
+            Expr::Ceil(a) => Expr::Ceil(simplify(a)),
+            Expr::Sin(a) => Expr::Sin(simplify(a)),
+            Expr::Cos(a) => Expr::Cos(simplify(a)),
+            Expr::Min(a, b) => Expr::Min(simplify(a), simplify(b)),
+            Expr::Max(a, b) => Expr::Max(simplify(a), simplify(b)),
+            Expr::Hill(x, k, n) => Expr::Hill(simplify(x), simplify(k), simplify(n)),
+            Expr::Lt(a, b) => Expr::Lt(simplify(a), simplify(b)),
+            Expr::Le(a, b) => Expr::Le(simplify(a), simplify(b)),
+            Expr::Gt(a, b) => Expr::Gt(simplify(a), simplify(b)),
+            Expr::Ge(a, b) => Expr::Ge(simplify(a), simplify(b)),
+            Expr::Eq(a, b) => Expr::Eq(simplify(a), simplify(b)),
+            Expr::Ne(a, b) => Expr::Ne(simplify(a), simplify(b)),
+            Expr::And(a, b) => Expr::And(simplify(a), simplify(b)),
+            Expr::Or(a, b) => Expr::Or(simplify(a), simplify(b)),
+            Expr::Not(a) => Expr::Not(simplify(a)),
+            Expr::If(condition, a, b) => match condition.simplify() {
+                Expr::Constant(c) if c != 0. => return a.simplify(),
+                Expr::Constant(_) => return b.simplify(),
+                condition => Expr::If(Box::new(condition), simplify(a), simplify(b)),
+            },
+        };
+        if expr
+            .operands()
+            .into_iter()
+            .all(|a| matches!(a, Expr::Constant(_)))
+        {
+            Expr::Constant(expr.eval(&[]))
+        } else {
+            expr.identities()
+        }
+    }
+    /// Removes the neutral operations at the root of the expression,
+    /// whose operands are simplified.
+    fn identities(self) -> Expr {
+        let is = |a: &Expr, value: f64| matches!(*a, Expr::Constant(c) if c == value);
+        match self {
+            Expr::Add(a, b) if is(&b, 0.) => *a,
+            Expr::Add(a, b) if is(&a, 0.) => *b,
+            Expr::Sub(a, b) if is(&b, 0.) => *a,
+            Expr::Mul(a, b) if is(&b, 1.) => *a,
+            Expr::Mul(a, b) if is(&a, 1.) => *b,
+            Expr::Mul(a, b) if is(&a, 0.) && b.is_finite() || is(&b, 0.) && a.is_finite() => {
+                Expr::Constant(0.)
+            }
+            Expr::Div(a, b) if is(&b, 1.) => *a,
+            Expr::Pow(_, b) if is(&b, 0.) => Expr::Constant(1.),
+            Expr::Pow(a, b) if is(&b, 1.) => *a,
+            Expr::Pow(a, c) => match (*a, *c) {
+                (Expr::Pow(a, b), Expr::Constant(c)) => match *b {
+                    Expr::Constant(b)
+                        if b.fract() == 0. && c.fract() == 0.
+                            || matches!(*a, Expr::Concentration(_)) =>
+                    {
+                        Expr::Pow(a, Box::new(Expr::Constant(b * c))).identities()
+                    }
+                    b => Expr::Pow(
+                        Box::new(Expr::Pow(a, Box::new(b))),
+                        Box::new(Expr::Constant(c)),
+                    ),
+                },
+                (a, c) => Expr::Pow(Box::new(a), Box::new(c)),
+            },
+            expr => expr,
+        }
+    }
+    /// Whether the expression is finite for all amounts of species,
+    /// conservatively.
+    fn is_finite(&self) -> bool {
+        match self {
+            Expr::Constant(c) => c.is_finite(),
+            Expr::Concentration(_)
+            | Expr::Lt(_, _)
+            | Expr::Le(_, _)
+            | Expr::Gt(_, _)
+            | Expr::Ge(_, _)
+            | Expr::Eq(_, _)
+            | Expr::Ne(_, _)
+            | Expr::And(_, _)
+            | Expr::Or(_, _)
+            | Expr::Not(_) => true,
+            Expr::Abs(a) | Expr::Floor(a) | Expr::Ceil(a) | Expr::Sin(a) | Expr::Cos(a) => {
+                a.is_finite()
+            }
+            Expr::Min(a, b) | Expr::Max(a, b) | Expr::If(_, a, b) => a.is_finite() && b.is_finite(),
+            _ => false,
+        }
+    }
+    /// Compiles the expression into a [`Program`], which evaluates it
+    /// faster.  The parameters evaluate to `NaN`: they must be replaced
+    /// by their values before, as the problems do.
+    ///
+    /// ```
+    /// use rebop::gillespie::Expr;
+    /// // 2 + x0 * x1
+    /// let expr = Expr::Add(
+    ///     Box::new(Expr::Constant(2.)),
+    ///     Box::new(Expr::Mul(
+    ///         Box::new(Expr::Concentration(0)),
+    ///         Box::new(Expr::Concentration(1)),
+    ///     )),
+    /// );
+    /// assert_eq!(expr.compile().eval(&[3, 4]), 14.);
+    /// ```
+    pub fn compile(&self) -> Program {
+        let mut ops = Vec::new();
+        let depth = self.emit(&mut ops, 0);
+        let mut species: Vec<usize> = ops
+            .iter()
+            .filter_map(|op| match *op {
+                Op::Concentration(index) => Some(index),
+                _ => None,
+            })
+            .collect();
+        species.sort_unstable();
+        species.dedup();
+        Program {
+            ops,
+            depth,
+            species,
+        }
+    }
+    /// Appends to `ops` the instructions that push the value of the
+    /// expression over `height` values, and returns the maximal height
+    /// of the stack meanwhile.
+    fn emit(&self, ops: &mut Vec<Op>, height: usize) -> usize {
+        fn binary(ops: &mut Vec<Op>, height: usize, a: &Expr, b: &Expr, op: Op) -> usize {
+            let depth = a.emit(ops, height).max(b.emit(ops, height + 1));
+            ops.push(op);
+            depth
+        }
+        match self {
+            Expr::Constant(c) => {
+                ops.push(Op::Constant(*c));
+                height + 1
+            }
+            &Expr::Concentration(index) => {
+                ops.push(Op::Concentration(index));
+                height + 1
+            }
+            Expr::Parameter(_) => {
+                ops.push(Op::Constant(f64::NAN));
+                height + 1
+            }
+            Expr::Add(a, b) => binary(ops, height, a, b, Op::Add),
+            Expr::Sub(a, b) => binary(ops, height, a, b, Op::Sub),
+            Expr::Mul(a, b) => binary(ops, height, a, b, Op::Mul),
+            Expr::Div(a, b) => binary(ops, height, a, b, Op::Div),
+            Expr::Pow(a, b) => binary(ops, height, a, b, Op::Pow),
+            Expr::Min(a, b) => binary(ops, height, a, b, Op::Min),
+            Expr::Max(a, b) => binary(ops, height, a, b, Op::Max),
+            Expr::Lt(a, b) => binary(ops, height, a, b, Op::Lt),
+            Expr::Le(a, b) => binary(ops, height, a, b, Op::Le),
+            Expr::Gt(a, b) => binary(ops, height, a, b, Op::Gt),
+            Expr::Ge(a, b) => binary(ops, height, a, b, Op::Ge),
+            Expr::Eq(a, b) => binary(ops, height, a, b, Op::Eq),
+            Expr::Ne(a, b) => binary(ops, height, a, b, Op::Ne),
+            Expr::And(a, b) => binary(ops, height, a, b, Op::And),
+            Expr::Or(a, b) => binary(ops, height, a, b, Op::Or),
+            Expr::Exp(a)
+            | Expr::Ln(a)
+            | Expr::Log10(a)
+            | Expr::Sqrt(a)
+            | Expr::Abs(a)
+            | Expr::Floor(a)
+            | Expr::Ceil(a)
+            | Expr::Sin(a)
+            | Expr::Cos(a)
+            | Expr::Not(a) => {
+                let depth = a.emit(ops, height);
+                ops.push(match self {
+                    Expr::Exp(_) => Op::Exp,
+                    Expr::Ln(_) => Op::Ln,
+                    Expr::Log10(_) => Op::Log10,
+                    Expr::Sqrt(_) => Op::Sqrt,
+                    Expr::Abs(_) => Op::Abs,
+                    Expr::Floor(_) => Op::Floor,
+                    Expr::Ceil(_) => Op::Ceil,
+                    Expr::Sin(_) => Op::Sin,
+                    Expr::Cos(_) => Op::Cos,
+                    _ => Op::Not,
+                });
+                depth
+            }
+            Expr::Hill(x, k, n) => {
+                let depth = x
+                    .emit(ops, height)
+                    .max(k.emit(ops, height + 1))
+                    .max(n.emit(ops, height + 2));
+                ops.push(Op::Hill);
+                depth
+            }
+            Expr::If(condition, a, b) => {
+                let mut depth = condition.emit(ops, height);
+                let jump_to_b = ops.len();
+                ops.push(Op::JumpIfZero(0));
+                depth = depth.max(a.emit(ops, height));
+                let jump_to_end = ops.len();
+                ops.push(Op::Jump(0));
+                ops[jump_to_b] = Op::JumpIfZero(ops.len());
+                depth = depth.max(b.emit(ops, height));
+                ops[jump_to_end] = Op::Jump(ops.len());
+                depth
+            }
+        }
+    }
     fn eval(&self, species: &[isize]) -> f64 {
         match self {
             Expr::Constant(c) => *c,
+            // the indices are checked when the rate is added
             Expr::Concentration(i) => *unsafe { species.get_unchecked(*i) } as f64,
             Expr::Add(a, b) => a.eval(species) + b.eval(species),
             Expr::Sub(a, b) => a.eval(species) - b.eval(species),
@@ -28,6 +418,238 @@ impl Expr {
             Expr::Div(a, b) => a.eval(species) / b.eval(species),
             Expr::Pow(a, b) => a.eval(species).powf(b.eval(species)),
             Expr::Exp(a) => a.eval(species).exp(),
+            Expr::Ln(a) => a.eval(species).ln(),
+            Expr::Log10(a) => a.eval(species).log10(),
+            Expr::Sqrt(a) => a.eval(species).sqrt(),
+            Expr::Abs(a) => a.eval(species).abs(),
+            Expr::Floor(a) => a.eval(species).floor(),
+            Expr::Ceil(a) => a.eval(species).ceil(),
+            Expr::Sin(a) => a.eval(species).sin(),
+            Expr::Cos(a) => a.eval(species).cos(),
+            Expr::Min(a, b) => a.eval(species).min(b.eval(species)),
+            Expr::Max(a, b) => a.eval(species).max(b.eval(species)),
+            Expr::Hill(x, k, n) => {
+                let n = n.eval(species);
+                let xn = x.eval(species).powf(n);
+                xn / (k.eval(species).powf(n) + xn)
+            }
+            Expr::Lt(a, b) => truth(a.eval(species) < b.eval(species)),
+            Expr::Le(a, b) => truth(a.eval(species) <= b.eval(species)),
+            Expr::Gt(a, b) => truth(a.eval(species) > b.eval(species)),
+            Expr::Ge(a, b) => truth(a.eval(species) >= b.eval(species)),
+            Expr::Eq(a, b) => truth(a.eval(species) == b.eval(species)),
+            Expr::Ne(a, b) => truth(a.eval(species) != b.eval(species)),
+            Expr::And(a, b) => truth(a.eval(species) != 0. && b.eval(species) != 0.),
+            Expr::Or(a, b) => truth(a.eval(species) != 0. || b.eval(species) != 0.),
+            Expr::Not(a) => truth(a.eval(species) == 0.),
+            Expr::If(condition, a, b) => {
+                if condition.eval(species) != 0. {
+                    a.eval(species)
+                } else {
+                    b.eval(species)
+                }
+            }
+            // the parameters are replaced when the rate is added
+            Expr::Parameter(_) => f64::NAN,
+        }
+    }
+}
+
+/// Value of a boolean in an [`Expr`].
+fn truth(b: bool) -> f64 {
+    if b {
+        1.
+    } else {
+        0.
+    }
+}
+
+/// Instruction of a [`Program`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Op {
+    Constant(f64),
+    Concentration(usize),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Exp,
+    Ln,
+    Log10,
+    Sqrt,
+    Abs,
+    Floor,
+    Ceil,
+    Sin,
+    Cos,
+    Min,
+    Max,
+    Hill,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+    Not,
+    /// Pops the condition, and continues at the given instruction if it
+    /// is `0`.
+    JumpIfZero(usize),
+    Jump(usize),
+}
+
+/// Stack size of the programs evaluated without allocation.
+const STACK_SIZE: usize = 16;
+
+/// [`Expr`] compiled by [`Expr::compile`] into a flat sequence of
+/// instructions, in postfix order, evaluated over a stack.
+///
+/// Evaluating a program does not chase pointers through the boxed nodes
+/// of an expression, which makes it faster.  The problems compile the
+/// expression rates they are given, see [`Rate::compile`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    pub(crate) ops: Vec<Op>,
+    /// Maximal height of the stack.
+    depth: usize,
+    /// Sorted indices of the species read by the program.
+    species: Vec<usize>,
+}
+
+impl Program {
+    /// Evaluates the program for the amounts of species `species`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the program reads a species out of `species`.
+    pub fn eval(&self, species: &[isize]) -> f64 {
+        if let Some(&last) = self.species.last() {
+            assert!(last < species.len(), "species {last} out of bounds");
+        }
+        // SAFETY: the species were just checked
+        unsafe { self.eval_unchecked(species) }
+    }
+    /// Evaluates the program without checking that the species it reads
+    /// are in `species`.
+    ///
+    /// # Safety
+    ///
+    /// The program must only read species of `species`, which
+    /// [`check`](Program::check) guarantees.
+    unsafe fn eval_unchecked(&self, species: &[isize]) -> f64 {
+        if self.depth <= STACK_SIZE {
+            self.run(species, &mut [0.; STACK_SIZE])
+        } else {
+            self.run(species, &mut vec![0.; self.depth])
+        }
+    }
+    /// Runs the program with the stack `stack`, large enough.
+    ///
+    /// # Safety
+    ///
+    /// See [`eval_unchecked`](Program::eval_unchecked).
+    #[inline(always)]
+    unsafe fn run(&self, species: &[isize], stack: &mut [f64]) -> f64 {
+        // the stack is large enough for the programs made by compile, and
+        // every instruction but the first pops as many values as it pushes
+        #[inline(always)]
+        unsafe fn unary(stack: &mut [f64], len: usize, f: impl Fn(f64) -> f64) {
+            let a = stack.get_unchecked_mut(len - 1);
+            *a = f(*a);
+        }
+        #[inline(always)]
+        unsafe fn binary(stack: &mut [f64], len: &mut usize, f: impl Fn(f64, f64) -> f64) {
+            *len -= 1;
+            let b = *stack.get_unchecked(*len);
+            let a = stack.get_unchecked_mut(*len - 1);
+            *a = f(*a, b);
+        }
+        let mut len = 0;
+        let mut pc = 0;
+        while let Some(&op) = self.ops.get(pc) {
+            pc += 1;
+            match op {
+                Op::Constant(c) => {
+                    *stack.get_unchecked_mut(len) = c;
+                    len += 1;
+                }
+                Op::Concentration(index) => {
+                    *stack.get_unchecked_mut(len) = *species.get_unchecked(index) as f64;
+                    len += 1;
+                }
+                Op::Add => binary(stack, &mut len, |a, b| a + b),
+                Op::Sub => binary(stack, &mut len, |a, b| a - b),
+                Op::Mul => binary(stack, &mut len, |a, b| a * b),
+                Op::Div => binary(stack, &mut len, |a, b| a / b),
+                Op::Pow => binary(stack, &mut len, f64::powf),
+                Op::Exp => unary(stack, len, f64::exp),
+                Op::Ln => unary(stack, len, f64::ln),
+                Op::Log10 => unary(stack, len, f64::log10),
+                Op::Sqrt => unary(stack, len, f64::sqrt),
+                Op::Abs => unary(stack, len, f64::abs),
+                Op::Floor => unary(stack, len, f64::floor),
+                Op::Ceil => unary(stack, len, f64::ceil),
+                Op::Sin => unary(stack, len, f64::sin),
+                Op::Cos => unary(stack, len, f64::cos),
+                Op::Min => binary(stack, &mut len, f64::min),
+                Op::Max => binary(stack, &mut len, f64::max),
+                Op::Hill => {
+                    len -= 2;
+                    let (x, k, n) = (stack[len - 1], stack[len], stack[len + 1]);
+                    let xn = x.powf(n);
+                    stack[len - 1] = xn / (k.powf(n) + xn);
+                }
+                Op::Lt => binary(stack, &mut len, |a, b| truth(a < b)),
+                Op::Le => binary(stack, &mut len, |a, b| truth(a <= b)),
+                Op::Gt => binary(stack, &mut len, |a, b| truth(a > b)),
+                Op::Ge => binary(stack, &mut len, |a, b| truth(a >= b)),
+                Op::Eq => binary(stack, &mut len, |a, b| truth(a == b)),
+                Op::Ne => binary(stack, &mut len, |a, b| truth(a != b)),
+                Op::And => binary(stack, &mut len, |a, b| truth(a != 0. && b != 0.)),
+                Op::Or => binary(stack, &mut len, |a, b| truth(a != 0. || b != 0.)),
+                Op::Not => unary(stack, len, |a| truth(a == 0.)),
+                Op::JumpIfZero(target) => {
+                    len -= 1;
+                    if stack[len] == 0. {
+                        pc = target;
+                    }
+                }
+                Op::Jump(target) => pc = target,
+            }
+        }
+        stack[0]
+    }
+    /// Checks that the program only reads the `nb_species` species of a
+    /// problem.
+    fn check(&self, nb_species: usize) -> Result<(), Error> {
+        match self.species.last() {
+            Some(&index) if index >= nb_species => Err(Error::UnknownSpecies { index, nb_species }),
+            _ => Ok(()),
+        }
+    }
+    /// Returns the sorted indices of the species read by the program.
+    pub fn species(&self) -> &[usize] {
+        &self.species
+    }
+    /// Returns the program that reads the species of index `map(i)`
+    /// instead of each species of index `i`.
+    pub(crate) fn map_species(&self, map: impl Fn(usize) -> usize) -> Program {
+        let ops = (self.ops.iter())
+            .map(|&op| match op {
+                Op::Concentration(index) => Op::Concentration(map(index)),
+                op => op,
+            })
+            .collect();
+        let mut species: Vec<usize> = self.species.iter().map(|&index| map(index)).collect();
+        species.sort_unstable();
+        species.dedup();
+        Program {
+            ops,
+            depth: self.depth,
+            species,
         }
     }
 }
@@ -36,13 +658,34 @@ impl Expr {
 pub enum Rate {
     LMA(f64, Vec<u32>),
     LMASparse(f64, Vec<(u32, u32)>),
+    /// Law of mass action whose rate constant is the value of a named
+    /// parameter of the problem, see [`Gillespie::set_param`].
+    LMAParam(String, Vec<u32>),
     Expr(Expr),
+    /// Expression compiled by [`Rate::compile`], the form in which the
+    /// problems store the expression rates.
+    Compiled(Program),
+}
+
+/// Returns the value of the parameter `name`.
+fn parameter(params: &BTreeMap<String, f64>, name: &str) -> Result<f64, Error> {
+    params
+        .get(name)
+        .copied()
+        .ok_or_else(|| Error::UnknownParameter {
+            name: name.to_string(),
+        })
 }
 
 impl Rate {
     pub fn lma<V: AsRef<[u32]>>(rate: f64, reactants: V) -> Self {
         Rate::LMA(rate, reactants.as_ref().to_vec())
     }
+    /// Law of mass action with the rate constant given by the parameter
+    /// `name`.
+    pub fn lma_param<V: AsRef<[u32]>>(name: &str, reactants: V) -> Self {
+        Rate::LMAParam(name.to_string(), reactants.as_ref().to_vec())
+    }
     pub fn sparse(self) -> Self {
         match self {
             Rate::LMA(rate, reactants) => {
@@ -55,11 +698,96 @@ impl Rate {
                     .collect();
                 Rate::LMASparse(rate, sparse)
             }
-            Rate::LMASparse(_, _) => self,
-            Rate::Expr(_) => unimplemented!(),
+            Rate::LMASparse(_, _) | Rate::LMAParam(_, _) | Rate::Expr(_) | Rate::Compiled(_) => {
+                self
+            }
+        }
+    }
+    /// Converts a sparse rate into a dense one, for a problem with
+    /// `nb_species` species.
+    pub fn dense(self, nb_species: usize) -> Self {
+        match self {
+            Rate::LMASparse(rate, sparse) => {
+                let mut reactants = vec![0; nb_species];
+                for (index, exponent) in sparse {
+                    reactants[index as usize] += exponent;
+                }
+                Rate::LMA(rate, reactants)
+            }
+            Rate::LMA(_, _) | Rate::LMAParam(_, _) | Rate::Expr(_) | Rate::Compiled(_) => self,
+        }
+    }
+    /// Chooses the dense or the sparse representation of a law of mass
+    /// action for a problem of `nb_species` species: the dense one when
+    /// the reactants are at least half of the species, which avoids the
+    /// indirection of the indices, and the sparse one otherwise.
+    pub(crate) fn auto(self, nb_species: usize) -> Self {
+        let nonzeros = match &self {
+            Rate::LMA(_, reactants) => reactants.iter().filter(|&&e| e > 0).count(),
+            Rate::LMASparse(_, sparse) => sparse.iter().filter(|&&(_, e)| e > 0).count(),
+            Rate::LMAParam(_, _) | Rate::Expr(_) | Rate::Compiled(_) => return self,
+        };
+        if 2 * nonzeros >= nb_species {
+            self.dense(nb_species)
+        } else {
+            self.sparse()
+        }
+    }
+    /// Compiles an expression rate into a [`Program`], once simplified,
+    /// see [`Expr::simplify`] and [`Expr::compile`].  The other rates are
+    /// returned unchanged.
+    pub fn compile(self) -> Self {
+        match self {
+            Rate::Expr(expr) => Rate::Compiled(expr.simplify().compile()),
+            _ => self,
+        }
+    }
+    /// Checks that the rate only refers to the `nb_species` species of a
+    /// problem.
+    pub(crate) fn check(&self, nb_species: usize) -> Result<(), Error> {
+        match self {
+            Rate::LMA(_, reactants) | Rate::LMAParam(_, reactants)
+                if reactants.len() != nb_species =>
+            {
+                Err(Error::SpeciesCount {
+                    expected: nb_species,
+                    found: reactants.len(),
+                })
+            }
+            Rate::LMA(_, _) | Rate::LMAParam(_, _) => Ok(()),
+            Rate::LMASparse(_, sparse) => {
+                match sparse.iter().find(|&&(i, _)| i as usize >= nb_species) {
+                    Some(&(index, _)) => Err(Error::UnknownSpecies {
+                        index: index as usize,
+                        nb_species,
+                    }),
+                    None => Ok(()),
+                }
+            }
+            Rate::Expr(expr) => expr.check(nb_species),
+            Rate::Compiled(program) => program.check(nb_species),
+        }
+    }
+    /// Returns the rate with its parameters replaced by their values in
+    /// `params`.
+    fn resolve(&self, params: &BTreeMap<String, f64>) -> Result<Rate, Error> {
+        match self {
+            Rate::LMAParam(name, reactants) => {
+                Ok(Rate::LMA(parameter(params, name)?, reactants.clone()))
+            }
+            Rate::Expr(expr) => expr.resolve(params).map(Rate::Expr),
+            Rate::LMA(_, _) | Rate::LMASparse(_, _) | Rate::Compiled(_) => Ok(self.clone()),
+        }
+    }
+    /// Whether the rate refers to parameters.
+    fn has_parameters(&self) -> bool {
+        match self {
+            Rate::LMAParam(_, _) => true,
+            Rate::Expr(expr) => expr.has_parameters(),
+            Rate::LMA(_, _) | Rate::LMASparse(_, _) | Rate::Compiled(_) => false,
         }
     }
-    fn rate(&self, species: &[isize]) -> f64 {
+    pub(crate) fn rate(&self, species: &[isize]) -> f64 {
         match self {
             Rate::LMA(rate, ref reactants) => species
                 .iter()
@@ -77,6 +805,10 @@ impl Rate {
                 rate
             }
             Rate::Expr(expr) => expr.eval(species),
+            // the species are checked when the rate is added
+            Rate::Compiled(program) => unsafe { program.eval_unchecked(species) },
+            // the parameters are replaced when the rate is added
+            Rate::LMAParam(_, _) => f64::NAN,
         }
     }
 }
@@ -109,7 +841,53 @@ impl Jump {
             Jump::Sparse(_) => self,
         }
     }
-    fn affect(&self, species: &mut [isize]) {
+    /// Converts a sparse jump into a dense one, for a problem with
+    /// `nb_species` species.
+    pub fn dense(self, nb_species: usize) -> Self {
+        match self {
+            Jump::Sparse(sparse) => {
+                let mut differences = vec![0; nb_species];
+                for (index, difference) in sparse {
+                    differences[index] += difference;
+                }
+                Jump::Flat(differences)
+            }
+            Jump::Flat(_) => self,
+        }
+    }
+    /// Chooses the dense or the sparse representation of the jump for a
+    /// problem of `nb_species` species: the dense one when it changes at
+    /// least a quarter of the species, since adding a whole vector
+    /// vectorizes well, and the sparse one otherwise.
+    pub(crate) fn auto(self, nb_species: usize) -> Self {
+        let nonzeros = match &self {
+            Jump::Flat(differences) => differences.iter().filter(|&&d| d != 0).count(),
+            Jump::Sparse(sparse) => sparse.iter().filter(|&&(_, d)| d != 0).count(),
+        };
+        if 4 * nonzeros >= nb_species {
+            self.dense(nb_species)
+        } else {
+            self.sparse()
+        }
+    }
+    /// Checks that the jump only changes the `nb_species` species of a
+    /// problem.
+    pub(crate) fn check(&self, nb_species: usize) -> Result<(), Error> {
+        match self {
+            Jump::Flat(differences) if differences.len() != nb_species => {
+                Err(Error::SpeciesCount {
+                    expected: nb_species,
+                    found: differences.len(),
+                })
+            }
+            Jump::Flat(_) => Ok(()),
+            Jump::Sparse(sparse) => match sparse.iter().find(|&&(i, _)| i >= nb_species) {
+                Some(&(index, _)) => Err(Error::UnknownSpecies { index, nb_species }),
+                None => Ok(()),
+            },
+        }
+    }
+    pub(crate) fn affect(&self, species: &mut [isize]) {
         match self {
             Jump::Flat(differences) => species
                 .iter_mut()
@@ -122,37 +900,486 @@ impl Jump {
     }
 }
 
+/// Index of a species in a problem.
+///
+/// It is implemented for `usize` and for the enumerations defined with
+/// [`index_enum`](crate::index_enum), so that species can be designated
+/// by name.
+pub trait SpeciesIndex: Copy {
+    /// Returns the position of the species in the state vector.
+    fn index(self) -> usize;
+}
+
+impl SpeciesIndex for usize {
+    fn index(self) -> usize {
+        self
+    }
+}
+
+/// Enumeration of all the species of a problem, usually defined with
+/// [`index_enum`](crate::index_enum).
+pub trait SpeciesEnum: SpeciesIndex {
+    /// Number of species.
+    const COUNT: usize;
+}
+
+/// Returns a dense vector of values indexed by species, from
+/// `(species, value)` pairs, the values of the other species being
+/// zero.
+///
+/// # Panics
+///
+/// Panics if a species is given twice.
+pub fn dense<S, T, V>(entries: V) -> Vec<T>
+where
+    S: SpeciesEnum,
+    T: Copy + Default,
+    V: IntoIterator<Item = (S, T)>,
+{
+    let mut vector = vec![T::default(); S::COUNT];
+    let mut seen = vec![false; S::COUNT];
+    for (species, value) in entries {
+        let i = species.index();
+        assert!(!seen[i], "species of index {i} given twice");
+        seen[i] = true;
+        vector[i] = value;
+    }
+    vector
+}
+
+/// Returns a dense vector of values indexed by species, from
+/// `(name, value)` pairs, `names` being the ordered names of all
+/// species.
+///
+/// # Panics
+///
+/// Panics if a name is unknown or given twice.
+pub fn dense_by_name<N, T, V>(names: &[N], entries: V) -> Vec<T>
+where
+    N: AsRef<str>,
+    T: Copy + Default,
+    V: IntoIterator<Item = (&'static str, T)>,
+{
+    let mut vector = vec![T::default(); names.len()];
+    let mut seen = vec![false; names.len()];
+    for (name, value) in entries {
+        let i = names
+            .iter()
+            .position(|n| n.as_ref() == name)
+            .unwrap_or_else(|| panic!("unknown species `{name}`"));
+        assert!(!seen[i], "species `{name}` given twice");
+        seen[i] = true;
+        vector[i] = value;
+    }
+    vector
+}
+
+/// Returns a sparse vector of `(index, value)` pairs from `(species,
+/// value)` pairs.
+pub fn sparse<S, T, V>(entries: V) -> Vec<(usize, T)>
+where
+    S: SpeciesIndex,
+    V: IntoIterator<Item = (S, T)>,
+{
+    entries
+        .into_iter()
+        .map(|(species, value)| (species.index(), value))
+        .collect()
+}
+
+/// Description of how a result was simulated.
+///
+/// Two metadata are equal when they describe the same simulation,
+/// whatever their wall times.
+#[derive(Clone, Debug, Default)]
+pub struct Metadata {
+    /// Version of rebop.
+    pub version: String,
+    /// Seed of the random number generator, if one was given.
+    pub seed: Option<u64>,
+    /// Simulation methods, in the order in which they were used.
+    pub methods: Vec<Method>,
+    /// Values of the parameters.
+    pub parameters: BTreeMap<String, f64>,
+    /// Fingerprint of the model, see
+    /// [`Model::fingerprint`](crate::model::Model::fingerprint).
+    pub model_fingerprint: Option<String>,
+    /// Canonical hash of the reaction network of the model, see
+    /// [`Model::model_hash`](crate::model::Model::model_hash).
+    pub model_hash: Option<String>,
+    /// Duration of the simulation.
+    pub wall_time: Duration,
+}
+
+impl Metadata {
+    /// Creates metadata for the current version of rebop.
+    pub fn new() -> Self {
+        Metadata {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+impl PartialEq for Metadata {
+    fn eq(&self, other: &Self) -> bool {
+        self.version == other.version
+            && self.seed == other.seed
+            && self.methods == other.methods
+            && self.parameters == other.parameters
+            && self.model_fingerprint == other.model_fingerprint
+            && self.model_hash == other.model_hash
+    }
+}
+
+/// Amounts of species recorded at successive time points.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Trajectory {
+    /// Time points.
+    pub times: Vec<f64>,
+    /// `species[s][i]` is the amount of species `s` at time `times[i]`.
+    pub species: Vec<Vec<isize>>,
+    /// `firings[r][i]` is the number of firings of reaction `r` between
+    /// `times[i - 1]` and `times[i]`, if they were counted, or empty.
+    pub firings: Vec<Vec<u64>>,
+    /// `observables[o][i]` is the value of the observable `o` of the
+    /// problem at time `times[i]`, in the order of
+    /// [`Gillespie::observables`].
+    pub observables: Vec<Vec<f64>>,
+    /// How the trajectory was simulated, if known.
+    pub metadata: Option<Metadata>,
+}
+
+impl Trajectory {
+    /// Creates an empty trajectory for `nb_species` species.
+    pub fn new(nb_species: usize) -> Self {
+        Trajectory {
+            times: Vec::new(),
+            species: vec![Vec::new(); nb_species],
+            firings: Vec::new(),
+            observables: Vec::new(),
+            metadata: None,
+        }
+    }
+    /// Records the current time and state of a problem, with its
+    /// observables, and the firings counted since the last call to
+    /// [`reset_firings`](Gillespie::reset_firings), if they are counted.
+    pub fn push<R>(&mut self, problem: &Gillespie<R>) {
+        self.times.push(problem.t);
+        for (values, &amount) in self.species.iter_mut().zip(&problem.species) {
+            values.push(amount);
+        }
+        if let Some(firings) = &problem.records.firings {
+            self.firings.resize(firings.len(), Vec::new());
+            for (values, &n) in self.firings.iter_mut().zip(firings) {
+                values.push(n);
+            }
+        }
+        self.observables
+            .resize(problem.observables.len(), Vec::new());
+        for (values, (_, weights)) in self.observables.iter_mut().zip(&problem.observables) {
+            values.push(observe(weights, &problem.species));
+        }
+    }
+}
+
+/// Distribution of the waiting time of a reaction, for non-Markovian
+/// simulations.
+///
+/// The waiting time is expressed in units of integrated propensity: a
+/// reaction fires when the integral of its propensity since its last
+/// firing reaches a value drawn from this distribution.  With a
+/// constant propensity `a`, the waiting time is thus a draw divided by
+/// `a`.  The exponential distribution of mean 1 gives back the usual
+/// Markovian dynamics, and the gamma distribution of shape `n` and
+/// scale `1/n` lumps `n` successive Markovian steps into one reaction.
+///
+/// It is implemented for all the distributions of [`rand_distr`], such
+/// as `Gamma` or `LogNormal`, and for [`Deterministic`] and
+/// [`Laplace`].
+pub trait WaitingTime: Debug + Send + Sync {
+    /// Draws a waiting time.
+    fn draw(&self, rng: &mut dyn RngCore) -> f64;
+}
+
+impl<D: Distribution<f64> + Debug + Send + Sync> WaitingTime for D {
+    fn draw(&self, rng: &mut dyn RngCore) -> f64 {
+        self.sample(rng)
+    }
+}
+
+/// Waiting time that is always the same, to model a fixed delay.
+///
+/// With a constant propensity `a`, the reaction fires every `d / a`
+/// time units.
+#[derive(Clone, Copy, Debug)]
+pub struct Deterministic(pub f64);
+
+impl Distribution<f64> for Deterministic {
+    fn sample<R: Rng + ?Sized>(&self, _rng: &mut R) -> f64 {
+        self.0
+    }
+}
+
+/// Waiting time distributed as a mixture of exponential distributions,
+/// whose rates are distributed according to `D`.
+///
+/// This is the Laplace Gillespie algorithm: a rate is drawn for each
+/// waiting time, and the waiting time is then exponential with this
+/// rate.  It generates all the waiting-time distributions whose
+/// survival function is completely monotone, such as the gamma
+/// distributions of shape at most 1 or the power laws.
+#[derive(Clone, Copy, Debug)]
+pub struct Laplace<D>(pub D);
+
+impl<D: Distribution<f64>> Distribution<f64> for Laplace<D> {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> f64 {
+        let rate = self.0.sample(rng);
+        rng.sample::<f64, _>(Exp1) / rate
+    }
+}
+
 /// Main structure, represents the problem and contains simulation methods.
+///
+/// It is generic over the pseudo-random number generator, which is a
+/// [`SmallRng`] by default.  Another generator can be given with
+/// [`Gillespie::with_rng`], for example to check that the results do
+/// not depend on it.
 #[derive(Clone, Debug)]
-pub struct Gillespie {
+pub struct Gillespie<R = SmallRng> {
     species: Vec<isize>,
     t: f64,
     reactions: Vec<(Rate, Jump)>,
-    rng: SmallRng,
+    rng: R,
+    waiting_times: Vec<Option<Arc<dyn WaitingTime>>>,
+    /// Integrated propensity since the last firing and firing threshold
+    /// of each reaction, for non-Markovian simulations.
+    clocks: Vec<(f64, f64)>,
+    extended_precision: bool,
+    method: Method,
+    /// Order in which the reactions are scanned by the sorting direct
+    /// method.
+    order: Vec<usize>,
+    /// Rates of the disabled reactions, whose rates are zero meanwhile.
+    disabled: Vec<Option<Rate>>,
+    /// Fast reversible pairs of reactions, for the slow-scale method.
+    fast_pairs: Vec<(usize, usize)>,
+    /// Delay between the firing of each reaction and its effect.
+    delays: Vec<f64>,
+    /// Delayed reactions that fired and have not taken effect yet.
+    pending: BinaryHeap<Scheduled>,
+    /// Custom simulation algorithm, used instead of the method.
+    algorithm: Option<Arc<dyn SsaAlgorithm>>,
+    /// Factors of the rates of the reactions, empty if none is scaled.
+    scales: Vec<f64>,
+    /// Values of the named parameters.
+    params: BTreeMap<String, f64>,
+    /// Rates of the reactions that refer to parameters, as given, empty
+    /// if none does.
+    symbolic: Vec<Option<Rate>>,
+    /// What is recorded of the reactions that fire.
+    records: Records,
+    /// Named linear combinations of the species, with one weight per
+    /// species.
+    observables: Vec<(String, Vec<f64>)>,
+    /// Limits of each call to `advance_until`.
+    limits: Limits,
+    /// Limit that stopped the last call to `advance_until`, if any.
+    limit_reached: Option<Limit>,
+    /// Rates of the direct method laid out over lanes of reactions, when
+    /// they all follow the law of mass action, at the first simulation
+    /// after they change.
+    lma_rates: OnceLock<Option<Arc<LmaRates>>>,
+    /// Buffers of the simulations.
+    workspace: Workspace,
+    /// Whether the direct method uses the rates compiled to native code.
+    #[cfg(feature = "jit")]
+    jit: bool,
+    /// Rates compiled to native code, at the first simulation, and again
+    /// after they change shape.
+    #[cfg(feature = "jit")]
+    native: Option<Arc<NativeRates>>,
+    /// Values read by the native code, empty after the rates change.
+    #[cfg(feature = "jit")]
+    native_values: Vec<f64>,
+}
+
+/// Simulation algorithm used by [`Gillespie::advance_until`].
+///
+/// All methods but R-leaping are exact and sample the same
+/// distribution of trajectories, but their performance depends on the
+/// problem.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Method {
+    /// Direct method of Gillespie: the time of the next reaction is
+    /// drawn from the total rate, and the reaction is chosen with a
+    /// second random number among the cumulative rates.
+    #[default]
+    Direct,
+    /// First reaction method of Gillespie: a firing time is drawn for
+    /// each reaction and the earliest one happens.
+    FirstReaction,
+    /// Next reaction method, in the modified version of Anderson
+    /// (2007): each reaction has its own clock, kept in a priority
+    /// queue, and only the rates that depend on the species modified by
+    /// a reaction are updated.  Efficient for large sparse networks.
+    NextReaction,
+    /// Sorting direct method of McCollum et al. (2006): the direct
+    /// method, where a reaction moves one place towards the front each
+    /// time it happens, so that the frequent reactions are found first.
+    /// The order is kept from one call of
+    /// [`advance_until`](Gillespie::advance_until) to the next, which
+    /// makes the performance almost independent of the order in which
+    /// the reactions were added.
+    SortingDirect,
+    /// R-leaping of Auger et al. (2006), an approximate method that
+    /// fires the given number of reactions at each step: the duration
+    /// of the step follows a gamma distribution and the reactions that
+    /// fire a multinomial distribution, for the rates at the beginning
+    /// of the step.  When a step would make an amount negative, it is
+    /// retried with half as many reactions.  With `1` reaction per
+    /// step, this is the direct method.
+    RLeaping(u32),
+    /// Slow-scale SSA of Cao, Gillespie and Petzold (2005), an
+    /// approximate method for problems where some reversible pairs of
+    /// reactions are much faster than the others, like the binding of an
+    /// enzyme to its substrate.  The fast pairs are assumed to be at
+    /// equilibrium: only the other reactions are simulated, with their
+    /// rates averaged over the stationary distribution of the fast pairs.
+    /// See [`Gillespie::set_fast_pairs`].
+    SlowScale,
+}
+
+impl Method {
+    /// Whether the method samples the exact distribution of the
+    /// trajectories.
+    pub fn is_exact(&self) -> bool {
+        !matches!(self, Method::RLeaping(_) | Method::SlowScale)
+    }
+}
+
+impl fmt::Display for Method {
+    /// Writes the name of the method, as parsed by [`FromStr`].
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Method::Direct => write!(f, "direct"),
+            Method::FirstReaction => write!(f, "first_reaction"),
+            Method::NextReaction => write!(f, "next_reaction"),
+            Method::SortingDirect => write!(f, "sorting_direct"),
+            Method::RLeaping(nb_firings) => write!(f, "r_leaping:{nb_firings}"),
+            Method::SlowScale => write!(f, "slow_scale"),
+        }
+    }
+}
+
+impl FromStr for Method {
+    type Err = String;
+    /// Parses the name of a method in snake case, like `"next_reaction"`,
+    /// or `"r_leaping:100"` for R-leaping with 100 reactions per step.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "direct" => Ok(Method::Direct),
+            "first_reaction" => Ok(Method::FirstReaction),
+            "next_reaction" => Ok(Method::NextReaction),
+            "sorting_direct" => Ok(Method::SortingDirect),
+            "slow_scale" => Ok(Method::SlowScale),
+            _ => match s.strip_prefix("r_leaping:").map(str::parse) {
+                Some(Ok(nb_firings)) if nb_firings > 0 => Ok(Method::RLeaping(nb_firings)),
+                _ => Err(format!(
+                    "unknown method `{s}`, expected `direct`, `first_reaction`, \
+                     `next_reaction`, `sorting_direct`, `r_leaping:<number of reactions>` \
+                     or `slow_scale`"
+                )),
+            },
+        }
+    }
 }
 
 impl Gillespie {
     /// Creates a new problem instance, with `N` different species of
     /// specified initial conditions.
     pub fn new<V: AsRef<[isize]>>(species: V) -> Self {
-        Gillespie {
-            species: species.as_ref().to_vec(),
-            t: 0.,
-            reactions: Vec::new(),
-            rng: SmallRng::from_entropy(),
-        }
+        Gillespie::with_rng(species, SmallRng::from_entropy())
     }
     pub fn new_with_seed<V: AsRef<[isize]>>(species: V, seed: u64) -> Self {
+        Gillespie::with_rng(species, SmallRng::seed_from_u64(seed))
+    }
+}
+
+impl<R: Rng + SeedableRng> Gillespie<R> {
+    /// Creates a new problem instance, with `N` different species of
+    /// specified initial conditions, using the random number generator
+    /// `rng`.
+    ///
+    /// ```
+    /// use rebop::gillespie::{Gillespie, Rate};
+    /// use rand::rngs::StdRng;
+    /// use rand::SeedableRng;
+    /// let mut p = Gillespie::with_rng([0], StdRng::seed_from_u64(0));
+    /// p.add_reaction(Rate::lma(10., [0]), [1]);
+    /// p.advance_until(1.);
+    /// assert!(p.get_species(0) > 0);
+    /// ```
+    pub fn with_rng<V: AsRef<[isize]>>(species: V, rng: R) -> Self {
         Gillespie {
             species: species.as_ref().to_vec(),
             t: 0.,
             reactions: Vec::new(),
-            rng: SmallRng::seed_from_u64(seed),
+            rng,
+            waiting_times: Vec::new(),
+            clocks: Vec::new(),
+            extended_precision: false,
+            method: Method::Direct,
+            order: Vec::new(),
+            disabled: Vec::new(),
+            fast_pairs: Vec::new(),
+            delays: Vec::new(),
+            pending: BinaryHeap::new(),
+            algorithm: None,
+            scales: Vec::new(),
+            params: BTreeMap::new(),
+            symbolic: Vec::new(),
+            records: Records::default(),
+            observables: Vec::new(),
+            limits: Limits::default(),
+            limit_reached: None,
+            lma_rates: OnceLock::new(),
+            workspace: Workspace::default(),
+            #[cfg(feature = "jit")]
+            jit: false,
+            #[cfg(feature = "jit")]
+            native: None,
+            #[cfg(feature = "jit")]
+            native_values: Vec::new(),
         }
     }
     /// Seeds the random number generator.
     pub fn seed(&mut self, seed: u64) {
-        self.rng = SmallRng::seed_from_u64(seed);
+        self.rng = R::seed_from_u64(seed);
+    }
+    /// Returns the random number generator, whose state determines the
+    /// rest of the simulation.
+    pub fn rng(&self) -> &R {
+        &self.rng
+    }
+    /// Replaces the random number generator, for example by the one of
+    /// another problem to continue its simulation.
+    ///
+    /// ```
+    /// use rebop::gillespie::{Gillespie, Rate};
+    /// let mut p = Gillespie::new_with_seed([0], 0);
+    /// p.add_reaction(Rate::lma(10., [0]), [1]);
+    /// p.advance_until(1.);
+    /// // a copy of the model, with a different rate, continues the run
+    /// let mut q = Gillespie::new(p.species());
+    /// q.add_reaction(Rate::lma(20., [0]), [1]);
+    /// q.set_time(p.get_time());
+    /// q.set_rng(p.rng().clone());
+    /// q.advance_until(2.);
+    /// assert!(q.get_species(0) >= p.get_species(0));
+    /// ```
+    pub fn set_rng(&mut self, rng: R) {
+        self.rng = rng;
     }
     /// Returns the number of species in the problem.
     ///
@@ -174,10 +1401,36 @@ impl Gillespie {
     pub fn nb_reactions(&self) -> usize {
         self.reactions.len()
     }
+    /// Returns the stoichiometry matrix of the problem: the change of
+    /// each species, by row, when each reaction fires, by column.
+    ///
+    /// ```
+    /// use rebop::gillespie::{Gillespie, Rate};
+    /// let mut sir: Gillespie = Gillespie::new([999, 1, 0]);
+    /// sir.add_reaction(Rate::lma(1e-4, [1, 1, 0]), [-1, 1, 0]);
+    /// sir.add_reaction(Rate::lma(0.01, [0, 1, 0]), [0, -1, 1]);
+    /// assert_eq!(sir.stoichiometry_matrix(), [[-1, 0], [1, -1], [0, 1]]);
+    /// ```
+    pub fn stoichiometry_matrix(&self) -> Vec<Vec<isize>> {
+        let mut matrix = vec![vec![0; self.reactions.len()]; self.species.len()];
+        for (ireaction, (_, jump)) in self.reactions.iter().enumerate() {
+            let Jump::Flat(differences) = jump.clone().dense(self.species.len()) else {
+                unreachable!("dense jumps are flat")
+            };
+            for (row, difference) in matrix.iter_mut().zip(differences) {
+                row[ireaction] = difference;
+            }
+        }
+        matrix
+    }
     /// Adds a reaction to the problem.
     ///
     /// `rate` is the reaction rate and `reaction` is an array
-    /// describing the state change as a result of the reaction.
+    /// describing the state change as a result of the reaction.  Each
+    /// reaction stores its law of mass action and its jump in a dense or
+    /// a sparse form, whichever suits the number of species it involves,
+    /// so that large networks of small reactions do not scan every
+    /// species.
     /// ```
     /// use rebop::gillespie::{Gillespie, Rate};
     /// let mut sir = Gillespie::new([9999, 1, 0]);
@@ -187,132 +1440,2846 @@ impl Gillespie {
     /// // I -> R with rate 0.01
     /// sir.add_reaction(Rate::lma(0.01, [0, 1, 0]), [0, -1, 1]);
     /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the rate or the jump do not correspond to the species
+    /// of the problem, see [`try_add_reaction`](Gillespie::try_add_reaction).
     pub fn add_reaction<V: AsRef<[isize]>>(&mut self, rate: Rate, differences: V) {
-        // This assert ensures that the jump does not go out of bounds of the species
-        assert_eq!(differences.as_ref().len(), self.species.len());
-        let jump = Jump::new(differences);
-        self.reactions.push((rate.sparse(), jump));
+        if let Err(err) = self.try_add_reaction(rate, differences) {
+            panic!("{err}");
+        }
     }
-    /// Returns the current time in the model.
-    pub fn get_time(&self) -> f64 {
-        self.t
+    /// Adds a reaction to the problem, like
+    /// [`add_reaction`](Gillespie::add_reaction), or returns an error if
+    /// `differences` does not have one element per species or if `rate`
+    /// refers to species that do not exist.
+    ///
+    /// ```
+    /// use rebop::gillespie::{Expr, Gillespie, Rate};
+    /// use rebop::Error;
+    /// let mut p = Gillespie::new([0, 0]);
+    /// assert_eq!(
+    ///     p.try_add_reaction(Rate::lma(1., [0, 0]), [1]),
+    ///     Err(Error::SpeciesCount { expected: 2, found: 1 }),
+    /// );
+    /// let rate = Rate::Expr(Expr::Concentration(2));
+    /// assert!(p.try_add_reaction(rate, [1, 0]).is_err());
+    /// assert_eq!(p.nb_reactions(), 0);
+    /// ```
+    pub fn try_add_reaction<V: AsRef<[isize]>>(
+        &mut self,
+        rate: Rate,
+        differences: V,
+    ) -> Result<(), Error> {
+        let jump = Jump::new(differences);
+        // the simulations rely on these checks to index the species
+        // without bounds checks
+        jump.check(self.species.len())?;
+        rate.check(self.species.len())?;
+        rate.resolve(&self.params)?;
+        // SAFETY: the rate and the jump were checked
+        unsafe { self.add_reaction_unchecked(rate, jump) };
+        Ok(())
     }
-    /// Sets the current time in the model.
-    pub fn set_time(&mut self, t: f64) {
-        self.t = t;
+    /// Adds a reaction with the jump `jump` to the problem, without
+    /// checking them, for large generated networks whose consistency is
+    /// guaranteed by construction.
+    ///
+    /// # Safety
+    ///
+    /// The rate and the jump must only refer to species of the problem,
+    /// and dense rates and jumps must have one element per species, as
+    /// checked by [`try_add_reaction`](Gillespie::try_add_reaction):
+    /// otherwise the simulations read and write out of bounds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the rate refers to a parameter that is not defined.
+    pub unsafe fn add_reaction_unchecked(&mut self, rate: Rate, jump: Jump) {
+        let resolved = rate
+            .resolve(&self.params)
+            .unwrap_or_else(|err| panic!("{err}"));
+        if rate.has_parameters() {
+            self.symbolic.resize(self.reactions.len(), None);
+            self.symbolic.push(Some(rate));
+        }
+        let nb_species = self.species.len();
+        self.reactions
+            .push((resolved.auto(nb_species).compile(), jump.auto(nb_species)));
+        if let Some(firings) = &mut self.records.firings {
+            firings.push(0);
+        }
+        self.rates_changed();
+        if !self.scales.is_empty() {
+            self.scales.push(1.);
+        }
     }
-    /// Returns the current amount of a species.
+    /// Adds the reactions `species[0] -> species[1] -> ...` of first
+    /// order, with the rate constant `rate`, like the steps of a linear
+    /// pathway or the stages of a delay, and returns the indices of the
+    /// new reactions.
     ///
     /// ```
     /// use rebop::gillespie::Gillespie;
-    /// let p: Gillespie = Gillespie::new([0, 1, 10, 100]);
-    /// assert_eq!(p.get_species(2), 10);
+    /// let mut p: Gillespie = Gillespie::new([10, 0, 0, 0]);
+    /// assert_eq!(p.add_chain(&[0, 1, 2, 3], 2.), 0..3);
+    /// assert_eq!(p.add_ring(&[1, 2], 1.), 3..5);
+    /// p.advance_until(100.);
+    /// assert_eq!(p.get_species(3), 10);
     /// ```
-    pub fn get_species(&self, s: usize) -> isize {
-        self.species[s]
-    }
-    /// Sets the amount of species in the model.
-    pub fn set_species<V: AsRef<[isize]>>(&mut self, species: V) {
-        assert_eq!(species.as_ref().len(), self.species.len());
-        self.species = species.as_ref().to_vec();
+    ///
+    /// # Panics
+    ///
+    /// Panics if a species does not exist.
+    pub fn add_chain(&mut self, species: &[usize], rate: f64) -> Range<usize> {
+        let start = self.reactions.len();
+        for pair in species.windows(2) {
+            self.add_family_reaction(rate, &[pair[0]], &[pair[1]]);
+        }
+        start..self.reactions.len()
     }
-    /// Simulates the problem until the next discrete reaction.
-    pub fn advance_one_reaction(&mut self) {
-        let mut rates = vec![f64::NAN; self.nb_reactions()];
-        self._advance_one_reaction(&mut rates);
+    /// Adds the reactions of [`add_chain`](Gillespie::add_chain), and
+    /// the reaction from the last species back to the first one, like
+    /// the states of a cycle, and returns the indices of the new
+    /// reactions.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a species does not exist.
+    pub fn add_ring(&mut self, species: &[usize], rate: f64) -> Range<usize> {
+        let start = self.reactions.len();
+        self.add_chain(species, rate);
+        if let (Some(&first), Some(&last)) = (species.first(), species.last()) {
+            self.add_family_reaction(rate, &[last], &[first]);
+        }
+        start..self.reactions.len()
     }
-
-    #[inline]
-    pub fn _advance_one_reaction(&mut self, rates: &mut [f64]) {
-        // let total_rate = make_rates(&self.reactions, &self.species, rates);
-        let total_rate = make_cumrates(&self.reactions, &self.species, rates);
-
+    /// Adds a reaction between each pair of `species`, and returns the
+    /// indices of the new reactions.
+    ///
+    /// For all the positions `i <= j` in `species`, `reaction(i, j)`
+    /// returns either `None`, or the rate constant and the products, as
+    /// positions in `species`, of the reaction `species[i] + species[j]
+    /// -> products` of law of mass action.  This builds aggregation
+    /// networks, where clusters of all sizes merge:
+    ///
+    /// ```
+    /// use rebop::gillespie::Gillespie;
+    /// // clusters of 1 to 10 particles, from 100 single particles
+    /// let mut sizes = vec![0; 10];
+    /// sizes[0] = 100;
+    /// let mut p: Gillespie = Gillespie::new(sizes);
+    /// let clusters: Vec<usize> = (0..10).collect();
+    /// // the cluster at position i has i + 1 particles
+    /// let aggregations =
+    ///     p.add_all_pairs(&clusters, |i, j| (i + j + 1 < 10).then_some((1e-2, [i + j + 1])));
+    /// assert_eq!(aggregations.len(), 25);
+    /// p.advance_until(10.);
+    /// let particles: isize = (0..10).map(|i| (i as isize + 1) * p.get_species(i)).sum();
+    /// assert_eq!(particles, 100);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if a species does not exist, or if a position is not in
+    /// `species`.
+    pub fn add_all_pairs<F, V>(&mut self, species: &[usize], mut reaction: F) -> Range<usize>
+    where
+        F: FnMut(usize, usize) -> Option<(f64, V)>,
+        V: AsRef<[usize]>,
+    {
+        let start = self.reactions.len();
+        for i in 0..species.len() {
+            for j in i..species.len() {
+                if let Some((rate, products)) = reaction(i, j) {
+                    let products: Vec<usize> =
+                        products.as_ref().iter().map(|&k| species[k]).collect();
+                    self.add_family_reaction(rate, &[species[i], species[j]], &products);
+                }
+            }
+        }
+        start..self.reactions.len()
+    }
+    /// Adds the reaction of law of mass action `reactants -> products` of
+    /// rate constant `rate`, where the species appear as many times as
+    /// their stoichiometries, in the sparse representation.
+    fn add_family_reaction(&mut self, rate: f64, reactants: &[usize], products: &[usize]) {
+        let nb_species = self.species.len();
+        let mut stoichiometries = BTreeMap::new();
+        let mut differences = BTreeMap::new();
+        for &index in reactants.iter().chain(products) {
+            if index >= nb_species {
+                panic!("{}", Error::UnknownSpecies { index, nb_species });
+            }
+        }
+        for &index in reactants {
+            *stoichiometries.entry(index as u32).or_insert(0) += 1;
+            *differences.entry(index).or_insert(0) -= 1;
+        }
+        for &index in products {
+            *differences.entry(index).or_insert(0) += 1;
+        }
+        let rate = Rate::LMASparse(rate, stoichiometries.into_iter().collect());
+        let jump = Jump::Sparse(differences.into_iter().filter(|&(_, d)| d != 0).collect());
+        // SAFETY: all the species exist
+        unsafe { self.add_reaction_unchecked(rate, jump) };
+    }
+    /// Replaces the rate of the reaction of index `ireaction`.  If the
+    /// reaction is disabled, the new rate applies once it is enabled.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no such reaction or if the rate refers to
+    /// species that do not exist, see [`try_set_rate`](Gillespie::try_set_rate).
+    pub fn set_rate(&mut self, ireaction: usize, rate: Rate) {
+        if let Err(err) = self.try_set_rate(ireaction, rate) {
+            panic!("{err}");
+        }
+    }
+    /// Replaces the rate of the reaction of index `ireaction`, like
+    /// [`set_rate`](Gillespie::set_rate), or returns an error if there is
+    /// no such reaction or if the rate refers to species or parameters
+    /// that do not exist.
+    pub fn try_set_rate(&mut self, ireaction: usize, rate: Rate) -> Result<(), Error> {
+        if ireaction >= self.reactions.len() {
+            return Err(Error::UnknownReaction {
+                index: ireaction,
+                nb_reactions: self.reactions.len(),
+            });
+        }
+        rate.check(self.species.len())?;
+        let resolved = rate
+            .resolve(&self.params)?
+            .auto(self.species.len())
+            .compile();
+        if rate.has_parameters() {
+            self.symbolic.resize(self.reactions.len(), None);
+            self.symbolic[ireaction] = Some(rate);
+        } else if let Some(symbolic) = self.symbolic.get_mut(ireaction) {
+            *symbolic = None;
+        }
+        self.store_rate(ireaction, resolved);
+        Ok(())
+    }
+    /// Stores the rate of the reaction of index `ireaction`, where the
+    /// simulations find it if the reaction is enabled.
+    fn store_rate(&mut self, ireaction: usize, rate: Rate) {
+        match self.disabled.get_mut(ireaction) {
+            Some(Some(disabled)) => *disabled = rate,
+            _ => self.reactions[ireaction].0 = rate,
+        }
+        self.rates_changed();
+    }
+    /// Forgets what was derived from the rates of the reactions, after
+    /// they change.
+    fn rates_changed(&mut self) {
+        self.lma_rates.take();
+        #[cfg(feature = "jit")]
+        self.native_values.clear();
+        if let Some(propensities) = &mut self.records.propensities {
+            propensities.update(self.t, &self.reactions, &self.scales, &self.species);
+        }
+    }
+    /// Sets the value of the parameter `name`, defining it if needed,
+    /// and updates the rates of the reactions that refer to it, with
+    /// [`Rate::lma_param`] or [`Expr::Parameter`].
+    ///
+    /// The parameters can be changed between two calls to
+    /// [`advance_until`](Gillespie::advance_until), without rebuilding
+    /// the problem.
+    ///
+    /// ```
+    /// use rebop::gillespie::{Expr, Gillespie, Rate};
+    /// let mut p = Gillespie::new_with_seed([0, 0], 0);
+    /// p.set_param("k", 10.);
+    /// p.add_reaction(Rate::lma_param("k", [0, 0]), [1, 0]);
+    /// let saturating = Expr::Div(
+    ///     Box::new(Expr::Parameter("k".to_string())),
+    ///     Box::new(Expr::Add(Box::new(Expr::Constant(1.)), Box::new(Expr::Concentration(1)))),
+    /// );
+    /// p.add_reaction(Rate::Expr(saturating), [0, 1]);
+    /// p.advance_until(1.);
+    /// // both reactions stop at t = 1
+    /// p.set_param("k", 0.);
+    /// let before = p.species().to_vec();
+    /// p.advance_until(2.);
+    /// assert_eq!(p.species(), before);
+    /// assert_eq!(p.get_param("k"), Some(0.));
+    /// ```
+    pub fn set_param(&mut self, name: &str, value: f64) {
+        self.params.insert(name.to_string(), value);
+        for ireaction in 0..self.symbolic.len() {
+            if let Some(rate) = &self.symbolic[ireaction] {
+                // the parameters of the rates are defined when they are added
+                let resolved = rate
+                    .resolve(&self.params)
+                    .expect("the parameters are defined")
+                    .auto(self.species.len())
+                    .compile();
+                self.store_rate(ireaction, resolved);
+            }
+        }
+    }
+    /// Returns the value of the parameter `name`, if it is defined.
+    pub fn get_param(&self, name: &str) -> Option<f64> {
+        self.params.get(name).copied()
+    }
+    /// Starts counting the firings of each reaction from zero, or stops
+    /// counting them.
+    ///
+    /// A firing is counted when the reaction changes the species, so
+    /// after its delay if it has one.  The fast reactions of the
+    /// slow-scale method are not simulated and thus not counted.
+    ///
+    /// ```
+    /// use rebop::gillespie::{Gillespie, Rate};
+    /// let mut p = Gillespie::new([0]);
+    /// p.add_reaction(Rate::lma(10., [0]), [1]);
+    /// p.add_reaction(Rate::lma(1., [1]), [-1]);
+    /// p.set_count_firings(true);
+    /// p.advance_until(10.);
+    /// let firings = p.firings().unwrap();
+    /// assert_eq!(firings[0] as isize - firings[1] as isize, p.get_species(0));
+    /// ```
+    pub fn set_count_firings(&mut self, enabled: bool) {
+        self.records.firings = enabled.then(|| vec![0; self.reactions.len()]);
+    }
+    /// Returns the number of firings of each reaction since they are
+    /// counted, or since the last call to
+    /// [`reset_firings`](Gillespie::reset_firings).
+    pub fn firings(&self) -> Option<&[u64]> {
+        self.records.firings.as_deref()
+    }
+    /// Resets the counts of firings to zero, for example at each report
+    /// time to get the fluxes of the reactions over time.
+    pub fn reset_firings(&mut self) {
+        if let Some(firings) = &mut self.records.firings {
+            firings.fill(0);
+        }
+    }
+    /// Enables or disables the tracking of the statistics of the
+    /// reactions, from the current time, returned by
+    /// [`reaction_stats`](Gillespie::reaction_stats).
+    ///
+    /// Once enabled, all propensities are computed after each reaction,
+    /// which slows down the simulations of large networks.
+    pub fn set_reaction_stats(&mut self, enabled: bool) {
+        self.records.propensities = enabled
+            .then(|| Propensities::new(self.t, &self.reactions, &self.scales, &self.species));
+    }
+    /// Returns the number of firings and the propensity averaged over
+    /// time of each reaction, since their statistics are tracked.
+    ///
+    /// They tell which reactions dominate the cost of the simulations,
+    /// and which ones are fast enough to be approximated, for example by
+    /// [`Method::SlowScale`].  The averages are the current propensities
+    /// if no time has passed.
+    ///
+    /// ```
+    /// use rebop::gillespie::{Gillespie, Rate};
+    /// let mut p = Gillespie::new_with_seed([0], 0);
+    /// p.add_reaction(Rate::lma(10., [0]), [1]);
+    /// p.add_reaction(Rate::lma(1., [1]), [-1]);
+    /// p.set_reaction_stats(true);
+    /// p.advance_until(1000.);
+    /// let stats = p.reaction_stats().unwrap();
+    /// assert!((stats[0].mean_propensity - 10.).abs() < 1e-9);
+    /// // at equilibrium, the amount is Poisson of mean 10
+    /// assert!((stats[1].mean_propensity - 10.).abs() < 0.5);
+    /// assert!(stats[0].firings.abs_diff(10_000) < 500);
+    /// ```
+    pub fn reaction_stats(&self) -> Option<Vec<ReactionStats>> {
+        let propensities = self.records.propensities.as_ref()?;
+        let elapsed = self.t - propensities.start;
+        let remaining = self.t - propensities.last;
+        let stats = (propensities.integrals.iter())
+            .zip(&propensities.current)
+            .zip(&propensities.firings)
+            .map(|((integral, rate), &firings)| ReactionStats {
+                firings,
+                mean_propensity: if elapsed > 0. {
+                    (integral + rate * remaining) / elapsed
+                } else {
+                    *rate
+                },
+            })
+            .collect();
+        Some(stats)
+    }
+    /// Enables or disables the recording of the reactions that fire, in
+    /// an [`EventLog`] that starts from the current state.
+    ///
+    /// The log describes a single trajectory: the time and the species
+    /// should not be set while it is recorded, but
+    /// [`take_events`](Gillespie::take_events) can be called before.
+    pub fn set_record_events(&mut self, enabled: bool) {
+        self.records.log = enabled.then(|| EventLog::new(self.t, self.species.clone()));
+    }
+    /// Returns the reactions that fired since they are recorded, or since
+    /// the last call to [`take_events`](Gillespie::take_events).
+    pub fn event_log(&self) -> Option<&EventLog> {
+        self.records.log.as_ref()
+    }
+    /// Returns the reactions that fired, if they are recorded, and starts
+    /// a new log from the current state.
+    pub fn take_events(&mut self) -> Option<EventLog> {
+        let log = EventLog::new(self.t, self.species.clone());
+        self.records
+            .log
+            .as_mut()
+            .map(|current| std::mem::replace(current, log))
+    }
+    /// Sets the problem to the initial state of `log` and fires its
+    /// reactions at their times, without random numbers.
+    ///
+    /// The problem must have the reactions of the problem that recorded
+    /// the log, up to their rates, which are not used.  See
+    /// [`replay_with`](Gillespie::replay_with) to observe the states
+    /// along the way.
+    ///
+    /// ```
+    /// use rebop::gillespie::{Gillespie, Rate};
+    /// let mut p = Gillespie::new_with_seed([0], 0);
+    /// p.add_reaction(Rate::lma(10., [0]), [1]);
+    /// p.add_reaction(Rate::lma(1., [1]), [-1]);
+    /// p.set_record_events(true);
+    /// p.advance_until(10.);
+    /// let log = p.take_events().unwrap();
+    /// let mut q = p.clone();
+    /// q.replay(&log).unwrap();
+    /// assert_eq!(q.species(), p.species());
+    /// assert_eq!(q.get_time(), log.events().last().unwrap().0);
+    /// ```
+    pub fn replay(&mut self, log: &EventLog) -> Result<(), Error> {
+        self.replay_with(log, |_, _, _| ())
+    }
+    /// Like [`replay`](Gillespie::replay), and calls `observe` with the
+    /// time, the index of the reaction and the new amounts of species
+    /// after each reaction.
+    ///
+    /// Returns an error if the log does not have one initial amount per
+    /// species, refers to an unknown reaction, or makes an amount
+    /// negative, the problem being left at the last valid event.
+    pub fn replay_with<F>(&mut self, log: &EventLog, mut observe: F) -> Result<(), Error>
+    where
+        F: FnMut(f64, usize, &[isize]),
+    {
+        if log.initial().len() != self.species.len() {
+            return Err(Error::SpeciesCount {
+                expected: self.species.len(),
+                found: log.initial().len(),
+            });
+        }
+        self.t = log.start();
+        self.species = log.initial().to_vec();
+        for (t, ireaction) in log.events() {
+            let Some((_, jump)) = self.reactions.get(ireaction) else {
+                return Err(Error::UnknownReaction {
+                    index: ireaction,
+                    nb_reactions: self.reactions.len(),
+                });
+            };
+            let mut species = self.species.clone();
+            jump.affect(&mut species);
+            if let Some(s) = species.iter().position(|&amount| amount < 0) {
+                return Err(Error::NegativeAmount {
+                    time: t,
+                    reaction: ireaction,
+                    species: s,
+                    state: self.species.clone(),
+                });
+            }
+            self.t = t;
+            self.species = species;
+            self.records.count(
+                t,
+                ireaction,
+                1,
+                &self.reactions,
+                &self.scales,
+                &self.species,
+            );
+            observe(t, ireaction, &self.species);
+        }
+        Ok(())
+    }
+    /// Enables or disables the reaction of index `ireaction`, for
+    /// example for a knockout experiment.  All reactions are enabled by
+    /// default.
+    ///
+    /// A disabled reaction keeps its place, with a rate of zero, so that
+    /// this is cheap and can happen between two calls of
+    /// [`advance_until`](Gillespie::advance_until).
+    ///
+    /// ```
+    /// use rebop::gillespie::{Gillespie, Rate};
+    /// let mut p = Gillespie::new([0]);
+    /// p.add_reaction(Rate::lma(10., [0]), [1]);
+    /// p.set_reaction_enabled(0, false);
+    /// p.advance_until(1.);
+    /// assert_eq!(p.get_species(0), 0);
+    /// p.set_reaction_enabled(0, true);
+    /// p.advance_until(2.);
+    /// assert!(p.get_species(0) > 0);
+    /// ```
+    pub fn set_reaction_enabled(&mut self, ireaction: usize, enabled: bool) {
+        assert!(ireaction < self.reactions.len());
+        if self.disabled.len() < self.reactions.len() {
+            self.disabled.resize(self.reactions.len(), None);
+        }
+        let rate = &mut self.reactions[ireaction].0;
+        match (enabled, self.disabled[ireaction].take()) {
+            (true, Some(disabled)) => *rate = disabled,
+            (false, None) => {
+                let zero = Rate::LMASparse(0., Vec::new());
+                self.disabled[ireaction] = Some(std::mem::replace(rate, zero));
+            }
+            (_, disabled) => self.disabled[ireaction] = disabled,
+        }
+        self.rates_changed();
+    }
+    /// Whether the reaction of index `ireaction` is enabled.
+    pub fn is_reaction_enabled(&self, ireaction: usize) -> bool {
+        !matches!(self.disabled.get(ireaction), Some(Some(_)))
+    }
+    /// Multiplies the rate of the reaction of index `ireaction` by
+    /// `factor`, without changing the rate itself: another call replaces
+    /// the factor rather than compounding it, and a factor of `1`
+    /// restores the original rate.
+    ///
+    /// Like disabling a reaction, this is cheap and can happen between
+    /// two calls of [`advance_until`](Gillespie::advance_until), for
+    /// example to explore a model interactively.  The factor applies to
+    /// all methods, and to the rates later given to
+    /// [`set_rate`](Gillespie::set_rate).
+    ///
+    /// ```
+    /// use rebop::gillespie::{Gillespie, Rate};
+    /// let mut p = Gillespie::new_with_seed([0], 0);
+    /// p.add_reaction(Rate::lma(10., [0]), [1]);
+    /// p.scale_rate(0, 100.);
+    /// p.advance_until(1.);
+    /// assert!(p.get_species(0) > 800);
+    /// assert_eq!(p.rate_scale(0), 100.);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no such reaction or if the factor is negative
+    /// or not finite, see [`try_scale_rate`](Gillespie::try_scale_rate).
+    pub fn scale_rate(&mut self, ireaction: usize, factor: f64) {
+        if let Err(err) = self.try_scale_rate(ireaction, factor) {
+            panic!("{err}");
+        }
+    }
+    /// Multiplies the rate of the reaction of index `ireaction` by
+    /// `factor`, like [`scale_rate`](Gillespie::scale_rate), or returns an
+    /// error if there is no such reaction or if the factor is negative or
+    /// not finite.
+    ///
+    /// With the next reaction method, the clock of the reaction is drawn
+    /// again if its waiting times are exponential, which does not change
+    /// the dynamics.  The clocks of the other reactions, and those of
+    /// custom waiting times, keep their progress.
+    pub fn try_scale_rate(&mut self, ireaction: usize, factor: f64) -> Result<(), Error> {
+        if ireaction >= self.reactions.len() {
+            return Err(Error::UnknownReaction {
+                index: ireaction,
+                nb_reactions: self.reactions.len(),
+            });
+        }
+        if !(0. ..f64::INFINITY).contains(&factor) {
+            return Err(Error::InvalidFactor {
+                reaction: ireaction,
+                factor,
+            });
+        }
+        self.scales.resize(self.reactions.len(), 1.);
+        self.scales[ireaction] = factor;
+        let markovian = !matches!(self.waiting_times.get(ireaction), Some(Some(_)));
+        if markovian && ireaction < self.clocks.len() {
+            self.clocks[ireaction] = (0., self.rng.sample(Exp1));
+        }
+        self.rates_changed();
+        Ok(())
+    }
+    /// Returns the factor of the rate of the reaction of index
+    /// `ireaction`, `1` unless set by [`scale_rate`](Gillespie::scale_rate).
+    pub fn rate_scale(&self, ireaction: usize) -> f64 {
+        assert!(ireaction < self.reactions.len());
+        scale(&self.scales, ireaction)
+    }
+    /// Delays the effect of the reaction of index `ireaction` by `delay`
+    /// after it fires, as in the delay SSA of Barrio et al. (2006) for
+    /// reactions that do not consume their reactants when they fire.
+    ///
+    /// Such reactions model slow processes like transcription, whose
+    /// products only appear after some time.  The problem is then
+    /// simulated with the direct method, whatever the chosen
+    /// [`Method`], and the delayed effects that are still pending are
+    /// kept from one call of [`advance_until`](Gillespie::advance_until)
+    /// to the next.
+    ///
+    /// ```
+    /// use rebop::gillespie::{Gillespie, Rate};
+    /// let mut p = Gillespie::new([0]);
+    /// p.add_reaction(Rate::lma(10., [0]), [1]);
+    /// p.set_delay(0, 5.);
+    /// p.advance_until(5.);
+    /// assert_eq!(p.get_species(0), 0);
+    /// p.advance_until(6.);
+    /// assert!(p.get_species(0) > 0);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the delay is negative or not finite.
+    pub fn set_delay(&mut self, ireaction: usize, delay: f64) {
+        assert!(ireaction < self.reactions.len());
+        assert!(
+            (0. ..f64::INFINITY).contains(&delay),
+            "invalid delay {delay}"
+        );
+        self.delays.resize(self.reactions.len(), 0.);
+        self.delays[ireaction] = delay;
+    }
+    /// Replaces the exponential waiting time of the reaction of index
+    /// `ireaction` by a custom distribution, which makes the simulation
+    /// non-Markovian.
+    ///
+    /// As soon as one reaction has a custom waiting time, the problem is
+    /// simulated with the modified next reaction method, where each
+    /// reaction has its own clock.  These clocks are kept from one call
+    /// of [`advance_until`](Gillespie::advance_until) to the next.  See
+    /// [`WaitingTime`] for the meaning of the distribution.
+    ///
+    /// ```
+    /// use rebop::gillespie::{Gillespie, Rate};
+    /// use rand_distr::Gamma;
+    /// let mut p = Gillespie::new([0]);
+    /// // 0 -> A at rate 1, with almost regular waiting times
+    /// p.add_reaction(Rate::lma(1., [0]), [1]);
+    /// p.set_waiting_time(0, Gamma::new(1000., 1e-3).unwrap());
+    /// p.advance_until(10.5);
+    /// assert_eq!(p.get_species(0), 10);
+    /// ```
+    pub fn set_waiting_time<W: WaitingTime + 'static>(
+        &mut self,
+        ireaction: usize,
+        waiting_time: W,
+    ) {
+        assert!(ireaction < self.reactions.len());
+        self.waiting_times.resize(self.reactions.len(), None);
+        self.waiting_times[ireaction] = Some(Arc::new(waiting_time));
+        self.clocks.clear();
+    }
+    /// Sets the simulation algorithm, [`Method::Direct`] by default.
+    ///
+    /// Reactions with custom waiting times are always simulated with the
+    /// next reaction method.
+    ///
+    /// ```
+    /// use rebop::gillespie::{Gillespie, Method, Rate};
+    /// let mut sir = Gillespie::new([999, 1, 0]);
+    /// sir.add_reaction(Rate::lma(1e-4, [1, 1, 0]), [-1, 1, 0]);
+    /// sir.add_reaction(Rate::lma(0.01, [0, 1, 0]), [0, -1, 1]);
+    /// sir.set_method(Method::SortingDirect);
+    /// sir.advance_until(250.);
+    /// assert_eq!(sir.species().iter().sum::<isize>(), 1000);
+    /// ```
+    pub fn set_method(&mut self, method: Method) {
+        self.method = method;
+        self.algorithm = None;
+        self.clocks.clear();
+    }
+    /// Returns the simulation algorithm.
+    pub fn method(&self) -> Method {
+        self.method
+    }
+    /// Simulates the problem with a custom algorithm instead of the
+    /// method, until the next call of [`set_method`](Gillespie::set_method).
+    /// See [`SsaAlgorithm`] for what it must do.
+    ///
+    /// # Panics
+    ///
+    /// [`advance_until`](Gillespie::advance_until) panics if the problem
+    /// also has custom waiting times or delays, or rates that the
+    /// algorithm does not support.
+    pub fn set_algorithm<A: SsaAlgorithm + 'static>(&mut self, algorithm: A) {
+        self.set_algorithm_arc(Arc::new(algorithm));
+    }
+    pub(crate) fn set_algorithm_arc(&mut self, algorithm: Arc<dyn SsaAlgorithm>) {
+        self.algorithm = Some(algorithm);
+        self.clocks.clear();
+    }
+    /// Returns the custom algorithm, if any.
+    pub fn algorithm(&self) -> Option<&dyn SsaAlgorithm> {
+        self.algorithm.as_deref()
+    }
+    /// Enables or disables the extended precision mode, disabled by
+    /// default.
+    ///
+    /// In this mode, [`advance_until`](Gillespie::advance_until) sums
+    /// the rates and the time increments in double-double arithmetic,
+    /// with about 32 significant digits instead of 16.  This is slower,
+    /// but needed when the rates span more than 15 orders of magnitude,
+    /// as the smallest ones are otherwise lost in the sum of all rates,
+    /// or when the time increments are too small compared to the
+    /// current time to change it.  It only applies to
+    /// [`Method::Direct`], without custom waiting times.
+    ///
+    /// ```
+    /// use rebop::gillespie::{Gillespie, Rate};
+    /// let mut p = Gillespie::new([0]);
+    /// p.add_reaction(Rate::lma(1e5, [0]), [1]);
+    /// p.set_extended_precision(true);
+    /// // increments of about 1e-5 are below the resolution of t
+    /// p.set_time(1e12);
+    /// p.advance_until(1e12 + 0.01);
+    /// assert!((800..1200).contains(&p.get_species(0)));
+    /// ```
+    pub fn set_extended_precision(&mut self, enabled: bool) {
+        self.extended_precision = enabled;
+    }
+    /// Compiles the rates of all the reactions into a single native
+    /// function, with Cranelift, or stops using it.  Requires the `jit`
+    /// feature.
+    ///
+    /// The direct method then evaluates this function at each step
+    /// instead of interpreting the rates, which is faster for problems
+    /// with many reactions or with [expression rates](Rate::Expr), and
+    /// gives exactly the same trajectories.  The function is compiled at
+    /// the first simulation, and again after a reaction is added or its
+    /// rate is replaced by one of another form: the rate constants, the
+    /// parameters of [`set_param`](Gillespie::set_param), the factors of
+    /// [`scale_rate`](Gillespie::scale_rate) and the disabled reactions
+    /// are read from memory.  The other methods ignore it.
+    ///
+    /// ```
+    /// use rebop::gillespie::{Expr, Gillespie, Rate};
+    /// let mut p = Gillespie::new_with_seed([0, 0], 0);
+    /// p.set_jit(true);
+    /// p.add_reaction(Rate::lma(10., [0, 0]), [1, 0]);
+    /// let saturating = Expr::Div(
+    ///     Box::new(Expr::Concentration(0)),
+    ///     Box::new(Expr::Add(Box::new(Expr::Constant(5.)), Box::new(Expr::Concentration(0)))),
+    /// );
+    /// p.add_reaction(Rate::Expr(saturating), [0, 1]);
+    /// p.advance_until(10.);
+    /// assert!(p.get_species(1) > 0);
+    /// ```
+    ///
+    /// If the compilation fails, for example on a platform that
+    /// Cranelift does not support, the rates are interpreted, as after
+    /// `set_jit(false)`.
+    #[cfg(feature = "jit")]
+    pub fn set_jit(&mut self, enabled: bool) {
+        self.jit = enabled;
+        self.native = None;
+        self.native_values.clear();
+    }
+    /// Returns the rates compiled to native code, with their values in
+    /// `native_values`, compiling them if needed, or `None` if they are
+    /// not used.
+    #[cfg(feature = "jit")]
+    fn native_rates(&mut self) -> Option<Arc<NativeRates>> {
+        if !self.jit {
+            return None;
+        }
+        if self.native_values.is_empty() {
+            // the disabled reactions are compiled with their rates
+            let rates: Vec<(&Rate, bool)> = (self.reactions.iter().enumerate())
+                .map(
+                    |(ireaction, (rate, _))| match self.disabled.get(ireaction) {
+                        Some(Some(disabled)) => (disabled, false),
+                        _ => (rate, true),
+                    },
+                )
+                .collect();
+            let values =
+                (self.native.as_ref()).and_then(|native| native.values(&rates, &self.scales));
+            if let Some(values) = values {
+                self.native_values = values;
+            } else {
+                let compiled: Vec<&Rate> = rates.iter().map(|&(rate, _)| rate).collect();
+                let Ok(native) = NativeRates::compile(&compiled, self.species.len()) else {
+                    self.jit = false;
+                    self.native = None;
+                    return None;
+                };
+                self.native_values = (native.values(&rates, &self.scales))
+                    .expect("the rates have the compiled shapes");
+                self.native = Some(Arc::new(native));
+            }
+        }
+        self.native.clone()
+    }
+    /// Returns the rates laid out over lanes of reactions, or `None` if
+    /// some of them do not follow the law of mass action.
+    fn lma_rates(&self) -> Option<Arc<LmaRates>> {
+        self.lma_rates
+            .get_or_init(|| LmaRates::new(&self.reactions, self.species.len()).map(Arc::new))
+            .clone()
+    }
+    /// Declares the fast reversible pairs of reactions `(forward,
+    /// backward)` of the slow-scale method, [`Method::SlowScale`].
+    ///
+    /// If no pair is declared, they are detected by
+    /// [`detect_fast_pairs`](Gillespie::detect_fast_pairs) with a
+    /// separation of 100 at the first simulation with this method.
+    ///
+    /// ```
+    /// use rebop::gillespie::{Gillespie, Method, Rate};
+    /// // E + S <-> ES -> E + P, with fast binding
+    /// let mut p = Gillespie::new_with_seed([10, 100, 0, 0], 0);
+    /// p.add_reaction(Rate::lma(10., [1, 1, 0, 0]), [-1, -1, 1, 0]);
+    /// p.add_reaction(Rate::lma(1000., [0, 0, 1, 0]), [1, 1, -1, 0]);
+    /// p.add_reaction(Rate::lma(1., [0, 0, 1, 0]), [1, 0, -1, 1]);
+    /// p.set_fast_pairs(&[(0, 1)]);
+    /// p.set_method(Method::SlowScale);
+    /// p.advance_until(5.);
+    /// assert_eq!(p.get_species(0) + p.get_species(2), 10);
+    /// assert!(p.get_species(3) > 0);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the reactions of a pair do not have opposite effects, or
+    /// if two pairs share a reaction or a species.
+    pub fn set_fast_pairs(&mut self, pairs: &[(usize, usize)]) {
+        let nb_species = self.species.len();
+        let changes = |ireaction: usize| match self.reactions[ireaction].1.clone().dense(nb_species)
+        {
+            Jump::Flat(differences) => differences,
+            Jump::Sparse(_) => unreachable!(),
+        };
+        let mut used_species = vec![false; nb_species];
+        let mut used_reactions = vec![false; self.reactions.len()];
+        for &(forward, backward) in pairs {
+            let differences = changes(forward);
+            assert!(
+                differences
+                    .iter()
+                    .zip(changes(backward))
+                    .all(|(f, b)| f + b == 0),
+                "reactions {forward} and {backward} do not have opposite effects"
+            );
+            for ireaction in [forward, backward] {
+                assert!(
+                    !used_reactions[ireaction],
+                    "reaction {ireaction} is in two pairs"
+                );
+                used_reactions[ireaction] = true;
+            }
+            for (s, _) in differences.iter().enumerate().filter(|&(_, &d)| d != 0) {
+                assert!(!used_species[s], "species {s} is changed by two pairs");
+                used_species[s] = true;
+            }
+        }
+        self.fast_pairs = pairs.to_vec();
+    }
+    /// Returns the fast reversible pairs of reactions of the slow-scale
+    /// method.
+    pub fn fast_pairs(&self) -> &[(usize, usize)] {
+        &self.fast_pairs
+    }
+    /// Returns the reversible pairs of reactions whose total rate in the
+    /// current state is at least `separation` times the total rate of the
+    /// reactions that are not in such a pair.
+    ///
+    /// Pairs that would share a species with a faster pair are left out,
+    /// so that the result can be given to
+    /// [`set_fast_pairs`](Gillespie::set_fast_pairs).
+    pub fn detect_fast_pairs(&self, separation: f64) -> Vec<(usize, usize)> {
+        let nb_species = self.species.len();
+        let changes: Vec<Vec<isize>> = self
+            .reactions
+            .iter()
+            .map(|(_, jump)| match jump.clone().dense(nb_species) {
+                Jump::Flat(differences) => differences,
+                Jump::Sparse(_) => unreachable!(),
+            })
+            .collect();
+        let mut rates = vec![f64::NAN; self.reactions.len()];
+        make_rates(&self.reactions, &self.scales, &self.species, &mut rates);
+        let mut candidates = Vec::new();
+        let mut paired = vec![false; self.reactions.len()];
+        for forward in 0..self.reactions.len() {
+            for backward in forward + 1..self.reactions.len() {
+                let opposite = changes[forward]
+                    .iter()
+                    .zip(&changes[backward])
+                    .all(|(f, b)| f + b == 0);
+                if opposite && changes[forward].iter().any(|&d| d != 0) {
+                    candidates.push((rates[forward] + rates[backward], forward, backward));
+                    paired[forward] = true;
+                    paired[backward] = true;
+                }
+            }
+        }
+        let slow_rate: f64 = rates
+            .iter()
+            .zip(&paired)
+            .filter(|&(_, &paired)| !paired)
+            .map(|(rate, _)| rate)
+            .sum();
+        candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let mut used_species = vec![false; nb_species];
+        let mut used_reactions = vec![false; self.reactions.len()];
+        let mut pairs = Vec::new();
+        for (rate, forward, backward) in candidates {
+            let changed = || (0..nb_species).filter(|&s| changes[forward][s] != 0);
+            if rate > 0.
+                && rate >= separation * slow_rate
+                && !used_reactions[forward]
+                && !used_reactions[backward]
+                && !changed().any(|s| used_species[s])
+            {
+                changed().for_each(|s| used_species[s] = true);
+                used_reactions[forward] = true;
+                used_reactions[backward] = true;
+                pairs.push((forward, backward));
+            }
+        }
+        pairs
+    }
+    /// Returns the reactions of the problem, with their rates and jumps.
+    pub(crate) fn reactions(&self) -> &[(Rate, Jump)] {
+        &self.reactions
+    }
+    /// Returns the rate of the reaction of index `ireaction` as given,
+    /// with its parameters, if it has some.
+    pub(crate) fn symbolic_rate(&self, ireaction: usize) -> Option<&Rate> {
+        self.symbolic.get(ireaction).and_then(Option::as_ref)
+    }
+    /// Returns the rate of the reaction of index `ireaction` if it is
+    /// disabled, which then has a rate of zero.
+    pub(crate) fn disabled_rate(&self, ireaction: usize) -> Option<&Rate> {
+        self.disabled.get(ireaction).and_then(Option::as_ref)
+    }
+    /// Whether some reactions have custom waiting times.
+    pub(crate) fn has_waiting_times(&self) -> bool {
+        !self.waiting_times.is_empty()
+    }
+    /// Reorders the reactions so that the reaction of index `order[i]`
+    /// becomes the reaction of index `i`.
+    pub(crate) fn reorder_reactions(&mut self, order: &[usize]) {
+        assert_eq!(order.len(), self.reactions.len());
+        // the vectors of the reactions that can be shorter, or empty, are
+        // padded with `default`
+        fn permute<T: Clone>(values: &mut Vec<T>, order: &[usize], default: T) {
+            if !values.is_empty() {
+                *values = (order.iter())
+                    .map(|&i| values.get(i).cloned().unwrap_or_else(|| default.clone()))
+                    .collect();
+            }
+        }
+        if let Some(propensities) = &mut self.records.propensities {
+            // integrates the propensities in the old order
+            propensities.update(self.t, &self.reactions, &self.scales, &self.species);
+            permute(&mut propensities.current, order, 0.);
+            permute(&mut propensities.integrals, order, 0.);
+            permute(&mut propensities.firings, order, 0);
+        }
+        if let Some(firings) = &mut self.records.firings {
+            permute(firings, order, 0);
+        }
+        self.order.clear();
+        self.reactions = order.iter().map(|&i| self.reactions[i].clone()).collect();
+        permute(&mut self.disabled, order, None);
+        permute(&mut self.symbolic, order, None);
+        permute(&mut self.delays, order, 0.);
+        permute(&mut self.scales, order, 1.);
+        permute(&mut self.waiting_times, order, None);
+        let mut position = vec![0; order.len()];
+        for (new, &old) in order.iter().enumerate() {
+            position[old] = new;
+        }
+        for (forward, backward) in self.fast_pairs.iter_mut() {
+            (*forward, *backward) = (position[*forward], position[*backward]);
+        }
+        if let Some(log) = &mut self.records.log {
+            log.renumber(&position);
+        }
+        self.pending = self
+            .pending
+            .drain()
+            .map(|Scheduled(t, ireaction)| Scheduled(t, position[ireaction]))
+            .collect();
+        self.clocks.clear();
+        self.rates_changed();
+    }
+    /// Returns the current time in the model.
+    pub fn get_time(&self) -> f64 {
+        self.t
+    }
+    /// Sets the current time in the model.
+    pub fn set_time(&mut self, t: f64) {
+        if let Some(propensities) = &mut self.records.propensities {
+            // the time in between does not count
+            propensities.update(self.t, &self.reactions, &self.scales, &self.species);
+            propensities.start += t - self.t;
+            propensities.last = t;
+        }
+        self.t = t;
+    }
+    /// Returns the current amount of a species.
+    ///
+    /// ```
+    /// use rebop::gillespie::Gillespie;
+    /// let p: Gillespie = Gillespie::new([0, 1, 10, 100]);
+    /// assert_eq!(p.get_species(2), 10);
+    /// ```
+    pub fn get_species<S: SpeciesIndex>(&self, s: S) -> isize {
+        self.species[s.index()]
+    }
+    /// Returns the current amounts of all species.
+    pub fn species(&self) -> &[isize] {
+        &self.species
+    }
+    /// Sets the amount of species in the model.
+    pub fn set_species<V: AsRef<[isize]>>(&mut self, species: V) {
+        assert_eq!(species.as_ref().len(), self.species.len());
+        self.species = species.as_ref().to_vec();
+        if let Some(propensities) = &mut self.records.propensities {
+            propensities.update(self.t, &self.reactions, &self.scales, &self.species);
+        }
+    }
+    /// Returns the propensity of each reaction in the current state, as
+    /// used by the simulations: with the factors of
+    /// [`scale_rate`](Gillespie::scale_rate), and zero for the disabled
+    /// reactions.
+    ///
+    /// ```
+    /// use rebop::gillespie::{Gillespie, Rate};
+    /// let mut p = Gillespie::new([10, 2]);
+    /// p.add_reaction(Rate::lma(0.5, [1, 0]), [-1, 1]);
+    /// p.add_reaction(Rate::lma(0.1, [1, 1]), [0, -1]);
+    /// p.add_reaction(Rate::lma(3., [0, 0]), [1, 0]);
+    /// p.scale_rate(1, 2.);
+    /// p.set_reaction_enabled(2, false);
+    /// assert_eq!(p.current_propensities(), [5., 4., 0.]);
+    /// assert_eq!(p.total_rate(), 9.);
+    /// ```
+    pub fn current_propensities(&self) -> Vec<f64> {
+        let mut rates = vec![0.; self.reactions.len()];
+        make_rates(&self.reactions, &self.scales, &self.species, &mut rates);
+        rates
+    }
+    /// Returns the sum of the propensities of all reactions in the
+    /// current state, the rate at which the next reaction happens.
+    pub fn total_rate(&self) -> f64 {
+        let mut rates = vec![0.; self.reactions.len()];
+        make_rates(&self.reactions, &self.scales, &self.species, &mut rates)
+    }
+    /// Adds an observable to the problem, the linear combination of the
+    /// species with the weights `weights`, or replaces the observable of
+    /// the same name.
+    ///
+    /// Observables are recorded by [`Trajectory::push`], and
+    /// [`observable_fn`](Gillespie::observable_fn) evaluates them in
+    /// stopping conditions.
+    ///
+    /// ```
+    /// use rebop::gillespie::{Gillespie, Rate};
+    /// let mut p = Gillespie::new([10, 0]);
+    /// //                         [protein, dimer]
+    /// p.add_reaction(Rate::lma(1., [2, 0]), [-2, 1]);
+    /// p.add_observable("total_protein", [1., 2.]);
+    /// p.advance_until(100.);
+    /// assert_eq!(p.get_observable("total_protein"), Some(10.));
+    /// assert_eq!(p.get_observable("unknown"), None);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `weights` does not have one element per species, see
+    /// [`try_add_observable`](Gillespie::try_add_observable).
+    pub fn add_observable<V: AsRef<[f64]>>(&mut self, name: &str, weights: V) {
+        if let Err(err) = self.try_add_observable(name, weights) {
+            panic!("{err}");
+        }
+    }
+    /// Adds an observable to the problem, like
+    /// [`add_observable`](Gillespie::add_observable), or returns an error
+    /// if `weights` does not have one element per species.
+    pub fn try_add_observable<V: AsRef<[f64]>>(
+        &mut self,
+        name: &str,
+        weights: V,
+    ) -> Result<(), Error> {
+        let weights = weights.as_ref().to_vec();
+        if weights.len() != self.species.len() {
+            return Err(Error::SpeciesCount {
+                expected: self.species.len(),
+                found: weights.len(),
+            });
+        }
+        match self.observables.iter_mut().find(|(other, _)| other == name) {
+            Some((_, current)) => *current = weights,
+            None => self.observables.push((name.to_string(), weights)),
+        }
+        Ok(())
+    }
+    /// Returns the names and the weights of the observables, in the order
+    /// in which they were added.
+    pub fn observables(&self) -> &[(String, Vec<f64>)] {
+        &self.observables
+    }
+    /// Returns the current value of the observable `name`, or `None` if
+    /// there is no such observable.
+    pub fn get_observable(&self, name: &str) -> Option<f64> {
+        self.observable_fn(name).map(|value| value(&self.species))
+    }
+    /// Returns the function that computes the observable `name` from the
+    /// amounts of species, or `None` if there is no such observable.
+    ///
+    /// It does not borrow the problem, so that it can be used in the
+    /// stopping conditions of the simulations.
+    ///
+    /// ```
+    /// use rebop::gillespie::{Gillespie, Rate};
+    /// use rebop::passage::first_passage_time;
+    /// let mut p = Gillespie::new_with_seed([0, 0], 0);
+    /// p.add_reaction(Rate::lma(10., [0, 0]), [1, 0]);
+    /// p.add_reaction(Rate::lma(1., [1, 0]), [-1, 1]);
+    /// p.add_observable("total", [1., 1.]);
+    /// let total = p.observable_fn("total").unwrap();
+    /// first_passage_time(&mut p, |x| total(x) >= 50., 1e3).unwrap();
+    /// assert_eq!(p.get_observable("total"), Some(50.));
+    /// ```
+    pub fn observable_fn(&self, name: &str) -> Option<impl Fn(&[isize]) -> f64 + 'static> {
+        let (_, weights) = self.observables.iter().find(|(other, _)| other == name)?;
+        let weights = weights.clone();
+        Some(move |species: &[isize]| observe(&weights, species))
+    }
+    /// Simulates the problem until the next discrete reaction.
+    pub fn advance_one_reaction(&mut self) {
+        let mut rates = vec![f64::NAN; self.nb_reactions()];
+        self._advance_one_reaction(&mut rates);
+    }
+
+    #[inline]
+    pub fn _advance_one_reaction(&mut self, rates: &mut [f64]) {
+        // let total_rate = make_rates(&self.reactions, &self.scales, &self.species, rates);
+        let total_rate = make_cumrates(&self.reactions, &self.scales, &self.species, rates);
+
         // we don't want to use partial_cmp, for performance
         #[allow(clippy::neg_cmp_op_on_partial_ord)]
         if !(0. < total_rate) {
             self.t = f64::INFINITY;
             return;
         }
-        self.t += self.rng.sample::<f64, _>(Exp1) / total_rate;
-        let chosen_rate = total_rate * self.rng.gen::<f64>();
+        self.t += self.rng.sample::<f64, _>(Exp1) / total_rate;
+        let chosen_rate = total_rate * self.rng.gen::<f64>();
+
+        // let ireaction = choose_rate_sum(chosen_rate, &rates);
+        // let ireaction = choose_rate_for(chosen_rate, &rates);
+        let ireaction = choose_cumrate_sum(chosen_rate, rates);
+        // let ireaction = choose_cumrate_for(chosen_rate, &rates);
+        // let ireaction = choose_cumrate_takewhile(chosen_rate, &rates);
+        // here we have ireaction < self.reactions.len() because chosen_rate < total_rate
+        let reaction = unsafe { self.reactions.get_unchecked(ireaction) };
+
+        reaction.1.affect(&mut self.species);
+        self.records.count(
+            self.t,
+            ireaction,
+            1,
+            &self.reactions,
+            &self.scales,
+            &self.species,
+        );
+    }
+    /// Simulates the problem until `tmax` with the direct method, like
+    /// [`advance_until`](Gillespie::advance_until), but checks the rates
+    /// before each reaction and the amounts of species after it, to
+    /// diagnose wrong models.
+    ///
+    /// It returns an error describing the reaction, the time and the
+    /// state that caused it if an amount becomes negative, if a rate is
+    /// negative or `NaN`, or if the total rate is infinite.  The problem
+    /// is then left in this state, before the faulty reaction.  These
+    /// checks make the simulation slower, and the other methods of
+    /// simulation are not used.
+    ///
+    /// ```
+    /// use rebop::gillespie::{Gillespie, Rate};
+    /// use rebop::Error;
+    /// let mut p = Gillespie::new([3, 0]);
+    /// // A -> B, but with a wrong sign
+    /// p.add_reaction(Rate::lma(1., [1, 0]), [-1, -1]);
+    /// let Err(Error::NegativeAmount { reaction, species, state, .. }) =
+    ///     p.advance_until_checked(10.)
+    /// else {
+    ///     panic!("the error was not detected");
+    /// };
+    /// assert_eq!((reaction, species, state), (0, 1, vec![3, 0]));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if some reactions have custom waiting times or delays.
+    pub fn advance_until_checked(&mut self, tmax: f64) -> Result<(), Error> {
+        assert!(
+            self.waiting_times.is_empty() && self.delays.is_empty(),
+            "checked simulations use the direct method"
+        );
+        let mut rates = vec![0.; self.reactions.len()];
+        loop {
+            let mut total_rate = 0.;
+            for (ireaction, (rate, (r, _))) in rates.iter_mut().zip(&self.reactions).enumerate() {
+                *rate = r.rate(&self.species) * scale(&self.scales, ireaction);
+                // also true for NaN
+                #[allow(clippy::neg_cmp_op_on_partial_ord)]
+                if !(*rate >= 0.) {
+                    return Err(Error::InvalidRate {
+                        time: self.t,
+                        reaction: ireaction,
+                        rate: *rate,
+                        state: self.species.clone(),
+                    });
+                }
+                total_rate += *rate;
+            }
+            if total_rate.is_infinite() {
+                return Err(Error::InfiniteTotalRate {
+                    time: self.t,
+                    state: self.species.clone(),
+                });
+            }
+            let t = self.t + self.rng.sample::<f64, _>(Exp1) / total_rate;
+            if t > tmax {
+                self.t = tmax;
+                return Ok(());
+            }
+            let mut chosen_rate = total_rate * self.rng.gen::<f64>();
+            let ireaction = rates
+                .iter()
+                .position(|&rate| {
+                    chosen_rate -= rate;
+                    chosen_rate < 0.
+                })
+                .unwrap_or_else(|| rates.iter().rposition(|&rate| rate > 0.).unwrap());
+            let mut species = self.species.clone();
+            self.reactions[ireaction].1.affect(&mut species);
+            if let Some(s) = species.iter().position(|&amount| amount < 0) {
+                return Err(Error::NegativeAmount {
+                    time: t,
+                    reaction: ireaction,
+                    species: s,
+                    state: self.species.clone(),
+                });
+            }
+            self.t = t;
+            self.species = species;
+            self.records.count(
+                self.t,
+                ireaction,
+                1,
+                &self.reactions,
+                &self.scales,
+                &self.species,
+            );
+        }
+    }
+    /// Simulates the problem until `tmax`.
+    ///
+    /// ```
+    /// use rebop::gillespie::{Gillespie, Rate};
+    /// let mut dimers = Gillespie::new([1, 0, 0, 0]);
+    /// //                              [G, M, P, D]
+    /// dimers.add_reaction(Rate::lma(25., [1, 0, 0, 0]), [0, 1, 0, 0]);
+    /// dimers.add_reaction(Rate::lma(1000., [0, 1, 0, 0]), [0, 0, 1, 0]);
+    /// dimers.add_reaction(Rate::lma(0.001, [0, 0, 2, 0]), [0, 0, -2, 1]);
+    /// dimers.add_reaction(Rate::lma(0.1, [0, 1, 0, 0]), [0, -1, 0, 0]);
+    /// dimers.add_reaction(Rate::lma(1., [0, 0, 1, 0]), [0, 0, -1, 0]);
+    /// assert_eq!(dimers.get_time(), 0.);
+    /// assert_eq!(dimers.get_species(3), 0);
+    /// dimers.advance_until(1.);
+    /// assert_eq!(dimers.get_time(), 1.);
+    /// assert!(dimers.get_species(3) > 0);
+    /// ```
+    pub fn advance_until(&mut self, tmax: f64) {
+        let limited = self.limits != Limits::default();
+        self.records.budget = limited.then(|| Budget::new(&self.limits));
+        let mut workspace = std::mem::take(&mut self.workspace);
+        self.simulate_until(tmax, &mut workspace);
+        self.workspace = workspace;
+        self.limit_reached = self.records.budget.take().and_then(|budget| budget.reached);
+    }
+    /// Simulates the problem until `tmax` like
+    /// [`advance_until`](Gillespie::advance_until), and returns an error if
+    /// a limit of [`set_limits`](Gillespie::set_limits) stopped it.
+    ///
+    /// ```
+    /// use rebop::gillespie::{Gillespie, Limit, Limits, Rate};
+    /// use rebop::Error;
+    /// // autocatalysis, which explodes in finite time
+    /// let mut p = Gillespie::new_with_seed([10], 0);
+    /// p.add_reaction(Rate::lma(1., [2]), [1]);
+    /// p.set_limits(Limits {
+    ///     max_reactions: Some(100_000),
+    ///     ..Limits::default()
+    /// });
+    /// let Err(Error::LimitReached { limit, time, .. }) = p.try_advance_until(10.) else {
+    ///     panic!("the explosion was not stopped");
+    /// };
+    /// assert_eq!(limit, Limit::Reactions);
+    /// assert!(time < 1.);
+    /// assert_eq!(p.get_species(0), 100_010);
+    /// ```
+    pub fn try_advance_until(&mut self, tmax: f64) -> Result<(), Error> {
+        self.advance_until(tmax);
+        match self.limit_reached {
+            Some(limit) => Err(Error::LimitReached {
+                limit,
+                time: self.t,
+                state: self.species.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+    /// Sets the limits of the work of each call to
+    /// [`advance_until`](Gillespie::advance_until), none by default.
+    ///
+    /// When a limit is reached, the simulation stops at the last
+    /// reaction, before `tmax`, and
+    /// [`limit_reached`](Gillespie::limit_reached) tells which limit it
+    /// was.  This guards the automated scans of parameters against the
+    /// models that explode, like autocatalytic ones, which would
+    /// otherwise never reach `tmax`.  The approximate methods stop at the
+    /// end of the step in which the limit is reached, and the custom
+    /// algorithms when they check
+    /// [`SsaState::limit_reached`](crate::algorithm::SsaState::limit_reached).
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+    /// Returns the limits of [`set_limits`](Gillespie::set_limits).
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+    /// Returns the limit that stopped the last call to
+    /// [`advance_until`](Gillespie::advance_until), if any.
+    pub fn limit_reached(&self) -> Option<Limit> {
+        self.limit_reached
+    }
+    /// Uses the buffers of `workspace` for the simulations, instead of
+    /// allocating new ones.
+    ///
+    /// The successive calls to [`advance_until`](Gillespie::advance_until)
+    /// of a problem already share their buffers, but its clones start
+    /// with empty ones: the drivers of ensembles of many short runs can
+    /// thus move the buffers of each run to the next one, with
+    /// [`take_workspace`](Gillespie::take_workspace).
+    ///
+    /// ```
+    /// use rebop::gillespie::{Gillespie, Rate, Workspace};
+    /// let mut problem = Gillespie::new([100, 0]);
+    /// problem.add_reaction(Rate::lma(1., [1, 0]), [-1, 1]);
+    /// let mut workspace = Workspace::default();
+    /// for seed in 0..100 {
+    ///     let mut run = problem.clone().with_workspace(workspace);
+    ///     run.seed(seed);
+    ///     run.advance_until(1.);
+    ///     assert_eq!(run.get_species(0) + run.get_species(1), 100);
+    ///     workspace = run.take_workspace();
+    /// }
+    /// ```
+    pub fn with_workspace(mut self, workspace: Workspace) -> Self {
+        self.workspace = workspace;
+        self
+    }
+    /// Returns the buffers of the simulations, for another problem, see
+    /// [`with_workspace`](Gillespie::with_workspace).  The problem then
+    /// allocates new ones at its next simulation.
+    pub fn take_workspace(&mut self) -> Workspace {
+        std::mem::take(&mut self.workspace)
+    }
+    /// Simulates the problem until `tmax` with its method, or until the
+    /// budget of its records is spent.
+    fn simulate_until(&mut self, tmax: f64, workspace: &mut Workspace) {
+        if let Some(algorithm) = self.algorithm.clone() {
+            return self.advance_until_custom(&*algorithm, tmax);
+        }
+        if !self.waiting_times.is_empty() {
+            assert!(
+                self.delays.is_empty(),
+                "delays cannot be combined with custom waiting times"
+            );
+            return self.advance_until_nonmarkovian(tmax);
+        }
+        if !self.delays.is_empty() {
+            return self.advance_until_delayed(tmax, workspace);
+        }
+        match self.method {
+            Method::Direct if self.extended_precision => return self.advance_until_extended(tmax),
+            Method::Direct => (),
+            Method::FirstReaction => return self.advance_until_first_reaction(tmax, workspace),
+            Method::NextReaction => return self.advance_until_nonmarkovian(tmax),
+            Method::SortingDirect => return self.advance_until_sorting_direct(tmax, workspace),
+            Method::RLeaping(nb_firings) => {
+                return self.advance_until_r_leaping(tmax, nb_firings, workspace)
+            }
+            Method::SlowScale => return self.advance_until_slow_scale(tmax),
+        }
+        self.advance_until_direct(tmax, u64::MAX, workspace, |_| ());
+    }
+    /// Simulates the problem with the direct method until `tmax`, or
+    /// until `max_reactions` reactions fired, and returns whether `tmax`
+    /// was reached.
+    ///
+    /// The time is otherwise left at the last reaction, and the next
+    /// calls continue the same trajectory as a single call to
+    /// [`advance_until`](Gillespie::advance_until) would: a long
+    /// simulation can thus be split into bounded amounts of work, for
+    /// example to check for a cancellation between them.
+    ///
+    /// ```
+    /// use rebop::gillespie::{Gillespie, Rate};
+    /// let mut p = Gillespie::new_with_seed([0], 0);
+    /// p.add_reaction(Rate::lma(1000., [0]), [1]);
+    /// let mut q = p.clone();
+    /// let mut nb_calls = 1;
+    /// while !p.advance_until_bounded(10., 1000) {
+    ///     nb_calls += 1;
+    /// }
+    /// q.advance_until(10.);
+    /// assert!(nb_calls > 5);
+    /// assert_eq!(p.species(), q.species());
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the problem is not simulated by the direct method, with
+    /// the default precision and without custom waiting times, delays or
+    /// algorithm.
+    pub fn advance_until_bounded(&mut self, tmax: f64, max_reactions: u64) -> bool {
+        self.assert_direct("bounded simulations use the direct method");
+        let mut workspace = std::mem::take(&mut self.workspace);
+        let reached = self.advance_until_direct(tmax, max_reactions, &mut workspace, |_| ());
+        self.workspace = workspace;
+        reached
+    }
+    /// Simulates `n` reactions with the direct method, or fewer if the
+    /// total rate becomes zero, and returns the number of reactions that
+    /// fired.
+    ///
+    /// The time is left at the last reaction, for example at the end of
+    /// a burn-in phase, or to measure the cost of each reaction.  If the
+    /// total rate becomes zero, the time becomes infinite, as with
+    /// [`advance_until`](Gillespie::advance_until) until infinity.
+    ///
+    /// ```
+    /// use rebop::gillespie::{Gillespie, Rate};
+    /// let mut p = Gillespie::new_with_seed([10, 0], 0);
+    /// p.add_reaction(Rate::lma(1., [1, 0]), [-1, 1]);
+    /// assert_eq!(p.advance_n_reactions(4)?, 4);
+    /// assert_eq!(p.species(), [6, 4]);
+    /// assert!(p.get_time().is_finite());
+    /// assert_eq!(p.advance_n_reactions(100)?, 6);
+    /// assert_eq!(p.get_time(), f64::INFINITY);
+    /// # Ok::<(), rebop::Error>(())
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::DirectOnly`] if the problem is not simulated by
+    /// the direct method, with the default precision and without custom
+    /// waiting times, delays or algorithm.
+    pub fn advance_n_reactions(&mut self, n: usize) -> Result<usize, Error> {
+        self.check_direct("simulations by number of reactions")?;
+        let mut fired = 0;
+        let mut workspace = std::mem::take(&mut self.workspace);
+        self.advance_until_direct(f64::INFINITY, n as u64, &mut workspace, |_| fired += 1);
+        self.workspace = workspace;
+        Ok(fired)
+    }
+    /// Returns an iterator over the reactions simulated with the direct
+    /// method, as the time, the index of the reaction and the amounts of
+    /// species after it, until the total rate becomes zero.
+    ///
+    /// The reactions are simulated lazily, as the iterator advances, so
+    /// that the usual combinators can decide when to stop and what to
+    /// keep.  The amounts of species are copied at each reaction.
+    ///
+    /// ```
+    /// use rebop::gillespie::{Gillespie, Rate};
+    /// let mut p = Gillespie::new_with_seed([0, 0], 0);
+    /// p.add_reaction(Rate::lma(10., [0, 0]), [1, 0]);
+    /// p.add_reaction(Rate::lma(1., [1, 0]), [-1, 1]);
+    /// // amounts of the first species after every 10th degradation, until t = 5
+    /// let samples: Vec<isize> = p
+    ///     .events()?
+    ///     .take_while(|(t, _, _)| *t < 5.)
+    ///     .filter(|(_, ireaction, _)| *ireaction == 1)
+    ///     .step_by(10)
+    ///     .map(|(_, _, species)| species[0])
+    ///     .collect();
+    /// assert!(!samples.is_empty());
+    /// # Ok::<(), rebop::Error>(())
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error in the same cases as
+    /// [`advance_n_reactions`](Gillespie::advance_n_reactions).
+    pub fn events(&mut self) -> Result<Events<'_, R>, Error> {
+        self.check_direct("iterators over reactions")?;
+        Ok(Events { problem: self })
+    }
+    /// Returns [`Error::DirectOnly`] for `operation` if the problem is
+    /// not simulated by the direct method, with the default precision
+    /// and without custom waiting times, delays or algorithm.
+    fn check_direct(&self, operation: &str) -> Result<(), Error> {
+        if self.is_direct() {
+            Ok(())
+        } else {
+            Err(Error::DirectOnly {
+                operation: operation.to_string(),
+            })
+        }
+    }
+    /// Whether the problem is simulated by the direct method, with the
+    /// default precision and without custom waiting times, delays or
+    /// algorithm.
+    fn is_direct(&self) -> bool {
+        self.algorithm.is_none()
+            && self.waiting_times.is_empty()
+            && self.delays.is_empty()
+            && self.method == Method::Direct
+            && !self.extended_precision
+    }
+    /// Panics with `message` if the problem is not simulated by the
+    /// direct method, with the default precision and without custom
+    /// waiting times, delays or algorithm.
+    fn assert_direct(&self, message: &str) {
+        assert!(self.is_direct(), "{message}");
+    }
+    /// Simulates the problem with the direct method until `tmax`, or
+    /// until `max_reactions` reactions fired, calling `fired` with the
+    /// index of each reaction, and returns whether `tmax` was reached.
+    fn advance_until_direct(
+        &mut self,
+        tmax: f64,
+        max_reactions: u64,
+        workspace: &mut Workspace,
+        mut fired: impl FnMut(usize),
+    ) -> bool {
+        #[cfg(feature = "jit")]
+        let native = self.native_rates();
+        let lma_rates = self.lma_rates();
+        let amounts = &mut workspace.amounts;
+        let rates = fill(&mut workspace.rates, self.reactions.len(), f64::NAN);
+        for _ in 0..max_reactions {
+            if self.records.stopped() {
+                return false;
+            }
+            //let total_rate = make_rates(&self.reactions, &self.scales, &self.species, rates);
+            #[cfg(feature = "jit")]
+            let total_rate = match (&native, &lma_rates) {
+                (Some(native), _) => native.cum_rates(&self.species, &self.native_values, rates),
+                (None, Some(lanes)) => lanes.cum_rates(&self.species, &self.scales, amounts, rates),
+                (None, None) => make_cumrates(&self.reactions, &self.scales, &self.species, rates),
+            };
+            #[cfg(not(feature = "jit"))]
+            let total_rate = match &lma_rates {
+                Some(lanes) => lanes.cum_rates(&self.species, &self.scales, amounts, rates),
+                None => make_cumrates(&self.reactions, &self.scales, &self.species, rates),
+            };
+
+            // we don't want to use partial_cmp, for performance
+            #[allow(clippy::neg_cmp_op_on_partial_ord)]
+            if !(0. < total_rate) {
+                self.t = tmax;
+                return true;
+            }
+            let t = self.t + self.rng.sample::<f64, _>(Exp1) / total_rate;
+            if t > tmax {
+                self.t = tmax;
+                return true;
+            }
+            self.t = t;
+            let chosen_rate = total_rate * self.rng.gen::<f64>();
+
+            //let ireaction = choose_rate_sum(chosen_rate, &rates);
+            //let ireaction = choose_rate_for(chosen_rate, &rates);
+            let ireaction = choose_cumrate_sum(chosen_rate, rates);
+            //let ireaction = choose_cumrate_for(chosen_rate, &rates);
+            //let ireaction = choose_cumrate_takewhile(chosen_rate, &rates);
+            // here we have ireaction < self.reactions.len() because chosen_rate < total_rate
+            let reaction = unsafe { self.reactions.get_unchecked(ireaction) };
+
+            reaction.1.affect(&mut self.species);
+            self.records.count(
+                self.t,
+                ireaction,
+                1,
+                &self.reactions,
+                &self.scales,
+                &self.species,
+            );
+            fired(ireaction);
+        }
+        false
+    }
+    /// Simulates the problem until `tmax` by uniformization at the rate
+    /// `bound`, with the uniform numbers drawn by `uniform`: the number of
+    /// events follows a Poisson distribution, and each event fires a
+    /// reaction with the probability of its rate over `bound`, or none.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the total rate exceeds `bound`, or if the problem has
+    /// custom waiting times or delays.
+    pub(crate) fn advance_until_uniformized<F>(&mut self, tmax: f64, bound: f64, mut uniform: F)
+    where
+        F: FnMut() -> f64,
+    {
+        assert!(
+            self.waiting_times.is_empty() && self.delays.is_empty(),
+            "uniformization cannot be combined with custom waiting times or delays"
+        );
+        if tmax <= self.t {
+            return;
+        }
+        // the Poisson distribution is inverted over chunks of moderate
+        // means, where its probabilities do not underflow
+        let mean = bound * (tmax - self.t);
+        let nb_chunks = (mean / 500.).ceil().max(1.);
+        let mut nb_events = 0;
+        for _ in 0..nb_chunks as usize {
+            nb_events += poisson_quantile(mean / nb_chunks, uniform());
+        }
+        let mut rates = std::mem::take(&mut self.workspace.rates);
+        fill(&mut rates, self.reactions.len(), f64::NAN);
+        for _ in 0..nb_events {
+            let total_rate =
+                make_cumrates(&self.reactions, &self.scales, &self.species, &mut rates);
+            assert!(
+                total_rate <= bound,
+                "the total rate {total_rate} exceeds the uniformization rate {bound}"
+            );
+            let chosen_rate = bound * uniform();
+            if chosen_rate < total_rate {
+                let ireaction = choose_cumrate_sum(chosen_rate, &rates);
+                self.reactions[ireaction].1.affect(&mut self.species);
+                self.records.count(
+                    tmax,
+                    ireaction,
+                    1,
+                    &self.reactions,
+                    &self.scales,
+                    &self.species,
+                );
+            }
+        }
+        self.workspace.rates = rates;
+        self.t = tmax;
+    }
+    /// Simulates the problem until `tmax` with a custom algorithm.
+    fn advance_until_custom(&mut self, algorithm: &dyn SsaAlgorithm, tmax: f64) {
+        assert!(
+            self.waiting_times.is_empty() && self.delays.is_empty(),
+            "custom algorithms cannot be combined with custom waiting times or delays"
+        );
+        for (ireaction, (rate, _)) in self.reactions.iter().enumerate() {
+            assert!(
+                algorithm.supports(rate),
+                "{} does not support the rate of reaction {ireaction}",
+                algorithm.name()
+            );
+        }
+        let mut state = SsaState::new(
+            &mut self.t,
+            &mut self.species,
+            &self.reactions,
+            &self.scales,
+            &mut self.records,
+            &mut self.rng,
+        );
+        algorithm.advance_until(&mut state, tmax);
+    }
+    /// Simulates the problem until `tmax` with the first reaction
+    /// method.
+    fn advance_until_first_reaction(&mut self, tmax: f64, workspace: &mut Workspace) {
+        let rates = fill(&mut workspace.rates, self.reactions.len(), f64::NAN);
+        loop {
+            if self.records.stopped() {
+                return;
+            }
+            make_rates(&self.reactions, &self.scales, &self.species, rates);
+            let mut first = (f64::INFINITY, 0);
+            for (ireaction, &rate) in rates.iter().enumerate() {
+                if rate > 0. {
+                    let delay = self.rng.sample::<f64, _>(Exp1) / rate;
+                    if delay < first.0 {
+                        first = (delay, ireaction);
+                    }
+                }
+            }
+            self.t += first.0;
+            if self.t > tmax {
+                self.t = tmax;
+                return;
+            }
+            self.reactions[first.1].1.affect(&mut self.species);
+            self.records.count(
+                self.t,
+                first.1,
+                1,
+                &self.reactions,
+                &self.scales,
+                &self.species,
+            );
+        }
+    }
+    /// Simulates the problem until `tmax` with the sorting direct
+    /// method.  The order of the reactions starts from the order in
+    /// which they were added, and is updated online.
+    fn advance_until_sorting_direct(&mut self, tmax: f64, workspace: &mut Workspace) {
+        if self.order.len() != self.reactions.len() {
+            self.order = (0..self.reactions.len()).collect();
+        }
+        let cumrates = fill(&mut workspace.rates, self.reactions.len(), f64::NAN);
+        loop {
+            if self.records.stopped() {
+                return;
+            }
+            let mut total_rate = 0.;
+            for (&ireaction, cumrate) in self.order.iter().zip(cumrates.iter_mut()) {
+                total_rate += self.reactions[ireaction].0.rate(&self.species)
+                    * scale(&self.scales, ireaction);
+                *cumrate = total_rate;
+            }
+            // we don't want to use partial_cmp, for performance
+            #[allow(clippy::neg_cmp_op_on_partial_ord)]
+            if !(0. < total_rate) {
+                self.t = tmax;
+                return;
+            }
+            self.t += self.rng.sample::<f64, _>(Exp1) / total_rate;
+            if self.t > tmax {
+                self.t = tmax;
+                return;
+            }
+            let chosen_rate = total_rate * self.rng.gen::<f64>();
+            let position = choose_cumrate_sum(chosen_rate, cumrates);
+            self.reactions[self.order[position]]
+                .1
+                .affect(&mut self.species);
+            self.records.count(
+                self.t,
+                self.order[position],
+                1,
+                &self.reactions,
+                &self.scales,
+                &self.species,
+            );
+            if position > 0 {
+                self.order.swap(position, position - 1);
+            }
+        }
+    }
+    /// Simulates the problem until `tmax` with R-leaping, firing
+    /// `nb_firings` reactions per step.
+    fn advance_until_r_leaping(&mut self, tmax: f64, nb_firings: u32, workspace: &mut Workspace) {
+        let rates = fill(&mut workspace.rates, self.reactions.len(), f64::NAN);
+        let counts = fill(&mut workspace.counts, self.reactions.len(), 0);
+        let mut leap = nb_firings.max(1);
+        loop {
+            if self.records.stopped() {
+                return;
+            }
+            let total_rate = make_rates(&self.reactions, &self.scales, &self.species, rates);
+            // we don't want to use partial_cmp, for performance
+            #[allow(clippy::neg_cmp_op_on_partial_ord)]
+            if !(0. < total_rate) {
+                self.t = tmax;
+                return;
+            }
+            let duration = Gamma::new(leap as f64, 1. / total_rate)
+                .unwrap()
+                .sample(&mut self.rng);
+            let last = self.t + duration > tmax;
+            let mut remaining = if last {
+                // the other reactions happen uniformly during the step
+                let fraction = ((tmax - self.t) / duration).clamp(0., 1.);
+                Binomial::new(u64::from(leap - 1), fraction)
+                    .unwrap()
+                    .sample(&mut self.rng)
+            } else {
+                u64::from(leap)
+            };
+            let mut remaining_rate = total_rate;
+            for (count, &rate) in counts.iter_mut().zip(rates.iter()) {
+                *count = if remaining == 0 || rate <= 0. {
+                    0
+                } else if rate >= remaining_rate {
+                    remaining
+                } else {
+                    Binomial::new(remaining, rate / remaining_rate)
+                        .unwrap()
+                        .sample(&mut self.rng)
+                };
+                remaining -= *count;
+                remaining_rate -= rate;
+            }
+            let before = self.species.clone();
+            for ((_, jump), &count) in self.reactions.iter().zip(counts.iter()) {
+                for _ in 0..count {
+                    jump.affect(&mut self.species);
+                }
+            }
+            if leap > 1 && self.species.iter().any(|&s| s < 0) {
+                self.species = before;
+                leap /= 2;
+                continue;
+            }
+            let end = if last { tmax } else { self.t + duration };
+            for (ireaction, &n) in counts.iter().enumerate() {
+                self.records.count(
+                    end,
+                    ireaction,
+                    n,
+                    &self.reactions,
+                    &self.scales,
+                    &self.species,
+                );
+            }
+            self.t = end;
+            if last {
+                return;
+            }
+            leap = nb_firings.max(1);
+        }
+    }
+    /// Simulates the problem until `tmax` with the direct method and
+    /// delayed reactions.
+    ///
+    /// When a delayed reaction takes effect before the next reaction,
+    /// the rates change and the time until the next reaction is drawn
+    /// again, which is correct because it is memoryless.
+    fn advance_until_delayed(&mut self, tmax: f64, workspace: &mut Workspace) {
+        let cumrates = fill(&mut workspace.rates, self.reactions.len(), f64::NAN);
+        loop {
+            if self.records.stopped() {
+                return;
+            }
+            let total_rate = make_cumrates(&self.reactions, &self.scales, &self.species, cumrates);
+            let next = if total_rate > 0. {
+                self.t + self.rng.sample::<f64, _>(Exp1) / total_rate
+            } else {
+                f64::INFINITY
+            };
+            let completion = self.pending.peek().map_or(f64::INFINITY, |s| s.0);
+            if completion <= next.min(tmax) {
+                let Scheduled(t, ireaction) = self.pending.pop().unwrap();
+                self.t = t;
+                self.reactions[ireaction].1.affect(&mut self.species);
+                self.records.count(
+                    self.t,
+                    ireaction,
+                    1,
+                    &self.reactions,
+                    &self.scales,
+                    &self.species,
+                );
+                continue;
+            }
+            if next > tmax {
+                self.t = tmax;
+                return;
+            }
+            self.t = next;
+            let ireaction = choose_cumrate_sum(total_rate * self.rng.gen::<f64>(), cumrates);
+            match self.delays.get(ireaction) {
+                Some(&delay) if delay > 0. => self.pending.push(Scheduled(next + delay, ireaction)),
+                _ => {
+                    self.reactions[ireaction].1.affect(&mut self.species);
+                    self.records.count(
+                        self.t,
+                        ireaction,
+                        1,
+                        &self.reactions,
+                        &self.scales,
+                        &self.species,
+                    );
+                }
+            }
+        }
+    }
+    /// Simulates the problem until `tmax` with the slow-scale method.
+    ///
+    /// At each step, the stationary distributions of the fast pairs are
+    /// computed from the current state.  A slow reaction is chosen
+    /// according to its rate averaged over these distributions, and
+    /// fires from a state of the fast pairs drawn in proportion to its
+    /// probability times the rate of the reaction there.  At `tmax`, the
+    /// fast pairs are set to a state drawn from their distributions.
+    fn advance_until_slow_scale(&mut self, tmax: f64) {
+        if self.fast_pairs.is_empty() {
+            self.fast_pairs = self.detect_fast_pairs(100.);
+        }
+        let nb_species = self.species.len();
+        // changes of the species when the forward reaction of each pair fires
+        let changes: Vec<Vec<(usize, isize)>> = self
+            .fast_pairs
+            .iter()
+            .map(|&(forward, _)| {
+                match self.reactions[forward].1.clone().dense(nb_species).sparse() {
+                    Jump::Sparse(changes) => changes,
+                    Jump::Flat(_) => unreachable!(),
+                }
+            })
+            .collect();
+        // slow reactions, with the fast pairs that change a species their
+        // rates depend on
+        let slow: Vec<(usize, Vec<usize>)> = (0..self.reactions.len())
+            .filter(|&i| !self.fast_pairs.iter().any(|&(f, b)| i == f || i == b))
+            .map(|ireaction| {
+                let rate = &self.reactions[ireaction].0;
+                let ipairs = (0..changes.len())
+                    .filter(|&p| changes[p].iter().any(|&(s, _)| reads(rate, s)))
+                    .collect();
+                (ireaction, ipairs)
+            })
+            .collect();
+        let mut rates = vec![0.; slow.len()];
+        loop {
+            if self.records.stopped() {
+                return;
+            }
+            let equilibria: Vec<FastEquilibrium> = self
+                .fast_pairs
+                .iter()
+                .zip(&changes)
+                .map(|(&(forward, backward), changes)| {
+                    FastEquilibrium::new(
+                        (&self.reactions[forward].0, scale(&self.scales, forward)),
+                        (&self.reactions[backward].0, scale(&self.scales, backward)),
+                        changes,
+                        &self.species,
+                    )
+                })
+                .collect();
+            let mut total_rate = 0.;
+            for ((ireaction, ipairs), mean_rate) in slow.iter().zip(rates.iter_mut()) {
+                let rate = &self.reactions[*ireaction].0;
+                let factor = scale(&self.scales, *ireaction);
+                let ipairs: Vec<&FastEquilibrium> =
+                    ipairs.iter().map(|&p| &equilibria[p]).collect();
+                *mean_rate = 0.;
+                visit_fast_states(
+                    &mut self.species,
+                    &ipairs,
+                    1.,
+                    &mut |species, probability| {
+                        *mean_rate += probability * rate.rate(species) * factor;
+                        true
+                    },
+                );
+                total_rate += *mean_rate;
+            }
+            let delay = if total_rate > 0. {
+                self.rng.sample::<f64, _>(Exp1) / total_rate
+            } else {
+                f64::INFINITY
+            };
+            if self.t + delay > tmax {
+                for equilibrium in &equilibria {
+                    let state = equilibrium.sample(self.rng.gen());
+                    equilibrium.shift(&mut self.species, state);
+                }
+                self.t = tmax;
+                return;
+            }
+            self.t += delay;
+            let mut chosen_rate = total_rate * self.rng.gen::<f64>();
+            let islow = rates
+                .iter()
+                .position(|&rate| {
+                    chosen_rate -= rate;
+                    chosen_rate < 0.
+                })
+                .unwrap_or_else(|| rates.iter().rposition(|&rate| rate > 0.).unwrap());
+            let (ireaction, ipairs) = &slow[islow];
+            let rate = &self.reactions[*ireaction].0;
+            let factor = scale(&self.scales, *ireaction);
+            let ipairs: Vec<&FastEquilibrium> = ipairs.iter().map(|&p| &equilibria[p]).collect();
+            // the species are left in the chosen state of the fast pairs
+            let mut chosen_rate = rates[islow] * self.rng.gen::<f64>();
+            visit_fast_states(
+                &mut self.species,
+                &ipairs,
+                1.,
+                &mut |species, probability| {
+                    let weight = probability * rate.rate(species) * factor;
+                    chosen_rate -= weight;
+                    !(chosen_rate < 0. && weight > 0.)
+                },
+            );
+            self.reactions[*ireaction].1.affect(&mut self.species);
+            self.records.count(
+                self.t,
+                *ireaction,
+                1,
+                &self.reactions,
+                &self.scales,
+                &self.species,
+            );
+        }
+    }
+    /// Simulates the problem until `tmax` like
+    /// [`advance_until`](Gillespie::advance_until), with the cumulative
+    /// rates and the time in double-double arithmetic.
+    fn advance_until_extended(&mut self, tmax: f64) {
+        let mut cumrates = vec![DoubleDouble::ZERO; self.reactions.len()];
+        let mut t = DoubleDouble(self.t, 0.);
+        loop {
+            if self.records.stopped() {
+                return;
+            }
+            let mut total_rate = DoubleDouble::ZERO;
+            for (ireaction, ((rate, _), cumrate)) in
+                self.reactions.iter().zip(cumrates.iter_mut()).enumerate()
+            {
+                let rate = rate.rate(&self.species) * scale(&self.scales, ireaction);
+                total_rate = total_rate.add(DoubleDouble(rate, 0.));
+                *cumrate = total_rate;
+            }
+            // we don't want to use partial_cmp, for performance
+            #[allow(clippy::neg_cmp_op_on_partial_ord)]
+            if !(0. < total_rate.0) {
+                self.t = tmax;
+                return;
+            }
+            t = t.add(DoubleDouble(self.rng.sample(Exp1), 0.).div(total_rate));
+            if DoubleDouble(tmax, 0.).lt(t) {
+                self.t = tmax;
+                return;
+            }
+            self.t = t.0;
+            // a uniform number in [0, 1) with 106 random bits
+            let uniform = DoubleDouble(self.rng.gen(), 0.)
+                .add(DoubleDouble(self.rng.gen::<f64>() * f64::EPSILON / 2., 0.));
+            let chosen_rate = total_rate.mul(uniform);
+            // rounding errors can only select a reaction of zero rate at
+            // the end, in which case the last one of positive rate fires
+            let ireaction = cumrates
+                .iter()
+                .position(|cumrate| chosen_rate.lt(*cumrate))
+                .unwrap_or_else(|| cumrates.iter().position(|c| *c == total_rate).unwrap());
+            self.reactions[ireaction].1.affect(&mut self.species);
+            self.records.count(
+                self.t,
+                ireaction,
+                1,
+                &self.reactions,
+                &self.scales,
+                &self.species,
+            );
+        }
+    }
+    /// Draws the firing threshold of the reaction of index `ireaction`.
+    fn draw_threshold(&mut self, ireaction: usize) -> f64 {
+        match self.waiting_times.get(ireaction) {
+            Some(Some(waiting_time)) => waiting_time.draw(&mut self.rng),
+            _ => self.rng.sample(Exp1),
+        }
+    }
+    /// Simulates the problem until `tmax` with the modified next
+    /// reaction method, for reactions with custom waiting times and for
+    /// [`Method::NextReaction`].
+    ///
+    /// The next firing times are kept in a priority queue, and after
+    /// each reaction only the firing times of the reactions whose rates
+    /// depend on the modified species are updated.
+    fn advance_until_nonmarkovian(&mut self, tmax: f64) {
+        let nb_reactions = self.reactions.len();
+        if self.clocks.len() != nb_reactions {
+            self.clocks = (0..nb_reactions)
+                .map(|ireaction| (0., self.draw_threshold(ireaction)))
+                .collect();
+        }
+        let dependents = make_dependents(&self.reactions, self.species.len());
+        let mut rates = vec![f64::NAN; nb_reactions];
+        make_rates(&self.reactions, &self.scales, &self.species, &mut rates);
+        // time at which the integral of each clock was last updated
+        let mut updated = vec![self.t; nb_reactions];
+        let mut next_times = vec![f64::INFINITY; nb_reactions];
+        let mut queue = BinaryHeap::with_capacity(nb_reactions);
+        for (ireaction, (&rate, &(integral, threshold))) in
+            rates.iter().zip(self.clocks.iter()).enumerate()
+        {
+            let next_time = self.t + (threshold - integral) / rate;
+            // this excludes NaN rates
+            if next_time < f64::INFINITY {
+                next_times[ireaction] = next_time.max(self.t);
+                queue.push(Scheduled(next_times[ireaction], ireaction));
+            }
+        }
+        while let Some(Scheduled(t, ireaction)) = queue.pop() {
+            if self.records.stopped() {
+                break;
+            }
+            if t != next_times[ireaction] {
+                // outdated entry
+                continue;
+            }
+            if t > tmax {
+                break;
+            }
+            self.t = t;
+            self.reactions[ireaction].1.affect(&mut self.species);
+            self.records.count(
+                self.t,
+                ireaction,
+                1,
+                &self.reactions,
+                &self.scales,
+                &self.species,
+            );
+            self.clocks[ireaction] = (0., self.draw_threshold(ireaction));
+            updated[ireaction] = t;
+            for &idependent in &dependents[ireaction] {
+                let clock = &mut self.clocks[idependent];
+                clock.0 += rates[idependent] * (t - updated[idependent]);
+                updated[idependent] = t;
+                rates[idependent] = self.reactions[idependent].0.rate(&self.species)
+                    * scale(&self.scales, idependent);
+                let next_time = t + (clock.1 - clock.0) / rates[idependent];
+                next_times[idependent] = f64::INFINITY;
+                if next_time < f64::INFINITY {
+                    next_times[idependent] = next_time.max(t);
+                    queue.push(Scheduled(next_times[idependent], idependent));
+                }
+            }
+        }
+        // a stopped simulation stays at the last reaction
+        let end = if self.records.stopped() { self.t } else { tmax };
+        for ((clock, rate), updated) in self.clocks.iter_mut().zip(rates).zip(updated) {
+            if rate > 0. {
+                clock.0 += rate * (end - updated);
+            }
+        }
+        self.t = end;
+    }
+}
+
+/// Statistics of a reaction, returned by [`Gillespie::reaction_stats`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReactionStats {
+    /// Number of firings.
+    pub firings: u64,
+    /// Propensity averaged over time.
+    pub mean_propensity: f64,
+}
+
+/// Limits of the work of each call to [`Gillespie::advance_until`], set
+/// by [`Gillespie::set_limits`].
+///
+/// ```
+/// use std::time::Duration;
+/// use rebop::gillespie::Limits;
+/// let limits = Limits {
+///     max_reactions: Some(1_000_000),
+///     max_wall_time: Some(Duration::from_secs(10)),
+/// };
+/// assert_eq!(Limits::default().max_reactions, None);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum number of reactions that fire.
+    pub max_reactions: Option<u64>,
+    /// Maximum wall-clock time, checked every 1024 reactions.
+    pub max_wall_time: Option<Duration>,
+}
+
+/// Number of reactions between two checks of the wall-clock time of
+/// [`Limits::max_wall_time`].
+const WALL_TIME_CHECK: u32 = 1024;
+
+/// Limit of [`Limits`] that stopped a simulation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    /// [`Limits::max_reactions`] reactions fired.
+    Reactions,
+    /// The simulation took longer than [`Limits::max_wall_time`].
+    WallTime,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Reactions => write!(f, "maximum number of reactions"),
+            Limit::WallTime => write!(f, "maximum wall-clock time"),
+        }
+    }
+}
+
+/// Remaining work of the current call to [`Gillespie::advance_until`].
+#[derive(Clone, Debug)]
+struct Budget {
+    /// Number of reactions that can still fire.
+    reactions: u64,
+    /// Instant after which the simulation stops.
+    deadline: Option<Instant>,
+    /// Number of reactions until the next check of the deadline, which
+    /// is checked every [`WALL_TIME_CHECK`] reactions, or at the end of
+    /// the steps that fire more reactions.
+    countdown: u32,
+    /// Limit that was reached, if any.
+    reached: Option<Limit>,
+}
+
+impl Budget {
+    fn new(limits: &Limits) -> Self {
+        let reactions = limits.max_reactions.unwrap_or(u64::MAX);
+        Budget {
+            reactions,
+            deadline: limits
+                .max_wall_time
+                .map(|duration| Instant::now() + duration),
+            countdown: WALL_TIME_CHECK,
+            reached: (reactions == 0).then_some(Limit::Reactions),
+        }
+    }
+    /// Spends `n` reactions.
+    fn spend(&mut self, n: u64) {
+        if n == 0 {
+            return;
+        }
+        self.reactions = self.reactions.saturating_sub(n);
+        if self.reactions == 0 {
+            self.reached = Some(Limit::Reactions);
+        }
+        if let Some(deadline) = self.deadline {
+            let n = u32::try_from(n).unwrap_or(u32::MAX);
+            self.countdown = self.countdown.saturating_sub(n);
+            if self.countdown == 0 {
+                self.countdown = WALL_TIME_CHECK;
+                if Instant::now() > deadline {
+                    self.reached = Some(Limit::WallTime);
+                }
+            }
+        }
+    }
+}
+
+/// Buffers of the simulations, reused by the successive calls to
+/// [`Gillespie::advance_until`] of a problem, and between problems with
+/// [`Gillespie::with_workspace`].
+///
+/// The buffers hold no state between two calls, so that cloning a
+/// workspace returns an empty one.
+#[derive(Debug, Default)]
+pub struct Workspace {
+    /// Rates or cumulative rates of the reactions.
+    rates: Vec<f64>,
+    /// Amounts of species as floating-point numbers, see [`LmaRates`].
+    amounts: Vec<f64>,
+    /// Numbers of firings of the reactions in a step of R-leaping.
+    counts: Vec<u64>,
+}
+
+impl Clone for Workspace {
+    fn clone(&self) -> Self {
+        Workspace::default()
+    }
+}
+
+/// Returns `buffer` filled with `len` copies of `value`, without
+/// allocating if it is large enough.
+fn fill<T: Clone>(buffer: &mut Vec<T>, len: usize, value: T) -> &mut [T] {
+    buffer.clear();
+    buffer.resize(len, value);
+    buffer
+}
+
+/// What is recorded of the reactions that fire, each part being
+/// enabled separately.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Records {
+    /// Number of firings of each reaction, if they are counted.
+    firings: Option<Vec<u64>>,
+    /// Reactions that fired, if they are recorded.
+    log: Option<EventLog>,
+    /// Firings and integrated propensities of each reaction, if they
+    /// are tracked.
+    propensities: Option<Propensities>,
+    /// Remaining work of the current simulation, if it is limited.
+    budget: Option<Budget>,
+}
+
+impl Records {
+    /// Adds `n` firings of the reaction of index `ireaction` at `t`,
+    /// after which the problem is in the state `species`.
+    #[inline]
+    pub(crate) fn count(
+        &mut self,
+        t: f64,
+        ireaction: usize,
+        n: u64,
+        reactions: &[(Rate, Jump)],
+        scales: &[f64],
+        species: &[isize],
+    ) {
+        if let Some(firings) = &mut self.firings {
+            firings[ireaction] += n;
+        }
+        if let Some(log) = &mut self.log {
+            for _ in 0..n {
+                log.push(t, ireaction);
+            }
+        }
+        if let Some(propensities) = &mut self.propensities {
+            propensities.firings[ireaction] += n;
+            propensities.update(t, reactions, scales, species);
+        }
+        if let Some(budget) = &mut self.budget {
+            budget.spend(n);
+        }
+    }
+    /// Whether a limit of the current simulation was reached, after
+    /// which it must stop at the last reaction.
+    #[inline]
+    pub(crate) fn stopped(&self) -> bool {
+        self.budget
+            .as_ref()
+            .is_some_and(|budget| budget.reached.is_some())
+    }
+}
+
+/// Firings and propensities of the reactions integrated over time, for
+/// [`Gillespie::reaction_stats`].
+#[derive(Clone, Debug)]
+struct Propensities {
+    /// Time at which the tracking started.
+    start: f64,
+    /// Time of the last change of the propensities.
+    last: f64,
+    /// Propensities since `last`.
+    current: Vec<f64>,
+    /// Integrals of the propensities from `start` to `last`.
+    integrals: Vec<f64>,
+    firings: Vec<u64>,
+}
+
+impl Propensities {
+    /// Starts the tracking at `t`, in the state `species`.
+    fn new(t: f64, reactions: &[(Rate, Jump)], scales: &[f64], species: &[isize]) -> Self {
+        let mut propensities = Propensities {
+            start: t,
+            last: t,
+            current: Vec::new(),
+            integrals: Vec::new(),
+            firings: Vec::new(),
+        };
+        propensities.update(t, reactions, scales, species);
+        propensities
+    }
+    /// Integrates the propensities until `t`, from when they changed the
+    /// last time, and computes the new ones in the state `species`.
+    fn update(&mut self, t: f64, reactions: &[(Rate, Jump)], scales: &[f64], species: &[isize]) {
+        let duration = t - self.last;
+        for (integral, rate) in self.integrals.iter_mut().zip(&self.current) {
+            *integral += rate * duration;
+        }
+        self.last = t;
+        let nb_reactions = reactions.len();
+        self.current.resize(nb_reactions, 0.);
+        self.integrals.resize(nb_reactions, 0.);
+        self.firings.resize(nb_reactions, 0);
+        make_rates(reactions, scales, species, &mut self.current);
+    }
+}
+
+/// Iterator over the reactions of a problem, returned by
+/// [`Gillespie::events`].
+pub struct Events<'a, R = SmallRng> {
+    problem: &'a mut Gillespie<R>,
+}
+
+impl<R: Rng + SeedableRng> Iterator for Events<'_, R> {
+    /// Time, index of the reaction and amounts of species after it.
+    type Item = (f64, usize, Vec<isize>);
+    fn next(&mut self) -> Option<Self::Item> {
+        let problem = &mut *self.problem;
+        let mut last = None;
+        let mut workspace = std::mem::take(&mut problem.workspace);
+        problem.advance_until_direct(f64::INFINITY, 1, &mut workspace, |ireaction| {
+            last = Some(ireaction)
+        });
+        problem.workspace = workspace;
+        Some((problem.t, last?, problem.species.clone()))
+    }
+}
+
+/// Firing time of a reaction in the priority queue of
+/// [`Gillespie::advance_until_nonmarkovian`], or time when a delayed
+/// reaction takes effect, ordered so that the earliest time comes first.
+#[derive(Clone, Debug)]
+struct Scheduled(f64, usize);
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.total_cmp(&self.0).then(other.1.cmp(&self.1))
+    }
+}
+
+/// Reusable driver for many short simulations of the same problem
+/// from the same initial state.
+///
+/// The propensities of the initial state are computed once, for the
+/// first reaction of each run, and the buffers are allocated once, so
+/// that the per-run overhead is minimal when simulating millions of
+/// short runs.  Each run is limited by
+/// [`set_limits`](Gillespie::set_limits) like a call to
+/// [`advance_until`](Gillespie::advance_until), and the firings, event
+/// log and reaction statistics of the problem start over at each run.
+///
+/// ```
+/// use rebop::gillespie::{Gillespie, Rate, Stepper};
+/// let mut sir = Gillespie::new_with_seed([999, 1, 0], 0);
+/// sir.add_reaction(Rate::lma(1e-4, [1, 1, 0]), [-1, 1, 0]);
+/// sir.add_reaction(Rate::lma(0.01, [0, 1, 0]), [0, -1, 1]);
+/// let mut stepper = Stepper::new(sir);
+/// let mut recovered = 0;
+/// stepper.run_many(100, 10., |species| recovered += species[2]);
+/// assert!(recovered > 0);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Stepper {
+    problem: Gillespie,
+    initial_species: Vec<isize>,
+    initial_t: f64,
+    initial_cumrates: Vec<f64>,
+    initial_total_rate: f64,
+}
+
+impl Stepper {
+    /// Creates a stepper whose runs all start from the current state
+    /// of `problem`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `problem` is not simulated with the direct method: if
+    /// it has another method, a custom algorithm, custom waiting times,
+    /// delays or extended precision, which are not supported.
+    pub fn new(problem: Gillespie) -> Self {
+        problem.assert_direct("Stepper only supports the direct method");
+        let mut initial_cumrates = vec![f64::NAN; problem.nb_reactions()];
+        let initial_total_rate = make_cumrates(
+            &problem.reactions,
+            &problem.scales,
+            &problem.species,
+            &mut initial_cumrates,
+        );
+        Stepper {
+            initial_species: problem.species.clone(),
+            initial_t: problem.t,
+            initial_cumrates,
+            initial_total_rate,
+            problem,
+        }
+    }
+    /// Seeds the random number generator.
+    pub fn seed(&mut self, seed: u64) {
+        self.problem.seed(seed);
+    }
+    /// Returns the problem in its state at the end of the last run.
+    pub fn problem(&self) -> &Gillespie {
+        &self.problem
+    }
+    /// Simulates one run from the initial state until `tmax` and
+    /// returns the final amounts of species.
+    pub fn run(&mut self, tmax: f64) -> &[isize] {
+        let problem = &mut self.problem;
+        problem.species.copy_from_slice(&self.initial_species);
+        problem.t = self.initial_t;
+        problem.reset_firings();
+        if problem.records.log.is_some() {
+            problem.set_record_events(true);
+        }
+        if problem.records.propensities.is_some() {
+            problem.set_reaction_stats(true);
+        }
+        let limited = problem.limits != Limits::default();
+        problem.records.budget = limited.then(|| Budget::new(&problem.limits));
+        if self.first_step(tmax) {
+            let problem = &mut self.problem;
+            let mut workspace = std::mem::take(&mut problem.workspace);
+            problem.advance_until_direct(tmax, u64::MAX, &mut workspace, |_| ());
+            problem.workspace = workspace;
+        }
+        let problem = &mut self.problem;
+        problem.limit_reached = problem
+            .records
+            .budget
+            .take()
+            .and_then(|budget| budget.reached);
+        &problem.species
+    }
+    /// Fires the first reaction of a run with the propensities of the
+    /// initial state, and returns whether the run goes on.
+    fn first_step(&mut self, tmax: f64) -> bool {
+        let problem = &mut self.problem;
+        if problem.records.stopped() {
+            return false;
+        }
+        let total_rate = self.initial_total_rate;
+        // we don't want to use partial_cmp, for performance
+        #[allow(clippy::neg_cmp_op_on_partial_ord)]
+        if !(0. < total_rate) {
+            problem.t = tmax;
+            return false;
+        }
+        let t = problem.t + problem.rng.sample::<f64, _>(Exp1) / total_rate;
+        if t > tmax {
+            problem.t = tmax;
+            return false;
+        }
+        problem.t = t;
+        let chosen_rate = total_rate * problem.rng.gen::<f64>();
+        let ireaction = choose_cumrate_sum(chosen_rate, &self.initial_cumrates);
+        // here we have ireaction < self.reactions.len() because chosen_rate < total_rate
+        let reaction = unsafe { problem.reactions.get_unchecked(ireaction) };
+        reaction.1.affect(&mut problem.species);
+        problem.records.count(
+            problem.t,
+            ireaction,
+            1,
+            &problem.reactions,
+            &problem.scales,
+            &problem.species,
+        );
+        true
+    }
+    /// Simulates `n` runs from the initial state until `tmax`, calling
+    /// `f` with the final amounts of species of each run.
+    pub fn run_many<F: FnMut(&[isize])>(&mut self, n: usize, tmax: f64, mut f: F) {
+        for _ in 0..n {
+            f(self.run(tmax));
+        }
+    }
+}
+
+/// Whether `rate` depends on the species of index `index`.
+pub(crate) fn reads(rate: &Rate, index: usize) -> bool {
+    fn expr_reads(expr: &Expr, index: usize) -> bool {
+        match expr {
+            Expr::Concentration(i) => *i == index,
+            _ => expr.operands().into_iter().any(|a| expr_reads(a, index)),
+        }
+    }
+    match rate {
+        Rate::LMA(_, reactants) | Rate::LMAParam(_, reactants) => {
+            reactants.get(index).is_some_and(|&e| e > 0)
+        }
+        Rate::LMASparse(_, sparse) => sparse.iter().any(|&(i, _)| i as usize == index),
+        Rate::Expr(expr) => expr_reads(expr, index),
+        Rate::Compiled(program) => program.species.binary_search(&index).is_ok(),
+    }
+}
 
-        // let ireaction = choose_rate_sum(chosen_rate, &rates);
-        // let ireaction = choose_rate_for(chosen_rate, &rates);
-        let ireaction = choose_cumrate_sum(chosen_rate, &rates);
-        // let ireaction = choose_cumrate_for(chosen_rate, &rates);
-        // let ireaction = choose_cumrate_takewhile(chosen_rate, &rates);
-        // here we have ireaction < self.reactions.len() because chosen_rate < total_rate
-        let reaction = unsafe { self.reactions.get_unchecked(ireaction) };
+/// Stationary distribution of a fast reversible pair of reactions, over
+/// the states reached from the current one by firing either reaction.
+struct FastEquilibrium<'a> {
+    /// Changes of the species when the forward reaction fires.
+    changes: &'a [(usize, isize)],
+    /// Number of forward firings from the current state to the first
+    /// state, which is negative for backward firings.
+    first: isize,
+    /// Probabilities of the successive states.
+    probabilities: Vec<f64>,
+}
 
-        reaction.1.affect(&mut self.species);
-    }
-    /// Simulates the problem until `tmax`.
-    ///
-    /// ```
-    /// use rebop::gillespie::{Gillespie, Rate};
-    /// let mut dimers = Gillespie::new([1, 0, 0, 0]);
-    /// //                              [G, M, P, D]
-    /// dimers.add_reaction(Rate::lma(25., [1, 0, 0, 0]), [0, 1, 0, 0]);
-    /// dimers.add_reaction(Rate::lma(1000., [0, 1, 0, 0]), [0, 0, 1, 0]);
-    /// dimers.add_reaction(Rate::lma(0.001, [0, 0, 2, 0]), [0, 0, -2, 1]);
-    /// dimers.add_reaction(Rate::lma(0.1, [0, 1, 0, 0]), [0, -1, 0, 0]);
-    /// dimers.add_reaction(Rate::lma(1., [0, 0, 1, 0]), [0, 0, -1, 0]);
-    /// assert_eq!(dimers.get_time(), 0.);
-    /// assert_eq!(dimers.get_species(3), 0);
-    /// dimers.advance_until(1.);
-    /// assert_eq!(dimers.get_time(), 1.);
-    /// assert!(dimers.get_species(3) > 0);
-    /// ```
-    pub fn advance_until(&mut self, tmax: f64) {
-        let mut rates = vec![f64::NAN; self.reactions.len()];
+impl<'a> FastEquilibrium<'a> {
+    /// States less likely than the most likely one by more than this
+    /// factor, in logarithm, are neglected.
+    const LOG_CUTOFF: f64 = 40.;
+    /// Computes the distribution from the rates of the `forward` and
+    /// `backward` reactions, with their factors, whose firings go through
+    /// a chain of states whose stationary probabilities are in the ratio
+    /// of the rates.
+    fn new(
+        (forward, forward_scale): (&Rate, f64),
+        (backward, backward_scale): (&Rate, f64),
+        changes: &'a [(usize, isize)],
+        species: &[isize],
+    ) -> Self {
+        let mut equilibrium = FastEquilibrium {
+            changes,
+            first: 0,
+            probabilities: Vec::new(),
+        };
+        let mut state = species.to_vec();
+        // logarithms of the unnormalized probabilities
+        let mut above = vec![0.];
+        let mut max = 0_f64;
         loop {
-            //let total_rate = make_rates(&self.reactions, &self.species, &mut rates);
-            let total_rate = make_cumrates(&self.reactions, &self.species, &mut rates);
-
-            // we don't want to use partial_cmp, for performance
-            #[allow(clippy::neg_cmp_op_on_partial_ord)]
-            if !(0. < total_rate) {
-                self.t = tmax;
-                return;
+            let rate_in = forward.rate(&state) * forward_scale;
+            equilibrium.shift(&mut state, 1);
+            let rate_out = backward.rate(&state) * backward_scale;
+            if !(rate_in > 0. && rate_out > 0.) {
+                break;
             }
-            self.t += self.rng.sample::<f64, _>(Exp1) / total_rate;
-            if self.t > tmax {
-                self.t = tmax;
-                return;
+            let log = above.last().unwrap() + rate_in.ln() - rate_out.ln();
+            max = max.max(log);
+            if log < max - Self::LOG_CUTOFF {
+                break;
             }
-            let chosen_rate = total_rate * self.rng.gen::<f64>();
-
-            //let ireaction = choose_rate_sum(chosen_rate, &rates);
-            //let ireaction = choose_rate_for(chosen_rate, &rates);
-            let ireaction = choose_cumrate_sum(chosen_rate, &rates);
-            //let ireaction = choose_cumrate_for(chosen_rate, &rates);
-            //let ireaction = choose_cumrate_takewhile(chosen_rate, &rates);
-            // here we have ireaction < self.reactions.len() because chosen_rate < total_rate
-            let reaction = unsafe { self.reactions.get_unchecked(ireaction) };
+            above.push(log);
+        }
+        state.copy_from_slice(species);
+        let mut below = Vec::new();
+        loop {
+            let rate_in = backward.rate(&state) * backward_scale;
+            equilibrium.shift(&mut state, -1);
+            let rate_out = forward.rate(&state) * forward_scale;
+            if !(rate_in > 0. && rate_out > 0.) {
+                break;
+            }
+            let log = below.last().unwrap_or(&0.) + rate_in.ln() - rate_out.ln();
+            max = max.max(log);
+            if log < max - Self::LOG_CUTOFF {
+                break;
+            }
+            below.push(log);
+        }
+        equilibrium.first = -(below.len() as isize);
+        equilibrium.probabilities = below
+            .iter()
+            .rev()
+            .chain(&above)
+            .map(|log| (log - max).exp())
+            .collect();
+        let total: f64 = equilibrium.probabilities.iter().sum();
+        equilibrium
+            .probabilities
+            .iter_mut()
+            .for_each(|p| *p /= total);
+        equilibrium
+    }
+    /// Fires the forward reaction `nb_firings` times, or the backward one
+    /// if negative.
+    fn shift(&self, species: &mut [isize], nb_firings: isize) {
+        for &(index, difference) in self.changes {
+            species[index] += nb_firings * difference;
+        }
+    }
+    /// Returns the number of forward firings from the current state to
+    /// a state drawn with the uniform number `uniform` in `[0, 1)`.
+    fn sample(&self, mut uniform: f64) -> isize {
+        let k = self
+            .probabilities
+            .iter()
+            .position(|&p| {
+                uniform -= p;
+                uniform < 0.
+            })
+            .unwrap_or(self.probabilities.len() - 1);
+        self.first + k as isize
+    }
+}
 
-            reaction.1.affect(&mut self.species);
+/// Calls `f` with the species in each joint state of the fast pairs
+/// `equilibria`, and its probability times `probability`.  When `f`
+/// returns `false`, stops and leaves the species in that state.
+fn visit_fast_states(
+    species: &mut [isize],
+    equilibria: &[&FastEquilibrium],
+    probability: f64,
+    f: &mut dyn FnMut(&[isize], f64) -> bool,
+) -> bool {
+    let Some((equilibrium, others)) = equilibria.split_first() else {
+        return f(species, probability);
+    };
+    equilibrium.shift(species, equilibrium.first);
+    for (k, &p) in equilibrium.probabilities.iter().enumerate() {
+        if k > 0 {
+            equilibrium.shift(species, 1);
+        }
+        if !visit_fast_states(species, others, probability * p, f) {
+            return false;
         }
     }
+    let last = equilibrium.first + equilibrium.probabilities.len() as isize - 1;
+    equilibrium.shift(species, -last);
+    true
+}
+
+/// Returns the value of the observable of weights `weights` in the
+/// state `species`.
+fn observe(weights: &[f64], species: &[isize]) -> f64 {
+    weights
+        .iter()
+        .zip(species)
+        .map(|(weight, &amount)| weight * amount as f64)
+        .sum()
+}
+
+/// Returns the factor of the rate of the reaction of index `ireaction`.
+pub(crate) fn scale(scales: &[f64], ireaction: usize) -> f64 {
+    if scales.is_empty() {
+        1.
+    } else {
+        scales[ireaction]
+    }
 }
 
-fn make_rates(reactions: &[(Rate, Jump)], species: &[isize], rates: &mut [f64]) -> f64 {
+fn make_rates(
+    reactions: &[(Rate, Jump)],
+    scales: &[f64],
+    species: &[isize],
+    rates: &mut [f64],
+) -> f64 {
     let mut total_rate = 0.0;
-    for ((rate, _), num_rate) in reactions.iter().zip(rates.iter_mut()) {
-        *num_rate = rate.rate(species);
-        total_rate += *num_rate;
+    if scales.is_empty() {
+        for ((rate, _), num_rate) in reactions.iter().zip(rates.iter_mut()) {
+            *num_rate = rate.rate(species);
+            total_rate += *num_rate;
+        }
+    } else {
+        for (((rate, _), scale), num_rate) in reactions.iter().zip(scales).zip(rates.iter_mut()) {
+            *num_rate = rate.rate(species) * scale;
+            total_rate += *num_rate;
+        }
     }
     total_rate
 }
 
-fn make_cumrates(reactions: &[(Rate, Jump)], species: &[isize], cum_rates: &mut [f64]) -> f64 {
+/// Returns, for each reaction, the reactions whose rates depend on a
+/// species modified by it, including itself.
+fn make_dependents(reactions: &[(Rate, Jump)], nb_species: usize) -> Vec<Vec<usize>> {
+    // reactions whose rate depends on each species
+    let mut readers = vec![Vec::new(); nb_species];
+    for (ireaction, (rate, _)) in reactions.iter().enumerate() {
+        match rate {
+            Rate::LMA(_, reactants) | Rate::LMAParam(_, reactants) => reactants
+                .iter()
+                .enumerate()
+                .filter(|&(_, &e)| e > 0)
+                .for_each(|(index, _)| readers[index].push(ireaction)),
+            Rate::LMASparse(_, sparse) => sparse
+                .iter()
+                .for_each(|&(index, _)| readers[index as usize].push(ireaction)),
+            Rate::Expr(_) => readers.iter_mut().for_each(|r| r.push(ireaction)),
+            Rate::Compiled(program) => program
+                .species
+                .iter()
+                .for_each(|&index| readers[index].push(ireaction)),
+        }
+    }
+    reactions
+        .iter()
+        .enumerate()
+        .map(|(ireaction, (_, jump))| {
+            let mut dependents = vec![ireaction];
+            match jump {
+                Jump::Flat(differences) => differences
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &d)| d != 0)
+                    .for_each(|(index, _)| dependents.extend(&readers[index])),
+                Jump::Sparse(differences) => differences
+                    .iter()
+                    .for_each(|&(index, _)| dependents.extend(&readers[index])),
+            }
+            dependents.sort_unstable();
+            dependents.dedup();
+            dependents
+        })
+        .collect()
+}
+
+/// Returns the quantile `u` of the Poisson distribution of mean `mean`.
+fn poisson_quantile(mean: f64, u: f64) -> u64 {
+    let mut k = 0;
+    let mut probability = (-mean).exp();
+    let mut cdf = probability;
+    // the rounding errors may keep the cdf below u in the tail
+    while cdf <= u && (probability > 0. || (k as f64) < mean) {
+        k += 1;
+        probability *= mean / k as f64;
+        cdf += probability;
+    }
+    k
+}
+
+pub(crate) fn make_cumrates(
+    reactions: &[(Rate, Jump)],
+    scales: &[f64],
+    species: &[isize],
+    cum_rates: &mut [f64],
+) -> f64 {
     let mut total_rate = 0.0;
-    for ((rate, _), cum_rate) in reactions.iter().zip(cum_rates.iter_mut()) {
-        *cum_rate = total_rate + rate.rate(species);
-        total_rate = *cum_rate;
+    if scales.is_empty() {
+        for ((rate, _), cum_rate) in reactions.iter().zip(cum_rates.iter_mut()) {
+            *cum_rate = total_rate + rate.rate(species);
+            total_rate = *cum_rate;
+        }
+    } else {
+        for (((rate, _), scale), cum_rate) in reactions.iter().zip(scales).zip(cum_rates.iter_mut())
+        {
+            *cum_rate = total_rate + rate.rate(species) * scale;
+            total_rate = *cum_rate;
+        }
     }
     total_rate
 }
 
+/// Unevaluated sum `hi + lo` of two floating-point numbers with
+/// `|lo| <= ulp(hi) / 2`, which represents a real number with about
+/// 106 bits of precision.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct DoubleDouble(f64, f64);
+
+impl DoubleDouble {
+    const ZERO: Self = DoubleDouble(0., 0.);
+    /// Exact sum `a + b` of two floating-point numbers.
+    fn two_sum(a: f64, b: f64) -> Self {
+        let s = a + b;
+        let bb = s - a;
+        DoubleDouble(s, (a - (s - bb)) + (b - bb))
+    }
+    fn add(self, other: Self) -> Self {
+        let DoubleDouble(s, e) = Self::two_sum(self.0, other.0);
+        let DoubleDouble(s, e2) = Self::two_sum(s, e + self.1 + other.1);
+        DoubleDouble(s, e2)
+    }
+    fn mul(self, other: Self) -> Self {
+        let p = self.0 * other.0;
+        let e = self.0.mul_add(other.0, -p);
+        Self::two_sum(p, e + self.0 * other.1 + self.1 * other.0)
+    }
+    fn div(self, other: Self) -> Self {
+        let q = self.0 / other.0;
+        let remainder = self.add(DoubleDouble(-q, 0.).mul(other));
+        Self::two_sum(q, remainder.0 / other.0)
+    }
+    fn lt(self, other: Self) -> bool {
+        self.0 < other.0 || (self.0 == other.0 && self.1 < other.1)
+    }
+}
+
+#[allow(dead_code)]
 fn choose_rate_for(mut chosen_rate: f64, rates: &[f64]) -> usize {
     let mut ireaction = rates.len() - 1;
     for (ir, &rate) in rates.iter().enumerate() {
@@ -325,6 +4292,7 @@ fn choose_rate_for(mut chosen_rate: f64, rates: &[f64]) -> usize {
     ireaction
 }
 
+#[allow(dead_code)]
 fn choose_cumrate_for(chosen_rate: f64, cumrates: &[f64]) -> usize {
     let mut ireaction = cumrates.len() - 1;
     for (ir, &cumrate) in cumrates.iter().enumerate() {
@@ -336,6 +4304,7 @@ fn choose_cumrate_for(chosen_rate: f64, cumrates: &[f64]) -> usize {
     ireaction
 }
 
+#[allow(dead_code)]
 fn choose_rate_sum(chosen_rate: f64, rates: &[f64]) -> usize {
     rates
         .iter()
@@ -347,12 +4316,10 @@ fn choose_rate_sum(chosen_rate: f64, rates: &[f64]) -> usize {
 }
 
 fn choose_cumrate_sum(chosen_rate: f64, cumrates: &[f64]) -> usize {
-    cumrates
-        .iter()
-        .map(|&cum| if cum < chosen_rate { 1 } else { 0 })
-        .sum()
+    simd::count_below(cumrates, chosen_rate)
 }
 
+#[allow(dead_code)]
 fn choose_cumrate_takewhile(chosen_rate: f64, cumrates: &[f64]) -> usize {
     cumrates
         .iter()
@@ -362,7 +4329,14 @@ fn choose_cumrate_takewhile(chosen_rate: f64, cumrates: &[f64]) -> usize {
 
 #[cfg(test)]
 mod tests {
-    use crate::gillespie::{Gillespie, Rate};
+    use std::time::Duration;
+
+    use crate::events::EventLog;
+    use crate::gillespie::{
+        reads, Budget, Deterministic, DoubleDouble, Expr, Gillespie, Jump, Laplace, Limit, Limits,
+        Method, Rate, Stepper, Workspace, WALL_TIME_CHECK,
+    };
+    use crate::Error;
     #[test]
     fn sir() {
         let mut sir = Gillespie::new([9999, 1, 0]);
@@ -375,6 +4349,582 @@ mod tests {
         );
     }
     #[test]
+    fn stepper() {
+        let mut sir = Gillespie::new_with_seed([999, 1, 0], 0);
+        sir.add_reaction(Rate::lma(1e-4, [1, 1, 0]), [-1, 1, 0]);
+        sir.add_reaction(Rate::lma(0.01, [0, 1, 0]), [0, -1, 1]);
+        let mut stepper = Stepper::new(sir.clone());
+        let mut finals = Vec::new();
+        stepper.run_many(3, 50., |species| finals.push(species.to_vec()));
+        assert_eq!(finals.len(), 3);
+        assert!(finals.iter().all(|s| s.iter().sum::<isize>() == 1000));
+        // a run of the stepper is the same as a run of the problem
+        stepper.seed(1);
+        sir.seed(1);
+        sir.advance_until(50.);
+        let expected: Vec<isize> = (0..3).map(|s| sir.get_species(s)).collect();
+        assert_eq!(stepper.run(50.), expected);
+        assert_eq!(stepper.problem().get_time(), 50.);
+    }
+    #[test]
+    fn stepper_records() {
+        let mut p = Gillespie::new_with_seed([0], 0);
+        p.add_reaction(Rate::lma(10., [0]), [1]);
+        p.set_count_firings(true);
+        p.set_record_events(true);
+        p.set_limits(Limits {
+            max_reactions: Some(5),
+            ..Limits::default()
+        });
+        let mut stepper = Stepper::new(p);
+        for _ in 0..3 {
+            // each run is limited, and its records start over
+            assert_eq!(stepper.run(100.), [5]);
+            let problem = stepper.problem();
+            assert_eq!(problem.limit_reached(), Some(Limit::Reactions));
+            assert!(problem.get_time() < 100.);
+            assert_eq!(problem.firings(), Some(&[5][..]));
+            assert_eq!(problem.event_log().unwrap().len(), 5);
+        }
+    }
+    #[test]
+    fn waiting_times() {
+        use rand_distr::{Exp1, Gamma};
+        // clocks are kept between successive calls
+        let mut p = Gillespie::new([0]);
+        p.add_reaction(Rate::lma(2., [0]), [1]);
+        p.set_waiting_time(0, Gamma::new(1000., 1e-3).unwrap());
+        for i in 1..=100 {
+            p.advance_until(i as f64 * 0.1);
+        }
+        assert_eq!(p.get_time(), 10.);
+        assert!((19..=20).contains(&p.get_species(0)));
+        // the exponential distribution gives back the Markovian dynamics
+        let mut sir = Gillespie::new_with_seed([999, 1, 0], 0);
+        sir.add_reaction(Rate::lma(1e-4, [1, 1, 0]), [-1, 1, 0]);
+        sir.add_reaction(Rate::lma(0.01, [0, 1, 0]), [0, -1, 1]);
+        sir.set_waiting_time(1, Exp1);
+        sir.advance_until(250.);
+        assert_eq!(sir.get_time(), 250.);
+        assert_eq!(
+            sir.get_species(0) + sir.get_species(1) + sir.get_species(2),
+            1000
+        );
+    }
+    #[test]
+    fn renewal_distributions() {
+        use rand_distr::Uniform;
+        // A is produced every second, B every 0.7 / A time units
+        let mut p = Gillespie::new_with_seed([0, 0], 0);
+        p.add_reaction(Rate::lma(1., [0, 0]), [1, 0]);
+        p.add_reaction(Rate::lma(1., [1, 0]), [0, 1]);
+        p.set_waiting_time(0, Deterministic(1.));
+        p.set_waiting_time(1, Deterministic(0.7));
+        p.advance_until(1.5);
+        assert_eq!((p.get_species(0), p.get_species(1)), (1, 0));
+        p.advance_until(2.5);
+        // B is produced at 1.7 and 2.2
+        assert_eq!((p.get_species(0), p.get_species(1)), (2, 2));
+        // mean waiting time of ln(2)
+        let mut p = Gillespie::new_with_seed([0], 0);
+        p.add_reaction(Rate::lma(1., [0]), [1]);
+        p.set_waiting_time(0, Laplace(Uniform::new(1., 2.)));
+        p.advance_until(1000.);
+        assert!((1300..1600).contains(&p.get_species(0)));
+    }
+    #[test]
+    fn other_rng() {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+        let mut sir = Gillespie::with_rng([999, 1, 0], StdRng::seed_from_u64(0));
+        sir.add_reaction(Rate::lma(1e-4, [1, 1, 0]), [-1, 1, 0]);
+        sir.add_reaction(Rate::lma(0.01, [0, 1, 0]), [0, -1, 1]);
+        let mut other = sir.clone();
+        sir.advance_until(250.);
+        other.advance_until(250.);
+        assert_eq!(sir.species(), other.species());
+        assert_eq!(sir.species().iter().sum::<isize>(), 1000);
+        sir.seed(1);
+        sir.advance_until(500.);
+        assert_eq!(sir.get_time(), 500.);
+    }
+    #[test]
+    fn methods() {
+        let methods = [
+            Method::Direct,
+            Method::FirstReaction,
+            Method::NextReaction,
+            Method::SortingDirect,
+        ];
+        for method in methods {
+            // birth and death, with a stationary Poisson distribution
+            let mut p = Gillespie::new_with_seed([0, 0], 0);
+            p.add_reaction(Rate::lma(100., [0, 0]), [1, 0]);
+            p.add_reaction(Rate::lma(1., [1, 0]), [-1, 0]);
+            p.add_reaction(Rate::lma(0., [0, 0]), [0, 1]);
+            p.set_method(method);
+            assert_eq!(p.method(), method);
+            let mut total = 0;
+            for t in 1..=200 {
+                p.advance_until(10. * t as f64);
+                assert_eq!(p.get_time(), 10. * t as f64);
+                total += p.get_species(0);
+            }
+            let mean = total as f64 / 200.;
+            assert!((mean - 100.).abs() < 3., "{method:?}: {mean}");
+            assert_eq!(p.get_species(1), 0);
+        }
+        assert_eq!("sorting_direct".parse(), Ok(Method::SortingDirect));
+        // the frequent reaction moves to the front and stays there
+        let mut p = Gillespie::new_with_seed([0], 0);
+        p.add_reaction(Rate::lma(0.1, [0]), [0]);
+        p.add_reaction(Rate::lma(0.1, [0]), [0]);
+        p.add_reaction(Rate::lma(100., [0]), [1]);
+        p.set_method(Method::SortingDirect);
+        for t in 1..=10 {
+            p.advance_until(t as f64);
+        }
+        assert_eq!(p.order[0], 2);
+        assert!("gillespie".parse::<Method>().is_err());
+        assert_eq!("r_leaping:10".parse(), Ok(Method::RLeaping(10)));
+        assert!("r_leaping:0".parse::<Method>().is_err());
+        for method in [
+            Method::NextReaction,
+            Method::RLeaping(100),
+            Method::SlowScale,
+        ] {
+            assert_eq!(method.to_string().parse(), Ok(method));
+        }
+    }
+    #[test]
+    fn replay() {
+        let methods = [
+            Method::Direct,
+            Method::FirstReaction,
+            Method::NextReaction,
+            Method::SortingDirect,
+            Method::RLeaping(10),
+        ];
+        for method in methods {
+            // dimerization
+            let mut p = Gillespie::new_with_seed([200, 0], 0);
+            p.add_reaction(Rate::lma(0.01, [2, 0]), [-2, 1]);
+            p.add_reaction(Rate::lma(0.1, [0, 1]), [2, -1]);
+            p.set_method(method);
+            p.set_record_events(true);
+            let mut states = Vec::new();
+            for t in 1..=10 {
+                p.advance_until(t as f64);
+                states.push(p.species().to_vec());
+            }
+            let log = p.take_events().unwrap();
+            assert!(log.len() > 100, "{method:?}");
+            assert_eq!((log.start(), log.initial()), (0., &[200, 0][..]));
+            assert!(p.event_log().unwrap().is_empty());
+            let mut q = Gillespie::new([0, 0]);
+            q.add_reaction(Rate::lma(1., [2, 0]), [-2, 1]);
+            q.add_reaction(Rate::lma(1., [0, 1]), [2, -1]);
+            q.set_count_firings(true);
+            let mut replayed = vec![vec![200, 0]; 10];
+            q.replay_with(&log, |t, _, species| {
+                replayed[t.ceil() as usize - 1..].fill(species.to_vec());
+            })
+            .unwrap();
+            assert_eq!(replayed, states, "{method:?}");
+            assert_eq!(q.firings().unwrap().iter().sum::<u64>(), log.len() as u64);
+        }
+        // the log must match the problem
+        let mut log = EventLog::new(0., vec![1, 0]);
+        log.push(0.5, 0);
+        let mut p = Gillespie::new([0]);
+        assert_eq!(
+            p.replay(&log),
+            Err(Error::SpeciesCount {
+                expected: 1,
+                found: 2
+            })
+        );
+        let mut p = Gillespie::new([0, 0]);
+        assert_eq!(
+            p.replay(&log),
+            Err(Error::UnknownReaction {
+                index: 0,
+                nb_reactions: 0
+            })
+        );
+        p.add_reaction(Rate::lma(1., [2, 0]), [-2, 1]);
+        assert!(matches!(
+            p.replay(&log),
+            Err(Error::NegativeAmount { reaction: 0, .. })
+        ));
+    }
+    #[test]
+    fn events() {
+        let mut p = Gillespie::new_with_seed([20, 0], 0);
+        p.add_reaction(Rate::lma(1., [1, 0]), [-1, 1]);
+        p.add_reaction(Rate::lma(0.5, [0, 1]), [0, -1]);
+        let mut q = p.clone();
+        let events: Vec<_> = p.events().unwrap().collect();
+        assert_eq!(events.len(), 40);
+        assert_eq!(p.species(), [0, 0]);
+        assert_eq!((events[39].1, &events[39].2[..]), (1, &[0, 0][..]));
+        assert_eq!(p.get_time(), f64::INFINITY);
+        assert!(events.windows(2).all(|w| w[0].0 < w[1].0));
+        // the same reactions as without iterator
+        for (t, ireaction, species) in &events {
+            let before = q.get_species(0);
+            assert_eq!(q.advance_n_reactions(1), Ok(1));
+            assert_eq!((q.get_time(), q.species()), (*t, &species[..]));
+            assert_eq!(*ireaction == 0, q.get_species(0) < before);
+        }
+        assert!(p.events().unwrap().next().is_none());
+        assert_eq!(q.advance_n_reactions(1), Ok(0));
+        // the other methods are not supported
+        q.set_method(Method::FirstReaction);
+        let unsupported = Err(Error::DirectOnly {
+            operation: "simulations by number of reactions".to_string(),
+        });
+        assert_eq!(q.advance_n_reactions(1), unsupported);
+        assert!(q.events().is_err());
+    }
+    #[test]
+    fn reaction_stats() {
+        let mut p = Gillespie::new_with_seed([3], 0);
+        p.add_reaction(Rate::lma(2., [1]), [0]);
+        assert_eq!(p.reaction_stats(), None);
+        p.set_reaction_stats(true);
+        let mean = |p: &Gillespie| p.reaction_stats().unwrap()[0].mean_propensity;
+        assert_eq!(mean(&p), 6.);
+        p.advance_until(10.);
+        assert!((mean(&p) - 6.).abs() < 1e-12);
+        let firings = p.reaction_stats().unwrap()[0].firings;
+        assert!(firings.abs_diff(60) < 25, "{firings}");
+        // the propensities follow the changes of the species and rates
+        p.set_species([1]);
+        p.advance_until(20.);
+        assert!((mean(&p) - 4.).abs() < 1e-12);
+        p.scale_rate(0, 0.);
+        p.advance_until(30.);
+        assert!((mean(&p) - 80. / 30.).abs() < 1e-12);
+        // and the time that is skipped does not count
+        p.set_time(40.);
+        p.advance_until(50.);
+        assert!((mean(&p) - 2.).abs() < 1e-12);
+        // reactions added later start from zero
+        p.add_reaction(Rate::lma(1., [0]), [1]);
+        assert_eq!(p.reaction_stats().unwrap().len(), 2);
+        p.set_reaction_stats(false);
+        assert_eq!(p.reaction_stats(), None);
+    }
+    #[test]
+    fn limits() {
+        // autocatalysis, which explodes in finite time
+        let mut p = Gillespie::new_with_seed([10], 0);
+        p.add_reaction(Rate::lma(1., [2]), [1]);
+        p.set_limits(Limits {
+            max_reactions: Some(1000),
+            max_wall_time: None,
+        });
+        for method in [
+            Method::Direct,
+            Method::FirstReaction,
+            Method::NextReaction,
+            Method::SortingDirect,
+        ] {
+            p.set_method(method);
+            p.set_time(0.);
+            p.set_species([10]);
+            p.advance_until(10.);
+            assert_eq!(p.limit_reached(), Some(Limit::Reactions), "{method}");
+            assert_eq!(p.get_species(0), 1010, "{method}");
+            assert!(p.get_time() < 1., "{method}");
+        }
+        p.set_method(Method::RLeaping(100));
+        p.set_species([10]);
+        p.advance_until(10.);
+        assert_eq!(p.limit_reached(), Some(Limit::Reactions));
+        assert!((1010..1110).contains(&p.get_species(0)));
+        // the wall-clock time is checked every 1024 reactions
+        p.set_method(Method::Direct);
+        p.set_species([10]);
+        p.set_limits(Limits {
+            max_reactions: None,
+            max_wall_time: Some(Duration::ZERO),
+        });
+        let Err(Error::LimitReached { limit, time, state }) = p.try_advance_until(10.) else {
+            panic!("the simulation was not stopped");
+        };
+        assert_eq!(
+            (limit, time, state),
+            (Limit::WallTime, p.get_time(), vec![1034])
+        );
+        // a simulation that reaches tmax is not stopped
+        p.set_species([0]);
+        assert_eq!(p.try_advance_until(10.), Ok(()));
+        assert_eq!((p.limit_reached(), p.get_time()), (None, 10.));
+        p.set_limits(Limits {
+            max_reactions: Some(0),
+            max_wall_time: None,
+        });
+        p.set_species([10]);
+        p.advance_until(20.);
+        assert_eq!((p.get_species(0), p.get_time()), (10, 10.));
+        // the steps of many reactions count them all, and those of none
+        // count nothing
+        let mut budget = Budget::new(&Limits {
+            max_reactions: None,
+            max_wall_time: Some(Duration::ZERO),
+        });
+        budget.spend(0);
+        assert_eq!(budget.countdown, WALL_TIME_CHECK);
+        budget.spend(1000);
+        assert_eq!(budget.reached, None);
+        budget.spend(100);
+        assert_eq!(budget.reached, Some(Limit::WallTime));
+    }
+    #[test]
+    fn r_leaping() {
+        // birth and death, with a stationary Poisson distribution
+        let mut p = Gillespie::new_with_seed([0], 0);
+        p.add_reaction(Rate::lma(1000., [0]), [1]);
+        p.add_reaction(Rate::lma(1., [1]), [-1]);
+        p.set_method(Method::RLeaping(20));
+        let mut total = 0;
+        for t in 1..=200 {
+            p.advance_until(10. * t as f64);
+            assert_eq!(p.get_time(), 10. * t as f64);
+            total += p.get_species(0);
+        }
+        let mean = total as f64 / 200.;
+        assert!((mean - 1000.).abs() < 10., "{mean}");
+        // large steps are shortened to keep the amounts non-negative
+        let mut p = Gillespie::new_with_seed([10, 0], 0);
+        p.add_reaction(Rate::lma(1., [1, 0]), [-1, 1]);
+        p.set_method(Method::RLeaping(1000));
+        p.advance_until(100.);
+        assert_eq!(p.species(), [0, 10]);
+    }
+    #[test]
+    fn slow_scale() {
+        // E + S <-> ES -> E + P, with fast binding
+        let enzyme = |method| {
+            let mut p = Gillespie::new_with_seed([10, 100, 0, 0], 0);
+            p.add_reaction(Rate::lma(10., [1, 1, 0, 0]), [-1, -1, 1, 0]);
+            p.add_reaction(Rate::lma(1000., [0, 0, 1, 0]), [1, 1, -1, 0]);
+            p.add_reaction(Rate::lma(1., [0, 0, 1, 0]), [1, 0, -1, 1]);
+            p.set_method(method);
+            p
+        };
+        assert_eq!(enzyme(Method::Direct).detect_fast_pairs(100.), [(0, 1)]);
+        let mut means = Vec::new();
+        for method in [Method::Direct, Method::SlowScale] {
+            let mut p = enzyme(method);
+            let mut total = 0;
+            for _ in 0..100 {
+                p.set_species([10, 100, 0, 0]);
+                p.set_time(0.);
+                p.advance_until(2.);
+                let species = p.species();
+                assert_eq!(species[0] + species[2], 10);
+                assert_eq!(species[1] + species[2] + species[3], 100);
+                total += species[3];
+            }
+            means.push(total as f64 / 100.);
+        }
+        assert_eq!(enzyme(Method::SlowScale).fast_pairs(), []);
+        assert!((means[0] - means[1]).abs() < 1.5, "{means:?}");
+        // the pairs follow the reactions when they are reordered
+        let mut p = enzyme(Method::SlowScale);
+        p.set_fast_pairs(&[(0, 1)]);
+        p.reorder_reactions(&[2, 1, 0]);
+        assert_eq!(p.fast_pairs(), [(2, 1)]);
+    }
+    #[test]
+    #[should_panic(expected = "do not have opposite effects")]
+    fn fast_pairs_not_reversible() {
+        let mut p = Gillespie::new([0, 0]);
+        p.add_reaction(Rate::lma(1., [1, 0]), [-1, 1]);
+        p.add_reaction(Rate::lma(1., [0, 1]), [0, -1]);
+        p.set_fast_pairs(&[(0, 1)]);
+    }
+    #[test]
+    fn delays() {
+        // production with a delay, and degradation
+        let mut p = Gillespie::new_with_seed([0, 0], 0);
+        p.add_reaction(Rate::lma(100., [0, 0]), [1, 0]);
+        p.add_reaction(Rate::lma(1., [1, 0]), [-1, 1]);
+        p.set_delay(0, 2.);
+        p.advance_until(2.);
+        assert_eq!(p.species(), [0, 0]);
+        assert!(p.pending.len() > 100);
+        // about 200 productions are pending at any time
+        let mut total = 0;
+        for t in 1..=100 {
+            p.advance_until(2. + t as f64);
+            total += p.pending.len();
+        }
+        let mean = total as f64 / 100.;
+        assert!((mean - 200.).abs() < 20., "{mean}");
+        p.reorder_reactions(&[1, 0]);
+        assert!(p.pending.iter().all(|s| s.1 == 1));
+    }
+    #[test]
+    #[should_panic(expected = "Stepper only supports the direct method")]
+    fn stepper_delays() {
+        let mut p = Gillespie::new([0, 0]);
+        p.add_reaction(Rate::lma(100., [0, 0]), [1, 0]);
+        p.set_delay(0, 1000.);
+        Stepper::new(p);
+    }
+    #[test]
+    fn disabled_reactions() {
+        let mut p = Gillespie::new_with_seed([0, 0], 0);
+        p.add_reaction(Rate::lma(10., [0, 0]), [1, 0]);
+        p.add_reaction(Rate::lma(10., [0, 0]), [0, 1]);
+        p.set_reaction_enabled(1, false);
+        p.set_reaction_enabled(1, false);
+        assert!(p.is_reaction_enabled(0));
+        assert!(!p.is_reaction_enabled(1));
+        p.advance_until(10.);
+        assert!(p.get_species(0) > 0);
+        assert_eq!(p.get_species(1), 0);
+        // the new rate is used once the reaction is enabled again
+        p.set_rate(1, Rate::lma(1000., [0, 0]));
+        p.set_reaction_enabled(0, false);
+        p.set_reaction_enabled(1, true);
+        p.set_reaction_enabled(1, true);
+        let before = p.get_species(0);
+        p.advance_until(20.);
+        assert_eq!(p.get_species(0), before);
+        assert!(p.get_species(1) > 5000);
+    }
+    #[test]
+    fn rate_scales() {
+        let methods = [
+            Method::Direct,
+            Method::FirstReaction,
+            Method::NextReaction,
+            Method::SortingDirect,
+            Method::RLeaping(10),
+        ];
+        for method in methods {
+            let mut p = Gillespie::new_with_seed([0, 0], 0);
+            p.add_reaction(Rate::lma(10., [0, 0]), [1, 0]);
+            p.set_method(method);
+            p.scale_rate(0, 0.);
+            p.add_reaction(Rate::lma(10., [0, 0]), [0, 1]);
+            assert_eq!(p.rate_scale(1), 1.);
+            p.advance_until(10.);
+            assert_eq!(p.get_species(0), 0, "{method}");
+            assert!(p.get_species(1) > 0);
+            // the factor replaces the previous one and applies to new rates
+            p.scale_rate(0, 2.);
+            p.set_rate(0, Rate::lma(100., [0, 0]));
+            p.scale_rate(0, 3.);
+            p.advance_until(20.);
+            assert!((2700..3300).contains(&p.get_species(0)), "{method}");
+        }
+        // the factors follow the reactions when they are reordered
+        let mut p = Gillespie::new([0, 0]);
+        p.add_reaction(Rate::lma(10., [0, 0]), [1, 0]);
+        p.add_reaction(Rate::lma(10., [0, 0]), [0, 1]);
+        p.scale_rate(1, 0.5);
+        p.reorder_reactions(&[1, 0]);
+        assert_eq!((p.rate_scale(0), p.rate_scale(1)), (0.5, 1.));
+        assert_eq!(
+            p.try_scale_rate(2, 1.),
+            Err(Error::UnknownReaction {
+                index: 2,
+                nb_reactions: 2
+            })
+        );
+        assert_eq!(
+            p.try_scale_rate(0, f64::NAN).unwrap_err().to_string(),
+            "reaction 0 has the invalid factor NaN"
+        );
+        // the clocks of custom waiting times keep their progress
+        let mut p = Gillespie::new_with_seed([0, 0], 0);
+        p.add_reaction(Rate::lma(1., [0, 0]), [1, 0]);
+        p.add_reaction(Rate::lma(1., [0, 0]), [0, 1]);
+        p.set_waiting_time(0, Deterministic(1.));
+        p.advance_until(0.5);
+        p.scale_rate(1, 3.);
+        p.advance_until(1.2);
+        assert_eq!(p.get_species(0), 1);
+        // and run at the new rate
+        p.scale_rate(0, 2.);
+        p.advance_until(1.5);
+        assert_eq!(p.get_species(0), 1);
+        p.advance_until(1.7);
+        assert_eq!(p.get_species(0), 2);
+    }
+    #[test]
+    fn reorder_reactions() {
+        let mut p = Gillespie::new_with_seed([0, 0], 0);
+        p.set_param("k", 10.);
+        p.add_reaction(Rate::lma_param("k", [0, 0]), [1, 0]);
+        p.set_delay(0, 1.);
+        p.add_reaction(Rate::lma(20., [0, 0]), [0, 1]);
+        p.set_count_firings(true);
+        p.set_reaction_stats(true);
+        p.set_record_events(true);
+        p.advance_until(10.);
+        let firings = p.firings().unwrap().to_vec();
+        let stats = p.reaction_stats().unwrap();
+        let events: Vec<_> = p.event_log().unwrap().events().collect();
+        p.reorder_reactions(&[1, 0]);
+        assert_eq!(p.firings().unwrap(), [firings[1], firings[0]]);
+        assert_eq!(p.reaction_stats().unwrap(), [stats[1], stats[0]]);
+        let renumbered = events.iter().map(|&(t, ireaction)| (t, 1 - ireaction));
+        assert!(p.event_log().unwrap().events().eq(renumbered));
+        // the delays, shorter than the reactions, and the parameters
+        // follow the reactions
+        assert_eq!(p.delays, [0., 1.]);
+        p.set_param("k", 0.);
+        let (before, pending) = (p.species().to_vec(), p.pending.len() as isize);
+        assert!(pending > 0);
+        p.advance_until(20.);
+        assert_eq!(p.get_species(0), before[0] + pending);
+        assert!(p.get_species(1) > before[1]);
+    }
+    #[test]
+    fn double_double() {
+        let big = DoubleDouble(1e16, 0.);
+        let one = DoubleDouble(1., 0.);
+        assert_eq!(1e16 + 1., 1e16);
+        let sum = big.add(one);
+        assert_eq!(sum, DoubleDouble(1e16, 1.));
+        assert!(big.lt(sum));
+        assert_eq!(sum.add(DoubleDouble(-1e16, 0.)), one);
+        let third = one.div(DoubleDouble(3., 0.));
+        assert_eq!(third.0, 1. / 3.);
+        assert!(third.1 != 0.);
+        let product = third.mul(DoubleDouble(3., 0.));
+        assert!((product.0 - 1.).abs() + product.1.abs() < 1e-30);
+    }
+    #[test]
+    fn extended_precision() {
+        // increments of about 1e-5 are below half the resolution of t
+        let mut p = Gillespie::new_with_seed([0], 0);
+        p.add_reaction(Rate::lma(1e5, [0]), [1]);
+        p.set_time(1e12);
+        let mut extended = p.clone();
+        extended.set_extended_precision(true);
+        p.advance_until(1e12 + 0.01);
+        extended.advance_until(1e12 + 0.01);
+        assert_eq!(extended.get_time(), 1e12 + 0.01);
+        assert!((850..1150).contains(&extended.get_species(0)));
+        assert!(p.get_species(0) > 2000);
+        // usual problems are simulated as well
+        let mut sir = Gillespie::new_with_seed([999, 1, 0], 0);
+        sir.add_reaction(Rate::lma(1e-4, [1, 1, 0]), [-1, 1, 0]);
+        sir.add_reaction(Rate::lma(0.01, [0, 1, 0]), [0, -1, 1]);
+        sir.set_extended_precision(true);
+        sir.advance_until(250.);
+        assert_eq!(sir.species().iter().sum::<isize>(), 1000);
+        assert!(sir.get_species(2) > 0);
+    }
+    #[test]
     fn dimers() {
         let mut dimers = Gillespie::new([1, 0, 0, 0]);
         dimers.add_reaction(Rate::lma(25., [1, 0, 0, 0]), [0, 1, 0, 0]);
@@ -387,4 +4937,571 @@ mod tests {
         assert!(1000 < dimers.get_species(2));
         assert!(dimers.get_species(3) < 10000);
     }
+    #[test]
+    fn errors() {
+        let mut p = Gillespie::new([5, 0]);
+        let err = p.try_add_reaction(Rate::lma(1., [1, 0, 0]), [-1, 1]);
+        assert_eq!(
+            err,
+            Err(Error::SpeciesCount {
+                expected: 2,
+                found: 3
+            })
+        );
+        let sparse = Rate::lma(1., [0, 0, 1]).sparse();
+        let err = p.try_add_reaction(sparse, [-1, 1]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown species of index 2, the problem has 2 species"
+        );
+        let expr = Expr::Add(
+            Box::new(Expr::Constant(1.)),
+            Box::new(Expr::Concentration(3)),
+        );
+        assert!(p.try_add_reaction(Rate::Expr(expr), [-1, 1]).is_err());
+        assert_eq!(p.nb_reactions(), 0);
+        p.try_add_reaction(Rate::lma(1., [1, 0]), [-1, 1]).unwrap();
+        assert_eq!(
+            p.try_set_rate(1, Rate::lma(1., [1, 0])),
+            Err(Error::UnknownReaction {
+                index: 1,
+                nb_reactions: 1
+            })
+        );
+        assert!(p.try_set_rate(0, Rate::lma(1., [1])).is_err());
+        // SAFETY: the rate and the jump refer to the two species
+        unsafe { p.add_reaction_unchecked(Rate::lma(0., [0, 1]), Jump::new_sparse([(1, -1)])) };
+        p.advance_until(100.);
+        assert_eq!(p.species(), [0, 5]);
+    }
+    #[test]
+    fn math_functions() {
+        let x = || Box::new(Expr::Concentration(0));
+        let c = |value: f64| Box::new(Expr::Constant(value));
+        let eval = |expr: Expr| Rate::Expr(expr).rate(&[100, 3]);
+        assert_eq!(eval(Expr::Ln(x())), 100f64.ln());
+        assert_eq!(eval(Expr::Log10(x())), 2.);
+        assert_eq!(eval(Expr::Sqrt(x())), 10.);
+        assert_eq!(eval(Expr::Abs(Box::new(Expr::Sub(c(1.), x())))), 99.);
+        assert_eq!(eval(Expr::Floor(c(2.7))), 2.);
+        assert_eq!(eval(Expr::Ceil(c(2.2))), 3.);
+        assert_eq!(eval(Expr::Sin(c(0.))), 0.);
+        assert_eq!(eval(Expr::Cos(c(0.))), 1.);
+        assert_eq!(eval(Expr::Min(x(), c(5.))), 5.);
+        assert_eq!(eval(Expr::Max(x(), c(5.))), 100.);
+        assert_eq!(eval(Expr::Hill(x(), c(100.), c(4.))), 0.5);
+        assert_eq!(eval(Expr::Hill(c(0.), c(100.), c(2.))), 0.);
+        // the operands are checked and resolved
+        let mut p = Gillespie::new([1]);
+        let expr = Expr::Hill(x(), Box::new(Expr::Parameter("K".into())), c(2.));
+        assert!(p.try_add_reaction(Rate::Expr(expr.clone()), [1]).is_err());
+        p.set_param("K", 1.);
+        p.add_reaction(Rate::Expr(expr), [1]);
+        let far = Expr::Max(c(1.), Box::new(Expr::Concentration(1)));
+        assert!(p.try_add_reaction(Rate::Expr(far), [1]).is_err());
+    }
+    #[test]
+    fn conditions() {
+        let x = || Box::new(Expr::Concentration(0));
+        let c = |value: f64| Box::new(Expr::Constant(value));
+        let eval = |expr: Expr| Rate::Expr(expr).rate(&[100, 3]);
+        assert_eq!(eval(Expr::Lt(x(), c(100.))), 0.);
+        assert_eq!(eval(Expr::Le(x(), c(100.))), 1.);
+        assert_eq!(eval(Expr::Gt(x(), c(10.))), 1.);
+        assert_eq!(eval(Expr::Ge(c(1.), x())), 0.);
+        assert_eq!(eval(Expr::Eq(x(), c(100.))), 1.);
+        assert_eq!(eval(Expr::Ne(x(), c(100.))), 0.);
+        assert_eq!(eval(Expr::Lt(c(f64::NAN), x())), 0.);
+        assert_eq!(eval(Expr::And(x(), c(0.))), 0.);
+        assert_eq!(eval(Expr::And(x(), c(-2.))), 1.);
+        assert_eq!(eval(Expr::Or(c(0.), x())), 1.);
+        assert_eq!(eval(Expr::Not(c(0.))), 1.);
+        assert_eq!(eval(Expr::Not(x())), 0.);
+        // only the chosen branch is evaluated
+        let ratio = Expr::If(
+            Box::new(Expr::Gt(x(), c(0.))),
+            Box::new(Expr::Div(c(1.), x())),
+            c(0.),
+        );
+        assert_eq!(Rate::Expr(ratio.clone()).rate(&[4]), 0.25);
+        assert_eq!(Rate::Expr(ratio).rate(&[0]), 0.);
+        // a production switched on after a threshold on a parameter
+        let mut p = Gillespie::new_with_seed([0, 0], 0);
+        let switch = Expr::If(
+            Box::new(Expr::Ge(x(), Box::new(Expr::Parameter("threshold".into())))),
+            c(10.),
+            c(0.),
+        );
+        p.set_param("threshold", 1e9);
+        p.add_reaction(Rate::lma(1., [0, 0]), [1, 0]);
+        p.add_reaction(Rate::Expr(switch), [0, 1]);
+        p.advance_until(100.);
+        assert!(p.get_species(0) > 50);
+        assert_eq!(p.get_species(1), 0);
+        p.set_param("threshold", 5.);
+        p.advance_until(110.);
+        assert!(p.get_species(1) > 50);
+    }
+    #[test]
+    fn compiled() {
+        let x = |i: usize| Box::new(Expr::Concentration(i));
+        let c = |value: f64| Box::new(Expr::Constant(value));
+        let exprs = [
+            Expr::Constant(2.5),
+            Expr::Parameter("k".into()),
+            Expr::Sub(x(0), Box::new(Expr::Div(x(1), c(2.)))),
+            Expr::Pow(Box::new(Expr::Exp(c(1.))), Box::new(Expr::Ln(x(2)))),
+            Expr::Hill(
+                Box::new(Expr::Sqrt(x(0))),
+                Box::new(Expr::Max(x(1), Box::new(Expr::Floor(c(2.5))))),
+                Box::new(Expr::Abs(c(-2.))),
+            ),
+            Expr::Mul(
+                Box::new(Expr::Min(x(0), Box::new(Expr::Log10(x(1))))),
+                Box::new(Expr::Add(
+                    Box::new(Expr::Sin(x(2))),
+                    Box::new(Expr::Cos(Box::new(Expr::Ceil(c(0.2))))),
+                )),
+            ),
+            Expr::If(
+                Box::new(Expr::And(
+                    Box::new(Expr::Lt(x(0), x(1))),
+                    Box::new(Expr::Not(Box::new(Expr::Eq(x(2), c(0.))))),
+                )),
+                Box::new(Expr::Div(x(1), x(0))),
+                Box::new(Expr::If(
+                    Box::new(Expr::Or(
+                        Box::new(Expr::Ge(x(0), c(5.))),
+                        Box::new(Expr::Ne(x(1), x(2))),
+                    )),
+                    Box::new(Expr::Sub(
+                        Box::new(Expr::Le(x(1), c(3.))),
+                        Box::new(Expr::Gt(x(2), c(1.))),
+                    )),
+                    c(-1.),
+                )),
+            ),
+        ];
+        for expr in &exprs {
+            let program = expr.compile();
+            for species in [[0, 0, 0], [1, 2, 3], [4, 3, 2], [7, 7, 0], [0, 10, 1]] {
+                let (expected, found) = (expr.eval(&species), program.eval(&species));
+                assert!(
+                    expected == found || expected.is_nan() && found.is_nan(),
+                    "{expr:?} {species:?}"
+                );
+            }
+        }
+        assert_eq!(exprs[2].compile().species(), [0, 1]);
+        assert_eq!(exprs[1].compile().species(), [] as [usize; 0]);
+        // deep expressions do not fit in the stack without allocation
+        let mut deep = Expr::Concentration(0);
+        for i in 0..50 {
+            deep = Expr::Add(c(f64::from(i)), Box::new(deep));
+        }
+        assert_eq!(deep.compile().eval(&[1]), 1226.);
+        // the problems store the expression rates compiled
+        let mut p = Gillespie::new([0, 10]);
+        p.add_reaction(Rate::Expr(exprs[2].clone()), [1, 0]);
+        assert!(matches!(p.reactions()[0].0, Rate::Compiled(_)));
+        assert!(reads(&p.reactions()[0].0, 1));
+        assert!(!reads(&p.reactions()[0].0, 2));
+        let program = Rate::Expr(*x(2)).compile();
+        assert!(p.try_add_reaction(program, [1, 0]).is_err());
+    }
+    #[test]
+    fn simplify() {
+        let x = |i: usize| Box::new(Expr::Concentration(i));
+        let c = |value: f64| Box::new(Expr::Constant(value));
+        let simplified = |expr: Expr| format!("{:?}", expr.simplify());
+        assert_eq!(
+            simplified(Expr::Mul(
+                Box::new(Expr::Div(Box::new(Expr::Exp(c(0.))), c(4.))),
+                x(0)
+            )),
+            format!("{:?}", Expr::Mul(c(0.25), x(0)))
+        );
+        assert_eq!(
+            simplified(Expr::Sub(Box::new(Expr::Pow(x(0), c(1.))), c(0.))),
+            "Concentration(0)"
+        );
+        assert_eq!(
+            simplified(Expr::Pow(Box::new(Expr::Div(x(1), x(0))), c(0.))),
+            "Constant(1.0)"
+        );
+        assert_eq!(
+            simplified(Expr::Mul(c(1.), Box::new(Expr::Add(c(0.), x(1))))),
+            "Concentration(1)"
+        );
+        // 0 * x0 is 0, but not 0 / x0 nor 0 * 1 / x0, infinite for x0 = 0
+        assert_eq!(simplified(Expr::Mul(x(0), c(0.))), "Constant(0.0)");
+        let inverse = Expr::Div(c(1.), x(0));
+        assert_eq!(
+            simplified(Expr::Mul(c(0.), Box::new(inverse.clone()))),
+            format!("{:?}", Expr::Mul(c(0.), Box::new(inverse)))
+        );
+        // nested powers
+        let power = |a, b| Expr::Pow(Box::new(a), c(b));
+        assert_eq!(
+            simplified(power(power(Expr::Sqrt(x(0)), 2.), 3.)),
+            format!("{:?}", power(Expr::Sqrt(x(0)), 6.))
+        );
+        assert_eq!(simplified(power(power(*x(0), 0.5), 2.)), "Concentration(0)");
+        let root = power(power(Expr::Sub(x(0), x(1)), 2.), 0.5);
+        assert_eq!(simplified(root.clone()), format!("{root:?}"));
+        // conditions
+        let switch = |condition| Expr::If(condition, x(0), x(1));
+        assert_eq!(
+            simplified(switch(Box::new(Expr::Lt(c(1.), c(2.))))),
+            "Concentration(0)"
+        );
+        assert_eq!(simplified(switch(c(0.))), "Concentration(1)");
+        let sign = switch(Box::new(Expr::Gt(x(0), c(0.))));
+        assert_eq!(simplified(sign.clone()), format!("{sign:?}"));
+        // the parameters are kept
+        assert_eq!(
+            simplified(Expr::Add(Box::new(Expr::Parameter("k".into())), c(0.))),
+            "Parameter(\"k\")"
+        );
+        // the same values, up to the rounding of the powers
+        let exprs = [
+            Expr::Hill(x(0), Box::new(Expr::Add(c(2.), c(3.))), c(2.)),
+            Expr::Max(
+                Box::new(Expr::Mul(c(0.), Box::new(Expr::Sin(x(1))))),
+                Box::new(Expr::Ln(x(0))),
+            ),
+            Expr::If(
+                Box::new(Expr::And(
+                    Box::new(Expr::Not(c(0.))),
+                    Box::new(Expr::Ge(x(0), x(1))),
+                )),
+                Box::new(Expr::Pow(Box::new(Expr::Pow(x(1), c(-1.))), c(-3.))),
+                Box::new(Expr::Div(x(0), Box::new(Expr::Sub(c(3.), c(2.))))),
+            ),
+        ];
+        for expr in &exprs {
+            let simplified = expr.simplify();
+            for species in [[0, 0], [1, 2], [5, 3], [10, 0]] {
+                let (expected, found) = (expr.eval(&species), simplified.eval(&species));
+                assert!(
+                    expected == found
+                        || (expected - found).abs() < 1e-12 * expected.abs()
+                        || expected.is_nan() && found.is_nan(),
+                    "{expr:?} {species:?}"
+                );
+            }
+        }
+        // the problems simplify the rates once the parameters are known
+        let mut p = Gillespie::new([0]);
+        p.set_param("k", 0.);
+        let rate = Expr::Mul(Box::new(Expr::Parameter("k".into())), x(0));
+        p.add_reaction(Rate::Expr(rate), [1]);
+        match &p.reactions()[0].0 {
+            Rate::Compiled(program) => assert_eq!(program.species(), [] as [usize; 0]),
+            rate => panic!("{rate:?}"),
+        }
+    }
+    #[cfg(feature = "jit")]
+    #[test]
+    fn jit() {
+        let x = |i: usize| Box::new(Expr::Concentration(i));
+        let c = |value: f64| Box::new(Expr::Constant(value));
+        let problem = || {
+            let mut p = Gillespie::new_with_seed([50, 0, 5], 0);
+            p.set_param("k", 2.);
+            p.add_reaction(Rate::lma(1e-3, [2, 0, 0]), [-2, 1, 0]);
+            p.add_reaction(Rate::lma_param("k", [0, 1, 0]), [2, -1, 0]);
+            p.add_reaction(Rate::lma(0.5, [0, 0, 0]), [1, 0, 0]);
+            let switch = Expr::If(
+                Box::new(Expr::Gt(x(0), c(40.))),
+                Box::new(Expr::Hill(x(0), c(30.), c(2.))),
+                Box::new(Expr::Min(x(2), Box::new(Expr::Sqrt(x(1))))),
+            );
+            p.add_reaction(Rate::Expr(switch), [0, 0, 1]);
+            p.add_reaction(Rate::lma(0.1, [0, 0, 1]), [0, 0, -1]);
+            p.scale_rate(3, 1.5);
+            p
+        };
+        let (mut interpreted, mut native) = (problem(), problem());
+        native.set_jit(true);
+        for (i, t) in [10., 20., 30.].into_iter().enumerate() {
+            interpreted.advance_until(t);
+            native.advance_until(t);
+            assert_eq!(interpreted.get_time(), native.get_time());
+            assert_eq!(interpreted.get_species(0), native.get_species(0));
+            assert_eq!(interpreted.get_species(2), native.get_species(2));
+            // the changes of the rates are followed
+            for p in [&mut interpreted, &mut native] {
+                match i {
+                    0 => p.set_param("k", 0.1),
+                    _ => p.set_rate(4, Rate::lma(1., [0, 0, 1])),
+                }
+            }
+        }
+        native.set_jit(false);
+        native.advance_until(40.);
+        interpreted.advance_until(40.);
+        assert_eq!(interpreted.get_species(1), native.get_species(1));
+        // the constants, factors and disabled reactions are not compiled
+        native.set_jit(true);
+        native.advance_until(50.);
+        interpreted.advance_until(50.);
+        let compiled = native.native.clone().unwrap();
+        for p in [&mut interpreted, &mut native] {
+            p.set_param("k", 3.);
+            p.scale_rate(0, 0.5);
+            p.set_reaction_enabled(2, false);
+            p.set_rate(4, Rate::lma(2., [0, 0, 1]));
+            p.advance_until(60.);
+        }
+        assert!(std::sync::Arc::ptr_eq(
+            &compiled,
+            native.native.as_ref().unwrap()
+        ));
+        assert_eq!(interpreted.species(), native.species());
+        // but the forms of the rates are
+        native.set_rate(4, Rate::lma(2., [0, 1, 1]));
+        native.advance_until(70.);
+        assert!(!std::sync::Arc::ptr_eq(
+            &compiled,
+            native.native.as_ref().unwrap()
+        ));
+    }
+    #[test]
+    fn firings() {
+        let mut birth_death = Gillespie::new_with_seed([10], 0);
+        birth_death.add_reaction(Rate::lma(20., [0]), [1]);
+        birth_death.add_reaction(Rate::lma(1., [1]), [-1]);
+        assert_eq!(birth_death.firings(), None);
+        for method in [
+            Method::Direct,
+            Method::FirstReaction,
+            Method::NextReaction,
+            Method::SortingDirect,
+            Method::RLeaping(10),
+        ] {
+            let mut p = birth_death.clone();
+            p.set_method(method);
+            p.set_count_firings(true);
+            p.advance_until(10.);
+            let firings = p.firings().unwrap().to_vec();
+            assert!(firings[1] > 0, "{method}");
+            assert_eq!(
+                firings[0] as isize - firings[1] as isize,
+                p.get_species(0) - 10
+            );
+            p.reset_firings();
+            assert_eq!(p.firings(), Some(&[0, 0][..]));
+            p.set_count_firings(false);
+            p.advance_until(20.);
+            assert_eq!(p.firings(), None);
+        }
+        let mut p = birth_death.clone();
+        p.set_count_firings(true);
+        p.set_extended_precision(true);
+        p.advance_until(1.);
+        p.set_extended_precision(false);
+        p.set_delay(0, 0.5);
+        p.advance_until(2.);
+        p.add_reaction(Rate::lma(100., [0]), [0]);
+        p.advance_until(3.);
+        let firings = p.firings().unwrap();
+        assert_eq!(
+            firings[0] as isize - firings[1] as isize,
+            p.get_species(0) - 10
+        );
+        assert!(firings[2] > 0);
+        // the trajectories record the firings between two time points
+        let mut p = birth_death.clone();
+        p.set_count_firings(true);
+        let mut trajectory = crate::gillespie::Trajectory::new(1);
+        for t in 0..=10 {
+            p.advance_until(t as f64);
+            trajectory.push(&p);
+            p.reset_firings();
+        }
+        assert_eq!(trajectory.firings.len(), 2);
+        assert_eq!(trajectory.firings[0][0], 0);
+        for i in 1..=10 {
+            let change = trajectory.species[0][i] - trajectory.species[0][i - 1];
+            let births = trajectory.firings[0][i] as isize;
+            assert_eq!(births - trajectory.firings[1][i] as isize, change);
+        }
+    }
+    #[test]
+    fn parameters() {
+        let mut p = Gillespie::new_with_seed([0, 0], 0);
+        let err = p.try_add_reaction(Rate::lma_param("k", [0, 0]), [1, 0]);
+        assert_eq!(err, Err(Error::UnknownParameter { name: "k".into() }));
+        assert_eq!(p.nb_reactions(), 0);
+        p.set_param("k", 5.);
+        p.add_reaction(Rate::lma_param("k", [0, 0]), [1, 0]);
+        p.add_reaction(Rate::lma(5., [0, 0]), [0, 1]);
+        p.advance_until(10.);
+        assert!(p.get_species(0) > 0);
+        // a disabled reaction follows its parameter once enabled
+        p.set_reaction_enabled(0, false);
+        p.set_param("k", 0.);
+        p.set_reaction_enabled(0, true);
+        let produced = p.get_species(0);
+        p.advance_until(20.);
+        assert_eq!(p.get_species(0), produced);
+        p.set_param("k", 5.);
+        p.advance_until(30.);
+        assert!(p.get_species(0) > produced);
+        // a new rate without parameter is not changed by them anymore
+        p.set_rate(0, Rate::lma(0., [0, 0]));
+        p.set_param("k", 1e3);
+        let produced = p.get_species(0);
+        p.advance_until(40.);
+        assert_eq!(p.get_species(0), produced);
+        let expr = Expr::Mul(
+            Box::new(Expr::Parameter("j".into())),
+            Box::new(Expr::Concentration(0)),
+        );
+        assert_eq!(
+            p.try_set_rate(1, Rate::Expr(expr.clone()))
+                .unwrap_err()
+                .to_string(),
+            "unknown parameter `j`"
+        );
+        p.set_param("j", 0.);
+        p.set_rate(1, Rate::Expr(expr));
+        let decayed = p.get_species(1);
+        p.advance_until(50.);
+        assert_eq!(p.get_species(1), decayed);
+        assert_eq!(p.get_param("j"), Some(0.));
+        assert_eq!(p.get_param("l"), None);
+    }
+    #[test]
+    #[should_panic(expected = "expected one value per species (1) but found 2")]
+    fn add_reaction_panics() {
+        let mut p = Gillespie::new([0]);
+        p.add_reaction(Rate::lma(1., [0]), [1, 0]);
+    }
+    #[test]
+    fn checked() {
+        let mut p = Gillespie::new_with_seed([10, 0], 0);
+        p.add_reaction(Rate::lma(1., [1, 0]), [-1, 1]);
+        p.add_reaction(Rate::lma(0.5, [0, 1]), [1, -1]);
+        p.advance_until_checked(10.).unwrap();
+        assert_eq!(p.get_time(), 10.);
+        assert_eq!(p.get_species(0) + p.get_species(1), 10);
+        // a negative rate
+        p.set_species([5, 5]);
+        p.set_rate(1, Rate::lma(-0.5, [0, 1]));
+        let err = p.advance_until_checked(20.).unwrap_err();
+        assert_eq!(
+            err,
+            Error::InvalidRate {
+                time: 10.,
+                reaction: 1,
+                rate: -2.5,
+                state: vec![5, 5]
+            }
+        );
+        // a NaN rate
+        p.set_rate(1, Rate::lma(f64::NAN, [0, 0]));
+        let err = p.advance_until_checked(20.).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("reaction 1 has the invalid rate NaN"));
+        // an infinite rate
+        p.set_rate(1, Rate::lma(f64::INFINITY, [0, 0]));
+        let err = p.advance_until_checked(20.).unwrap_err();
+        assert!(matches!(err, Error::InfiniteTotalRate { .. }));
+        // nothing can happen anymore
+        let mut q = Gillespie::new([0]);
+        q.add_reaction(Rate::lma(1., [1]), [-1]);
+        q.advance_until_checked(5.).unwrap();
+        assert_eq!(q.get_time(), 5.);
+    }
+    #[test]
+    fn representations() {
+        let mut p = Gillespie::new_with_seed([10, 10, 10, 10, 0, 0, 0, 0], 0);
+        // few reactants, but a quarter of the species change
+        p.add_reaction(
+            Rate::lma(1., [1, 0, 0, 0, 0, 0, 0, 0]),
+            [-1, 0, 0, 0, 1, 0, 0, 0],
+        );
+        // many reactants, but a single species changes
+        let rate = Rate::LMASparse(0.001, vec![(0, 1), (1, 1), (2, 1), (3, 1)]);
+        // SAFETY: the rate and the jump refer to existing species
+        unsafe { p.add_reaction_unchecked(rate, Jump::new_sparse([(5, 1)])) };
+        let reactions = p.reactions();
+        assert!(matches!(&reactions[0].0, Rate::LMASparse(_, v) if v == &[(0, 1)]));
+        assert!(matches!(&reactions[0].1, Jump::Flat(_)));
+        assert!(matches!(&reactions[1].0, Rate::LMA(_, v) if v == &[1, 1, 1, 1, 0, 0, 0, 0]));
+        assert!(matches!(&reactions[1].1, Jump::Sparse(v) if v == &[(5, 1)]));
+        p.advance_until(10.);
+        assert_eq!(p.get_species(0) + p.get_species(4), 10);
+        assert!(p.get_species(5) > 0);
+        p.set_rate(0, Rate::lma(1., [1, 1, 1, 1, 0, 0, 0, 0]));
+        assert!(matches!(&p.reactions()[0].0, Rate::LMA(..)));
+    }
+    #[test]
+    fn families() {
+        let mut p = Gillespie::new_with_seed([5, 0, 0, 0], 0);
+        assert_eq!(p.add_ring(&[0, 1, 2], 1.), 0..3);
+        assert_eq!(p.add_ring(&[], 1.), 3..3);
+        assert_eq!(p.add_chain(&[3], 1.), 3..3);
+        // dimerization of each species into the next one
+        let dimerizations = p.add_all_pairs(&[0, 1, 2, 3], |i, j| {
+            (i == j && i < 3).then_some((0.1, vec![i + 1]))
+        });
+        assert_eq!(dimerizations, 3..6);
+        assert_eq!(
+            p.stoichiometry_matrix(),
+            [
+                [-1, 0, 1, -2, 0, 0],
+                [1, -1, 0, 1, -2, 0],
+                [0, 1, -1, 0, 1, -2],
+                [0, 0, 0, 0, 0, 1],
+            ]
+        );
+        let mut q = Gillespie::new_with_seed([5, 0, 0, 0], 0);
+        q.add_reaction(Rate::lma(1., [1, 0, 0, 0]), [-1, 1, 0, 0]);
+        q.add_reaction(Rate::lma(1., [0, 1, 0, 0]), [0, -1, 1, 0]);
+        q.add_reaction(Rate::lma(1., [0, 0, 1, 0]), [1, 0, -1, 0]);
+        for i in 0..3 {
+            let mut reactants = [0; 4];
+            reactants[i] = 2;
+            let mut differences = [0; 4];
+            (differences[i], differences[i + 1]) = (-2, 1);
+            q.add_reaction(Rate::lma(0.1, reactants), differences);
+        }
+        p.advance_until(10.);
+        q.advance_until(10.);
+        assert_eq!(p.species(), q.species());
+    }
+    #[test]
+    #[should_panic]
+    fn family_unknown_species() {
+        Gillespie::new([0]).add_chain(&[0, 1], 1.);
+    }
+    #[test]
+    fn workspace() {
+        let mut problem = Gillespie::new_with_seed([50, 0, 0], 0);
+        problem.add_reaction(Rate::lma(1e-2, [1, 1, 0]), [-1, 1, 0]);
+        problem.add_reaction(Rate::lma(1., [0, 1, 0]), [0, -1, 1]);
+        problem.add_reaction(Rate::lma(0.5, [0, 0, 0]), [0, 1, 0]);
+        let mut workspace = Workspace::default();
+        for method in [
+            Method::Direct,
+            Method::FirstReaction,
+            Method::SortingDirect,
+            Method::RLeaping(4),
+        ] {
+            problem.set_method(method);
+            let mut fresh = problem.clone();
+            let mut reused = problem.clone().with_workspace(workspace);
+            for t in [1., 2., 5.] {
+                fresh.advance_until(t);
+                reused.advance_until(t);
+                assert_eq!(fresh.species(), reused.species());
+            }
+            workspace = reused.take_workspace();
+        }
+        assert!(workspace.rates.capacity() >= 3);
+    }
 }
@@ -7,21 +7,123 @@
 ///
 /// This macro creates a `struct` containing state variables, parameter
 /// values and a pseudo-random number generator.  The state variables
-/// and parameter values can be modified directly.  It implements three
-/// functions: `new`, `with_parameters` and `advance_until`.
+/// and parameter values can be modified directly.  It implements six
+/// functions: `new`, `with_parameters`, `advance_until`,
+/// `advance_n_reactions`, `advance_and_record` and `to_gillespie`.
 ///
 /// The function `new` creates a new instance of the structure with
 /// all state variables set to `0` and all parameter values set to
-/// `f64:NAN`.  The parameter values have then to be initialized
-/// manually.  If a `NAN` remains at the time of the simulation, no
-/// reaction will happen.
+/// `f64:NAN`, unless default values are given in the definition, as
+/// `S = 999` for species and `r_inf = 1e-4` for parameters (parameter
+/// defaults must be literals).  The other parameter values have then
+/// to be initialized manually.  If a `NAN` remains at the time of the
+/// simulation, no reaction will happen.
 ///
 /// The function `with_parameters` is an alternate initializer that
-/// allows to give directly all the parameter values.
+/// allows to give directly all the parameter values.  The state
+/// variables are set to their default values.
 ///
 /// The function `advance_until` simulates the system until the
 /// specified time.
 ///
+/// The function `advance_n_reactions` simulates the specified number of
+/// reactions, or fewer if the total rate becomes zero, and returns the
+/// number of reactions that fired.
+///
+/// The function `advance_and_record` simulates the system until the
+/// specified time and returns the state of the system at regularly
+/// spaced time points.  For a system named `Dimers`, it is returned as
+/// a `DimersTrace` structure with one vector per species and a vector
+/// `t` of the time points.
+///
+/// The structure also implements the trait
+/// [`SSASystem`](crate::system::SSASystem), shared with the
+/// function-based API.
+///
+/// The function `to_gillespie` converts the system in its current state
+/// into the equivalent problem of the function-based API
+/// [`Gillespie`](crate::gillespie::Gillespie), with the species in the
+/// order of the definition and the rates evaluated with the current
+/// parameter values.  It panics if the system has events.
+///
+/// # Events
+///
+/// Events can be listed after the reactions, separated from them by a
+/// `;`.  An event is written `event name @ (condition) { body }`: the
+/// first time that `condition` becomes true, `body` is executed.  Both
+/// can refer to species and parameters by their names, and the
+/// condition can also refer to the time `t` (as a `f64`).  Conditions
+/// that depend on time are detected at the precise time they become
+/// true, conditions that depend on species are checked after each
+/// reaction.  The conditions on time must stay true once they are true,
+/// like `t > 30.`: they are only checked at the end of the interval
+/// until the next reaction, before searching when they became true, so
+/// that a condition like `t > 30. && t < 31.` is missed if it becomes
+/// false again within this interval.  Each event happens at most once,
+/// and the structure has a boolean field of the same name, telling
+/// whether it already happened.
+///
+/// ```
+/// use rebop::define_system;
+///
+/// define_system! {
+///     r_inf r_heal;
+///     SIR { S, I, R }
+///     infection   : S + I => 2 I  @ r_inf
+///     healing     : I     => R    @ r_heal;
+///     event lockdown @ (t > 30.) { r_inf *= 0.3; }
+///     event eradication @ (I == 0) { S = 0; }
+/// }
+/// let mut sir = SIR::with_parameters(1e-4, 0.01);
+/// sir.S = 999;
+/// sir.I = 1;
+/// sir.advance_until(250.);
+/// assert!(sir.lockdown);
+/// ```
+///
+/// # Default values
+///
+/// ```
+/// use rebop::define_system;
+///
+/// define_system! {
+///     r_inf = 1e-4 r_heal = 0.01;
+///     SIR { S = 999, I = 1, R }
+///     infection   : S + I => 2 I  @ r_inf
+///     healing     : I     => R    @ r_heal
+/// }
+/// let mut sir = SIR::new();
+/// assert_eq!((sir.S, sir.I, sir.R), (999, 1, 0));
+/// sir.advance_until(250.);
+/// assert_eq!(sir.S + sir.I + sir.R, 1000);
+/// ```
+///
+/// # Stoichiometries
+///
+/// The stoichiometry of a species is written before its name, on either
+/// side of a reaction, as a positive integer literal (`2 protein`,
+/// `12 X => 3 Y`) or as an integer expression in parentheses
+/// (`(N + 1) protein`, where `N` is a constant).
+///
+/// # Diagnostics
+///
+/// The definition is checked at compile time: the names of the species,
+/// parameters, reactions and events must be unique, and the reactions
+/// must only involve known species.  Misspelled species are reported
+/// with a suggestion:
+///
+/// ```compile_fail
+/// use rebop::define_system;
+///
+/// define_system! {
+///     r_deg;
+///     Decay { protein }
+///     // error: unknown species `proteinn` in reaction `decay`, did you
+///     // mean `protein`?
+///     decay : proteinn => @ r_deg
+/// }
+/// ```
+///
 /// # Example
 ///
 /// ```
@@ -45,123 +147,17 @@
 /// dimers.gene = 1;
 /// dimers.advance_until(1.);
 /// println!("t = {}, dimer = {}", dimers.t, dimers.dimer);
+/// let trace: DimersTrace = dimers.advance_and_record(2., 10);
+/// assert_eq!(trace.t, [1., 1.1, 1.2, 1.3, 1.4, 1.5, 1.6, 1.7, 1.8, 1.9, 2.]);
+/// assert_eq!(trace.gene, [1; 11]);
+/// let mut problem = dimers.to_gillespie();
+/// assert_eq!(problem.nb_reactions(), 5);
+/// problem.advance_until(3.);
 /// ```
 #[macro_export]
 macro_rules! define_system {
-    (
-      $($param:ident)*;
-      $name:ident { $($species:ident),* }
-      $($rname:ident:
-          $($($nr:literal)? $r:ident)? $(+ $($tnr:literal)? $tr:ident)* =>
-          $($($np:literal)? $p:ident)? $(+ $($tnp:literal)? $tp:ident)*
-          @ $rate:expr)*
-      ) => {
-        /// Structure representing the problem, with the species and the time.
-        #[allow(non_snake_case)]
-        #[derive(Clone, Debug)]
-        struct $name {
-            $($species:isize,)*
-            $($param:f64,)*
-            t: f64,
-            rng: $crate::rand::rngs::SmallRng,
-        }
-        impl $name {
-            /// Constructs an object representing the problem.
-            fn new() -> Self {
-                use $crate::rand::{Rng, SeedableRng};
-                $name {
-                    $($species: 0,)*
-                    $($param: f64::NAN,)*
-                    t: 0.,
-                    rng: $crate::rand::rngs::SmallRng::from_entropy()
-                }
-            }
-            /// Seeds the random number generator.
-            fn seed(&mut self, seed: u64) {
-                use $crate::rand::{Rng, SeedableRng};
-                self.rng = $crate::rand::rngs::SmallRng::seed_from_u64(seed);
-            }
-            /// Constructs an object representing the problem,
-            /// specifying parameter values.
-            #[allow(non_snake_case)]
-            fn with_parameters($($param: f64),*) -> Self {
-                use $crate::rand::{Rng, SeedableRng};
-                $name {
-                    $($species: 0,)*
-                    $($param,)*
-                    t: 0.,
-                    rng: $crate::rand::rngs::SmallRng::from_entropy()
-                }
-            }
-            /// Simulates the problem until `t = tmax`.
-            #[allow(non_snake_case)]
-            fn advance_until(&mut self, tmax: f64) {
-                use $crate::rand::Rng;
-                $(let $param = self.$param;)*
-                $(let $species = self.$species as f64;)*
-                loop {
-                    $(let $rname = $rate $(* $crate::_rate_lma!($($nr)? * self.$r))? $(* $crate::_rate_lma!($($tnr)? * self.$tr) )*;)*
-                    let total_rate = 0. $(+ $rname)*;
-                    // we don't want to use partial_cmp, for performance
-                    #[allow(clippy::neg_cmp_op_on_partial_ord)]
-                    if !(total_rate > 0.) {
-                        self.t = tmax;
-                        return
-                    }
-                    self.t += self.rng.sample::<f64, _>($crate::rand_distr::Exp1) / total_rate;
-                    if self.t > tmax {
-                        self.t = tmax;
-                        return
-                    }
-                    let reaction_choice = total_rate * self.rng.gen::<f64>();
-                    $crate::_choice!(self reaction_choice 0.;
-                        $($rname:
-                            $($($nr)? $r)? $(+ $($tnr)? $tr)* =>
-                            $($($np)? $p)? $(+ $($tnp)? $tp)*;)*);
-                }
-            }
-        }
-    };
-}
-
-/// Auxiliary macro used in `define_system`.
-#[macro_export]
-macro_rules! _rate_lma {
-    ($($n:literal)? * $species:expr) => {
-        {
-            let mut rate = $species;
-            $(
-                for i in 1..$n {
-                    rate *= $species - i;
-                }
-            )?
-            rate as f64
-        }
-    }
-}
-
-/// Auxiliary macro used in `define_system`.
-#[macro_export]
-macro_rules! _choice {
-    ($self:ident $rc:ident $carry:expr; ) => {};
-    ($self:ident $rc:ident $carry:expr;
-     $rname:ident:
-     $($($nr:literal)? $r:ident)? $(+ $($tnr:literal)? $tr:ident)* =>
-     $($($np:literal)? $p:ident)? $(+ $($tnp:literal)? $tp:ident)*;
-     $($tail:ident:
-         $($($tailnr:literal)? $tailr:ident)? $(+ $($tailtnr:literal)? $tailtr:ident)* =>
-         $($($tailnp:literal)? $tailp:ident)? $(+ $($tailtnp:literal)? $tailtp:ident)*;)*) => {
-        if $rc < $carry + $rname {
-            $($self.$r -= 1 $(+ $nr - 1)?;)?
-            $($self.$tr -= 1 $(+ $tnr - 1)?;)*
-            $($self.$p += 1 $(+ $np - 1)?;)?
-            $($self.$tp += 1 $(+ $tnp - 1)?;)*
-        } else {
-            $crate::_choice!($self $rc $carry + $rname;
-                $($tail:
-                    $($($tailnr)? $tailr)? $(+ $($tailtnr)? $tailtr)* =>
-                    $($($tailnp)? $tailp)? $(+ $($tailtnp)? $tailtp)*;)*);
-        }
+    ($($tokens:tt)*) => {
+        $crate::__define_system! { $crate; $($tokens)* }
     };
 }
 
@@ -232,6 +228,172 @@ mod tests {
         assert_eq!(birth_death.A, 0);
     }
     #[test]
+    fn default_values() {
+        define_system! {
+            r_birth = 10 r_death = 0.1;
+            BirthDeath { A = 100, B, }
+            birth:      => A    @ r_birth
+            death:  A   => B    @ r_death
+        }
+        let birth_death = BirthDeath::new();
+        assert_eq!((birth_death.A, birth_death.B), (100, 0));
+        assert_eq!((birth_death.r_birth, birth_death.r_death), (10., 0.1));
+        let mut birth_death = BirthDeath::with_parameters(0., 1.);
+        assert_eq!(birth_death.A, 100);
+        birth_death.advance_until(100.);
+        assert_eq!((birth_death.A, birth_death.B), (0, 100));
+    }
+    #[test]
+    fn to_gillespie() {
+        define_system! {
+            r_inf r_heal;
+            SIR { S, I, R }
+            infection   : S + I => 2 I  @ r_inf
+            healing     : I     => R    @ 2. * r_heal
+        }
+        let mut sir = SIR::with_parameters(1e-4, 0.005);
+        sir.S = 999;
+        sir.I = 1;
+        sir.t = 1.;
+        let mut problem = sir.to_gillespie();
+        assert_eq!(problem.nb_species(), 3);
+        assert_eq!(problem.nb_reactions(), 2);
+        assert_eq!(problem.get_time(), 1.);
+        assert_eq!(problem.get_species(0), 999);
+        problem.advance_until(250.);
+        assert_eq!(
+            problem.get_species(0) + problem.get_species(1) + problem.get_species(2),
+            1000
+        );
+    }
+    #[test]
+    fn large_stoichiometries() {
+        define_system! {
+            r_forward r_backward;
+            Cluster { X = 120, Y }
+            forward     : 12 X  => 3 Y      @ r_forward
+            backward    : 3 Y   => 12 X     @ r_backward
+        }
+        let mut cluster = Cluster::with_parameters(1e-12, 1e-2);
+        cluster.advance_until(1000.);
+        assert_eq!(cluster.X % 4, 0);
+        assert_eq!(cluster.X + 4 * cluster.Y, 120);
+        let mut problem = cluster.to_gillespie();
+        problem.advance_until(2000.);
+        assert_eq!(problem.get_species(0) + 4 * problem.get_species(1), 120);
+    }
+    #[test]
+    fn stoichiometry_expressions() {
+        const N: isize = 3;
+        define_system! {
+            r_poly r_break;
+            Polymers { monomer = 30, polymer }
+            polymerization  : (N) monomer  => polymer           @ r_poly
+            breaking        : polymer      => (N) monomer       @ r_break
+        }
+        let mut polymers = Polymers::with_parameters(1., 0.1);
+        polymers.advance_until(100.);
+        assert_eq!(polymers.monomer + N * polymers.polymer, 30);
+        let mut problem = polymers.to_gillespie();
+        problem.advance_until(200.);
+        assert_eq!(problem.get_species(0) + N * problem.get_species(1), 30);
+    }
+    #[test]
+    fn record() {
+        define_system! {
+            r_inf r_heal;
+            SIR { S, I, R }
+            infection   : S + I => 2 I  @ r_inf
+            healing     : I     => R    @ r_heal
+        }
+        let mut sir = SIR::with_parameters(1e-4, 0.01);
+        sir.seed(0);
+        sir.S = 999;
+        sir.I = 1;
+        let trace = sir.advance_and_record(250., 250);
+        assert_eq!(trace.t.len(), 251);
+        assert_eq!(trace.t[0], 0.);
+        assert_eq!(trace.t[250], 250.);
+        for i in 0..251 {
+            assert_eq!(trace.t[i], i as f64);
+            assert_eq!(trace.S[i] + trace.I[i] + trace.R[i], 1000);
+        }
+        assert_eq!(
+            (trace.S[250], trace.I[250], trace.R[250]),
+            (sir.S, sir.I, sir.R)
+        );
+        let mut other = SIR::with_parameters(1e-4, 0.01);
+        other.seed(0);
+        other.S = 999;
+        other.I = 1;
+        let mut expected = SIRTrace::default();
+        for t in 0..=250 {
+            other.advance_until(t as f64);
+            expected.t.push(other.t);
+            expected.S.push(other.S);
+            expected.I.push(other.I);
+            expected.R.push(other.R);
+        }
+        assert_eq!(trace, expected);
+    }
+    #[test]
+    fn n_reactions() {
+        define_system! {
+            r_dim r_undim;
+            Dimers { A = 100, A2 }
+            dimerization    : 2 A   => A2   @ r_dim
+            dissociation    : A2    => 2 A  @ r_undim
+        }
+        let mut dimers = Dimers::with_parameters(0.01, 0.);
+        dimers.seed(0);
+        assert_eq!(dimers.advance_n_reactions(10), 10);
+        assert_eq!((dimers.A, dimers.A2), (80, 10));
+        assert!(dimers.t > 0.);
+        assert_eq!(dimers.advance_n_reactions(100), 40);
+        assert_eq!((dimers.A, dimers.A2), (0, 50));
+        assert!(dimers.t.is_finite());
+    }
+    #[test]
+    fn timed_event() {
+        define_system! {
+            r_inf r_heal;
+            SIR { S, I, R }
+            infection   : S + I => 2 I  @ r_inf
+            healing     : I     => R    @ r_heal;
+            event lockdown @ (t > 30.) { r_inf = 0.; }
+        }
+        let mut sir = SIR::with_parameters(1e-4, 0.01);
+        sir.seed(0);
+        sir.S = 999;
+        sir.I = 1;
+        sir.advance_until(30.);
+        assert!(!sir.lockdown);
+        let s = sir.S;
+        sir.advance_until(250.);
+        assert!(sir.lockdown);
+        assert_eq!(sir.r_inf, 0.);
+        assert_eq!(sir.S, s);
+    }
+    #[test]
+    fn state_events() {
+        define_system! {
+            r_birth;
+            Birth { A, B }
+            birth:  => A    @ r_birth;
+            event stop @ (A >= 10) { r_birth = 0.; }
+            event reset @ (t > 1000. && B == 0) { A = 0; B = 1; }
+        }
+        let mut birth = Birth::with_parameters(1.);
+        birth.advance_until(500.);
+        assert!(birth.stop);
+        assert!(!birth.reset);
+        assert_eq!(birth.A, 10);
+        // time events are detected even without any reaction
+        birth.advance_until(2000.);
+        assert!(birth.reset);
+        assert_eq!((birth.A, birth.B), (0, 1));
+    }
+    #[test]
     fn no_reactions() {
         define_system! {
             ;
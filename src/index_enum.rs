@@ -0,0 +1,241 @@
+//! Macros to designate species by name in the function-based API.
+//!
+//! See [`index_enum`], [`jump`] and [`reactants`].
+
+/// Definition of an enumeration of species names, usable as indices
+/// in the function-based API.
+///
+/// The variants are numbered in the order of their declaration, and
+/// the enumeration implements [`SpeciesIndex`](crate::gillespie::SpeciesIndex)
+/// so that its variants can be given wherever a species index is
+/// expected.  It also gets a few associated items:
+///
+/// * `COUNT`, the number of variants;
+/// * `ALL`, an array of all the variants in order;
+/// * `index(self)`, the index of a variant;
+/// * `name(self)`, the name of a variant;
+/// * `vector(entries)`, a dense array of values indexed by the
+///   variants, built from `(variant, value)` pairs, the other values
+///   being zero.
+///
+/// Building the dense vectors of `Rate::lma` and `add_reaction` from
+/// names makes them robust to reordering of the species.
+///
+/// # Example
+///
+/// ```
+/// use rebop::gillespie::{Gillespie, Rate};
+/// use rebop::index_enum;
+///
+/// index_enum! {
+///     enum Species { S, I, R }
+/// }
+/// use Species::*;
+///
+/// let mut sir = Gillespie::new(Species::vector([(S, 999), (I, 1)]));
+/// sir.add_reaction(
+///     Rate::lma(1e-4, Species::vector([(S, 1), (I, 1)])),
+///     Species::vector([(S, -1), (I, 1)]),
+/// );
+/// sir.add_reaction(
+///     Rate::lma(0.01, Species::vector([(I, 1)])),
+///     Species::vector([(I, -1), (R, 1)]),
+/// );
+/// sir.advance_until(250.);
+/// assert_eq!(sir.get_species(S) + sir.get_species(I) + sir.get_species(R), 1000);
+/// ```
+#[macro_export]
+macro_rules! index_enum {
+    ($(#[$meta:meta])* $vis:vis enum $name:ident { $($variant:ident),* $(,)? }) => {
+        $(#[$meta])*
+        #[allow(non_camel_case_types)]
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        $vis enum $name {
+            $($variant,)*
+        }
+        #[allow(dead_code)]
+        impl $name {
+            /// Number of species.
+            pub const COUNT: usize = [$(stringify!($variant)),*].len();
+            /// All the species, in order.
+            pub const ALL: [Self; Self::COUNT] = [$(Self::$variant),*];
+            /// Returns the index of the species.
+            pub const fn index(self) -> usize {
+                self as usize
+            }
+            /// Returns the name of the species.
+            pub const fn name(self) -> &'static str {
+                match self {
+                    $(Self::$variant => stringify!($variant),)*
+                }
+            }
+            /// Returns a dense array from `(species, value)` pairs, the
+            /// values of the other species being zero.
+            pub fn vector<T, V>(entries: V) -> [T; Self::COUNT]
+            where
+                T: Copy + Default,
+                V: IntoIterator<Item = (Self, T)>,
+            {
+                let mut vector = [T::default(); Self::COUNT];
+                for (species, value) in entries {
+                    vector[species as usize] = value;
+                }
+                vector
+            }
+        }
+        impl $crate::gillespie::SpeciesIndex for $name {
+            fn index(self) -> usize {
+                self as usize
+            }
+        }
+        impl $crate::gillespie::SpeciesEnum for $name {
+            const COUNT: usize = $name::COUNT;
+        }
+    };
+}
+
+/// Vector of species differences caused by a reaction.
+///
+/// The species are designated by the variants of an enumeration
+/// defined with [`index_enum`], and the differences are given after a
+/// colon, with an optional `+` sign.  The other species are unchanged.
+/// Three forms are accepted:
+///
+/// * `jump![A: -1, R: +1]` returns a dense `Vec<isize>`, as expected by
+///   `Gillespie::add_reaction`;
+/// * `jump![sparse; A: -1, R: +1]` returns a sparse `Vec<(usize,
+///   isize)>`, as expected by `Jump::new_sparse`;
+/// * `jump![names; "A": -1, "R": +1]` returns a dense `Vec<isize>`
+///   where the species are designated by their names in the list of
+///   species names `names`.
+///
+/// ```
+/// use rebop::{index_enum, jump};
+/// index_enum! {
+///     enum Species { S, I, R }
+/// }
+/// use Species::*;
+/// assert_eq!(jump![I: -1, R: +1], [0, -1, 1]);
+/// assert_eq!(jump![sparse; I: -1, R: +1], [(1, -1), (2, 1)]);
+/// assert_eq!(jump![["S", "I", "R"]; "S": -1, "I": 1], [-1, 1, 0]);
+/// ```
+#[macro_export]
+macro_rules! jump {
+    (sparse; $($species:path : $(+)? $value:expr),* $(,)?) => {
+        $crate::gillespie::sparse::<_, isize, _>([$(($species, $value)),*])
+    };
+    ($($species:path : $(+)? $value:expr),* $(,)?) => {
+        $crate::gillespie::dense::<_, isize, _>([$(($species, $value)),*])
+    };
+    ($names:expr; $($species:literal : $(+)? $value:expr),* $(,)?) => {
+        $crate::gillespie::dense_by_name::<_, isize, _>(&$names, [$(($species, $value)),*])
+    };
+}
+
+/// Vector of reactant stoichiometries of a reaction.
+///
+/// It accepts the same three forms as [`jump`]: the dense form
+/// `reactants![S: 1, I: 1]` returns a `Vec<u32>` as expected by
+/// `Rate::lma`, the sparse form `reactants![sparse; S: 1, I: 1]`
+/// returns a `Vec<(u32, u32)>` as expected by `Rate::LMASparse`, and
+/// `reactants![names; "S": 1, "I": 1]` designates species by name.
+///
+/// ```
+/// use rebop::gillespie::{Gillespie, Rate};
+/// use rebop::{index_enum, jump, reactants};
+/// index_enum! {
+///     enum Species { S, I, R }
+/// }
+/// use Species::*;
+/// let mut sir = Gillespie::new([999, 1, 0]);
+/// sir.add_reaction(Rate::lma(1e-4, reactants![S: 1, I: 1]), jump![S: -1, I: +1]);
+/// sir.add_reaction(Rate::lma(0.01, reactants![I: 1]), jump![I: -1, R: +1]);
+/// assert_eq!(reactants![sparse; I: 1], [(1, 1)]);
+/// ```
+#[macro_export]
+macro_rules! reactants {
+    (sparse; $($species:path : $(+)? $value:expr),* $(,)?) => {
+        $crate::gillespie::sparse::<_, u32, _>([$(($species, $value)),*])
+            .into_iter()
+            .map(|(index, value)| (index as u32, value))
+            .collect::<Vec<(u32, u32)>>()
+    };
+    ($($species:path : $(+)? $value:expr),* $(,)?) => {
+        $crate::gillespie::dense::<_, u32, _>([$(($species, $value)),*])
+    };
+    ($names:expr; $($species:literal : $(+)? $value:expr),* $(,)?) => {
+        $crate::gillespie::dense_by_name::<_, u32, _>(&$names, [$(($species, $value)),*])
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gillespie::{Gillespie, Jump, Rate};
+
+    index_enum! {
+        enum Dimers { G, M, P, D }
+    }
+
+    #[test]
+    fn dimers() {
+        use Dimers::*;
+        assert_eq!(Dimers::COUNT, 4);
+        assert_eq!(Dimers::ALL, [G, M, P, D]);
+        assert_eq!((P.index(), P.name()), (2, "P"));
+        assert_eq!(Dimers::vector([(P, -2), (D, 1)]), [0, 0, -2, 1]);
+        let mut dimers = Gillespie::new(Dimers::vector([(G, 1)]));
+        dimers.add_reaction(
+            Rate::lma(25., Dimers::vector([(G, 1)])),
+            Dimers::vector([(M, 1)]),
+        );
+        dimers.add_reaction(
+            Rate::lma(1000., Dimers::vector([(M, 1)])),
+            Dimers::vector([(P, 1)]),
+        );
+        dimers.add_reaction(
+            Rate::lma(0.001, Dimers::vector([(P, 2)])),
+            Dimers::vector([(P, -2), (D, 1)]),
+        );
+        dimers.add_reaction(
+            Rate::lma(0.1, Dimers::vector([(M, 1)])),
+            Dimers::vector([(M, -1)]),
+        );
+        dimers.add_reaction(
+            Rate::lma(1., Dimers::vector([(P, 1)])),
+            Dimers::vector([(P, -1)]),
+        );
+        dimers.advance_until(1.);
+        assert_eq!(dimers.get_species(G), 1);
+        assert!(1000 < dimers.get_species(P));
+        assert!(dimers.get_species(D) < 10000);
+    }
+    #[test]
+    fn jumps_and_reactants() {
+        use Dimers::*;
+        assert_eq!(jump![P: -2, D: +1], Dimers::vector([(P, -2), (D, 1)]));
+        assert_eq!(reactants![P: 2], Dimers::vector([(P, 2)]));
+        assert_eq!(jump![Dimers::M: -1], [0, -1, 0, 0]);
+        assert!(matches!(
+            Jump::new_sparse(jump![sparse; P: -2, D: 1]),
+            Jump::Sparse(v) if v == [(2, -2), (3, 1)]
+        ));
+        assert!(matches!(
+            Rate::LMASparse(1., reactants![sparse; P: 2]),
+            Rate::LMASparse(_, v) if v == [(2, 2)]
+        ));
+        let names = ["G", "M", "P", "D"];
+        assert_eq!(jump![names; "P": -2, "D": 1], [0, 0, -2, 1]);
+        assert_eq!(reactants![names; "M": 1], [0, 1, 0, 0]);
+    }
+    #[test]
+    #[should_panic(expected = "unknown species `X`")]
+    fn unknown_name() {
+        jump![["A", "B"]; "X": 1];
+    }
+    #[test]
+    #[should_panic(expected = "given twice")]
+    fn duplicate_species() {
+        use Dimers::*;
+        jump![P: -1, P: 1];
+    }
+}
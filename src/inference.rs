@@ -0,0 +1,471 @@
+//! Approximate Bayesian computation.
+//!
+//! The likelihood of stochastic models is rarely tractable, but they
+//! are cheap to simulate.  Approximate Bayesian computation (ABC)
+//! replaces the likelihood by simulations: parameters drawn from a
+//! [`Prior`] are kept when a simulation with them is closer to the
+//! [`Observations`] than a threshold, according to a distance function
+//! like [`euclidean`].
+//!
+//! [`abc_rejection`] draws all the parameters from the prior, and
+//! [`abc_smc`] refines a population of particles over decreasing
+//! thresholds with the sequential Monte Carlo algorithm of Toni et al.
+//! (2009), which needs far fewer simulations.  Both spread the
+//! simulations over all available threads, with random numbers drawn
+//! from [`Philox`] streams derived from a seed, so that the results do
+//! not depend on the number of threads.
+//!
+//! ```
+//! use rebop::gillespie::{Gillespie, Rate};
+//! use rebop::inference::{abc_smc, euclidean, Observations, UniformPrior};
+//! let make = |params: &[f64]| {
+//!     let mut p = Gillespie::new([0]);
+//!     p.add_reaction(Rate::lma(params[0], [0]), [1]);
+//!     p
+//! };
+//! // births at rate 10
+//! let observed = Observations::simulate(&make(&[10.]), &[5., 10.], &[0], 0);
+//! let prior = UniformPrior(vec![(0., 50.)]);
+//! let posterior = abc_smc(&prior, make, &observed, euclidean, 100, &[50., 20., 10.], 0);
+//! assert!((posterior.mean()[0] - 10.).abs() < 3.);
+//! ```
+
+use std::ops::Range;
+use std::{panic, thread};
+
+use rand::{Rng, RngCore};
+use rand_distr::StandardNormal;
+
+use crate::rng::Philox;
+use crate::system::SSASystem;
+
+/// Prior distribution of the parameters.
+pub trait Prior: Sync {
+    /// Draws values of the parameters.
+    fn sample(&self, rng: &mut dyn RngCore) -> Vec<f64>;
+    /// Returns the density of the distribution at `params`, up to a
+    /// constant factor, which is zero outside of its support.
+    fn density(&self, params: &[f64]) -> f64;
+}
+
+/// Independent uniform distributions of the parameters between the
+/// given bounds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UniformPrior(pub Vec<(f64, f64)>);
+
+impl Prior for UniformPrior {
+    fn sample(&self, rng: &mut dyn RngCore) -> Vec<f64> {
+        self.0
+            .iter()
+            .map(|&(low, high)| low + (high - low) * rng.gen::<f64>())
+            .collect()
+    }
+    fn density(&self, params: &[f64]) -> f64 {
+        let inside = self
+            .0
+            .iter()
+            .zip(params)
+            .all(|(&(low, high), p)| (low..=high).contains(p));
+        if inside {
+            1.
+        } else {
+            0.
+        }
+    }
+}
+
+/// Observed amounts of some species over time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Observations {
+    /// Time points.
+    pub times: Vec<f64>,
+    /// Indices of the observed species.
+    pub observables: Vec<usize>,
+    /// Observed amounts, indexed by observable and time.
+    pub values: Vec<Vec<f64>>,
+}
+
+impl Observations {
+    /// Simulates `problem` with the seed `seed` and observes the species
+    /// of indices `observables` at the `times`, for example to test an
+    /// inference on synthetic data, or to compare a simulation to
+    /// observations.
+    pub fn simulate<P: SSASystem + Clone>(
+        problem: &P,
+        times: &[f64],
+        observables: &[usize],
+        seed: u64,
+    ) -> Self {
+        let mut problem = problem.clone();
+        problem.seed(seed);
+        let mut values = vec![Vec::with_capacity(times.len()); observables.len()];
+        for &t in times {
+            problem.advance_until(t);
+            let species = problem.species();
+            for (v, &o) in values.iter_mut().zip(observables) {
+                v.push(species[o] as f64);
+            }
+        }
+        Observations {
+            times: times.to_vec(),
+            observables: observables.to_vec(),
+            values,
+        }
+    }
+}
+
+/// Euclidean distance between simulated and observed amounts, indexed by
+/// observable and time.
+pub fn euclidean(simulated: &[Vec<f64>], observed: &[Vec<f64>]) -> f64 {
+    simulated
+        .iter()
+        .flatten()
+        .zip(observed.iter().flatten())
+        .map(|(s, o)| (s - o) * (s - o))
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// Weighted sample of the approximate posterior distribution of the
+/// parameters, computed by [`abc_rejection`] or [`abc_smc`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct AbcPosterior {
+    /// Accepted values of the parameters.
+    pub particles: Vec<Vec<f64>>,
+    /// Normalized weights of the particles.
+    pub weights: Vec<f64>,
+    /// Distance of the simulation of each particle to the observations.
+    pub distances: Vec<f64>,
+    /// Threshold of the distances.
+    pub epsilon: f64,
+    /// Total number of simulations.
+    pub nb_simulations: u64,
+}
+
+impl AbcPosterior {
+    /// Returns the weighted mean of each parameter, none without
+    /// particles.
+    pub fn mean(&self) -> Vec<f64> {
+        let nb_params = self.particles.first().map_or(0, Vec::len);
+        (0..nb_params)
+            .map(|k| {
+                self.particles
+                    .iter()
+                    .zip(&self.weights)
+                    .map(|(p, w)| p[k] * w)
+                    .sum()
+            })
+            .collect()
+    }
+    /// Returns the weighted variance of each parameter.
+    pub fn variance(&self) -> Vec<f64> {
+        self.mean()
+            .iter()
+            .enumerate()
+            .map(|(k, mean)| {
+                self.particles
+                    .iter()
+                    .zip(&self.weights)
+                    .map(|(p, w)| w * (p[k] - mean) * (p[k] - mean))
+                    .sum()
+            })
+            .collect()
+    }
+}
+
+/// Draws `nb_simulations` values of the parameters from `prior`,
+/// simulates the problem built by `make` for each one, and keeps those
+/// whose simulation is at a `distance` of at most `epsilon` from the
+/// observations.
+///
+/// `make` builds the problem, in its initial state, from values of the
+/// parameters, with either of the APIs of rebop (see [`SSASystem`]).
+/// The accepted particles all have the same weight.
+pub fn abc_rejection<P, F, D>(
+    prior: &dyn Prior,
+    make: F,
+    observed: &Observations,
+    distance: D,
+    nb_simulations: usize,
+    epsilon: f64,
+    seed: u64,
+) -> AbcPosterior
+where
+    P: SSASystem,
+    F: Fn(&[f64]) -> P + Sync,
+    D: Fn(&[Vec<f64>], &[Vec<f64>]) -> f64 + Sync,
+{
+    let results = parallel_map(nb_simulations, |i| {
+        let mut rng = Philox::new(seed, i as u64);
+        let params = prior.sample(&mut rng);
+        let simulated = simulate(&make, &params, observed, rng.next_u64());
+        (params, distance(&simulated, &observed.values))
+    });
+    let (particles, distances): (Vec<_>, Vec<_>) =
+        results.into_iter().filter(|&(_, d)| d <= epsilon).unzip();
+    let weights = vec![1. / particles.len() as f64; particles.len()];
+    AbcPosterior {
+        particles,
+        weights,
+        distances,
+        epsilon,
+        nb_simulations: nb_simulations as u64,
+    }
+}
+
+/// Samples `nb_particles` particles from the approximate posterior
+/// distribution of the parameters with the sequential Monte Carlo
+/// algorithm of Toni et al. (2009), for the decreasing thresholds
+/// `epsilons`.
+///
+/// The first population is drawn from `prior` with the first threshold.
+/// Each next one is drawn from the previous one, perturbed with a
+/// Gaussian kernel whose variance is twice the weighted variance of the
+/// previous population (Beaumont et al., 2009), and weighted by the
+/// ratio of the prior density to the density of the proposal.  Each
+/// population is simulated until `nb_particles` particles are accepted,
+/// so a threshold that is too small makes it run for a long time.
+///
+/// # Panics
+///
+/// Panics if there is no threshold or no particle.
+pub fn abc_smc<P, F, D>(
+    prior: &dyn Prior,
+    make: F,
+    observed: &Observations,
+    distance: D,
+    nb_particles: usize,
+    epsilons: &[f64],
+    seed: u64,
+) -> AbcPosterior
+where
+    P: SSASystem,
+    F: Fn(&[f64]) -> P + Sync,
+    D: Fn(&[Vec<f64>], &[Vec<f64>]) -> f64 + Sync,
+{
+    assert!(!epsilons.is_empty(), "no threshold");
+    assert!(nb_particles > 0, "no particle");
+    let mut posterior: Option<AbcPosterior> = None;
+    let mut nb_simulations = 0;
+    for (generation, &epsilon) in epsilons.iter().enumerate() {
+        let previous = posterior.as_ref();
+        let scales: Vec<f64> = previous.map_or(Vec::new(), |p| {
+            p.variance().iter().map(|v| (2. * v).sqrt()).collect()
+        });
+        let results = parallel_map(nb_particles, |i| {
+            let mut rng = Philox::new(seed, (generation as u64) << 32 | i as u64);
+            let mut attempts = 0;
+            loop {
+                let params = match previous {
+                    None => prior.sample(&mut rng),
+                    Some(previous) => {
+                        let chosen = choose(&previous.weights, rng.gen());
+                        previous.particles[chosen]
+                            .iter()
+                            .zip(&scales)
+                            .map(|(p, scale)| p + scale * rng.sample::<f64, _>(StandardNormal))
+                            .collect()
+                    }
+                };
+                if prior.density(&params) <= 0. {
+                    continue;
+                }
+                attempts += 1;
+                let simulated = simulate(&make, &params, observed, rng.next_u64());
+                let d = distance(&simulated, &observed.values);
+                if d <= epsilon {
+                    return (params, d, attempts);
+                }
+            }
+        });
+        nb_simulations += results.iter().map(|r| r.2).sum::<u64>();
+        let (particles, distances): (Vec<_>, Vec<_>) =
+            results.into_iter().map(|(p, d, _)| (p, d)).unzip();
+        let mut weights: Vec<f64> = match previous {
+            None => vec![1.; nb_particles],
+            Some(previous) => particles
+                .iter()
+                .map(|particle| {
+                    let proposal: f64 = previous
+                        .particles
+                        .iter()
+                        .zip(&previous.weights)
+                        .map(|(p, w)| w * kernel(particle, p, &scales))
+                        .sum();
+                    prior.density(particle) / proposal
+                })
+                .collect(),
+        };
+        let total: f64 = weights.iter().sum();
+        weights.iter_mut().for_each(|w| *w /= total);
+        posterior = Some(AbcPosterior {
+            particles,
+            weights,
+            distances,
+            epsilon,
+            nb_simulations,
+        });
+    }
+    posterior.unwrap()
+}
+
+/// Simulates the problem built by `make` with the parameters `params`
+/// and the seed `seed`, and returns the observed amounts.
+fn simulate<P, F>(make: &F, params: &[f64], observed: &Observations, seed: u64) -> Vec<Vec<f64>>
+where
+    P: SSASystem,
+    F: Fn(&[f64]) -> P,
+{
+    let mut problem = make(params);
+    problem.seed(seed);
+    let mut values = vec![Vec::with_capacity(observed.times.len()); observed.observables.len()];
+    for &t in &observed.times {
+        problem.advance_until(t);
+        let species = problem.species();
+        for (v, &o) in values.iter_mut().zip(&observed.observables) {
+            v.push(species[o] as f64);
+        }
+    }
+    values
+}
+
+/// Returns the index chosen by the uniform number `uniform` among the
+/// normalized `weights`.
+fn choose(weights: &[f64], mut uniform: f64) -> usize {
+    weights
+        .iter()
+        .position(|&w| {
+            uniform -= w;
+            uniform < 0.
+        })
+        .unwrap_or(weights.len() - 1)
+}
+
+/// Density of the Gaussian kernel of standard deviations `scales`
+/// centered on `center` at `x`, up to a constant factor.
+fn kernel(x: &[f64], center: &[f64], scales: &[f64]) -> f64 {
+    let exponent: f64 = x
+        .iter()
+        .zip(center)
+        .zip(scales)
+        .filter(|(_, &scale)| scale > 0.)
+        .map(|((x, c), scale)| ((x - c) / scale).powi(2))
+        .sum();
+    (-exponent / 2.).exp()
+}
+
+/// Computes `f(i)` for `i` in `0..n`, spread over all available threads.
+pub(crate) fn parallel_map<T, F>(n: usize, f: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(usize) -> T + Sync,
+{
+    parallel_chunks(n, |indices| indices.map(&f).collect::<Vec<_>>())
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+/// Splits `0..n` into consecutive ranges, one per available thread, and
+/// returns `f(range)` for each of them, in order, for the computations
+/// that reduce their results as they go.
+pub(crate) fn parallel_chunks<T, F>(n: usize, f: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(Range<usize>) -> T + Sync,
+{
+    let nb_threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = n.div_ceil(nb_threads).max(1);
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..n)
+            .step_by(chunk_size)
+            .map(|start| {
+                let f = &f;
+                scope.spawn(move || f(start..(start + chunk_size).min(n)))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|panic| panic::resume_unwind(panic))
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gillespie::{Gillespie, Rate};
+    use crate::inference::{
+        abc_rejection, abc_smc, choose, euclidean, kernel, Observations, Prior, UniformPrior,
+    };
+    use crate::rng::Philox;
+
+    fn birth_death(params: &[f64]) -> Gillespie {
+        let mut p = Gillespie::new([0]);
+        p.add_reaction(Rate::lma(params[0], [0]), [1]);
+        p.add_reaction(Rate::lma(params[1], [1]), [-1]);
+        p
+    }
+
+    #[test]
+    fn helpers() {
+        let prior = UniformPrior(vec![(0., 1.), (10., 20.)]);
+        let mut rng = Philox::new(0, 0);
+        for _ in 0..100 {
+            let params = prior.sample(&mut rng);
+            assert_eq!(prior.density(&params), 1.);
+        }
+        assert_eq!(prior.density(&[0.5, 21.]), 0.);
+        assert_eq!(euclidean(&[vec![1., 2.]], &[vec![4., 6.]]), 5.);
+        assert_eq!(choose(&[0.25, 0.5, 0.25], 0.2), 0);
+        assert_eq!(choose(&[0.25, 0.5, 0.25], 0.7), 1);
+        assert_eq!(choose(&[0.25, 0.5, 0.25], 1.), 2);
+        assert_eq!(kernel(&[1., 5.], &[1., 3.], &[1., 0.]), 1.);
+        assert_eq!(kernel(&[3.], &[1.], &[2.]), (-0.5_f64).exp());
+    }
+
+    #[test]
+    fn rejection() {
+        let observed = Observations::simulate(&birth_death(&[10., 0.1]), &[5., 20.], &[0], 0);
+        let prior = UniformPrior(vec![(0., 40.), (0.1, 0.1)]);
+        let posterior = abc_rejection(&prior, birth_death, &observed, euclidean, 2000, 15., 0);
+        assert_eq!(posterior.nb_simulations, 2000);
+        assert!(!posterior.particles.is_empty());
+        assert!(posterior.distances.iter().all(|&d| d <= 20.));
+        assert!((posterior.weights.iter().sum::<f64>() - 1.).abs() < 1e-12);
+        let mean = posterior.mean();
+        assert!((mean[0] - 10.).abs() < 3., "{mean:?}");
+        assert!((mean[1] - 0.1).abs() < 1e-12);
+        // the seed determines the results
+        let again = abc_rejection(&prior, birth_death, &observed, euclidean, 2000, 15., 0);
+        assert_eq!(posterior, again);
+    }
+
+    #[test]
+    fn smc() {
+        let observed = Observations::simulate(&birth_death(&[10., 0.1]), &[5., 10., 20.], &[0], 1);
+        let prior = UniformPrior(vec![(0., 40.), (0., 1.)]);
+        let epsilons = [100., 50., 30., 20.];
+        let posterior = abc_smc(&prior, birth_death, &observed, euclidean, 100, &epsilons, 0);
+        assert_eq!(posterior.particles.len(), 100);
+        assert_eq!(posterior.epsilon, 20.);
+        assert!(posterior.distances.iter().all(|&d| d <= 20.));
+        assert!(posterior.nb_simulations >= 400);
+        assert!((posterior.weights.iter().sum::<f64>() - 1.).abs() < 1e-12);
+        // the stationary mean k / γ is much better identified than k or γ
+        let ratios: f64 = posterior
+            .particles
+            .iter()
+            .zip(&posterior.weights)
+            .map(|(p, w)| w * p[0] / p[1])
+            .sum();
+        assert!((ratios - 100.).abs() < 25., "{ratios}");
+        let prior_variance = 40. * 40. / 12.;
+        assert!(posterior.variance()[0] < prior_variance);
+        let again = abc_smc(&prior, birth_death, &observed, euclidean, 100, &epsilons, 0);
+        assert_eq!(posterior, again);
+    }
+}
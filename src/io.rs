@@ -0,0 +1,716 @@
+//! Streaming of trajectories to files.
+//!
+//! Long trajectories, like those of single cells over many generations,
+//! do not fit in memory.  The writers of this module receive the rows
+//! `(t, species...)` of a trajectory during the simulation and write them
+//! by batches of [`DEFAULT_BATCH_SIZE`] rows: [`CsvWriter`] to CSV, and
+//! [`IpcWriter`] and [`ParquetWriter`] to Arrow IPC and Parquet files
+//! with the `arrow` and `parquet` features.  [`Background`] writes the
+//! batches of any writer in another thread, so that the simulation does
+//! not wait for the disk.
+//!
+//! ```
+//! use rebop::gillespie::{Gillespie, Rate};
+//! use rebop::io::{write_trajectory, CsvWriter, TrajectoryWriter};
+//!
+//! let mut decay = Gillespie::new_with_seed([10, 0], 0);
+//! decay.add_reaction(Rate::lma(1., [1, 0]), [-1, 1]);
+//! let mut writer = CsvWriter::new(Vec::new(), &["A", "B"]);
+//! write_trajectory(&mut decay, &[0., 1., 2.], &mut writer).unwrap();
+//! writer.finish().unwrap();
+//! let csv = String::from_utf8(writer.into_inner()).unwrap();
+//! assert!(csv.starts_with("time,A,B\n0,10,0\n1,"));
+//! ```
+
+use std::fmt;
+use std::io::Write;
+use std::sync::mpsc;
+use std::thread;
+
+use rand::{Rng, SeedableRng};
+
+use crate::gillespie::Gillespie;
+
+/// Number of rows that the writers buffer before writing them.
+pub const DEFAULT_BATCH_SIZE: usize = 8192;
+
+/// Errors of the writers of trajectories.
+#[derive(Debug)]
+pub enum WriteError {
+    /// The underlying writer failed.
+    Io(std::io::Error),
+    /// The Arrow or Parquet encoder failed.
+    Format(String),
+    /// A row does not have one amount per species.
+    RowLength { expected: usize, found: usize },
+    /// A row was written after [`TrajectoryWriter::finish`].
+    Finished,
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteError::Io(e) => write!(f, "cannot write trajectory: {e}"),
+            WriteError::Format(msg) => write!(f, "cannot encode trajectory: {msg}"),
+            WriteError::RowLength { expected, found } => write!(
+                f,
+                "expected one amount per species ({expected}) but found {found}"
+            ),
+            WriteError::Finished => write!(f, "the trajectory is already finished"),
+        }
+    }
+}
+
+impl std::error::Error for WriteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WriteError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for WriteError {
+    fn from(e: std::io::Error) -> Self {
+        WriteError::Io(e)
+    }
+}
+
+#[cfg(feature = "arrow")]
+impl From<arrow_schema::ArrowError> for WriteError {
+    fn from(e: arrow_schema::ArrowError) -> Self {
+        WriteError::Format(e.to_string())
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl From<parquet::errors::ParquetError> for WriteError {
+    fn from(e: parquet::errors::ParquetError) -> Self {
+        WriteError::Format(e.to_string())
+    }
+}
+
+/// Destination of the rows `(t, species...)` of a trajectory.
+///
+/// The rows are buffered: they are only guaranteed to be written after
+/// [`flush`](TrajectoryWriter::flush) or
+/// [`finish`](TrajectoryWriter::finish).  The writers of this module
+/// finish when they are dropped, but ignore the errors then.
+pub trait TrajectoryWriter {
+    /// Writes the amounts `species` of the species at time `t`.
+    fn write_row(&mut self, t: f64, species: &[isize]) -> Result<(), WriteError>;
+    /// Writes the buffered rows and flushes the underlying writer.
+    fn flush(&mut self) -> Result<(), WriteError>;
+    /// Writes the buffered rows and the end of the file, if the format
+    /// has one.  No row can be written afterwards, and finishing again
+    /// does nothing.
+    fn finish(&mut self) -> Result<(), WriteError>;
+}
+
+impl<T: TrajectoryWriter + ?Sized> TrajectoryWriter for Box<T> {
+    fn write_row(&mut self, t: f64, species: &[isize]) -> Result<(), WriteError> {
+        (**self).write_row(t, species)
+    }
+    fn flush(&mut self) -> Result<(), WriteError> {
+        (**self).flush()
+    }
+    fn finish(&mut self) -> Result<(), WriteError> {
+        (**self).finish()
+    }
+}
+
+/// Simulates `problem` and writes its state at the time points `times`,
+/// which must be sorted, to `writer`.
+///
+/// The writer is neither flushed nor finished, so that several
+/// simulations can be written one after the other.
+pub fn write_trajectory<R, W>(
+    problem: &mut Gillespie<R>,
+    times: &[f64],
+    writer: &mut W,
+) -> Result<(), WriteError>
+where
+    R: Rng + SeedableRng,
+    W: TrajectoryWriter + ?Sized,
+{
+    for &t in times {
+        problem.advance_until(t);
+        writer.write_row(t, problem.species())?;
+    }
+    Ok(())
+}
+
+/// Checks that a row has one amount per species.
+fn check_row(nb_species: usize, species: &[isize]) -> Result<(), WriteError> {
+    if species.len() == nb_species {
+        Ok(())
+    } else {
+        Err(WriteError::RowLength {
+            expected: nb_species,
+            found: species.len(),
+        })
+    }
+}
+
+/// Writer of trajectories in CSV, with a header `time,` followed by the
+/// names of the species.
+pub struct CsvWriter<W: Write> {
+    inner: Option<W>,
+    nb_species: usize,
+    batch_size: usize,
+    /// Formatted rows that are not written yet.
+    buffer: String,
+    nb_buffered: usize,
+    finished: bool,
+}
+
+impl<W: Write> CsvWriter<W> {
+    /// Creates a writer to `inner` of the species named `names`.
+    pub fn new<N: AsRef<str>>(inner: W, names: &[N]) -> Self {
+        let mut header = String::from("time");
+        for name in names {
+            header.push(',');
+            header.push_str(name.as_ref());
+        }
+        header.push('\n');
+        CsvWriter {
+            inner: Some(inner),
+            buffer: header,
+            nb_species: names.len(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            nb_buffered: 0,
+            finished: false,
+        }
+    }
+    /// Sets the number of rows buffered before writing them.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+    /// Returns the underlying writer, after writing the buffered rows.
+    ///
+    /// # Panics
+    ///
+    /// Panics if writing the buffered rows fails.
+    pub fn into_inner(mut self) -> W {
+        self.write_buffer().expect("cannot write trajectory");
+        self.inner
+            .take()
+            .expect("the writer is present until dropped")
+    }
+    fn write_buffer(&mut self) -> Result<(), WriteError> {
+        if let Some(inner) = &mut self.inner {
+            inner.write_all(self.buffer.as_bytes())?;
+        }
+        self.buffer.clear();
+        self.nb_buffered = 0;
+        Ok(())
+    }
+}
+
+impl<W: Write> TrajectoryWriter for CsvWriter<W> {
+    fn write_row(&mut self, t: f64, species: &[isize]) -> Result<(), WriteError> {
+        use std::fmt::Write;
+        if self.finished {
+            return Err(WriteError::Finished);
+        }
+        check_row(self.nb_species, species)?;
+        write!(self.buffer, "{t}").unwrap();
+        for n in species {
+            write!(self.buffer, ",{n}").unwrap();
+        }
+        self.buffer.push('\n');
+        self.nb_buffered += 1;
+        if self.nb_buffered >= self.batch_size {
+            self.write_buffer()?;
+        }
+        Ok(())
+    }
+    fn flush(&mut self) -> Result<(), WriteError> {
+        self.write_buffer()?;
+        if let Some(inner) = &mut self.inner {
+            inner.flush()?;
+        }
+        Ok(())
+    }
+    fn finish(&mut self) -> Result<(), WriteError> {
+        self.flush()?;
+        self.finished = true;
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for CsvWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Rows buffered by the Arrow writers, in columns.
+#[cfg(feature = "arrow")]
+struct Columns {
+    schema: arrow_schema::SchemaRef,
+    times: Vec<f64>,
+    amounts: Vec<Vec<i64>>,
+}
+
+#[cfg(feature = "arrow")]
+impl Columns {
+    /// Creates empty columns with the Arrow schema of a trajectory of the
+    /// species `names`: a column `time` of `Float64`, then one column of
+    /// `Int64` per species.
+    fn new<N: AsRef<str>>(names: &[N]) -> Self {
+        use arrow_schema::{DataType, Field, Schema};
+        let fields: Vec<Field> = std::iter::once(Field::new("time", DataType::Float64, false))
+            .chain(
+                names
+                    .iter()
+                    .map(|name| Field::new(name.as_ref(), DataType::Int64, false)),
+            )
+            .collect();
+        Columns {
+            schema: std::sync::Arc::new(Schema::new(fields)),
+            times: Vec::new(),
+            amounts: vec![Vec::new(); names.len()],
+        }
+    }
+    fn len(&self) -> usize {
+        self.times.len()
+    }
+    fn push(&mut self, t: f64, species: &[isize]) -> Result<(), WriteError> {
+        check_row(self.amounts.len(), species)?;
+        self.times.push(t);
+        for (column, &n) in self.amounts.iter_mut().zip(species) {
+            column.push(n as i64);
+        }
+        Ok(())
+    }
+    /// Empties the columns into a record batch.
+    fn take_batch(&mut self) -> Result<arrow_array::RecordBatch, WriteError> {
+        use arrow_array::{ArrayRef, Float64Array, Int64Array};
+        let times: ArrayRef =
+            std::sync::Arc::new(Float64Array::from(std::mem::take(&mut self.times)));
+        let columns = std::iter::once(times).chain(self.amounts.iter_mut().map(|column| {
+            std::sync::Arc::new(Int64Array::from(std::mem::take(column))) as ArrayRef
+        }));
+        Ok(arrow_array::RecordBatch::try_new(
+            self.schema.clone(),
+            columns.collect(),
+        )?)
+    }
+}
+
+/// Writer of trajectories in the Arrow IPC file format, also known as
+/// Feather, with the `arrow` feature.
+///
+/// The file has a column `time` of `Float64` and one column of `Int64`
+/// per species, and each batch of rows is a record batch.
+#[cfg(feature = "arrow")]
+pub struct IpcWriter<W: Write> {
+    inner: arrow_ipc::writer::FileWriter<W>,
+    columns: Columns,
+    batch_size: usize,
+    finished: bool,
+}
+
+#[cfg(feature = "arrow")]
+impl<W: Write> IpcWriter<W> {
+    /// Creates a writer to `inner` of the species named `names`, and
+    /// writes the header of the file.
+    pub fn try_new<N: AsRef<str>>(inner: W, names: &[N]) -> Result<Self, WriteError> {
+        let columns = Columns::new(names);
+        Ok(IpcWriter {
+            inner: arrow_ipc::writer::FileWriter::try_new(inner, &columns.schema)?,
+            columns,
+            batch_size: DEFAULT_BATCH_SIZE,
+            finished: false,
+        })
+    }
+    /// Sets the number of rows of the record batches.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+    fn write_batch(&mut self) -> Result<(), WriteError> {
+        if self.columns.len() > 0 {
+            let batch = self.columns.take_batch()?;
+            self.inner.write(&batch)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "arrow")]
+impl<W: Write> TrajectoryWriter for IpcWriter<W> {
+    fn write_row(&mut self, t: f64, species: &[isize]) -> Result<(), WriteError> {
+        if self.finished {
+            return Err(WriteError::Finished);
+        }
+        self.columns.push(t, species)?;
+        if self.columns.len() >= self.batch_size {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+    fn flush(&mut self) -> Result<(), WriteError> {
+        if !self.finished {
+            self.write_batch()?;
+            self.inner.flush()?;
+        }
+        Ok(())
+    }
+    fn finish(&mut self) -> Result<(), WriteError> {
+        if !self.finished {
+            self.write_batch()?;
+            self.finished = true;
+            self.inner.finish()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "arrow")]
+impl<W: Write> Drop for IpcWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Writer of trajectories in the Parquet format, with the `parquet`
+/// feature.
+///
+/// The file has the same columns as with [`IpcWriter`], and each batch of
+/// rows is a row group.
+#[cfg(feature = "parquet")]
+pub struct ParquetWriter<W: Write + Send> {
+    inner: parquet::arrow::ArrowWriter<W>,
+    columns: Columns,
+    batch_size: usize,
+    finished: bool,
+}
+
+#[cfg(feature = "parquet")]
+impl<W: Write + Send> ParquetWriter<W> {
+    /// Creates a writer to `inner` of the species named `names`, with the
+    /// default properties of Parquet, and writes the header of the file.
+    pub fn try_new<N: AsRef<str>>(inner: W, names: &[N]) -> Result<Self, WriteError> {
+        let columns = Columns::new(names);
+        Ok(ParquetWriter {
+            inner: parquet::arrow::ArrowWriter::try_new(inner, columns.schema.clone(), None)?,
+            columns,
+            batch_size: DEFAULT_BATCH_SIZE,
+            finished: false,
+        })
+    }
+    /// Sets the number of rows of the row groups.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+    fn write_batch(&mut self) -> Result<(), WriteError> {
+        if self.columns.len() > 0 {
+            let batch = self.columns.take_batch()?;
+            self.inner.write(&batch)?;
+            self.inner.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl<W: Write + Send> TrajectoryWriter for ParquetWriter<W> {
+    fn write_row(&mut self, t: f64, species: &[isize]) -> Result<(), WriteError> {
+        if self.finished {
+            return Err(WriteError::Finished);
+        }
+        self.columns.push(t, species)?;
+        if self.columns.len() >= self.batch_size {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+    fn flush(&mut self) -> Result<(), WriteError> {
+        if !self.finished {
+            self.write_batch()?;
+            self.inner.inner_mut().flush()?;
+        }
+        Ok(())
+    }
+    fn finish(&mut self) -> Result<(), WriteError> {
+        if !self.finished {
+            self.write_batch()?;
+            self.finished = true;
+            self.inner.finish()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl<W: Write + Send> Drop for ParquetWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Messages from a [`Background`] writer to its thread.
+enum Message {
+    /// Times and amounts, one row after the other, of consecutive rows.
+    Rows(Vec<f64>, Vec<isize>),
+    Flush,
+}
+
+/// Writer that sends the rows by batches to another writer, which writes
+/// them in its own thread.
+///
+/// The simulation only waits for the disk when the thread is more than
+/// two batches behind.  The errors of the thread are reported by the
+/// next call after them, and at the latest by
+/// [`finish`](TrajectoryWriter::finish).
+pub struct Background {
+    sender: Option<mpsc::SyncSender<Message>>,
+    thread: Option<thread::JoinHandle<Result<(), WriteError>>>,
+    batch_size: usize,
+    nb_species: Option<usize>,
+    times: Vec<f64>,
+    amounts: Vec<isize>,
+}
+
+impl Background {
+    /// Moves `writer` to a new thread, and sends it batches of
+    /// [`DEFAULT_BATCH_SIZE`] rows.
+    pub fn new<W: TrajectoryWriter + Send + 'static>(writer: W) -> Self {
+        Background::with_batch_size(writer, DEFAULT_BATCH_SIZE)
+    }
+    /// Moves `writer` to a new thread, and sends it batches of
+    /// `batch_size` rows.
+    pub fn with_batch_size<W: TrajectoryWriter + Send + 'static>(
+        mut writer: W,
+        batch_size: usize,
+    ) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(2);
+        let thread = thread::spawn(move || {
+            for message in receiver {
+                match message {
+                    Message::Rows(times, amounts) => {
+                        let nb_species = amounts.len() / times.len().max(1);
+                        for (i, &t) in times.iter().enumerate() {
+                            let row = &amounts[i * nb_species..(i + 1) * nb_species];
+                            writer.write_row(t, row)?;
+                        }
+                    }
+                    Message::Flush => writer.flush()?,
+                }
+            }
+            writer.finish()
+        });
+        Background {
+            sender: Some(sender),
+            thread: Some(thread),
+            batch_size: batch_size.max(1),
+            nb_species: None,
+            times: Vec::new(),
+            amounts: Vec::new(),
+        }
+    }
+    /// Sends `message` to the thread, or returns its error if it stopped.
+    fn send(&mut self, message: Message) -> Result<(), WriteError> {
+        let Some(sender) = &self.sender else {
+            return Err(WriteError::Finished);
+        };
+        if sender.send(message).is_err() {
+            self.sender = None;
+            return self.join();
+        }
+        Ok(())
+    }
+    /// Sends the buffered rows to the thread.
+    fn send_rows(&mut self) -> Result<(), WriteError> {
+        if self.times.is_empty() {
+            return Ok(());
+        }
+        let times = std::mem::take(&mut self.times);
+        let amounts = std::mem::take(&mut self.amounts);
+        self.send(Message::Rows(times, amounts))
+    }
+    /// Waits for the thread to stop, and returns its result.
+    fn join(&mut self) -> Result<(), WriteError> {
+        match self.thread.take() {
+            Some(thread) => thread
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic)),
+            None => Err(WriteError::Finished),
+        }
+    }
+}
+
+impl TrajectoryWriter for Background {
+    fn write_row(&mut self, t: f64, species: &[isize]) -> Result<(), WriteError> {
+        if self.sender.is_none() {
+            return Err(WriteError::Finished);
+        }
+        check_row(*self.nb_species.get_or_insert(species.len()), species)?;
+        self.times.push(t);
+        self.amounts.extend_from_slice(species);
+        if self.times.len() >= self.batch_size {
+            self.send_rows()?;
+        }
+        Ok(())
+    }
+    fn flush(&mut self) -> Result<(), WriteError> {
+        self.send_rows()?;
+        self.send(Message::Flush)
+    }
+    fn finish(&mut self) -> Result<(), WriteError> {
+        if self.sender.is_none() && self.thread.is_none() {
+            return Ok(());
+        }
+        self.send_rows()?;
+        // the thread finishes the writer once the channel is closed
+        self.sender = None;
+        self.join()
+    }
+}
+
+impl Drop for Background {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            let _ = self.finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::gillespie::{Gillespie, Rate};
+    use crate::io::*;
+
+    /// Path of a temporary file for the test `name`.
+    fn temporary(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rebop-{}-{name}", std::process::id()))
+    }
+
+    fn decay() -> Gillespie {
+        let mut decay = Gillespie::new_with_seed([100, 0], 0);
+        decay.add_reaction(Rate::lma(0.1, [1, 0]), [-1, 1]);
+        decay
+    }
+
+    #[test]
+    fn csv() {
+        let mut writer = CsvWriter::new(Vec::new(), &["A", "B"]).with_batch_size(2);
+        writer.write_row(0., &[1, 2]).unwrap();
+        assert!(writer.inner.as_ref().unwrap().is_empty());
+        writer.write_row(0.5, &[3, -4]).unwrap();
+        assert_eq!(
+            writer.inner.as_ref().unwrap(),
+            b"time,A,B\n0,1,2\n0.5,3,-4\n"
+        );
+        assert!(matches!(
+            writer.write_row(1., &[1]),
+            Err(WriteError::RowLength {
+                expected: 2,
+                found: 1
+            })
+        ));
+        writer.write_row(1., &[5, 6]).unwrap();
+        writer.finish().unwrap();
+        assert!(matches!(
+            writer.write_row(2., &[1, 2]),
+            Err(WriteError::Finished)
+        ));
+        let csv = String::from_utf8(writer.into_inner()).unwrap();
+        assert_eq!(csv, "time,A,B\n0,1,2\n0.5,3,-4\n1,5,6\n");
+    }
+
+    #[test]
+    fn background() {
+        let path = temporary("background.csv");
+        let file = std::fs::File::create(&path).unwrap();
+        let mut writer = Background::with_batch_size(CsvWriter::new(file, &["A", "B"]), 3);
+        let times: Vec<f64> = (0..=10).map(f64::from).collect();
+        write_trajectory(&mut decay(), &times, &mut writer).unwrap();
+        writer.flush().unwrap();
+        write_trajectory(&mut decay(), &times, &mut writer).unwrap();
+        writer.finish().unwrap();
+        writer.finish().unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 1 + 2 * 11);
+        assert_eq!(lines[..2], ["time,A,B", "0,100,0"]);
+        // the same seed gives the same trajectory
+        assert_eq!(lines[1..12], lines[12..]);
+        // the errors of the thread are reported
+        let mut writer = Background::new(CsvWriter::new(Vec::new(), &["A", "B"]));
+        writer.write_row(0., &[1]).unwrap();
+        assert!(matches!(
+            writer.finish(),
+            Err(WriteError::RowLength {
+                expected: 2,
+                found: 1
+            })
+        ));
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn ipc() {
+        use arrow_array::{Float64Array, Int64Array};
+        let path = temporary("ipc.arrow");
+        let file = std::fs::File::create(&path).unwrap();
+        let mut writer = IpcWriter::try_new(file, &["A", "B"])
+            .unwrap()
+            .with_batch_size(4);
+        let times: Vec<f64> = (0..=10).map(f64::from).collect();
+        write_trajectory(&mut decay(), &times, &mut writer).unwrap();
+        writer.finish().unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        let reader = arrow_ipc::reader::FileReader::try_new(file, None).unwrap();
+        let batches: Vec<_> = reader.map(Result::unwrap).collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            batches.iter().map(|b| b.num_rows()).collect::<Vec<_>>(),
+            [4, 4, 3]
+        );
+        let schema = batches[0].schema();
+        let names: Vec<&String> = schema.fields().iter().map(|f| f.name()).collect();
+        assert_eq!(names, ["time", "A", "B"]);
+        let column = |b: usize, c: usize| batches[b].column(c).clone();
+        let t = column(2, 0);
+        let t = t.as_any().downcast_ref::<Float64Array>().unwrap();
+        assert_eq!(t.values(), &[8., 9., 10.]);
+        for b in 0..3 {
+            let (a, b) = (column(b, 1), column(b, 2));
+            let a = a.as_any().downcast_ref::<Int64Array>().unwrap();
+            let b = b.as_any().downcast_ref::<Int64Array>().unwrap();
+            assert!(a.values().iter().zip(b.values()).all(|(a, b)| a + b == 100));
+        }
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn parquet() {
+        use arrow_array::Int64Array;
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+        let path = temporary("trajectory.parquet");
+        let file = std::fs::File::create(&path).unwrap();
+        let mut writer = ParquetWriter::try_new(file, &["A", "B"])
+            .unwrap()
+            .with_batch_size(5);
+        let times: Vec<f64> = (0..=10).map(f64::from).collect();
+        write_trajectory(&mut decay(), &times, &mut writer).unwrap();
+        writer.finish().unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file).unwrap();
+        assert_eq!(builder.metadata().num_row_groups(), 3);
+        let batches: Vec<_> = builder.build().unwrap().map(Result::unwrap).collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 11);
+        let a = batches[0].column(1).clone();
+        let a = a.as_any().downcast_ref::<Int64Array>().unwrap();
+        assert_eq!(a.value(0), 100);
+    }
+}
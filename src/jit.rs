@@ -0,0 +1,484 @@
+//! Compilation of the rates of a problem to native code, with Cranelift.
+
+use std::fmt;
+
+use cranelift_codegen::ir::condcodes::FloatCC;
+use cranelift_codegen::ir::{types, AbiParam, FuncRef, InstBuilder, MemFlags, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+
+use crate::gillespie::{scale, Op, Rate};
+
+/// Signature of the compiled function, which writes the cumulative rates
+/// of the reactions for the amounts of species and the values of
+/// [`NativeRates::values`], and returns the total.
+type RatesFn =
+    unsafe extern "C" fn(species: *const isize, values: *const f64, cum_rates: *mut f64) -> f64;
+
+/// Mathematical functions called by the compiled code, with the same
+/// results as in the interpreter.
+const FUNCTIONS: [(&str, usize, *const u8); 8] = [
+    ("rebop_exp", 1, exp as *const u8),
+    ("rebop_ln", 1, ln as *const u8),
+    ("rebop_log10", 1, log10 as *const u8),
+    ("rebop_sin", 1, sin as *const u8),
+    ("rebop_cos", 1, cos as *const u8),
+    ("rebop_pow", 2, pow as *const u8),
+    ("rebop_min", 2, min as *const u8),
+    ("rebop_max", 2, max as *const u8),
+];
+
+extern "C" fn exp(x: f64) -> f64 {
+    x.exp()
+}
+extern "C" fn ln(x: f64) -> f64 {
+    x.ln()
+}
+extern "C" fn log10(x: f64) -> f64 {
+    x.log10()
+}
+extern "C" fn sin(x: f64) -> f64 {
+    x.sin()
+}
+extern "C" fn cos(x: f64) -> f64 {
+    x.cos()
+}
+extern "C" fn pow(x: f64, y: f64) -> f64 {
+    x.powf(y)
+}
+extern "C" fn min(x: f64, y: f64) -> f64 {
+    x.min(y)
+}
+extern "C" fn max(x: f64, y: f64) -> f64 {
+    x.max(y)
+}
+
+/// Structure of a rate without its constants, which the compiled code
+/// loads from memory.
+#[derive(Debug, PartialEq)]
+enum Shape {
+    /// Law of mass action, with the exponents of its reactants.
+    Lma(Vec<(u32, u32)>),
+    /// Stack program, whose constants are all `0`.
+    Program(Vec<Op>),
+}
+
+impl Shape {
+    /// Splits `rate` into its shape and its constants, which are appended
+    /// to `constants` in the order in which the compiled code loads them.
+    fn split(rate: &Rate, constants: &mut Vec<f64>) -> Shape {
+        let program = |ops: &[Op], constants: &mut Vec<f64>| {
+            let ops = (ops.iter())
+                .map(|&op| match op {
+                    Op::Constant(c) => {
+                        constants.push(c);
+                        Op::Constant(0.)
+                    }
+                    op => op,
+                })
+                .collect();
+            Shape::Program(ops)
+        };
+        match rate {
+            Rate::LMA(rate, reactants) => {
+                constants.push(*rate);
+                let sparse = (reactants.iter().enumerate())
+                    .map(|(index, &exponent)| (index as u32, exponent))
+                    .collect();
+                Shape::Lma(sparse)
+            }
+            Rate::LMASparse(rate, sparse) => {
+                constants.push(*rate);
+                Shape::Lma(sparse.clone())
+            }
+            Rate::Compiled(compiled) => program(&compiled.ops, constants),
+            Rate::Expr(expr) => program(&expr.compile().ops, constants),
+            Rate::LMAParam(_, _) => unreachable!("the parameters are resolved"),
+        }
+    }
+}
+
+/// Native function computing the cumulative rates of all the reactions
+/// of a problem, in the same order and with the same rounding as
+/// `make_cumrates`, so that the simulations are identical.
+///
+/// Only the shapes of the rates are compiled: their constants, the
+/// factors of [`Gillespie::scale_rate`](crate::gillespie::Gillespie::scale_rate)
+/// and whether the reactions are enabled are loaded from the values of
+/// [`NativeRates::values`], so that changing them does not need another
+/// compilation.
+pub(crate) struct NativeRates {
+    /// Module owning the code, freed with it.
+    module: Option<JITModule>,
+    function: RatesFn,
+    nb_species: usize,
+    /// Shapes of the compiled rates.
+    shapes: Vec<Shape>,
+    /// Number of values read by the code.
+    nb_values: usize,
+}
+
+// SAFETY: the compiled code is immutable, and only freed on drop
+unsafe impl Send for NativeRates {}
+unsafe impl Sync for NativeRates {}
+
+impl fmt::Debug for NativeRates {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NativeRates")
+            .field("nb_reactions", &self.shapes.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for NativeRates {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // SAFETY: the function cannot be called anymore
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+impl NativeRates {
+    /// Compiles the shapes of `rates`, for a problem with `nb_species`
+    /// species.  The rates must have been checked and resolved.
+    pub(crate) fn compile(rates: &[&Rate], nb_species: usize) -> Result<NativeRates, String> {
+        let mut constants = Vec::new();
+        let shapes: Vec<Shape> = (rates.iter())
+            .map(|rate| Shape::split(rate, &mut constants))
+            .collect();
+        let mut flags = settings::builder();
+        let set = |flags: &mut settings::Builder, name, value| {
+            flags.set(name, value).map_err(|err| err.to_string())
+        };
+        set(&mut flags, "use_colocated_libcalls", "false")?;
+        set(&mut flags, "is_pic", "false")?;
+        set(&mut flags, "opt_level", "speed")?;
+        let isa = cranelift_native::builder()
+            .map_err(|err| format!("unsupported host: {err}"))?
+            .finish(settings::Flags::new(flags))
+            .map_err(|err| err.to_string())?;
+        let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+        for (name, _, pointer) in FUNCTIONS {
+            builder.symbol(name, pointer);
+        }
+        let mut module = JITModule::new(builder);
+        let pointer = module.target_config().pointer_type();
+        let mut context = module.make_context();
+        for _ in 0..3 {
+            context.func.signature.params.push(AbiParam::new(pointer));
+        }
+        context
+            .func
+            .signature
+            .returns
+            .push(AbiParam::new(types::F64));
+        let mut imports = Vec::new();
+        for (name, nb_args, _) in FUNCTIONS {
+            let mut signature = module.make_signature();
+            for _ in 0..nb_args {
+                signature.params.push(AbiParam::new(types::F64));
+            }
+            signature.returns.push(AbiParam::new(types::F64));
+            let id = module
+                .declare_function(name, Linkage::Import, &signature)
+                .map_err(|err| err.to_string())?;
+            imports.push(module.declare_func_in_func(id, &mut context.func));
+        }
+        let mut function_context = FunctionBuilderContext::new();
+        let mut b = FunctionBuilder::new(&mut context.func, &mut function_context);
+        let block = b.create_block();
+        b.append_block_params_for_function_params(block);
+        b.switch_to_block(block);
+        let [species, values, cum_rates] = b.block_params(block)[..] else {
+            unreachable!("the function has three parameters");
+        };
+        let mut translator = Translator {
+            b,
+            species,
+            values,
+            // the factors and the flags of the reactions come first
+            next_value: 2 * shapes.len(),
+            pointer,
+            imports,
+        };
+        let mut total = translator.b.ins().f64const(0.);
+        let zero = translator.b.ins().f64const(0.);
+        for (ireaction, shape) in shapes.iter().enumerate() {
+            let rate = translator.shape(shape);
+            // multiplying by a factor of `1` changes nothing, like in
+            // `make_cumrates` without factors
+            let factor = translator.value(ireaction);
+            let rate = translator.b.ins().fmul(rate, factor);
+            // the rate of a disabled reaction is `0`
+            let enabled = translator.value(shapes.len() + ireaction);
+            let enabled = translator.is_true(enabled);
+            let rate = translator.b.ins().select(enabled, rate, zero);
+            total = translator.b.ins().fadd(total, rate);
+            let offset = (ireaction * std::mem::size_of::<f64>()) as i32;
+            translator
+                .b
+                .ins()
+                .store(MemFlags::trusted(), total, cum_rates, offset);
+        }
+        translator.b.ins().return_(&[total]);
+        translator.b.seal_all_blocks();
+        translator.b.finalize();
+        let id = module
+            .declare_function("rates", Linkage::Local, &context.func.signature)
+            .map_err(|err| err.to_string())?;
+        module
+            .define_function(id, &mut context)
+            .map_err(|err| err.to_string())?;
+        module.clear_context(&mut context);
+        module
+            .finalize_definitions()
+            .map_err(|err| err.to_string())?;
+        // SAFETY: the function was compiled with this signature
+        let function =
+            unsafe { std::mem::transmute::<*const u8, RatesFn>(module.get_finalized_function(id)) };
+        Ok(NativeRates {
+            module: Some(module),
+            function,
+            nb_species,
+            nb_values: 2 * shapes.len() + constants.len(),
+            shapes,
+        })
+    }
+    /// Returns the values read by the code for `rates`, with whether
+    /// each reaction is enabled, multiplied by `scales` if it is not
+    /// empty, or `None` if the rates do not have the compiled shapes.
+    pub(crate) fn values(&self, rates: &[(&Rate, bool)], scales: &[f64]) -> Option<Vec<f64>> {
+        if rates.len() != self.shapes.len() {
+            return None;
+        }
+        let mut values = Vec::with_capacity(self.nb_values);
+        values.extend((0..rates.len()).map(|ireaction| scale(scales, ireaction)));
+        values.extend(
+            rates
+                .iter()
+                .map(|&(_, enabled)| f64::from(u8::from(enabled))),
+        );
+        for (&(rate, _), shape) in rates.iter().zip(&self.shapes) {
+            if Shape::split(rate, &mut values) != *shape {
+                return None;
+            }
+        }
+        Some(values)
+    }
+    /// Writes the cumulative rates of the reactions in `cum_rates`, and
+    /// returns the total rate.
+    pub(crate) fn cum_rates(
+        &self,
+        species: &[isize],
+        values: &[f64],
+        cum_rates: &mut [f64],
+    ) -> f64 {
+        assert!(species.len() >= self.nb_species && cum_rates.len() >= self.shapes.len());
+        assert_eq!(values.len(), self.nb_values);
+        // SAFETY: the code only reads the species and the values, and
+        // writes the rates of the problem, whose numbers were just checked
+        unsafe { (self.function)(species.as_ptr(), values.as_ptr(), cum_rates.as_mut_ptr()) }
+    }
+}
+
+/// Translation of rates into the instructions of a function.
+struct Translator<'a> {
+    b: FunctionBuilder<'a>,
+    /// Pointer to the amounts of species.
+    species: Value,
+    /// Pointer to the values of [`NativeRates::values`].
+    values: Value,
+    /// Index of the next constant in the values.
+    next_value: usize,
+    pointer: types::Type,
+    /// Functions of `FUNCTIONS`, in the same order.
+    imports: Vec<FuncRef>,
+}
+
+impl Translator<'_> {
+    /// Returns the rate of shape `shape`, like [`Rate::rate`].
+    fn shape(&mut self, shape: &Shape) -> Value {
+        match shape {
+            Shape::Lma(sparse) => self.lma(sparse),
+            Shape::Program(ops) => self.program(ops),
+        }
+    }
+    /// Returns the value of index `index`.
+    fn value(&mut self, index: usize) -> Value {
+        let offset = (index * std::mem::size_of::<f64>()) as i32;
+        self.b
+            .ins()
+            .load(types::F64, MemFlags::trusted(), self.values, offset)
+    }
+    /// Returns the next constant of the rates.
+    fn constant(&mut self) -> Value {
+        self.next_value += 1;
+        self.value(self.next_value - 1)
+    }
+    /// Returns the amount of the species of index `index`, as an integer.
+    fn amount(&mut self, index: usize) -> Value {
+        let offset = (index * std::mem::size_of::<isize>()) as i32;
+        self.b
+            .ins()
+            .load(self.pointer, MemFlags::trusted(), self.species, offset)
+    }
+    /// Law of mass action, with the factors in the order of the
+    /// interpreter.
+    fn lma(&mut self, sparse: &[(u32, u32)]) -> Value {
+        let mut acc = self.constant();
+        for &(index, exponent) in sparse {
+            if exponent == 0 {
+                continue;
+            }
+            let n = self.amount(index as usize);
+            for j in 0..exponent {
+                // n + 1 - exponent, ..., n
+                let x = self
+                    .b
+                    .ins()
+                    .iadd_imm(n, 1 - i64::from(exponent) + i64::from(j));
+                let x = self.b.ins().fcvt_from_sint(types::F64, x);
+                acc = self.b.ins().fmul(acc, x);
+            }
+        }
+        acc
+    }
+    /// Calls the function of index `ifunction` in `FUNCTIONS`.
+    fn call(&mut self, ifunction: usize, args: &[Value]) -> Value {
+        let call = self.b.ins().call(self.imports[ifunction], args);
+        self.b.inst_results(call)[0]
+    }
+    /// `1` if `condition` holds, `0` otherwise.
+    fn truth(&mut self, condition: Value) -> Value {
+        let (one, zero) = (self.b.ins().f64const(1.), self.b.ins().f64const(0.));
+        self.b.ins().select(condition, one, zero)
+    }
+    /// Whether `a` is not `0`, as a condition.
+    fn is_true(&mut self, a: Value) -> Value {
+        let zero = self.b.ins().f64const(0.);
+        self.b.ins().fcmp(FloatCC::NotEqual, a, zero)
+    }
+    /// Evaluates the stack program of instructions `ops`.  Both branches of the
+    /// conditions are evaluated, which makes no difference without side
+    /// effects, and the value of the chosen one is selected.
+    fn program(&mut self, ops: &[Op]) -> Value {
+        /// Condition whose branches are being translated.
+        struct Branch {
+            condition: Value,
+            /// Value of the first branch, once translated.
+            first: Option<Value>,
+            /// Index of the instruction after the second branch.
+            end: usize,
+        }
+        let mut stack: Vec<Value> = Vec::new();
+        let mut branches: Vec<Branch> = Vec::new();
+        for pc in 0..=ops.len() {
+            while branches.last().is_some_and(|branch| branch.end == pc) {
+                let branch = branches.pop().expect("a branch");
+                let second = stack.pop().expect("a value");
+                let first = branch.first.expect("the first branch");
+                let value = self.b.ins().select(branch.condition, first, second);
+                stack.push(value);
+            }
+            let Some(&op) = ops.get(pc) else {
+                break;
+            };
+            let value = match op {
+                Op::Constant(_) => self.constant(),
+                Op::Concentration(index) => {
+                    let n = self.amount(index);
+                    self.b.ins().fcvt_from_sint(types::F64, n)
+                }
+                Op::JumpIfZero(_) => {
+                    let condition = stack.pop().expect("a condition");
+                    let condition = self.is_true(condition);
+                    branches.push(Branch {
+                        condition,
+                        first: None,
+                        end: usize::MAX,
+                    });
+                    continue;
+                }
+                Op::Jump(end) => {
+                    let branch = branches.last_mut().expect("a branch");
+                    branch.first = stack.pop();
+                    branch.end = end;
+                    continue;
+                }
+                Op::Exp | Op::Ln | Op::Log10 | Op::Sqrt | Op::Abs | Op::Floor | Op::Ceil => {
+                    self.unary(op, stack.pop().expect("an operand"))
+                }
+                Op::Sin | Op::Cos | Op::Not => self.unary(op, stack.pop().expect("an operand")),
+                Op::Hill => {
+                    let n = stack.pop().expect("an operand");
+                    let k = stack.pop().expect("an operand");
+                    let x = stack.pop().expect("an operand");
+                    let xn = self.call(5, &[x, n]);
+                    let kn = self.call(5, &[k, n]);
+                    let denominator = self.b.ins().fadd(kn, xn);
+                    self.b.ins().fdiv(xn, denominator)
+                }
+                _ => {
+                    let b = stack.pop().expect("an operand");
+                    let a = stack.pop().expect("an operand");
+                    self.binary(op, a, b)
+                }
+            };
+            stack.push(value);
+        }
+        stack.pop().expect("a value")
+    }
+    fn unary(&mut self, op: Op, a: Value) -> Value {
+        match op {
+            Op::Exp => self.call(0, &[a]),
+            Op::Ln => self.call(1, &[a]),
+            Op::Log10 => self.call(2, &[a]),
+            Op::Sin => self.call(3, &[a]),
+            Op::Cos => self.call(4, &[a]),
+            Op::Sqrt => self.b.ins().sqrt(a),
+            Op::Abs => self.b.ins().fabs(a),
+            Op::Floor => self.b.ins().floor(a),
+            Op::Ceil => self.b.ins().ceil(a),
+            Op::Not => {
+                let zero = self.b.ins().f64const(0.);
+                let condition = self.b.ins().fcmp(FloatCC::Equal, a, zero);
+                self.truth(condition)
+            }
+            _ => unreachable!("not a unary operator"),
+        }
+    }
+    fn binary(&mut self, op: Op, a: Value, b: Value) -> Value {
+        let compare = |translator: &mut Self, cc| {
+            let condition = translator.b.ins().fcmp(cc, a, b);
+            translator.truth(condition)
+        };
+        match op {
+            Op::Add => self.b.ins().fadd(a, b),
+            Op::Sub => self.b.ins().fsub(a, b),
+            Op::Mul => self.b.ins().fmul(a, b),
+            Op::Div => self.b.ins().fdiv(a, b),
+            Op::Pow => self.call(5, &[a, b]),
+            Op::Min => self.call(6, &[a, b]),
+            Op::Max => self.call(7, &[a, b]),
+            Op::Lt => compare(self, FloatCC::LessThan),
+            Op::Le => compare(self, FloatCC::LessThanOrEqual),
+            Op::Gt => compare(self, FloatCC::GreaterThan),
+            Op::Ge => compare(self, FloatCC::GreaterThanOrEqual),
+            Op::Eq => compare(self, FloatCC::Equal),
+            Op::Ne => compare(self, FloatCC::NotEqual),
+            Op::And | Op::Or => {
+                let (a, b) = (self.is_true(a), self.is_true(b));
+                let condition = if op == Op::And {
+                    self.b.ins().band(a, b)
+                } else {
+                    self.b.ins().bor(a, b)
+                };
+                self.truth(condition)
+            }
+            _ => unreachable!("not a binary operator"),
+        }
+    }
+}
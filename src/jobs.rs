@@ -0,0 +1,382 @@
+//! In-process queue of simulation jobs.
+//!
+//! A [`JobQueue`] runs simulations of [`Model`]s on a fixed number of
+//! worker threads.  Each [`Job`] gives a model, for example a variant
+//! built with [`Model::variant`], a seed and an output grid.  Jobs are
+//! started by decreasing priority, then in the order of their
+//! submission, those that have not started yet can be cancelled, and the
+//! results are collected as they complete, in any order, which is what
+//! graphical interfaces and services need to stay responsive.
+//!
+//! ```
+//! use std::sync::Arc;
+//! use rebop::jobs::{Job, JobQueue};
+//! use rebop::model::Model;
+//! let model = Arc::new(Model::from_toml_str(r#"
+//!     version = 1
+//!     species = [{ name = "A", initial = 100 }]
+//!     [[reactions]]
+//!     reactants = { A = 1 }
+//!     rate = 0.1
+//! "#).unwrap());
+//! let queue = JobQueue::new(2);
+//! for seed in 0..10 {
+//!     queue.submit(Job::new(model.clone(), seed, 10., 10));
+//! }
+//! let urgent = queue.submit(Job::new(model.clone(), 42, 1., 1).with_priority(1));
+//! let mut nb_results = 0;
+//! for result in queue.results() {
+//!     let trajectory = result.trajectory.unwrap();
+//!     if result.id == urgent {
+//!         assert_eq!(trajectory.times, [0., 1.]);
+//!     }
+//!     nb_results += 1;
+//! }
+//! assert_eq!(nb_results, 11);
+//! ```
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::gillespie::Trajectory;
+use crate::model::{Model, ModelError, Output};
+
+/// Simulation of a model with a seed, until `tmax` with `nb_steps`
+/// steps, submitted to a [`JobQueue`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Job {
+    /// Model to simulate, shared between the jobs.  The time points of
+    /// its `output` section are replaced by `tmax` and `nb_steps`.
+    pub model: Arc<Model>,
+    pub seed: u64,
+    pub tmax: f64,
+    pub nb_steps: usize,
+    /// Jobs of higher priority start first, `0` by default.
+    pub priority: i64,
+}
+
+impl Job {
+    /// Creates a job of priority `0`.
+    pub fn new(model: Arc<Model>, seed: u64, tmax: f64, nb_steps: usize) -> Self {
+        Job {
+            model,
+            seed,
+            tmax,
+            nb_steps,
+            priority: 0,
+        }
+    }
+    /// Sets the priority of the job.
+    pub fn with_priority(mut self, priority: i64) -> Self {
+        self.priority = priority;
+        self
+    }
+    fn run(&self) -> Result<Trajectory, ModelError> {
+        let mut model = Model::clone(&self.model);
+        model.output = Some(Output {
+            tmax: self.tmax,
+            nb_steps: self.nb_steps,
+            firings: self
+                .model
+                .output
+                .as_ref()
+                .is_some_and(|output| output.firings),
+        });
+        model.run(Some(self.seed))
+    }
+}
+
+/// Identifier of a job, in the order of submission.
+pub type JobId = u64;
+
+/// Result of a job, returned by [`JobQueue::recv`].
+#[derive(Debug)]
+pub struct JobResult {
+    pub id: JobId,
+    pub trajectory: Result<Trajectory, ModelError>,
+}
+
+/// Job waiting in the queue, ordered by priority then by submission.
+struct Queued {
+    id: JobId,
+    job: Job,
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // the greatest is popped first
+        (self.job.priority, other.id).cmp(&(other.job.priority, self.id))
+    }
+}
+
+/// State shared by the queue and its workers.
+#[derive(Default)]
+struct State {
+    waiting: BinaryHeap<Queued>,
+    /// Number of submitted jobs whose result was not received, and that
+    /// were not cancelled.
+    pending: usize,
+    next_id: JobId,
+    closed: bool,
+}
+
+/// Queue of simulation jobs run by a pool of worker threads.
+///
+/// Dropping the queue cancels the jobs that have not started, and waits
+/// for the running ones.
+pub struct JobQueue {
+    state: Arc<(Mutex<State>, Condvar)>,
+    workers: Vec<JoinHandle<()>>,
+    results: Receiver<JobResult>,
+}
+
+impl JobQueue {
+    /// Creates a queue that runs at most `nb_threads` jobs at the same
+    /// time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `nb_threads` is zero.
+    pub fn new(nb_threads: usize) -> Self {
+        assert!(nb_threads > 0, "at least one thread is needed");
+        let state = Arc::new((Mutex::new(State::default()), Condvar::new()));
+        let (sender, results) = mpsc::channel();
+        let workers = (0..nb_threads)
+            .map(|_| {
+                let (state, sender) = (state.clone(), sender.clone());
+                thread::spawn(move || work(&state, &sender))
+            })
+            .collect();
+        JobQueue {
+            state,
+            workers,
+            results,
+        }
+    }
+    /// Submits a job and returns its identifier.
+    pub fn submit(&self, job: Job) -> JobId {
+        let (lock, condvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.pending += 1;
+        state.waiting.push(Queued { id, job });
+        condvar.notify_one();
+        id
+    }
+    /// Cancels the job `id` if it has not started yet, and returns
+    /// whether it was cancelled.  Running jobs are not interrupted.
+    pub fn cancel(&self, id: JobId) -> bool {
+        let mut state = self.state.0.lock().unwrap();
+        let before = state.waiting.len();
+        state.waiting.retain(|queued| queued.id != id);
+        let cancelled = state.waiting.len() < before;
+        if cancelled {
+            state.pending -= 1;
+        }
+        cancelled
+    }
+    /// Returns the number of jobs that have not started yet.
+    pub fn nb_waiting(&self) -> usize {
+        self.state.0.lock().unwrap().waiting.len()
+    }
+    /// Returns the number of jobs whose result has not been received,
+    /// waiting or running.
+    pub fn nb_pending(&self) -> usize {
+        self.state.0.lock().unwrap().pending
+    }
+    /// Waits for the next result, or returns `None` if no job is
+    /// pending.
+    pub fn recv(&self) -> Option<JobResult> {
+        if self.nb_pending() == 0 {
+            return None;
+        }
+        // the workers live as long as the queue
+        let result = self.results.recv().expect("the workers stopped");
+        self.state.0.lock().unwrap().pending -= 1;
+        Some(result)
+    }
+    /// Returns the next result if one is available, without waiting.
+    pub fn try_recv(&self) -> Option<JobResult> {
+        let result = self.results.try_recv().ok()?;
+        self.state.0.lock().unwrap().pending -= 1;
+        Some(result)
+    }
+    /// Returns an iterator over the results, in the order in which they
+    /// complete, until no job is pending.
+    pub fn results(&self) -> impl Iterator<Item = JobResult> + '_ {
+        std::iter::from_fn(|| self.recv())
+    }
+}
+
+impl Drop for JobQueue {
+    fn drop(&mut self) {
+        let (lock, condvar) = &*self.state;
+        {
+            let mut state = lock.lock().unwrap();
+            state.closed = true;
+            state.waiting.clear();
+        }
+        condvar.notify_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Runs the jobs of the queue until it is closed.
+fn work(state: &(Mutex<State>, Condvar), sender: &Sender<JobResult>) {
+    let (lock, condvar) = state;
+    loop {
+        let queued = {
+            let mut state = lock.lock().unwrap();
+            loop {
+                if state.closed {
+                    return;
+                }
+                if let Some(queued) = state.waiting.pop() {
+                    break queued;
+                }
+                state = condvar.wait(state).unwrap();
+            }
+        };
+        // a panicking job gives an error, so that its result is still
+        // received and the worker keeps running
+        let trajectory =
+            panic::catch_unwind(AssertUnwindSafe(|| queued.job.run())).unwrap_or_else(|payload| {
+                let msg = match (
+                    payload.downcast_ref::<&str>(),
+                    payload.downcast_ref::<String>(),
+                ) {
+                    (Some(msg), _) => msg.to_string(),
+                    (None, Some(msg)) => msg.clone(),
+                    (None, None) => "unknown panic".to_string(),
+                };
+                Err(ModelError::Panicked(msg))
+            });
+        let result = JobResult {
+            id: queued.id,
+            trajectory,
+        };
+        // the queue may have been dropped meanwhile
+        let _ = sender.send(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use crate::jobs::{Job, JobQueue};
+    use crate::model::Model;
+
+    fn model() -> Arc<Model> {
+        let model = Model::from_toml_str(
+            r#"
+            version = 1
+            species = [{ name = "A", initial = 10 }]
+            [[reactions]]
+            reactants = { A = 1 }
+            rate = 0.5
+            "#,
+        )
+        .unwrap();
+        Arc::new(model)
+    }
+
+    #[test]
+    fn priorities() {
+        // a single worker, busy with a long job while the others are
+        // submitted, runs them by priority
+        let queue = JobQueue::new(1);
+        let mut long = Model::clone(&model());
+        long.species[0].initial = 1_000_000;
+        long.reactions[0].rate = crate::model::RateSpec::Value(1e-3);
+        let first = queue.submit(Job::new(Arc::new(long), 0, 1e3, 1));
+        while queue.nb_waiting() > 0 {
+            thread::yield_now();
+        }
+        let low = queue.submit(Job::new(model(), 1, 10., 10).with_priority(-1));
+        let normal = queue.submit(Job::new(model(), 2, 10., 10));
+        let high = queue.submit(Job::new(model(), 3, 10., 10).with_priority(5));
+        let cancelled = queue.submit(Job::new(model(), 4, 10., 10));
+        assert!(queue.cancel(cancelled));
+        assert!(!queue.cancel(cancelled));
+        let order: Vec<_> = queue.results().map(|result| result.id).collect();
+        assert_eq!(order, [first, high, normal, low]);
+        assert_eq!(queue.nb_pending(), 0);
+        assert!(queue.recv().is_none());
+    }
+
+    #[test]
+    fn results() {
+        let queue = JobQueue::new(4);
+        let ids: Vec<_> = (0..20)
+            .map(|seed| queue.submit(Job::new(model(), seed, 10., 5)))
+            .collect();
+        let mut results: Vec<_> = queue.results().collect();
+        results.sort_by_key(|result| result.id);
+        assert_eq!(results.iter().map(|r| r.id).collect::<Vec<_>>(), ids);
+        // the same as direct simulations
+        let mut expected = Model::clone(&model());
+        expected.output = Some(crate::model::Output {
+            tmax: 10.,
+            nb_steps: 5,
+            firings: false,
+        });
+        let trajectory = results[3].trajectory.as_ref().unwrap();
+        assert_eq!(trajectory.species, expected.run(Some(3)).unwrap().species);
+        // errors are results too
+        let mut invalid = Model::clone(&model());
+        invalid.species[0].name = "B".to_string();
+        queue.submit(Job::new(Arc::new(invalid), 0, 1., 1));
+        let result = queue.recv().unwrap();
+        assert!(result.trajectory.is_err());
+        assert!(queue.try_recv().is_none());
+        // the queue can be dropped with waiting jobs
+        for seed in 0..100 {
+            queue.submit(Job::new(model(), seed, 1e3, 1000));
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn panics() {
+        // the amount overflows, which only panics with debug assertions
+        let mut overflow = Model::clone(&model());
+        overflow.species[0].initial = isize::MAX;
+        overflow.reactions[0].reactants.clear();
+        overflow.reactions[0].products.insert("A".to_string(), 1);
+        let queue = JobQueue::new(1);
+        let panicking = queue.submit(Job::new(Arc::new(overflow), 0, 10., 1));
+        let normal = queue.submit(Job::new(model(), 0, 10., 1));
+        let results: Vec<_> = queue.results().collect();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].id, panicking);
+        assert!(matches!(
+            results[0].trajectory,
+            Err(crate::model::ModelError::Panicked(ref msg)) if msg.contains("overflow")
+        ));
+        assert_eq!(results[1].id, normal);
+        assert!(results[1].trajectory.is_ok());
+    }
+}
@@ -6,12 +6,12 @@
 //! networks:
 //!
 //! * a macro-based DSL implemented by [`define_system`], usually the
-//! most efficient, but that requires to compile a rust program;
+//!   most efficient, but that requires to compile a rust program;
 //! * a function-based API implemented by the module [`gillespie`], also
-//! available through Python bindings.  This one does not require a rust
-//! compilation and allows the system to be defined at run time.  It is
-//! typically 2 or 3 times slower than the macro DSL, but still faster
-//! than all other software tried.
+//!   available through Python bindings.  This one does not require a rust
+//!   compilation and allows the system to be defined at run time.  It is
+//!   typically 2 or 3 times slower than the macro DSL, but still faster
+//!   than all other software tried.
 //!
 //! # The macro DSL
 //!
@@ -107,7 +107,7 @@
 //! You can test this code by installing `rebop` from PyPI with
 //! `pip install rebop`. To build the Python bindings from source,
 //! the simplest is to clone this git repository and use `maturin
-//! develop`.
+//! develop`, which enables the `python` feature of the crate.
 //!
 //! # The traditional API
 //!
@@ -168,23 +168,19 @@
 //! fastest is rebop's traditional API invoked by convenience through
 //! the Python bindings.
 //!
+//! The delayed reactions are exercised by the Hes1 oscillator of Monk
+//! (2003), whose period is compared with the published one, in
+//! [benches/hes1](https://github.com/Armavica/rebop/tree/main/benches/hes1).
+//!
 //! # Features to come
 //!
 //! * compartment volumes
-//! * arbitrary reaction rates
-//! * other SSA algorithms
-//! * tau-leaping
 //! * adaptive tau-leaping
 //! * hybrid models (continuous and discrete)
-//! * SBML
 //! * CLI interface
-//! * parameter estimation
-//! * local sensitivity analysis
-//! * parallelization
 //!
 //! # Features probably not to come
 //!
-//! * events
 //! * space (reaction-diffusion systems)
 //! * rule modelling
 //!
@@ -230,165 +226,52 @@
 //! * [SmartCell](http://software.crg.es/smartcell/)
 //! * [NFsim](http://michaelsneddon.net/nfsim/)
 
-use pyo3::prelude::*;
-use std::collections::HashMap;
-
+pub use error::Error;
 pub use rand;
 pub use rand_distr;
+// called by `define_system!` with the path `$crate` of this crate, so that
+// the generated code works even if the crate is renamed
+#[doc(hidden)]
+pub use rebop_macros::define_system as __define_system;
 
+pub mod accuracy;
+pub mod algorithm;
+#[cfg(feature = "model")]
+pub mod bngl;
+pub mod builder;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod codegen;
+pub mod conservation;
+mod error;
+pub mod events;
+pub mod filter;
+pub mod fixed;
 pub mod gillespie;
 mod gillespie_macro;
-
-/// Reaction system composed of species and reactions.
-#[pyclass]
-struct Gillespie {
-    species: HashMap<String, usize>,
-    reactions: Vec<(f64, Vec<String>, Vec<String>)>,
-}
-
-#[pymethods]
-impl Gillespie {
-    #[new]
-    fn new() -> Self {
-        Gillespie {
-            species: HashMap::new(),
-            reactions: Vec::new(),
-        }
-    }
-    /// Number of species currently in the system
-    fn nb_species(&self) -> PyResult<usize> {
-        Ok(self.species.len())
-    }
-    /// Add a Law of Mass Action reaction to the system.
-    ///
-    /// The forward reaction rate is `rate`, while `reactants` and `products` are lists of
-    /// respectively reactant names and product names.  Add the reverse reaction with the rate
-    /// `reverse_rate` if it is not `None`.
-    #[pyo3(signature = (rate, reactants, products, reverse_rate=None))]
-    fn add_reaction(
-        &mut self,
-        rate: f64,
-        reactants: Vec<String>,
-        products: Vec<String>,
-        reverse_rate: Option<f64>,
-    ) -> PyResult<()> {
-        // Insert unknown reactants in known species
-        for reactant in &reactants {
-            if !self.species.contains_key(reactant) {
-                self.species.insert(reactant.clone(), self.species.len());
-            }
-        }
-        // Insert unknown products in known species
-        for product in &products {
-            if !self.species.contains_key(product) {
-                self.species.insert(product.clone(), self.species.len());
-            }
-        }
-        self.reactions
-            .push((rate, reactants.clone(), products.clone()));
-        if let Some(rrate) = reverse_rate {
-            self.reactions.push((rrate, products, reactants));
-        }
-        Ok(())
-    }
-    /// Number of reactions currently in the system.
-    fn nb_reactions(&self) -> PyResult<usize> {
-        Ok(self.reactions.len())
-    }
-    /// Run the system until `tmax` with `nb_steps` steps.
-    ///
-    /// The initial configuration is specified in the dictionary `init`.
-    /// Returns `times, vars` where `times` is an array of `nb_steps + 1` uniformly spaced time
-    /// points between `0` and `tmax`, and `vars` is a dictionary of species name to array of
-    /// values at the given time points.  One can specify a random `seed` for reproducibility.
-    /// If `nb_steps` is `0`, then returns all reactions, ending with the first that happens at
-    /// or after `tmax`.
-    #[pyo3(signature = (init, tmax, nb_steps, seed=None))]
-    fn run(
-        &self,
-        init: HashMap<String, usize>,
-        tmax: f64,
-        nb_steps: usize,
-        seed: Option<u64>,
-    ) -> PyResult<(Vec<f64>, HashMap<String, Vec<isize>>)> {
-        let mut x0 = vec![0; self.species.len()];
-        for (name, &value) in &init {
-            if let Some(&id) = self.species.get(name) {
-                x0[id] = value as isize;
-            }
-        }
-        let mut g = match seed {
-            Some(seed) => gillespie::Gillespie::new_with_seed(x0, seed),
-            None => gillespie::Gillespie::new(x0),
-        };
-
-        for (rate, reactants, products) in self.reactions.iter() {
-            let mut vreactants = vec![0; self.species.len()];
-            for reactant in reactants {
-                vreactants[self.species[reactant]] += 1;
-            }
-            let rate = gillespie::Rate::lma(*rate, vreactants);
-            let mut actions = vec![0; self.species.len()];
-            for reactant in reactants {
-                actions[self.species[reactant]] -= 1;
-            }
-            for product in products {
-                actions[self.species[product]] += 1;
-            }
-            g.add_reaction(rate, actions);
-        }
-        let mut times = Vec::new();
-        // species.shape = (species, nb_steps)
-        let mut species = vec![Vec::new(); self.species.len()];
-        if nb_steps > 0 {
-            for i in 0..=nb_steps {
-                let t = tmax * i as f64 / nb_steps as f64;
-                times.push(t);
-                g.advance_until(t);
-                for s in 0..self.species.len() {
-                    species[s].push(g.get_species(s));
-                }
-            }
-        } else {
-            // nb_steps = 0: we return every step
-            let mut rates = vec![f64::NAN; g.nb_reactions()];
-            times.push(g.get_time());
-            for s in 0..self.species.len() {
-                species[s].push(g.get_species(s));
-            }
-            while g.get_time() < tmax {
-                g._advance_one_reaction(&mut rates);
-                times.push(g.get_time());
-                for s in 0..self.species.len() {
-                    species[s].push(g.get_species(s));
-                }
-            }
-        }
-        let mut result = HashMap::new();
-        for (name, &id) in &self.species {
-            result.insert(name.clone(), species[id].clone());
-        }
-        Ok((times, result))
-    }
-    fn __str__(&self) -> PyResult<String> {
-        let mut s = format!(
-            "{} species and {} reactions\n",
-            self.species.len(),
-            self.reactions.len()
-        );
-        for (rate, reactants, products) in &self.reactions {
-            s.push_str(&reactants.join(" + "));
-            s.push_str(" --> ");
-            s.push_str(&products.join(" + "));
-            s.push_str(&format!(" @ {}\n", rate));
-        }
-        Ok(s)
-    }
-}
-
-#[pymodule]
-fn rebop(m: &Bound<'_, PyModule>) -> PyResult<()> {
-    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
-    m.add_class::<Gillespie>()?;
-    Ok(())
-}
+mod index_enum;
+pub mod inference;
+pub mod io;
+#[cfg(feature = "jit")]
+mod jit;
+#[cfg(feature = "model")]
+pub mod jobs;
+#[cfg(feature = "model")]
+pub mod model;
+pub mod objectives;
+pub mod ode;
+pub mod pacing;
+pub mod passage;
+pub mod population;
+pub mod predictive;
+#[cfg(feature = "python")]
+mod python;
+pub mod qmc;
+pub mod rng;
+pub mod sensitivity;
+mod simd;
+pub mod stats;
+pub mod system;
+pub mod testing;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -0,0 +1,2767 @@
+//! Declarative model format.
+//!
+//! A model can be described in a TOML or JSON document listing its
+//! species, parameters, reactions, timed events, output settings and
+//! observables.
+//! The document is validated strictly: unknown fields, unknown species
+//! or parameters, and schema versions that this version of rebop does
+//! not know about are all reported as errors.
+//!
+//! ```toml
+//! version = 1
+//!
+//! species = [
+//!     { name = "S", initial = 999 },
+//!     { name = "I", initial = 1 },
+//!     { name = "R" },
+//! ]
+//! parameters = [
+//!     { name = "r_inf", value = 1e-4 },
+//!     { name = "r_heal", value = 0.01 },
+//! ]
+//!
+//! [[reactions]]
+//! name = "infection"
+//! reactants = { S = 1, I = 1 }
+//! products = { I = 2 }
+//! rate = "r_inf"
+//! annotations = { sbo = "SBO:0000176", reference = "doi:10.1098/rspa.1927.0118" }
+//!
+//! [[reactions]]
+//! name = "healing"
+//! reactants = { I = 1 }
+//! products = { R = 1 }
+//! rate = "r_heal"
+//!
+//! # lockdown at t = 30
+//! [[events]]
+//! time = 30.0
+//! parameters = { r_inf = 3e-5 }
+//!
+//! # no more recovery at t = 200
+//! [[events]]
+//! time = 200.0
+//! reactions = { healing = false }
+//!
+//! [output]
+//! tmax = 250.0
+//! nb_steps = 250
+//! ```
+//!
+//! Such a document is loaded with [`Model::from_toml_str`],
+//! [`Model::from_json_str`] or [`Model::from_file`], and can then be
+//! turned into a [`Gillespie`] problem or simulated directly:
+//!
+//! ```
+//! use rebop::model::Model;
+//! let model = Model::from_toml_str(r#"
+//!     version = 1
+//!     species = [{ name = "A", initial = 10 }]
+//!     [[reactions]]
+//!     reactants = { A = 1 }
+//!     rate = 0.1
+//!     [output]
+//!     tmax = 10.0
+//!     nb_steps = 10
+//! "#).unwrap();
+//! let trajectory = model.run(Some(0)).unwrap();
+//! assert_eq!(trajectory.times.len(), 11);
+//! assert!(trajectory.species[0][10] <= 10);
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::iter::Peekable;
+use std::ops::AddAssign;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Instant;
+
+use rand::{Rng, RngCore};
+use rand_distr::{Binomial, Distribution, Poisson, StandardNormal};
+
+use crate::gillespie::{Gillespie, Metadata, Method, Rate, Trajectory};
+use crate::rng::Philox;
+
+/// Version of the model schema understood and written by this version
+/// of rebop.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Errors that can happen when loading, validating or running a model.
+#[derive(Debug)]
+pub enum ModelError {
+    /// The model file could not be read.
+    Io(std::io::Error),
+    /// The file extension is not `.toml`, `.json` or `.net`.
+    UnknownFormat(String),
+    /// The document is not valid TOML or JSON, or does not follow the
+    /// schema.
+    Parse(String),
+    /// The model cannot be written as TOML or JSON.
+    Serialize(String),
+    /// The document does not declare its schema version.
+    MissingVersion,
+    /// The document uses a schema version that is not supported.
+    UnsupportedVersion { found: u32, supported: u32 },
+    /// The document follows the schema but is inconsistent.
+    Invalid(String),
+    /// The model has no `output` section but one is needed to run it.
+    MissingOutput,
+    /// The simulation of the model panicked, with this message.
+    Panicked(String),
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelError::Io(e) => write!(f, "cannot read model file: {e}"),
+            ModelError::UnknownFormat(ext) => write!(
+                f,
+                "unknown model file extension `{ext}`, expected `toml`, `json` or `net`"
+            ),
+            ModelError::Parse(msg) => write!(f, "cannot parse model: {msg}"),
+            ModelError::Serialize(msg) => write!(f, "cannot serialize model: {msg}"),
+            ModelError::MissingVersion => write!(
+                f,
+                "model does not declare its schema version, add `version = {SCHEMA_VERSION}`"
+            ),
+            ModelError::UnsupportedVersion { found, supported } if found > supported => write!(
+                f,
+                "model uses schema version {found} but this version of rebop only \
+                 supports versions up to {supported}, please upgrade rebop"
+            ),
+            ModelError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "model uses schema version {found} which is not supported anymore, \
+                 please migrate it to version {supported}"
+            ),
+            ModelError::Invalid(msg) => write!(f, "invalid model: {msg}"),
+            ModelError::MissingOutput => write!(f, "model has no `output` section"),
+            ModelError::Panicked(msg) => write!(f, "simulation panicked: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for ModelError {}
+
+/// Free-form annotations of a species or a reaction, like SBO terms,
+/// comments or literature references, by key.  They do not change the
+/// simulations, but are kept by the serializers.
+pub type Annotations = BTreeMap<String, String>;
+
+/// Chemical species, with its initial amount.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Species {
+    pub name: String,
+    #[serde(default)]
+    pub initial: isize,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: Annotations,
+}
+
+/// Named numerical parameter, usable as a reaction rate.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Parameter {
+    pub name: String,
+    pub value: f64,
+}
+
+/// Rate constant of a reaction, either given directly or as the name
+/// of a parameter.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RateSpec {
+    Value(f64),
+    Parameter(String),
+}
+
+impl fmt::Display for RateSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RateSpec::Value(value) => write!(f, "{value}"),
+            RateSpec::Parameter(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// Reaction following the law of mass action.
+///
+/// `reactants` and `products` map species names to their
+/// stoichiometries.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Reaction {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub reactants: BTreeMap<String, u32>,
+    #[serde(default)]
+    pub products: BTreeMap<String, u32>,
+    pub rate: RateSpec,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: Annotations,
+}
+
+impl FromStr for Reaction {
+    type Err = ModelError;
+    /// Parses a reaction written as an equation, like
+    /// `"infection: S + I -> 2 I @ r_inf"`, with an optional name, the
+    /// stoichiometries before the species names, and a rate constant
+    /// that is a number or the name of a parameter.  An empty side, or
+    /// `0`, has no species.
+    ///
+    /// ```
+    /// use rebop::model::{RateSpec, Reaction};
+    /// let reaction: Reaction = "S + I -> 2 I @ 1e-4".parse().unwrap();
+    /// assert_eq!(reaction.reactants.len(), 2);
+    /// assert_eq!(reaction.products["I"], 2);
+    /// assert_eq!(reaction.rate, RateSpec::Value(1e-4));
+    /// assert_eq!(reaction.to_string(), "I + S -> 2 I @ 0.0001");
+    /// ```
+    fn from_str(s: &str) -> Result<Self, ModelError> {
+        let error = |msg: String| ModelError::Parse(format!("{msg} in reaction `{s}`"));
+        let (equation, rate) = s
+            .rsplit_once('@')
+            .ok_or_else(|| error("no `@ rate`".to_string()))?;
+        let rate = match rate.trim() {
+            rate if is_name(rate) => RateSpec::Parameter(rate.to_string()),
+            rate => RateSpec::Value(
+                rate.parse()
+                    .map_err(|_| error(format!("invalid rate `{rate}`")))?,
+            ),
+        };
+        let (name, equation) = match equation.split_once(':') {
+            Some((name, equation)) if is_name(name.trim()) => {
+                (Some(name.trim().to_string()), equation)
+            }
+            Some((name, _)) => return Err(error(format!("invalid name `{}`", name.trim()))),
+            None => (None, equation),
+        };
+        let (reactants, products) = equation
+            .split_once("->")
+            .ok_or_else(|| error("no `->`".to_string()))?;
+        Ok(Reaction {
+            name,
+            reactants: parse_side(reactants).map_err(error)?,
+            products: parse_side(products).map_err(error)?,
+            rate,
+            annotations: Annotations::new(),
+        })
+    }
+}
+
+impl fmt::Display for Reaction {
+    /// Writes the reaction as an equation that [`Reaction::from_str`]
+    /// parses back, without its annotations.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let side = |stoichiometries: &BTreeMap<String, u32>| {
+            stoichiometries
+                .iter()
+                .map(|(name, &n)| match n {
+                    1 => name.clone(),
+                    n => format!("{n} {name}"),
+                })
+                .collect::<Vec<_>>()
+                .join(" + ")
+        };
+        if let Some(name) = &self.name {
+            write!(f, "{name}: ")?;
+        }
+        let (reactants, products) = (side(&self.reactants), side(&self.products));
+        let arrow = match (reactants.is_empty(), products.is_empty()) {
+            (true, true) => "0 -> 0",
+            (true, false) => "0 -> ",
+            (false, true) => " -> 0",
+            (false, false) => " -> ",
+        };
+        write!(f, "{reactants}{arrow}{products} @ {}", self.rate)
+    }
+}
+
+/// Whether `name` can name a species, a parameter or a reaction in an
+/// equation: a letter or an underscore, then letters, digits or
+/// underscores.
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Parses a side of an equation, like `2 A + B`, into stoichiometries.
+fn parse_side(side: &str) -> Result<BTreeMap<String, u32>, String> {
+    let mut stoichiometries = BTreeMap::new();
+    let side = side.trim();
+    if side.is_empty() || side == "0" {
+        return Ok(stoichiometries);
+    }
+    for term in side.split('+') {
+        let term = term.trim();
+        let digits = term
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(term.len());
+        let (n, name) = term.split_at(digits);
+        let n = match n {
+            "" => 1,
+            n => n
+                .parse()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(|| format!("invalid stoichiometry `{n}`"))?,
+        };
+        let name = name.trim();
+        if !is_name(name) {
+            return Err(format!("invalid species `{term}`"));
+        }
+        *stoichiometries.entry(name.to_string()).or_insert(0) += n;
+    }
+    Ok(stoichiometries)
+}
+
+/// Modification of the model happening at a given time.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Event {
+    pub time: f64,
+    /// New amounts of species.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub species: BTreeMap<String, isize>,
+    /// New values of parameters.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub parameters: BTreeMap<String, f64>,
+    /// Reactions enabled (`true`) or disabled (`false`), by name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub reactions: BTreeMap<String, bool>,
+}
+
+/// Time points at which the state of the model is reported: `nb_steps
+/// + 1` uniformly spaced points between `0` and `tmax`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Output {
+    pub tmax: f64,
+    pub nb_steps: usize,
+    /// Whether to report the number of firings of each reaction between
+    /// two time points, in [`Trajectory::firings`].
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub firings: bool,
+}
+
+/// Measurement noise of an [`Observable`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Noise {
+    /// Exact measurements.
+    #[default]
+    None,
+    /// Additive Gaussian noise of standard deviation `sd`.
+    Gaussian { sd: f64 },
+    /// Multiplicative log-normal noise: the measurement is the exact
+    /// value times `exp(sigma * z)` for a standard normal `z`.
+    LogNormal { sigma: f64 },
+    /// Poisson counts of mean the exact value, like the number of
+    /// photons emitted by the molecules.
+    Poisson,
+    /// Detection of each molecule with the given `probability`, like in
+    /// single-molecule imaging.  The exact value is rounded to the
+    /// nearest number of molecules.
+    Binomial { probability: f64 },
+}
+
+impl Noise {
+    /// Returns a noisy measurement of `value`.
+    fn measure<R: Rng>(&self, value: f64, rng: &mut R) -> f64 {
+        match *self {
+            Noise::None => value,
+            Noise::Gaussian { sd } => value + sd * rng.sample::<f64, _>(StandardNormal),
+            Noise::LogNormal { sigma } => {
+                value * (sigma * rng.sample::<f64, _>(StandardNormal)).exp()
+            }
+            Noise::Poisson if value > 0. => Poisson::new(value).unwrap().sample(rng),
+            Noise::Poisson => 0.,
+            Noise::Binomial { probability } => {
+                let n = value.round().max(0.) as u64;
+                Binomial::new(n, probability).unwrap().sample(rng) as f64
+            }
+        }
+    }
+}
+
+/// Named linear combination of the species of a model, like the total
+/// amount of a protein in its free and bound forms.
+///
+/// Observables are reported by [`Model::run`] at the time points of the
+/// output, and measured by [`Model::observe`] at their own sampling
+/// times, with their noise, like the species measured in an experiment.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Observable {
+    pub name: String,
+    /// Weights of the species in the observable, by name.
+    pub species: BTreeMap<String, f64>,
+    /// Increasing sampling times, for [`Model::observe`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub times: Vec<f64>,
+    #[serde(default)]
+    pub noise: Noise,
+}
+
+/// Measurements of an [`Observable`], returned by [`Model::observe`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Measurements {
+    /// Name of the observable.
+    pub name: String,
+    /// Sampling times.
+    pub times: Vec<f64>,
+    /// Measured values at the sampling times.
+    pub values: Vec<f64>,
+}
+
+/// Phase of a simulation schedule, see [`Model::run_schedule`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Phase {
+    /// Time at which the phase starts.
+    pub start: f64,
+    /// Time at which the phase ends.
+    pub end: f64,
+    /// Values of the parameters that differ from the model during the
+    /// phase.
+    pub parameters: BTreeMap<String, f64>,
+    /// Simulation method of the phase, the default one if `None`.
+    pub method: Option<Method>,
+    /// Number of uniformly spaced time points reported in the phase,
+    /// after its start.
+    pub nb_steps: usize,
+}
+
+impl Phase {
+    /// Creates a phase from `start` to `end` with the parameters of the
+    /// model, reported at `nb_steps` time points.
+    pub fn new(start: f64, end: f64, nb_steps: usize) -> Self {
+        Phase {
+            start,
+            end,
+            parameters: BTreeMap::new(),
+            method: None,
+            nb_steps,
+        }
+    }
+    /// Sets the value of the parameter `name` during the phase.
+    pub fn with_parameter(mut self, name: &str, value: f64) -> Self {
+        self.parameters.insert(name.to_string(), value);
+        self
+    }
+    /// Sets the simulation method of the phase.
+    pub fn with_method(mut self, method: Method) -> Self {
+        self.method = Some(method);
+        self
+    }
+}
+
+/// Complete description of a model.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Model {
+    pub version: u32,
+    #[serde(default)]
+    pub species: Vec<Species>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parameters: Vec<Parameter>,
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<Event>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<Output>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub observables: Vec<Observable>,
+}
+
+/// Only the version of the document, read before the strict parsing so
+/// that version errors take precedence over schema errors.
+#[derive(Deserialize)]
+struct Header {
+    version: Option<u32>,
+}
+
+fn check_version(version: Option<u32>) -> Result<(), ModelError> {
+    match version {
+        None => Err(ModelError::MissingVersion),
+        Some(SCHEMA_VERSION) => Ok(()),
+        Some(found) => Err(ModelError::UnsupportedVersion {
+            found,
+            supported: SCHEMA_VERSION,
+        }),
+    }
+}
+
+impl Model {
+    /// Creates an empty model with the current schema version.
+    pub fn new() -> Self {
+        Model {
+            version: SCHEMA_VERSION,
+            species: Vec::new(),
+            parameters: Vec::new(),
+            reactions: Vec::new(),
+            events: Vec::new(),
+            output: None,
+            observables: Vec::new(),
+        }
+    }
+    /// Parses and validates a model from a TOML document.
+    pub fn from_toml_str(s: &str) -> Result<Self, ModelError> {
+        let header: Header = toml::from_str(s).map_err(|e| ModelError::Parse(e.to_string()))?;
+        check_version(header.version)?;
+        let model: Model = toml::from_str(s).map_err(|e| ModelError::Parse(e.to_string()))?;
+        model.validate()?;
+        Ok(model)
+    }
+    /// Parses and validates a model from a JSON document.
+    pub fn from_json_str(s: &str) -> Result<Self, ModelError> {
+        let header: Header =
+            serde_json::from_str(s).map_err(|e| ModelError::Parse(e.to_string()))?;
+        check_version(header.version)?;
+        let model: Model = serde_json::from_str(s).map_err(|e| ModelError::Parse(e.to_string()))?;
+        model.validate()?;
+        Ok(model)
+    }
+    /// Reads a model from a `.toml` or `.json` file, or from a BioNetGen
+    /// network in a `.net` file, read by [`bngl::parse_net`](crate::bngl::parse_net).
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ModelError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(ModelError::Io)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Model::from_toml_str(&content),
+            Some("json") => Model::from_json_str(&content),
+            Some("net") => crate::bngl::parse_net(&content),
+            ext => Err(ModelError::UnknownFormat(ext.unwrap_or("").to_string())),
+        }
+    }
+    /// Serializes the model to a TOML document.
+    pub fn to_toml_string(&self) -> Result<String, ModelError> {
+        toml::to_string(self).map_err(|e| ModelError::Serialize(e.to_string()))
+    }
+    /// Serializes the model to a JSON document.
+    pub fn to_json_string(&self) -> Result<String, ModelError> {
+        serde_json::to_string_pretty(self).map_err(|e| ModelError::Serialize(e.to_string()))
+    }
+    /// Returns the model with `prefix` before the names of its species,
+    /// parameters, reactions and observables.
+    ///
+    /// Several copies of a reusable module, like a receptor, then compose
+    /// in a [`Composition`] without their names colliding, and aliases of
+    /// the prefixed names connect the species they share.
+    ///
+    /// ```
+    /// use rebop::model::{Composition, Model};
+    /// let receptor = Model::from_toml_str(r#"
+    ///     version = 1
+    ///     species = [{ name = "L", initial = 100 }, { name = "R", initial = 10 }, { name = "LR" }]
+    ///     parameters = [{ name = "k_on", value = 1e-3 }]
+    ///     [[reactions]]
+    ///     name = "binding"
+    ///     reactants = { L = 1, R = 1 }
+    ///     products = { LR = 1 }
+    ///     rate = "k_on"
+    /// "#).unwrap();
+    /// let composition = Composition::new()
+    ///     .alias("r1_L", "L")
+    ///     .alias("r2_L", "L")
+    ///     .import(&receptor.with_prefix("r1_"))
+    ///     .import(&receptor.with_prefix("r2_"));
+    /// let model = composition.to_model().unwrap();
+    /// let names: Vec<_> = model.species.iter().map(|s| s.name.as_str()).collect();
+    /// assert_eq!(names, ["L", "r1_R", "r1_LR", "r2_R", "r2_LR"]);
+    /// assert_eq!(model.reactions[1].to_string(), "r2_binding: L + r2_R -> r2_LR @ r2_k_on");
+    /// ```
+    pub fn with_prefix(&self, prefix: &str) -> Model {
+        let name = |name: &str| format!("{prefix}{name}");
+        Model {
+            species: (self.species.iter())
+                .map(|species| Species {
+                    name: name(&species.name),
+                    ..species.clone()
+                })
+                .collect(),
+            parameters: (self.parameters.iter())
+                .map(|param| Parameter {
+                    name: name(&param.name),
+                    value: param.value,
+                })
+                .collect(),
+            reactions: (self.reactions.iter())
+                .map(|reaction| Reaction {
+                    name: reaction.name.as_deref().map(name),
+                    reactants: prefix_keys(prefix, &reaction.reactants),
+                    products: prefix_keys(prefix, &reaction.products),
+                    rate: match &reaction.rate {
+                        RateSpec::Parameter(param) => RateSpec::Parameter(name(param)),
+                        RateSpec::Value(value) => RateSpec::Value(*value),
+                    },
+                    annotations: reaction.annotations.clone(),
+                })
+                .collect(),
+            events: (self.events.iter())
+                .map(|event| Event {
+                    time: event.time,
+                    species: prefix_keys(prefix, &event.species),
+                    parameters: prefix_keys(prefix, &event.parameters),
+                    reactions: prefix_keys(prefix, &event.reactions),
+                })
+                .collect(),
+            observables: (self.observables.iter())
+                .map(|observable| Observable {
+                    name: name(&observable.name),
+                    species: prefix_keys(prefix, &observable.species),
+                    ..observable.clone()
+                })
+                .collect(),
+            version: self.version,
+            output: self.output.clone(),
+        }
+    }
+    /// Returns the stoichiometry matrix of the model: the net change of
+    /// each species, by row in the order of the species, when each
+    /// reaction fires, by column.  The model is assumed valid.
+    ///
+    /// ```
+    /// use rebop::model::{Model, Reaction, Species};
+    /// let mut model = Model::new();
+    /// for name in ["S", "I", "R"] {
+    ///     model.species.push(Species { name: name.into(), initial: 0, annotations: Default::default() });
+    /// }
+    /// model.reactions.push("S + I -> 2 I @ 1e-4".parse().unwrap());
+    /// model.reactions.push("I -> R @ 0.01".parse().unwrap());
+    /// assert_eq!(model.stoichiometry_matrix(), [[-1, 0], [1, -1], [0, 1]]);
+    /// ```
+    pub fn stoichiometry_matrix(&self) -> Vec<Vec<isize>> {
+        let species = self.species_indices();
+        let mut matrix = vec![vec![0; self.reactions.len()]; self.species.len()];
+        for (ireaction, reaction) in self.reactions.iter().enumerate() {
+            for (name, &stoichiometry) in &reaction.reactants {
+                matrix[species[name.as_str()]][ireaction] -= stoichiometry as isize;
+            }
+            for (name, &stoichiometry) in &reaction.products {
+                matrix[species[name.as_str()]][ireaction] += stoichiometry as isize;
+            }
+        }
+        matrix
+    }
+    /// Writes the reaction network in the DOT language of Graphviz, as a
+    /// bipartite graph: the species are ellipses, the reactions are
+    /// boxes, and the arrows go from the reactants to the reactions and
+    /// from the reactions to the products, labeled by the
+    /// stoichiometries other than `1`.  The model is assumed valid.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph network {\n    node [shape=ellipse];\n");
+        for (i, s) in self.species.iter().enumerate() {
+            dot += &format!("    s{i} [label=\"{}\"];\n", escape_dot(&s.name));
+        }
+        dot += "    node [shape=box];\n";
+        for (ireaction, reaction) in self.reactions.iter().enumerate() {
+            dot += &format!(
+                "    r{ireaction} [label=\"{}\\n{}\"];\n",
+                escape_dot(&reaction_label(reaction, ireaction)),
+                escape_dot(&reaction.rate.to_string())
+            );
+        }
+        for (source, target, n) in self.edges() {
+            dot += &match n {
+                1 => format!("    {source} -> {target};\n"),
+                n => format!("    {source} -> {target} [label=\"{n}\"];\n"),
+            };
+        }
+        dot + "}\n"
+    }
+    /// Writes the reaction network in GraphML, as the bipartite graph of
+    /// [`to_dot`](Model::to_dot), for network-analysis tools.  The nodes
+    /// have a `kind`, `species` or `reaction`, and a `name`; the reactions
+    /// also have a `rate`, and the edges a `stoichiometry`.  The model is
+    /// assumed valid.
+    pub fn to_graphml(&self) -> String {
+        let mut xml = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+            "  <key id=\"kind\" for=\"node\" attr.name=\"kind\" attr.type=\"string\"/>\n",
+            "  <key id=\"name\" for=\"node\" attr.name=\"name\" attr.type=\"string\"/>\n",
+            "  <key id=\"rate\" for=\"node\" attr.name=\"rate\" attr.type=\"string\"/>\n",
+            "  <key id=\"stoichiometry\" for=\"edge\" attr.name=\"stoichiometry\" ",
+            "attr.type=\"int\"/>\n",
+            "  <graph id=\"network\" edgedefault=\"directed\">\n",
+        ));
+        for (i, s) in self.species.iter().enumerate() {
+            xml += &format!(
+                "    <node id=\"s{i}\"><data key=\"kind\">species</data>\
+                 <data key=\"name\">{}</data></node>\n",
+                escape_xml(&s.name)
+            );
+        }
+        for (ireaction, reaction) in self.reactions.iter().enumerate() {
+            xml += &format!(
+                "    <node id=\"r{ireaction}\"><data key=\"kind\">reaction</data>\
+                 <data key=\"name\">{}</data><data key=\"rate\">{}</data></node>\n",
+                escape_xml(&reaction_label(reaction, ireaction)),
+                escape_xml(&reaction.rate.to_string())
+            );
+        }
+        for (source, target, n) in self.edges() {
+            xml += &format!(
+                "    <edge source=\"{source}\" target=\"{target}\">\
+                 <data key=\"stoichiometry\">{n}</data></edge>\n"
+            );
+        }
+        xml + "  </graph>\n</graphml>\n"
+    }
+    /// Returns the edges of the bipartite graph of the network, from the
+    /// reactants `s{i}` to the reactions `r{j}` and from the reactions to
+    /// the products, with their stoichiometries.
+    fn edges(&self) -> Vec<(String, String, u32)> {
+        let species = self.species_indices();
+        let mut edges = Vec::new();
+        for (ireaction, reaction) in self.reactions.iter().enumerate() {
+            for (name, &n) in &reaction.reactants {
+                edges.push((
+                    format!("s{}", species[name.as_str()]),
+                    format!("r{ireaction}"),
+                    n,
+                ));
+            }
+            for (name, &n) in &reaction.products {
+                edges.push((
+                    format!("r{ireaction}"),
+                    format!("s{}", species[name.as_str()]),
+                    n,
+                ));
+            }
+        }
+        edges
+    }
+    /// Checks that the model is consistent: names are unique, reactions
+    /// and events only refer to declared species and parameters, and
+    /// numerical values are in their valid range.
+    pub fn validate(&self) -> Result<(), ModelError> {
+        check_version(Some(self.version))?;
+        let invalid = |msg: String| Err(ModelError::Invalid(msg));
+        let mut names = HashSet::new();
+        for species in &self.species {
+            if !names.insert(species.name.as_str()) {
+                return invalid(format!("species `{}` is declared twice", species.name));
+            }
+            if species.initial < 0 {
+                return invalid(format!(
+                    "species `{}` has a negative initial amount",
+                    species.name
+                ));
+            }
+        }
+        for param in &self.parameters {
+            if !names.insert(param.name.as_str()) {
+                return invalid(format!("name `{}` is declared twice", param.name));
+            }
+            if !param.value.is_finite() {
+                return invalid(format!("parameter `{}` is not finite", param.name));
+            }
+        }
+        let species = self.species_indices();
+        let params = self.parameter_values();
+        for (ireaction, reaction) in self.reactions.iter().enumerate() {
+            let rname = reaction_label(reaction, ireaction);
+            for (name, &stoichiometry) in reaction.reactants.iter().chain(&reaction.products) {
+                if !species.contains_key(name.as_str()) {
+                    return invalid(format!(
+                        "reaction `{rname}` refers to unknown species `{name}`"
+                    ));
+                }
+                if stoichiometry == 0 {
+                    return invalid(format!(
+                        "reaction `{rname}` has a zero stoichiometry for `{name}`"
+                    ));
+                }
+            }
+            let rate = match &reaction.rate {
+                RateSpec::Value(value) => *value,
+                RateSpec::Parameter(name) => match params.get(name.as_str()) {
+                    Some(&value) => value,
+                    None => {
+                        return invalid(format!(
+                            "reaction `{rname}` refers to unknown parameter `{name}`"
+                        ))
+                    }
+                },
+            };
+            if !(rate >= 0. && rate.is_finite()) {
+                return invalid(format!("reaction `{rname}` has an invalid rate {rate}"));
+            }
+        }
+        for event in &self.events {
+            if !(event.time >= 0. && event.time.is_finite()) {
+                return invalid(format!("event has an invalid time {}", event.time));
+            }
+            for (name, &amount) in &event.species {
+                if !species.contains_key(name.as_str()) {
+                    return invalid(format!("event refers to unknown species `{name}`"));
+                }
+                if amount < 0 {
+                    return invalid(format!("event sets a negative amount of `{name}`"));
+                }
+            }
+            for (name, value) in &event.parameters {
+                if !params.contains_key(name.as_str()) {
+                    return invalid(format!("event refers to unknown parameter `{name}`"));
+                }
+                if !(*value >= 0. && value.is_finite()) {
+                    return invalid(format!("event sets `{name}` to an invalid value {value}"));
+                }
+            }
+            for name in event.reactions.keys() {
+                if self.reaction_index(name).is_none() {
+                    return invalid(format!("event refers to unknown reaction `{name}`"));
+                }
+            }
+        }
+        if let Some(output) = &self.output {
+            if !(output.tmax >= 0. && output.tmax.is_finite()) {
+                return invalid(format!("output has an invalid tmax {}", output.tmax));
+            }
+            if output.nb_steps == 0 {
+                return invalid("output needs at least one step".to_string());
+            }
+        }
+        let mut observables = HashSet::new();
+        for observable in &self.observables {
+            let name = &observable.name;
+            if !observables.insert(name.as_str()) {
+                return invalid(format!("observable `{name}` is declared twice"));
+            }
+            for (species_name, weight) in &observable.species {
+                if !species.contains_key(species_name.as_str()) {
+                    return invalid(format!(
+                        "observable `{name}` refers to unknown species `{species_name}`"
+                    ));
+                }
+                if !weight.is_finite() {
+                    return invalid(format!(
+                        "observable `{name}` has an invalid weight for `{species_name}`"
+                    ));
+                }
+            }
+            let increasing = observable.times.windows(2).all(|w| w[0] < w[1]);
+            let valid = |t: &f64| *t >= 0. && t.is_finite();
+            if !increasing || !observable.times.iter().all(valid) {
+                return invalid(format!("observable `{name}` has invalid sampling times"));
+            }
+            let valid_noise = match observable.noise {
+                Noise::None | Noise::Poisson => true,
+                Noise::Gaussian { sd: scale } | Noise::LogNormal { sigma: scale } => {
+                    scale >= 0. && scale.is_finite()
+                }
+                Noise::Binomial { probability } => (0. ..=1.).contains(&probability),
+            };
+            if !valid_noise {
+                return invalid(format!("observable `{name}` has an invalid noise"));
+            }
+        }
+        Ok(())
+    }
+    fn species_indices(&self) -> HashMap<&str, usize> {
+        self.species
+            .iter()
+            .enumerate()
+            .map(|(i, species)| (species.name.as_str(), i))
+            .collect()
+    }
+    fn reaction_index(&self, name: &str) -> Option<usize> {
+        self.reactions
+            .iter()
+            .position(|reaction| reaction.name.as_deref() == Some(name))
+    }
+    fn parameter_values(&self) -> HashMap<&str, f64> {
+        self.parameters
+            .iter()
+            .map(|param| (param.name.as_str(), param.value))
+            .collect()
+    }
+    fn rates(&self, params: &HashMap<&str, f64>) -> Vec<Rate> {
+        let species = self.species_indices();
+        self.reactions
+            .iter()
+            .map(|reaction| {
+                let value = match &reaction.rate {
+                    RateSpec::Value(value) => *value,
+                    RateSpec::Parameter(name) => params[name.as_str()],
+                };
+                let mut reactants = vec![0; self.species.len()];
+                for (name, &stoichiometry) in &reaction.reactants {
+                    reactants[species[name.as_str()]] += stoichiometry;
+                }
+                Rate::lma(value, reactants)
+            })
+            .collect()
+    }
+    /// Builds the [`Gillespie`] problem corresponding to this model, with
+    /// species at their initial amounts.  The model is assumed valid.
+    pub fn to_gillespie(&self) -> Gillespie {
+        let species = self.species_indices();
+        let mut g = Gillespie::new(self.species.iter().map(|s| s.initial).collect::<Vec<_>>());
+        for (rate, reaction) in self
+            .rates(&self.parameter_values())
+            .into_iter()
+            .zip(&self.reactions)
+        {
+            let mut differences = vec![0; self.species.len()];
+            for (name, &stoichiometry) in &reaction.reactants {
+                differences[species[name.as_str()]] -= stoichiometry as isize;
+            }
+            for (name, &stoichiometry) in &reaction.products {
+                differences[species[name.as_str()]] += stoichiometry as isize;
+            }
+            g.add_reaction(rate, differences);
+        }
+        for observable in &self.observables {
+            let mut weights = vec![0.; self.species.len()];
+            for (name, &weight) in &observable.species {
+                weights[species[name.as_str()]] = weight;
+            }
+            g.add_observable(&observable.name, weights);
+        }
+        g
+    }
+    /// Simulates the model as described by its `output` section,
+    /// applying the events at their respective times.  The values of the
+    /// observables are reported in [`Trajectory::observables`], in the
+    /// order of the model.
+    ///
+    /// ```
+    /// use rebop::model::Model;
+    /// let model = Model::from_toml_str(r#"
+    ///     version = 1
+    ///     species = [{ name = "protein", initial = 100 }, { name = "dimer" }]
+    ///     [[reactions]]
+    ///     reactants = { protein = 2 }
+    ///     products = { dimer = 1 }
+    ///     rate = 0.01
+    ///     [[observables]]
+    ///     name = "total_protein"
+    ///     species = { protein = 1, dimer = 2 }
+    ///     [output]
+    ///     tmax = 10.0
+    ///     nb_steps = 10
+    /// "#).unwrap();
+    /// let trajectory = model.run(Some(0)).unwrap();
+    /// assert!(trajectory.species[1][10] > 0);
+    /// assert_eq!(trajectory.observables[0], [100.; 11]);
+    /// ```
+    pub fn run(&self, seed: Option<u64>) -> Result<Trajectory, ModelError> {
+        self.validate()?;
+        let start = Instant::now();
+        let output = self.output.as_ref().ok_or(ModelError::MissingOutput)?;
+        let mut params = self.parameter_values();
+        let mut g = self.to_gillespie();
+        if let Some(seed) = seed {
+            g.seed(seed);
+        }
+        let mut events = self.sorted_events(0.);
+        let mut trajectory = Trajectory::new(self.species.len());
+        g.set_count_firings(output.firings);
+        for i in 0..=output.nb_steps {
+            let t = output.tmax * i as f64 / output.nb_steps as f64;
+            self.advance_with_events(&mut g, &mut params, &mut events, t);
+            trajectory.push(&g);
+            g.reset_firings();
+        }
+        trajectory.metadata = Some(self.metadata(seed, vec![g.method()], start));
+        Ok(trajectory)
+    }
+    /// Simulates the model, applying the events at their respective
+    /// times, and measures each of its observables at their own sampling
+    /// times, with their noise.  The `output` section is not used.
+    ///
+    /// The simulation and the noise are both determined by the seed, if
+    /// any.
+    ///
+    /// ```
+    /// use rebop::model::Model;
+    /// let model = Model::from_toml_str(r#"
+    ///     version = 1
+    ///     species = [{ name = "mRNA" }, { name = "protein" }]
+    ///     [[reactions]]
+    ///     products = { mRNA = 1 }
+    ///     rate = 10.0
+    ///     [[reactions]]
+    ///     reactants = { mRNA = 1 }
+    ///     products = { mRNA = 1, protein = 1 }
+    ///     rate = 1.0
+    ///     [[observables]]
+    ///     name = "FISH"
+    ///     species = { mRNA = 1 }
+    ///     times = [1.0, 2.0]
+    ///     noise = { type = "binomial", probability = 0.8 }
+    ///     [[observables]]
+    ///     name = "fluorescence"
+    ///     species = { protein = 0.5 }
+    ///     times = [0.5, 1.0, 1.5, 2.0]
+    ///     noise = { type = "gaussian", sd = 2.0 }
+    /// "#).unwrap();
+    /// let measurements = model.observe(Some(0)).unwrap();
+    /// assert_eq!(measurements[0].name, "FISH");
+    /// assert_eq!(measurements[0].values.len(), 2);
+    /// assert_eq!(measurements[1].times, [0.5, 1.0, 1.5, 2.0]);
+    /// ```
+    pub fn observe(&self, seed: Option<u64>) -> Result<Vec<Measurements>, ModelError> {
+        self.validate()?;
+        let species = self.species_indices();
+        let mut params = self.parameter_values();
+        let mut g = self.to_gillespie();
+        let mut rng = match seed {
+            Some(seed) => {
+                g.seed(seed);
+                Philox::new(seed, 1)
+            }
+            None => Philox::new(rand::thread_rng().gen(), 1),
+        };
+        // sampling times of all observables, in chronological order
+        let mut samples: Vec<(f64, usize)> = self
+            .observables
+            .iter()
+            .enumerate()
+            .flat_map(|(i, observable)| observable.times.iter().map(move |&t| (t, i)))
+            .collect();
+        samples.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut measurements: Vec<Measurements> = self
+            .observables
+            .iter()
+            .map(|observable| Measurements {
+                name: observable.name.clone(),
+                times: observable.times.clone(),
+                values: Vec::with_capacity(observable.times.len()),
+            })
+            .collect();
+        let mut events = self.sorted_events(0.);
+        for (t, i) in samples {
+            self.advance_with_events(&mut g, &mut params, &mut events, t);
+            let observable = &self.observables[i];
+            let value = observable
+                .species
+                .iter()
+                .map(|(name, weight)| weight * g.get_species(species[name.as_str()]) as f64)
+                .sum();
+            measurements[i]
+                .values
+                .push(observable.noise.measure(value, &mut rng));
+        }
+        Ok(measurements)
+    }
+    /// Simulates the model through consecutive phases, each with its own
+    /// parameter values and simulation method, and returns the state at
+    /// the start of the first phase and at the time points of each phase.
+    ///
+    /// The simulation starts from the initial amounts of the species at
+    /// the start of the first phase, which does not need to be `0`.  At
+    /// the start of each phase, the parameters are reset to the values
+    /// of the model, replaced by those of the phase.  The events of the
+    /// model are applied at their respective times, except those before
+    /// the start of the first phase.  The `output` section is not used.
+    ///
+    /// ```
+    /// use rebop::model::{Model, Phase};
+    /// let model = Model::from_toml_str(r#"
+    ///     version = 1
+    ///     species = [{ name = "A" }]
+    ///     parameters = [{ name = "k", value = 10.0 }]
+    ///     [[reactions]]
+    ///     products = { A = 1 }
+    ///     rate = "k"
+    /// "#).unwrap();
+    /// let schedule = [
+    ///     Phase::new(0., 10., 10),
+    ///     Phase::new(10., 50., 40).with_parameter("k", 0.),
+    /// ];
+    /// let trajectory = model.run_schedule(&schedule, Some(0)).unwrap();
+    /// assert_eq!(trajectory.times.len(), 51);
+    /// assert!(trajectory.species[0][10] > 0);
+    /// assert_eq!(trajectory.species[0][50], trajectory.species[0][10]);
+    /// ```
+    pub fn run_schedule(
+        &self,
+        phases: &[Phase],
+        seed: Option<u64>,
+    ) -> Result<Trajectory, ModelError> {
+        self.validate()?;
+        self.validate_schedule(phases)?;
+        let start = Instant::now();
+        let mut g = self.to_gillespie();
+        if let Some(seed) = seed {
+            g.seed(seed);
+        }
+        g.set_time(phases[0].start);
+        let mut events = self.sorted_events(phases[0].start);
+        let mut trajectory = Trajectory::new(self.species.len());
+        trajectory.push(&g);
+        for phase in phases {
+            let mut params = self.parameter_values();
+            for (name, &value) in &phase.parameters {
+                params.insert(name.as_str(), value);
+            }
+            for (ireaction, rate) in self.rates(&params).into_iter().enumerate() {
+                g.set_rate(ireaction, rate);
+            }
+            g.set_method(phase.method.unwrap_or_default());
+            for i in 1..=phase.nb_steps {
+                let t = if i == phase.nb_steps {
+                    phase.end
+                } else {
+                    phase.start + (phase.end - phase.start) * i as f64 / phase.nb_steps as f64
+                };
+                self.advance_with_events(&mut g, &mut params, &mut events, t);
+                trajectory.push(&g);
+            }
+        }
+        let methods = phases
+            .iter()
+            .map(|phase| phase.method.unwrap_or_default())
+            .collect();
+        trajectory.metadata = Some(self.metadata(seed, methods, start));
+        Ok(trajectory)
+    }
+    /// Returns a fingerprint of the model, that changes with any of its
+    /// parts: the 64-bit FNV-1a hash of its JSON serialization, in
+    /// hexadecimal.  It is stable across versions of Rust and platforms.
+    pub fn fingerprint(&self) -> String {
+        fnv1a(&serde_json::to_string(self).expect("models can be serialized"))
+    }
+    /// Returns a canonical hash of the reaction network of the model: its
+    /// species names, parameters, and reactions with their rates, in
+    /// hexadecimal.
+    ///
+    /// Unlike [`fingerprint`](Model::fingerprint), it does not depend on
+    /// the order of the species, parameters and reactions, nor on the
+    /// names of the reactions, so that two documents describing the same
+    /// network have the same hash, on all platforms and versions of rebop.
+    /// Initial amounts, annotations, events, observables and output
+    /// settings are not part of the network.
+    ///
+    /// ```
+    /// use rebop::model::Model;
+    /// let a = Model::from_toml_str(r#"
+    ///     version = 1
+    ///     species = [{ name = "A" }, { name = "B" }]
+    ///     [[reactions]]
+    ///     reactants = { A = 1 }
+    ///     products = { B = 1 }
+    ///     rate = 2.0
+    ///     [[reactions]]
+    ///     products = { A = 1 }
+    ///     rate = 1.0
+    /// "#).unwrap();
+    /// let mut b = a.clone();
+    /// b.species.reverse();
+    /// b.reactions.reverse();
+    /// assert_eq!(a.model_hash(), b.model_hash());
+    /// assert_ne!(a.model_hash(), a.fingerprint());
+    /// ```
+    pub fn model_hash(&self) -> String {
+        let reactions = self.reactions.iter().map(|reaction| {
+            let rate = match &reaction.rate {
+                RateSpec::Value(value) => CanonicalRate::Value(*value),
+                RateSpec::Parameter(name) => CanonicalRate::Parameter(name),
+            };
+            (&reaction.reactants, &reaction.products, rate)
+        });
+        network_hash(
+            self.species.iter().map(|species| species.name.as_str()),
+            self.parameters
+                .iter()
+                .map(|param| (param.name.as_str(), param.value)),
+            reactions,
+        )
+    }
+    /// Metadata of a simulation of the model that started at `start`.
+    fn metadata(&self, seed: Option<u64>, methods: Vec<Method>, start: Instant) -> Metadata {
+        Metadata {
+            seed,
+            methods,
+            parameters: self
+                .parameters
+                .iter()
+                .map(|param| (param.name.clone(), param.value))
+                .collect(),
+            model_fingerprint: Some(self.fingerprint()),
+            model_hash: Some(self.model_hash()),
+            wall_time: start.elapsed(),
+            ..Metadata::new()
+        }
+    }
+    /// Checks that the phases are consecutive and only refer to
+    /// parameters of the model.
+    fn validate_schedule(&self, phases: &[Phase]) -> Result<(), ModelError> {
+        let invalid = |msg: String| Err(ModelError::Invalid(msg));
+        let params = self.parameter_values();
+        if phases.is_empty() {
+            return invalid("schedule has no phase".to_string());
+        }
+        for (iphase, phase) in phases.iter().enumerate() {
+            if !(phase.start >= 0. && phase.start <= phase.end && phase.end.is_finite()) {
+                return invalid(format!(
+                    "phase #{iphase} has an invalid time interval [{}, {}]",
+                    phase.start, phase.end
+                ));
+            }
+            if iphase > 0 && phase.start != phases[iphase - 1].end {
+                return invalid(format!(
+                    "phase #{iphase} starts at {} but the previous one ends at {}",
+                    phase.start,
+                    phases[iphase - 1].end
+                ));
+            }
+            if phase.nb_steps == 0 {
+                return invalid(format!("phase #{iphase} needs at least one step"));
+            }
+            for (name, value) in &phase.parameters {
+                if !params.contains_key(name.as_str()) {
+                    return invalid(format!(
+                        "phase #{iphase} refers to unknown parameter `{name}`"
+                    ));
+                }
+                if !(*value >= 0. && value.is_finite()) {
+                    return invalid(format!(
+                        "phase #{iphase} sets `{name}` to an invalid value {value}"
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Returns the events that do not happen before `start`, sorted by
+    /// time.
+    fn sorted_events(&self, start: f64) -> Peekable<std::vec::IntoIter<&Event>> {
+        let mut events: Vec<&Event> = self
+            .events
+            .iter()
+            .filter(|event| event.time >= start)
+            .collect();
+        events.sort_by(|a, b| a.time.total_cmp(&b.time));
+        events.into_iter().peekable()
+    }
+    /// Simulates `g` until `t`, applying the events that happen until
+    /// then and updating the parameters `params` accordingly.
+    fn advance_with_events<'a>(
+        &'a self,
+        g: &mut Gillespie,
+        params: &mut HashMap<&'a str, f64>,
+        events: &mut Peekable<std::vec::IntoIter<&'a Event>>,
+        t: f64,
+    ) {
+        let species = self.species_indices();
+        while let Some(event) = events.next_if(|event| event.time <= t) {
+            g.advance_until(event.time);
+            let mut x = g.species().to_vec();
+            for (name, &amount) in &event.species {
+                x[species[name.as_str()]] = amount;
+            }
+            g.set_species(x);
+            if !event.parameters.is_empty() {
+                for (name, &value) in &event.parameters {
+                    params.insert(name.as_str(), value);
+                }
+                for (ireaction, rate) in self.rates(params).into_iter().enumerate() {
+                    g.set_rate(ireaction, rate);
+                }
+            }
+            for (name, &enabled) in &event.reactions {
+                g.set_reaction_enabled(self.reaction_index(name).unwrap(), enabled);
+            }
+        }
+        g.advance_until(t);
+    }
+}
+
+/// 64-bit FNV-1a hash of `text`, in hexadecimal.
+/// Name of the reaction of index `ireaction` in the messages and the
+/// exported graphs.
+fn reaction_label(reaction: &Reaction, ireaction: usize) -> String {
+    reaction.name.clone().unwrap_or(format!("#{ireaction}"))
+}
+
+/// Escapes the quotes and backslashes of `text` in a DOT string.
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Escapes the special characters of XML in `text`.
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn fnv1a(text: &str) -> String {
+    let hash = text.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{hash:016x}")
+}
+
+/// Rate of a reaction in [`network_hash`].
+pub(crate) enum CanonicalRate<'a> {
+    Value(f64),
+    Parameter(&'a str),
+}
+
+/// Order-insensitive hash of a reaction network, see
+/// [`Model::model_hash`].
+///
+/// The reactions are given by their reactants, products and rate.  The
+/// network is encoded in JSON with sorted species, parameters and
+/// reactions, and with the exact bits of the numbers, which does not
+/// depend on the platform, before being hashed.
+pub(crate) fn network_hash<'a, S, P, R>(species: S, parameters: P, reactions: R) -> String
+where
+    S: IntoIterator<Item = &'a str>,
+    P: IntoIterator<Item = (&'a str, f64)>,
+    R: IntoIterator<
+        Item = (
+            &'a BTreeMap<String, u32>,
+            &'a BTreeMap<String, u32>,
+            CanonicalRate<'a>,
+        ),
+    >,
+{
+    let species: BTreeSet<&str> = species.into_iter().collect();
+    let parameters: BTreeMap<&str, String> = parameters
+        .into_iter()
+        .map(|(name, value)| (name, format!("{:016x}", value.to_bits())))
+        .collect();
+    let mut reactions: Vec<String> = reactions
+        .into_iter()
+        .map(|(reactants, products, rate)| {
+            let rate = match rate {
+                CanonicalRate::Value(value) => format!("value:{:016x}", value.to_bits()),
+                CanonicalRate::Parameter(name) => format!("parameter:{name}"),
+            };
+            // species with a stoichiometry of zero do not take part
+            let nonzero = |map: &'a BTreeMap<String, u32>| -> BTreeMap<&'a str, u32> {
+                map.iter()
+                    .filter(|(_, &n)| n > 0)
+                    .map(|(name, &n)| (name.as_str(), n))
+                    .collect()
+            };
+            serde_json::to_string(&(nonzero(reactants), nonzero(products), rate))
+                .expect("reactions can be serialized")
+        })
+        .collect();
+    reactions.sort_unstable();
+    let canonical = serde_json::to_string(&(species, parameters, reactions))
+        .expect("networks can be serialized");
+    fnv1a(&canonical)
+}
+
+/// Variant of a model, with some parameter values replaced and some
+/// reactions disabled, created by [`Model::variant`].
+///
+/// It only stores its differences with the base model, which is
+/// borrowed, so that many variants of a large network are cheap.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Variant<'a> {
+    base: &'a Model,
+    parameters: BTreeMap<String, f64>,
+    disabled: BTreeSet<String>,
+}
+
+impl Model {
+    /// Creates a variant of the model, identical to it until it is
+    /// modified.
+    ///
+    /// ```
+    /// use rebop::model::Model;
+    /// let model = Model::from_toml_str(r#"
+    ///     version = 1
+    ///     species = [{ name = "A" }]
+    ///     parameters = [{ name = "k", value = 10.0 }]
+    ///     [[reactions]]
+    ///     name = "birth"
+    ///     products = { A = 1 }
+    ///     rate = "k"
+    ///     [[reactions]]
+    ///     name = "decay"
+    ///     reactants = { A = 1 }
+    ///     rate = 0.1
+    ///     [output]
+    ///     tmax = 10.0
+    ///     nb_steps = 10
+    /// "#).unwrap();
+    /// let variant = model.variant().set_param("k", 2.).disable_reaction("decay");
+    /// let trajectory = variant.run(Some(0)).unwrap();
+    /// assert!(trajectory.species[0].windows(2).all(|w| w[0] <= w[1]));
+    /// ```
+    pub fn variant(&self) -> Variant<'_> {
+        Variant {
+            base: self,
+            parameters: BTreeMap::new(),
+            disabled: BTreeSet::new(),
+        }
+    }
+    /// Returns the model rescaled by the system size `omega`, keeping
+    /// the same concentrations and deterministic limit.
+    ///
+    /// The initial amounts of species and the amounts set by the events
+    /// are multiplied by `omega` and rounded, and the rate constant of
+    /// each reaction of order `n` (the total stoichiometry of its
+    /// reactants) is multiplied by `omega^(1 - n)`: zeroth-order rates
+    /// grow with the system, first-order ones are unchanged, and
+    /// higher-order ones shrink.  A parameter used as a rate is rescaled
+    /// once, together with the values that the events give it, so it
+    /// must only be the rate of reactions of the same order.
+    ///
+    /// As `omega` grows, the concentrations, amounts divided by `omega`,
+    /// converge to the solution of the deterministic rate equations:
+    ///
+    /// ```
+    /// use rebop::model::Model;
+    /// let model = Model::from_toml_str(r#"
+    ///     version = 1
+    ///     species = [{ name = "A", initial = 1 }]
+    ///     [[reactions]]
+    ///     products = { A = 1 }
+    ///     rate = 1.0
+    ///     [[reactions]]
+    ///     reactants = { A = 2 }
+    ///     rate = 0.5
+    ///     [output]
+    ///     tmax = 10.0
+    ///     nb_steps = 1
+    /// "#).unwrap();
+    /// // births at rate 1 balance the loss of two A at rate 0.5 A², so A → 1
+    /// for omega in [100., 1000., 10000.] {
+    ///     let large = model.rescale(omega).unwrap();
+    ///     let trajectory = large.run(Some(0)).unwrap();
+    ///     let concentration = trajectory.species[0][1] as f64 / omega;
+    ///     assert!((concentration - 1.).abs() < 10. / omega.sqrt());
+    /// }
+    /// ```
+    pub fn rescale(&self, omega: f64) -> Result<Model, ModelError> {
+        self.validate()?;
+        if !(omega.is_finite() && omega > 0.) {
+            return Err(ModelError::Invalid(format!(
+                "system size {omega} is not positive and finite"
+            )));
+        }
+        let factor = |reaction: &Reaction| {
+            let order: u32 = reaction.reactants.values().sum();
+            omega.powi(1 - order as i32)
+        };
+        let amount = |amount: isize| (amount as f64 * omega).round() as isize;
+        // factor of each parameter used as a rate
+        let mut factors: HashMap<&str, f64> = HashMap::new();
+        for reaction in &self.reactions {
+            if let RateSpec::Parameter(name) = &reaction.rate {
+                let f = factor(reaction);
+                if *factors.entry(name).or_insert(f) != f {
+                    return Err(ModelError::Invalid(format!(
+                        "parameter `{name}` is the rate of reactions of different orders \
+                         and cannot be rescaled"
+                    )));
+                }
+            }
+        }
+        let mut model = self.clone();
+        for species in &mut model.species {
+            species.initial = amount(species.initial);
+        }
+        for param in &mut model.parameters {
+            param.value *= factors.get(param.name.as_str()).unwrap_or(&1.);
+        }
+        for (reaction, original) in model.reactions.iter_mut().zip(&self.reactions) {
+            if let RateSpec::Value(value) = &mut reaction.rate {
+                *value *= factor(original);
+            }
+        }
+        for event in &mut model.events {
+            for value in event.species.values_mut() {
+                *value = amount(*value);
+            }
+            for (name, value) in event.parameters.iter_mut() {
+                *value *= factors.get(name.as_str()).unwrap_or(&1.);
+            }
+        }
+        Ok(model)
+    }
+}
+
+impl Variant<'_> {
+    /// Sets the value of the parameter `name`.
+    pub fn set_param(mut self, name: &str, value: f64) -> Self {
+        self.parameters.insert(name.to_string(), value);
+        self
+    }
+    /// Disables the reaction named `name`, whose rate becomes `0`.
+    pub fn disable_reaction(mut self, name: &str) -> Self {
+        self.disabled.insert(name.to_string());
+        self
+    }
+    /// Returns the base model.
+    pub fn base(&self) -> &Model {
+        self.base
+    }
+    /// Builds the complete model of the variant.  The disabled reactions
+    /// are kept, with a rate of `0`, so that the reactions of all the
+    /// variants of a model correspond.
+    pub fn to_model(&self) -> Result<Model, ModelError> {
+        let mut model = self.base.clone();
+        for (name, &value) in &self.parameters {
+            match model
+                .parameters
+                .iter_mut()
+                .find(|param| &param.name == name)
+            {
+                Some(param) => param.value = value,
+                None => {
+                    return Err(ModelError::Invalid(format!(
+                        "variant refers to unknown parameter `{name}`"
+                    )))
+                }
+            }
+        }
+        for name in &self.disabled {
+            match model
+                .reactions
+                .iter_mut()
+                .find(|reaction| reaction.name.as_ref() == Some(name))
+            {
+                Some(reaction) => reaction.rate = RateSpec::Value(0.),
+                None => {
+                    return Err(ModelError::Invalid(format!(
+                        "variant refers to unknown reaction `{name}`"
+                    )))
+                }
+            }
+        }
+        model.validate()?;
+        Ok(model)
+    }
+    /// Simulates the variant as described by the `output` section of
+    /// the model, see [`Model::run`].
+    pub fn run(&self, seed: Option<u64>) -> Result<Trajectory, ModelError> {
+        self.to_model()?.run(seed)
+    }
+}
+
+/// Differences between the simulations of variants of a model, as
+/// returned by [`compare_variants`].
+///
+/// All the values are indexed by variant, then species, then time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VariantComparison {
+    /// Time points.
+    pub times: Vec<f64>,
+    /// Names of the species.
+    pub species: Vec<String>,
+    /// Mean amounts of the species over the runs.
+    pub means: Vec<Vec<Vec<f64>>>,
+    /// Mean differences of the amounts of the species with the first
+    /// variant, between runs with the same seed.
+    pub differences: Vec<Vec<Vec<f64>>>,
+    /// Standard errors of the mean differences.
+    pub standard_errors: Vec<Vec<Vec<f64>>>,
+}
+
+/// Simulates `nb_runs` times each of the `variants`, as described by
+/// the `output` section of their model, and compares them with the
+/// first one.
+///
+/// The run `i` of all the variants uses the same seed, derived from
+/// `seed`: these common random numbers correlate the runs of the
+/// different variants, which makes their differences more precise than
+/// with independent runs.
+///
+/// ```
+/// use rebop::model::{compare_variants, Model};
+/// let model = Model::from_toml_str(r#"
+///     version = 1
+///     species = [{ name = "A" }]
+///     parameters = [{ name = "k", value = 10.0 }]
+///     [[reactions]]
+///     products = { A = 1 }
+///     rate = "k"
+///     [output]
+///     tmax = 10.0
+///     nb_steps = 10
+/// "#).unwrap();
+/// let variants = [model.variant(), model.variant().set_param("k", 20.)];
+/// let comparison = compare_variants(&variants, 50, 0).unwrap();
+/// // twice as many births
+/// let difference = comparison.differences[1][0][10];
+/// assert!((difference - 100.).abs() < 5. * comparison.standard_errors[1][0][10]);
+/// ```
+///
+/// # Errors
+///
+/// Returns an error if a variant is invalid, or if the variants do not
+/// have the same species and `output` section.
+pub fn compare_variants(
+    variants: &[Variant],
+    nb_runs: usize,
+    seed: u64,
+) -> Result<VariantComparison, ModelError> {
+    let models = variants
+        .iter()
+        .map(Variant::to_model)
+        .collect::<Result<Vec<_>, _>>()?;
+    let Some(first) = models.first() else {
+        return Err(ModelError::Invalid("no variant to compare".to_string()));
+    };
+    if models
+        .iter()
+        .any(|model| model.species != first.species || model.output != first.output)
+    {
+        return Err(ModelError::Invalid(
+            "variants have different species or outputs".to_string(),
+        ));
+    }
+    let output = first.output.as_ref().ok_or(ModelError::MissingOutput)?;
+    let (nb_species, nb_times) = (first.species.len(), output.nb_steps + 1);
+    let zeros = vec![vec![vec![0.; nb_times]; nb_species]; models.len()];
+    let (mut means, mut differences, mut squares) = (zeros.clone(), zeros.clone(), zeros);
+    let mut times = Vec::new();
+    for run in 0..nb_runs {
+        let seed = Philox::new(seed, run as u64).next_u64();
+        let trajectories = models
+            .iter()
+            .map(|model| model.run(Some(seed)))
+            .collect::<Result<Vec<_>, _>>()?;
+        for (ivariant, trajectory) in trajectories.iter().enumerate() {
+            for (is, values) in trajectory.species.iter().enumerate() {
+                for (it, &value) in values.iter().enumerate() {
+                    let difference = (value - trajectories[0].species[is][it]) as f64;
+                    means[ivariant][is][it] += value as f64;
+                    differences[ivariant][is][it] += difference;
+                    squares[ivariant][is][it] += difference * difference;
+                }
+            }
+        }
+        times.clone_from(&trajectories[0].times);
+    }
+    let n = nb_runs as f64;
+    let standard_errors = differences
+        .iter()
+        .zip(&squares)
+        .map(|(differences, squares)| {
+            differences
+                .iter()
+                .zip(squares)
+                .map(|(differences, squares)| {
+                    differences
+                        .iter()
+                        .zip(squares)
+                        .map(|(sum, squares)| {
+                            let variance = (squares - sum * sum / n) / (n - 1.);
+                            (variance.max(0.) / n).sqrt()
+                        })
+                        .collect()
+                })
+                .collect()
+        })
+        .collect();
+    for value in means.iter_mut().chain(&mut differences).flatten().flatten() {
+        *value /= n;
+    }
+    Ok(VariantComparison {
+        times,
+        species: first.species.iter().map(|s| s.name.clone()).collect(),
+        means,
+        differences,
+        standard_errors,
+    })
+}
+
+/// Perturbations of a model screened by [`perturbation_screen`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Screen {
+    /// Knockout of each named reaction.
+    Knockouts,
+    /// Multiplication of each parameter by a factor.
+    ParameterScaling(f64),
+}
+
+/// Effect of a perturbation on the observables at the end of the
+/// simulations, as returned by [`perturbation_screen`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct PerturbationEffect {
+    /// Name of the reaction knocked out or of the parameter scaled.
+    pub target: String,
+    /// Mean amount of each observable.
+    pub means: Vec<f64>,
+    /// Mean difference of each observable with the unperturbed model.
+    pub differences: Vec<f64>,
+    /// Standard error of the mean differences.
+    pub standard_errors: Vec<f64>,
+}
+
+/// Applies the perturbations of `screen` one at a time, simulates
+/// `nb_runs` times each perturbed model as well as `model`, and returns
+/// the effect of each perturbation on the amounts of the species named
+/// `observables` at the end of the `output` section.
+///
+/// The simulations use common random numbers, see [`compare_variants`].
+/// Unnamed reactions are not knocked out.
+///
+/// ```
+/// use rebop::model::{perturbation_screen, Model, Screen};
+/// let model = Model::from_toml_str(r#"
+///     version = 1
+///     species = [{ name = "A" }, { name = "B" }]
+///     [[reactions]]
+///     name = "production"
+///     products = { A = 1 }
+///     rate = 10.0
+///     [[reactions]]
+///     name = "conversion"
+///     reactants = { A = 1 }
+///     products = { B = 1 }
+///     rate = 1.0
+///     [output]
+///     tmax = 10.0
+///     nb_steps = 1
+/// "#).unwrap();
+/// let effects = perturbation_screen(&model, Screen::Knockouts, &["B"], 20, 0).unwrap();
+/// assert_eq!(effects[0].target, "production");
+/// assert_eq!(effects[0].means, [0.]);
+/// assert!(effects[1].differences[0] < 0.);
+/// ```
+pub fn perturbation_screen(
+    model: &Model,
+    screen: Screen,
+    observables: &[&str],
+    nb_runs: usize,
+    seed: u64,
+) -> Result<Vec<PerturbationEffect>, ModelError> {
+    let species = model.species_indices();
+    let observed = observables
+        .iter()
+        .map(|name| {
+            species
+                .get(name)
+                .copied()
+                .ok_or_else(|| ModelError::Invalid(format!("unknown observable species `{name}`")))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let (targets, perturbed): (Vec<String>, Vec<Variant>) = match screen {
+        Screen::Knockouts => model
+            .reactions
+            .iter()
+            .filter_map(|reaction| reaction.name.as_ref())
+            .map(|name| (name.clone(), model.variant().disable_reaction(name)))
+            .unzip(),
+        Screen::ParameterScaling(factor) => model
+            .parameters
+            .iter()
+            .map(|param| {
+                let variant = model.variant().set_param(&param.name, factor * param.value);
+                (param.name.clone(), variant)
+            })
+            .unzip(),
+    };
+    let variants: Vec<Variant> = std::iter::once(model.variant()).chain(perturbed).collect();
+    let comparison = compare_variants(&variants, nb_runs, seed)?;
+    let last = comparison.times.len() - 1;
+    let at_end = |values: &[Vec<f64>]| observed.iter().map(|&s| values[s][last]).collect();
+    Ok(targets
+        .into_iter()
+        .enumerate()
+        .map(|(i, target)| PerturbationEffect {
+            target,
+            means: at_end(&comparison.means[i + 1]),
+            differences: at_end(&comparison.differences[i + 1]),
+            standard_errors: at_end(&comparison.standard_errors[i + 1]),
+        })
+        .collect())
+}
+
+/// Conflict found while composing models with a [`Composition`].  In
+/// every case, the first definition is kept.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Conflict {
+    /// The name `name` was already an alias of `kept`, and cannot also
+    /// be an alias of `ignored`.
+    Alias {
+        name: String,
+        kept: String,
+        ignored: String,
+    },
+    /// Aliasing `name` to `canonical` would make a cycle of aliases.
+    AliasCycle { name: String, canonical: String },
+    /// The species `species` has different initial amounts in two
+    /// models.
+    Initial {
+        species: String,
+        kept: isize,
+        ignored: isize,
+    },
+    /// The parameter `parameter` has different values in two models.
+    Value {
+        parameter: String,
+        kept: f64,
+        ignored: f64,
+    },
+    /// The name `name` is a species in a model and a parameter in
+    /// another one.
+    Kind { name: String },
+    /// Two different reactions have the name `name`.
+    Reaction { name: String },
+    /// Two different observables have the name `name`.
+    Observable { name: String },
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Conflict::Alias {
+                name,
+                kept,
+                ignored,
+            } => write!(
+                f,
+                "`{name}` is already an alias of `{kept}`, not of `{ignored}`"
+            ),
+            Conflict::AliasCycle { name, canonical } => write!(
+                f,
+                "aliasing `{name}` to `{canonical}` makes a cycle of aliases"
+            ),
+            Conflict::Initial {
+                species,
+                kept,
+                ignored,
+            } => write!(
+                f,
+                "species `{species}` starts at {kept} and at {ignored}, {kept} is kept"
+            ),
+            Conflict::Value {
+                parameter,
+                kept,
+                ignored,
+            } => write!(
+                f,
+                "parameter `{parameter}` is {kept} and {ignored}, {kept} is kept"
+            ),
+            Conflict::Kind { name } => {
+                write!(f, "`{name}` is both a species and a parameter")
+            }
+            Conflict::Reaction { name } => {
+                write!(f, "reaction `{name}` is defined differently twice")
+            }
+            Conflict::Observable { name } => {
+                write!(f, "observable `{name}` is defined differently twice")
+            }
+        }
+    }
+}
+
+/// Model composed of several models, whose species and parameters are
+/// identified by name, after the renaming of aliases.
+///
+/// Sub-models written separately, or converted from other formats,
+/// rarely follow the same naming conventions.  Aliases map their names
+/// to a canonical one without editing them, and the inconsistencies
+/// between the models are reported as [`Conflict`]s rather than errors,
+/// so that they can all be reviewed at once.
+///
+/// ```
+/// use rebop::model::{Composition, Model};
+/// let transport = Model::from_toml_str(r#"
+///     version = 1
+///     species = [{ name = "Glc_ext", initial = 1000 }, { name = "Glc_int" }]
+///     [[reactions]]
+///     name = "uptake"
+///     reactants = { Glc_ext = 1 }
+///     products = { Glc_int = 1 }
+///     rate = 0.1
+/// "#).unwrap();
+/// let metabolism = Model::from_toml_str(r#"
+///     version = 1
+///     species = [{ name = "Glucose" }, { name = "ATP" }]
+///     [[reactions]]
+///     name = "glycolysis"
+///     reactants = { Glucose = 1 }
+///     products = { ATP = 2 }
+///     rate = 1.0
+/// "#).unwrap();
+/// let composition = Composition::new()
+///     .alias("Glc_int", "Glucose")
+///     .import(&transport)
+///     .import(&metabolism);
+/// assert!(composition.conflicts().is_empty());
+/// let model = composition.to_model().unwrap();
+/// let names: Vec<_> = model.species.iter().map(|s| s.name.as_str()).collect();
+/// assert_eq!(names, ["Glc_ext", "Glucose", "ATP"]);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Composition {
+    aliases: BTreeMap<String, String>,
+    model: Model,
+    conflicts: Vec<Conflict>,
+}
+
+impl Composition {
+    /// Creates an empty composition.
+    pub fn new() -> Self {
+        Composition {
+            aliases: BTreeMap::new(),
+            model: Model::new(),
+            conflicts: Vec::new(),
+        }
+    }
+    /// Renames the species or parameter `name` to `canonical` in the
+    /// models imported afterwards.  Aliases can be chained.
+    pub fn alias(mut self, name: &str, canonical: &str) -> Self {
+        if let Some(kept) = self.aliases.get(name) {
+            if kept != canonical {
+                self.conflicts.push(Conflict::Alias {
+                    name: name.to_string(),
+                    kept: kept.clone(),
+                    ignored: canonical.to_string(),
+                });
+            }
+        } else if self.resolve(canonical) == name {
+            if name != canonical {
+                self.conflicts.push(Conflict::AliasCycle {
+                    name: name.to_string(),
+                    canonical: canonical.to_string(),
+                });
+            }
+        } else {
+            self.aliases.insert(name.to_string(), canonical.to_string());
+        }
+        self
+    }
+    /// Returns the canonical name of `name`.
+    pub fn resolve<'a>(&'a self, mut name: &'a str) -> &'a str {
+        // there is no cycle
+        while let Some(canonical) = self.aliases.get(name) {
+            name = canonical;
+        }
+        name
+    }
+    /// Adds the species, parameters, reactions, events and observables
+    /// of `model`, renamed by the aliases, to the composition.
+    ///
+    /// Species and parameters of the same name are merged, and so are
+    /// identical events, and identical reactions or observables of the
+    /// same name, which usually come from a shared part of the models.
+    /// The annotations of merged species and reactions are gathered, the
+    /// first value of each key being kept.  The `output`
+    /// section is the first one found.
+    pub fn import(mut self, model: &Model) -> Self {
+        let renamed_reactions: Vec<Reaction> = model
+            .reactions
+            .iter()
+            .map(|reaction| Reaction {
+                name: reaction.name.clone(),
+                reactants: self.rename(&reaction.reactants),
+                products: self.rename(&reaction.products),
+                annotations: reaction.annotations.clone(),
+                rate: match &reaction.rate {
+                    RateSpec::Parameter(name) => {
+                        RateSpec::Parameter(self.resolve(name).to_string())
+                    }
+                    RateSpec::Value(value) => RateSpec::Value(*value),
+                },
+            })
+            .collect();
+        let renamed_events: Vec<Event> = model
+            .events
+            .iter()
+            .map(|event| Event {
+                time: event.time,
+                species: event
+                    .species
+                    .iter()
+                    .map(|(name, &amount)| (self.resolve(name).to_string(), amount))
+                    .collect(),
+                parameters: event
+                    .parameters
+                    .iter()
+                    .map(|(name, &value)| (self.resolve(name).to_string(), value))
+                    .collect(),
+                reactions: event.reactions.clone(),
+            })
+            .collect();
+        let renamed_observables: Vec<Observable> = model
+            .observables
+            .iter()
+            .map(|observable| Observable {
+                species: self.rename(&observable.species),
+                ..observable.clone()
+            })
+            .collect();
+        let renamed_species: Vec<Species> = model
+            .species
+            .iter()
+            .map(|species| Species {
+                name: self.resolve(&species.name).to_string(),
+                ..species.clone()
+            })
+            .collect();
+        let renamed_parameters: Vec<Parameter> = model
+            .parameters
+            .iter()
+            .map(|param| Parameter {
+                name: self.resolve(&param.name).to_string(),
+                value: param.value,
+            })
+            .collect();
+        for species in renamed_species {
+            let name = species.name.clone();
+            if let Some(kept) = self.model.species.iter_mut().find(|s| s.name == name) {
+                merge_annotations(&mut kept.annotations, species.annotations);
+                if kept.initial != species.initial {
+                    self.conflicts.push(Conflict::Initial {
+                        kept: kept.initial,
+                        ignored: species.initial,
+                        species: name,
+                    });
+                }
+            } else if self.model.parameters.iter().any(|p| p.name == name) {
+                self.conflicts.push(Conflict::Kind { name });
+            } else {
+                self.model.species.push(species);
+            }
+        }
+        for param in renamed_parameters {
+            let name = param.name.clone();
+            if let Some(kept) = self.model.parameters.iter().find(|p| p.name == name) {
+                if kept.value != param.value {
+                    self.conflicts.push(Conflict::Value {
+                        kept: kept.value,
+                        ignored: param.value,
+                        parameter: name,
+                    });
+                }
+            } else if self.model.species.iter().any(|s| s.name == name) {
+                self.conflicts.push(Conflict::Kind { name });
+            } else {
+                self.model.parameters.push(param);
+            }
+        }
+        for reaction in renamed_reactions {
+            let existing = reaction
+                .name
+                .as_deref()
+                .and_then(|name| self.model.reaction_index(name));
+            match existing {
+                None => self.model.reactions.push(reaction),
+                Some(i) if same_reaction(&self.model.reactions[i], &reaction) => {
+                    let kept = &mut self.model.reactions[i].annotations;
+                    merge_annotations(kept, reaction.annotations);
+                }
+                Some(_) => self.conflicts.push(Conflict::Reaction {
+                    name: reaction.name.unwrap_or_default(),
+                }),
+            }
+        }
+        for event in renamed_events {
+            if !self.model.events.contains(&event) {
+                self.model.events.push(event);
+            }
+        }
+        if self.model.output.is_none() {
+            self.model.output.clone_from(&model.output);
+        }
+        for observable in renamed_observables {
+            let existing = self
+                .model
+                .observables
+                .iter()
+                .find(|o| o.name == observable.name);
+            match existing {
+                None => self.model.observables.push(observable),
+                Some(kept) if *kept == observable => {}
+                Some(_) => self.conflicts.push(Conflict::Observable {
+                    name: observable.name,
+                }),
+            }
+        }
+        self
+    }
+    /// Renames the keys of `names`, adding the values of the names with
+    /// the same canonical name.
+    fn rename<T: Copy + Default + AddAssign>(
+        &self,
+        names: &BTreeMap<String, T>,
+    ) -> BTreeMap<String, T> {
+        let mut renamed = BTreeMap::new();
+        for (name, &value) in names {
+            *renamed
+                .entry(self.resolve(name).to_string())
+                .or_insert_with(T::default) += value;
+        }
+        renamed
+    }
+    /// Returns the conflicts found so far, in the order in which they
+    /// were found.
+    pub fn conflicts(&self) -> &[Conflict] {
+        &self.conflicts
+    }
+    /// Returns the composed model, after checking that it is valid.
+    pub fn to_model(&self) -> Result<Model, ModelError> {
+        self.model.validate()?;
+        Ok(self.model.clone())
+    }
+}
+
+/// Returns `map` with `prefix` before its keys.
+fn prefix_keys<T: Clone>(prefix: &str, map: &BTreeMap<String, T>) -> BTreeMap<String, T> {
+    map.iter()
+        .map(|(name, value)| (format!("{prefix}{name}"), value.clone()))
+        .collect()
+}
+
+/// Adds to `kept` the annotations of `other` whose keys it does not have.
+fn merge_annotations(kept: &mut Annotations, other: Annotations) {
+    for (key, value) in other {
+        kept.entry(key).or_insert(value);
+    }
+}
+
+/// Whether two reactions are the same, whatever their annotations.
+fn same_reaction(a: &Reaction, b: &Reaction) -> bool {
+    (&a.name, &a.reactants, &a.products, &a.rate) == (&b.name, &b.reactants, &b.products, &b.rate)
+}
+
+impl Default for Composition {
+    fn default() -> Self {
+        Composition::new()
+    }
+}
+
+impl Default for Model {
+    fn default() -> Self {
+        Model::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::gillespie::Method;
+    use crate::model::{
+        compare_variants, perturbation_screen, Composition, Conflict, Event, Model, ModelError,
+        Noise, Observable, Phase, RateSpec, Reaction, Screen, SCHEMA_VERSION,
+    };
+
+    const SIR: &str = r#"
+        version = 1
+        species = [
+            { name = "S", initial = 999 },
+            { name = "I", initial = 1 },
+            { name = "R" },
+        ]
+        parameters = [
+            { name = "r_inf", value = 1e-4 },
+            { name = "r_heal", value = 0.01 },
+        ]
+        [[reactions]]
+        name = "infection"
+        reactants = { S = 1, I = 1 }
+        products = { I = 2 }
+        rate = "r_inf"
+        [[reactions]]
+        name = "healing"
+        reactants = { I = 1 }
+        products = { R = 1 }
+        rate = "r_heal"
+        [[events]]
+        time = 30.0
+        parameters = { r_inf = 0.0 }
+        [output]
+        tmax = 250.0
+        nb_steps = 250
+    "#;
+
+    #[test]
+    fn sir_toml_json_roundtrip() {
+        let model = Model::from_toml_str(SIR).unwrap();
+        assert_eq!(model.species.len(), 3);
+        assert_eq!(model.reactions[0].rate, RateSpec::Parameter("r_inf".into()));
+        let json = model.to_json_string().unwrap();
+        assert_eq!(Model::from_json_str(&json).unwrap(), model);
+        let toml = model.to_toml_string().unwrap();
+        assert_eq!(Model::from_toml_str(&toml).unwrap(), model);
+    }
+    #[test]
+    fn sir_run_with_event() {
+        let model = Model::from_toml_str(SIR).unwrap();
+        let trajectory = model.run(Some(0)).unwrap();
+        assert_eq!(trajectory.times.len(), 251);
+        for i in 0..251 {
+            let total: isize = trajectory.species.iter().map(|s| s[i]).sum();
+            assert_eq!(total, 1000);
+        }
+        // no infection after the lockdown
+        assert!(trajectory.species[0][31..]
+            .iter()
+            .all(|&s| s == trajectory.species[0][30]));
+        assert_eq!(model.run(Some(0)).unwrap(), trajectory);
+    }
+    #[test]
+    fn schedule() {
+        let model = Model::from_toml_str(SIR).unwrap();
+        // the model event stops infections at t = 30, the schedule
+        // restarts them at t = 50, with another method
+        let schedule = [
+            Phase::new(20., 50., 30),
+            Phase::new(50., 100., 10)
+                .with_parameter("r_heal", 0.02)
+                .with_method(Method::FirstReaction),
+        ];
+        let trajectory = model.run_schedule(&schedule, Some(0)).unwrap();
+        assert_eq!(trajectory.times.len(), 41);
+        assert_eq!(trajectory.times[0], 20.);
+        assert_eq!(trajectory.times[30], 50.);
+        assert_eq!(trajectory.times[40], 100.);
+        let susceptible = &trajectory.species[0];
+        assert!(susceptible[10..=30].iter().all(|&s| s == susceptible[10]));
+        for i in 0..41 {
+            let total: isize = trajectory.species.iter().map(|s| s[i]).sum();
+            assert_eq!(total, 1000);
+        }
+        assert_eq!(model.run_schedule(&schedule, Some(0)).unwrap(), trajectory);
+        let err = model
+            .run_schedule(&[Phase::new(0., 1., 1), Phase::new(2., 3., 1)], None)
+            .unwrap_err();
+        assert!(err.to_string().contains("previous one ends at 1"));
+        let err = model
+            .run_schedule(&[Phase::new(0., 1., 1).with_parameter("k", 1.)], None)
+            .unwrap_err();
+        assert!(err.to_string().contains("unknown parameter `k`"));
+        assert!(model.run_schedule(&[], None).is_err());
+    }
+    #[test]
+    fn variants() {
+        let model = Model::from_toml_str(SIR).unwrap();
+        let no_healing = model.variant().disable_reaction("healing");
+        assert_eq!(no_healing.base(), &model);
+        let trajectory = no_healing.run(Some(0)).unwrap();
+        assert!(trajectory.species[2].iter().all(|&r| r == 0));
+        let err = model.variant().set_param("r", 1.).to_model().unwrap_err();
+        assert!(err.to_string().contains("unknown parameter `r`"));
+        let err = model.variant().disable_reaction("death").run(None);
+        assert!(err.is_err());
+        let err = model
+            .variant()
+            .set_param("r_inf", -1.)
+            .run(None)
+            .unwrap_err();
+        assert!(matches!(err, ModelError::Invalid(_)));
+
+        let variants = [
+            model.variant(),
+            model.variant().set_param("r_heal", 0.02),
+            no_healing,
+        ];
+        let comparison = compare_variants(&variants, 20, 0).unwrap();
+        assert_eq!(comparison.times.len(), 251);
+        assert_eq!(comparison.species, ["S", "I", "R"]);
+        // the first variant is the reference
+        assert!(comparison.differences[0].iter().flatten().all(|&d| d == 0.));
+        assert!(comparison.standard_errors[0]
+            .iter()
+            .flatten()
+            .all(|&e| e == 0.));
+        // nobody recovers without healing
+        assert_eq!(comparison.means[2][2][250], 0.);
+        assert!(comparison.differences[2][2][250] < 0.);
+        assert!(comparison.standard_errors[2][2][250] > 0.);
+        let means = &comparison.means;
+        for (variant, differences) in comparison.differences.iter().enumerate() {
+            let difference = differences[1][100];
+            assert!((difference - (means[variant][1][100] - means[0][1][100])).abs() < 1e-9);
+        }
+        assert_eq!(compare_variants(&variants, 20, 0).unwrap(), comparison);
+        assert!(compare_variants(&[], 20, 0).is_err());
+    }
+    #[test]
+    fn knockout_event() {
+        let mut model = Model::from_toml_str(SIR).unwrap();
+        model.species[1].initial = 100;
+        model.events[0].parameters.clear();
+        model.events[0]
+            .reactions
+            .insert("healing".to_string(), false);
+        model.events.push(Event {
+            time: 100.,
+            species: BTreeMap::new(),
+            parameters: BTreeMap::new(),
+            reactions: BTreeMap::from([("healing".to_string(), true)]),
+        });
+        let toml = model.to_toml_string().unwrap();
+        assert_eq!(Model::from_toml_str(&toml).unwrap(), model);
+        let trajectory = model.run(Some(0)).unwrap();
+        let recovered = &trajectory.species[2];
+        assert!(recovered[31..=100].iter().all(|&r| r == recovered[30]));
+        assert!(recovered[250] > recovered[100]);
+        model.events[1].reactions.insert("death".to_string(), false);
+        let err = model.validate().unwrap_err();
+        assert!(err.to_string().contains("unknown reaction `death`"));
+    }
+    #[test]
+    fn observables() {
+        let mut model = Model::from_toml_str(SIR).unwrap();
+        let times: Vec<f64> = (0..=250).map(f64::from).collect();
+        model.observables = vec![
+            Observable {
+                name: "infected".to_string(),
+                species: BTreeMap::from([("I".to_string(), 1.)]),
+                times: times.clone(),
+                noise: Noise::None,
+            },
+            Observable {
+                name: "recovered".to_string(),
+                species: BTreeMap::from([("R".to_string(), 1.)]),
+                times: vec![100., 200.],
+                noise: Noise::Binomial { probability: 0. },
+            },
+            Observable {
+                name: "total".to_string(),
+                species: BTreeMap::from([("S".to_string(), 2.), ("I".to_string(), 2.)]),
+                times: vec![0., 250.],
+                noise: Noise::LogNormal { sigma: 0. },
+            },
+        ];
+        let toml = model.to_toml_string().unwrap();
+        assert!(toml.contains("type = \"binomial\""));
+        assert_eq!(Model::from_toml_str(&toml).unwrap(), model);
+        let json = model.to_json_string().unwrap();
+        assert_eq!(Model::from_json_str(&json).unwrap(), model);
+        // exact measurements at the output times follow the trajectory
+        // with the same seed
+        let mut infected_only = model.clone();
+        infected_only.observables.truncate(1);
+        let measurements = infected_only.observe(Some(0)).unwrap();
+        let trajectory = model.run(Some(0)).unwrap();
+        let infected: Vec<f64> = trajectory.species[1].iter().map(|&i| i as f64).collect();
+        assert_eq!(measurements[0].times, times);
+        assert_eq!(measurements[0].values, infected);
+        let measurements = model.observe(Some(0)).unwrap();
+        assert_eq!(measurements[1].values, [0., 0.]);
+        assert_eq!(measurements[2].values[0], 2000.);
+        assert_eq!(measurements, model.observe(Some(0)).unwrap());
+        // noisy measurements
+        infected_only.observables[0].noise = Noise::Gaussian { sd: 3. };
+        let noisy = infected_only.observe(Some(0)).unwrap();
+        let residuals: Vec<f64> = noisy[0]
+            .values
+            .iter()
+            .zip(&infected)
+            .map(|(n, i)| n - i)
+            .collect();
+        let variance = residuals.iter().map(|r| r * r).sum::<f64>() / residuals.len() as f64;
+        assert!((variance - 9.).abs() < 2., "{variance}");
+        model.observables[0].noise = Noise::Poisson;
+        assert!(model.observe(None).unwrap()[0].values[0] >= 0.);
+        // invalid observables
+        model.observables[1].times = vec![200., 100.];
+        let err = model.validate().unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("`recovered` has invalid sampling times"));
+        model.observables[1].times = vec![100.];
+        model.observables[1].noise = Noise::Binomial { probability: 2. };
+        let err = model.validate().unwrap_err();
+        assert!(err.to_string().contains("`recovered` has an invalid noise"));
+        model.observables[1].species.insert("D".to_string(), 1.);
+        let err = model.validate().unwrap_err();
+        assert!(err.to_string().contains("unknown species `D`"));
+    }
+    #[test]
+    fn screens() {
+        let model = Model::from_toml_str(SIR).unwrap();
+        let effects = perturbation_screen(&model, Screen::Knockouts, &["R", "S"], 10, 0).unwrap();
+        let targets: Vec<&str> = effects.iter().map(|e| e.target.as_str()).collect();
+        assert_eq!(targets, ["infection", "healing"]);
+        // without infection, S stays at 999
+        assert_eq!(effects[0].means[1], 999.);
+        assert_eq!(effects[1].means[0], 0.);
+        let effects =
+            perturbation_screen(&model, Screen::ParameterScaling(2.), &["I"], 10, 0).unwrap();
+        let targets: Vec<&str> = effects.iter().map(|e| e.target.as_str()).collect();
+        assert_eq!(targets, ["r_inf", "r_heal"]);
+        assert!(effects.iter().all(|e| e.differences.len() == 1));
+        let err = perturbation_screen(&model, Screen::Knockouts, &["X"], 10, 0).unwrap_err();
+        assert!(err.to_string().contains("unknown observable species `X`"));
+    }
+    #[test]
+    fn composition() {
+        let sir = Model::from_toml_str(SIR).unwrap();
+        let other = Model::from_toml_str(
+            r#"
+            version = 1
+            species = [
+                { name = "Infected", initial = 1 },
+                { name = "Sick", initial = 1 },
+                { name = "Dead" },
+                { name = "R", initial = 5 },
+                { name = "S", initial = 999 },
+            ]
+            parameters = [{ name = "mortality", value = 0.001 }, { name = "r_heal", value = 0.02 }]
+            [[reactions]]
+            name = "death"
+            reactants = { Infected = 1, Sick = 1 }
+            products = { Dead = 1 }
+            rate = "mortality"
+            [[reactions]]
+            name = "infection"
+            reactants = { S = 1 }
+            rate = 1.0
+            [[events]]
+            time = 10.0
+            species = { Sick = 3 }
+            "#,
+        )
+        .unwrap();
+        let composition = Composition::new()
+            .alias("Sick", "Infected")
+            .alias("Infected", "I")
+            .alias("Infected", "S")
+            .alias("I", "Sick")
+            .alias("I", "I")
+            .import(&sir)
+            .import(&sir)
+            .import(&other);
+        assert_eq!(composition.resolve("Sick"), "I");
+        assert_eq!(composition.resolve("Dead"), "Dead");
+        assert_eq!(
+            composition.conflicts(),
+            [
+                Conflict::Alias {
+                    name: "Infected".into(),
+                    kept: "I".into(),
+                    ignored: "S".into()
+                },
+                Conflict::AliasCycle {
+                    name: "I".into(),
+                    canonical: "Sick".into()
+                },
+                Conflict::Initial {
+                    species: "R".into(),
+                    kept: 0,
+                    ignored: 5
+                },
+                Conflict::Value {
+                    parameter: "r_heal".into(),
+                    kept: 0.01,
+                    ignored: 0.02
+                },
+                Conflict::Reaction {
+                    name: "infection".into()
+                },
+            ]
+        );
+        assert_eq!(
+            composition.conflicts()[4].to_string(),
+            "reaction `infection` is defined differently twice"
+        );
+        let model = composition.to_model().unwrap();
+        let names: Vec<_> = model.species.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["S", "I", "R", "Dead"]);
+        assert_eq!(model.parameters.len(), 3);
+        assert_eq!(model.reactions.len(), 3);
+        assert_eq!(
+            model.reactions[2].reactants,
+            BTreeMap::from([("I".into(), 2)])
+        );
+        assert_eq!(model.events.len(), sir.events.len() + 1);
+        let last = model.events.last().unwrap();
+        assert_eq!(last.species, BTreeMap::from([("I".into(), 3)]));
+        // a species cannot also be a parameter
+        let kind = Composition::new()
+            .alias("r_inf", "S")
+            .import(&sir)
+            .import(&sir);
+        assert_eq!(kind.conflicts()[0], Conflict::Kind { name: "S".into() });
+        assert!(kind.to_model().is_err());
+    }
+    #[test]
+    fn annotations() {
+        let model = Model::from_toml_str(
+            r#"
+            version = 1
+            [[species]]
+            name = "A"
+            annotations = { sbo = "SBO:0000247", comment = "free \"A\"" }
+            [[reactions]]
+            name = "decay"
+            reactants = { A = 1 }
+            rate = 0.1
+            annotations = { reference = "doi:10.1000/182" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(model.species[0].annotations["sbo"], "SBO:0000247");
+        let json = model.to_json_string().unwrap();
+        assert!(json.contains("\"reference\": \"doi:10.1000/182\""));
+        assert_eq!(Model::from_json_str(&json).unwrap(), model);
+        let toml = model.to_toml_string().unwrap();
+        assert_eq!(Model::from_toml_str(&toml).unwrap(), model);
+        // they are not part of the network
+        let mut bare = model.clone();
+        bare.species[0].annotations.clear();
+        bare.reactions[0].annotations.clear();
+        assert_eq!(bare.model_hash(), model.model_hash());
+        assert!(!bare.to_json_string().unwrap().contains("annotations"));
+        // and are gathered by compositions
+        let mut other = bare.clone();
+        other.species[0]
+            .annotations
+            .insert("sbo".into(), "SBO:0000000".into());
+        other.reactions[0]
+            .annotations
+            .insert("comment".into(), "first order".into());
+        let composed = Composition::new()
+            .import(&model)
+            .import(&other)
+            .to_model()
+            .unwrap();
+        assert_eq!(
+            composed.species[0].annotations,
+            model.species[0].annotations
+        );
+        assert_eq!(composed.reactions.len(), 1);
+        assert_eq!(composed.reactions[0].annotations.len(), 2);
+    }
+    #[test]
+    fn firings() {
+        let mut model = Model::from_toml_str(SIR).unwrap();
+        assert!(!model.to_toml_string().unwrap().contains("firings"));
+        assert!(model.run(Some(0)).unwrap().firings.is_empty());
+        model.output.as_mut().unwrap().firings = true;
+        let toml = model.to_toml_string().unwrap();
+        assert_eq!(Model::from_toml_str(&toml).unwrap(), model);
+        let trajectory = model.run(Some(0)).unwrap();
+        assert_eq!(trajectory.firings.len(), 2);
+        assert_eq!(trajectory.firings[0].len(), trajectory.times.len());
+        // the recovered are the sum of the recoveries
+        let recoveries: u64 = trajectory.firings[1].iter().sum();
+        assert_eq!(recoveries as isize, *trajectory.species[2].last().unwrap());
+        // counting does not change the simulation
+        model.output.as_mut().unwrap().firings = false;
+        assert_eq!(model.run(Some(0)).unwrap().species, trajectory.species);
+    }
+    #[test]
+    fn rescale() {
+        let model = Model::from_toml_str(SIR).unwrap();
+        let large = model.rescale(10.).unwrap();
+        let initial: Vec<isize> = large.species.iter().map(|s| s.initial).collect();
+        assert_eq!(initial, [9990, 10, 0]);
+        // infection is of order 2, healing of order 1
+        assert!((large.parameters[0].value - 1e-5).abs() < 1e-18);
+        assert_eq!(large.parameters[1].value, 0.01);
+        assert_eq!(large.events[0].parameters["r_inf"], 0.);
+        assert_eq!(large.reactions, model.reactions);
+        let mut model = Model::from_toml_str(
+            r#"
+            version = 1
+            species = [{ name = "A", initial = 3 }]
+            parameters = [{ name = "k", value = 2.0 }]
+            [[reactions]]
+            products = { A = 1 }
+            rate = 2.0
+            [[reactions]]
+            reactants = { A = 3 }
+            rate = 8.0
+            [[reactions]]
+            reactants = { A = 1 }
+            rate = "k"
+            [[events]]
+            time = 1.0
+            species = { A = 2 }
+            parameters = { k = 4.0 }
+        "#,
+        )
+        .unwrap();
+        let large = model.rescale(0.5).unwrap();
+        assert_eq!(large.species[0].initial, 2);
+        assert_eq!(large.events[0].species["A"], 1);
+        assert_eq!(large.reactions[0].rate, RateSpec::Value(1.));
+        assert_eq!(large.reactions[1].rate, RateSpec::Value(32.));
+        // first order
+        assert_eq!(large.parameters[0].value, 2.);
+        assert_eq!(large.events[0].parameters["k"], 4.);
+        let err = model.rescale(0.).unwrap_err();
+        assert!(err.to_string().contains("system size 0"));
+        model.reactions[1].rate = RateSpec::Parameter("k".to_string());
+        let err = model.rescale(2.).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("parameter `k` is the rate of reactions"));
+    }
+    #[test]
+    fn model_hash() {
+        let model = Model::from_toml_str(SIR).unwrap();
+        let hash = model.model_hash();
+        // the same on all platforms and in all versions
+        assert_eq!(hash, "dcfac2118988dc98");
+        let mut shuffled = model.clone();
+        shuffled.species.rotate_left(1);
+        shuffled.parameters.reverse();
+        shuffled.reactions.reverse();
+        shuffled.reactions[0].name = Some("recovery".to_string());
+        shuffled.species[0].initial = 5;
+        shuffled.events.clear();
+        shuffled.output = None;
+        assert_eq!(shuffled.model_hash(), hash);
+        assert_ne!(shuffled.fingerprint(), model.fingerprint());
+        let mut roundtrip = Model::from_json_str(&model.to_json_string().unwrap()).unwrap();
+        assert_eq!(roundtrip.model_hash(), hash);
+        roundtrip.reactions[0].products.insert("R".to_string(), 0);
+        assert_eq!(roundtrip.model_hash(), hash);
+        // any change to the network changes it
+        let mut other = model.clone();
+        other.parameters[0].value = 2e-4;
+        assert_ne!(other.model_hash(), hash);
+        let mut other = model.clone();
+        other.reactions[1].rate = RateSpec::Value(0.01);
+        assert_ne!(other.model_hash(), hash);
+        let mut other = model.clone();
+        other.species[2].name = "D".to_string();
+        assert_ne!(other.model_hash(), hash);
+        let mut other = model.clone();
+        other.reactions.push(other.reactions[0].clone());
+        assert_ne!(other.model_hash(), hash);
+    }
+    #[test]
+    fn metadata() {
+        let model = Model::from_toml_str(SIR).unwrap();
+        let metadata = model.run(Some(3)).unwrap().metadata.unwrap();
+        assert_eq!(metadata.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(metadata.seed, Some(3));
+        assert_eq!(metadata.methods, [Method::Direct]);
+        assert_eq!(metadata.parameters["r_heal"], 0.01);
+        assert_eq!(metadata.model_fingerprint, Some(model.fingerprint()));
+        assert!(metadata.wall_time.as_nanos() > 0);
+        assert_eq!(metadata.model_hash, Some(model.model_hash()));
+        let mut other = model.clone();
+        other.parameters[0].value = 2e-4;
+        assert_ne!(other.fingerprint(), model.fingerprint());
+        let schedule = [
+            Phase::new(0., 1., 1),
+            Phase::new(1., 2., 1).with_method(Method::NextReaction),
+        ];
+        let metadata = model.run_schedule(&schedule, None).unwrap().metadata;
+        let metadata = metadata.unwrap();
+        assert_eq!(metadata.seed, None);
+        assert_eq!(metadata.methods, [Method::Direct, Method::NextReaction]);
+    }
+    #[test]
+    fn versions() {
+        let err = Model::from_toml_str("species = []").unwrap_err();
+        assert!(matches!(err, ModelError::MissingVersion));
+        let err = Model::from_toml_str("version = 2\nnew_field = 1").unwrap_err();
+        assert!(matches!(
+            err,
+            ModelError::UnsupportedVersion {
+                found: 2,
+                supported: SCHEMA_VERSION
+            }
+        ));
+        assert!(err.to_string().contains("upgrade"));
+        let err = Model::from_json_str(r#"{"version": 0}"#).unwrap_err();
+        assert!(err.to_string().contains("migrate"));
+    }
+    #[test]
+    fn strict_schema() {
+        let err = Model::from_toml_str("version = 1\nspecies = [{ name = \"A\", init = 3 }]");
+        assert!(matches!(err, Err(ModelError::Parse(_))));
+        let err = Model::from_toml_str(
+            "version = 1\nspecies = [{ name = \"A\" }]\n\
+             [[reactions]]\nreactants = { B = 1 }\nrate = 1.0",
+        );
+        assert!(matches!(err, Err(ModelError::Invalid(msg)) if msg.contains("`B`")));
+        let err = Model::from_toml_str(
+            "version = 1\nspecies = [{ name = \"A\" }]\n\
+             [[reactions]]\nreactants = { A = 1 }\nrate = \"k\"",
+        );
+        assert!(matches!(err, Err(ModelError::Invalid(msg)) if msg.contains("`k`")));
+        let err =
+            Model::from_toml_str("version = 1\nspecies = [{ name = \"A\" }, { name = \"A\" }]");
+        assert!(matches!(err, Err(ModelError::Invalid(_))));
+    }
+    #[test]
+    fn equations() {
+        let model = Model::from_toml_str(SIR).unwrap();
+        for reaction in &model.reactions {
+            assert_eq!(&reaction.to_string().parse::<Reaction>().unwrap(), reaction);
+        }
+        assert_eq!(
+            model.reactions[0].to_string(),
+            "infection: I + S -> 2 I @ r_inf"
+        );
+        let reaction: Reaction = " 2A + B+A ->  @ 2.5".parse().unwrap();
+        assert_eq!(reaction.name, None);
+        assert_eq!(
+            reaction.reactants,
+            BTreeMap::from([("A".into(), 3), ("B".into(), 1)])
+        );
+        assert!(reaction.products.is_empty());
+        assert_eq!(reaction.rate, RateSpec::Value(2.5));
+        assert_eq!(reaction.to_string(), "3 A + B -> 0 @ 2.5");
+        let creation: Reaction = "0 -> mRNA_1 @ k_tx".parse().unwrap();
+        assert!(creation.reactants.is_empty());
+        assert_eq!(creation.to_string().parse::<Reaction>().unwrap(), creation);
+        for (equation, message) in [
+            ("A -> B", "no `@ rate`"),
+            ("A => B @ 1", "no `->`"),
+            ("A -> B @ 1 2", "invalid rate `1 2`"),
+            ("my reaction: A -> B @ 1", "invalid name `my reaction`"),
+            ("A + -> B @ 1", "invalid species ``"),
+            ("A -> 0 B @ 1", "invalid stoichiometry `0`"),
+            ("A -> 2 3B @ 1", "invalid species `2 3B`"),
+        ] {
+            let err = equation.parse::<Reaction>().unwrap_err();
+            assert!(
+                matches!(&err, ModelError::Parse(msg) if msg.contains(message)),
+                "{equation}: {err}"
+            );
+        }
+    }
+    #[test]
+    fn network() {
+        let mut model = Model::from_toml_str(SIR).unwrap();
+        let matrix = model.stoichiometry_matrix();
+        assert_eq!(matrix, [[-1, 0], [1, -1], [0, 1]]);
+        assert_eq!(model.to_gillespie().stoichiometry_matrix(), matrix);
+        let dot = model.to_dot();
+        assert!(dot.starts_with("digraph network {"));
+        assert!(dot.contains("    r0 [label=\"infection\\nr_inf\"];\n"));
+        assert!(dot.contains("    s1 -> r0;\n    s0 -> r0;\n    r0 -> s1 [label=\"2\"];\n"));
+        assert!(dot.contains("    r1 -> s2;\n"));
+        model.species[2].name = "R\"1\"".into();
+        model.reactions[1].products = BTreeMap::from([("R\"1\"".into(), 1)]);
+        model.reactions[1].name = None;
+        assert!(model.to_dot().contains("    s2 [label=\"R\\\"1\\\"\"];\n"));
+        let xml = model.to_graphml();
+        assert!(xml.contains("<data key=\"name\">R&quot;1&quot;</data>"));
+        assert!(xml.contains("<data key=\"name\">#1</data><data key=\"rate\">r_heal</data>"));
+        assert!(xml.contains(
+            "<edge source=\"r0\" target=\"s1\"><data key=\"stoichiometry\">2</data></edge>"
+        ));
+        assert_eq!(xml.matches("<edge ").count(), 5);
+    }
+    #[test]
+    fn prefix() {
+        let model = Model::from_toml_str(SIR).unwrap();
+        let prefixed = model.with_prefix("city_");
+        prefixed.validate().unwrap();
+        assert_eq!(prefixed.species[1].name, "city_I");
+        assert_eq!(prefixed.parameters[0].name, "city_r_inf");
+        assert_eq!(
+            prefixed.reactions[0].to_string(),
+            "city_infection: city_I + city_S -> 2 city_I @ city_r_inf"
+        );
+        assert_eq!(prefixed.events[0].parameters["city_r_inf"], 0.);
+        assert_eq!(prefixed.output, model.output);
+        assert_eq!(prefixed.with_prefix(""), prefixed);
+    }
+}
@@ -0,0 +1,220 @@
+//! Scalar summaries of simulations, for optimization frameworks.
+//!
+//! Optimizers and experiment designers treat a model as a black box
+//! that turns a vector of parameters into a few numbers to minimize or
+//! to trade off against each other.  [`evaluate`] is that black box: it
+//! simulates a batch of parameter vectors in parallel and reduces the
+//! simulations of each one to the user-defined [`Summary`] values, so
+//! that a single call does all the work of a generation of an
+//! optimizer.
+//!
+//! ```
+//! use rebop::gillespie::{Gillespie, Rate};
+//! use rebop::objectives::{evaluate, Summary};
+//! // SIR model, parametrized by its infection and recovery rates
+//! let make = |params: &[f64]| {
+//!     let mut sir = Gillespie::new([999, 1, 0]);
+//!     sir.add_reaction(Rate::lma(params[0], [1, 1, 0]), [-1, 1, 0]);
+//!     sir.add_reaction(Rate::lma(params[1], [0, 1, 0]), [0, -1, 1]);
+//!     sir
+//! };
+//! let summaries = [
+//!     // final size of the epidemic
+//!     Summary::Mean { species: 2, time: 250. },
+//!     Summary::Variance { species: 2, time: 250. },
+//!     // time until 100 people are infected
+//!     Summary::TimeToThreshold { species: 1, threshold: 100, tmax: 250. },
+//! ];
+//! let batch = [vec![1e-4, 0.01], vec![3e-4, 0.01]];
+//! let values = evaluate(make, &batch, &summaries, 100, 0);
+//! assert_eq!(values.len(), 2);
+//! assert!(values[1][0] > values[0][0]);
+//! assert!(values[1][2] < values[0][2]);
+//! ```
+
+use rand::RngCore;
+
+use crate::gillespie::Gillespie;
+use crate::inference::parallel_map;
+use crate::passage::first_passage_time;
+use crate::rng::Philox;
+
+/// Scalar summary of the simulations of a parameter vector, computed by
+/// [`evaluate`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Summary {
+    /// Mean amount of the species of index `species` at `time`.
+    Mean { species: usize, time: f64 },
+    /// Unbiased variance of the amount of the species of index `species`
+    /// at `time`, `NaN` with fewer than two runs.
+    Variance { species: usize, time: f64 },
+    /// Mean time until the amount of the species of index `species`
+    /// reaches `threshold`, from below or from above depending on its
+    /// initial amount.  The runs where it does not happen before `tmax`
+    /// count as `tmax`, so that the summary stays finite.
+    TimeToThreshold {
+        species: usize,
+        threshold: isize,
+        tmax: f64,
+    },
+}
+
+/// Simulates `nb_runs` runs of the problem built by `make` for each
+/// parameter vector of `parameters`, and returns the values of the
+/// `summaries` for each vector.
+///
+/// `make` builds the problem, in its initial state, from a parameter
+/// vector.  The runs of all parameter vectors use the same seeds, drawn
+/// from [`Philox`] streams derived from `seed`: these common random
+/// numbers make the summaries vary smoothly with the parameters, which
+/// helps the optimizers.  All the simulations of the batch are spread
+/// over all available threads.
+///
+/// # Panics
+///
+/// Panics if there is no run, or if a time to threshold is requested
+/// for a problem with custom waiting times.
+pub fn evaluate<F>(
+    make: F,
+    parameters: &[Vec<f64>],
+    summaries: &[Summary],
+    nb_runs: usize,
+    seed: u64,
+) -> Vec<Vec<f64>>
+where
+    F: Fn(&[f64]) -> Gillespie + Sync,
+{
+    assert!(nb_runs > 0, "no simulation to run");
+    // times at which the amounts are needed, in chronological order
+    let mut times: Vec<f64> = summaries
+        .iter()
+        .filter_map(|summary| match summary {
+            Summary::Mean { time, .. } | Summary::Variance { time, .. } => Some(*time),
+            Summary::TimeToThreshold { .. } => None,
+        })
+        .collect();
+    times.sort_by(f64::total_cmp);
+    times.dedup();
+    let nb_tasks = parameters.len() * nb_runs;
+    // value of each summary in each run, before the reduction
+    let runs = parallel_map(nb_tasks, |itask| {
+        let initial = make(&parameters[itask / nb_runs]);
+        let seed = Philox::new(seed, (itask % nb_runs) as u64).next_u64();
+        simulate(&initial, &times, summaries, seed)
+    });
+    runs.chunks(nb_runs)
+        .map(|runs| {
+            (0..summaries.len())
+                .map(|k| {
+                    let n = nb_runs as f64;
+                    let mean = runs.iter().map(|run| run[k]).sum::<f64>() / n;
+                    match summaries[k] {
+                        Summary::Variance { .. } if nb_runs < 2 => f64::NAN,
+                        Summary::Variance { .. } => {
+                            let squares: f64 = runs.iter().map(|run| (run[k] - mean).powi(2)).sum();
+                            squares / (n - 1.)
+                        }
+                        _ => mean,
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Simulates one run of `initial` with the seed `seed`, and returns the
+/// value of each summary in this run: the amount of the species at the
+/// time of the summary, or the time to the threshold.
+fn simulate(initial: &Gillespie, times: &[f64], summaries: &[Summary], seed: u64) -> Vec<f64> {
+    let mut problem = initial.clone();
+    problem.seed(seed);
+    let mut states = Vec::with_capacity(times.len());
+    for &t in times {
+        problem.advance_until(t);
+        states.push(problem.species().to_vec());
+    }
+    summaries
+        .iter()
+        .map(|summary| match *summary {
+            Summary::Mean { species, time } | Summary::Variance { species, time } => {
+                let i = times.partition_point(|&t| t < time);
+                states[i][species] as f64
+            }
+            Summary::TimeToThreshold {
+                species,
+                threshold,
+                tmax,
+            } => {
+                let mut problem = initial.clone();
+                problem.seed(seed);
+                let passage = if problem.get_species(species) < threshold {
+                    first_passage_time(&mut problem, |x| x[species] >= threshold, tmax)
+                } else {
+                    first_passage_time(&mut problem, |x| x[species] <= threshold, tmax)
+                };
+                passage.unwrap_or(tmax)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gillespie::{Gillespie, Rate};
+    use crate::objectives::{evaluate, Summary};
+
+    fn birth(params: &[f64]) -> Gillespie {
+        let mut p = Gillespie::new([5]);
+        p.add_reaction(Rate::lma(params[0], [0]), [1]);
+        p
+    }
+
+    #[test]
+    fn summaries() {
+        let summaries = [
+            Summary::Mean {
+                species: 0,
+                time: 10.,
+            },
+            Summary::Variance {
+                species: 0,
+                time: 10.,
+            },
+            Summary::Mean {
+                species: 0,
+                time: 0.,
+            },
+            Summary::TimeToThreshold {
+                species: 0,
+                threshold: 25,
+                tmax: 100.,
+            },
+            Summary::TimeToThreshold {
+                species: 0,
+                threshold: 5,
+                tmax: 100.,
+            },
+            Summary::TimeToThreshold {
+                species: 0,
+                threshold: 10,
+                tmax: 1.,
+            },
+        ];
+        let batch = [vec![2.], vec![0.]];
+        let values = evaluate(birth, &batch, &summaries, 2000, 0);
+        // Poisson process of rate 2 from 5
+        assert!((values[0][0] - 25.).abs() < 0.5, "{:?}", values[0]);
+        assert!((values[0][1] - 20.).abs() < 2., "{:?}", values[0]);
+        assert_eq!(values[0][2], 5.);
+        // 20 births take 10 on average
+        assert!((values[0][3] - 10.).abs() < 0.2, "{:?}", values[0]);
+        assert_eq!(values[0][4], 0.);
+        assert!(values[0][5] > 0. && values[0][5] < 1.);
+        // nothing happens without births, the passages never happen
+        assert_eq!(values[1], [5., 0., 5., 100., 0., 1.]);
+        assert_eq!(values, evaluate(birth, &batch, &summaries, 2000, 0));
+        let single = evaluate(birth, &batch[..1], &summaries[..1], 1, 0);
+        assert_eq!(single.len(), 1);
+        assert!(evaluate(birth, &batch, &summaries[1..2], 1, 0)[0][0].is_nan());
+    }
+}
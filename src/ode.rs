@@ -0,0 +1,168 @@
+//! Integration of ordinary differential equations.
+//!
+//! When the amounts of species are large, a model is well approximated by
+//! its mean-field, deterministic rate equations.  [`integrate`] solves
+//! such equations `dy/dt = f(t, y)` with the explicit Runge--Kutta method
+//! of Dormand and Prince, of order 5, whose step size is controlled by the
+//! embedded method of order 4.
+//!
+//! ```
+//! use rebop::ode::integrate;
+//! // exponential decay
+//! let values = integrate(|_, y, dy| dy[0] = -y[0], &[1.], &[0., 1., 2.], 1e-8).unwrap();
+//! assert_eq!(values[0], [1.]);
+//! assert!((values[2][0] - (-2f64).exp()).abs() < 1e-6);
+//! ```
+
+/// Nodes of the stages of the method of Dormand and Prince.
+const C: [f64; 7] = [0., 1. / 5., 3. / 10., 4. / 5., 8. / 9., 1., 1.];
+
+/// Coefficients of the previous stages in each stage, the last one being
+/// the solution of order 5.
+const A: [[f64; 6]; 7] = [
+    [0., 0., 0., 0., 0., 0.],
+    [1. / 5., 0., 0., 0., 0., 0.],
+    [3. / 40., 9. / 40., 0., 0., 0., 0.],
+    [44. / 45., -56. / 15., 32. / 9., 0., 0., 0.],
+    [
+        19372. / 6561.,
+        -25360. / 2187.,
+        64448. / 6561.,
+        -212. / 729.,
+        0.,
+        0.,
+    ],
+    [
+        9017. / 3168.,
+        -355. / 33.,
+        46732. / 5247.,
+        49. / 176.,
+        -5103. / 18656.,
+        0.,
+    ],
+    [
+        35. / 384.,
+        0.,
+        500. / 1113.,
+        125. / 192.,
+        -2187. / 6784.,
+        11. / 84.,
+    ],
+];
+
+/// Differences between the weights of the solutions of orders 5 and 4.
+const E: [f64; 7] = [
+    71. / 57600.,
+    0.,
+    -71. / 16695.,
+    71. / 1920.,
+    -17253. / 339200.,
+    22. / 525.,
+    -1. / 40.,
+];
+
+/// Solves `dy/dt = f(t, y)` from `y(times[0]) = y0` and returns the
+/// values of `y` at the time points `times`.
+///
+/// `f(t, y, dy)` writes the derivatives in `dy`.  The steps keep the
+/// estimated local error on each component below `tolerance` times one
+/// plus its magnitude, so that `tolerance` is an absolute tolerance for
+/// small values and a relative one for large values.  Returns the time
+/// at which the step size vanished if the integration fails, usually
+/// because the solution blows up.
+///
+/// # Panics
+///
+/// Panics if the time points are not sorted.
+pub fn integrate<F>(f: F, y0: &[f64], times: &[f64], tolerance: f64) -> Result<Vec<Vec<f64>>, f64>
+where
+    F: Fn(f64, &[f64], &mut [f64]),
+{
+    assert!(
+        times.windows(2).all(|w| w[0] <= w[1]),
+        "the time points must be sorted"
+    );
+    let Some((&first, targets)) = times.split_first() else {
+        return Ok(Vec::new());
+    };
+    let n = y0.len();
+    let mut t = first;
+    let mut y = y0.to_vec();
+    let mut values = vec![y.clone()];
+    let mut h = 1e-3 * (times[times.len() - 1] - first);
+    let mut k = [(); 7].map(|_| vec![0.; n]);
+    let mut stage = vec![0.; n];
+    for &target in targets {
+        while t < target {
+            let last = t + h >= target;
+            let step = if last { target - t } else { h };
+            f(t, &y, &mut k[0]);
+            for s in 1..7 {
+                for i in 0..n {
+                    let increment: f64 = (0..s).map(|j| A[s][j] * k[j][i]).sum();
+                    stage[i] = y[i] + step * increment;
+                }
+                f(t + C[s] * step, &stage, &mut k[s]);
+            }
+            // the last stage is evaluated at the solution of order 5
+            let error = if n == 0 {
+                0.
+            } else {
+                let sum: f64 = (0..n)
+                    .map(|i| {
+                        let e: f64 = (0..7).map(|j| E[j] * k[j][i]).sum();
+                        let scale = tolerance * (1. + y[i].abs().max(stage[i].abs()));
+                        (step * e / scale).powi(2)
+                    })
+                    .sum();
+                (sum / n as f64).sqrt()
+            };
+            if error <= 1. {
+                t = if last { target } else { t + step };
+                y.copy_from_slice(&stage);
+            }
+            if !last || error > 1. {
+                let factor = if error.is_finite() {
+                    (0.9 * error.powf(-0.2)).clamp(0.2, 5.)
+                } else {
+                    0.2
+                };
+                h = step * factor;
+                if h <= f64::EPSILON * t.abs().max(1.) {
+                    return Err(t);
+                }
+            }
+        }
+        values.push(y.clone());
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ode::integrate;
+
+    #[test]
+    fn logistic() {
+        // dy/dt = y (1 - y), with y(t) = 1 / (1 + 9 exp(-t))
+        let times: Vec<f64> = (0..=20).map(|i| i as f64 / 2.).collect();
+        let values =
+            integrate(|_, y, dy| dy[0] = y[0] * (1. - y[0]), &[0.1], &times, 1e-10).unwrap();
+        assert_eq!(values.len(), times.len());
+        for (&t, y) in times.iter().zip(&values) {
+            assert!((y[0] - 1. / (1. + 9. * (-t).exp())).abs() < 1e-8, "{t}");
+        }
+        // repeated time points
+        let values = integrate(|_, y, dy| dy[0] = -y[0], &[1.], &[0., 0., 1., 1.], 1e-8).unwrap();
+        assert_eq!(values[1], [1.]);
+        assert_eq!(values[2], values[3]);
+    }
+
+    #[test]
+    fn blow_up() {
+        // dy/dt = y², with y(t) = 1 / (1 - t)
+        let result = integrate(|_, y, dy| dy[0] = y[0] * y[0], &[1.], &[0., 2.], 1e-8);
+        let t = result.unwrap_err();
+        assert!((t - 1.).abs() < 1e-3, "{t}");
+    }
+}
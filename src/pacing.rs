@@ -0,0 +1,112 @@
+//! Simulations paced against the wall clock.
+//!
+//! [`run_paced`] slows a simulation down so that the simulated time
+//! unfolds at a fixed ratio of the wall-clock time, and hands the state
+//! to a callback at regular time points as they "happen", for live
+//! demonstrations and dashboards.  The pacing is soft: when the
+//! simulation or the callback cannot keep up, the states are emitted as
+//! soon as possible, without skipping any.
+//!
+//! ```
+//! use std::ops::ControlFlow;
+//! use std::sync::mpsc;
+//! use std::thread;
+//! use rebop::gillespie::{Gillespie, Rate};
+//! use rebop::pacing::run_paced;
+//! let mut sir = Gillespie::new_with_seed([999, 1, 0], 0);
+//! sir.add_reaction(Rate::lma(1e-4, [1, 1, 0]), [-1, 1, 0]);
+//! sir.add_reaction(Rate::lma(0.01, [0, 1, 0]), [0, -1, 1]);
+//! // 250 days in 50 ms, sent to another thread that would draw them
+//! let (sender, receiver) = mpsc::channel();
+//! let drawer = thread::spawn(move || receiver.iter().count());
+//! run_paced(&mut sir, 250., 50, 5000., |t, x| {
+//!     sender.send((t, x.to_vec())).unwrap();
+//!     ControlFlow::<()>::Continue(())
+//! });
+//! drop(sender);
+//! assert_eq!(drawer.join().unwrap(), 51);
+//! ```
+
+use std::ops::ControlFlow;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::system::SSASystem;
+
+/// Simulates `problem` until `tmax` with `nb_steps` steps, at `speed`
+/// units of simulated time per second of wall-clock time, and calls
+/// `callback` with the time and the amounts of species at the start and
+/// at each step.
+///
+/// The state at time `t` is emitted `(t - t0) / speed` seconds after
+/// the call, `t0` being the initial time of the problem.  The simulation
+/// stops early if the callback returns [`ControlFlow::Break`], whose
+/// value is then returned.  An infinite speed disables the pacing.
+///
+/// # Panics
+///
+/// Panics if `nb_steps` is zero or if `speed` is not positive.
+pub fn run_paced<P, F, B>(
+    problem: &mut P,
+    tmax: f64,
+    nb_steps: usize,
+    speed: f64,
+    mut callback: F,
+) -> ControlFlow<B>
+where
+    P: SSASystem,
+    F: FnMut(f64, &[isize]) -> ControlFlow<B>,
+{
+    assert!(nb_steps > 0, "at least one step is needed");
+    assert!(speed > 0., "the speed must be positive");
+    let start = Instant::now();
+    let t0 = problem.t();
+    callback(t0, &problem.species())?;
+    for i in 1..=nb_steps {
+        let t = t0 + (tmax - t0) * i as f64 / nb_steps as f64;
+        problem.advance_until(t);
+        let due = Duration::from_secs_f64((t - t0) / speed);
+        thread::sleep(due.saturating_sub(start.elapsed()));
+        callback(t, &problem.species())?;
+    }
+    ControlFlow::Continue(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::ControlFlow;
+    use std::time::{Duration, Instant};
+
+    use crate::gillespie::{Gillespie, Rate};
+    use crate::pacing::run_paced;
+
+    #[test]
+    fn pacing() {
+        let mut p = Gillespie::new_with_seed([0], 0);
+        p.add_reaction(Rate::lma(10., [0]), [1]);
+        let mut times = Vec::new();
+        let start = Instant::now();
+        let flow = run_paced(&mut p, 5., 10, 100., |t, _| {
+            times.push((t, start.elapsed()));
+            ControlFlow::<()>::Continue(())
+        });
+        assert_eq!(flow, ControlFlow::Continue(()));
+        assert_eq!(times.len(), 11);
+        assert_eq!(times[10].0, 5.);
+        // the state at t is emitted after t / 100 seconds
+        for &(t, elapsed) in &times {
+            assert!(elapsed >= Duration::from_secs_f64(t / 100.));
+        }
+        assert_eq!(p.get_time(), 5.);
+        // stopped by the callback, without pacing
+        let flow = run_paced(&mut p, 10., 10, f64::INFINITY, |t, x| {
+            if t >= 7. {
+                ControlFlow::Break(x[0])
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        assert_eq!(flow, ControlFlow::Break(p.get_species(0)));
+        assert_eq!(p.get_time(), 7.);
+    }
+}
@@ -0,0 +1,181 @@
+//! First-passage times.
+//!
+//! [`first_passage_time`] simulates a problem reaction by reaction until
+//! a condition on the amounts of species holds, for example the
+//! extinction of a species or the switch of a bistable system, and
+//! [`first_passage_times`] estimates the distribution of this time over
+//! many independent simulations.
+//!
+//! ```
+//! use rebop::gillespie::{Gillespie, Rate};
+//! use rebop::passage::first_passage_times;
+//! let mut sir = Gillespie::new([999, 1, 0]);
+//! sir.add_reaction(Rate::lma(1e-4, [1, 1, 0]), [-1, 1, 0]);
+//! sir.add_reaction(Rate::lma(0.01, [0, 1, 0]), [0, -1, 1]);
+//! // extinction of the infection
+//! let extinctions = first_passage_times(&sir, |x| x[1] == 0, 1e4, 100, 0);
+//! assert!(extinctions.cdf(1e4) > 0.9);
+//! assert!(extinctions.quantile(0.5).unwrap() > 0.);
+//! ```
+
+use rand::{Rng, RngCore, SeedableRng};
+
+use crate::gillespie::Gillespie;
+use crate::inference::parallel_map;
+use crate::rng::Philox;
+
+/// Simulates `problem` until the amounts of species satisfy `condition`
+/// and returns the time when this happens, or `None` if it does not
+/// happen before `tmax`.
+///
+/// The condition is checked in the current state, then after each
+/// reaction, which is simulated with the direct method.  The problem is
+/// left at the first passage, or at `tmax` if it does not happen.
+///
+/// ```
+/// use rebop::gillespie::{Gillespie, Rate};
+/// use rebop::passage::first_passage_time;
+/// let mut p = Gillespie::new_with_seed([0], 0);
+/// p.add_reaction(Rate::lma(10., [0]), [1]);
+/// let t = first_passage_time(&mut p, |x| x[0] >= 20, 100.).unwrap();
+/// assert_eq!(p.get_species(0), 20);
+/// assert_eq!(p.get_time(), t);
+/// assert_eq!(first_passage_time(&mut p, |x| x[0] < 0, 200.), None);
+/// assert_eq!(p.get_time(), 200.);
+/// ```
+///
+/// # Panics
+///
+/// Panics if some reactions have custom waiting times.
+pub fn first_passage_time<R, F>(problem: &mut Gillespie<R>, condition: F, tmax: f64) -> Option<f64>
+where
+    R: Rng + SeedableRng,
+    F: Fn(&[isize]) -> bool,
+{
+    assert!(
+        !problem.has_waiting_times(),
+        "first passages are simulated with the direct method"
+    );
+    let mut rates = vec![f64::NAN; problem.nb_reactions()];
+    let mut previous = problem.species().to_vec();
+    loop {
+        if condition(problem.species()) {
+            return Some(problem.get_time());
+        }
+        previous.copy_from_slice(problem.species());
+        problem._advance_one_reaction(&mut rates);
+        if problem.get_time() > tmax {
+            problem.set_species(&previous);
+            problem.set_time(tmax);
+            return None;
+        }
+    }
+}
+
+/// Empirical distribution of first-passage times, computed by
+/// [`first_passage_times`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct FirstPassageTimes {
+    /// First-passage time of each simulation, `None` if it did not
+    /// happen before `tmax`.
+    pub times: Vec<Option<f64>>,
+    /// End of the simulations.
+    pub tmax: f64,
+}
+
+impl FirstPassageTimes {
+    /// Returns the fraction of the simulations whose first passage
+    /// happened at or before `t`.
+    pub fn cdf(&self, t: f64) -> f64 {
+        let nb_passed = self
+            .times
+            .iter()
+            .flatten()
+            .filter(|&&time| time <= t)
+            .count();
+        nb_passed as f64 / self.times.len() as f64
+    }
+    /// Returns the mean first-passage time, or `None` if some
+    /// simulations did not reach it, in which case it is unknown.
+    pub fn mean(&self) -> Option<f64> {
+        let sum: Option<f64> = self.times.iter().copied().sum();
+        sum.map(|sum| sum / self.times.len() as f64)
+    }
+    /// Returns the smallest first-passage time `t` such that
+    /// `cdf(t) >= q`, or `None` if fewer than a fraction `q` of the
+    /// simulations reached it.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        let mut sorted: Vec<f64> = self.times.iter().flatten().copied().collect();
+        sorted.sort_unstable_by(f64::total_cmp);
+        let rank = (q * self.times.len() as f64).ceil().max(1.) as usize;
+        sorted.get(rank - 1).copied()
+    }
+}
+
+/// Simulates `nb_runs` copies of `problem` with [`first_passage_time`]
+/// and returns their first-passage times.
+///
+/// The simulations are spread over all available threads, and the seed
+/// of each one is drawn from its own [`Philox`] stream derived from
+/// `seed`, so that the results do not depend on the number of threads.
+pub fn first_passage_times<F>(
+    problem: &Gillespie,
+    condition: F,
+    tmax: f64,
+    nb_runs: usize,
+    seed: u64,
+) -> FirstPassageTimes
+where
+    F: Fn(&[isize]) -> bool + Sync,
+{
+    let times = parallel_map(nb_runs, |irun| {
+        let mut problem = problem.clone();
+        problem.seed(Philox::new(seed, irun as u64).next_u64());
+        first_passage_time(&mut problem, &condition, tmax)
+    });
+    FirstPassageTimes { times, tmax }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gillespie::{Gillespie, Rate};
+    use crate::passage::{first_passage_time, first_passage_times, FirstPassageTimes};
+    #[test]
+    fn extinction() {
+        // the extinction time of a single decaying molecule is exponential
+        let mut p = Gillespie::new([1]);
+        p.add_reaction(Rate::lma(1., [1]), [-1]);
+        let extinctions = first_passage_times(&p, |x| x[0] == 0, 100., 2000, 0);
+        let mean = extinctions.mean().unwrap();
+        assert!((mean - 1.).abs() < 0.1, "{mean}");
+        let median = extinctions.quantile(0.5).unwrap();
+        assert!((median - 2_f64.ln()).abs() < 0.1, "{median}");
+        assert!((extinctions.cdf(1.) - (1. - (-1_f64).exp())).abs() < 0.05);
+        assert_eq!(
+            extinctions,
+            first_passage_times(&p, |x| x[0] == 0, 100., 2000, 0)
+        );
+        // the condition already holds
+        let mut q = p.clone();
+        q.set_species([0]);
+        q.set_time(3.);
+        assert_eq!(first_passage_time(&mut q, |x| x[0] == 0, 10.), Some(3.));
+    }
+    #[test]
+    fn censored() {
+        let passages = FirstPassageTimes {
+            times: vec![Some(2.), None, Some(1.), None],
+            tmax: 5.,
+        };
+        assert_eq!(passages.mean(), None);
+        assert_eq!(passages.cdf(1.5), 0.25);
+        assert_eq!(passages.cdf(5.), 0.5);
+        assert_eq!(passages.quantile(0.5), Some(2.));
+        assert_eq!(passages.quantile(0.75), None);
+        // no reaction can happen
+        let mut p = Gillespie::new([1]);
+        p.add_reaction(Rate::lma(0., [1]), [-1]);
+        assert_eq!(first_passage_time(&mut p, |x| x[0] == 0, 10.), None);
+        assert_eq!((p.get_time(), p.get_species(0)), (10., 1));
+    }
+}
@@ -0,0 +1,487 @@
+//! Populations of dividing cells.
+//!
+//! A [`Population`] holds independent copies of a problem, the cells,
+//! which it simulates in lock-step from one reporting time to the next.
+//! The cells divide following a [`Division`] rule, after a time drawn at
+//! their birth or when a species marking their size reaches a threshold,
+//! and their molecules are shared between the two daughters by binomial
+//! partitioning.  The population records the lineage tree of all the
+//! cells, and can be capped to a maximal number of cells, like a
+//! turbidostat that keeps a culture at a constant density.
+//!
+//! ```
+//! use rebop::gillespie::{Gillespie, Rate};
+//! use rebop::population::{Division, Population};
+//!
+//! // constitutive expression of a protein
+//! let mut cell = Gillespie::new([0]);
+//! cell.add_reaction(Rate::lma(50., [0]), [1]);
+//! let division = Division::Timer { mean: 1., cv: 0.1 };
+//! let mut population = Population::new(&cell, 4, division, 0).with_max_cells(100);
+//! let snapshots = population.run(&[0., 1., 2., 10.]);
+//! assert_eq!(snapshots[0].species, [[0], [0], [0], [0]]);
+//! assert!(snapshots[2].ids.len() > 4);
+//! assert_eq!(snapshots[3].ids.len(), 100);
+//! // the cells at the end descend from the first four cells
+//! let root = |mut id: usize| {
+//!     while let Some(parent) = population.lineage()[id].parent {
+//!         id = parent;
+//!     }
+//!     id
+//! };
+//! assert!(snapshots[3].ids.iter().all(|&id| root(id) < 4));
+//! ```
+
+use std::fmt::Write;
+
+use rand::rngs::SmallRng;
+use rand::{Rng, RngCore, SeedableRng};
+use rand_distr::{Binomial, Distribution, Gamma};
+
+use crate::gillespie::Gillespie;
+use crate::passage::first_passage_time;
+
+/// Maximal number of successive divisions of a lineage at a single time,
+/// beyond which the cells are considered to divide forever.
+const MAX_INSTANT_DIVISIONS: usize = 64;
+
+/// Rule deciding when the cells divide.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Division {
+    /// The cells divide at an age drawn at their birth from a gamma
+    /// distribution of mean `mean` and coefficient of variation `cv`, or
+    /// exactly at `mean` if `cv` is zero.
+    Timer { mean: f64, cv: f64 },
+    /// The cells divide as soon as the amount of the species `species`,
+    /// like a marker of their size produced by the reactions, reaches
+    /// `threshold`, which must be at least 2 for the daughters to be
+    /// below it.  The reactions are then simulated one by one with the
+    /// direct method.
+    Threshold { species: usize, threshold: isize },
+}
+
+/// End of the life of a cell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fate {
+    /// The cell divided into two daughters.
+    Divided,
+    /// The cell was removed to cap the size of the population.
+    Removed,
+}
+
+/// Node of the lineage tree of a [`Population`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct CellRecord {
+    /// Identifier of the cell, its index in the lineage.
+    pub id: usize,
+    /// Identifier of the mother, `None` for the initial cells.
+    pub parent: Option<usize>,
+    /// Number of divisions since the initial cells.
+    pub generation: usize,
+    /// Time of the birth of the cell.
+    pub birth: f64,
+    /// Time and cause of the end of the cell, `None` while it lives.
+    pub end: Option<(f64, Fate)>,
+}
+
+/// State of the living cells of a [`Population`] at a reporting time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub time: f64,
+    /// Identifiers of the living cells, in increasing order.
+    pub ids: Vec<usize>,
+    /// Amounts of species of each living cell, in the order of `ids`.
+    pub species: Vec<Vec<isize>>,
+}
+
+/// Living cell of a [`Population`].
+#[derive(Clone, Debug)]
+struct Cell<R> {
+    id: usize,
+    problem: Gillespie<R>,
+    /// Time of the next division with a [`Division::Timer`].
+    division_time: f64,
+    /// Number of successive divisions of its ancestors at its birth time.
+    instant_divisions: usize,
+}
+
+/// Population of cells simulated as independent problems, which divide
+/// and are partitioned between their daughters.
+#[derive(Clone, Debug)]
+pub struct Population<R = SmallRng> {
+    cells: Vec<Cell<R>>,
+    lineage: Vec<CellRecord>,
+    division: Division,
+    /// Species copied into both daughters instead of partitioned.
+    copied: Vec<usize>,
+    max_cells: Option<usize>,
+    /// Random number generator of the divisions, of the partitions and
+    /// of the removals, and of the seeds of the new cells.
+    rng: SmallRng,
+    t: f64,
+}
+
+impl<R: Rng + SeedableRng + Clone> Population<R> {
+    /// Creates a population of `nb_cells` newborn copies of `cell`, at the
+    /// time of `cell`, whose random number generators are seeded from
+    /// `seed`.
+    ///
+    /// The initial cells are synchronized: with a [`Division::Timer`],
+    /// they all divide around the same time, and simulating the
+    /// population for a few generations first makes it asynchronous.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the division rule refers to a species that does not
+    /// exist, or has a nonpositive mean, a threshold below 2, or a
+    /// negative coefficient of variation.
+    pub fn new(cell: &Gillespie<R>, nb_cells: usize, division: Division, seed: u64) -> Self {
+        match division {
+            Division::Timer { mean, cv } => assert!(
+                mean > 0. && cv >= 0. && mean.is_finite() && cv.is_finite(),
+                "invalid division time of mean {mean} and coefficient of variation {cv}"
+            ),
+            Division::Threshold { species, threshold } => {
+                assert!(species < cell.nb_species(), "unknown species {species}");
+                assert!(threshold >= 2, "invalid division threshold {threshold}");
+            }
+        }
+        let mut population = Population {
+            cells: Vec::new(),
+            lineage: Vec::new(),
+            division,
+            copied: Vec::new(),
+            max_cells: None,
+            rng: SmallRng::seed_from_u64(seed),
+            t: cell.get_time(),
+        };
+        for _ in 0..nb_cells {
+            let mut problem = cell.clone();
+            problem.seed(population.rng.next_u64());
+            let cell = population.add_cell(problem, None, 0);
+            population.cells.push(cell);
+        }
+        population
+    }
+    /// Copies the species `species` into both daughters at the divisions,
+    /// like the genes of the cells, instead of partitioning them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the species mark the size of the cells for a
+    /// [`Division::Threshold`], since the daughters would divide again
+    /// forever.
+    pub fn with_copied_species(mut self, species: &[usize]) -> Self {
+        if let Division::Threshold { species: size, .. } = self.division {
+            assert!(
+                !species.contains(&size),
+                "the species {size} must be partitioned"
+            );
+        }
+        self.copied = species.to_vec();
+        self
+    }
+    /// Caps the population to `max_cells` cells: at each reporting time,
+    /// random cells are removed until at most `max_cells` remain.  The
+    /// population grows freely between the reporting times, which should
+    /// be close enough.
+    pub fn with_max_cells(mut self, max_cells: usize) -> Self {
+        self.max_cells = Some(max_cells);
+        self
+    }
+    /// Returns the current time of the population.
+    pub fn get_time(&self) -> f64 {
+        self.t
+    }
+    /// Returns the number of living cells.
+    pub fn nb_cells(&self) -> usize {
+        self.cells.len()
+    }
+    /// Returns the identifiers and the problems of the living cells, in
+    /// increasing order of identifiers.
+    pub fn cells(&self) -> impl Iterator<Item = (usize, &Gillespie<R>)> {
+        self.cells.iter().map(|cell| (cell.id, &cell.problem))
+    }
+    /// Returns the records of all the cells that ever lived, indexed by
+    /// their identifiers.
+    pub fn lineage(&self) -> &[CellRecord] {
+        &self.lineage
+    }
+    /// Returns the state of the living cells.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            time: self.t,
+            ids: self.cells.iter().map(|cell| cell.id).collect(),
+            species: (self.cells.iter())
+                .map(|cell| cell.problem.species().to_vec())
+                .collect(),
+        }
+    }
+    /// Simulates all the cells, and their descendants, until `tmax`, then
+    /// caps the population.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `tmax` is before the current time, if the cells have
+    /// custom waiting times with a [`Division::Threshold`], or if a lineage
+    /// divides too many times at a single time, like when a reaction
+    /// produces many times the threshold at once.
+    pub fn advance_until(&mut self, tmax: f64) {
+        assert!(tmax >= self.t, "cannot go back in time to {tmax}");
+        let mut pending = std::mem::take(&mut self.cells);
+        pending.reverse();
+        while let Some(mut cell) = pending.pop() {
+            let division_time = match self.division {
+                Division::Timer { .. } if cell.division_time > tmax => None,
+                Division::Timer { .. } => {
+                    cell.problem.advance_until(cell.division_time);
+                    Some(cell.division_time)
+                }
+                Division::Threshold { species, threshold } => first_passage_time(
+                    &mut cell.problem,
+                    |amounts| amounts[species] >= threshold,
+                    tmax,
+                ),
+            };
+            match division_time {
+                None => {
+                    cell.problem.advance_until(tmax);
+                    self.cells.push(cell);
+                }
+                Some(t) => {
+                    let (first, second) = self.divide(cell, t);
+                    pending.push(second);
+                    pending.push(first);
+                }
+            }
+        }
+        self.cells.sort_by_key(|cell| cell.id);
+        if let Some(max_cells) = self.max_cells {
+            while self.cells.len() > max_cells {
+                let cell = self.cells.remove(self.rng.gen_range(0..self.cells.len()));
+                self.lineage[cell.id].end = Some((tmax, Fate::Removed));
+            }
+        }
+        self.t = tmax;
+    }
+    /// Simulates the population through the increasing reporting times
+    /// `times`, and returns its state at each of them.
+    ///
+    /// # Panics
+    ///
+    /// Panics like [`advance_until`](Population::advance_until).
+    pub fn run(&mut self, times: &[f64]) -> Vec<Snapshot> {
+        times
+            .iter()
+            .map(|&t| {
+                self.advance_until(t);
+                self.snapshot()
+            })
+            .collect()
+    }
+    /// Writes the lineage trees in the Newick format, one line per initial
+    /// cell, with the identifiers of the cells as labels and their
+    /// lifetimes, until now for the living cells, as branch lengths.
+    pub fn to_newick(&self) -> String {
+        let mut children = vec![Vec::new(); self.lineage.len()];
+        for record in &self.lineage {
+            if let Some(parent) = record.parent {
+                children[parent].push(record.id);
+            }
+        }
+        let mut newick = String::new();
+        for root in self.lineage.iter().filter(|record| record.parent.is_none()) {
+            // cells to open, or to close once their subtrees are written,
+            // with an iterative traversal for the long lineages
+            let mut stack = vec![(root.id, false)];
+            while let Some((id, close)) = stack.pop() {
+                if close {
+                    if !children[id].is_empty() {
+                        newick.push(')');
+                    }
+                    let record = &self.lineage[id];
+                    let end = record.end.map_or(self.t, |(end, _)| end);
+                    write!(newick, "{id}:{}", end - record.birth).unwrap();
+                    if let Some(&(_, false)) = stack.last() {
+                        newick.push(',');
+                    }
+                    continue;
+                }
+                stack.push((id, true));
+                if !children[id].is_empty() {
+                    newick.push('(');
+                    stack.extend(children[id].iter().rev().map(|&child| (child, false)));
+                }
+            }
+            newick.push_str(";\n");
+        }
+        newick
+    }
+    /// Adds a newborn cell simulated by `problem`, daughter of `parent`.
+    fn add_cell(
+        &mut self,
+        problem: Gillespie<R>,
+        parent: Option<usize>,
+        instant_divisions: usize,
+    ) -> Cell<R> {
+        let birth = problem.get_time();
+        let id = self.lineage.len();
+        self.lineage.push(CellRecord {
+            id,
+            parent,
+            generation: parent.map_or(0, |parent| self.lineage[parent].generation + 1),
+            birth,
+            end: None,
+        });
+        let division_time = match self.division {
+            Division::Timer { mean, cv: 0. } => birth + mean,
+            Division::Timer { mean, cv } => {
+                let shape = 1. / (cv * cv);
+                let gamma = Gamma::new(shape, mean / shape).expect("valid parameters");
+                birth + gamma.sample(&mut self.rng)
+            }
+            Division::Threshold { .. } => f64::NAN,
+        };
+        Cell {
+            id,
+            problem,
+            division_time,
+            instant_divisions,
+        }
+    }
+    /// Divides `cell` at the time `t`, partitioning its molecules, and
+    /// returns its two daughters.
+    fn divide(&mut self, cell: Cell<R>, t: f64) -> (Cell<R>, Cell<R>) {
+        let instant_divisions = match self.lineage[cell.id].birth == t {
+            true => cell.instant_divisions + 1,
+            false => 1,
+        };
+        assert!(
+            instant_divisions <= MAX_INSTANT_DIVISIONS,
+            "the cells divide endlessly at time {t}"
+        );
+        self.lineage[cell.id].end = Some((t, Fate::Divided));
+        let amounts = cell.problem.species().to_vec();
+        let (mut first, mut second) = (amounts.clone(), amounts.clone());
+        for (s, &amount) in amounts.iter().enumerate() {
+            if !self.copied.contains(&s) && amount > 0 {
+                let binomial = Binomial::new(amount as u64, 0.5).expect("valid parameters");
+                first[s] = binomial.sample(&mut self.rng) as isize;
+                second[s] = amount - first[s];
+            }
+        }
+        let mut problem = cell.problem;
+        let mut sister = problem.clone();
+        sister.seed(self.rng.next_u64());
+        problem.set_species(first);
+        sister.set_species(second);
+        (
+            self.add_cell(problem, Some(cell.id), instant_divisions),
+            self.add_cell(sister, Some(cell.id), instant_divisions),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gillespie::{Gillespie, Rate};
+    use crate::population::*;
+
+    #[test]
+    fn timer() {
+        let mut cell = Gillespie::new([0, 1]);
+        cell.add_reaction(Rate::lma(100., [0, 1]), [1, 0]);
+        let division = Division::Timer { mean: 1., cv: 0. };
+        let mut population = Population::new(&cell, 1, division, 3).with_copied_species(&[1]);
+        let snapshots = population.run(&[0.5, 1.5, 2.5]);
+        let sizes: Vec<usize> = snapshots.iter().map(|s| s.ids.len()).collect();
+        assert_eq!(sizes, [1, 2, 4]);
+        assert_eq!(snapshots[1].ids, [1, 2]);
+        assert_eq!(snapshots[2].ids, [3, 4, 5, 6]);
+        // the genes are copied and the proteins partitioned
+        assert!(snapshots[2].species.iter().all(|x| x[1] == 1));
+        let lineage = population.lineage();
+        assert_eq!(lineage[0].end, Some((1., Fate::Divided)));
+        assert_eq!(lineage[5].parent, Some(2));
+        assert_eq!(lineage[5].generation, 2);
+        assert_eq!(lineage[5].birth, 2.);
+        assert_eq!(
+            population.to_newick(),
+            "((3:0.5,4:0.5)1:1,(5:0.5,6:0.5)2:1)0:1;\n"
+        );
+        for (_, problem) in population.cells() {
+            assert_eq!(problem.get_time(), 2.5);
+        }
+        // same seed, same population
+        let mut again = Population::new(&cell, 1, division, 3).with_copied_species(&[1]);
+        assert_eq!(again.run(&[0.5, 1.5, 2.5]), snapshots);
+    }
+
+    #[test]
+    fn threshold() {
+        // the size grows until 20 and is halved at the divisions
+        let mut cell = Gillespie::new([10, 0]);
+        cell.add_reaction(Rate::lma(1., [1, 0]), [1, 0]);
+        cell.add_reaction(Rate::lma(5., [0, 0]), [0, 1]);
+        let division = Division::Threshold {
+            species: 0,
+            threshold: 20,
+        };
+        let mut population = Population::new(&cell, 2, division, 0).with_max_cells(50);
+        population.advance_until(5.);
+        assert_eq!(population.nb_cells(), 50);
+        assert_eq!(population.get_time(), 5.);
+        for (_, problem) in population.cells() {
+            assert!(problem.get_species(0) < 20);
+        }
+        let removed = (population.lineage().iter())
+            .filter(|record| record.end == Some((5., Fate::Removed)))
+            .count();
+        let living = (population.lineage().iter())
+            .filter(|record| record.end.is_none())
+            .count();
+        assert!(removed > 0);
+        assert_eq!(living, 50);
+        for record in &population.lineage()[2..] {
+            let parent = &population.lineage()[record.parent.unwrap()];
+            assert_eq!(parent.end, Some((record.birth, Fate::Divided)));
+        }
+    }
+
+    #[test]
+    fn deep_lineage() {
+        let cell = Gillespie::new([0]);
+        let division = Division::Timer { mean: 1., cv: 0. };
+        let mut population = Population::new(&cell, 1, division, 0).with_max_cells(1);
+        let times: Vec<f64> = (0..=100_000).map(|i| i as f64 + 0.5).collect();
+        population.run(&times);
+        let newick = population.to_newick();
+        assert_eq!(newick.matches('(').count(), 100_000);
+        assert!(newick.starts_with("(("));
+        assert!(newick.ends_with(")0:1;\n"));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid division threshold 1")]
+    fn threshold_one() {
+        let mut cell = Gillespie::new([0]);
+        cell.add_reaction(Rate::lma(1., [0]), [1]);
+        let division = Division::Threshold {
+            species: 0,
+            threshold: 1,
+        };
+        Population::new(&cell, 1, division, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "the cells divide endlessly")]
+    fn endless_divisions() {
+        let mut cell = Gillespie::new([0]);
+        cell.add_reaction(Rate::lma(1., [0]), [isize::MAX / 2]);
+        let division = Division::Threshold {
+            species: 0,
+            threshold: 2,
+        };
+        Population::new(&cell, 1, division, 0).advance_until(5.);
+    }
+}
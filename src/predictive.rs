@@ -0,0 +1,181 @@
+//! Posterior predictive simulations.
+//!
+//! Given samples of the parameters, for example from a posterior
+//! distribution, [`posterior_predictive`] simulates trajectories for
+//! each sample in parallel and summarizes them as quantiles over time.
+//!
+//! ```
+//! use rebop::gillespie::{Gillespie, Rate};
+//! use rebop::predictive::posterior_predictive;
+//! // samples of the birth and death rates
+//! let samples = vec![vec![10., 0.1], vec![12., 0.1], vec![8., 0.12]];
+//! let bands = posterior_predictive(
+//!     &samples,
+//!     |params| {
+//!         let mut p = Gillespie::new([0]);
+//!         p.add_reaction(Rate::lma(params[0], [0]), [1]);
+//!         p.add_reaction(Rate::lma(params[1], [1]), [-1]);
+//!         p
+//!     },
+//!     10,
+//!     &[0., 10., 20., 50.],
+//!     &[0],
+//!     &[0.05, 0.5, 0.95],
+//!     0,
+//! );
+//! let median = bands.band(0, 1);
+//! assert_eq!(median[0], 0.);
+//! assert!(median[3] > 30.);
+//! ```
+
+use rand::RngCore;
+
+use crate::inference::parallel_map;
+use crate::rng::Philox;
+use crate::system::SSASystem;
+
+/// Quantiles over time of observed species across simulations.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PredictiveBands {
+    /// Time points.
+    pub times: Vec<f64>,
+    /// Indices of the observed species.
+    pub observables: Vec<usize>,
+    /// Probabilities of the quantiles.
+    pub quantiles: Vec<f64>,
+    /// Values of the quantiles, indexed by observable, quantile and time.
+    pub values: Vec<Vec<Vec<f64>>>,
+}
+
+impl PredictiveBands {
+    /// Returns the values over time of the quantile of index
+    /// `iquantile` of the observable of index `iobservable`.
+    pub fn band(&self, iobservable: usize, iquantile: usize) -> &[f64] {
+        &self.values[iobservable][iquantile]
+    }
+}
+
+/// Simulates `runs_per_sample` trajectories for each parameter sample
+/// in `samples`, and returns the `quantiles` of the amounts of the
+/// species of indices `observables` at each of the `times`.
+///
+/// `make` builds the problem, in its initial state, from a sample, with
+/// either of the APIs of rebop (see [`SSASystem`]).  The simulations
+/// are spread over all available threads, and the seed of each run is
+/// drawn from its own [`Philox`] stream derived from `seed`, so that the
+/// results do not depend on the number of threads.  Quantiles are interpolated linearly between the
+/// simulated values.
+///
+/// # Panics
+///
+/// Panics if a quantile is not in `[0, 1]` or if there is no simulation.
+pub fn posterior_predictive<S, P, F>(
+    samples: &[S],
+    make: F,
+    runs_per_sample: usize,
+    times: &[f64],
+    observables: &[usize],
+    quantiles: &[f64],
+    seed: u64,
+) -> PredictiveBands
+where
+    S: Sync,
+    P: SSASystem,
+    F: Fn(&S) -> P + Sync,
+{
+    assert!(quantiles.iter().all(|q| (0. ..=1.).contains(q)));
+    let nb_runs = samples.len() * runs_per_sample;
+    assert!(nb_runs > 0, "no simulation to run");
+    // observed values, indexed by run, then time and observable
+    let runs = parallel_map(nb_runs, |irun| {
+        let mut problem = make(&samples[irun / runs_per_sample]);
+        problem.seed(Philox::new(seed, irun as u64).next_u64());
+        let mut run = Vec::with_capacity(times.len() * observables.len());
+        for &t in times {
+            problem.advance_until(t);
+            let species = problem.species();
+            run.extend(observables.iter().map(|&o| species[o] as f64));
+        }
+        run
+    });
+    let mut values = vec![vec![vec![0.; times.len()]; quantiles.len()]; observables.len()];
+    let mut column = vec![0.; nb_runs];
+    for itime in 0..times.len() {
+        for (iobservable, observable_values) in values.iter_mut().enumerate() {
+            for (c, run) in column.iter_mut().zip(&runs) {
+                *c = run[itime * observables.len() + iobservable];
+            }
+            column.sort_unstable_by(f64::total_cmp);
+            for (quantile_values, &q) in observable_values.iter_mut().zip(quantiles) {
+                quantile_values[itime] = quantile(&column, q);
+            }
+        }
+    }
+    PredictiveBands {
+        times: times.to_vec(),
+        observables: observables.to_vec(),
+        quantiles: quantiles.to_vec(),
+        values,
+    }
+}
+
+/// Returns the quantile `q` of the sorted values `sorted`, interpolated
+/// linearly.
+pub(crate) fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let position = q * (sorted.len() - 1) as f64;
+    let below = position.floor() as usize;
+    let above = position.ceil() as usize;
+    sorted[below] + (sorted[above] - sorted[below]) * (position - below as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::define_system;
+    use crate::gillespie::{Gillespie, Rate};
+    use crate::predictive::{posterior_predictive, quantile};
+    #[test]
+    fn quantiles() {
+        let sorted = [1., 2., 3., 4., 5.];
+        assert_eq!(quantile(&sorted, 0.), 1.);
+        assert_eq!(quantile(&sorted, 0.5), 3.);
+        assert_eq!(quantile(&sorted, 0.625), 3.5);
+        assert_eq!(quantile(&sorted, 1.), 5.);
+        assert_eq!(quantile(&[2.], 0.3), 2.);
+    }
+    #[test]
+    fn birth() {
+        let samples = [1., 10., 100.];
+        let make = |rate: &f64| {
+            let mut p = Gillespie::new([0, 5]);
+            p.add_reaction(Rate::lma(*rate, [0, 0]), [1, 0]);
+            p
+        };
+        let times = [0., 1., 2.];
+        let bands = posterior_predictive(&samples, make, 20, &times, &[1, 0], &[0., 0.5, 1.], 0);
+        assert_eq!(bands.values.len(), 2);
+        // the second species is constant
+        assert!(bands.values[0].iter().flatten().all(|&v| v == 5.));
+        // quantiles are ordered
+        for itime in 0..times.len() {
+            assert!(bands.band(1, 0)[itime] <= bands.band(1, 1)[itime]);
+            assert!(bands.band(1, 1)[itime] <= bands.band(1, 2)[itime]);
+        }
+        assert_eq!(bands.band(1, 2)[0], 0.);
+        assert!(bands.band(1, 2)[2] > 100.);
+        assert!(bands.band(1, 0)[2] < 10.);
+        // the seed determines the results
+        let again = posterior_predictive(&samples, make, 20, &times, &[1, 0], &[0., 0.5, 1.], 0);
+        assert_eq!(bands, again);
+    }
+    #[test]
+    fn macro_models() {
+        define_system! {
+            r_birth;
+            Birth { A, B = 5 }
+            birth   :   => A    @ r_birth
+        }
+        let make = |rate: &f64| Birth::with_parameters(*rate);
+        let bands = posterior_predictive(&[1., 10.], make, 10, &[0., 1.], &[1], &[0.5], 0);
+        assert_eq!(bands.band(0, 0), [5., 5.]);
+    }
+}
@@ -0,0 +1,1590 @@
+//! Python bindings, with the `python` feature.
+//!
+//! They define the extension module `rebop.rebop` built by maturin, whose
+//! `Gillespie` class describes reaction networks by the names of their
+//! species and simulates them with the function-based API of
+//! [`gillespie`].  The Python package in `python/rebop` wraps its results
+//! into xarray datasets.
+
+// The code generated by `#[pymethods]` for `PyResult` return types
+// triggers this lint with recent clippy versions.  It is generated next
+// to the `impl` blocks, out of reach of their attributes, so the lint is
+// allowed in this module only.
+#![allow(clippy::useless_conversion)]
+
+use pyo3::exceptions::{PyIndexError, PyTimeoutError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use rand::RngCore;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::ControlFlow;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{codegen, conservation, gillespie, inference, model, objectives, ode, pacing, rng};
+
+/// Minimal duration between two calls to the progress callback of Python
+/// ensembles.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Number of reactions simulated by Python runs between two checks for
+/// signals, like a keyboard interrupt, and for their time limit.
+const REACTIONS_BETWEEN_CHECKS: u64 = 100_000;
+
+/// Reaction system composed of species and reactions.
+#[pyclass(module = "rebop")]
+#[derive(Clone)]
+struct Gillespie {
+    species: HashMap<String, usize>,
+    reactions: Vec<(f64, Vec<String>, Vec<String>)>,
+    /// Initial configuration kept with the system, not used by the runs.
+    init: HashMap<String, usize>,
+    /// Observables, as weights of the species by name, in the order in
+    /// which they were added.
+    observables: Vec<(String, HashMap<String, f64>)>,
+}
+
+/// Law of Mass Action reaction of a system, as listed by `Gillespie.reactions`.
+///
+/// `reactants` and `products` map species names to their stoichiometries.
+#[pyclass(module = "rebop", frozen, eq, get_all)]
+#[derive(Clone, PartialEq)]
+struct Reaction {
+    reactants: BTreeMap<String, u32>,
+    products: BTreeMap<String, u32>,
+    rate: f64,
+}
+
+/// State at the end of a run, from which another run can resume.
+///
+/// It holds the time, the amounts of the species by name, and the state of the random number
+/// generator.
+#[pyclass(module = "rebop", frozen)]
+#[derive(Clone)]
+struct State {
+    #[pyo3(get)]
+    time: f64,
+    #[pyo3(get)]
+    species: HashMap<String, isize>,
+    rng: rand::rngs::SmallRng,
+}
+
+/// Iterator over the chunks of a run, returned by `Gillespie.iter_run`.
+///
+/// Each chunk is a tuple `times, values` of at most `chunk_size` time points, where `values`
+/// is the flat array of the amounts of shape `(time, species)`.
+#[pyclass(module = "rebop")]
+struct RunChunks {
+    problem: gillespie::Gillespie,
+    tmax: f64,
+    /// Number of steps, or `0` to return every reaction.
+    nb_steps: usize,
+    chunk_size: usize,
+    /// Index of the next time point, with a uniform grid.
+    next: usize,
+    done: bool,
+}
+
+impl RunChunks {
+    /// Simulates the next chunk, and returns `None` at the end of the run.
+    fn chunk(&mut self) -> Option<(Vec<f64>, Vec<isize>)> {
+        if self.done {
+            return None;
+        }
+        let g = &mut self.problem;
+        let mut times = Vec::with_capacity(self.chunk_size);
+        let mut values = Vec::with_capacity(self.chunk_size * g.nb_species());
+        let mut rates = vec![f64::NAN; g.nb_reactions()];
+        while times.len() < self.chunk_size && !self.done {
+            let t = if self.nb_steps == 0 {
+                // nb_steps = 0: we return every step
+                if self.next > 0 {
+                    g._advance_one_reaction(&mut rates);
+                }
+                self.done = g.get_time() >= self.tmax;
+                g.get_time()
+            } else {
+                let t = self.tmax * self.next as f64 / self.nb_steps as f64;
+                g.advance_until(t);
+                self.done = self.next == self.nb_steps;
+                t
+            };
+            self.next += 1;
+            times.push(t);
+            values.extend_from_slice(g.species());
+        }
+        Some((times, values))
+    }
+}
+
+#[pymethods]
+impl RunChunks {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+    fn __next__(mut slf: PyRefMut<'_, Self>, py: Python<'_>) -> Option<(Vec<f64>, Vec<isize>)> {
+        let chunks = &mut *slf;
+        py.allow_threads(|| chunks.chunk())
+    }
+}
+
+#[pymethods]
+impl State {
+    fn __repr__(&self) -> String {
+        let mut species: Vec<_> = self.species.iter().collect();
+        species.sort();
+        let species: Vec<_> = species
+            .into_iter()
+            .map(|(name, amount)| format!("{name}: {amount}"))
+            .collect();
+        format!(
+            "State(time={}, species={{{}}})",
+            self.time,
+            species.join(", ")
+        )
+    }
+}
+
+#[pymethods]
+impl Reaction {
+    fn __repr__(&self) -> String {
+        let reaction = model::Reaction {
+            name: None,
+            reactants: self.reactants.clone(),
+            products: self.products.clone(),
+            rate: model::RateSpec::Value(self.rate),
+            annotations: model::Annotations::new(),
+        };
+        format!("Reaction(\"{reaction}\")")
+    }
+}
+
+#[pymethods]
+impl Gillespie {
+    #[new]
+    fn new() -> Self {
+        Gillespie {
+            species: HashMap::new(),
+            reactions: Vec::new(),
+            init: HashMap::new(),
+            observables: Vec::new(),
+        }
+    }
+    /// Load the reactions of a model from a `.toml` or `.json` file, or from a BioNetGen
+    /// network in a `.net` file.
+    ///
+    /// Rates given as parameter names are replaced by the parameter
+    /// values.  The initial amounts are kept in `init`, but `run` still
+    /// needs to be given the initial configuration.  The observables are
+    /// added with their weights, but events, sampling times, noises and
+    /// output settings of the file are not used.
+    #[staticmethod]
+    fn from_file(path: &str) -> PyResult<Self> {
+        let model =
+            model::Model::from_file(path).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        let params: HashMap<&str, f64> = model
+            .parameters
+            .iter()
+            .map(|param| (param.name.as_str(), param.value))
+            .collect();
+        let mut g = Gillespie::new();
+        for species in &model.species {
+            g.species.insert(species.name.clone(), g.species.len());
+            if species.initial > 0 {
+                g.init
+                    .insert(species.name.clone(), species.initial as usize);
+            }
+        }
+        for reaction in &model.reactions {
+            let rate = match &reaction.rate {
+                model::RateSpec::Value(value) => *value,
+                model::RateSpec::Parameter(name) => params[name.as_str()],
+            };
+            g.reactions.push((
+                rate,
+                expand(&reaction.reactants),
+                expand(&reaction.products),
+            ));
+        }
+        for observable in &model.observables {
+            let weights = observable.species.clone().into_iter().collect();
+            g.observables.push((observable.name.clone(), weights));
+        }
+        Ok(g)
+    }
+    /// Names of the species, in the order in which they were added.
+    #[getter]
+    fn species(&self) -> Vec<String> {
+        let mut species: Vec<_> = self.species.iter().collect();
+        species.sort_by_key(|&(_, &id)| id);
+        species.into_iter().map(|(name, _)| name.clone()).collect()
+    }
+    /// Reactions, in the order in which they were added.
+    #[getter]
+    fn reactions(&self) -> Vec<Reaction> {
+        self.reactions
+            .iter()
+            .map(|(rate, reactants, products)| Reaction {
+                reactants: count(reactants),
+                products: count(products),
+                rate: *rate,
+            })
+            .collect()
+    }
+    /// Initial configuration of the system, a dictionary of species name to amount.
+    ///
+    /// It is filled by `from_file`, and only kept with the system: it can be passed as the
+    /// `init` of the runs.
+    #[getter]
+    fn init(&self) -> HashMap<String, usize> {
+        self.init.clone()
+    }
+    #[setter]
+    fn set_init(&mut self, init: HashMap<String, usize>) {
+        self.init = init;
+    }
+    /// Number of species currently in the system
+    fn nb_species(&self) -> PyResult<usize> {
+        Ok(self.species.len())
+    }
+    /// Add a Law of Mass Action reaction to the system.
+    ///
+    /// The forward reaction rate is `rate`, while `reactants` and `products` are lists of
+    /// respectively reactant names and product names.  Add the reverse reaction with the rate
+    /// `reverse_rate` if it is not `None`.
+    #[pyo3(signature = (rate, reactants, products, reverse_rate=None))]
+    fn add_reaction(
+        &mut self,
+        rate: f64,
+        reactants: Vec<String>,
+        products: Vec<String>,
+        reverse_rate: Option<f64>,
+    ) -> PyResult<()> {
+        // Insert unknown reactants in known species
+        for reactant in &reactants {
+            if !self.species.contains_key(reactant) {
+                self.species.insert(reactant.clone(), self.species.len());
+            }
+        }
+        // Insert unknown products in known species
+        for product in &products {
+            if !self.species.contains_key(product) {
+                self.species.insert(product.clone(), self.species.len());
+            }
+        }
+        self.reactions
+            .push((rate, reactants.clone(), products.clone()));
+        if let Some(rrate) = reverse_rate {
+            self.reactions.push((rrate, products, reactants));
+        }
+        Ok(())
+    }
+    /// Add a Law of Mass Action reaction written as an equation to the system.
+    ///
+    /// The equation gives the stoichiometries before the species names and the rate after
+    /// `@`, like `"S + I -> 2 I @ 1e-4"`, with `0` or nothing for no species, and optionally
+    /// a name before `:`, which is ignored.  Add the reverse reaction with the rate
+    /// `reverse_rate` if it is not `None`.
+    #[pyo3(signature = (equation, reverse_rate=None))]
+    fn add_reaction_str(&mut self, equation: &str, reverse_rate: Option<f64>) -> PyResult<()> {
+        let reaction: model::Reaction = equation
+            .parse()
+            .map_err(|e: model::ModelError| PyValueError::new_err(e.to_string()))?;
+        let model::RateSpec::Value(rate) = reaction.rate else {
+            return Err(PyValueError::new_err(format!(
+                "the rate of reaction `{equation}` is not a number"
+            )));
+        };
+        self.add_reaction(
+            rate,
+            expand(&reaction.reactants),
+            expand(&reaction.products),
+            reverse_rate,
+        )
+    }
+    /// Remove the reaction of index `index`, in the order in which they were added.
+    ///
+    /// The species are kept, even if no reaction refers to them anymore.
+    fn remove_reaction(&mut self, index: usize) -> PyResult<()> {
+        self.check_reaction(index)?;
+        self.reactions.remove(index);
+        Ok(())
+    }
+    /// Set the rate of the reaction of index `index` to `rate`.
+    fn set_rate(&mut self, index: usize, rate: f64) -> PyResult<()> {
+        self.check_reaction(index)?;
+        self.reactions[index].0 = rate;
+        Ok(())
+    }
+    /// Remove all species, reactions and observables, and the initial configuration.
+    fn clear(&mut self) {
+        self.species.clear();
+        self.reactions.clear();
+        self.init.clear();
+        self.observables.clear();
+    }
+    /// Add the species, reactions, initial configuration and observables of `other` to the
+    /// system, to build large models from reusable modules.
+    ///
+    /// The species of `other` are renamed, then merged with the species of the same name:
+    /// those in the dictionary `rename` take the given names, to connect the modules, and
+    /// the others get `prefix` before their names, to namespace them.  The observables of
+    /// `other` also get the prefix.  Raises a `ValueError`, before any change, if a merged
+    /// species has two different initial amounts, or if two different observables have
+    /// the same name.
+    #[pyo3(signature = (other, prefix="", rename=None))]
+    fn merge(
+        &mut self,
+        other: Gillespie,
+        prefix: &str,
+        rename: Option<HashMap<String, String>>,
+    ) -> PyResult<()> {
+        let rename = rename.unwrap_or_default();
+        let name = |species: &String| {
+            (rename.get(species).cloned()).unwrap_or_else(|| format!("{prefix}{species}"))
+        };
+        let names = |species: &[String]| species.iter().map(name).collect::<Vec<_>>();
+        let init: Vec<(String, usize)> = (other.init.iter())
+            .map(|(species, &amount)| (name(species), amount))
+            .collect();
+        for (species, amount) in &init {
+            match self.init.get(species) {
+                Some(kept) if kept != amount => {
+                    return Err(PyValueError::new_err(format!(
+                        "species `{species}` starts at {kept} and at {amount}"
+                    )));
+                }
+                _ => {}
+            }
+        }
+        let species: Vec<String> = names(&other.species());
+        let mut observables = Vec::new();
+        for (observable, weights) in &other.observables {
+            let observable = format!("{prefix}{observable}");
+            let mut renamed = HashMap::new();
+            for (species, &weight) in weights {
+                *renamed.entry(name(species)).or_insert(0.) += weight;
+            }
+            if self.species.contains_key(&observable) || species.contains(&observable) {
+                return Err(PyValueError::new_err(format!(
+                    "observable `{observable}` has the name of a species"
+                )));
+            }
+            match self
+                .observables
+                .iter()
+                .find(|(kept, _)| *kept == observable)
+            {
+                Some((_, kept)) if *kept != renamed => {
+                    return Err(PyValueError::new_err(format!(
+                        "observable `{observable}` is defined differently twice"
+                    )));
+                }
+                Some(_) => {}
+                None => observables.push((observable, renamed)),
+            }
+        }
+        for species in species {
+            if !self.species.contains_key(&species) {
+                self.species.insert(species, self.species.len());
+            }
+        }
+        for (rate, reactants, products) in &other.reactions {
+            self.reactions
+                .push((*rate, names(reactants), names(products)));
+        }
+        self.init.extend(init);
+        self.observables.extend(observables);
+        Ok(())
+    }
+    /// Number of reactions currently in the system.
+    fn nb_reactions(&self) -> PyResult<usize> {
+        Ok(self.reactions.len())
+    }
+    /// Add an observable, the linear combination of the species with the weights
+    /// `weights`, a dictionary of species name to weight, or replace the observable of the
+    /// same name.
+    ///
+    /// Like `total_protein = protein + 2 * dimer`, observables are reported by `run` next
+    /// to the species, and given to the callback of `run_paced`.
+    fn add_observable(&mut self, name: &str, weights: HashMap<String, f64>) -> PyResult<()> {
+        if self.species.contains_key(name) {
+            return Err(PyValueError::new_err(format!(
+                "observable `{name}` has the name of a species"
+            )));
+        }
+        for (species, weight) in &weights {
+            if !self.species.contains_key(species) {
+                return Err(PyValueError::new_err(format!(
+                    "observable `{name}` refers to unknown species `{species}`"
+                )));
+            }
+            if !weight.is_finite() {
+                return Err(PyValueError::new_err(format!(
+                    "observable `{name}` has an invalid weight for `{species}`"
+                )));
+            }
+        }
+        match self.observables.iter_mut().find(|(other, _)| other == name) {
+            Some((_, current)) => *current = weights,
+            None => self.observables.push((name.to_string(), weights)),
+        }
+        Ok(())
+    }
+    /// Observables, a dictionary of name to weights of the species by name.
+    #[getter]
+    fn observables(&self) -> HashMap<String, HashMap<String, f64>> {
+        self.observables.iter().cloned().collect()
+    }
+    /// Conservation laws of the stoichiometry, like `S + I + R` in the SIR model.
+    ///
+    /// Returns a basis of the linear combinations of the species that no reaction
+    /// changes, each as a dictionary of species name to nonzero weight.
+    fn conservation_laws(&self) -> Vec<HashMap<String, f64>> {
+        let names = self.species();
+        conservation::conservation_laws(&self.problem(&HashMap::new(), None))
+            .into_iter()
+            .map(|law| {
+                (names.iter().cloned())
+                    .zip(law.weights)
+                    .filter(|&(_, weight)| weight != 0.)
+                    .collect()
+            })
+            .collect()
+    }
+    /// Stoichiometry matrix of the system, as a list of rows.
+    ///
+    /// `matrix[s][r]` is the change of the species `s`, in the order of `species`, when the
+    /// reaction `r` fires.
+    fn stoichiometry_matrix(&self) -> Vec<Vec<isize>> {
+        self.problem(&HashMap::new(), None).stoichiometry_matrix()
+    }
+    /// Reaction network in the DOT language of Graphviz.
+    ///
+    /// The graph is bipartite: the species are ellipses and the reactions boxes, with arrows
+    /// from the reactants to the reactions and from the reactions to the products.
+    fn to_dot(&self) -> String {
+        self.model().to_dot()
+    }
+    /// Reaction network in GraphML, as the bipartite graph of `to_dot`, for network-analysis
+    /// tools like networkx.
+    fn to_graphml(&self) -> String {
+        self.model().to_graphml()
+    }
+    /// Rust source of the system, as a `define_system!` definition of a structure named
+    /// `name`, with the initial amounts of `init`.
+    ///
+    /// Pasted into a Rust program depending on rebop, it compiles into a dedicated simulator,
+    /// for the large ensembles of runs of a model prototyped in Python.
+    #[pyo3(signature = (name="Model", init=None))]
+    fn to_rust(&self, name: &str, init: Option<HashMap<String, usize>>) -> PyResult<String> {
+        let problem = self.problem(&init.unwrap_or_default(), None);
+        codegen::define_system(&problem, name, &self.species())
+            .map_err(|err| PyValueError::new_err(err.to_string()))
+    }
+    /// Run the system until `tmax` with `nb_steps` steps.
+    ///
+    /// The initial configuration is specified in the dictionary `init`.
+    /// Returns `times, vars` where `times` is an array of `nb_steps + 1` uniformly spaced time
+    /// points between `0` and `tmax`, and `vars` is a dictionary of species name to array of
+    /// values at the given time points.  One can specify a random `seed` for reproducibility.
+    /// If `nb_steps` is `0`, then returns all reactions, ending with the first that happens at
+    /// or after `tmax`.  Also returns a dictionary of metadata: the `version` of rebop, the
+    /// `seed` if any, the simulation `method`, the `model_hash` of the system and the
+    /// `wall_time` in seconds.
+    ///
+    /// Instead of `tmax` and `nb_steps`, `t_eval` can give the time points, nonnegative and
+    /// sorted but not necessarily uniformly spaced, like the sampling times of an experiment.
+    ///
+    /// The last element returned is the `State` at the end of the run.  Given as `resume`,
+    /// another run continues from it, with the current reactions of the system: the time
+    /// points then start at its time, the amounts given in `init` replace those of the state,
+    /// and the random number generator continues unless a `seed` is given.
+    ///
+    /// If `firings` is true, the result continues with the numbers of firings of each
+    /// reaction between consecutive time points, `firings[r][i]` for the reaction `r` until
+    /// the time point `i`, and `None` otherwise.  It ends with a dictionary of observable
+    /// name to array of values at the time points.
+    ///
+    /// The run can be interrupted with Ctrl-C, and raises a `TimeoutError` if it takes more
+    /// than `max_wall_time` seconds.  It releases the GIL, so that other Python threads keep
+    /// running meanwhile.  If given, `progress` is called with the simulated time and the
+    /// fraction of the simulated time span done, at most five times per second, at the
+    /// start and at the end of the run.
+    ///
+    /// `method` is the name of the simulation method, `"direct"` by default, or one of
+    /// those of `run_schedule`, like `"r_leaping:100"` for the approximate R-leaping with
+    /// 100 reactions per step, faster when many reactions fire between the time points.
+    /// Only the direct method returns every reaction, and the other methods only check for
+    /// interruptions at the time points.
+    #[pyo3(signature = (
+        init,
+        tmax=None,
+        nb_steps=None,
+        seed=None,
+        t_eval=None,
+        resume=None,
+        firings=false,
+        max_wall_time=None,
+        progress=None,
+        method="direct",
+    ))]
+    #[allow(clippy::type_complexity, clippy::too_many_arguments)]
+    fn run<'py>(
+        &self,
+        py: Python<'py>,
+        init: HashMap<String, usize>,
+        tmax: Option<f64>,
+        nb_steps: Option<usize>,
+        seed: Option<u64>,
+        t_eval: Option<Vec<f64>>,
+        resume: Option<State>,
+        firings: bool,
+        max_wall_time: Option<f64>,
+        progress: Option<PyObject>,
+        method: &str,
+    ) -> PyResult<(
+        Vec<f64>,
+        HashMap<String, Vec<isize>>,
+        Bound<'py, PyDict>,
+        State,
+        Option<Vec<Vec<u64>>>,
+        HashMap<String, Vec<f64>>,
+    )> {
+        let start = Instant::now();
+        let t0 = resume.as_ref().map_or(0., |state| state.time);
+        if tmax.is_some_and(|tmax| tmax < t0) {
+            return Err(PyValueError::new_err(format!(
+                "`tmax` is before the resumed time {t0}"
+            )));
+        }
+        let t_eval = match (t_eval, tmax, nb_steps) {
+            (Some(t_eval), None, None) => {
+                check_times(&t_eval)?;
+                if t_eval[0] < t0 {
+                    return Err(PyValueError::new_err(format!(
+                        "the time points start before the resumed time {t0}"
+                    )));
+                }
+                Some(t_eval)
+            }
+            (None, Some(tmax), Some(nb_steps)) if nb_steps > 0 => Some(
+                (0..=nb_steps)
+                    .map(|i| t0 + (tmax - t0) * i as f64 / nb_steps as f64)
+                    .collect(),
+            ),
+            (None, Some(_), Some(_)) => None,
+            _ => {
+                return Err(PyValueError::new_err(
+                    "give either `tmax` and `nb_steps`, or `t_eval`",
+                ))
+            }
+        };
+        let method: gillespie::Method = method.parse().map_err(PyValueError::new_err)?;
+        if t_eval.is_none() && method != gillespie::Method::Direct {
+            return Err(PyValueError::new_err(
+                "only the direct method returns every reaction",
+            ));
+        }
+        let mut g = match &resume {
+            Some(state) => {
+                let mut amounts: HashMap<String, usize> = state
+                    .species
+                    .iter()
+                    .map(|(name, &amount)| (name.clone(), amount as usize))
+                    .collect();
+                amounts.extend(init);
+                let mut g = self.problem(&amounts, seed);
+                g.set_time(state.time);
+                if seed.is_none() {
+                    g.set_rng(state.rng.clone());
+                }
+                g
+            }
+            None => self.problem(&init, seed),
+        };
+        let deadline = match max_wall_time {
+            Some(seconds) => Some(
+                start
+                    + Duration::try_from_secs_f64(seconds).map_err(|_| {
+                        PyValueError::new_err("`max_wall_time` must be nonnegative")
+                    })?,
+            ),
+            None => None,
+        };
+        let check = || -> PyResult<()> {
+            Python::with_gil(|py| py.check_signals())?;
+            match deadline {
+                Some(deadline) if Instant::now() > deadline => {
+                    Err(PyTimeoutError::new_err(format!(
+                        "the run took more than {} s",
+                        max_wall_time.unwrap_or_default()
+                    )))
+                }
+                _ => Ok(()),
+            }
+        };
+        let tend = match &t_eval {
+            Some(t_eval) => t_eval[t_eval.len() - 1],
+            None => tmax.expect("given with nb_steps"),
+        };
+        let mut last_report: Option<Instant> = None;
+        let mut report = |t: f64, force: bool| -> PyResult<()> {
+            let Some(progress) = &progress else {
+                return Ok(());
+            };
+            if force || last_report.is_none_or(|last| last.elapsed() >= PROGRESS_INTERVAL) {
+                let fraction = if tend > t0 {
+                    ((t - t0) / (tend - t0)).clamp(0., 1.)
+                } else {
+                    1.
+                };
+                Python::with_gil(|py| progress.call1(py, (t, fraction)))?;
+                last_report = Some(Instant::now());
+            }
+            Ok(())
+        };
+        g.set_method(method);
+        g.set_count_firings(firings);
+        let mut trajectory = gillespie::Trajectory::new(self.species.len());
+        // other Python threads run meanwhile
+        py.allow_threads(|| -> PyResult<()> {
+            report(t0, true)?;
+            if let Some(t_eval) = t_eval {
+                for t in t_eval {
+                    if method == gillespie::Method::Direct {
+                        while !g.advance_until_bounded(t, REACTIONS_BETWEEN_CHECKS) {
+                            check()?;
+                            report(g.get_time(), false)?;
+                        }
+                    } else {
+                        g.advance_until(t);
+                        check()?;
+                    }
+                    trajectory.push(&g);
+                    g.reset_firings();
+                    report(t, false)?;
+                }
+            } else {
+                // nb_steps = 0: we return every step
+                let mut rates = vec![f64::NAN; g.nb_reactions()];
+                trajectory.push(&g);
+                while g.get_time() < tend {
+                    g._advance_one_reaction(&mut rates);
+                    trajectory.push(&g);
+                    g.reset_firings();
+                    if (trajectory.times.len() as u64).is_multiple_of(REACTIONS_BETWEEN_CHECKS) {
+                        check()?;
+                        report(g.get_time(), false)?;
+                    }
+                }
+            }
+            report(tend, true)
+        })?;
+        let mut result = HashMap::new();
+        for (name, &id) in &self.species {
+            result.insert(name.clone(), std::mem::take(&mut trajectory.species[id]));
+        }
+        let metadata = metadata(py, seed, &[g.method()], &self.model_hash(), start)?;
+        let state = State {
+            time: g.get_time(),
+            species: self
+                .species
+                .iter()
+                .map(|(name, &id)| (name.clone(), g.get_species(id)))
+                .collect(),
+            rng: g.rng().clone(),
+        };
+        let firings = firings.then_some(trajectory.firings);
+        let observables = (self.observables.iter())
+            .zip(trajectory.observables)
+            .map(|((name, _), values)| (name.clone(), values))
+            .collect();
+        Ok((
+            trajectory.times,
+            result,
+            metadata,
+            state,
+            firings,
+            observables,
+        ))
+    }
+    /// Run the system until `tmax` with `nb_steps` steps, by chunks of `chunk_size` time
+    /// points.
+    ///
+    /// Returns an iterator over the chunks `times, values`, simulated as they are requested,
+    /// where `values` is the flat array of the amounts of shape `(time, species)`, with the
+    /// species in the order of `species`.  Like with `run`, `nb_steps = 0` returns every
+    /// reaction, and the iterator lets such long runs be processed or written incrementally.
+    #[pyo3(signature = (init, tmax, nb_steps, seed=None, chunk_size=100_000))]
+    fn iter_run(
+        &self,
+        init: HashMap<String, usize>,
+        tmax: f64,
+        nb_steps: usize,
+        seed: Option<u64>,
+        chunk_size: usize,
+    ) -> PyResult<RunChunks> {
+        if chunk_size == 0 {
+            return Err(PyValueError::new_err("chunks need at least one time point"));
+        }
+        Ok(RunChunks {
+            problem: self.problem(&init, seed),
+            tmax,
+            nb_steps,
+            chunk_size,
+            next: 0,
+            done: false,
+        })
+    }
+    /// Run the system until `tmax` with `nb_steps` steps, paced against the wall clock.
+    ///
+    /// The simulated time unfolds at `speed` time units per second, and `callback` is
+    /// called with the time and a dictionary of species and observable name to value at each
+    /// of the `nb_steps + 1` time points, as soon as they are reached, for example to animate
+    /// a plot.  The simulation stops early if `callback` returns `False`.  The initial
+    /// configuration is specified in the dictionary `init`.
+    #[pyo3(signature = (init, tmax, nb_steps, speed, callback, seed=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn run_paced(
+        &self,
+        py: Python<'_>,
+        init: HashMap<String, usize>,
+        tmax: f64,
+        nb_steps: usize,
+        speed: f64,
+        callback: PyObject,
+        seed: Option<u64>,
+    ) -> PyResult<()> {
+        if nb_steps == 0 {
+            return Err(PyValueError::new_err("paced runs need at least one step"));
+        }
+        if speed.is_nan() || speed <= 0. {
+            return Err(PyValueError::new_err("the speed must be positive"));
+        }
+        let mut g = self.problem(&init, seed);
+        let observables: Vec<_> = (self.observables.iter())
+            .map(|(name, _)| (name, g.observable_fn(name).unwrap()))
+            .collect();
+        let flow = py.allow_threads(|| {
+            pacing::run_paced(&mut g, tmax, nb_steps, speed, |t, x| {
+                let go_on = Python::with_gil(|py| -> PyResult<bool> {
+                    let state = PyDict::new(py);
+                    for (name, &id) in &self.species {
+                        state.set_item(name, x[id])?;
+                    }
+                    for (name, value) in &observables {
+                        state.set_item(name, value(x))?;
+                    }
+                    let result = callback.call1(py, (t, state))?;
+                    py.check_signals()?;
+                    Ok(result.extract::<bool>(py).ok() != Some(false))
+                });
+                match go_on {
+                    Ok(true) => ControlFlow::Continue(()),
+                    Ok(false) => ControlFlow::Break(Ok(())),
+                    Err(err) => ControlFlow::Break(Err(err)),
+                }
+            })
+        });
+        match flow {
+            ControlFlow::Break(result) => result,
+            ControlFlow::Continue(()) => Ok(()),
+        }
+    }
+    /// Evaluate scalar summaries of the system for a batch of rate vectors.
+    ///
+    /// Each element of `rates` gives the rates of all reactions, in the order in which they
+    /// were added.  For each of them, `nb_runs` simulations start from the configuration
+    /// `init`, and each summary is computed over them.  A summary is a dictionary with a
+    /// `type` and a `species` name: `"mean"` and `"variance"` of its amount at `time`, or
+    /// `"time_to_threshold"`, the mean time until its amount reaches `threshold`, counting
+    /// the simulations where it does not happen before `tmax` as `tmax`.  Returns one list of
+    /// summary values per rate vector.  All simulations run in parallel, and the rate vectors
+    /// share the same seeds, so that the summaries vary smoothly with the rates.
+    #[pyo3(signature = (init, rates, summaries, nb_runs, seed=None))]
+    fn evaluate(
+        &self,
+        py: Python<'_>,
+        init: HashMap<String, usize>,
+        rates: Vec<Vec<f64>>,
+        summaries: Vec<Bound<'_, PyDict>>,
+        nb_runs: usize,
+        seed: Option<u64>,
+    ) -> PyResult<Vec<Vec<f64>>> {
+        if nb_runs == 0 {
+            return Err(PyValueError::new_err("no simulation to run"));
+        }
+        if let Some(i) = rates.iter().position(|r| r.len() != self.reactions.len()) {
+            return Err(PyValueError::new_err(format!(
+                "rate vector #{i} does not have one rate per reaction"
+            )));
+        }
+        let summaries = summaries
+            .iter()
+            .enumerate()
+            .map(|(isummary, summary)| {
+                let get = |key: &str| {
+                    summary.get_item(key)?.ok_or_else(|| {
+                        PyValueError::new_err(format!("summary #{isummary} has no `{key}`"))
+                    })
+                };
+                let name: String = get("species")?.extract()?;
+                let species = *self.species.get(&name).ok_or_else(|| {
+                    PyValueError::new_err(format!("summary #{isummary}: unknown species `{name}`"))
+                })?;
+                match get("type")?.extract::<String>()?.as_str() {
+                    "mean" => Ok(objectives::Summary::Mean {
+                        species,
+                        time: get("time")?.extract()?,
+                    }),
+                    "variance" => Ok(objectives::Summary::Variance {
+                        species,
+                        time: get("time")?.extract()?,
+                    }),
+                    "time_to_threshold" => Ok(objectives::Summary::TimeToThreshold {
+                        species,
+                        threshold: get("threshold")?.extract()?,
+                        tmax: get("tmax")?.extract()?,
+                    }),
+                    other => Err(PyValueError::new_err(format!(
+                        "summary #{isummary} has an unknown type `{other}`"
+                    ))),
+                }
+            })
+            .collect::<PyResult<Vec<_>>>()?;
+        let problem = self.problem(&init, None);
+        let make = |rates: &[f64]| {
+            let mut g = problem.clone();
+            for (ireaction, (rate, (_, reactants, _))) in
+                rates.iter().zip(&self.reactions).enumerate()
+            {
+                g.set_rate(ireaction, self.rate(*rate, reactants));
+            }
+            g
+        };
+        let seed = seed.unwrap_or_else(rand::random);
+        Ok(py.allow_threads(|| objectives::evaluate(make, &rates, &summaries, nb_runs, seed)))
+    }
+    /// Run `nb_runs` independent simulations until `tmax` with `nb_steps` steps.
+    ///
+    /// The simulations are spread over all available threads.  Returns `times, vars,
+    /// metadata` like `run`, where `vars` maps each species name to one array of values per
+    /// simulation.  For a given `seed`, the results do not depend on the number of threads.
+    /// If given, `progress` is called with the numbers of completed and total simulations at
+    /// most five times per second, and once all simulations are completed.
+    ///
+    /// If the trajectories would take more than `max_memory` bytes, they are not kept and
+    /// `vars` maps each species name to its mean and standard deviation over the simulations
+    /// instead.  The `mode` of the metadata is then `"summary"` instead of `"trajectories"`.
+    #[pyo3(signature = (init, tmax, nb_steps, nb_runs, seed=None, progress=None, max_memory=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn run_ensemble<'py>(
+        &self,
+        py: Python<'py>,
+        init: HashMap<String, usize>,
+        tmax: f64,
+        nb_steps: usize,
+        nb_runs: usize,
+        seed: Option<u64>,
+        progress: Option<PyObject>,
+        max_memory: Option<usize>,
+    ) -> PyResult<(Vec<f64>, Bound<'py, PyDict>, Bound<'py, PyDict>)> {
+        if nb_steps == 0 {
+            return Err(PyValueError::new_err("ensembles need at least one step"));
+        }
+        let start = Instant::now();
+        let problem = self.problem(&init, None);
+        let base_seed = seed.unwrap_or_else(rand::random);
+        let times: Vec<f64> = (0..=nb_steps)
+            .map(|i| tmax * i as f64 / nb_steps as f64)
+            .collect();
+        let projected_memory = nb_runs
+            .saturating_mul(self.species.len())
+            .saturating_mul(times.len() * std::mem::size_of::<isize>());
+        let summarize = max_memory.is_some_and(|max_memory| projected_memory > max_memory);
+        let output = py.allow_threads(|| {
+            let (sender, receiver) = mpsc::channel();
+            thread::scope(|scope| {
+                // the runs are simulated in the background while the
+                // progress is reported
+                let (problem, times) = (&problem, &times);
+                let runs = scope.spawn(move || {
+                    inference::parallel_chunks(nb_runs, |runs| {
+                        let sender = sender.clone();
+                        let mut output = EnsembleOutput::new(summarize);
+                        let mut workspace = gillespie::Workspace::default();
+                        for irun in runs {
+                            let mut g = problem.clone().with_workspace(workspace);
+                            g.seed(rng::Philox::new(base_seed, irun as u64).next_u64());
+                            // run[s][i] is the amount of species s at times[i]
+                            let mut run = vec![Vec::with_capacity(times.len()); g.nb_species()];
+                            for &t in times {
+                                g.advance_until(t);
+                                for (s, values) in run.iter_mut().enumerate() {
+                                    values.push(g.get_species(s));
+                                }
+                            }
+                            output.add(run);
+                            workspace = g.take_workspace();
+                            // the receiver is only dropped if the callback failed
+                            if sender.send(()).is_err() {
+                                break;
+                            }
+                        }
+                        output
+                    })
+                });
+                report_progress(progress.as_ref(), nb_runs, receiver)?;
+                let mut output = EnsembleOutput::new(summarize);
+                for chunk in runs.join().expect("a simulation panicked") {
+                    output.merge(chunk);
+                }
+                Ok::<_, PyErr>(output)
+            })
+        })?;
+        let result = PyDict::new(py);
+        for (name, &id) in &self.species {
+            match &output {
+                EnsembleOutput::Trajectories(runs) => {
+                    let values: Vec<&[isize]> = runs.iter().map(|run| &run[id][..]).collect();
+                    result.set_item(name, values)?;
+                }
+                EnsembleOutput::Summary { .. } => result.set_item(name, output.summary(id))?,
+            }
+        }
+        let metadata = metadata(py, seed, &[problem.method()], &self.model_hash(), start)?;
+        let mode = if summarize { "summary" } else { "trajectories" };
+        metadata.set_item("mode", mode)?;
+        Ok((times, result, metadata))
+    }
+    /// Run `nb_replicates` simulations until `tmax` with `nb_steps` steps for each combination
+    /// of rates of `params_grid`.
+    ///
+    /// `params_grid` maps the index of a reaction to the list of its rates to try, and the
+    /// other reactions keep their rates.  The combinations are those of the cartesian product,
+    /// by increasing reaction index with the last one varying the fastest, and all
+    /// simulations run in parallel.  The replicate `r` of every combination uses the same
+    /// seed, so that the results vary smoothly with the rates.  Returns `times, reactions,
+    /// combos, values, metadata`, where `combos[c]` gives the rates of the `reactions` in the
+    /// combination `c`, and `values` is the flat array of the amounts of shape `(combo,
+    /// replicate, time, species)`, with the species in the order of `species`.
+    #[pyo3(signature = (init, params_grid, tmax, nb_steps, nb_replicates, seed=None))]
+    #[allow(clippy::type_complexity, clippy::too_many_arguments)]
+    fn sweep<'py>(
+        &self,
+        py: Python<'py>,
+        init: HashMap<String, usize>,
+        params_grid: BTreeMap<usize, Vec<f64>>,
+        tmax: f64,
+        nb_steps: usize,
+        nb_replicates: usize,
+        seed: Option<u64>,
+    ) -> PyResult<(
+        Vec<f64>,
+        Vec<usize>,
+        Vec<Vec<f64>>,
+        Vec<isize>,
+        Bound<'py, PyDict>,
+    )> {
+        if nb_steps == 0 {
+            return Err(PyValueError::new_err("sweeps need at least one step"));
+        }
+        if nb_replicates == 0 {
+            return Err(PyValueError::new_err("no simulation to run"));
+        }
+        for (&ireaction, rates) in &params_grid {
+            self.check_reaction(ireaction)?;
+            if rates.is_empty() {
+                return Err(PyValueError::new_err(format!(
+                    "no rate to try for reaction {ireaction}"
+                )));
+            }
+        }
+        let start = Instant::now();
+        let reactions: Vec<usize> = params_grid.keys().copied().collect();
+        let mut combos = vec![Vec::new()];
+        for rates in params_grid.values() {
+            combos = combos
+                .iter()
+                .flat_map(|combo| {
+                    rates
+                        .iter()
+                        .map(move |&rate| [&combo[..], &[rate]].concat())
+                })
+                .collect();
+        }
+        let problem = self.problem(&init, None);
+        let base_seed = seed.unwrap_or_else(rand::random);
+        let times: Vec<f64> = (0..=nb_steps)
+            .map(|i| tmax * i as f64 / nb_steps as f64)
+            .collect();
+        // values of a simulation, by time point then by species
+        let block = times.len() * self.species.len();
+        let nb_runs = combos.len() * nb_replicates;
+        let values = py.allow_threads(|| {
+            inference::parallel_map(nb_runs, |irun| {
+                let (combo, replicate) = (irun / nb_replicates, irun % nb_replicates);
+                let mut g = problem.clone();
+                for (&ireaction, &rate) in reactions.iter().zip(&combos[combo]) {
+                    let reactants = &self.reactions[ireaction].1;
+                    g.set_rate(ireaction, self.rate(rate, reactants));
+                }
+                g.seed(rng::Philox::new(base_seed, replicate as u64).next_u64());
+                let mut run = Vec::with_capacity(block);
+                for &t in &times {
+                    g.advance_until(t);
+                    run.extend_from_slice(g.species());
+                }
+                run
+            })
+            .concat()
+        });
+        let metadata = metadata(py, seed, &[problem.method()], &self.model_hash(), start)?;
+        Ok((times, reactions, combos, values, metadata))
+    }
+    /// Run the system through consecutive phases.
+    ///
+    /// The initial configuration is specified in the dictionary `init`.
+    /// Each phase is a dictionary with the times `start` and `end` of the
+    /// phase, the number of steps `nb_steps`, and optionally `rates`, a
+    /// dictionary from the index of a reaction to its rate during the
+    /// phase, and `method`, the name of the simulation method (`"direct"`,
+    /// `"first_reaction"`, `"next_reaction"`, `"sorting_direct"`,
+    /// `"r_leaping:100"` for R-leaping with 100 reactions per step, or
+    /// `"slow_scale"` with automatically detected fast pairs).  Each
+    /// phase must start when the previous one ends.  Returns `times, vars,
+    /// metadata` like `run`, with the state at the start of the first phase
+    /// and at `nb_steps` uniformly spaced time points in each phase, and
+    /// the methods of the phases separated by commas in the metadata.
+    #[pyo3(signature = (init, phases, seed=None))]
+    #[allow(clippy::type_complexity)]
+    fn run_schedule<'py>(
+        &self,
+        py: Python<'py>,
+        init: HashMap<String, usize>,
+        phases: Vec<Bound<'py, PyDict>>,
+        seed: Option<u64>,
+    ) -> PyResult<(Vec<f64>, HashMap<String, Vec<isize>>, Bound<'py, PyDict>)> {
+        if phases.is_empty() {
+            return Err(PyValueError::new_err("schedule has no phase"));
+        }
+        let start_time = Instant::now();
+        let mut methods = Vec::new();
+        let mut g = self.problem(&init, seed);
+        let mut times = Vec::new();
+        let mut species = vec![Vec::new(); self.species.len()];
+        let mut previous_end = None;
+        for (iphase, phase) in phases.iter().enumerate() {
+            let get = |key: &str| {
+                phase
+                    .get_item(key)?
+                    .ok_or_else(|| PyValueError::new_err(format!("phase #{iphase} has no `{key}`")))
+            };
+            let start: f64 = get("start")?.extract()?;
+            let end: f64 = get("end")?.extract()?;
+            let nb_steps: usize = get("nb_steps")?.extract()?;
+            let rates: HashMap<usize, f64> = match phase.get_item("rates")? {
+                Some(rates) => rates.extract()?,
+                None => HashMap::new(),
+            };
+            let method = match phase.get_item("method")? {
+                Some(method) => method
+                    .extract::<String>()?
+                    .parse()
+                    .map_err(PyValueError::new_err)?,
+                None => gillespie::Method::default(),
+            };
+            if !(start <= end && end.is_finite()) || nb_steps == 0 {
+                return Err(PyValueError::new_err(format!(
+                    "phase #{iphase} needs a time interval and at least one step"
+                )));
+            }
+            if let Some(ireaction) = rates.keys().find(|&&i| i >= self.reactions.len()) {
+                return Err(PyValueError::new_err(format!(
+                    "phase #{iphase} refers to unknown reaction {ireaction}"
+                )));
+            }
+            match previous_end {
+                None => {
+                    g.set_time(start);
+                    times.push(start);
+                    for (s, values) in species.iter_mut().enumerate() {
+                        values.push(g.get_species(s));
+                    }
+                }
+                Some(previous_end) if previous_end != start => {
+                    return Err(PyValueError::new_err(format!(
+                        "phase #{iphase} starts at {start} but the previous one ends at {previous_end}"
+                    )))
+                }
+                Some(_) => (),
+            }
+            previous_end = Some(end);
+            for (ireaction, (rate, reactants, _)) in self.reactions.iter().enumerate() {
+                let rate = rates.get(&ireaction).unwrap_or(rate);
+                g.set_rate(ireaction, self.rate(*rate, reactants));
+            }
+            g.set_method(method);
+            methods.push(method);
+            for i in 1..=nb_steps {
+                let t = if i == nb_steps {
+                    end
+                } else {
+                    start + (end - start) * i as f64 / nb_steps as f64
+                };
+                g.advance_until(t);
+                times.push(t);
+                for (s, values) in species.iter_mut().enumerate() {
+                    values.push(g.get_species(s));
+                }
+            }
+        }
+        let mut result = HashMap::new();
+        for (name, &id) in &self.species {
+            result.insert(name.clone(), species[id].clone());
+        }
+        let metadata = metadata(py, seed, &methods, &self.model_hash(), start_time)?;
+        Ok((times, result, metadata))
+    }
+    /// Integrate the mean-field ordinary differential equations of the system until `tmax`
+    /// with `nb_steps` steps.
+    ///
+    /// The initial configuration is specified in the dictionary `init`, and `params` can
+    /// override the rates of some reactions, by index.  Each reaction of rate `k` occurs at
+    /// the deterministic rate `k` times the product of the amounts of its reactants, with
+    /// their multiplicities, which the stochastic rates approach for large amounts.  Returns
+    /// `times, vars, metadata` like `run`, with real amounts, and the `method` `"ode"`.
+    #[pyo3(signature = (init, tmax, nb_steps, params=None))]
+    #[allow(clippy::type_complexity)]
+    fn run_ode<'py>(
+        &self,
+        py: Python<'py>,
+        init: HashMap<String, usize>,
+        tmax: f64,
+        nb_steps: usize,
+        params: Option<HashMap<usize, f64>>,
+    ) -> PyResult<(Vec<f64>, HashMap<String, Vec<f64>>, Bound<'py, PyDict>)> {
+        if nb_steps == 0 {
+            return Err(PyValueError::new_err("ODE runs need at least one step"));
+        }
+        if !(tmax >= 0. && tmax.is_finite()) {
+            return Err(PyValueError::new_err(
+                "`tmax` must be nonnegative and finite",
+            ));
+        }
+        let params = params.unwrap_or_default();
+        for &ireaction in params.keys() {
+            self.check_reaction(ireaction)?;
+        }
+        let start = Instant::now();
+        // rate, indices of the reactants with multiplicities, and jump
+        let reactions: Vec<(f64, Vec<usize>, Vec<f64>)> = self
+            .reactions
+            .iter()
+            .enumerate()
+            .map(|(ireaction, (rate, reactants, products))| {
+                let reactants: Vec<usize> = reactants.iter().map(|r| self.species[r]).collect();
+                let mut jump = vec![0.; self.species.len()];
+                for &r in &reactants {
+                    jump[r] -= 1.;
+                }
+                for product in products {
+                    jump[self.species[product]] += 1.;
+                }
+                (*params.get(&ireaction).unwrap_or(rate), reactants, jump)
+            })
+            .collect();
+        let mut y0 = vec![0.; self.species.len()];
+        for (name, &value) in &init {
+            if let Some(&id) = self.species.get(name) {
+                y0[id] = value as f64;
+            }
+        }
+        let times: Vec<f64> = (0..=nb_steps)
+            .map(|i| tmax * i as f64 / nb_steps as f64)
+            .collect();
+        let derivatives = |_: f64, y: &[f64], dy: &mut [f64]| {
+            dy.fill(0.);
+            for (rate, reactants, jump) in &reactions {
+                let flux = reactants.iter().fold(*rate, |acc, &r| acc * y[r]);
+                for (d, j) in dy.iter_mut().zip(jump) {
+                    *d += flux * j;
+                }
+            }
+        };
+        let values = py
+            .allow_threads(|| ode::integrate(derivatives, &y0, &times, 1e-8))
+            .map_err(|t| {
+                PyValueError::new_err(format!(
+                    "the integration failed at time {t}, the solution may blow up"
+                ))
+            })?;
+        let mut result = HashMap::new();
+        for (name, &id) in &self.species {
+            result.insert(name.clone(), values.iter().map(|y| y[id]).collect());
+        }
+        let metadata = metadata(py, None, &[], &self.model_hash(), start)?;
+        metadata.set_item("method", "ode")?;
+        Ok((times, result, metadata))
+    }
+    /// Canonical hash of the species and reactions of the system, in
+    /// hexadecimal.
+    ///
+    /// It does not depend on the order in which the species and the
+    /// reactions were added, nor on the platform, and is stored in the
+    /// metadata of the simulations as `model_hash`.
+    fn model_hash(&self) -> String {
+        let reactions: Vec<_> = self
+            .reactions
+            .iter()
+            .map(|(rate, reactants, products)| (*rate, count(reactants), count(products)))
+            .collect();
+        model::network_hash(
+            self.species.keys().map(String::as_str),
+            [],
+            reactions.iter().map(|(rate, reactants, products)| {
+                (reactants, products, model::CanonicalRate::Value(*rate))
+            }),
+        )
+    }
+    /// State of the system for `pickle`: the species in order, the reactions as tuples
+    /// `(rate, reactants, products)`, the initial configuration, and the observables as
+    /// tuples `(name, weights)`.
+    #[allow(clippy::type_complexity)]
+    fn __getstate__(
+        &self,
+    ) -> (
+        Vec<String>,
+        Vec<(f64, Vec<String>, Vec<String>)>,
+        HashMap<String, usize>,
+        Vec<(String, HashMap<String, f64>)>,
+    ) {
+        (
+            self.species(),
+            self.reactions.clone(),
+            self.init.clone(),
+            self.observables.clone(),
+        )
+    }
+    #[allow(clippy::type_complexity)]
+    fn __setstate__(
+        &mut self,
+        state: (
+            Vec<String>,
+            Vec<(f64, Vec<String>, Vec<String>)>,
+            HashMap<String, usize>,
+            Vec<(String, HashMap<String, f64>)>,
+        ),
+    ) -> PyResult<()> {
+        let (species, reactions, init, observables) = state;
+        let nb_species = species.len();
+        let species: HashMap<String, usize> = species
+            .into_iter()
+            .enumerate()
+            .map(|(id, name)| (name, id))
+            .collect();
+        let known = |names: &[String]| names.iter().all(|name| species.contains_key(name));
+        if species.len() != nb_species
+            || !reactions.iter().all(|(_, r, p)| known(r) && known(p))
+            || !observables
+                .iter()
+                .all(|(_, weights)| weights.keys().all(|name| species.contains_key(name)))
+        {
+            return Err(PyValueError::new_err("invalid state of a system"));
+        }
+        *self = Gillespie {
+            species,
+            reactions,
+            init,
+            observables,
+        };
+        Ok(())
+    }
+    fn __copy__(&self) -> Self {
+        self.clone()
+    }
+    fn __deepcopy__(&self, _memo: &Bound<'_, PyDict>) -> Self {
+        self.clone()
+    }
+    fn __str__(&self) -> PyResult<String> {
+        let mut s = format!(
+            "{} species and {} reactions\n",
+            self.species.len(),
+            self.reactions.len()
+        );
+        for (rate, reactants, products) in &self.reactions {
+            s.push_str(&reactants.join(" + "));
+            s.push_str(" --> ");
+            s.push_str(&products.join(" + "));
+            s.push_str(&format!(" @ {}\n", rate));
+        }
+        Ok(s)
+    }
+}
+
+impl Gillespie {
+    /// Checks that there is a reaction of index `index`.
+    fn check_reaction(&self, index: usize) -> PyResult<()> {
+        if index < self.reactions.len() {
+            Ok(())
+        } else {
+            Err(PyIndexError::new_err(format!(
+                "unknown reaction of index {index}, the system has {} reactions",
+                self.reactions.len()
+            )))
+        }
+    }
+    /// Law of mass action rate `rate` with the reactants `reactants`.
+    fn rate(&self, rate: f64, reactants: &[String]) -> gillespie::Rate {
+        let mut vreactants = vec![0; self.species.len()];
+        for reactant in reactants {
+            vreactants[self.species[reactant]] += 1;
+        }
+        gillespie::Rate::lma(rate, vreactants)
+    }
+    /// Builds the model of the system, with its initial configuration
+    /// and its reactions.
+    fn model(&self) -> model::Model {
+        let mut m = model::Model::new();
+        for name in self.species() {
+            m.species.push(model::Species {
+                initial: self.init.get(&name).map_or(0, |&n| n as isize),
+                name,
+                annotations: model::Annotations::new(),
+            });
+        }
+        for (rate, reactants, products) in &self.reactions {
+            m.reactions.push(model::Reaction {
+                name: None,
+                reactants: count(reactants),
+                products: count(products),
+                rate: model::RateSpec::Value(*rate),
+                annotations: model::Annotations::new(),
+            });
+        }
+        m
+    }
+    /// Builds the problem of the function-based API, with the initial
+    /// configuration `init`.
+    fn problem(&self, init: &HashMap<String, usize>, seed: Option<u64>) -> gillespie::Gillespie {
+        let mut x0 = vec![0; self.species.len()];
+        for (name, &value) in init {
+            if let Some(&id) = self.species.get(name) {
+                x0[id] = value as isize;
+            }
+        }
+        let mut g = match seed {
+            Some(seed) => gillespie::Gillespie::new_with_seed(x0, seed),
+            None => gillespie::Gillespie::new(x0),
+        };
+        for (rate, reactants, products) in self.reactions.iter() {
+            let mut actions = vec![0; self.species.len()];
+            for reactant in reactants {
+                actions[self.species[reactant]] -= 1;
+            }
+            for product in products {
+                actions[self.species[product]] += 1;
+            }
+            g.add_reaction(self.rate(*rate, reactants), actions);
+        }
+        for (name, weights) in &self.observables {
+            let mut vweights = vec![0.; self.species.len()];
+            for (species, &weight) in weights {
+                vweights[self.species[species]] = weight;
+            }
+            g.add_observable(name, vweights);
+        }
+        g
+    }
+}
+
+/// Checks that the time points `times` are given, nonnegative, finite and
+/// sorted.
+fn check_times(times: &[f64]) -> PyResult<()> {
+    let valid = !times.is_empty()
+        && times[0] >= 0.
+        && times.iter().all(|t| t.is_finite())
+        && times.windows(2).all(|w| w[0] <= w[1]);
+    if valid {
+        Ok(())
+    } else {
+        Err(PyValueError::new_err(
+            "the time points must be nonnegative, finite and sorted",
+        ))
+    }
+}
+
+/// Stoichiometries of the species of `names`, the inverse of [`expand`].
+fn count(names: &[String]) -> BTreeMap<String, u32> {
+    let mut counts = BTreeMap::new();
+    for name in names {
+        *counts.entry(name.clone()).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Species names repeated according to their stoichiometries.
+fn expand(stoichiometries: &BTreeMap<String, u32>) -> Vec<String> {
+    stoichiometries
+        .iter()
+        .flat_map(|(name, &n)| std::iter::repeat_n(name.clone(), n as usize))
+        .collect()
+}
+
+/// Metadata of a simulation of the model of hash `model_hash` that
+/// started at `start`, as a dictionary.
+fn metadata<'py>(
+    py: Python<'py>,
+    seed: Option<u64>,
+    methods: &[gillespie::Method],
+    model_hash: &str,
+    start: Instant,
+) -> PyResult<Bound<'py, PyDict>> {
+    let metadata = PyDict::new(py);
+    metadata.set_item("version", env!("CARGO_PKG_VERSION"))?;
+    if let Some(seed) = seed {
+        metadata.set_item("seed", seed)?;
+    }
+    let methods: Vec<String> = methods.iter().map(|m| m.to_string()).collect();
+    metadata.set_item("method", methods.join(","))?;
+    metadata.set_item("model_hash", model_hash)?;
+    metadata.set_item("wall_time", start.elapsed().as_secs_f64())?;
+    Ok(metadata)
+}
+
+/// Output of simulations of an ensemble.
+enum EnsembleOutput {
+    /// `runs[r][s][i]` is the amount of species `s` at the time point `i`
+    /// in the simulation `r`.
+    Trajectories(Vec<Vec<Vec<isize>>>),
+    /// Exact sums of the amounts and of their squares, by species and time
+    /// point, over `nb_runs` simulations.
+    Summary {
+        nb_runs: usize,
+        sums: Vec<Vec<(i128, i128)>>,
+    },
+}
+
+impl EnsembleOutput {
+    /// Creates an empty output, that only keeps the sums if `summarize`.
+    fn new(summarize: bool) -> Self {
+        if summarize {
+            EnsembleOutput::Summary {
+                nb_runs: 0,
+                sums: Vec::new(),
+            }
+        } else {
+            EnsembleOutput::Trajectories(Vec::new())
+        }
+    }
+    /// Adds the amounts `run[s][i]` of a simulation.
+    fn add(&mut self, run: Vec<Vec<isize>>) {
+        match self {
+            EnsembleOutput::Trajectories(runs) => runs.push(run),
+            EnsembleOutput::Summary { nb_runs, sums } => {
+                if sums.is_empty() {
+                    *sums = vec![vec![(0, 0); run[0].len()]; run.len()];
+                }
+                for (species_sums, values) in sums.iter_mut().zip(run) {
+                    for ((sum, sum_squares), value) in species_sums.iter_mut().zip(values) {
+                        *sum += value as i128;
+                        *sum_squares += value as i128 * value as i128;
+                    }
+                }
+                *nb_runs += 1;
+            }
+        }
+    }
+    /// Adds the simulations of `other`, after those of `self`.
+    fn merge(&mut self, other: Self) {
+        match (self, other) {
+            (EnsembleOutput::Trajectories(runs), EnsembleOutput::Trajectories(others)) => {
+                runs.extend(others);
+            }
+            (
+                EnsembleOutput::Summary { nb_runs, sums },
+                EnsembleOutput::Summary {
+                    nb_runs: other_nb_runs,
+                    sums: other_sums,
+                },
+            ) => {
+                if sums.is_empty() {
+                    *sums = other_sums;
+                } else if !other_sums.is_empty() {
+                    for (species_sums, others) in sums.iter_mut().zip(other_sums) {
+                        for (s, o) in species_sums.iter_mut().zip(others) {
+                            *s = (s.0 + o.0, s.1 + o.1);
+                        }
+                    }
+                }
+                *nb_runs += other_nb_runs;
+            }
+            _ => unreachable!("outputs of different kinds"),
+        }
+    }
+    /// Returns the means and the standard deviations over time of the
+    /// species of index `s`, for a summary.
+    fn summary(&self, s: usize) -> [Vec<f64>; 2] {
+        let EnsembleOutput::Summary { nb_runs, sums } = self else {
+            unreachable!("trajectories are not summarized");
+        };
+        let n = *nb_runs as i128;
+        let mut means = Vec::new();
+        let mut stds = Vec::new();
+        for &(sum, sum_squares) in sums.get(s).into_iter().flatten() {
+            means.push(sum as f64 / n as f64);
+            // computed exactly, then rounded
+            let variance = (n * sum_squares - sum * sum) as f64 / (n * (n - 1)) as f64;
+            stds.push(variance.sqrt());
+        }
+        [means, stds]
+    }
+}
+
+/// Calls `progress`, if any, with the numbers of completed and total
+/// simulations as `receiver` is notified of completed simulations, at
+/// most every `PROGRESS_INTERVAL` and once they are all completed.
+fn report_progress(
+    progress: Option<&PyObject>,
+    nb_runs: usize,
+    receiver: mpsc::Receiver<()>,
+) -> PyResult<()> {
+    let Some(progress) = progress else {
+        for () in receiver {}
+        return Ok(());
+    };
+    let report = |completed: usize| Python::with_gil(|py| progress.call1(py, (completed, nb_runs)));
+    report(0)?;
+    let (mut completed, mut reported) = (0, 0);
+    let mut last_report = Instant::now();
+    while completed < nb_runs {
+        match receiver.recv_timeout(PROGRESS_INTERVAL.saturating_sub(last_report.elapsed())) {
+            Ok(()) => completed += 1,
+            Err(RecvTimeoutError::Timeout) => (),
+            // a simulation panicked, the panic is propagated by the caller
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        if completed > reported
+            && (completed == nb_runs || last_report.elapsed() >= PROGRESS_INTERVAL)
+        {
+            report(completed)?;
+            reported = completed;
+            last_report = Instant::now();
+        }
+    }
+    Ok(())
+}
+
+// the Rust name `rebop` designates this crate, see `lib.rs`
+#[pymodule]
+#[pyo3(name = "rebop")]
+fn rebop_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    m.add_class::<Gillespie>()?;
+    m.add_class::<Reaction>()?;
+    m.add_class::<State>()?;
+    m.add_class::<RunChunks>()?;
+    Ok(())
+}
@@ -0,0 +1,172 @@
+//! Counter-based random number generators.
+//!
+//! A [`Philox`] generator computes its numbers from a counter with a
+//! keyed bijection, so that any number of independent streams can be
+//! derived from a seed without any shared state.  The replicate `i` of
+//! an ensemble simulated with the stream `(seed, i)` is the same
+//! whatever the number of replicates and the order in which they are
+//! run, for example in parallel:
+//!
+//! ```
+//! use rebop::gillespie::{Gillespie, Rate};
+//! use rebop::rng::Philox;
+//! let replicate = |i| {
+//!     let mut p = Gillespie::with_rng([0], Philox::new(42, i));
+//!     p.add_reaction(Rate::lma(10., [0]), [1]);
+//!     p.advance_until(1.);
+//!     p.get_species(0)
+//! };
+//! let forward: Vec<isize> = (0..10).map(replicate).collect();
+//! let backward: Vec<isize> = (0..10).rev().map(replicate).collect();
+//! assert!(forward.iter().eq(backward.iter().rev()));
+//! ```
+
+use rand::{Error, RngCore, SeedableRng};
+
+const MULTIPLIERS: [u32; 2] = [0xD251_1F53, 0xCD9E_8D57];
+const WEYL: [u32; 2] = [0x9E37_79B9, 0xBB67_AE85];
+const ROUNDS: usize = 10;
+
+/// Philox4x32-10 generator of Salmon et al. (2011), with a 64-bit key
+/// and a 128-bit counter.
+///
+/// The key is the seed and the upper half of the counter is the stream,
+/// which leaves 2⁶⁴ blocks of four 32-bit numbers for each stream.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Philox {
+    key: [u32; 2],
+    counter: [u32; 4],
+    block: [u32; 4],
+    index: usize,
+}
+
+impl Philox {
+    /// Creates the generator of the stream `stream` derived from `seed`,
+    /// for example the index of a replicate.
+    pub fn new(seed: u64, stream: u64) -> Self {
+        Philox {
+            key: [seed as u32, (seed >> 32) as u32],
+            counter: [0, 0, stream as u32, (stream >> 32) as u32],
+            block: [0; 4],
+            index: 4,
+        }
+    }
+    /// Returns the stream of the generator.
+    pub fn stream(&self) -> u64 {
+        u64::from(self.counter[2]) | u64::from(self.counter[3]) << 32
+    }
+    /// Computes the block of the current counter and increments it.
+    fn refill(&mut self) {
+        self.block = philox(self.counter, self.key);
+        self.index = 0;
+        let (low, carry) = self.counter[0].overflowing_add(1);
+        self.counter[0] = low;
+        self.counter[1] = self.counter[1].wrapping_add(u32::from(carry));
+    }
+}
+
+/// Philox4x32-10 bijection of `counter` with `key`.
+fn philox(mut counter: [u32; 4], mut key: [u32; 2]) -> [u32; 4] {
+    for round in 0..ROUNDS {
+        if round > 0 {
+            key[0] = key[0].wrapping_add(WEYL[0]);
+            key[1] = key[1].wrapping_add(WEYL[1]);
+        }
+        let product0 = u64::from(MULTIPLIERS[0]) * u64::from(counter[0]);
+        let product1 = u64::from(MULTIPLIERS[1]) * u64::from(counter[2]);
+        counter = [
+            (product1 >> 32) as u32 ^ counter[1] ^ key[0],
+            product1 as u32,
+            (product0 >> 32) as u32 ^ counter[3] ^ key[1],
+            product0 as u32,
+        ];
+    }
+    counter
+}
+
+impl RngCore for Philox {
+    fn next_u32(&mut self) -> u32 {
+        if self.index == 4 {
+            self.refill();
+        }
+        self.index += 1;
+        self.block[self.index - 1]
+    }
+    fn next_u64(&mut self) -> u64 {
+        u64::from(self.next_u32()) | u64::from(self.next_u32()) << 32
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            chunk.copy_from_slice(&self.next_u32().to_le_bytes()[..chunk.len()]);
+        }
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl SeedableRng for Philox {
+    /// The seed, then the stream, in little endian.
+    type Seed = [u8; 16];
+    fn from_seed(seed: Self::Seed) -> Self {
+        let (seed, stream) = seed.split_at(8);
+        Philox::new(
+            u64::from_le_bytes(seed.try_into().unwrap()),
+            u64::from_le_bytes(stream.try_into().unwrap()),
+        )
+    }
+    /// Creates the generator of the stream 0 derived from `seed`.
+    fn seed_from_u64(seed: u64) -> Self {
+        Philox::new(seed, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, RngCore, SeedableRng};
+
+    use crate::rng::{philox, Philox};
+    #[test]
+    fn known_answers() {
+        // test vectors of the Random123 library
+        assert_eq!(
+            philox([0; 4], [0; 2]),
+            [0x6627_e8d5, 0xe169_c58d, 0xbc57_ac4c, 0x9b00_dbd8]
+        );
+        assert_eq!(
+            philox([u32::MAX; 4], [u32::MAX; 2]),
+            [0x408f_276d, 0x41c8_3b0e, 0xa20b_c7c6, 0x6d54_51fd]
+        );
+        assert_eq!(
+            philox(
+                [0x243f_6a88, 0x85a3_08d3, 0x1319_8a2e, 0x0370_7344],
+                [0xa409_3822, 0x299f_31d0]
+            ),
+            [0xd16c_fe09, 0x94fd_cceb, 0x5001_e420, 0x2412_6ea1]
+        );
+    }
+    #[test]
+    fn streams() {
+        let mut rng = Philox::new(0, 0);
+        assert_eq!(rng.next_u32(), 0x6627_e8d5);
+        assert_eq!(rng.next_u64(), 0xbc57_ac4c_e169_c58d);
+        let mut bytes = [0; 6];
+        rng.fill_bytes(&mut bytes);
+        // the second block starts after the fourth number
+        assert_eq!(bytes[..4], 0x9b00_dbd8_u32.to_le_bytes());
+        assert_eq!(
+            bytes[4..],
+            philox([1, 0, 0, 0], [0; 2])[0].to_le_bytes()[..2]
+        );
+        let mut from_seed = Philox::from_seed([7, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(from_seed.stream(), 3);
+        let mut direct = Philox::new(7, 3);
+        assert_eq!(from_seed.gen::<[u64; 8]>(), direct.gen::<[u64; 8]>());
+        assert_eq!(Philox::seed_from_u64(7), Philox::new(7, 0));
+        // different streams and seeds give different numbers
+        let first = |seed, stream| Philox::new(seed, stream).gen::<u64>();
+        assert_ne!(first(7, 0), first(7, 1));
+        assert_ne!(first(7, 0), first(8, 0));
+    }
+}
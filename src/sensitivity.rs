@@ -0,0 +1,273 @@
+//! Local sensitivity analysis.
+//!
+//! [`local_sensitivities`] estimates the derivatives of the mean amounts
+//! of species over time with respect to each parameter of a model, by
+//! central finite differences.  The perturbed and nominal simulations of
+//! each run share the same random numbers, so that most of their
+//! difference comes from the perturbation rather than from the noise:
+//! with independent simulations, the variance of the estimate would grow
+//! as the inverse square of the step.
+//!
+//! ```
+//! use rebop::gillespie::{Gillespie, Rate};
+//! use rebop::sensitivity::local_sensitivities;
+//! // birth and death, with mean k / γ (1 - exp(-γ t))
+//! let make = |params: &[f64]| {
+//!     let mut p = Gillespie::new([0]);
+//!     p.add_reaction(Rate::lma(params[0], [0]), [1]);
+//!     p.add_reaction(Rate::lma(params[1], [1]), [-1]);
+//!     p
+//! };
+//! let sensitivities = local_sensitivities(&[10., 0.1], make, &[0., 10.], 1000, 0.05, 0);
+//! // d/dk = (1 - exp(-γ t)) / γ
+//! let expected = (1. - (-1_f64).exp()) / 0.1;
+//! assert!((sensitivities.derivative(0, 0)[1] - expected).abs() < 0.5);
+//! assert_eq!(sensitivities.derivative(0, 0)[0], 0.);
+//! ```
+
+use rand::RngCore;
+
+use crate::inference::parallel_chunks;
+use crate::rng::Philox;
+use crate::system::SSASystem;
+
+/// Derivatives over time of the mean amounts of species with respect to
+/// parameters, computed by [`local_sensitivities`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sensitivities {
+    /// Time points.
+    pub times: Vec<f64>,
+    /// Nominal values of the parameters.
+    pub parameters: Vec<f64>,
+    /// Absolute step of the finite differences of each parameter.
+    pub steps: Vec<f64>,
+    /// Mean amounts with the nominal parameters, indexed by species and
+    /// time.
+    pub means: Vec<Vec<f64>>,
+    /// Estimated derivatives, indexed by parameter, species and time.
+    pub derivatives: Vec<Vec<Vec<f64>>>,
+    /// Standard errors of the derivatives, indexed like them.
+    pub standard_errors: Vec<Vec<Vec<f64>>>,
+}
+
+impl Sensitivities {
+    /// Returns the derivatives over time of the mean amount of the
+    /// species of index `s` with respect to the parameter of index `p`.
+    pub fn derivative(&self, p: usize, s: usize) -> &[f64] {
+        &self.derivatives[p][s]
+    }
+    /// Returns the derivatives normalized by the nominal values of the
+    /// parameter and of the mean, `θ / ⟨X⟩ d⟨X⟩/dθ`, which do not depend
+    /// on units, `NaN` where the mean is zero.
+    pub fn relative(&self, p: usize, s: usize) -> Vec<f64> {
+        self.derivatives[p][s]
+            .iter()
+            .zip(&self.means[s])
+            .map(|(d, &mean)| {
+                if mean == 0. {
+                    f64::NAN
+                } else {
+                    d * self.parameters[p] / mean
+                }
+            })
+            .collect()
+    }
+}
+
+/// Estimates the derivatives of the mean amounts of species at each of
+/// the `times` with respect to each of the `parameters`, over `nb_runs`
+/// runs.
+///
+/// `make` builds the problem, in its initial state, from values of the
+/// parameters, with either of the APIs of rebop (see [`SSASystem`]).
+/// Each parameter `θ` is perturbed by `±relative_step * |θ|` (or
+/// `±relative_step` if it is zero) and the derivative is the central
+/// difference of the amounts, averaged over the runs.  All the
+/// simulations of a run use the same seed, drawn from its own [`Philox`]
+/// stream derived from `seed`, and the runs are spread over all
+/// available threads.
+///
+/// A smaller step reduces the bias of the finite differences, but with
+/// discrete amounts the perturbed runs eventually diverge from the
+/// nominal one, so the step should not be so small that only a few
+/// runs differ.  It should also be small enough that the perturbed
+/// parameters stay valid, for example positive rates.
+///
+/// # Panics
+///
+/// Panics if there is no run or if the step is not positive.
+pub fn local_sensitivities<P, F>(
+    parameters: &[f64],
+    make: F,
+    times: &[f64],
+    nb_runs: usize,
+    relative_step: f64,
+    seed: u64,
+) -> Sensitivities
+where
+    P: SSASystem,
+    F: Fn(&[f64]) -> P + Sync,
+{
+    assert!(nb_runs > 0, "no simulation to run");
+    assert!(relative_step > 0., "the step must be positive");
+    let steps: Vec<f64> = parameters
+        .iter()
+        .map(|&p| relative_step * if p == 0. { 1. } else { p.abs() })
+        .collect();
+    let nb_species = make(parameters).nb_species();
+    let simulate = |params: &[f64], seed: u64| {
+        let mut problem = make(params);
+        problem.seed(seed);
+        let mut amounts = vec![Vec::with_capacity(times.len()); nb_species];
+        for &t in times {
+            problem.advance_until(t);
+            for (a, &s) in amounts.iter_mut().zip(problem.species().iter()) {
+                a.push(s as f64);
+            }
+        }
+        amounts
+    };
+    let zeros = vec![vec![0.; times.len()]; nb_species];
+    // sums of the nominal amounts, and of the differences and their
+    // squares for each parameter
+    let chunks = parallel_chunks(nb_runs, |runs| {
+        let mut sums = zeros.clone();
+        let mut differences = vec![zeros.clone(); parameters.len()];
+        let mut squares = differences.clone();
+        let mut params = parameters.to_vec();
+        for irun in runs {
+            let seed = Philox::new(seed, irun as u64).next_u64();
+            add(&mut sums, &simulate(parameters, seed), |x| x);
+            for (p, &step) in steps.iter().enumerate() {
+                params[p] = parameters[p] + step;
+                let above = simulate(&params, seed);
+                params[p] = parameters[p] - step;
+                let mut difference = simulate(&params, seed);
+                params[p] = parameters[p];
+                for (d, a) in difference.iter_mut().flatten().zip(above.iter().flatten()) {
+                    *d = (a - *d) / (2. * step);
+                }
+                add(&mut differences[p], &difference, |x| x);
+                add(&mut squares[p], &difference, |x| x * x);
+            }
+        }
+        (sums, differences, squares)
+    });
+    let mut sums = zeros.clone();
+    let mut differences = vec![zeros.clone(); parameters.len()];
+    let mut squares = differences.clone();
+    for (chunk_sums, chunk_differences, chunk_squares) in chunks {
+        add(&mut sums, &chunk_sums, |x| x);
+        for p in 0..parameters.len() {
+            add(&mut differences[p], &chunk_differences[p], |x| x);
+            add(&mut squares[p], &chunk_squares[p], |x| x);
+        }
+    }
+    let n = nb_runs as f64;
+    let means = sums
+        .iter()
+        .map(|sums| sums.iter().map(|s| s / n).collect())
+        .collect();
+    let derivatives: Vec<Vec<Vec<f64>>> = differences
+        .iter()
+        .map(|d| {
+            d.iter()
+                .map(|d| d.iter().map(|d| d / n).collect())
+                .collect()
+        })
+        .collect();
+    let standard_errors = derivatives
+        .iter()
+        .zip(&squares)
+        .map(|(derivatives, squares)| {
+            derivatives
+                .iter()
+                .zip(squares)
+                .map(|(derivatives, squares)| {
+                    derivatives
+                        .iter()
+                        .zip(squares)
+                        .map(|(d, s)| {
+                            if nb_runs > 1 {
+                                ((s / n - d * d).max(0.) / (n - 1.)).sqrt()
+                            } else {
+                                f64::NAN
+                            }
+                        })
+                        .collect()
+                })
+                .collect()
+        })
+        .collect();
+    Sensitivities {
+        times: times.to_vec(),
+        parameters: parameters.to_vec(),
+        steps,
+        means,
+        derivatives,
+        standard_errors,
+    }
+}
+
+/// Adds `f` of each value of `values` to `total`, both indexed by
+/// species and time.
+fn add(total: &mut [Vec<f64>], values: &[Vec<f64>], f: impl Fn(f64) -> f64) {
+    for (t, &v) in total.iter_mut().flatten().zip(values.iter().flatten()) {
+        *t += f(v);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::define_system;
+    use crate::gillespie::{Gillespie, Rate};
+    use crate::sensitivity::local_sensitivities;
+    #[test]
+    fn birth_death() {
+        let (k, gamma) = (10., 0.1);
+        let make = |params: &[f64]| {
+            let mut p = Gillespie::new([0, 3]);
+            p.add_reaction(Rate::lma(params[0], [0, 0]), [1, 0]);
+            p.add_reaction(Rate::lma(params[1], [1, 0]), [-1, 0]);
+            p
+        };
+        let times = [0., 5., 10., 20.];
+        let s = local_sensitivities(&[k, gamma, 7.], make, &times, 2000, 0.05, 0);
+        assert_eq!(s.steps, [0.05 * k, 0.05 * gamma, 0.05 * 7.]);
+        for (i, &t) in times.iter().enumerate() {
+            let decay = f64::exp(-gamma * t);
+            let mean = k / gamma * (1. - decay);
+            let dk = (1. - decay) / gamma;
+            let dgamma = -k / gamma / gamma * (1. - decay) + k * t * decay / gamma;
+            assert!((s.means[0][i] - mean).abs() < 1., "{}", s.means[0][i]);
+            let tolerance = 5. * s.standard_errors[0][0][i] + 0.1;
+            assert!((s.derivative(0, 0)[i] - dk).abs() < tolerance);
+            let tolerance = 5. * s.standard_errors[1][0][i] + 0.1 * dgamma.abs();
+            assert!((s.derivative(1, 0)[i] - dgamma).abs() <= tolerance);
+        }
+        assert!((s.relative(0, 0)[2] - 1.).abs() < 0.05);
+        assert!(s.relative(0, 0)[0].is_nan());
+        // with the same random numbers, the unused parameter and the
+        // constant species have no effect at all
+        assert!(s.derivatives[2].iter().flatten().all(|&d| d == 0.));
+        assert!(s.derivative(0, 1).iter().all(|&d| d == 0.));
+        assert_eq!(
+            s,
+            local_sensitivities(&[k, gamma, 7.], make, &times, 2000, 0.05, 0)
+        );
+    }
+    #[test]
+    fn macro_models() {
+        define_system! {
+            r_birth;
+            Birth { A }
+            birth   :   => A    @ r_birth
+        }
+        let make = |params: &[f64]| Birth::with_parameters(params[0]);
+        let s = local_sensitivities(&[2.], make, &[0., 1.], 1, 0.5, 0);
+        assert_eq!(s.steps, [1.]);
+        assert_eq!(s.means[0][0], 0.);
+        assert_eq!(s.derivative(0, 0)[0], 0.);
+        assert!(s.standard_errors[0][0][1].is_nan());
+    }
+}
@@ -0,0 +1,344 @@
+//! Evaluation of the laws of mass action over lanes of reactions.
+//!
+//! The direct method spends most of its time computing the rates of all
+//! the reactions, then looking for the one that fires.  When all the
+//! rates follow the law of mass action, [`LmaRates`] stores them as a
+//! structure of arrays: the rate constants, then for each factor of the
+//! falling factorials the index of its species and its offset, so that
+//! `LANES` reactions are multiplied at once.  `std::simd` is not stable,
+//! so the lanes are [`F64x4`], written with the intrinsics of AVX when the
+//! target enables it, with those of SSE2 on the other x86-64 targets, and
+//! with arrays elsewhere.
+//!
+//! The factors are multiplied in the same order as in
+//! [`Rate::rate`](crate::gillespie::Rate), so that the rates, and thus
+//! the trajectories, are exactly the same.
+
+use std::ops::{Add, Mul, Sub};
+
+use crate::gillespie::{scale, Jump, Rate};
+
+/// Number of reactions evaluated at once.
+pub(crate) const LANES: usize = 4;
+
+/// Maximal order of the reactions, above which the problems are
+/// evaluated by the interpreter.
+const MAX_ORDER: usize = 4;
+
+/// Rates of the reactions of a problem whose rates all follow the law of
+/// mass action, as a structure of arrays padded to a multiple of
+/// [`LANES`] reactions.
+#[derive(Debug)]
+pub(crate) struct LmaRates {
+    nb_species: usize,
+    nb_reactions: usize,
+    /// Rate constant of each reaction, `0` for the padding.
+    constants: Vec<f64>,
+    /// Factors of the rates: the index of the species in the amounts,
+    /// where the index `nb_species` stands for the constant `1`, and the
+    /// offset subtracted from its amount.
+    factors: Vec<(Vec<u32>, Vec<f64>)>,
+}
+
+impl LmaRates {
+    /// Lays out the rates of `reactions`, for a problem with `nb_species`
+    /// species, or returns `None` if some rates do not follow the law of
+    /// mass action or are of order above [`MAX_ORDER`].
+    pub(crate) fn new(reactions: &[(Rate, Jump)], nb_species: usize) -> Option<LmaRates> {
+        let padded = reactions.len().div_ceil(LANES) * LANES;
+        let mut constants = vec![0.; padded];
+        let mut factors = vec![(vec![nb_species as u32; padded], vec![0.; padded]); MAX_ORDER];
+        for (ireaction, (rate, _)) in reactions.iter().enumerate() {
+            let (constant, sparse) = match rate.clone().sparse() {
+                Rate::LMASparse(constant, sparse) => (constant, sparse),
+                _ => return None,
+            };
+            constants[ireaction] = constant;
+            // the falling factorial of `n` and `e` is the product of
+            // `n - e + 1` up to `n`, in this order
+            let mut slots = factors.iter_mut();
+            for (index, exponent) in sparse {
+                for offset in (0..exponent).rev() {
+                    let (indices, offsets) = slots.next()?;
+                    indices[ireaction] = index;
+                    offsets[ireaction] = offset as f64;
+                }
+            }
+        }
+        // only the factors used by some reaction are evaluated
+        while factors
+            .last()
+            .is_some_and(|(indices, _)| indices.iter().all(|&i| i == nb_species as u32))
+        {
+            factors.pop();
+        }
+        Some(LmaRates {
+            nb_species,
+            nb_reactions: reactions.len(),
+            constants,
+            factors,
+        })
+    }
+    /// Writes the cumulative rates of the reactions, multiplied by
+    /// `scales` if it is not empty, in `cum_rates`, and returns the total
+    /// rate, like `make_cumrates`.  `amounts` is a buffer for the amounts
+    /// of species as floating-point numbers.
+    pub(crate) fn cum_rates(
+        &self,
+        species: &[isize],
+        scales: &[f64],
+        amounts: &mut Vec<f64>,
+        cum_rates: &mut [f64],
+    ) -> f64 {
+        assert!(species.len() >= self.nb_species && cum_rates.len() >= self.nb_reactions);
+        amounts.clear();
+        amounts.extend(species[..self.nb_species].iter().map(|&n| n as f64));
+        amounts.push(1.);
+        let mut rates = [0.; LANES];
+        for (start, constants) in self.constants.chunks_exact(LANES).enumerate() {
+            let start = start * LANES;
+            let mut lanes = F64x4::load(constants);
+            for (indices, offsets) in &self.factors {
+                let indices = &indices[start..][..LANES];
+                let mut factors = [0.; LANES];
+                for lane in 0..LANES {
+                    // SAFETY: the indices are at most `nb_species`
+                    factors[lane] = unsafe { *amounts.get_unchecked(indices[lane] as usize) };
+                }
+                lanes = lanes * (F64x4::load(&factors) - F64x4::load(&offsets[start..]));
+            }
+            let end = (start + LANES).min(self.nb_reactions);
+            if end - start == LANES {
+                lanes.store(&mut cum_rates[start..end]);
+            } else {
+                lanes.store(&mut rates);
+                cum_rates[start..end].copy_from_slice(&rates[..end - start]);
+            }
+        }
+        let mut total_rate = 0.;
+        let cum_rates = &mut cum_rates[..self.nb_reactions];
+        if scales.is_empty() {
+            for cum_rate in cum_rates {
+                total_rate += *cum_rate;
+                *cum_rate = total_rate;
+            }
+        } else {
+            for (ireaction, cum_rate) in cum_rates.iter_mut().enumerate() {
+                total_rate += *cum_rate * scale(scales, ireaction);
+                *cum_rate = total_rate;
+            }
+        }
+        total_rate
+    }
+}
+
+/// Returns the number of `values` below `threshold`, counted over
+/// [`LANES`] values at once.
+pub(crate) fn count_below(values: &[f64], threshold: f64) -> usize {
+    let (mut counts, thresholds) = (F64x4::splat(0.), F64x4::splat(threshold));
+    let mut chunks = values.chunks_exact(LANES);
+    for chunk in &mut chunks {
+        counts = counts + F64x4::load(chunk).below(thresholds);
+    }
+    let below = chunks
+        .remainder()
+        .iter()
+        .filter(|&&value| value < threshold)
+        .count();
+    counts.sum() as usize + below
+}
+
+/// Four `f64` in vector registers, with the AVX instructions.
+#[cfg(all(target_arch = "x86_64", target_feature = "avx"))]
+#[derive(Clone, Copy)]
+struct F64x4(std::arch::x86_64::__m256d);
+
+#[cfg(all(target_arch = "x86_64", target_feature = "avx"))]
+impl F64x4 {
+    fn splat(value: f64) -> Self {
+        use std::arch::x86_64::_mm256_set1_pd;
+        // SAFETY: the target has AVX
+        F64x4(unsafe { _mm256_set1_pd(value) })
+    }
+    /// Loads the first [`LANES`] values of `values`.
+    fn load(values: &[f64]) -> Self {
+        use std::arch::x86_64::_mm256_loadu_pd;
+        assert!(values.len() >= LANES);
+        // SAFETY: the target has AVX, and the values were just checked
+        F64x4(unsafe { _mm256_loadu_pd(values.as_ptr()) })
+    }
+    /// Stores the values in the first [`LANES`] values of `values`.
+    fn store(self, values: &mut [f64]) {
+        use std::arch::x86_64::_mm256_storeu_pd;
+        assert!(values.len() >= LANES);
+        // SAFETY: the target has AVX, and the values were just checked
+        unsafe { _mm256_storeu_pd(values.as_mut_ptr(), self.0) }
+    }
+    /// `1` in the lanes below `threshold`, `0` in the others.
+    fn below(self, threshold: Self) -> Self {
+        use std::arch::x86_64::{_mm256_and_pd, _mm256_cmp_pd, _CMP_LT_OQ};
+        // SAFETY: the target has AVX
+        unsafe {
+            let mask = _mm256_cmp_pd::<_CMP_LT_OQ>(self.0, threshold.0);
+            F64x4(_mm256_and_pd(mask, F64x4::splat(1.).0))
+        }
+    }
+}
+
+/// Four `f64` in pairs of vector registers, with the SSE2 instructions of
+/// all the x86-64 processors.
+#[cfg(all(target_arch = "x86_64", not(target_feature = "avx")))]
+#[derive(Clone, Copy)]
+struct F64x4(std::arch::x86_64::__m128d, std::arch::x86_64::__m128d);
+
+#[cfg(all(target_arch = "x86_64", not(target_feature = "avx")))]
+impl F64x4 {
+    fn splat(value: f64) -> Self {
+        use std::arch::x86_64::_mm_set1_pd;
+        // SAFETY: the target has SSE2
+        let half = unsafe { _mm_set1_pd(value) };
+        F64x4(half, half)
+    }
+    /// Loads the first [`LANES`] values of `values`.
+    fn load(values: &[f64]) -> Self {
+        use std::arch::x86_64::_mm_loadu_pd;
+        assert!(values.len() >= LANES);
+        // SAFETY: the target has SSE2, and the values were just checked
+        unsafe {
+            F64x4(
+                _mm_loadu_pd(values.as_ptr()),
+                _mm_loadu_pd(values.as_ptr().add(2)),
+            )
+        }
+    }
+    /// Stores the values in the first [`LANES`] values of `values`.
+    fn store(self, values: &mut [f64]) {
+        use std::arch::x86_64::_mm_storeu_pd;
+        assert!(values.len() >= LANES);
+        // SAFETY: the target has SSE2, and the values were just checked
+        unsafe {
+            _mm_storeu_pd(values.as_mut_ptr(), self.0);
+            _mm_storeu_pd(values.as_mut_ptr().add(2), self.1);
+        }
+    }
+    /// `1` in the lanes below `threshold`, `0` in the others.
+    fn below(self, threshold: Self) -> Self {
+        use std::arch::x86_64::{_mm_and_pd, _mm_cmplt_pd};
+        let one = F64x4::splat(1.);
+        // SAFETY: the target has SSE2
+        unsafe {
+            F64x4(
+                _mm_and_pd(_mm_cmplt_pd(self.0, threshold.0), one.0),
+                _mm_and_pd(_mm_cmplt_pd(self.1, threshold.1), one.1),
+            )
+        }
+    }
+}
+
+/// Four `f64` in an array, on the targets without intrinsics.
+#[cfg(not(target_arch = "x86_64"))]
+#[derive(Clone, Copy)]
+struct F64x4([f64; LANES]);
+
+#[cfg(not(target_arch = "x86_64"))]
+impl F64x4 {
+    fn splat(value: f64) -> Self {
+        F64x4([value; LANES])
+    }
+    /// Loads the first [`LANES`] values of `values`.
+    fn load(values: &[f64]) -> Self {
+        F64x4(values[..LANES].try_into().unwrap())
+    }
+    /// Stores the values in the first [`LANES`] values of `values`.
+    fn store(self, values: &mut [f64]) {
+        values[..LANES].copy_from_slice(&self.0);
+    }
+    /// `1` in the lanes below `threshold`, `0` in the others.
+    fn below(self, threshold: Self) -> Self {
+        F64x4(std::array::from_fn(|lane| {
+            f64::from(u8::from(self.0[lane] < threshold.0[lane]))
+        }))
+    }
+}
+
+impl F64x4 {
+    /// Sum of the lanes.
+    fn sum(self) -> f64 {
+        let mut values = [0.; LANES];
+        self.store(&mut values);
+        values.iter().sum()
+    }
+}
+
+/// Implements the arithmetic operators lane by lane, with the intrinsics
+/// of AVX and SSE2 or with the operator on arrays.
+macro_rules! operators {
+    ($($trait:ident $method:ident $avx:ident $sse2:ident $op:tt;)*) => {$(
+        impl $trait for F64x4 {
+            type Output = F64x4;
+            #[cfg(all(target_arch = "x86_64", target_feature = "avx"))]
+            fn $method(self, other: F64x4) -> F64x4 {
+                // SAFETY: the target has AVX
+                F64x4(unsafe { std::arch::x86_64::$avx(self.0, other.0) })
+            }
+            #[cfg(all(target_arch = "x86_64", not(target_feature = "avx")))]
+            fn $method(self, other: F64x4) -> F64x4 {
+                use std::arch::x86_64::$sse2;
+                // SAFETY: the target has SSE2
+                unsafe { F64x4($sse2(self.0, other.0), $sse2(self.1, other.1)) }
+            }
+            #[cfg(not(target_arch = "x86_64"))]
+            fn $method(self, other: F64x4) -> F64x4 {
+                F64x4(std::array::from_fn(|lane| self.0[lane] $op other.0[lane]))
+            }
+        }
+    )*};
+}
+
+operators! {
+    Add add _mm256_add_pd _mm_add_pd +;
+    Sub sub _mm256_sub_pd _mm_sub_pd -;
+    Mul mul _mm256_mul_pd _mm_mul_pd *;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gillespie::{make_cumrates, Expr, Jump, Rate};
+    use crate::simd::*;
+
+    #[test]
+    fn same_rates() {
+        let reactions = vec![
+            (Rate::lma(0.3, [1, 0, 0]), Jump::new([-1, 1, 0])),
+            (Rate::lma(1e-3, [2, 1, 0]), Jump::new([-2, -1, 1])),
+            (Rate::lma(7., [0, 0, 0]), Jump::new([1, 0, 0])),
+            (Rate::LMASparse(0.1, vec![(2, 3)]), Jump::new([0, 0, -1])),
+            (Rate::lma(2.5, [0, 1, 1]), Jump::new([0, -1, -1])),
+        ];
+        let mut amounts = Vec::new();
+        for scales in [vec![], vec![1., 0.5, 3., 0., 1e-7]] {
+            let lanes = LmaRates::new(&reactions, 3).unwrap();
+            for species in [[0, 0, 0], [1, 2, 3], [17, 1, 2], [1000, 999, 12345]] {
+                let (mut expected, mut found) = ([0.; 5], [0.; 5]);
+                let total = make_cumrates(&reactions, &scales, &species, &mut expected);
+                let cum_rates = lanes.cum_rates(&species, &scales, &mut amounts, &mut found);
+                assert_eq!(cum_rates, total);
+                assert_eq!(found, expected);
+            }
+        }
+        let expression = Rate::Expr(Expr::Concentration(0));
+        assert!(LmaRates::new(&[(expression, Jump::new([0]))], 1).is_none());
+        let high_order = Rate::lma(1., [5]);
+        assert!(LmaRates::new(&[(high_order, Jump::new([0]))], 1).is_none());
+    }
+
+    #[test]
+    fn count() {
+        let values: Vec<f64> = (0..11).map(f64::from).collect();
+        for threshold in [-1., 0., 0.5, 4., 9.5, 10., 20.] {
+            let expected = values.iter().filter(|&&v| v < threshold).count();
+            assert_eq!(count_below(&values, threshold), expected);
+        }
+        assert_eq!(count_below(&[], 1.), 0);
+    }
+}
@@ -0,0 +1,589 @@
+//! Summary statistics of ensembles of trajectories.
+//!
+//! [`EnsembleStats`] consumes trajectories one at a time and keeps, for
+//! each species and time point, the running mean and variance (with the
+//! algorithm of Welford), so that the memory does not depend on the
+//! number of trajectories.  With [`EnsembleStats::with_quantiles`], it
+//! also counts how many times each amount was seen, from which any
+//! quantile can be computed exactly: the memory then grows with the
+//! number of distinct amounts, not of trajectories.
+//!
+//! ```
+//! use rebop::gillespie::{Gillespie, Rate, Trajectory};
+//! use rebop::stats::EnsembleStats;
+//! let times: Vec<f64> = (0..=10).map(f64::from).collect();
+//! let mut stats = EnsembleStats::new(&times, 1).with_quantiles();
+//! for seed in 0..1000 {
+//!     let mut p = Gillespie::new_with_seed([0], seed);
+//!     p.add_reaction(Rate::lma(10., [0]), [1]);
+//!     let mut trajectory = Trajectory::new(1);
+//!     for &t in &times {
+//!         p.advance_until(t);
+//!         trajectory.push(&p);
+//!     }
+//!     stats.push(&trajectory);
+//! }
+//! // Poisson distribution of mean and variance 10 t
+//! assert!((stats.mean(0)[10] - 100.).abs() < 2.);
+//! assert!((stats.variance(0)[10] - 100.).abs() < 15.);
+//! assert!((stats.quantile(0, 0.5)[10] - 100.).abs() <= 2.);
+//! ```
+//!
+//! [`compare_ensembles`] compares the distributions of two such
+//! ensembles at each time point, for example to quantify the error of an
+//! approximate simulation method against an exact one.
+//!
+//! [`bootstrap`] resamples the replicates of an ensemble to give
+//! confidence intervals to any statistic computed from them, like a mean
+//! trajectory, stationary quantiles or quantiles of first-passage times.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use rand::Rng;
+
+use crate::gillespie::Trajectory;
+use crate::inference::parallel_map;
+use crate::predictive::quantile;
+use crate::rng::Philox;
+
+/// Streaming statistics of the amounts of species at fixed time points,
+/// over trajectories.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnsembleStats {
+    times: Vec<f64>,
+    nb_species: usize,
+    count: u64,
+    /// Means and sums of squared deviations to the mean, indexed by
+    /// species then time point.
+    means: Vec<Vec<f64>>,
+    squares: Vec<Vec<f64>>,
+    /// Number of times each amount was seen, indexed by species then
+    /// time point, if quantiles are computed.
+    histograms: Option<Vec<Vec<BTreeMap<isize, u64>>>>,
+}
+
+impl EnsembleStats {
+    /// Creates empty statistics of `nb_species` species at the time
+    /// points `times`.
+    pub fn new(times: &[f64], nb_species: usize) -> Self {
+        EnsembleStats {
+            times: times.to_vec(),
+            nb_species,
+            count: 0,
+            means: vec![vec![0.; times.len()]; nb_species],
+            squares: vec![vec![0.; times.len()]; nb_species],
+            histograms: None,
+        }
+    }
+    /// Also counts the amounts, for [`quantile`](EnsembleStats::quantile).
+    pub fn with_quantiles(mut self) -> Self {
+        self.histograms = Some(vec![
+            vec![BTreeMap::new(); self.times.len()];
+            self.nb_species
+        ]);
+        self
+    }
+    /// Adds a trajectory to the statistics.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the trajectory is not recorded at the time points of the
+    /// statistics, or for another number of species.
+    pub fn push(&mut self, trajectory: &Trajectory) {
+        assert_eq!(trajectory.times, self.times, "different time points");
+        assert_eq!(trajectory.species.len(), self.nb_species);
+        self.count += 1;
+        let n = self.count as f64;
+        for (s, values) in trajectory.species.iter().enumerate() {
+            for (i, &value) in values.iter().enumerate() {
+                let x = value as f64;
+                let delta = x - self.means[s][i];
+                self.means[s][i] += delta / n;
+                self.squares[s][i] += delta * (x - self.means[s][i]);
+            }
+            if let Some(histograms) = &mut self.histograms {
+                for (histogram, &value) in histograms[s].iter_mut().zip(values) {
+                    *histogram.entry(value).or_default() += 1;
+                }
+            }
+        }
+    }
+    /// Adds the trajectories summarized by `other`, for example from
+    /// another thread.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the statistics do not have the same time points and
+    /// species, or if only one of them computes quantiles.
+    pub fn merge(&mut self, other: &EnsembleStats) {
+        assert_eq!(other.times, self.times, "different time points");
+        assert_eq!(other.nb_species, self.nb_species);
+        assert_eq!(other.histograms.is_some(), self.histograms.is_some());
+        let (na, nb) = (self.count as f64, other.count as f64);
+        self.count += other.count;
+        if other.count == 0 {
+            return;
+        }
+        let n = self.count as f64;
+        for s in 0..self.nb_species {
+            for i in 0..self.times.len() {
+                let delta = other.means[s][i] - self.means[s][i];
+                self.means[s][i] += delta * nb / n;
+                self.squares[s][i] += other.squares[s][i] + delta * delta * na * nb / n;
+            }
+        }
+        if let (Some(histograms), Some(others)) = (&mut self.histograms, &other.histograms) {
+            for (histogram, other) in histograms.iter_mut().flatten().zip(others.iter().flatten()) {
+                for (&value, &count) in other {
+                    *histogram.entry(value).or_default() += count;
+                }
+            }
+        }
+    }
+    /// Returns the time points.
+    pub fn times(&self) -> &[f64] {
+        &self.times
+    }
+    /// Returns the number of trajectories.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+    /// Returns the mean amount of the species of index `s` at each time
+    /// point.
+    pub fn mean(&self, s: usize) -> &[f64] {
+        &self.means[s]
+    }
+    /// Returns the unbiased variance of the amount of the species of
+    /// index `s` at each time point, `NaN` with fewer than two
+    /// trajectories.
+    pub fn variance(&self, s: usize) -> Vec<f64> {
+        let n = self.count as f64;
+        self.squares[s]
+            .iter()
+            .map(|squares| {
+                if self.count > 1 {
+                    squares / (n - 1.)
+                } else {
+                    f64::NAN
+                }
+            })
+            .collect()
+    }
+    /// Returns the quantile `q` of the amount of the species of index `s`
+    /// at each time point, interpolated linearly between the amounts like
+    /// [`posterior_predictive`](crate::predictive::posterior_predictive).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the statistics were not created
+    /// [`with_quantiles`](EnsembleStats::with_quantiles), if there is no
+    /// trajectory, or if `q` is not in `[0, 1]`.
+    pub fn quantile(&self, s: usize, q: f64) -> Vec<f64> {
+        let histograms = self
+            .histograms
+            .as_ref()
+            .expect("quantiles are not computed");
+        assert!(self.count > 0, "no trajectory");
+        assert!((0. ..=1.).contains(&q));
+        let position = q * (self.count - 1) as f64;
+        let below = position.floor() as u64;
+        let above = position.ceil() as u64;
+        histograms[s]
+            .iter()
+            .map(|histogram| {
+                let (low, high) = (rank(histogram, below), rank(histogram, above));
+                low + (high - low) * (position - below as f64)
+            })
+            .collect()
+    }
+}
+
+/// Differences between the distributions of the amounts of species in
+/// two ensembles, computed by [`compare_ensembles`], indexed by species
+/// then time point.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnsembleComparison {
+    /// Time points.
+    pub times: Vec<f64>,
+    /// Two-sample Kolmogorov-Smirnov statistics: largest difference
+    /// between the empirical cumulative distribution functions.
+    pub ks_statistics: Vec<Vec<f64>>,
+    /// Asymptotic p-values of the Kolmogorov-Smirnov tests, under the
+    /// hypothesis that both ensembles have the same distribution.
+    pub p_values: Vec<Vec<f64>>,
+    /// Total variation distances between the histograms: half the sum of
+    /// the absolute differences of the frequencies of each amount.
+    pub total_variations: Vec<Vec<f64>>,
+}
+
+/// Compares the distributions of the amounts of each species at each time
+/// point in the ensembles summarized by `a` and `b`.
+///
+/// The p-values use the asymptotic distribution of the Kolmogorov-Smirnov
+/// statistic, which is conservative for discrete amounts: two ensembles
+/// from the same distribution rarely get small p-values, but testing
+/// every species and time point still needs a correction for multiple
+/// tests.  The total variation distance does not depend on the number of
+/// trajectories, but its estimate is biased upwards when they are few
+/// compared to the number of distinct amounts.
+///
+/// ```
+/// use rebop::gillespie::{Gillespie, Method, Rate, Trajectory};
+/// use rebop::stats::{compare_ensembles, EnsembleStats};
+/// let ensemble = |method| {
+///     let mut stats = EnsembleStats::new(&[5.], 1).with_quantiles();
+///     for seed in 0..500 {
+///         let mut p = Gillespie::new_with_seed([1000], seed);
+///         p.add_reaction(Rate::lma(0.1, [1]), [-1]);
+///         p.set_method(method);
+///         let mut trajectory = Trajectory::new(1);
+///         p.advance_until(5.);
+///         trajectory.push(&p);
+///         stats.push(&trajectory);
+///     }
+///     stats
+/// };
+/// let exact = ensemble(Method::Direct);
+/// let comparison = compare_ensembles(&exact, &ensemble(Method::NextReaction));
+/// assert!(comparison.p_values[0][0] > 0.001);
+/// // with a single huge leap, all decays happen at the same rate
+/// let comparison = compare_ensembles(&exact, &ensemble(Method::RLeaping(1000)));
+/// assert!(comparison.p_values[0][0] < 0.001);
+/// ```
+///
+/// # Panics
+///
+/// Panics if the ensembles do not have the same time points and species,
+/// if one of them does not compute quantiles, or if one of them is empty.
+pub fn compare_ensembles(a: &EnsembleStats, b: &EnsembleStats) -> EnsembleComparison {
+    assert_eq!(a.times, b.times, "different time points");
+    assert_eq!(a.nb_species, b.nb_species);
+    let (Some(histograms_a), Some(histograms_b)) = (&a.histograms, &b.histograms) else {
+        panic!("quantiles are not computed");
+    };
+    assert!(a.count > 0 && b.count > 0, "no trajectory");
+    let (na, nb) = (a.count as f64, b.count as f64);
+    let effective = (na * nb / (na + nb)).sqrt();
+    let mut ks_statistics = vec![Vec::with_capacity(a.times.len()); a.nb_species];
+    let mut total_variations = ks_statistics.clone();
+    for (s, (histograms_a, histograms_b)) in histograms_a.iter().zip(histograms_b).enumerate() {
+        for (ha, hb) in histograms_a.iter().zip(histograms_b) {
+            let amounts: BTreeSet<isize> = ha.keys().chain(hb.keys()).copied().collect();
+            let (mut cdf_a, mut cdf_b) = (0., 0.);
+            let (mut ks, mut tv) = (0_f64, 0.);
+            for amount in amounts {
+                let pa = ha.get(&amount).copied().unwrap_or(0) as f64 / na;
+                let pb = hb.get(&amount).copied().unwrap_or(0) as f64 / nb;
+                cdf_a += pa;
+                cdf_b += pb;
+                ks = ks.max((cdf_a - cdf_b).abs());
+                tv += (pa - pb).abs() / 2.;
+            }
+            ks_statistics[s].push(ks);
+            total_variations[s].push(tv);
+        }
+    }
+    let p_values = ks_statistics
+        .iter()
+        .map(|statistics| {
+            statistics
+                .iter()
+                .map(|d| kolmogorov((effective + 0.12 + 0.11 / effective) * d))
+                .collect()
+        })
+        .collect();
+    EnsembleComparison {
+        times: a.times.clone(),
+        ks_statistics,
+        p_values,
+        total_variations,
+    }
+}
+
+/// Confidence interval of a statistic, computed by [`bootstrap`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConfidenceInterval {
+    /// Value of the statistic on all the replicates.
+    pub estimate: f64,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+/// Computes percentile bootstrap confidence intervals, at the confidence
+/// `level`, for each value returned by `statistic`.
+///
+/// `statistic` reduces a sample of the replicates to a vector of values
+/// of fixed length, for example the mean amount of a species at each
+/// time point.  It is computed on the replicates for the estimates, then
+/// on `nb_resamples` samples drawn from them with replacement, in
+/// parallel over all available threads.  The sample of index `b` is
+/// drawn from the [`Philox`] stream `b` of `seed`, so that the intervals
+/// only depend on the seed.  The `NaN` values of the resamples, for
+/// example quantiles of first-passage times that never happened, are
+/// ignored, and the bounds are `NaN` if all the values are.
+///
+/// ```
+/// use rebop::gillespie::{Gillespie, Rate, Trajectory};
+/// use rebop::stats::bootstrap;
+/// let times = [0., 5., 10.];
+/// let trajectories: Vec<Trajectory> = (0..100)
+///     .map(|seed| {
+///         let mut p = Gillespie::new_with_seed([0], seed);
+///         p.add_reaction(Rate::lma(10., [0]), [1]);
+///         let mut trajectory = Trajectory::new(1);
+///         for &t in &times {
+///             p.advance_until(t);
+///             trajectory.push(&p);
+///         }
+///         trajectory
+///     })
+///     .collect();
+/// // mean trajectory of the species
+/// let mean = |sample: &[&Trajectory]| {
+///     (0..times.len())
+///         .map(|i| {
+///             let sum: isize = sample.iter().map(|t| t.species[0][i]).sum();
+///             sum as f64 / sample.len() as f64
+///         })
+///         .collect()
+/// };
+/// let intervals = bootstrap(&trajectories, mean, 1000, 0.95, 0);
+/// assert_eq!(intervals[0].lower, 0.);
+/// assert!(intervals[2].lower < 100. && 100. < intervals[2].upper);
+/// ```
+///
+/// # Panics
+///
+/// Panics if there is no replicate or no resample, if `level` is not
+/// between `0` and `1`, or if `statistic` does not always return the
+/// same number of values.
+pub fn bootstrap<T, F>(
+    replicates: &[T],
+    statistic: F,
+    nb_resamples: usize,
+    level: f64,
+    seed: u64,
+) -> Vec<ConfidenceInterval>
+where
+    T: Sync,
+    F: Fn(&[&T]) -> Vec<f64> + Sync,
+{
+    assert!(!replicates.is_empty(), "no replicate");
+    assert!(nb_resamples > 0, "no resample");
+    assert!(
+        level > 0. && level < 1.,
+        "the level must be between 0 and 1"
+    );
+    let n = replicates.len();
+    let all: Vec<&T> = replicates.iter().collect();
+    let estimates = statistic(&all);
+    let resamples = parallel_map(nb_resamples, |i| {
+        let mut rng = Philox::new(seed, i as u64);
+        let sample: Vec<&T> = (0..n).map(|_| &replicates[rng.gen_range(0..n)]).collect();
+        statistic(&sample)
+    });
+    assert!(
+        resamples
+            .iter()
+            .all(|values| values.len() == estimates.len()),
+        "different numbers of values"
+    );
+    estimates
+        .iter()
+        .enumerate()
+        .map(|(k, &estimate)| {
+            let mut values: Vec<f64> = resamples
+                .iter()
+                .map(|values| values[k])
+                .filter(|value| !value.is_nan())
+                .collect();
+            values.sort_by(f64::total_cmp);
+            let (lower, upper) = if values.is_empty() {
+                (f64::NAN, f64::NAN)
+            } else {
+                (
+                    quantile(&values, (1. - level) / 2.),
+                    quantile(&values, (1. + level) / 2.),
+                )
+            };
+            ConfidenceInterval {
+                estimate,
+                lower,
+                upper,
+            }
+        })
+        .collect()
+}
+
+/// Survival function of the Kolmogorov distribution at `lambda`,
+/// `2 Σ (-1)^(j-1) exp(-2 j² λ²)`, which is `1` when the series does not
+/// converge, for small `lambda`.
+fn kolmogorov(lambda: f64) -> f64 {
+    let mut sum = 0.;
+    let mut sign = 2.;
+    let mut previous: f64 = 0.;
+    for j in 1..=100 {
+        let term = sign * (-2. * (j * j) as f64 * lambda * lambda).exp();
+        sum += term;
+        if term.abs() <= 1e-3 * previous || term.abs() <= 1e-8 * sum {
+            return sum.clamp(0., 1.);
+        }
+        sign = -sign;
+        previous = term.abs();
+    }
+    1.
+}
+
+/// Returns the amount of rank `rank`, from `0`, among the amounts
+/// counted in `histogram`.
+fn rank(histogram: &BTreeMap<isize, u64>, rank: u64) -> f64 {
+    let mut seen = 0;
+    for (&value, &count) in histogram {
+        seen += count;
+        if rank < seen {
+            return value as f64;
+        }
+    }
+    unreachable!("rank out of bounds")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gillespie::Trajectory;
+    use crate::stats::{bootstrap, compare_ensembles, kolmogorov, EnsembleStats};
+
+    fn trajectory(values: &[isize]) -> Trajectory {
+        Trajectory {
+            times: vec![0., 1.],
+            species: vec![values.to_vec(), vec![0, 0]],
+            firings: Vec::new(),
+            observables: Vec::new(),
+            metadata: None,
+        }
+    }
+
+    #[test]
+    fn statistics() {
+        let mut stats = EnsembleStats::new(&[0., 1.], 2).with_quantiles();
+        for values in [[1, 10], [2, 20], [3, 30], [6, 40]] {
+            stats.push(&trajectory(&values));
+        }
+        assert_eq!(stats.count(), 4);
+        assert_eq!(stats.mean(0), [3., 25.]);
+        assert_eq!(stats.variance(0), [14. / 3., 500. / 3.]);
+        assert_eq!(stats.variance(1), [0., 0.]);
+        assert_eq!(stats.quantile(0, 0.), [1., 10.]);
+        assert_eq!(stats.quantile(0, 0.5), [2.5, 25.]);
+        assert_eq!(stats.quantile(0, 1.), [6., 40.]);
+        // the same as the quantiles of the predictive module
+        assert_eq!(stats.quantile(0, 0.625), [2.875, 28.75]);
+    }
+
+    #[test]
+    fn merge() {
+        let values = [[1, 10], [2, 20], [3, 30], [6, 40], [6, 45]];
+        let mut all = EnsembleStats::new(&[0., 1.], 2).with_quantiles();
+        let mut first = all.clone();
+        let mut second = all.clone();
+        for (i, values) in values.iter().enumerate() {
+            all.push(&trajectory(values));
+            if i < 2 {
+                first.push(&trajectory(values));
+            } else {
+                second.push(&trajectory(values));
+            }
+        }
+        first.merge(&second);
+        assert_eq!(first.count(), all.count());
+        for s in 0..2 {
+            for (a, b) in first.mean(s).iter().zip(all.mean(s)) {
+                assert!((a - b).abs() < 1e-12);
+            }
+            for (a, b) in first.variance(s).iter().zip(all.variance(s)) {
+                assert!((a - b).abs() < 1e-12);
+            }
+            assert_eq!(first.quantile(s, 0.3), all.quantile(s, 0.3));
+        }
+        let empty = EnsembleStats::new(&[0., 1.], 2).with_quantiles();
+        first.merge(&empty);
+        assert_eq!(first.count(), 5);
+        assert!(EnsembleStats::new(&[0.], 1).variance(0)[0].is_nan());
+    }
+
+    #[test]
+    fn comparison() {
+        let mut a = EnsembleStats::new(&[0., 1.], 2).with_quantiles();
+        let mut b = a.clone();
+        for values in [[1, 10], [2, 20], [3, 30], [4, 40]] {
+            a.push(&trajectory(&values));
+        }
+        for values in [[1, 10], [2, 10], [3, 10], [4, 40]] {
+            b.push(&trajectory(&values));
+        }
+        b.push(&trajectory(&[4, 40]));
+        let comparison = compare_ensembles(&a, &a);
+        assert_eq!(comparison.times, [0., 1.]);
+        assert_eq!(comparison.ks_statistics, [[0., 0.], [0., 0.]]);
+        assert_eq!(comparison.p_values, [[1., 1.], [1., 1.]]);
+        assert_eq!(comparison.total_variations, [[0., 0.], [0., 0.]]);
+        let comparison = compare_ensembles(&a, &b);
+        // cumulative distributions 1/4, 1/2, 3/4, 1 against 1/5, 2/5,
+        // 3/5, 1, then 1/4, 1/2, 3/4, 1 against 3/5, 3/5, 3/5, 1
+        let ks = &comparison.ks_statistics[0];
+        assert!((ks[0] - 0.15).abs() < 1e-12);
+        assert!((ks[1] - 0.35).abs() < 1e-12);
+        // frequencies 1/4 each against 1/5, 1/5, 1/5, 2/5, then against
+        // 3/5, 0, 0, 2/5
+        let tv = &comparison.total_variations[0];
+        assert!((tv[0] - 0.15).abs() < 1e-12);
+        assert!((tv[1] - 0.5).abs() < 1e-12);
+        assert!(comparison.p_values[0][0] > comparison.p_values[0][1]);
+        assert_eq!(comparison.ks_statistics[1], [0., 0.]);
+        // reference values of the Kolmogorov distribution
+        assert!((kolmogorov(1.36) - 0.0494).abs() < 1e-3);
+        assert!((kolmogorov(1.63) - 0.0098).abs() < 1e-3);
+        assert_eq!(kolmogorov(0.), 1.);
+    }
+
+    #[test]
+    fn bootstrap_intervals() {
+        // exponential sample of mean 1, by inversion on a regular grid
+        let values: Vec<f64> = (0..200)
+            .map(|i| -(1. - (i as f64 + 0.5) / 200.).ln())
+            .collect();
+        let mean =
+            |sample: &[&f64]| vec![sample.iter().copied().sum::<f64>() / sample.len() as f64];
+        let intervals = bootstrap(&values, mean, 2000, 0.95, 0);
+        let interval = intervals[0];
+        assert!((interval.estimate - 1.).abs() < 0.03);
+        // standard error of about 1 / sqrt(200)
+        assert!(interval.lower < interval.estimate && interval.estimate < interval.upper);
+        assert!(
+            (interval.upper - interval.lower - 0.28).abs() < 0.05,
+            "{interval:?}"
+        );
+        assert_eq!(intervals, bootstrap(&values, mean, 2000, 0.95, 0));
+        assert_ne!(intervals, bootstrap(&values, mean, 2000, 0.95, 1));
+        let narrower = bootstrap(&values, mean, 2000, 0.5, 0)[0];
+        assert!(narrower.lower > interval.lower && narrower.upper < interval.upper);
+        // several values: the median and the maximum
+        let median_max = |sample: &[&f64]| {
+            let mut sorted: Vec<f64> = sample.iter().map(|&&x| x).collect();
+            sorted.sort_by(f64::total_cmp);
+            vec![sorted[sorted.len() / 2], sorted[sorted.len() - 1]]
+        };
+        let intervals = bootstrap(&values, median_max, 500, 0.9, 0);
+        assert!(intervals[0].lower < 2f64.ln() && 2f64.ln() < intervals[0].upper);
+        assert_eq!(intervals[1].upper, intervals[1].estimate);
+        // first passages that never happen are ignored
+        let passages = [Some(1.), None, Some(3.)];
+        let first = |sample: &[&Option<f64>]| {
+            let times: Vec<f64> = sample.iter().filter_map(|t| **t).collect();
+            vec![times.iter().copied().fold(f64::NAN, f64::min), f64::NAN]
+        };
+        let intervals = bootstrap(&passages, first, 100, 0.95, 0);
+        assert_eq!(intervals[0].estimate, 1.);
+        assert!(intervals[0].lower == 1. && intervals[0].upper == 3.);
+        assert!(intervals[1].lower.is_nan() && intervals[1].upper.is_nan());
+    }
+}
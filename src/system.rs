@@ -0,0 +1,67 @@
+//! Common interface of the problems of both APIs.
+//!
+//! The structures generated by [`define_system`](crate::define_system)
+//! and [`Gillespie`] both implement [`SSASystem`], so that generic
+//! analysis code can accept either of them.
+//!
+//! ```
+//! use rebop::define_system;
+//! use rebop::gillespie::{Gillespie, Rate};
+//! use rebop::system::SSASystem;
+//!
+//! fn final_total<S: SSASystem>(mut problem: S) -> isize {
+//!     problem.seed(0);
+//!     problem.advance_until(100.);
+//!     problem.species().iter().sum()
+//! }
+//!
+//! define_system! {
+//!     r_inf = 1e-4 r_heal = 0.01;
+//!     SIR { S = 999, I = 1, R }
+//!     infection   : S + I => 2 I  @ r_inf
+//!     healing     : I     => R    @ r_heal
+//! }
+//! let sir = SIR::new();
+//! assert_eq!(final_total(sir.to_gillespie()), 1000);
+//! assert_eq!(final_total(sir), 1000);
+//! ```
+
+use std::borrow::Cow;
+
+use rand::{Rng, SeedableRng};
+
+use crate::gillespie::Gillespie;
+
+/// Problem that can be simulated with the stochastic simulation
+/// algorithm.
+pub trait SSASystem {
+    /// Returns the number of species.
+    fn nb_species(&self) -> usize;
+    /// Returns the current amounts of all species, in the order of
+    /// their definition.
+    fn species(&self) -> Cow<'_, [isize]>;
+    /// Returns the current time.
+    fn t(&self) -> f64;
+    /// Seeds the random number generator.
+    fn seed(&mut self, seed: u64);
+    /// Simulates the problem until `tmax`.
+    fn advance_until(&mut self, tmax: f64);
+}
+
+impl<R: Rng + SeedableRng> SSASystem for Gillespie<R> {
+    fn nb_species(&self) -> usize {
+        Gillespie::nb_species(self)
+    }
+    fn species(&self) -> Cow<'_, [isize]> {
+        Cow::Borrowed(Gillespie::species(self))
+    }
+    fn t(&self) -> f64 {
+        self.get_time()
+    }
+    fn seed(&mut self, seed: u64) {
+        Gillespie::seed(self, seed)
+    }
+    fn advance_until(&mut self, tmax: f64) {
+        Gillespie::advance_until(self, tmax)
+    }
+}
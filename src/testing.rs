@@ -0,0 +1,389 @@
+//! Property checks for user models.
+//!
+//! [`model_properties`] simulates a problem with several seeds and checks
+//! basic invariants of the simulation, so that it can be called from the
+//! test suite of any model:
+//!
+//! * the conservation laws of the stoichiometry hold;
+//! * the amounts of species never become negative;
+//! * the simulation is reproducible under the same seed;
+//! * the sparse and dense representations of the reactions agree.
+//!
+//! [`order_sensitivity`] shows how the simulation depends on the order
+//! in which the reactions were added: it reruns the same seed with
+//! shuffled orders and reports the timings and where the trajectories
+//! diverge.
+//!
+//! ```
+//! use rebop::gillespie::{Gillespie, Rate};
+//! use rebop::testing::model_properties;
+//! let mut sir = Gillespie::new([999, 1, 0]);
+//! sir.add_reaction(Rate::lma(1e-4, [1, 1, 0]), [-1, 1, 0]);
+//! sir.add_reaction(Rate::lma(0.01, [0, 1, 0]), [0, -1, 1]);
+//! model_properties(&sir);
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use rand::rngs::SmallRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+use crate::conservation::null_space;
+use crate::gillespie::Gillespie;
+
+/// Number of seeds used by [`model_properties`].
+pub const NB_SEEDS: u64 = 10;
+/// Number of reactions simulated for each seed by [`model_properties`].
+pub const NB_REACTIONS: usize = 1000;
+
+/// Invariant that does not hold in a simulation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PropertyViolation {
+    /// The weighted sum of species `law` changed.
+    Conservation {
+        seed: u64,
+        step: usize,
+        law: Vec<f64>,
+    },
+    /// The amount of a species became negative.
+    Negative {
+        seed: u64,
+        step: usize,
+        species: usize,
+        amount: isize,
+    },
+    /// Two simulations with the same seed differ.
+    NotReproducible { seed: u64 },
+    /// The sparse and dense representations of a reaction differ.
+    SparseDense {
+        seed: u64,
+        step: usize,
+        reaction: usize,
+    },
+}
+
+impl fmt::Display for PropertyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PropertyViolation::Conservation { seed, step, law } => write!(
+                f,
+                "conservation law {law:?} violated after {step} reactions (seed {seed})"
+            ),
+            PropertyViolation::Negative {
+                seed,
+                step,
+                species,
+                amount,
+            } => write!(
+                f,
+                "species {species} has a negative amount {amount} after {step} reactions (seed {seed})"
+            ),
+            PropertyViolation::NotReproducible { seed } => {
+                write!(f, "two simulations with seed {seed} differ")
+            }
+            PropertyViolation::SparseDense {
+                seed,
+                step,
+                reaction,
+            } => write!(
+                f,
+                "sparse and dense representations of reaction {reaction} differ \
+                 after {step} reactions (seed {seed})"
+            ),
+        }
+    }
+}
+
+impl Error for PropertyViolation {}
+
+/// Checks the invariants of `model` on [`NB_SEEDS`] simulations of
+/// [`NB_REACTIONS`] reactions each, starting from its current state.
+///
+/// # Panics
+///
+/// Panics with a description of the first invariant that does not hold.
+pub fn model_properties(model: &Gillespie) {
+    if let Err(violation) = check_model_properties(model, NB_SEEDS, NB_REACTIONS) {
+        panic!("{violation}");
+    }
+}
+
+/// Checks the invariants of `model` on `nb_seeds` simulations of
+/// `nb_reactions` reactions each, starting from its current state, and
+/// returns the first invariant that does not hold.
+pub fn check_model_properties(
+    model: &Gillespie,
+    nb_seeds: u64,
+    nb_reactions: usize,
+) -> Result<(), PropertyViolation> {
+    let laws = conservation_laws(model);
+    let nb_species = model.nb_species();
+    let dense: Vec<_> = model
+        .reactions()
+        .iter()
+        .map(|(rate, jump)| {
+            (
+                rate.clone().dense(nb_species),
+                jump.clone().dense(nb_species),
+            )
+        })
+        .collect();
+    let weighted = |law: &[f64], species: &[isize]| -> f64 {
+        law.iter().zip(species).map(|(w, &s)| w * s as f64).sum()
+    };
+    let initial: Vec<f64> = laws
+        .iter()
+        .map(|law| weighted(law, model.species()))
+        .collect();
+    for seed in 0..nb_seeds {
+        let mut trajectories = Vec::new();
+        for _ in 0..2 {
+            let mut problem = model.clone();
+            problem.seed(seed);
+            let mut trajectory = Vec::with_capacity(nb_reactions);
+            for step in 0..nb_reactions {
+                let species = problem.species();
+                for (reaction, ((rate, jump), (dense_rate, dense_jump))) in
+                    problem.reactions().iter().zip(&dense).enumerate()
+                {
+                    let (r, dense_r) = (rate.rate(species), dense_rate.rate(species));
+                    let (mut after, mut dense_after) = (species.to_vec(), species.to_vec());
+                    jump.affect(&mut after);
+                    dense_jump.affect(&mut dense_after);
+                    let same_rate =
+                        r == dense_r || (r.is_nan() && dense_r.is_nan()) || close(r, dense_r);
+                    if !same_rate || after != dense_after {
+                        return Err(PropertyViolation::SparseDense {
+                            seed,
+                            step,
+                            reaction,
+                        });
+                    }
+                }
+                problem.advance_one_reaction();
+                if problem.get_time() == f64::INFINITY {
+                    break;
+                }
+                let species = problem.species();
+                if let Some((index, &amount)) = species.iter().enumerate().find(|(_, &s)| s < 0) {
+                    return Err(PropertyViolation::Negative {
+                        seed,
+                        step: step + 1,
+                        species: index,
+                        amount,
+                    });
+                }
+                for (law, &value) in laws.iter().zip(&initial) {
+                    if !close(weighted(law, species), value) {
+                        return Err(PropertyViolation::Conservation {
+                            seed,
+                            step: step + 1,
+                            law: law.clone(),
+                        });
+                    }
+                }
+                trajectory.push((problem.get_time(), species.to_vec()));
+            }
+            trajectories.push(trajectory);
+        }
+        if trajectories[0] != trajectories[1] {
+            return Err(PropertyViolation::NotReproducible { seed });
+        }
+    }
+    Ok(())
+}
+
+/// Simulation of a problem with its reactions in a given order, as
+/// returned by [`order_sensitivity`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderRun {
+    /// Order of the reactions: the reaction of index `order[i]` in the
+    /// original problem comes in position `i`.
+    pub order: Vec<usize>,
+    /// Time taken by the simulation.
+    pub duration: Duration,
+    /// Time of the first reaction at which the trajectory differs from
+    /// the one in the original order, if it does before `tmax`.
+    pub divergence: Option<f64>,
+    /// Amounts of the species at `tmax`.
+    pub species: Vec<isize>,
+}
+
+/// Simulates `model` until `tmax` with the seed `seed`, first with its
+/// reactions in their original order, then in `nb_orders` random
+/// orders drawn from the same seed.
+///
+/// Permuting the reactions changes which reaction a random number
+/// selects, and the order of the floating-point sums of rates, so the
+/// trajectories usually diverge even though their distribution is the
+/// same.  The timings show how much the performance depends on the
+/// order, as reactions that happen more often are found faster when
+/// they come first.
+///
+/// ```
+/// use rebop::gillespie::{Gillespie, Rate};
+/// use rebop::testing::order_sensitivity;
+/// let mut sir = Gillespie::new([999, 1, 0]);
+/// sir.add_reaction(Rate::lma(1e-4, [1, 1, 0]), [-1, 1, 0]);
+/// sir.add_reaction(Rate::lma(0.01, [0, 1, 0]), [0, -1, 1]);
+/// let runs = order_sensitivity(&sir, 250., 3, 0);
+/// assert_eq!(runs[0].order, [0, 1]);
+/// assert_eq!(runs[0].divergence, None);
+/// for run in &runs {
+///     println!("{:?}: {:?}, diverges at {:?}", run.order, run.duration, run.divergence);
+/// }
+/// ```
+///
+/// # Panics
+///
+/// Panics if a reaction has a custom waiting time.
+pub fn order_sensitivity(
+    model: &Gillespie,
+    tmax: f64,
+    nb_orders: usize,
+    seed: u64,
+) -> Vec<OrderRun> {
+    assert!(
+        !model.has_waiting_times(),
+        "custom waiting times are not supported"
+    );
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let mut order: Vec<usize> = (0..model.nb_reactions()).collect();
+    let mut runs = Vec::with_capacity(nb_orders + 1);
+    for iorder in 0..=nb_orders {
+        if iorder > 0 {
+            order.shuffle(&mut rng);
+        }
+        let mut problem = model.clone();
+        problem.reorder_reactions(&order);
+        let mut timed = problem.clone();
+        timed.seed(seed);
+        let start = Instant::now();
+        timed.advance_until(tmax);
+        let duration = start.elapsed();
+        runs.push(OrderRun {
+            order: order.clone(),
+            duration,
+            divergence: divergence(model, &problem, tmax, seed),
+            species: timed.species().to_vec(),
+        });
+    }
+    runs
+}
+
+/// Returns the time of the first reaction at which the trajectories of
+/// `a` and `b` with the same seed differ, if it is not after `tmax`.
+fn divergence(a: &Gillespie, b: &Gillespie, tmax: f64, seed: u64) -> Option<f64> {
+    let (mut a, mut b) = (a.clone(), b.clone());
+    a.seed(seed);
+    b.seed(seed);
+    loop {
+        a.advance_one_reaction();
+        b.advance_one_reaction();
+        let t = a.get_time().min(b.get_time());
+        if t > tmax || t == f64::INFINITY {
+            return None;
+        }
+        if a.get_time() != b.get_time() || a.species() != b.species() {
+            return Some(t);
+        }
+    }
+}
+
+/// Whether `a` and `b` are equal up to rounding errors.
+fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() <= 1e-9 * (1. + a.abs().max(b.abs()))
+}
+
+/// Returns a basis of the conservation laws of `model`: the weights `w`
+/// such that the sum of `w[i]` times the amount of species `i` is not
+/// changed by any reaction.
+fn conservation_laws(model: &Gillespie) -> Vec<Vec<f64>> {
+    let order: Vec<usize> = (0..model.nb_species()).collect();
+    null_space(model, &order)
+        .into_iter()
+        .map(|(_, law)| law)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gillespie::{Gillespie, Rate};
+    use crate::testing::{
+        check_model_properties, conservation_laws, order_sensitivity, PropertyViolation,
+    };
+    #[test]
+    fn laws() {
+        let mut sir = Gillespie::new([999, 1, 0]);
+        sir.add_reaction(Rate::lma(1e-4, [1, 1, 0]), [-1, 1, 0]);
+        sir.add_reaction(Rate::lma(0.01, [0, 1, 0]), [0, -1, 1]);
+        assert_eq!(conservation_laws(&sir), [[1., 1., 1.]]);
+        let mut dimers = Gillespie::new([1, 0, 0, 0]);
+        dimers.add_reaction(Rate::lma(1000., [0, 1, 0, 0]), [0, 0, 1, 0]);
+        dimers.add_reaction(Rate::lma(0.001, [0, 0, 2, 0]), [0, 0, -2, 1]);
+        dimers.add_reaction(Rate::lma(1., [0, 0, 1, 0]), [0, 0, -1, 0]);
+        assert_eq!(
+            conservation_laws(&dimers),
+            [[1., 0., 0., 0.], [0., 1., 0., 0.]]
+        );
+    }
+    #[test]
+    fn violations() {
+        let mut dimers = Gillespie::new([1, 0, 0, 0]);
+        dimers.add_reaction(Rate::lma(25., [1, 0, 0, 0]), [0, 1, 0, 0]);
+        dimers.add_reaction(Rate::lma(1000., [0, 1, 0, 0]), [0, 0, 1, 0]);
+        dimers.add_reaction(Rate::lma(0.001, [0, 0, 2, 0]), [0, 0, -2, 1]);
+        dimers.add_reaction(Rate::lma(0.1, [0, 1, 0, 0]), [0, -1, 0, 0]);
+        dimers.add_reaction(Rate::lma(1., [0, 0, 1, 0]), [0, 0, -1, 0]);
+        assert_eq!(check_model_properties(&dimers, 3, 500), Ok(()));
+        // a degradation that does not depend on the amount
+        let mut broken = Gillespie::new([2]);
+        broken.add_reaction(Rate::lma(1., [0]), [-1]);
+        assert_eq!(
+            check_model_properties(&broken, 1, 10),
+            Err(PropertyViolation::Negative {
+                seed: 0,
+                step: 3,
+                species: 0,
+                amount: -1
+            })
+        );
+    }
+    #[test]
+    fn reaction_orders() {
+        let mut sir = Gillespie::new([999, 1, 0]);
+        sir.add_reaction(Rate::lma(1e-4, [1, 1, 0]), [-1, 1, 0]);
+        sir.add_reaction(Rate::lma(0.01, [0, 1, 0]), [0, -1, 1]);
+        let runs = order_sensitivity(&sir, 250., 5, 0);
+        assert_eq!(runs.len(), 6);
+        assert_eq!(runs[0].divergence, None);
+        let mut original = sir.clone();
+        original.seed(0);
+        original.advance_until(250.);
+        assert_eq!(runs[0].species, original.species());
+        for run in &runs[1..] {
+            let mut order = run.order.clone();
+            order.sort();
+            assert_eq!(order, [0, 1]);
+            // swapping the reactions changes the selected reactions
+            assert_eq!(run.divergence.is_some(), run.order == [1, 0]);
+            assert_eq!(run.species.iter().sum::<isize>(), 1000);
+        }
+        // the same seed gives the same orders
+        let again = order_sensitivity(&sir, 250., 5, 0);
+        for (run, other) in runs.iter().zip(&again) {
+            assert_eq!(
+                (&run.order, run.divergence),
+                (&other.order, other.divergence)
+            );
+        }
+        // with a single reaction, there is nothing to permute
+        let mut birth = Gillespie::new([0]);
+        birth.add_reaction(Rate::lma(10., [0]), [1]);
+        let runs = order_sensitivity(&birth, 10., 2, 1);
+        assert!(runs.iter().all(|run| run.divergence.is_none()));
+    }
+}
@@ -0,0 +1,193 @@
+//! JavaScript bindings, with the `wasm` feature.
+//!
+//! They mirror the Python `Gillespie` class on top of
+//! [`GillespieBuilder`], for interactive demonstrations in the browser:
+//! the species are declared by the reactions that use them, the initial
+//! amounts and the rates can be changed between runs, for example from
+//! sliders, and each run returns a [`Run`] with one array of amounts per
+//! species.
+//!
+//! ```js
+//! import { Gillespie } from "rebop";
+//! const sir = new Gillespie();
+//! sir.add_reaction_str("S + I -> 2 I @ 1e-4");
+//! sir.add_reaction_str("I -> R @ 0.01");
+//! sir.set_init("S", 999);
+//! sir.set_init("I", 1);
+//! const run = sir.run(250, 250, 42);
+//! plot(run.times(), run.amounts("I"));
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+
+use wasm_bindgen::prelude::*;
+
+use crate::builder::GillespieBuilder;
+use crate::model;
+
+/// Reaction system composed of species and reactions.
+#[wasm_bindgen]
+#[derive(Clone, Debug, Default)]
+pub struct Gillespie {
+    /// Names of the species, in the order in which they appeared.
+    species: Vec<String>,
+    reactions: Vec<(f64, Vec<String>, Vec<String>)>,
+    init: HashMap<String, u32>,
+}
+
+#[wasm_bindgen]
+impl Gillespie {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Gillespie::default()
+    }
+    /// Adds a reaction of law of mass action rate `rate`, from the
+    /// species `reactants` to the species `products`, repeated according
+    /// to their stoichiometries.
+    pub fn add_reaction(&mut self, rate: f64, reactants: Vec<String>, products: Vec<String>) {
+        for name in reactants.iter().chain(&products) {
+            if !self.species.contains(name) {
+                self.species.push(name.clone());
+            }
+        }
+        self.reactions.push((rate, reactants, products));
+    }
+    /// Adds a reaction written as an equation, like `"2 A + B -> C @
+    /// 0.1"`, see [`model::Reaction`].
+    pub fn add_reaction_str(&mut self, equation: &str) -> Result<(), JsError> {
+        let reaction: model::Reaction = equation.parse()?;
+        let model::RateSpec::Value(rate) = reaction.rate else {
+            return Err(JsError::new(&format!(
+                "the rate of reaction `{equation}` is not a number"
+            )));
+        };
+        let expand = |side: &BTreeMap<String, u32>| {
+            side.iter()
+                .flat_map(|(name, &n)| std::iter::repeat_n(name.clone(), n as usize))
+                .collect()
+        };
+        self.add_reaction(
+            rate,
+            expand(&reaction.reactants),
+            expand(&reaction.products),
+        );
+        Ok(())
+    }
+    /// Sets the rate of the reaction of index `index`, in the order in
+    /// which they were added.
+    pub fn set_rate(&mut self, index: usize, rate: f64) -> Result<(), JsError> {
+        match self.reactions.get_mut(index) {
+            Some(reaction) => {
+                reaction.0 = rate;
+                Ok(())
+            }
+            None => Err(JsError::new(&format!(
+                "unknown reaction of index {index}, the system has {} reactions",
+                self.reactions.len()
+            ))),
+        }
+    }
+    /// Sets the initial amount of the species `name`, `0` by default.
+    pub fn set_init(&mut self, name: &str, amount: u32) {
+        self.init.insert(name.to_string(), amount);
+    }
+    /// Names of the species, in the order in which they appeared.
+    pub fn species(&self) -> Vec<String> {
+        self.species.clone()
+    }
+    pub fn nb_reactions(&self) -> usize {
+        self.reactions.len()
+    }
+    /// Runs the system until `tmax` with `nb_steps` steps, from the
+    /// initial amounts, with the random `seed` if given.
+    pub fn run(&self, tmax: f64, nb_steps: usize, seed: Option<u32>) -> Result<Run, JsError> {
+        if nb_steps == 0 {
+            return Err(JsError::new("runs need at least one step"));
+        }
+        let mut builder = GillespieBuilder::new();
+        for name in &self.species {
+            let initial = self.init.get(name).copied().unwrap_or(0);
+            builder = builder.species(name, initial as isize);
+        }
+        for (i, (rate, reactants, products)) in self.reactions.iter().enumerate() {
+            builder = builder
+                .reaction(&format!("r{i}"))
+                .reactants(reactants)
+                .products(products)
+                .rate_lma(*rate);
+        }
+        let mut g = builder.build()?;
+        if let Some(seed) = seed {
+            g.seed(seed.into());
+        }
+        let times: Vec<f64> = (0..=nb_steps)
+            .map(|i| tmax * i as f64 / nb_steps as f64)
+            .collect();
+        let mut amounts = vec![Vec::with_capacity(times.len()); self.species.len()];
+        for &t in &times {
+            g.advance_until(t);
+            for (values, &amount) in amounts.iter_mut().zip(g.species()) {
+                values.push(amount as f64);
+            }
+        }
+        Ok(Run {
+            times,
+            species: self.species.clone(),
+            amounts,
+        })
+    }
+}
+
+/// Result of [`Gillespie::run`].
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct Run {
+    times: Vec<f64>,
+    species: Vec<String>,
+    /// `amounts[s][i]` is the amount of species `s` at `times[i]`.
+    amounts: Vec<Vec<f64>>,
+}
+
+#[wasm_bindgen]
+impl Run {
+    /// Time points of the run.
+    pub fn times(&self) -> Vec<f64> {
+        self.times.clone()
+    }
+    /// Amounts of the species `name` at the time points, if it exists.
+    pub fn amounts(&self, name: &str) -> Option<Vec<f64>> {
+        let s = self.species.iter().position(|species| species == name)?;
+        Some(self.amounts[s].clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wasm::Gillespie;
+
+    #[test]
+    fn sir() {
+        let mut sir = Gillespie::new();
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        sir.add_reaction(1e-4, names(&["S", "I"]), names(&["I", "I"]));
+        sir.add_reaction_str("I -> R @ 0.01").unwrap();
+        assert_eq!(sir.species(), ["S", "I", "R"]);
+        sir.set_init("S", 999);
+        sir.set_init("I", 1);
+        let run = sir.run(250., 10, Some(0)).unwrap();
+        assert_eq!(run.times().len(), 11);
+        let (s, i, r) = (
+            run.amounts("S").unwrap(),
+            run.amounts("I").unwrap(),
+            run.amounts("R").unwrap(),
+        );
+        assert_eq!((s[0], i[0], r[0]), (999., 1., 0.));
+        assert!((0..11).all(|k| s[k] + i[k] + r[k] == 1000.));
+        assert!(run.amounts("X").is_none());
+        // the same seed gives the same run
+        assert_eq!(sir.run(250., 10, Some(0)).unwrap().amounts("R"), Some(r));
+        sir.set_rate(1, 0.).unwrap();
+        let run = sir.run(250., 10, Some(0)).unwrap();
+        assert_eq!(run.amounts("R").unwrap()[10], 0.);
+    }
+}
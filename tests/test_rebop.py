@@ -1,3 +1,11 @@
+import copy
+import pickle
+import sys
+import threading
+import time
+import types
+from pathlib import Path
+
 import numpy as np
 import numpy.testing as npt
 import pytest
@@ -36,6 +44,243 @@ def test_fixed_seed() -> None:
     assert ds.R[-1] == 834
 
 
+def test_metadata() -> None:
+    sir = sir_model()
+    ds = sir.run({"S": 999, "I": 1}, tmax=250, nb_steps=250, seed=42)
+    assert ds.attrs["version"] == rebop.__version__
+    assert ds.attrs["seed"] == 42
+    assert ds.attrs["method"] == "direct"
+    assert ds.attrs["wall_time"] > 0
+    assert ds.attrs["model_hash"] == sir.model_hash()
+    ds = sir.run({"S": 999, "I": 1}, tmax=250, nb_steps=250)
+    assert "seed" not in ds.attrs
+    phases = [
+        {"start": 0, "end": 10, "nb_steps": 10},
+        {"start": 10, "end": 20, "nb_steps": 10, "method": "r_leaping:10"},
+    ]
+    ds = sir.run_schedule({"S": 999, "I": 1}, phases)
+    assert ds.attrs["method"] == "direct,r_leaping:10"
+
+
+def test_model_hash() -> None:
+    sir = sir_model()
+    reordered = rebop.Gillespie()
+    reordered.add_reaction(1e-2, ["I"], ["R"])
+    reordered.add_reaction(1e-4, ["I", "S"], ["I", "I"])
+    assert reordered.model_hash() == sir.model_hash()
+    other = rebop.Gillespie()
+    other.add_reaction(2e-4, ["S", "I"], ["I", "I"])
+    other.add_reaction(1e-2, ["I"], ["R"])
+    assert other.model_hash() != sir.model_hash()
+
+
+def test_add_reaction_str() -> None:
+    sir = rebop.Gillespie()
+    sir.add_reaction_str("infection: S + I -> 2 I @ 1e-4")
+    sir.add_reaction_str("I -> R @ 0.01")
+    assert sir.model_hash() == sir_model().model_hash()
+    birth_death = rebop.Gillespie()
+    birth_death.add_reaction_str("0 -> A @ 10", reverse_rate=1)
+    assert birth_death.nb_species() == 1
+    assert birth_death.nb_reactions() == 2
+    with pytest.raises(ValueError, match="no `->`"):
+        sir.add_reaction_str("S + I => 2 I @ 1e-4")
+    with pytest.raises(ValueError, match="not a number"):
+        sir.add_reaction_str("S + I -> 2 I @ r_inf")
+
+
+def test_introspection() -> None:
+    sir = sir_model()
+    assert sir.species == ["S", "I", "R"]
+    infection, healing = sir.reactions
+    assert infection.reactants == {"S": 1, "I": 1}
+    assert infection.products == {"I": 2}
+    assert infection.rate == 1e-4
+    assert healing == sir_model().reactions[1]
+    assert healing != infection
+    assert repr(healing) == 'Reaction("I -> R @ 0.01")'
+    assert sir.init == {}
+    sir.init = {"S": 999, "I": 1}
+    ds = sir.run(sir.init, tmax=10, nb_steps=10, seed=0)
+    assert ds.S[0] == 999
+
+
+def test_modify_reactions() -> None:
+    sir = sir_model()
+    sir.set_rate(0, 2e-4)
+    assert sir.reactions[0].rate == 2e-4
+    sir.add_reaction_str("R -> S @ 0.1")
+    sir.remove_reaction(1)
+    assert [repr(r) for r in sir.reactions] == [
+        'Reaction("I + S -> 2 I @ 0.0002")',
+        'Reaction("R -> S @ 0.1")',
+    ]
+    # no healing
+    ds = sir.run({"S": 999, "I": 1}, tmax=100, nb_steps=10, seed=0)
+    assert all(ds.R == 0)
+    with pytest.raises(IndexError, match="unknown reaction"):
+        sir.set_rate(2, 1.0)
+    with pytest.raises(IndexError, match="unknown reaction"):
+        sir.remove_reaction(5)
+    sir.clear()
+    assert sir.species == []
+    assert sir.nb_reactions() == 0
+
+
+def test_pickle() -> None:
+    sir = sir_model()
+    sir.init = {"S": 999, "I": 1}
+    for clone in [pickle.loads(pickle.dumps(sir)), copy.copy(sir), copy.deepcopy(sir)]:
+        assert clone.species == sir.species
+        assert clone.reactions == sir.reactions
+        assert clone.init == sir.init
+        assert clone.model_hash() == sir.model_hash()
+        xr.testing.assert_equal(
+            clone.run(clone.init, tmax=10, nb_steps=10, seed=0),
+            sir.run(sir.init, tmax=10, nb_steps=10, seed=0),
+        )
+    # the copies are independent
+    clone = copy.deepcopy(sir)
+    clone.set_rate(0, 0.0)
+    assert sir.reactions[0].rate == 1e-4
+
+
+def test_t_eval() -> None:
+    sir = sir_model()
+    t_eval = np.array([0.0, 0.5, 2.0, 10.0, 10.0, 250.0])
+    ds = sir.run({"S": 999, "I": 1}, t_eval=t_eval, seed=42)
+    npt.assert_array_equal(ds.time, t_eval)
+    npt.assert_array_equal(ds.S + ds.I + ds.R, [1000] * 6)
+    assert ds.S[0] == 999
+    assert ds.S[3] == ds.S[4]
+    with pytest.raises(ValueError, match="sorted"):
+        sir.run({"S": 999, "I": 1}, t_eval=[2.0, 1.0])
+    with pytest.raises(ValueError, match="either"):
+        sir.run({"S": 999, "I": 1}, tmax=10, t_eval=[1.0])
+
+
+def test_resume() -> None:
+    sir = sir_model()
+    init = {"S": 999, "I": 1}
+    full = sir.run(init, tmax=100, nb_steps=100, seed=3)
+    first, state = sir.run(init, tmax=40, nb_steps=40, seed=3, return_state=True)
+    assert isinstance(state, rebop.State)
+    assert state.time == 40
+    assert state.species == {name: int(first[name][-1]) for name in "SIR"}
+    second = sir.run({}, tmax=100, nb_steps=60, resume=state)
+    npt.assert_array_equal(second.time, np.arange(40, 101))
+    # the same as a single run
+    xr.testing.assert_equal(first, full.sel(time=first.time))
+    xr.testing.assert_equal(second, full.sel(time=second.time))
+    # washout: no more infected individuals, and no more infections
+    sir.set_rate(0, 0.0)
+    washout = sir.run({"I": 0}, t_eval=[40, 50], resume=state)
+    assert all(washout.I == 0)
+    assert all(washout.S == state.species["S"])
+    with pytest.raises(ValueError, match="before the resumed time"):
+        sir.run({}, tmax=10, nb_steps=10, resume=state)
+
+
+def test_firings() -> None:
+    sir = sir_model()
+    ds = sir.run({"S": 999, "I": 1}, tmax=250, nb_steps=250, seed=0, firings=True)
+    assert ds.firings.dims == ("reaction", "time")
+    assert ds.firings.shape == (2, 251)
+    assert all(ds.firings[:, 0] == 0)
+    # infections, then healings
+    npt.assert_array_equal(ds.S.diff("time"), -ds.firings[0, 1:])
+    npt.assert_array_equal(ds.R.diff("time"), ds.firings[1, 1:])
+    assert "firings" not in sir.run({"S": 999, "I": 1}, tmax=10, nb_steps=10)
+    every = sir.run({"S": 999, "I": 1}, tmax=10, nb_steps=0, firings=True)
+    npt.assert_array_equal(every.firings.sum("reaction")[1:-1], 1)
+
+
+def test_max_wall_time() -> None:
+    runaway = rebop.Gillespie()
+    runaway.add_reaction_str("A -> 2 A @ 1")
+    with pytest.raises(TimeoutError, match="more than 0.1 s"):
+        runaway.run({"A": 1}, tmax=100, nb_steps=10, max_wall_time=0.1)
+    ds = runaway.run({"A": 1}, tmax=1, nb_steps=10, max_wall_time=10)
+    assert ds.A[-1] >= 1
+
+
+def test_progress() -> None:
+    sir = sir_model()
+    calls = []
+    sir.run(
+        {"S": 999, "I": 1},
+        tmax=250,
+        nb_steps=250,
+        seed=0,
+        progress=lambda t, fraction: calls.append((t, fraction)),
+    )
+    assert calls[0] == (0, 0)
+    assert calls[-1] == (250, 1)
+    assert [f for _, f in calls] == sorted(f for _, f in calls)
+    calls.clear()
+    sir.run(
+        {"S": 999, "I": 1},
+        t_eval=[10, 20, 50],
+        progress=lambda t, fraction: calls.append((t, fraction)),
+    )
+    assert calls[0] == (0, 0)
+    assert calls[-1] == (50, 1)
+
+
+def test_iter_run() -> None:
+    sir = sir_model()
+    init = {"S": 999, "I": 1}
+    chunks = list(sir.iter_run(init, tmax=250, nb_steps=250, seed=0, chunk_size=100))
+    assert [len(times) for times, _ in chunks] == [100, 100, 51]
+    times = np.concatenate([times for times, _ in chunks])
+    states = np.concatenate([states for _, states in chunks])
+    ds = sir.run(init, tmax=250, nb_steps=250, seed=0)
+    npt.assert_array_equal(times, ds.time)
+    npt.assert_array_equal(states, ds[sir.species].to_array().T)
+    # every reaction
+    chunks = list(sir.iter_run(init, tmax=250, nb_steps=0, seed=0, chunk_size=100))
+    states = np.concatenate([states for _, states in chunks])
+    ds = sir.run(init, tmax=250, nb_steps=0, seed=0)
+    npt.assert_array_equal(states, ds[sir.species].to_array().T)
+    assert chunks[-1][0][-1] >= 250
+
+
+def test_method() -> None:
+    sir = sir_model()
+    ds = sir.run({"S": 999, "I": 1}, tmax=250, nb_steps=250, method="r_leaping:10")
+    assert ds.attrs["method"] == "r_leaping:10"
+    npt.assert_array_equal(ds.S + ds.I + ds.R, 1000)
+    ds = sir.run({"S": 999, "I": 1}, t_eval=[1, 2], method="sorting_direct")
+    assert ds.attrs["method"] == "sorting_direct"
+    with pytest.raises(ValueError, match="unknown method"):
+        sir.run({"S": 999, "I": 1}, tmax=10, nb_steps=10, method="tau")
+    with pytest.raises(ValueError, match="every reaction"):
+        sir.run({"S": 999, "I": 1}, tmax=10, nb_steps=0, method="r_leaping:10")
+
+
+def test_release_gil() -> None:
+    runaway = rebop.Gillespie()
+    runaway.add_reaction_str("A -> 2 A @ 1")
+    ticks = []
+    stop = threading.Event()
+
+    def tick() -> None:
+        while not stop.is_set():
+            ticks.append(time.perf_counter())
+            time.sleep(0.001)
+
+    thread = threading.Thread(target=tick)
+    thread.start()
+    start = time.perf_counter()
+    with pytest.raises(TimeoutError):
+        runaway.run({"A": 1}, tmax=100, nb_steps=1, max_wall_time=0.5)
+    end = time.perf_counter()
+    stop.set()
+    thread.join()
+    # the other thread kept running during the simulation
+    assert sum(start < t < end for t in ticks) > 50
+
+
 @pytest.mark.parametrize("seed", range(10))
 def test_all_reactions(seed: int) -> None:
     tmax = 250
@@ -50,3 +295,412 @@ def test_all_reactions(seed: int) -> None:
     assert set(dds.S.to_numpy()) <= {-1, 0}
     assert set(dds.I.to_numpy()) <= {-1, 1}
     assert set(dds.R.to_numpy()) <= {0, 1}
+
+
+def test_run_schedule() -> None:
+    sir = sir_model()
+    phases = [
+        {"start": 10, "end": 40, "nb_steps": 30},
+        {
+            "start": 40,
+            "end": 100,
+            "nb_steps": 60,
+            "rates": {0: 0.0},
+            "method": "first_reaction",
+        },
+    ]
+    ds = sir.run_schedule({"S": 999, "I": 1}, phases, seed=0)
+    npt.assert_array_equal(ds.time, np.arange(10, 101))
+    npt.assert_array_equal(ds.S + ds.I + ds.R, [1000] * 91)
+    # no infection in the second phase
+    assert all(ds.S[30:] == ds.S[30])
+    with pytest.raises(ValueError, match="previous one ends"):
+        sir.run_schedule({}, [phases[0], {**phases[1], "start": 50}])
+    with pytest.raises(ValueError, match="unknown method"):
+        sir.run_schedule({}, [{**phases[0], "method": "tau_leaping"}])
+
+
+def test_observables() -> None:
+    sir = sir_model()
+    sir.add_observable("alive", {"S": 1, "I": 1})
+    sir.add_observable("weighted", {"I": 2.5})
+    assert sir.observables == {"alive": {"S": 1, "I": 1}, "weighted": {"I": 2.5}}
+    ds = sir.run({"S": 999, "I": 1}, tmax=250, nb_steps=250, seed=0)
+    npt.assert_array_equal(ds.alive, ds.S + ds.I)
+    npt.assert_allclose(ds.weighted, 2.5 * ds.I)
+    clone = pickle.loads(pickle.dumps(sir))
+    assert clone.observables == sir.observables
+    with pytest.raises(ValueError, match="unknown species"):
+        sir.add_observable("total", {"X": 1})
+    with pytest.raises(ValueError, match="name of a species"):
+        sir.add_observable("S", {"I": 1})
+    states = []
+
+    # observables in a stopping condition
+    def stop(_: float, x: dict[str, float]) -> bool:
+        states.append(x)
+        return x["alive"] > 990
+
+    sir.run_paced({"S": 999, "I": 1}, 100, 10, 10_000, stop, seed=0)
+    assert all(x["alive"] == x["S"] + x["I"] for x in states)
+    assert states[-1]["alive"] <= 990 or len(states) == 11
+
+
+def test_conservation_laws() -> None:
+    assert sir_model().conservation_laws() == [{"S": 1, "I": 1, "R": 1}]
+    dimers = rebop.Gillespie()
+    dimers.add_reaction(0.1, ["A", "A"], ["A2"], 1.0)
+    dimers.add_reaction(0.1, ["A2", "B"], ["C"])
+    assert len(dimers.conservation_laws()) == 2
+
+
+def test_to_rust() -> None:
+    source = sir_model().to_rust("SIR", {"S": 999, "I": 1})
+    assert "SIR { S = 999, I = 1, R }" in source
+    assert "r0 : S + I => 2 I @ 0.0001" in source
+    assert "r1 : I => R @ 0.01" in source
+    decay = rebop.Gillespie()
+    decay.add_reaction(1.0, ["my-protein"], [])
+    with pytest.raises(ValueError, match="invalid name"):
+        decay.to_rust()
+
+
+def test_merge() -> None:
+    receptor = rebop.Gillespie()
+    receptor.add_reaction(1e-3, ["L", "R"], ["LR"], 0.1)
+    receptor.add_observable("bound", {"LR": 1.0})
+    receptor.init = {"L": 100, "R": 10}
+    cell = rebop.Gillespie()
+    cell.merge(receptor, prefix="egfr_", rename={"L": "L"})
+    cell.merge(receptor, prefix="her2_", rename={"L": "L"})
+    assert cell.species == ["L", "egfr_R", "egfr_LR", "her2_R", "her2_LR"]
+    assert cell.nb_reactions() == 4
+    assert cell.reactions[2].reactants == {"L": 1, "her2_R": 1}
+    assert cell.init == {"L": 100, "egfr_R": 10, "her2_R": 10}
+    assert cell.observables == {
+        "egfr_bound": {"egfr_LR": 1.0},
+        "her2_bound": {"her2_LR": 1.0},
+    }
+    other = rebop.Gillespie()
+    other.add_reaction(1.0, ["L"], [])
+    other.init = {"L": 5}
+    with pytest.raises(ValueError, match="starts at 100 and at 5"):
+        cell.merge(other)
+    assert cell.nb_reactions() == 4
+
+
+def test_network_export() -> None:
+    sir = sir_model()
+    assert sir.stoichiometry_matrix() == [[-1, 0], [1, -1], [0, 1]]
+    dot = sir.to_dot()
+    assert dot.startswith("digraph network {")
+    assert 's0 [label="S"];' in dot
+    assert 'r0 -> s1 [label="2"];' in dot
+    graphml = sir.to_graphml()
+    assert graphml.count("<node ") == 5
+    assert graphml.count("<edge ") == 5
+
+
+def test_run_paced() -> None:
+    sir = sir_model()
+    states = []
+    sir.run_paced(
+        {"S": 999, "I": 1},
+        tmax=100,
+        nb_steps=10,
+        speed=10_000,
+        callback=lambda t, x: states.append((t, x)),
+        seed=0,
+    )
+    assert [t for t, _ in states] == pytest.approx(range(0, 101, 10))
+    assert states[0][1] == {"S": 999, "I": 1, "R": 0}
+    assert all(sum(x.values()) == 1000 for _, x in states)
+    states.clear()
+
+    def stop(t: float, x: dict[str, int]) -> bool:
+        states.append((t, x))
+        return t < 50
+
+    sir.run_paced({"S": 999, "I": 1}, 100, 10, 10_000, stop)
+    assert len(states) == 6
+    with pytest.raises(ValueError, match="speed"):
+        sir.run_paced({"S": 999, "I": 1}, 100, 10, 0, stop)
+
+
+def test_evaluate() -> None:
+    sir = sir_model()
+    summaries = [
+        {"type": "mean", "species": "R", "time": 250},
+        {"type": "variance", "species": "R", "time": 250},
+        {"type": "time_to_threshold", "species": "I", "threshold": 100, "tmax": 250},
+        {"type": "mean", "species": "S", "time": 0},
+    ]
+    rates = [[1e-4, 0.01], [3e-4, 0.01]]
+    values = sir.evaluate({"S": 999, "I": 1}, rates, summaries, 100, seed=0)
+    assert len(values) == 2
+    assert all(len(v) == 4 for v in values)
+    assert values[1][0] > values[0][0]
+    assert values[1][2] < values[0][2]
+    assert values[0][3] == 999
+    assert values == sir.evaluate({"S": 999, "I": 1}, rates, summaries, 100, seed=0)
+    with pytest.raises(ValueError, match="one rate per reaction"):
+        sir.evaluate({"S": 999, "I": 1}, [[1e-4]], summaries, 10)
+    with pytest.raises(ValueError, match="unknown species"):
+        sir.evaluate({}, rates, [{"type": "mean", "species": "X", "time": 1}], 10)
+
+
+def test_run_ode() -> None:
+    decay = rebop.Gillespie()
+    decay.add_reaction(0.5, ["A"], [])
+    ds = decay.run_ode({"A": 1000}, tmax=4, nb_steps=4)
+    npt.assert_allclose(ds.A, 1000 * np.exp(-0.5 * ds.time), rtol=1e-6)
+    assert ds.attrs["method"] == "ode"
+    ds = decay.run_ode({"A": 1000}, tmax=4, nb_steps=4, params={0: 1.0})
+    npt.assert_allclose(ds.A, 1000 * np.exp(-ds.time), rtol=1e-6)
+    # the SIR model conserves its population
+    ds = sir_model().run_ode({"S": 999, "I": 1}, tmax=250, nb_steps=50)
+    npt.assert_allclose(ds.S + ds.I + ds.R, 1000)
+    assert ds.R[-1] > 500
+    with pytest.raises(IndexError):
+        decay.run_ode({}, 1, 1, params={1: 1.0})
+
+
+def test_run_ensemble() -> None:
+    sir = sir_model()
+    calls = []
+    ds = sir.run_ensemble(
+        {"S": 999, "I": 1},
+        tmax=250,
+        nb_steps=250,
+        nb_runs=20,
+        seed=0,
+        progress=lambda completed, total: calls.append((completed, total)),
+    )
+    assert ds.S.dims == ("run", "time")
+    assert ds.S.shape == (20, 251)
+    npt.assert_array_equal(ds.S + ds.I + ds.R, 1000)
+    assert calls[0] == (0, 20)
+    assert calls[-1] == (20, 20)
+    assert [c for c, _ in calls] == sorted({c for c, _ in calls})
+    # the attributes, which include the wall time, are not compared
+    again = sir.run_ensemble({"S": 999, "I": 1}, 250, 250, nb_runs=20, seed=0)
+    xr.testing.assert_equal(ds, again)
+    assert ds.attrs["mode"] == "trajectories"
+
+
+def test_sweep() -> None:
+    sir = sir_model()
+    da = sir.sweep(
+        {"S": 999, "I": 1},
+        {0: [1e-4, 2e-4, 3e-4], 1: [0.01, 0.02]},
+        tmax=100,
+        nb_steps=10,
+        nb_replicates=4,
+        seed=0,
+    )
+    assert da.dims == ("combo", "replicate", "time", "species")
+    assert da.shape == (6, 4, 11, 3)
+    assert list(da.species) == ["S", "I", "R"]
+    npt.assert_array_equal(da.rate_0, [1e-4, 1e-4, 2e-4, 2e-4, 3e-4, 3e-4])
+    npt.assert_array_equal(da.rate_1, [0.01, 0.02] * 3)
+    npt.assert_array_equal(da.sum("species"), 1000)
+    assert da.attrs["seed"] == 0
+    # the replicates of the original rates are the runs with the same seeds
+    again = sir.sweep({"S": 999, "I": 1}, {0: [1e-4]}, 100, 10, 4, seed=0)
+    npt.assert_array_equal(da[0], again[0])
+    with pytest.raises(IndexError):
+        sir.sweep({}, {3: [1.0]}, 1, 1, 1)
+    with pytest.raises(ValueError, match="no rate"):
+        sir.sweep({}, {0: []}, 1, 1, 1)
+
+
+def test_run_ensemble_max_memory() -> None:
+    sir = sir_model()
+    init = {"S": 999, "I": 1}
+    full = sir.run_ensemble(init, 250, 250, nb_runs=20, seed=0, max_memory=10**6)
+    assert full.attrs["mode"] == "trajectories"
+    # 20 runs * 3 species * 251 time points * 8 bytes
+    summary = sir.run_ensemble(init, 250, 250, nb_runs=20, seed=0, max_memory=10**5)
+    assert summary.attrs["mode"] == "summary"
+    assert summary.S.dims == ("statistic", "time")
+    npt.assert_allclose(summary.S.sel(statistic="mean"), full.S.mean("run"))
+    npt.assert_allclose(summary.S.sel(statistic="std"), full.S.std("run", ddof=1))
+
+
+def test_from_file(tmp_path: Path) -> None:
+    path = tmp_path / "sir.toml"
+    path.write_text(
+        """
+        version = 1
+        species = [{ name = "S" }, { name = "I" }, { name = "R" }]
+        parameters = [{ name = "r_inf", value = 1e-4 }]
+        [[reactions]]
+        reactants = { S = 1, I = 1 }
+        products = { I = 2 }
+        rate = "r_inf"
+        [[reactions]]
+        reactants = { I = 1 }
+        products = { R = 1 }
+        rate = 0.01
+        """
+    )
+    sir = rebop.Gillespie.from_file(str(path))
+    assert sir.nb_species() == 3
+    assert sir.init == {}
+    assert [r.rate for r in sir.reactions] == [1e-4, 0.01]
+    assert sir.nb_reactions() == 2
+    ds = sir.run({"S": 999, "I": 1}, tmax=250, nb_steps=250, seed=42)
+    npt.assert_array_equal(ds.S + ds.I + ds.R, [1000] * 251)
+
+
+def test_from_file_bngl(tmp_path: Path) -> None:
+    path = tmp_path / "binding.net"
+    path.write_text(
+        """
+        begin parameters
+            1 kp 1e-3
+            2 km 0.1
+        end parameters
+        begin species
+            1 A(b) 100
+            2 B(a) 50
+            3 A(b!1).B(a!1) 0
+        end species
+        begin reactions
+            1 1,2 3 kp #_R1
+            2 3 1,2 km #_reverse_R1
+        end reactions
+        begin groups
+            1 Atot 1,3
+        end groups
+        """
+    )
+    binding = rebop.Gillespie.from_file(str(path))
+    assert binding.species == ["A(b)", "B(a)", "A(b!1).B(a!1)"]
+    assert binding.init == {"A(b)": 100, "B(a)": 50}
+    assert binding.observables == {"Atot": {"A(b)": 1.0, "A(b!1).B(a!1)": 1.0}}
+    assert [r.rate for r in binding.reactions] == [1e-3, 0.1]
+
+
+SBML = """<?xml version="1.0" encoding="UTF-8"?>
+<sbml xmlns="http://www.sbml.org/sbml/level3/version2/core" level="3" version="2">
+  <model id="sir">
+    <listOfCompartments>
+      <compartment id="cell" size="2" constant="true"/>
+    </listOfCompartments>
+    <listOfSpecies>
+      <species id="S" compartment="cell" initialConcentration="499.5"
+               hasOnlySubstanceUnits="false" boundaryCondition="false"
+               constant="false"/>
+      <species id="I" compartment="cell" initialAmount="1"
+               hasOnlySubstanceUnits="true" boundaryCondition="false"
+               constant="false"/>
+      <species id="R" compartment="cell" initialAmount="0"
+               hasOnlySubstanceUnits="true" boundaryCondition="false"
+               constant="false"/>
+      <species id="Src" compartment="cell" initialAmount="1"
+               hasOnlySubstanceUnits="true" boundaryCondition="true"
+               constant="false"/>
+    </listOfSpecies>
+    <listOfParameters>
+      <parameter id="r_inf" value="1e-4" constant="true"/>
+    </listOfParameters>
+    <listOfReactions>
+      <reaction id="infection" reversible="false">
+        <listOfReactants>
+          <speciesReference species="S" stoichiometry="1" constant="true"/>
+          <speciesReference species="I" stoichiometry="1" constant="true"/>
+        </listOfReactants>
+        <listOfProducts>
+          <speciesReference species="I" stoichiometry="2" constant="true"/>
+        </listOfProducts>
+        <kineticLaw>
+          <math xmlns="http://www.w3.org/1998/Math/MathML">
+            <apply><times/><ci> r_inf </ci><ci> S </ci><ci> I </ci></apply>
+          </math>
+        </kineticLaw>
+      </reaction>
+      <reaction id="healing" reversible="true">
+        <listOfReactants>
+          <speciesReference species="I" stoichiometry="1" constant="true"/>
+        </listOfReactants>
+        <listOfProducts>
+          <speciesReference species="R" stoichiometry="1" constant="true"/>
+        </listOfProducts>
+        <kineticLaw>
+          <math xmlns="http://www.w3.org/1998/Math/MathML">
+            <apply>
+              <minus/>
+              <apply><times/><ci> k </ci><ci> I </ci></apply>
+              <apply>
+                <times/><cn type="e-notation"> 1 <sep/> -3 </cn><ci> R </ci>
+              </apply>
+            </apply>
+          </math>
+          <listOfLocalParameters>
+            <localParameter id="k" value="0.01"/>
+          </listOfLocalParameters>
+        </kineticLaw>
+      </reaction>
+      <reaction id="import" reversible="false">
+        <listOfReactants>
+          <speciesReference species="Src" stoichiometry="1" constant="true"/>
+        </listOfReactants>
+        <listOfProducts>
+          <speciesReference species="S" stoichiometry="1" constant="true"/>
+        </listOfProducts>
+        <kineticLaw>
+          <math xmlns="http://www.w3.org/1998/Math/MathML">
+            <apply><divide/><ci> Src </ci><cn> 2 </cn></apply>
+          </math>
+        </kineticLaw>
+      </reaction>
+    </listOfReactions>
+  </model>
+</sbml>
+"""
+
+
+def test_from_sbml(tmp_path: Path) -> None:
+    path = tmp_path / "sir.xml"
+    path.write_text(SBML)
+    sir = rebop.from_sbml(path)
+    assert sir.init == {"S": 999, "I": 1, "Src": 1}
+    assert [r.rate for r in sir.reactions] == [1e-4, 0.01, 1e-3, 0.5]
+    assert sir.reactions[1].reactants == {"I": 1}
+    assert sir.reactions[2].reactants == {"R": 1}
+    assert sir.reactions[3].products == {"S": 1, "Src": 1}
+    ds = sir.run(sir.init, tmax=10, nb_steps=10, seed=0)
+    npt.assert_array_equal(ds.Src, [1] * 11)
+    path.write_text(SBML.replace("<ci> S </ci><ci> I </ci>", "<ci> S </ci>"))
+    with pytest.raises(ValueError, match="not of mass action"):
+        rebop.from_sbml(path)
+
+
+def test_from_pysb(monkeypatch: pytest.MonkeyPatch) -> None:
+    net = """
+    begin parameters
+        1 k 0.5
+    end parameters
+    begin species
+        1 A() 10
+    end species
+    begin reactions
+        1 1 0 k #_R1
+    end reactions
+    """
+    bng = types.ModuleType("pysb.bng")
+    bng.generate_network = lambda _: net  # type: ignore[attr-defined]
+    monkeypatch.setitem(sys.modules, "pysb", types.ModuleType("pysb"))
+    monkeypatch.setitem(sys.modules, "pysb.bng", bng)
+    decay = rebop.from_pysb(object())
+    assert decay.species == ["A()"]
+    assert decay.init == {"A()": 10}
+    assert [r.rate for r in decay.reactions] == [0.5]
+
+
+def test_from_file_unsupported_version(tmp_path: Path) -> None:
+    path = tmp_path / "model.json"
+    path.write_text('{"version": 99}')
+    with pytest.raises(ValueError, match="upgrade rebop"):
+        rebop.Gillespie.from_file(str(path))